@@ -0,0 +1,119 @@
+#[macro_use]
+extern crate bencher;
+extern crate nalgebra as na;
+extern crate truescad;
+extern crate truescad_luascad;
+use bencher::Bencher;
+use truescad::flat_tree::FlatTree;
+use truescad_luascad::implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use truescad_luascad::tree::Node;
+use truescad_luascad::Float;
+
+// Booleans nested ten deep, each rounding a box with a sphere.
+static DEEP_CSG: &str = "
+o = Sphere(1)
+for i = 1, 10 do
+  local s = 1 + i / 2
+  o = Difference({Union({o, Box(s, s, s, .1)}, .1), Sphere(s / 2):translate(s / 2, 0, 0)}, .1)
+end
+build(o)";
+// 400 spheres on a grid.
+static BIG_UNION: &str = "
+spheres = {}
+for i = 1, 20 do
+  for j = 1, 20 do
+    spheres[#spheres + 1] = Sphere(.4):translate(i, j, 0)
+  end
+end
+build(Union(spheres))";
+static MESH: &str = "build(Mesh(\"xplicit.stl\"))";
+static TWIST: &str = "
+t=Twist(Difference({Box(1,1,1,.2), Sphere(0.5)},.2),4)
+t:rotate(-math.pi/4,0,0)
+build(t)";
+// Points along each axis of the box of an object.
+const SAMPLES: usize = 16;
+const PARAMETERS: PrimitiveParameters<Float> = PrimitiveParameters {
+    fade_range: 0.1,
+    r_multiplier: 1.0,
+};
+
+fn load(script: &str) -> (Box<dyn Object<Float>>, Option<Node>) {
+    let evaluation = ::truescad_luascad::evaluate(script, 0.).unwrap();
+    let mut object = evaluation.object.unwrap();
+    object.set_parameters(&PARAMETERS);
+    (object, evaluation.tree)
+}
+
+// The centers of the cells of a grid over bbox, and the slack to prune them with.
+fn grid(bbox: &BoundingBox<Float>) -> (Vec<na::Point3<Float>>, Float) {
+    let step = bbox.dim() / SAMPLES as Float;
+    let mut points = Vec::with_capacity(SAMPLES * SAMPLES * SAMPLES);
+    for i in 0..SAMPLES {
+        for j in 0..SAMPLES {
+            for k in 0..SAMPLES {
+                let cell = na::Vector3::new(i as Float, j as Float, k as Float).add_scalar(0.5);
+                points.push(bbox.min + step.component_mul(&cell));
+            }
+        }
+    }
+    (points, step.norm() / 2.)
+}
+
+fn interpreted(b: &mut Bencher, script: &str) {
+    let (object, _) = load(script);
+    let (points, slack) = grid(object.bbox());
+    b.iter(|| {
+        points
+            .iter()
+            .map(|p| object.approx_value(p, slack))
+            .sum::<Float>()
+    });
+}
+
+fn flat(b: &mut Bencher, script: &str) {
+    let (object, tree) = load(script);
+    let tree = FlatTree::new(&tree.unwrap(), PARAMETERS.r_multiplier).unwrap();
+    let (points, _) = grid(object.bbox());
+    b.iter(|| points.iter().map(|p| tree.value(p)).sum::<Float>());
+}
+
+fn deep_csg(b: &mut Bencher) {
+    interpreted(b, DEEP_CSG);
+}
+
+fn deep_csg_flat(b: &mut Bencher) {
+    flat(b, DEEP_CSG);
+}
+
+fn big_union(b: &mut Bencher) {
+    interpreted(b, BIG_UNION);
+}
+
+fn big_union_flat(b: &mut Bencher) {
+    flat(b, BIG_UNION);
+}
+
+fn mesh(b: &mut Bencher) {
+    interpreted(b, MESH);
+}
+
+fn twist(b: &mut Bencher) {
+    interpreted(b, TWIST);
+}
+
+fn twist_flat(b: &mut Bencher) {
+    flat(b, TWIST);
+}
+
+benchmark_group!(
+    bench_eval,
+    deep_csg,
+    deep_csg_flat,
+    big_union,
+    big_union_flat,
+    mesh,
+    twist,
+    twist_flat
+);
+benchmark_main!(bench_eval);
@@ -0,0 +1,335 @@
+// Evaluation and tessellation of scripts over a C ABI, so that other programs (e.g. in C++ or
+// Python with ctypes) can embed truescad instead of running the CLI. See truescad.h for how the
+// functions are used. The Rust functions evaluate and tessellate are shared with the WebAssembly
+// build.
+
+// The contract of the unsafe functions is documented in truescad.h.
+#![allow(clippy::missing_safety_doc)]
+
+extern crate nalgebra as na;
+extern crate rayon;
+extern crate tessellation;
+extern crate truescad_luascad;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use tessellation::{ImplicitFunction, ManifoldDualContouring};
+use truescad_luascad::implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use truescad_luascad::include::Includes;
+use truescad_luascad::measure::{Ray, SurfaceQuery};
+use truescad_luascad::ScriptError;
+
+// Like the defaults of the settings of the GUI.
+pub const PARAMETERS: PrimitiveParameters<f64> = PrimitiveParameters {
+    fade_range: 0.1,
+    r_multiplier: 1.0,
+};
+// The error ts_tessellate allows, like the default of the settings.
+pub const ERROR: f64 = 2.;
+
+// The object of a script, and what it printed.
+pub struct Evaluation {
+    pub object: Box<dyn Object<f64>>,
+    pub output: String,
+}
+
+// A tessellated object.
+pub struct Mesh {
+    // x, y, z of each vertex.
+    pub vertices: Vec<f64>,
+    pub normals: Vec<f64>,
+    // The vertices of each triangle.
+    pub indices: Vec<u32>,
+}
+
+// The threads tessellation spreads its work over, with rayon.
+pub trait Threads {
+    fn run<T: Send, F: FnOnce() -> T + Send>(&self, f: F) -> T;
+}
+
+// The global thread pool of rayon, a thread per core.
+pub struct Pool;
+
+impl Threads for Pool {
+    fn run<T: Send, F: FnOnce() -> T + Send>(&self, f: F) -> T {
+        f()
+    }
+}
+
+// Only the thread that created it, which must be the one calling run, so that no threads are
+// started. Browsers only have threads on cross-origin isolated pages, see the WebAssembly build.
+pub struct CurrentThread(ThreadPool);
+
+impl CurrentThread {
+    pub fn new() -> Result<CurrentThread, String> {
+        ThreadPoolBuilder::new()
+            .num_threads(1)
+            .use_current_thread()
+            .build()
+            .map(CurrentThread)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Threads for CurrentThread {
+    fn run<T: Send, F: FnOnce() -> T + Send>(&self, f: F) -> T {
+        self.0.install(f)
+    }
+}
+
+struct ObjectAdaptor<'a> {
+    implicit: &'a dyn Object<f64>,
+    resolution: f64,
+}
+
+impl<'a> ImplicitFunction<f64> for ObjectAdaptor<'a> {
+    fn bbox(&self) -> &BoundingBox<f64> {
+        self.implicit.bbox()
+    }
+    fn value(&self, p: &na::Point3<f64>) -> f64 {
+        self.implicit.approx_value(p, self.resolution)
+    }
+    fn normal(&self, p: &na::Point3<f64>) -> na::Vector3<f64> {
+        self.implicit.normal(p)
+    }
+}
+
+// Evaluate script at time (0..1), with the defaults of its parameters and includes.
+pub fn evaluate(script: &str, time: f64, includes: &Includes) -> Result<Evaluation, String> {
+    let evaluation = truescad_luascad::evaluate_in(script, time, &HashMap::new(), includes)
+        .map_err(|e| ScriptError::from(&e).to_string())?;
+    let mut object = evaluation
+        .object
+        .ok_or_else(|| "no object - did you call build()?".to_string())?;
+    object.set_parameters(&PARAMETERS);
+    Ok(Evaluation {
+        object,
+        output: evaluation.output,
+    })
+}
+
+// Tessellate object with resolution and error on threads, like truescad export.
+pub fn tessellate<T: Threads>(
+    threads: &T,
+    object: &dyn Object<f64>,
+    resolution: f64,
+    error: f64,
+) -> Result<Mesh, String> {
+    let bbox = object.bbox();
+    if !(bbox.min.coords.iter().chain(bbox.max.coords.iter())).all(|x| x.is_finite()) {
+        return Err(
+            "can not tessellate an infinite object, intersect it with a finite one".to_string(),
+        );
+    }
+    if resolution.is_nan() || resolution <= 0. {
+        return Err(format!("resolution must be positive, got {}", resolution));
+    }
+    let adaptor = ObjectAdaptor {
+        implicit: object,
+        resolution,
+    };
+    let mesh = threads
+        .run(|| ManifoldDualContouring::new(&adaptor, resolution, error).tessellate())
+        .ok_or_else(|| "tessellation failed".to_string())?;
+    let mut vertices = Vec::with_capacity(mesh.vertices.len() * 3);
+    let mut normals = Vec::with_capacity(mesh.vertices.len() * 3);
+    for v in &mesh.vertices {
+        vertices.extend(v);
+        normals.extend(object.normal(&na::Point3::new(v[0], v[1], v[2])).iter());
+    }
+    Ok(Mesh {
+        vertices,
+        normals,
+        indices: mesh
+            .faces
+            .iter()
+            .flat_map(|f| f.iter().map(|&i| i as u32))
+            .collect(),
+    })
+}
+
+// The result of f, with a panic in it as error, since unwinding into C (or JavaScript) is
+// undefined behavior. Handles are not used after a panic, so it does not matter if it left them
+// inconsistent.
+pub fn catch_panic<T, F>(f: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "unknown error".to_string()),
+        };
+        Err(format!("internal error: {}", message))
+    })
+}
+
+// The handle ts_eval returns.
+pub struct TsObject {
+    object: Option<Box<dyn Object<f64>>>,
+    error: Option<CString>,
+    output: CString,
+}
+
+// The handle ts_tessellate returns.
+pub struct TsMesh {
+    mesh: Option<Mesh>,
+    error: Option<CString>,
+}
+
+// Null is read as the empty string.
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+fn c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+fn error_ptr(error: &Option<CString>) -> *const c_char {
+    error.as_ref().map_or(ptr::null(), |e| e.as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_eval(script: *const c_char) -> *mut TsObject {
+    ts_eval_at(script, 0.)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_eval_at(script: *const c_char, time: f64) -> *mut TsObject {
+    let script = string(script);
+    let handle = match catch_panic(|| evaluate(&script, time, &Includes::default())) {
+        Ok(evaluation) => TsObject {
+            object: Some(evaluation.object),
+            error: None,
+            output: c_string(evaluation.output),
+        },
+        Err(e) => TsObject {
+            object: None,
+            error: Some(c_string(e)),
+            output: c_string(String::new()),
+        },
+    };
+    Box::into_raw(Box::new(handle))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_object_error(o: *const TsObject) -> *const c_char {
+    error_ptr(&(*o).error)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_object_output(o: *const TsObject) -> *const c_char {
+    (*o).output.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_object_free(o: *mut TsObject) {
+    if !o.is_null() {
+        drop(Box::from_raw(o));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_raycast(
+    o: *const TsObject,
+    origin: *const f64,
+    dir: *const f64,
+    max_t: f64,
+    hit: *mut f64,
+) -> c_int {
+    let object = match (*o).object {
+        Some(ref object) => object,
+        None => return 0,
+    };
+    let (origin, dir) = (
+        ::std::slice::from_raw_parts(origin, 3),
+        ::std::slice::from_raw_parts(dir, 3),
+    );
+    let ray = Ray::new(
+        na::Point3::new(origin[0], origin[1], origin[2]),
+        na::Vector3::new(dir[0], dir[1], dir[2]),
+    );
+    match catch_panic(|| Ok(object.raycast(&ray, max_t))) {
+        Ok(Some(h)) => {
+            let out = ::std::slice::from_raw_parts_mut(hit, 7);
+            out[..3].copy_from_slice(h.point.coords.as_slice());
+            out[3..6].copy_from_slice(h.normal.as_slice());
+            out[6] = h.t;
+            1
+        }
+        _ => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_tessellate(o: *const TsObject, resolution: f64) -> *mut TsMesh {
+    let result = match (*o).object {
+        Some(ref object) => catch_panic(|| tessellate(&Pool, &**object, resolution, ERROR)),
+        None => Err("the script has no object".to_string()),
+    };
+    let handle = match result {
+        Ok(mesh) => TsMesh {
+            mesh: Some(mesh),
+            error: None,
+        },
+        Err(e) => TsMesh {
+            mesh: None,
+            error: Some(c_string(e)),
+        },
+    };
+    Box::into_raw(Box::new(handle))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_error(m: *const TsMesh) -> *const c_char {
+    error_ptr(&(*m).error)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_vertex_count(m: *const TsMesh) -> usize {
+    (*m).mesh.as_ref().map_or(0, |m| m.vertices.len() / 3)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_vertices(m: *const TsMesh) -> *const f64 {
+    (*m).mesh
+        .as_ref()
+        .map_or(ptr::null(), |m| m.vertices.as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_normals(m: *const TsMesh) -> *const f64 {
+    (*m).mesh
+        .as_ref()
+        .map_or(ptr::null(), |m| m.normals.as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_triangle_count(m: *const TsMesh) -> usize {
+    (*m).mesh.as_ref().map_or(0, |m| m.indices.len() / 3)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_indices(m: *const TsMesh) -> *const u32 {
+    (*m).mesh
+        .as_ref()
+        .map_or(ptr::null(), |m| m.indices.as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ts_mesh_free(m: *mut TsMesh) {
+    if !m.is_null() {
+        drop(Box::from_raw(m));
+    }
+}
@@ -0,0 +1,63 @@
+/* Evaluation and tessellation of truescad scripts, see lib.rs. Link with libtruescad_capi,
+ * built by cargo build --release in capi.
+ *
+ *   ts_object *o = ts_eval("build(Sphere(5))");
+ *   if (ts_object_error(o)) { ... }
+ *   ts_mesh *m = ts_tessellate(o, 0.12);
+ *   if (ts_mesh_error(m)) { ... }
+ *   const double *v = ts_mesh_vertices(m);       // x, y, z of ts_mesh_vertex_count(m) vertices
+ *   const uint32_t *i = ts_mesh_indices(m);      // 3 vertices of ts_mesh_triangle_count(m) triangles
+ *   ts_mesh_free(m);
+ *   ts_object_free(o);
+ *
+ * Strings are UTF-8, null is read as the empty one. Strings and buffers belong to their handle
+ * and are valid until it is freed. Handles are never null, but may only be used from one thread
+ * at a time. Internal errors (panics) are reported like errors of the script, and make
+ * ts_raycast miss.
+ */
+#ifndef TRUESCAD_H
+#define TRUESCAD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TsObject ts_object;
+typedef struct TsMesh ts_mesh;
+
+/* Evaluate the script at time 0 (or time, 0..1) with the defaults of its parameters. Scripts
+ * can not include others. */
+ts_object *ts_eval(const char *script);
+ts_object *ts_eval_at(const char *script, double time);
+/* Why the evaluation failed, null if it did not. */
+const char *ts_object_error(const ts_object *o);
+/* What the script printed. */
+const char *ts_object_output(const ts_object *o);
+void ts_object_free(ts_object *o);
+/* Cast a ray from origin (x, y, z) along dir at the surface of the object, up to max_t (which may
+ * be INFINITY) from origin. If it hits, hit gets x, y, z of the point, the normal there and its
+ * distance from origin, and 1 is returned, else 0. */
+int ts_raycast(const ts_object *o, const double origin[3], const double dir[3], double max_t,
+               double hit[7]);
+
+/* Tessellate the object with the resolution, like truescad export. */
+ts_mesh *ts_tessellate(const ts_object *o, double resolution);
+/* Why the tessellation failed, null if it did not. */
+const char *ts_mesh_error(const ts_mesh *m);
+size_t ts_mesh_vertex_count(const ts_mesh *m);
+/* x, y, z of each vertex, and the normal there. Null if the tessellation failed. */
+const double *ts_mesh_vertices(const ts_mesh *m);
+const double *ts_mesh_normals(const ts_mesh *m);
+size_t ts_mesh_triangle_count(const ts_mesh *m);
+/* The indices of the vertices of each triangle. */
+const uint32_t *ts_mesh_indices(const ts_mesh *m);
+void ts_mesh_free(ts_mesh *m);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1 @@
+build(Sphere(5))
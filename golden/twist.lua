@@ -0,0 +1,4 @@
+t = Twist(Difference({Box(1,1,1,.2), Sphere(0.5)},.2),4)
+t = t:rotate(-math.pi/4,0,0)
+t = t:scale(10,10,10)
+build(t)
@@ -0,0 +1,5 @@
+spheres = {}
+for i = 1, 4 do
+  spheres[#spheres + 1] = Sphere(2):translate(3 * i, 0, 0)
+end
+build(Union(spheres, 1))
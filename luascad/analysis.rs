@@ -0,0 +1,813 @@
+use super::Float;
+use bounded;
+use implicit3d::{BoundingBox, Object};
+use measure::{self, Ray, SurfaceQuery};
+use nalgebra as na;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// mass_properties subdivides cells down to this many levels below the bounding box.
+const MASS_DEPTH: i32 = 7;
+// thin_walls, overhangs and draft_regions sample the surface in cells this many levels below the
+// bounding box.
+const SAMPLE_DEPTH: u32 = 6;
+// components finds the parts of objects in a grid of cells this many levels below the bounding
+// box. Parts closer than about a cell are joined into one.
+const COMPONENT_DEPTH: u32 = 6;
+// overhangs leaves out the surface up to this fraction of the size of the object above the
+// bottom of its bounding box, which rests on the build plate.
+const PLATE_TOLERANCE: Float = 1e-3;
+// support_pillars steps at least this fraction of the spacing of the pillars down to the object
+// below.
+const DROP_PRECISION: Float = 1e-2;
+// draft_regions joins neighboring surface points into one region if their normals differ by at
+// most this many degrees.
+const FACE_ANGLE: Float = 10.;
+// Marches through or along the object step at least this fraction of how far they go (the
+// maximum thickness for wall_thickness), and is_undercut starts its ray this fraction of the size
+// of the object off the surface.
+const MARCH_PRECISION: Float = 1e-3;
+const MAX_MARCH_STEPS: usize = 1000;
+
+// Volume, mass, center of mass and inertia tensor of an object of uniform density.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MassProperties {
+    pub volume: Float,
+    pub mass: Float,
+    pub centroid: na::Point3<Float>,
+    // About the centroid, in the axes of the object.
+    pub inertia: na::Matrix3<Float>,
+}
+
+// The mass properties of object, integrating over an octree of its bounding box. The values of
+// implicit3d objects are at most the distances to their surfaces, so cells are skipped or taken
+// whole where the value at their center shows they are completely outside or inside. Cells on
+// the surface are subdivided, and at the last level counted with the fraction a flat surface at
+// that distance from their center, along the normal there, would leave inside.
+// None if the bounding box is not finite.
+pub fn mass_properties(object: &dyn Object<Float>, density: Float) -> Option<MassProperties> {
+    let bbox = object.bbox();
+    if bounded::is_empty(bbox) {
+        return Some(MassProperties {
+            volume: 0.,
+            mass: 0.,
+            centroid: na::Point3::origin(),
+            inertia: na::Matrix3::zeros(),
+        });
+    }
+    if !bounded::is_finite(bbox) {
+        return None;
+    }
+    let mut volume = 0.;
+    let mut first = na::Vector3::zeros();
+    let mut second = na::Matrix3::zeros();
+    let center = na::center(&bbox.min, &bbox.max);
+    // Flat boxes have no volume.
+    if bbox.dim().iter().all(|&d| d > 0.) {
+        let min_radius = bbox.dim().norm() * 0.5 / (2 as Float).powi(MASS_DEPTH);
+        let mut cells = vec![(center, bbox.dim() * 0.5)];
+        while let Some((center, half)) = cells.pop() {
+            let v = object.approx_value(&center, Float::INFINITY);
+            let radius = half.norm();
+            if v >= radius {
+                continue;
+            }
+            let fraction = if v <= -radius {
+                1.
+            } else if radius <= min_radius {
+                let normal = object.normal(&center);
+                let extent = normal.abs().dot(&half);
+                if extent > 0. {
+                    (0.5 - v / (2. * extent)).clamp(0., 1.)
+                } else if v < 0. {
+                    1.
+                } else {
+                    0.
+                }
+            } else {
+                let half = half * 0.5;
+                for i in 0..8 {
+                    let offset = na::Vector3::new(
+                        if i & 1 == 0 { -half.x } else { half.x },
+                        if i & 2 == 0 { -half.y } else { half.y },
+                        if i & 4 == 0 { -half.z } else { half.z },
+                    );
+                    cells.push((center + offset, half));
+                }
+                continue;
+            };
+            // The integrals of 1, p and p * p^T over the cell.
+            let w = 8. * half.x * half.y * half.z * fraction;
+            let c = center.coords;
+            volume += w;
+            first += c * w;
+            second += (c * c.transpose()
+                + na::Matrix3::from_diagonal(&half.component_mul(&half)) / 3.)
+                * w;
+        }
+    }
+    if volume <= 0. {
+        return Some(MassProperties {
+            volume: 0.,
+            mass: 0.,
+            centroid: center,
+            inertia: na::Matrix3::zeros(),
+        });
+    }
+    let centroid = first / volume;
+    // The second moments about the centroid, and from them the inertia tensor.
+    let moments = (second - centroid * centroid.transpose() * volume) * density;
+    let inertia = na::Matrix3::identity() * moments.trace() - moments;
+    Some(MassProperties {
+        volume,
+        mass: volume * density,
+        centroid: na::Point3::from(centroid),
+        inertia,
+    })
+}
+
+// A connected part of the surface where the walls are thinner than the threshold given to
+// thin_walls.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThinRegion {
+    pub thickness: Float,
+    // Where the wall is thinnest.
+    pub at: na::Point3<Float>,
+    // Around the thin surface points.
+    pub bbox: BoundingBox<Float>,
+}
+
+// The thickness of the wall at the surface point p: the distance to the opposite surface, into
+// the object against the normal at p. Marches through the inside in steps of the depth, which
+// the values of implicit3d objects never exceed, so it does not skip thin parts of the wall.
+// Stops at max, which is also returned where the march does not get through, e.g. along a face,
+// or does not get in, e.g. on an edge.
+pub fn wall_thickness(object: &dyn Object<Float>, p: &na::Point3<Float>, max: Float) -> Float {
+    let normal = object.normal(p);
+    if !normal.iter().all(|x| x.is_finite()) {
+        return max;
+    }
+    let min_step = max * MARCH_PRECISION;
+    let mut t = min_step;
+    for step in 0..MAX_MARCH_STEPS {
+        let depth = -object.approx_value(&(p - normal * t), Float::INFINITY);
+        // At sharp edges, the normal can point along the other face instead of into the object.
+        if step == 0 && depth < t * 0.5 {
+            return max;
+        }
+        if depth <= 0. || t >= max {
+            return t.min(max);
+        }
+        t += depth.max(min_step);
+    }
+    max
+}
+
+// The regions of the surface of object where its walls (see wall_thickness) are thinner than
+// threshold, thinnest first. None if the bounding box is not finite.
+pub fn thin_walls(object: &dyn Object<Float>, threshold: Float) -> Option<Vec<ThinRegion>> {
+    let mut thin = HashMap::new();
+    for (index, p) in surface_samples(object)? {
+        let thickness = wall_thickness(object, &p, threshold);
+        if thickness < threshold {
+            thin.insert(index, (p, thickness));
+        }
+    }
+    let mut regions: Vec<ThinRegion> = connected(thin)
+        .into_iter()
+        .map(|samples| {
+            let (at, thickness) = samples
+                .iter()
+                .cloned()
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+                .expect("empty region");
+            ThinRegion {
+                thickness,
+                at,
+                bbox: bbox_of(samples.iter().map(|s| &s.0)),
+            }
+        })
+        .collect();
+    regions.sort_by(|a, b| {
+        a.thickness
+            .partial_cmp(&b.thickness)
+            .unwrap_or(Ordering::Equal)
+    });
+    Some(regions)
+}
+
+// A connected part of the surface which overhangs by more than the angle given to overhangs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverhangRegion {
+    // Around the overhanging surface points.
+    pub bbox: BoundingBox<Float>,
+    // Surface points sampled in the region.
+    pub points: Vec<na::Point3<Float>>,
+}
+
+// A support pillar along z, from bottom (on the build plate or the object) to top (on an
+// overhang).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pillar {
+    pub bottom: na::Point3<Float>,
+    pub top: na::Point3<Float>,
+}
+
+// Whether a surface with normal faces down by more than max_angle (in radians) from the walls
+// built along z, i.e. needs support when printed. Slicers usually allow about 45 degrees.
+pub fn is_overhang(normal: &na::Vector3<Float>, max_angle: Float) -> bool {
+    -normal.z > max_angle.sin()
+}
+
+// The regions of the surface of object which overhang (see is_overhang), lowest first. The
+// bottom of the object rests on the build plate and is left out. None if the bounding box is not
+// finite.
+pub fn overhangs(object: &dyn Object<Float>, max_angle: Float) -> Option<Vec<OverhangRegion>> {
+    let samples = surface_samples(object)?;
+    let plate = object.bbox().min.z + object.bbox().dim().norm() * PLATE_TOLERANCE;
+    let overhanging: HashMap<_, _> = samples
+        .into_iter()
+        .filter(|&(_, p)| p.z > plate && is_overhang(&object.normal(&p), max_angle))
+        .collect();
+    let mut regions: Vec<OverhangRegion> = connected(overhanging)
+        .into_iter()
+        .map(|points| OverhangRegion {
+            bbox: bbox_of(points.iter()),
+            points,
+        })
+        .collect();
+    regions.sort_by(|a, b| {
+        a.bbox
+            .min
+            .z
+            .partial_cmp(&b.bbox.min.z)
+            .unwrap_or(Ordering::Equal)
+    });
+    Some(regions)
+}
+
+// Pillars under the overhangs of object (see overhangs), about spacing apart, down to the build
+// plate or to the object below. None if the bounding box is not finite.
+pub fn support_pillars(
+    object: &dyn Object<Float>,
+    max_angle: Float,
+    spacing: Float,
+) -> Option<Vec<Pillar>> {
+    let plate = object.bbox().min.z;
+    let mut pillars = Vec::new();
+    for region in overhangs(object, max_angle)? {
+        // The point closest to the center of each square of the grid with spacing.
+        let mut tops: HashMap<(i64, i64), na::Point3<Float>> = HashMap::new();
+        for p in region.points {
+            let square = (
+                (p.x / spacing).floor() as i64,
+                (p.y / spacing).floor() as i64,
+            );
+            let center = |p: &na::Point3<Float>| {
+                let dx = p.x - (square.0 as Float + 0.5) * spacing;
+                let dy = p.y - (square.1 as Float + 0.5) * spacing;
+                dx * dx + dy * dy
+            };
+            match tops.get(&square) {
+                Some(top) if center(top) <= center(&p) => {}
+                _ => {
+                    tops.insert(square, p);
+                }
+            }
+        }
+        for top in tops.values() {
+            let bottom = drop_down(object, top, plate, spacing * DROP_PRECISION);
+            if bottom.z < top.z {
+                pillars.push(Pillar { bottom, top: *top });
+            }
+        }
+    }
+    Some(pillars)
+}
+
+// pin_positions samples the plane in a grid of this many points along each side.
+const PIN_SAMPLES: usize = 48;
+// Pins are at least this many radii inside the object, and this many radii apart.
+const PIN_WALL: Float = 1.;
+const PIN_SPACING: Float = 4.;
+
+// Where pins of radius, from the plane with the unit normal at distance d from the origin to depth
+// in front of it, fit into object: up to pins points of the plane at least PIN_WALL radii inside
+// object there and at depth, the deepest first, then each as far from those taken as possible.
+// None if the bounding box is not finite.
+pub fn pin_positions(
+    object: &dyn Object<Float>,
+    normal: &na::Vector3<Float>,
+    d: Float,
+    radius: Float,
+    depth: Float,
+    pins: usize,
+) -> Option<Vec<na::Point3<Float>>> {
+    let bbox = object.bbox();
+    if !bounded::is_finite(bbox) {
+        return None;
+    }
+    // The plane is spanned by u and v, the box lies within min and max along them.
+    let other = if normal.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::y()
+    };
+    let u = normal.cross(&other).normalize();
+    let v = normal.cross(&u);
+    let (mut min, mut max) = (
+        na::Vector2::repeat(Float::INFINITY),
+        na::Vector2::repeat(Float::NEG_INFINITY),
+    );
+    for corner in &bounded::corners(bbox) {
+        let along = na::Vector2::new(u.dot(&corner.coords), v.dot(&corner.coords));
+        min = min.zip_map(&along, Float::min);
+        max = max.zip_map(&along, Float::max);
+    }
+    let wall = radius * PIN_WALL;
+    let mut candidates = Vec::new();
+    for i in 0..PIN_SAMPLES {
+        for j in 0..PIN_SAMPLES {
+            let s = min.x + (max.x - min.x) * (i as Float + 0.5) / PIN_SAMPLES as Float;
+            let t = min.y + (max.y - min.y) * (j as Float + 0.5) / PIN_SAMPLES as Float;
+            let p = na::Point3::from(normal * d + u * s + v * t);
+            let value = object.approx_value(&p, 0.);
+            if value < -wall && object.approx_value(&(p + normal * depth), 0.) < -wall {
+                candidates.push((p, value));
+            }
+        }
+    }
+    let mut positions: Vec<na::Point3<Float>> = Vec::new();
+    if let Some(&(deepest, _)) = candidates
+        .iter()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+    {
+        positions.push(deepest);
+    }
+    while positions.len() < pins {
+        let distance = |p: &na::Point3<Float>| {
+            positions
+                .iter()
+                .map(|q| (p - q).norm())
+                .fold(Float::INFINITY, Float::min)
+        };
+        match candidates
+            .iter()
+            .map(|&(p, _)| (p, distance(&p)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        {
+            Some((p, distance)) if distance >= radius * PIN_SPACING => positions.push(p),
+            _ => break,
+        }
+    }
+    Some(positions)
+}
+
+// A connected part of the surface with similar normals, for draft_regions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DraftRegion {
+    // The smallest draft angle (see draft_angle) in the region, in radians.
+    pub draft: Float,
+    // The fraction of the region which is undercut, see is_undercut.
+    pub undercut: Float,
+    // The average normal.
+    pub normal: na::Vector3<Float>,
+    pub bbox: BoundingBox<Float>,
+}
+
+// The draft angle of a surface with normal for a mold pulled off along the unit vector pull, in
+// radians: 0 for walls parallel to pull, which drag along the mold, up to pi / 2 for surfaces
+// facing it. Surfaces facing away release to the other half of the mold, so they have the same
+// angle as facing it.
+pub fn draft_angle(normal: &na::Vector3<Float>, pull: &na::Vector3<Float>) -> Float {
+    normal.dot(pull).abs().min(1.).asin()
+}
+
+// Whether the mold half released from the surface point p with normal along the unit vector pull
+// (or against it, for surfaces facing away) would hit the object, i.e. p is in an undercut.
+// Casts a ray from a fraction of the size of the object off p, which does not hit the surface at
+// p.
+pub fn is_undercut(
+    object: &dyn Object<Float>,
+    p: &na::Point3<Float>,
+    normal: &na::Vector3<Float>,
+    pull: &na::Vector3<Float>,
+) -> bool {
+    let facing = normal.dot(pull);
+    if facing == 0. {
+        return false;
+    }
+    let direction = if facing > 0. { *pull } else { -pull };
+    let size = object.bbox().dim().norm();
+    let start = p + direction * (size * MARCH_PRECISION);
+    object.raycast(&Ray::new(start, direction), size).is_some()
+}
+
+// The surface of object split into regions of similar normals, with their draft angles and
+// undercuts for a mold pulled off along the unit vector pull. Undercut regions come first, then
+// those with the least draft. None if the bounding box is not finite.
+pub fn draft_regions(
+    object: &dyn Object<Float>,
+    pull: &na::Vector3<Float>,
+) -> Option<Vec<DraftRegion>> {
+    let samples = surface_samples(object)?
+        .into_iter()
+        .map(|(index, p)| {
+            let normal = object.normal(&p);
+            let undercut = is_undercut(object, &p, &normal, pull);
+            (index, (p, normal, undercut))
+        })
+        .collect();
+    let min_cos = FACE_ANGLE.to_radians().cos();
+    let groups = connected_by(samples, |a, b| a.1.dot(&b.1) >= min_cos);
+    let mut regions: Vec<DraftRegion> = groups
+        .into_iter()
+        .map(|samples| {
+            let normal = samples
+                .iter()
+                .fold(na::Vector3::zeros(), |sum, s| sum + s.1);
+            DraftRegion {
+                draft: samples
+                    .iter()
+                    .map(|s| draft_angle(&s.1, pull))
+                    .fold(Float::INFINITY, Float::min),
+                undercut: samples.iter().filter(|s| s.2).count() as Float / samples.len() as Float,
+                normal: normal.try_normalize(0.).unwrap_or(normal),
+                bbox: bbox_of(samples.iter().map(|s| &s.0)),
+            }
+        })
+        .collect();
+    regions.sort_by(|a, b| {
+        b.undercut
+            .partial_cmp(&a.undercut)
+            .unwrap_or(Ordering::Equal)
+            .then(a.draft.partial_cmp(&b.draft).unwrap_or(Ordering::Equal))
+    });
+    Some(regions)
+}
+
+// The connected solid parts of an object, as the cells of a grid over its bounding box which
+// contain some of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Components {
+    // The corner of the cell with index [0, 0, 0], and the size of the cells.
+    pub origin: na::Point3<Float>,
+    pub cell: na::Vector3<Float>,
+    // The cells of each part, largest part first.
+    pub parts: Vec<Vec<[i64; 3]>>,
+}
+
+impl Components {
+    // The box around the cells of part. All of the part is within it.
+    pub fn bbox(&self, part: usize) -> BoundingBox<Float> {
+        let mut bbox = BoundingBox::neg_infinity();
+        for index in &self.parts[part] {
+            bbox = bbox.union(&self.cell_bbox(index));
+        }
+        bbox
+    }
+    pub fn cell_bbox(&self, index: &[i64; 3]) -> BoundingBox<Float> {
+        let min = self.origin
+            + na::Vector3::new(index[0] as Float, index[1] as Float, index[2] as Float)
+                .component_mul(&self.cell);
+        BoundingBox::new(&min, &(min + self.cell))
+    }
+    // The index of the cell containing p.
+    pub fn index(&self, p: &na::Point3<Float>) -> [i64; 3] {
+        let mut index = [0; 3];
+        for (i, x) in index.iter_mut().enumerate() {
+            if self.cell[i] > 0. {
+                *x = ((p[i] - self.origin[i]) / self.cell[i]).floor() as i64;
+            }
+        }
+        index
+    }
+    // Whether another part has cells within the box around the cells of part, so that the box
+    // does not separate part from the rest.
+    pub fn overlaps(&self, part: usize) -> bool {
+        let cells = &self.parts[part];
+        let mut min = [i64::MAX; 3];
+        let mut max = [i64::MIN; 3];
+        for index in cells {
+            for i in 0..3 {
+                min[i] = min[i].min(index[i]);
+                max[i] = max[i].max(index[i]);
+            }
+        }
+        self.parts.iter().enumerate().any(|(other, cells)| {
+            other != part
+                && cells
+                    .iter()
+                    .any(|index| (0..3).all(|i| min[i] <= index[i] && index[i] <= max[i]))
+        })
+    }
+}
+
+// The connected parts of object, found by flood fill over the cells of an octree of its bounding
+// box which may contain some of it: those whose center is no farther from the object than their
+// corners. Cells completely inside are not subdivided further, but taken with all of their
+// cells at the last level. None if the bounding box is not finite.
+pub fn components(object: &dyn Object<Float>) -> Option<Components> {
+    let bbox = object.bbox();
+    let cells = (2 as Float).powi(COMPONENT_DEPTH as i32);
+    if bounded::is_empty(bbox) {
+        return Some(Components {
+            origin: na::Point3::origin(),
+            cell: na::Vector3::zeros(),
+            parts: Vec::new(),
+        });
+    }
+    if !bounded::is_finite(bbox) {
+        return None;
+    }
+    let mut occupied = HashMap::new();
+    let mut octree = vec![(
+        na::center(&bbox.min, &bbox.max),
+        bbox.dim() * 0.5,
+        [0i64; 3],
+        0,
+    )];
+    while let Some((center, half, index, level)) = octree.pop() {
+        let v = object.approx_value(&center, Float::INFINITY);
+        let radius = half.norm();
+        if v > radius {
+            continue;
+        }
+        if level == COMPONENT_DEPTH || v <= -radius {
+            // The cells at the last level within this one.
+            let n = 1i64 << (COMPONENT_DEPTH - level);
+            for x in 0..n {
+                for y in 0..n {
+                    for z in 0..n {
+                        let index = [index[0] * n + x, index[1] * n + y, index[2] * n + z];
+                        occupied.insert(index, index);
+                    }
+                }
+            }
+            continue;
+        }
+        let half = half * 0.5;
+        for i in 0..8 {
+            let bit = |axis: usize| (i >> axis) & 1;
+            let offset = na::Vector3::new(
+                if bit(0) == 0 { -half.x } else { half.x },
+                if bit(1) == 0 { -half.y } else { half.y },
+                if bit(2) == 0 { -half.z } else { half.z },
+            );
+            let index = [
+                index[0] * 2 + bit(0) as i64,
+                index[1] * 2 + bit(1) as i64,
+                index[2] * 2 + bit(2) as i64,
+            ];
+            octree.push((center + offset, half, index, level + 1));
+        }
+    }
+    let mut parts = connected(occupied);
+    parts.sort_by_key(|cells| ::std::cmp::Reverse(cells.len()));
+    Some(Components {
+        origin: bbox.min,
+        cell: bbox.dim() / cells,
+        parts,
+    })
+}
+
+// How far object is from being symmetric to the plane through the origin normal to axis: the
+// largest magnitude of its values at the mirror images of points sampled on its surface, which
+// are on the surface again for symmetric objects. None if the bounding box is not finite.
+pub fn asymmetry(object: &dyn Object<Float>, axis: usize) -> Option<Float> {
+    Some(
+        surface_samples(object)?
+            .into_iter()
+            .map(|(_, mut p)| {
+                p[axis] = -p[axis];
+                object.approx_value(&p, Float::INFINITY).abs()
+            })
+            .fold(0., Float::max),
+    )
+}
+
+// Where a point falling from the surface point p along -z hits the object or the plate at z.
+// Marches in steps of the value, which never passes the surface, but at least min_step, e.g.
+// along walls.
+fn drop_down(
+    object: &dyn Object<Float>,
+    p: &na::Point3<Float>,
+    plate: Float,
+    min_step: Float,
+) -> na::Point3<Float> {
+    let mut q = *p - na::Vector3::z() * min_step;
+    for _ in 0..MAX_MARCH_STEPS {
+        if q.z <= plate {
+            break;
+        }
+        let v = object.approx_value(&q, Float::INFINITY);
+        if v <= 0. {
+            return q;
+        }
+        q.z -= v.max(min_step);
+    }
+    na::Point3::new(q.x, q.y, plate)
+}
+
+// Points on the surface of object, closest to the centers of the cells of an octree of its
+// bounding box which contain some of the surface, with the integer indices of those cells.
+// None if the bounding box is not finite.
+fn surface_samples(object: &dyn Object<Float>) -> Option<Vec<([i64; 3], na::Point3<Float>)>> {
+    let bbox = object.bbox();
+    if bounded::is_empty(bbox) {
+        return Some(Vec::new());
+    }
+    if !bounded::is_finite(bbox) {
+        return None;
+    }
+    let tolerance = bbox.dim().norm().max(1.) * measure::PRECISION;
+    let mut samples = Vec::new();
+    let mut cells = vec![(
+        na::center(&bbox.min, &bbox.max),
+        bbox.dim() * 0.5,
+        [0i64; 3],
+        0,
+    )];
+    while let Some((center, half, index, level)) = cells.pop() {
+        let v = object.approx_value(&center, Float::INFINITY);
+        if v.abs() > half.norm() {
+            continue;
+        }
+        if level < SAMPLE_DEPTH {
+            let half = half * 0.5;
+            for i in 0..8 {
+                let bit = |axis: usize| (i >> axis) & 1;
+                let offset = na::Vector3::new(
+                    if bit(0) == 0 { -half.x } else { half.x },
+                    if bit(1) == 0 { -half.y } else { half.y },
+                    if bit(2) == 0 { -half.z } else { half.z },
+                );
+                let index = [
+                    index[0] * 2 + bit(0) as i64,
+                    index[1] * 2 + bit(1) as i64,
+                    index[2] * 2 + bit(2) as i64,
+                ];
+                cells.push((center + offset, half, index, level + 1));
+            }
+            continue;
+        }
+        if let Some(p) = measure::project(object, &center, tolerance) {
+            samples.push((index, p));
+        }
+    }
+    Some(samples)
+}
+
+// The values of samples, grouped by flood fill over neighboring cells.
+fn connected<T: Clone>(samples: HashMap<[i64; 3], T>) -> Vec<Vec<T>> {
+    connected_by(samples, |_, _| true)
+}
+
+// Like connected, joining only neighbors for which same is true.
+fn connected_by<T: Clone, F: Fn(&T, &T) -> bool>(
+    mut samples: HashMap<[i64; 3], T>,
+    same: F,
+) -> Vec<Vec<T>> {
+    let mut groups = Vec::new();
+    while let Some(&start) = samples.keys().next() {
+        let first = samples.remove(&start).expect("no sample");
+        let mut group = vec![first.clone()];
+        let mut queue = vec![(start, first)];
+        while let Some((index, sample)) = queue.pop() {
+            for neighbor in neighbors(index) {
+                if samples
+                    .get(&neighbor)
+                    .is_some_and(|other| same(&sample, other))
+                {
+                    let other = samples.remove(&neighbor).expect("no sample");
+                    group.push(other.clone());
+                    queue.push((neighbor, other));
+                }
+            }
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+fn bbox_of<'a, I: Iterator<Item = &'a na::Point3<Float>>>(points: I) -> BoundingBox<Float> {
+    let mut bbox = BoundingBox::neg_infinity();
+    for p in points {
+        bbox.insert(p);
+    }
+    bbox
+}
+
+// The indices of the 26 cells around the cell with index.
+fn neighbors(index: [i64; 3]) -> Vec<[i64; 3]> {
+    let mut neighbors = Vec::new();
+    for x in -1..2 {
+        for y in -1..2 {
+            for z in -1..2 {
+                if (x, y, z) != (0, 0, 0) {
+                    neighbors.push([index[0] + x, index[1] + y, index[2] + z]);
+                }
+            }
+        }
+    }
+    neighbors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float::consts::PI;
+
+    fn object(script: &str) -> Box<dyn Object<Float>> {
+        ::luascad::eval(script).unwrap().1.unwrap()
+    }
+
+    fn assert_close(a: Float, b: Float, tolerance: Float) {
+        assert!((a - b).abs() <= tolerance, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn spheres_have_their_mass_properties() {
+        let sphere = object("build(Sphere(2):translate(1, 2, 3))");
+        let m = mass_properties(&*sphere, 3.).unwrap();
+        let volume = 4. / 3. * PI * 8.;
+        assert_close(m.volume, volume, volume * 1e-3);
+        assert_close(m.mass, m.volume * 3., 1e-9);
+        assert!((m.centroid - na::Point3::new(1., 2., 3.)).norm() < 1e-3);
+        // 2/5 m r^2 about each axis through the center.
+        let moment = 0.4 * m.mass * 4.;
+        for i in 0..3 {
+            for j in 0..3 {
+                let expected = if i == j { moment } else { 0. };
+                assert_close(m.inertia[(i, j)], expected, moment * 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn thin_walls_have_their_thickness() {
+        let plate = object("build(Box(10, 10, 0.5))");
+        let regions = thin_walls(&*plate, 1.).unwrap();
+        assert!(!regions.is_empty());
+        assert_close(regions[0].thickness, 0.5, 0.05);
+        for region in &regions {
+            assert!(region.thickness < 1.);
+        }
+        let sphere = object("build(Sphere(2))");
+        assert_eq!(thin_walls(&*sphere, 1.).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn tilted_boxes_overhang() {
+        let max_angle = PI / 4.;
+        let upright = object("build(Box(2, 2, 2))");
+        assert_eq!(overhangs(&*upright, max_angle).unwrap(), Vec::new());
+        // Tilted by 30 degrees, the bottom faces down by 60 degrees, and rests on the plate only
+        // along an edge. The face next to it faces down by 30 degrees.
+        let tilted = object("build(Box(2, 2, 2):rotate(math.pi / 6, 0, 0))");
+        let regions = overhangs(&*tilted, max_angle).unwrap();
+        assert_eq!(regions.len(), 1);
+        for p in &regions[0].points {
+            let normal = tilted.normal(p);
+            assert!(is_overhang(&normal, max_angle), "{} at {}", normal, p);
+        }
+    }
+
+    #[test]
+    fn boxes_have_draft_on_their_faces() {
+        let pull = na::Vector3::z();
+        let cube = object("build(Box(2, 2, 2))");
+        let regions = draft_regions(&*cube, &pull).unwrap();
+        assert_eq!(regions.len(), 6);
+        // The four walls first, then the top and the bottom.
+        for (i, region) in regions.iter().enumerate() {
+            let draft = if i < 4 { 0. } else { PI / 2. };
+            assert_close(region.draft, draft, 1e-3);
+            assert_eq!(region.undercut, 0.);
+        }
+    }
+
+    #[test]
+    fn plates_over_each_other_are_undercut() {
+        let pull = na::Vector3::z();
+        let spool = object(
+            "build(Union({Box(4, 4, 1):translate(0, 0, 2), Box(1, 1, 4), \
+             Box(4, 4, 1):translate(0, 0, -2)}))",
+        );
+        let regions = draft_regions(&*spool, &pull).unwrap();
+        assert!(regions[0].undercut > 0.5, "{:?}", regions[0]);
+        let facing = regions[0].normal.dot(&pull);
+        assert!(facing.abs() > 0.99, "{:?}", regions[0]);
+    }
+
+    #[test]
+    fn disjoint_spheres_are_two_components() {
+        let apart = object("build(Union({Sphere(1), Sphere(1):translate(3, 0, 0)}))");
+        let parts = components(&*apart).unwrap();
+        assert_eq!(parts.parts.len(), 2);
+        let both = parts.bbox(0).union(&parts.bbox(1));
+        assert!(both.min.x <= -1. && both.max.x >= 4., "{:?}", both);
+        let joined = object("build(Union({Sphere(1), Sphere(1):translate(1.5, 0, 0)}))");
+        assert_eq!(components(&*joined).unwrap().parts.len(), 1);
+    }
+}
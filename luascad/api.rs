@@ -0,0 +1,108 @@
+use arrange;
+use blend;
+use constraints;
+use edge;
+use editable;
+use include;
+use lobject;
+use lobject_vector;
+use luascad;
+use outline;
+use params;
+use parts;
+use plugin;
+use pointcloud;
+use printbuffer;
+use threads;
+
+// A function scripts can call (or a variable they can read), for the reference of the GUI and
+// truescad --list-api. Each module lists the functions its export_factories defines in a const
+// API next to it, so that the list is updated along with them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Function {
+    // Methods of objects are named like "o:translate".
+    pub name: &'static str,
+    // The arguments as they are passed, e.g. "x, y, z" or "{l, r, s}" for a table. None for
+    // variables.
+    pub args: Option<&'static str>,
+    pub doc: &'static str,
+}
+
+impl Function {
+    // How the function is called, e.g. "Sphere(radius)" or "Cylinder{l, r, s}".
+    pub fn signature(&self) -> String {
+        match self.args {
+            Some(args) if args.starts_with('{') => format!("{}{}", self.name, args),
+            Some(args) => format!("{}({})", self.name, args),
+            None => self.name.to_string(),
+        }
+    }
+    // Whether this is a method of objects.
+    pub fn is_method(&self) -> bool {
+        self.name.contains(':')
+    }
+    // The name it is called by, without the object of methods.
+    pub fn short_name(&self) -> &'static str {
+        match self.name.find(':') {
+            Some(colon) => &self.name[colon + 1..],
+            None => self.name,
+        }
+    }
+    // Whether every word of query is in the signature or the doc, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let text = format!("{} {}", self.signature(), self.doc).to_lowercase();
+        query
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| text.contains(word))
+    }
+}
+
+// All functions by topic, in the order of the reference. The functions of plugins come last,
+// by plugin, see plugin::ObjectProvider.
+pub fn groups() -> Vec<(&'static str, &'static [Function])> {
+    let mut groups = builtin_groups();
+    groups.extend(plugin::providers().iter().map(|p| (p.name(), p.api())));
+    groups
+}
+
+// The functions of truescad by topic, without those of plugins.
+pub fn builtin_groups() -> Vec<(&'static str, &'static [Function])> {
+    vec![
+        ("Script", luascad::API),
+        ("Output", printbuffer::API),
+        ("Parameters", params::API),
+        ("Includes", include::API),
+        ("Primitives", lobject::API),
+        ("Booleans", lobject_vector::API),
+        ("Blends", blend::API),
+        ("Edges", edge::API),
+        ("Object methods", lobject::METHODS),
+        ("Editing in the view", editable::API),
+        ("Assemblies", constraints::API),
+        ("Parts", parts::API),
+        ("Build plate", arrange::API),
+        ("Threads", threads::API),
+        ("Imports", outline::API),
+        ("Point clouds", pointcloud::API),
+    ]
+}
+
+// The functions, or the methods, whose name starts with prefix, for completion in the editor.
+pub fn complete(prefix: &str, method: bool) -> Vec<&'static Function> {
+    let mut functions: Vec<_> = groups()
+        .into_iter()
+        .flat_map(|(_, functions)| functions.iter())
+        .filter(|f| f.is_method() == method && f.short_name().starts_with(prefix))
+        .collect();
+    functions.sort_by_key(|f| f.short_name());
+    functions
+}
+
+// The function, or the method, called name.
+pub fn find(name: &str, method: bool) -> Option<&'static Function> {
+    groups()
+        .into_iter()
+        .flat_map(|(_, functions)| functions.iter())
+        .find(|f| f.is_method() == method && f.short_name() == name)
+}
@@ -0,0 +1,152 @@
+use super::Float;
+use api::Function;
+use bounded;
+use error::{self, PrimitiveError};
+use hlua::{self, AnyLuaValue};
+use lobject;
+use lobject_vector::LObjectVector;
+use nalgebra as na;
+
+// Arranging parts on a build plate: their footprints, the boxes they cover in x and y, are
+// packed into rows from the corner of the plate at the origin, the deepest ones first, and each
+// part is set down onto z = 0.
+
+pub const API: &[Function] = &[Function {
+    name: "arrange",
+    args: Some("{parts, plate = 220, spacing = 5}"),
+    doc: "The parts (e.g. from o:components or o:split) laid out on a plate from the origin to plate (a size, or {width, depth}) with spacing between them, resting on z = 0. Returns their union and a table of {translate = {x, y, z}, min = {x, y}, max = {x, y}} for each part.",
+}];
+
+// The lower left corners of footprints of sizes packed into rows on plate, spacing apart, in
+// the order of sizes. Err with the index of the first part (in the order they are packed) which
+// does not fit.
+pub fn pack(
+    sizes: &[na::Vector2<Float>],
+    plate: na::Vector2<Float>,
+    spacing: Float,
+) -> Result<Vec<na::Point2<Float>>, usize> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| {
+        (sizes[b].y, sizes[b].x)
+            .partial_cmp(&(sizes[a].y, sizes[a].x))
+            .unwrap()
+    });
+    // The rows as their y, depth and how far they are filled along x.
+    let mut rows: Vec<(Float, Float, Float)> = Vec::new();
+    let mut corners = vec![na::Point2::origin(); sizes.len()];
+    for i in order {
+        let size = sizes[i];
+        let row = match rows
+            .iter()
+            .position(|&(_, depth, x)| size.y <= depth && x + size.x <= plate.x)
+        {
+            Some(row) => row,
+            None => {
+                let y = rows.last().map_or(0., |&(y, depth, _)| y + depth + spacing);
+                if size.x > plate.x || y + size.y > plate.y {
+                    return Err(i);
+                }
+                rows.push((y, size.y, 0.));
+                rows.len() - 1
+            }
+        };
+        let row = &mut rows[row];
+        corners[i] = na::Point2::new(row.2, row.0);
+        row.2 += size.x + spacing;
+    }
+    Ok(corners)
+}
+
+// The placements of the parts of ov on a plate of width and depth, see API.
+fn arrange(
+    ov: &LObjectVector,
+    width: Float,
+    depth: Float,
+    spacing: Float,
+) -> Result<AnyLuaValue, PrimitiveError> {
+    error::positive("arrange", "plate", width)?;
+    error::positive("arrange", "plate", depth)?;
+    error::non_negative("arrange", "spacing", spacing)?;
+    let boxes: Vec<_> = ov.objects().iter().map(|o| o.object().bbox()).collect();
+    if !boxes.iter().all(|b| bounded::is_finite(b)) {
+        return Err(PrimitiveError::Unbounded("arrange"));
+    }
+    let sizes: Vec<na::Vector2<Float>> = boxes
+        .iter()
+        .map(|b| na::Vector2::new(b.max.x - b.min.x, b.max.y - b.min.y))
+        .collect();
+    let corners = pack(&sizes, na::Vector2::new(width, depth), spacing).map_err(|i| {
+        PrimitiveError::NoRoom {
+            part: i + 1,
+            width,
+            depth,
+        }
+    })?;
+    let numbers = |values: &[Float]| {
+        AnyLuaValue::LuaArray(
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, &v)| {
+                    (
+                        AnyLuaValue::LuaNumber((i + 1) as f64),
+                        lobject::lua_number(v),
+                    )
+                })
+                .collect(),
+        )
+    };
+    let field = |name: &str, value| (AnyLuaValue::LuaString(name.to_string()), value);
+    Ok(AnyLuaValue::LuaArray(
+        boxes
+            .iter()
+            .zip(&corners)
+            .zip(&sizes)
+            .enumerate()
+            .map(|(i, ((b, corner), size))| {
+                let placement = AnyLuaValue::LuaArray(vec![
+                    field(
+                        "translate",
+                        numbers(&[corner.x - b.min.x, corner.y - b.min.y, -b.min.z]),
+                    ),
+                    field("min", numbers(&[corner.x, corner.y])),
+                    field("max", numbers(&[corner.x + size.x, corner.y + size.y])),
+                ]);
+                (AnyLuaValue::LuaNumber((i + 1) as f64), placement)
+            })
+            .collect(),
+    ))
+}
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.set(
+        "__arrange",
+        hlua::function4(
+            |ov: &LObjectVector, width: Float, depth: Float, spacing: Float| {
+                arrange(ov, width, depth, spacing)
+            },
+        ),
+    );
+    lua.execute::<()>(&format!(
+        "
+        function arrange(t)
+          local parts = t.parts or t[1]
+          local plate = t.plate or 220
+          local width, depth = plate, plate
+          if type(plate) == \"table\" then
+            width, depth = plate[1], plate[2]
+          end
+          local placements = __arrange(__array_to_ov(parts), width, depth, t.spacing or 5)
+          local placed = {{}}
+          for i, part in ipairs(parts) do
+            local move = placements[i].translate
+            placed[i] = part:translate(move[1], move[2], move[3])
+          end
+          return Union(placed), placements
+        end
+
+        {env}.arrange = arrange;",
+        env = env_name
+    ))
+    .unwrap();
+}
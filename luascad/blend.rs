@@ -0,0 +1,147 @@
+use super::{Float, RELATIVE_STEP};
+use api::Function;
+use error::{self, PrimitiveError};
+use hlua;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use lobject::LObject;
+use nalgebra as na;
+use tree::Node;
+
+// The value of a boolean which is smooth inside a region and sharp beyond width outside of it,
+// fading linearly in between.
+pub fn value(sharp: Float, blended: Float, region: Float, width: Float) -> Float {
+    let weight = (region / width).clamp(0., 1.);
+    blended + (sharp - blended) * weight
+}
+
+// A boolean rounded only where it is close to a region, e.g. a sphere around one joint.
+#[derive(Clone, Debug)]
+pub struct LocalBlend {
+    sharp: Box<dyn Object<Float>>,
+    blended: Box<dyn Object<Float>>,
+    region: Box<dyn Object<Float>>,
+    width: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl LocalBlend {
+    pub fn new(
+        sharp: Box<dyn Object<Float>>,
+        blended: Box<dyn Object<Float>>,
+        region: Box<dyn Object<Float>>,
+        width: Float,
+    ) -> Box<LocalBlend> {
+        let bbox = sharp.bbox().union(blended.bbox());
+        Box::new(LocalBlend {
+            sharp,
+            blended,
+            region,
+            width,
+            bbox,
+        })
+    }
+}
+
+impl Object<Float> for LocalBlend {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx > slack {
+            return approx;
+        }
+        // Far from the region only the sharp boolean is needed.
+        let region = self.region.approx_value(p, self.width);
+        if region >= self.width {
+            return self.sharp.approx_value(p, slack);
+        }
+        let blended = self.blended.approx_value(p, slack);
+        if region <= 0. {
+            return blended;
+        }
+        value(
+            self.sharp.approx_value(p, slack),
+            blended,
+            region,
+            self.width,
+        )
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.sharp.set_parameters(p);
+        self.blended.set_parameters(p);
+        self.region.set_parameters(p);
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.width * RELATIVE_STEP;
+        let difference = |d: na::Vector3<Float>| {
+            self.approx_value(&(p + d), 0.) - self.approx_value(&(p - d), 0.)
+        };
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            difference(na::Vector3::z() * e),
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
+
+// sharp inside of region replaced by blended, the same boolean rounded by smooth. The rounding
+// fades out over smooth outside of the region.
+pub fn local_blend(
+    sharp: &LObject,
+    blended: &LObject,
+    region: &LObject,
+    smooth: Float,
+) -> Result<LObject, PrimitiveError> {
+    let width = error::positive("LocalBlend", "smooth", smooth)?;
+    match (sharp.node(), region.node()) {
+        (&Node::Empty, _) | (&Node::Full, _) | (_, &Node::Empty) => return Ok(sharp.clone()),
+        (_, &Node::Full) => return Ok(blended.clone()),
+        _ => {}
+    }
+    Ok(LObject::new(
+        LocalBlend::new(
+            sharp.as_object(),
+            blended.as_object(),
+            region.as_object(),
+            width,
+        ),
+        Node::LocalBlend {
+            children: vec![sharp.as_node(), blended.as_node(), region.as_node()],
+            width,
+        },
+    ))
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "LocalBlend",
+        args: Some("boolean, objects, smooth, region"),
+        doc: "boolean (Union, Intersection or Difference) of objects, rounded by smooth only inside region.",
+    },
+];
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.set(
+        "__local_blend",
+        hlua::function4(
+            |sharp: &LObject, blended: &LObject, region: &LObject, smooth: Float| {
+                local_blend(sharp, blended, region, smooth)
+            },
+        ),
+    );
+    // boolean is Union, Intersection or Difference, built once sharp and once rounded.
+    lua.execute::<()>(&format!(
+        "
+        function LocalBlend(boolean, lobjects, smooth, region)
+          return __local_blend(boolean(lobjects, 0), boolean(lobjects, smooth), region, smooth)
+        end
+        {env}.LocalBlend = LocalBlend
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
@@ -0,0 +1,331 @@
+use super::Float;
+use implicit3d::{
+    BoundingBox, Intersection, Object, PlaneNegX, PlaneNegY, PlaneNegZ, PlaneX, PlaneY, PlaneZ,
+    PrimitiveParameters,
+};
+use nalgebra as na;
+use transformer;
+use tree::Node;
+
+// An object with a tighter bounding box than its own, e.g. a rotated union with the box around
+// its rotated parts instead of the box around its rotated box (see LObject::rotate).
+// Transformations keep the box around the transformed tighter box, where that is still tighter.
+#[derive(Clone, Debug)]
+pub struct Bounded {
+    object: Box<dyn Object<Float>>,
+    bbox: BoundingBox<Float>,
+}
+
+impl Bounded {
+    pub fn new(object: Box<dyn Object<Float>>, bbox: &BoundingBox<Float>) -> Box<Bounded> {
+        // The boxes implicit3d gives transformed infinite objects can be wrong, see transformer.
+        let bbox = if is_finite(object.bbox()) {
+            intersection(bbox, object.bbox())
+        } else {
+            bbox.clone()
+        };
+        Box::new(Bounded { object, bbox })
+    }
+    // object is the transformed object, m maps points of this one to it.
+    fn transformed(
+        &self,
+        object: Box<dyn Object<Float>>,
+        m: &na::Matrix4<Float>,
+    ) -> Box<dyn Object<Float>> {
+        Bounded::new(object, &transform(&self.bbox, m))
+    }
+}
+
+impl Object<Float> for Bounded {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx <= slack {
+            self.object.approx_value(p, slack)
+        } else {
+            approx
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.object.normal(p)
+    }
+    // The inverses of the transformations implicit3d evaluates the object at.
+    fn translate(&self, v: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+        self.transformed(
+            transformer::translate(&*self.object, v),
+            &na::Matrix4::new_translation(v),
+        )
+    }
+    fn rotate(&self, r: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+        let rotation = na::Rotation3::from_euler_angles(r.x, r.y, r.z).inverse();
+        self.transformed(
+            transformer::rotate(&*self.object, r),
+            &rotation.to_homogeneous(),
+        )
+    }
+    fn scale(&self, s: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+        self.transformed(
+            transformer::scale(&*self.object, s),
+            &na::Matrix4::new_nonuniform_scaling(s),
+        )
+    }
+}
+
+// The box around bbox transformed by the affine transformation m. Unlike BoundingBox::transform,
+// which transforms the corners, this keeps infinite extents: it never multiplies them by zero or
+// adds infinities of opposite signs.
+pub fn transform(bbox: &BoundingBox<Float>, m: &na::Matrix4<Float>) -> BoundingBox<Float> {
+    if is_empty(bbox) {
+        return BoundingBox::neg_infinity();
+    }
+    let mut min = na::Point3::origin();
+    let mut max = na::Point3::origin();
+    for i in 0..3 {
+        min[i] = m[(i, 3)];
+        max[i] = m[(i, 3)];
+        for j in 0..3 {
+            let f = m[(i, j)];
+            if f != 0. {
+                let (a, b) = (f * bbox.min[j], f * bbox.max[j]);
+                min[i] += a.min(b);
+                max[i] += a.max(b);
+            }
+        }
+    }
+    BoundingBox::new(&min, &max)
+}
+
+pub fn is_finite(bbox: &BoundingBox<Float>) -> bool {
+    bbox.min.coords.iter().all(|x| x.is_finite()) && bbox.max.coords.iter().all(|x| x.is_finite())
+}
+
+// Also for boxes with NaNs.
+pub fn is_empty(bbox: &BoundingBox<Float>) -> bool {
+    !(0..3).all(|i| bbox.min[i] <= bbox.max[i])
+}
+
+// The box covered by both a and b, empty (BoundingBox::neg_infinity) if they do not overlap.
+pub fn intersection(a: &BoundingBox<Float>, b: &BoundingBox<Float>) -> BoundingBox<Float> {
+    let result = a.intersection(b);
+    if is_empty(&result) {
+        BoundingBox::neg_infinity()
+    } else {
+        result
+    }
+}
+
+// The corners of bbox, corner c at the maximum along the axes whose bits are set in c (x 1, y 2,
+// z 4) and at the minimum along the others.
+pub fn corners(bbox: &BoundingBox<Float>) -> [na::Point3<Float>; 8] {
+    let (min, max) = (&bbox.min, &bbox.max);
+    let mut corners = [na::Point3::origin(); 8];
+    for (c, corner) in corners.iter_mut().enumerate() {
+        *corner = na::Point3::new(
+            if c & 1 == 0 { min.x } else { max.x },
+            if c & 2 == 0 { min.y } else { max.y },
+            if c & 4 == 0 { min.z } else { max.z },
+        );
+    }
+    corners
+}
+
+// Where the line through origin along dir enters and leaves bbox, as multiples of dir from
+// origin (negative behind it), None if it misses the box. Infinite boxes and directions parallel
+// to their sides are fine.
+pub fn clip_ray(
+    bbox: &BoundingBox<Float>,
+    origin: &na::Point3<Float>,
+    dir: &na::Vector3<Float>,
+) -> Option<(Float, Float)> {
+    if is_empty(bbox) {
+        return None;
+    }
+    let (mut near, mut far) = (Float::NEG_INFINITY, Float::INFINITY);
+    for i in 0..3 {
+        if dir[i] == 0. {
+            // Parallel to the slab, the line is either in it everywhere or nowhere.
+            if origin[i] < bbox.min[i] || origin[i] > bbox.max[i] {
+                return None;
+            }
+            continue;
+        }
+        let (a, b) = (
+            (bbox.min[i] - origin[i]) / dir[i],
+            (bbox.max[i] - origin[i]) / dir[i],
+        );
+        near = near.max(a.min(b));
+        far = far.min(a.max(b));
+    }
+    if near <= far {
+        Some((near, far))
+    } else {
+        None
+    }
+}
+
+// An object and its tree, cut by clip, crop or region_of_interest.
+pub type Cut = (Box<dyn Object<Float>>, Option<Node>);
+
+// object (and its tree) cut off at the sides of region where it is infinite, for tessellation and
+// export, which need finite objects.
+pub fn clip(
+    object: Box<dyn Object<Float>>,
+    tree: Option<Node>,
+    region: &BoundingBox<Float>,
+) -> Cut {
+    let bbox = object.bbox().clone();
+    cut(object, tree, region, |i, max| {
+        if max {
+            bbox.max[i].is_infinite()
+        } else {
+            bbox.min[i].is_infinite()
+        }
+    })
+}
+
+// object (and its tree) cut off at all sides of region.
+pub fn crop(
+    object: Box<dyn Object<Float>>,
+    tree: Option<Node>,
+    region: &BoundingBox<Float>,
+) -> Cut {
+    cut(object, tree, region, |_, _| true)
+}
+
+// object (and its tree) cropped to region, closed with flat caps where it is cut, to tessellate
+// only that part of it. Err if region does not overlap the object.
+pub fn region_of_interest(
+    object: Box<dyn Object<Float>>,
+    tree: Option<Node>,
+    region: &BoundingBox<Float>,
+) -> Result<Cut, String> {
+    if is_empty(&intersection(object.bbox(), region)) {
+        return Err("the tessellation region does not overlap the object".to_string());
+    }
+    Ok(crop(object, tree, region))
+}
+
+// object cut off at the sides of region for which side(axis, max) is true.
+fn cut<F: Fn(usize, bool) -> bool>(
+    object: Box<dyn Object<Float>>,
+    tree: Option<Node>,
+    region: &BoundingBox<Float>,
+    side: F,
+) -> Cut {
+    let mut objects = vec![object];
+    let mut nodes = Vec::new();
+    for i in 0..3 {
+        let mut normal = na::Vector3::zeros();
+        normal[i] = 1.;
+        if side(i, true) {
+            let d = region.max[i];
+            objects.push(match i {
+                0 => Box::new(PlaneX::new(d)) as Box<dyn Object<Float>>,
+                1 => Box::new(PlaneY::new(d)),
+                _ => Box::new(PlaneZ::new(d)),
+            });
+            nodes.push(Node::Plane { normal, p: d });
+        }
+        if side(i, false) {
+            let d = -region.min[i];
+            objects.push(match i {
+                0 => Box::new(PlaneNegX::new(d)) as Box<dyn Object<Float>>,
+                1 => Box::new(PlaneNegY::new(d)),
+                _ => Box::new(PlaneNegZ::new(d)),
+            });
+            nodes.push(Node::Plane {
+                normal: -normal,
+                p: d,
+            });
+        }
+    }
+    if nodes.is_empty() {
+        return (objects.remove(0), tree);
+    }
+    let clipped = Intersection::from_vec(objects, 0.).expect("no objects to clip");
+    let tree = tree.map(|tree| {
+        nodes.insert(0, tree);
+        Node::Intersection {
+            children: nodes,
+            smooth: 0.,
+        }
+    });
+    (clipped, tree)
+}
+
+// Bounding box of Twister::new(object, height): the xy extent of the box swept by the angles it is
+// twisted by over its z extent. implicit3d uses the full circle around the farthest corner.
+pub fn twisted_bbox(bbox: &BoundingBox<Float>, height: Float) -> BoundingBox<Float> {
+    let (min, max) = (&bbox.min, &bbox.max);
+    // Twister evaluates the object at p rotated by p.z * 2 * pi / height.
+    let scaler = -2. * ::float::consts::PI / height;
+    let (a, b) = (min.z * scaler, max.z * scaler);
+    let corners = [
+        (min.x, min.y),
+        (min.x, max.y),
+        (max.x, min.y),
+        (max.x, max.y),
+    ];
+    swept_bbox(&corners, a.min(b), a.max(b), min.z, max.z)
+}
+
+// Bounding box of Bender::new(object, width): the sector of the ring between the y extent of the
+// box, over the angles its x extent is bent to. implicit3d uses the full circle.
+pub fn bent_bbox(bbox: &BoundingBox<Float>, width: Float) -> BoundingBox<Float> {
+    let (min, max) = (&bbox.min, &bbox.max);
+    if max.y <= 0. {
+        return BoundingBox::infinity();
+    }
+    // Bender evaluates the object at x = atan2(p.x, -p.y) * width / (2 * pi), y = |p.xy|, so
+    // the angle from the x axis is x * 2 * pi / width - pi / 2, within one turn.
+    let pi = ::float::consts::PI;
+    let angle = |x: Float| (x * 2. * pi / width).max(-pi).min(pi) - pi / 2.;
+    let (a, b) = (angle(min.x), angle(max.x));
+    let radii = [(min.y.max(0.), 0.), (max.y, 0.)];
+    swept_bbox(&radii, a.min(b), a.max(b), min.z, max.z)
+}
+
+// Box around points in the xy plane rotated by all angles from a to b, from z0 to z1.
+fn swept_bbox(
+    points: &[(Float, Float)],
+    a: Float,
+    b: Float,
+    z0: Float,
+    z1: Float,
+) -> BoundingBox<Float> {
+    if !points.iter().all(|&(x, y)| x.is_finite() && y.is_finite()) {
+        return BoundingBox::infinity();
+    }
+    let pi = ::float::consts::PI;
+    // A full turn (or more, e.g. for infinite z extents).
+    let (a, b) = if b - a < 2. * pi {
+        (a, b)
+    } else {
+        (0., 2. * pi)
+    };
+    let mut bbox = BoundingBox::neg_infinity();
+    for &(x, y) in points {
+        let (r, phi) = (x.hypot(y), y.atan2(x));
+        for &angle in &[a, b] {
+            let (sin, cos) = (phi + angle).sin_cos();
+            bbox.insert(&na::Point3::new(r * cos, r * sin, z0));
+        }
+        // The arc reaches the axes at the multiples of pi / 2 it passes.
+        for k in 0..4 {
+            let axis = k as Float * pi / 2.;
+            if (axis - phi - a).rem_euclid(2. * pi) <= b - a {
+                let (sin, cos) = axis.sin_cos();
+                bbox.insert(&na::Point3::new(r * cos, r * sin, z0));
+            }
+        }
+    }
+    let mut top = bbox.min;
+    top.z = z1;
+    bbox.insert(&top);
+    bbox
+}
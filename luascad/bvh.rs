@@ -0,0 +1,278 @@
+use super::Float;
+use bounded;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters, Union};
+use nalgebra as na;
+use normals::{self, NormalEstimate};
+
+// Unions with at least this many children are built as BvhUnion.
+const MIN_CHILDREN: usize = 8;
+
+// Same as in implicit3d.
+const FADE_RANGE: Float = 0.1;
+const ALWAYS_PRECISE: Float = 1.;
+#[cfg(not(feature = "f32"))]
+const NORMAL_EPSILON: Float = 1e-10;
+#[cfg(feature = "f32")]
+const NORMAL_EPSILON: Float = 1e-4;
+
+// Drop-in for implicit3d::Union::from_vec, which uses a BvhUnion for many children.
+pub fn union_from_vec(v: Vec<Box<dyn Object<Float>>>, r: Float) -> Option<Box<dyn Object<Float>>> {
+    if v.len() < MIN_CHILDREN {
+        Union::from_vec(v, r)
+    } else {
+        Some(Box::new(BvhUnion::new(v, r)))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum BvhNode {
+    Leaf {
+        bbox: BoundingBox<Float>,
+        object: usize,
+    },
+    Inner {
+        bbox: BoundingBox<Float>,
+        children: (usize, usize),
+    },
+}
+
+impl BvhNode {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        match *self {
+            BvhNode::Leaf { ref bbox, .. } | BvhNode::Inner { ref bbox, .. } => bbox,
+        }
+    }
+}
+
+// Same as implicit3d::Union, but with a bounding volume hierarchy over the children.
+// Children whose bounding box is further away than the smallest value so far (plus the smoothing
+// range) can not contribute and are skipped, so only few children are evaluated per point.
+// This gives the same values as implicit3d::Union for children whose values are at least the
+// distance to their bounding box, as for exact distance fields.
+// Intersections can not be pruned like this, since every child may have the largest value.
+#[derive(Clone, Debug)]
+pub struct BvhUnion {
+    objs: Vec<Box<dyn Object<Float>>>,
+    // Children with infinite bounding boxes (e.g. planes), which are always evaluated.
+    unbounded: Vec<usize>,
+    // Root first.
+    nodes: Vec<BvhNode>,
+    r: Float,
+    exact_range: Float,
+    fade_range: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl BvhUnion {
+    pub fn new(objs: Vec<Box<dyn Object<Float>>>, r: Float) -> BvhUnion {
+        let mut bbox = objs
+            .iter()
+            .fold(BoundingBox::neg_infinity(), |union_box, x| {
+                union_box.union(x.bbox())
+            });
+        // Like implicit3d::Union.
+        bbox.dilate(r * 0.2);
+        let (mut bounded, unbounded): (Vec<usize>, Vec<usize>) =
+            (0..objs.len()).partition(|&i| bounded::is_finite(objs[i].bbox()));
+        let mut union = BvhUnion {
+            objs,
+            unbounded,
+            nodes: Vec::new(),
+            r,
+            exact_range: r,
+            fade_range: FADE_RANGE,
+            bbox,
+        };
+        if !bounded.is_empty() {
+            union.build(&mut bounded);
+        }
+        union
+    }
+    // Add the subtree over objects, split at the median along the axis in which their centers
+    // are spread most. Returns its index.
+    fn build(&mut self, objects: &mut [usize]) -> usize {
+        let index = self.nodes.len();
+        if objects.len() == 1 {
+            self.nodes.push(BvhNode::Leaf {
+                bbox: self.objs[objects[0]].bbox().clone(),
+                object: objects[0],
+            });
+            return index;
+        }
+        let bbox = objects
+            .iter()
+            .fold(BoundingBox::neg_infinity(), |union_box, &i| {
+                union_box.union(self.objs[i].bbox())
+            });
+        self.nodes.push(BvhNode::Inner {
+            bbox,
+            children: (0, 0),
+        });
+        let mut centers = BoundingBox::neg_infinity();
+        for &i in objects.iter() {
+            centers.insert(&center(self.objs[i].bbox()));
+        }
+        let axis = centers.dim().iamax();
+        objects.sort_by(|&a, &b| {
+            let (a, b) = (center(self.objs[a].bbox()), center(self.objs[b].bbox()));
+            a[axis].partial_cmp(&b[axis]).unwrap()
+        });
+        let (left, right) = objects.split_at_mut(objects.len() / 2);
+        let children = (self.build(left), self.build(right));
+        if let BvhNode::Inner {
+            children: ref mut c,
+            ..
+        } = self.nodes[index]
+        {
+            *c = children;
+        }
+        index
+    }
+    // Push the values of all children under node that may be less than limit + range onto
+    // values, nearest first. limit is lowered to the smallest value found.
+    fn collect(
+        &self,
+        node: usize,
+        p: &na::Point3<Float>,
+        slack: Float,
+        range: Float,
+        limit: &mut Float,
+        values: &mut Vec<(usize, Float)>,
+    ) {
+        if self.nodes[node].bbox().distance(p) > *limit + range {
+            return;
+        }
+        match self.nodes[node] {
+            BvhNode::Leaf { object, .. } => {
+                let value = self.objs[object].approx_value(p, slack);
+                *limit = limit.min(value);
+                values.push((object, value));
+            }
+            BvhNode::Inner {
+                children: (a, b), ..
+            } => {
+                let (a, b) = if self.nodes[a].bbox().distance(p) <= self.nodes[b].bbox().distance(p)
+                {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                self.collect(a, p, slack, range, limit, values);
+                self.collect(b, p, slack, range, limit, values);
+            }
+        }
+    }
+    // Values of all children that may matter at p: within range of the smallest one.
+    fn values(&self, p: &na::Point3<Float>, slack: Float, range: Float) -> Vec<(usize, Float)> {
+        let mut values: Vec<_> = self
+            .unbounded
+            .iter()
+            .map(|&i| (i, self.objs[i].approx_value(p, slack)))
+            .collect();
+        let mut limit = values
+            .iter()
+            .fold(Float::INFINITY, |min, &(_, v)| min.min(v));
+        if !self.nodes.is_empty() {
+            self.collect(0, p, slack, range, &mut limit, &mut values);
+        }
+        values
+    }
+}
+
+impl Object<Float> for BvhUnion {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx <= slack {
+            let values: Vec<Float> = self
+                .values(p, slack + self.r, self.r.max(self.exact_range))
+                .iter()
+                .map(|&(_, v)| v)
+                .collect();
+            rvmin(&values, self.r, self.exact_range)
+        } else {
+            approx
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.exact_range = self.r * p.r_multiplier;
+        self.fade_range = p.fade_range;
+        for o in &mut self.objs {
+            o.set_parameters(p);
+        }
+    }
+    // Same as implicit3d::Union::normal.
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        // Find the two smallest values with their indices. range 0 is enough for the smallest,
+        // but the second smallest may be up to exact_range further.
+        let values = self.values(p, ALWAYS_PRECISE, self.exact_range);
+        let (v0, v1) = values.iter().fold(
+            ((0, Float::INFINITY), (0, Float::INFINITY)),
+            |(v0, v1), &x| {
+                if x.1 < v0.1 {
+                    (x, v0)
+                } else if x.1 < v1.1 {
+                    (v0, x)
+                } else {
+                    (v0, v1)
+                }
+            },
+        );
+        match (v0.1 - v1.1).abs() {
+            // if they are close together, calc normal from full object
+            diff if diff < (self.exact_range * (1. - self.fade_range)) => {
+                normal_from_object(self, p)
+            }
+            diff if diff < self.exact_range => {
+                let fader = (diff / self.exact_range - 1. + self.fade_range) / self.fade_range;
+                (self.objs[v0.0].normal(p) * fader + normal_from_object(self, p) * (1. - fader))
+                    .normalize()
+            }
+            // they are far apart, use the min's normal
+            _ => self.objs[v0.0].normal(p),
+        }
+    }
+}
+
+fn center(bbox: &BoundingBox<Float>) -> na::Point3<Float> {
+    na::center(&bbox.min, &bbox.max)
+}
+
+// Same as rvmin in implicit3d.
+fn rvmin(v: &[Float], r: Float, exact_range: Float) -> Float {
+    let mut close_min = false;
+    let minimum = v.iter().fold(Float::INFINITY, |min, &x| {
+        if x < min {
+            close_min = (min - x) < exact_range;
+            x
+        } else {
+            if (x - min) < exact_range {
+                close_min = true;
+            }
+            min
+        }
+    });
+    if !close_min {
+        return minimum;
+    }
+    let min_plus_r = minimum + r;
+    let r4 = r / 4.;
+    let exp_sum = v
+        .iter()
+        .filter(|&&x| x < min_plus_r)
+        .fold(0., |sum, x| sum + (-x / r4).exp());
+    exp_sum.ln() * -r4
+}
+
+// Like normal_from_object in implicit3d, with central differences, which unlike its one-sided
+// ones are accurate near edges.
+fn normal_from_object(f: &dyn Object<Float>, p: &na::Point3<Float>) -> na::Vector3<Float> {
+    normals::estimate(
+        NormalEstimate::Central,
+        |q| f.approx_value(q, ALWAYS_PRECISE),
+        p,
+        NORMAL_EPSILON,
+    )
+}
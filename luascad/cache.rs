@@ -0,0 +1,127 @@
+use super::Float;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// The values are spread over this many shards, each with a lock of its own, so that threads
+// rendering or tessellating the object rarely wait for each other.
+const SHARDS: usize = 64;
+// A shard keeps at most this many values in each of its two generations, see Shard.
+const MAX_SHARD_ENTRIES: usize = (1 << 20) / SHARDS;
+
+// Grid point, in multiples of the quantum.
+type Key = (i64, i64, i64);
+// Value and whether it is exact (not just an approximation above slack).
+type Entry = (Float, bool);
+
+// A part of the cache. Values go into recent, and when it is full, it replaces old, which is
+// dropped. Values found in old move back into recent, so that those used since the last
+// replacement survive it, roughly like least recently used eviction without tracking every use.
+#[derive(Debug, Default)]
+struct Shard {
+    recent: HashMap<Key, Entry>,
+    old: HashMap<Key, Entry>,
+}
+
+impl Shard {
+    fn get(&mut self, key: &Key) -> Option<Entry> {
+        if let Some(&entry) = self.recent.get(key) {
+            return Some(entry);
+        }
+        let entry = self.old.remove(key)?;
+        self.insert(*key, entry);
+        Some(entry)
+    }
+    fn insert(&mut self, key: Key, entry: Entry) {
+        if self.recent.len() >= MAX_SHARD_ENTRIES {
+            self.old = ::std::mem::take(&mut self.recent);
+        }
+        self.recent.insert(key, entry);
+    }
+}
+
+// Remembers the values of an expensive object (e.g. a Mesh) on a grid.
+// Points are snapped to the closest grid point, so that nearby evaluations (like the corners of
+// neighboring cells during tessellation) share values. Values are off by at most
+// quantum * sqrt(3) / 2. Normals are not cached.
+#[derive(Debug)]
+pub struct Cache {
+    object: Box<dyn Object<Float>>,
+    quantum: Float,
+    shards: Vec<Mutex<Shard>>,
+}
+
+fn shards() -> Vec<Mutex<Shard>> {
+    (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect()
+}
+
+impl Cache {
+    pub fn new(object: Box<dyn Object<Float>>, quantum: Float) -> Box<Cache> {
+        Box::new(Cache {
+            object,
+            quantum,
+            shards: shards(),
+        })
+    }
+    // The shard of key. Neighboring grid points go to different shards.
+    fn shard(&self, key: &Key) -> &Mutex<Shard> {
+        let hash = (key.0 as u64)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            .wrapping_add((key.1 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F))
+            .wrapping_add((key.2 as u64).wrapping_mul(0x1656_67B1_9E37_79F9));
+        &self.shards[(hash >> 32) as usize % SHARDS]
+    }
+}
+
+// Clones start with an empty cache.
+impl Clone for Cache {
+    fn clone(&self) -> Cache {
+        Cache {
+            object: self.object.clone(),
+            quantum: self.quantum,
+            shards: shards(),
+        }
+    }
+}
+
+impl Object<Float> for Cache {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        self.object.bbox()
+    }
+    fn set_bbox(&mut self, bbox: &BoundingBox<Float>) {
+        self.object.set_bbox(bbox)
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+        self.shards = shards();
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox().distance(p);
+        if approx > slack {
+            return approx;
+        }
+        let key = (
+            (p.x / self.quantum).round() as i64,
+            (p.y / self.quantum).round() as i64,
+            (p.z / self.quantum).round() as i64,
+        );
+        let shard = self.shard(&key);
+        if let Some((value, exact)) = shard.lock().unwrap().get(&key) {
+            if exact || value > slack {
+                return value;
+            }
+        }
+        let grid_point = na::Point3::new(
+            key.0 as Float * self.quantum,
+            key.1 as Float * self.quantum,
+            key.2 as Float * self.quantum,
+        );
+        let value = self.object.approx_value(&grid_point, slack);
+        shard.lock().unwrap().insert(key, (value, value <= slack));
+        value
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.object.normal(p)
+    }
+}
@@ -0,0 +1,74 @@
+use super::Float;
+use analysis::Components;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+// One of the parts of an object found by analysis::components, for parts which are not
+// separated from the others by their bounding box (see LObject::components). It is the object
+// within the cells of the part and the cells next to them. Everywhere else, the other parts are
+// at least a cell away, or they would be connected to this one, and so is this one.
+#[derive(Clone, Debug)]
+pub struct Component {
+    object: Box<dyn Object<Float>>,
+    components: Arc<Components>,
+    // The cells of the part and their neighbors.
+    cells: Arc<HashSet<[i64; 3]>>,
+    // The smallest side of the cells.
+    gap: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl Component {
+    pub fn new(
+        object: Box<dyn Object<Float>>,
+        components: Arc<Components>,
+        part: usize,
+    ) -> Box<Component> {
+        let mut cells = HashSet::new();
+        for index in &components.parts[part] {
+            for x in -1..2 {
+                for y in -1..2 {
+                    for z in -1..2 {
+                        cells.insert([index[0] + x, index[1] + y, index[2] + z]);
+                    }
+                }
+            }
+        }
+        let bbox = components.bbox(part).intersection(object.bbox());
+        let cell = &components.cell;
+        let gap = cell.x.min(cell.y).min(cell.z);
+        Box::new(Component {
+            object,
+            components,
+            cells: Arc::new(cells),
+            gap,
+            bbox,
+        })
+    }
+}
+
+impl Object<Float> for Component {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx > slack {
+            return approx;
+        }
+        let v = self.object.approx_value(p, slack);
+        if self.cells.contains(&self.components.index(p)) {
+            v
+        } else {
+            v.max(self.gap).max(approx)
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.object.normal(p)
+    }
+}
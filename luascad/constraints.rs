@@ -0,0 +1,762 @@
+use super::Float;
+use api::Function;
+use error::PrimitiveError;
+use hlua;
+use hlua::AnyLuaValue;
+use lobject;
+use nalgebra as na;
+use std::collections::BTreeMap;
+
+// solve gives up after this many iterations.
+const MAX_ITERATIONS: usize = 200;
+// Step of the finite differences of the residuals.
+#[cfg(not(feature = "f32"))]
+const DIFFERENCE_STEP: Float = 1e-7;
+#[cfg(feature = "f32")]
+const DIFFERENCE_STEP: Float = 1e-3;
+// solve stops when the residuals are this small, relative to the size of the assembly.
+#[cfg(not(feature = "f32"))]
+const PRECISION: Float = 1e-12;
+#[cfg(feature = "f32")]
+const PRECISION: Float = 1e-6;
+// Damping of the steps of solve, see solve.
+const INITIAL_DAMPING: Float = 1e-3;
+const MAX_DAMPING: Float = 1e12;
+// solve_constraints fails if solve leaves larger residuals, see Solution.
+#[cfg(not(feature = "f32"))]
+const TOLERANCE: Float = 1e-6;
+#[cfg(feature = "f32")]
+const TOLERANCE: Float = 1e-3;
+
+// A frame attached to a part: a point and optionally a direction, in the coordinates of the
+// part before it is placed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anchor {
+    pub part: String,
+    pub at: na::Point3<Float>,
+    pub axis: Option<na::Vector3<Float>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constraint {
+    // The points of the anchors are at the same place, and their axes, if both have one, point
+    // the same way.
+    Coincident(Anchor, Anchor),
+    // The points of the anchors are this far apart.
+    Distance(Anchor, Anchor, Float),
+    // The axes of the anchors are at this angle, in radians.
+    Angle(Anchor, Anchor, Float),
+}
+
+impl Constraint {
+    fn anchors(&self) -> (&Anchor, &Anchor) {
+        match *self {
+            Constraint::Coincident(ref a, ref b)
+            | Constraint::Distance(ref a, ref b, _)
+            | Constraint::Angle(ref a, ref b, _) => (a, b),
+        }
+    }
+}
+
+// Where a part goes: rotated about the origin, then translated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub rotation: na::Rotation3<Float>,
+    pub translation: na::Vector3<Float>,
+}
+
+impl Placement {
+    pub fn identity() -> Placement {
+        Placement {
+            rotation: na::Rotation3::identity(),
+            translation: na::Vector3::zeros(),
+        }
+    }
+    // The placement for the rotation vector (axis times angle) r and the translation t.
+    fn new(r: &na::Vector3<Float>, t: &na::Vector3<Float>) -> Placement {
+        Placement {
+            rotation: na::Rotation3::from_scaled_axis(*r),
+            translation: *t,
+        }
+    }
+    pub fn point(&self, p: &na::Point3<Float>) -> na::Point3<Float> {
+        self.rotation * p + self.translation
+    }
+    pub fn vector(&self, v: &na::Vector3<Float>) -> na::Vector3<Float> {
+        self.rotation * v
+    }
+    // The angles to pass to LObject::rotate to rotate an object like this placement.
+    // LObject::rotate rotates objects by the inverse of the rotation of its angles.
+    pub fn euler_angles(&self) -> na::Vector3<Float> {
+        let (x, y, z) = self.rotation.inverse().euler_angles();
+        na::Vector3::new(x, y, z)
+    }
+}
+
+// The placements solve found, and the largest residual of the constraints it left, 0 if they are
+// all met: the error of distances relative to the size of the assembly (the largest distance or
+// anchor point, at least 1), or of angles in radians.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution {
+    pub placements: BTreeMap<String, Placement>,
+    pub residual: Float,
+}
+
+// Placements for all parts of the constraints which meet them as far as possible. The fixed
+// parts stay where they are, the others are moved as little as the constraints allow, since
+// the search starts from where they are and damps its steps (Levenberg-Marquardt, with the
+// derivatives from finite differences).
+pub fn solve(constraints: &[Constraint], fixed: &[String]) -> Solution {
+    let mut movable: Vec<String> = Vec::new();
+    let mut placements = BTreeMap::new();
+    let mut size: Float = 1.;
+    for constraint in constraints {
+        let (a, b) = constraint.anchors();
+        for anchor in &[a, b] {
+            size = size.max(anchor.at.coords.norm());
+            placements.insert(anchor.part.clone(), Placement::identity());
+            if !fixed.contains(&anchor.part) && !movable.contains(&anchor.part) {
+                movable.push(anchor.part.clone());
+            }
+        }
+        if let Constraint::Distance(_, _, d) = *constraint {
+            size = size.max(d.abs());
+        }
+    }
+    let place = |x: &na::DVector<Float>| {
+        let mut placements = placements.clone();
+        for (i, part) in movable.iter().enumerate() {
+            let r = na::Vector3::new(x[i * 6], x[i * 6 + 1], x[i * 6 + 2]);
+            let t = na::Vector3::new(x[i * 6 + 3], x[i * 6 + 4], x[i * 6 + 5]);
+            placements.insert(part.clone(), Placement::new(&r, &t));
+        }
+        placements
+    };
+    let f = |x: &na::DVector<Float>| residuals(constraints, &place(x), size);
+    let n = movable.len() * 6;
+    let mut x = na::DVector::zeros(n);
+    let mut r = f(&x);
+    let mut damping = INITIAL_DAMPING;
+    for _ in 0..MAX_ITERATIONS {
+        if n == 0 || r.norm() <= PRECISION {
+            break;
+        }
+        let mut jacobian = na::DMatrix::zeros(r.len(), n);
+        for j in 0..n {
+            let mut xj = x.clone();
+            xj[j] += DIFFERENCE_STEP;
+            jacobian.set_column(j, &((f(&xj) - &r) / DIFFERENCE_STEP));
+        }
+        let jt = jacobian.transpose();
+        let a = &jt * &jacobian;
+        let g = &jt * &r;
+        let mut improved = false;
+        while damping < MAX_DAMPING {
+            let mut m = a.clone();
+            for i in 0..n {
+                m[(i, i)] += damping * (a[(i, i)] + 1.);
+            }
+            if let Some(step) = m.cholesky().map(|c| c.solve(&-&g)) {
+                let next = &x + step;
+                let next_r = f(&next);
+                if next_r.norm() < r.norm() {
+                    x = next;
+                    r = next_r;
+                    damping = (damping * 0.1).max(Float::EPSILON);
+                    improved = true;
+                    break;
+                }
+            }
+            damping *= 10.;
+        }
+        if !improved {
+            break;
+        }
+    }
+    let residual = r.iter().fold(0., |max: Float, x| max.max(x.abs()));
+    Solution {
+        placements: place(&x),
+        residual,
+    }
+}
+
+// The residuals of the constraints for placements, with lengths divided by size so that they
+// weigh about as much as angles.
+fn residuals(
+    constraints: &[Constraint],
+    placements: &BTreeMap<String, Placement>,
+    size: Float,
+) -> na::DVector<Float> {
+    let mut r = Vec::new();
+    for constraint in constraints {
+        let (a, b) = constraint.anchors();
+        let (pa, pb) = (&placements[&a.part], &placements[&b.part]);
+        let d = (pa.point(&a.at) - pb.point(&b.at)) / size;
+        let axes = match (a.axis, b.axis) {
+            (Some(u), Some(v)) => Some((pa.vector(&u), pb.vector(&v))),
+            _ => None,
+        };
+        match *constraint {
+            Constraint::Coincident(..) => {
+                r.extend(d.iter());
+                if let Some((u, v)) = axes {
+                    r.extend((u - v).iter());
+                }
+            }
+            Constraint::Distance(_, _, distance) => r.push(d.norm() - distance / size),
+            Constraint::Angle(_, _, angle) => {
+                if let Some((u, v)) = axes {
+                    r.push(u.cross(&v).norm().atan2(u.dot(&v)) - angle);
+                }
+            }
+        }
+    }
+    na::DVector::from_vec(r.len(), r)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JointKind {
+    Revolute,
+    Prismatic,
+}
+
+// A joint moving the part of child relative to the part of parent. The child anchor is put on
+// the parent anchor with the axes of both pointing the same way, then turned about the axis by
+// value, in radians, for revolute joints, or moved along it by value for prismatic ones. Both
+// anchors have axes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joint {
+    pub kind: JointKind,
+    pub parent: Anchor,
+    pub child: Anchor,
+    pub value: Float,
+}
+
+impl Joint {
+    // The placement of the part of child for the placement of the part of parent.
+    pub fn child_placement(&self, parent: &Placement) -> Placement {
+        let u = self.parent.axis.expect("joint without axis");
+        let v = self.child.axis.expect("joint without axis");
+        let axis = parent.vector(&u);
+        // Opposite axes have no shortest rotation between them, any half turn will do.
+        let align = na::Rotation3::rotation_between(&v, &axis).unwrap_or_else(|| {
+            let other = if v.x.abs() < 0.5 {
+                na::Vector3::x()
+            } else {
+                na::Vector3::y()
+            };
+            na::Rotation3::from_axis_angle(
+                &na::Unit::new_normalize(v.cross(&other)),
+                ::float::consts::PI,
+            )
+        });
+        let (angle, offset) = match self.kind {
+            JointKind::Revolute => (self.value, 0.),
+            JointKind::Prismatic => (0., self.value),
+        };
+        let rotation =
+            na::Rotation3::from_axis_angle(&na::Unit::new_normalize(axis), angle) * align;
+        let at = parent.point(&self.parent.at) + axis * offset;
+        Placement {
+            rotation,
+            translation: at.coords - rotation * self.child.at.coords,
+        }
+    }
+}
+
+// The placements of the parts of a mechanism: the fixed parts stay where they are, and the
+// joints place their child parts from their parent parts outwards. An error (with the number of
+// the joint, counting from 1) for joints which close a loop or are not connected to a fixed part.
+pub fn pose(joints: &[Joint], fixed: &[String]) -> Result<BTreeMap<String, Placement>, String> {
+    let mut placements: BTreeMap<String, Placement> = fixed
+        .iter()
+        .map(|part| (part.clone(), Placement::identity()))
+        .collect();
+    let mut placed = vec![false; joints.len()];
+    loop {
+        let mut progress = false;
+        for (i, joint) in joints.iter().enumerate() {
+            if placed[i] {
+                continue;
+            }
+            if let Some(parent) = placements.get(&joint.parent.part).cloned() {
+                if placements.contains_key(&joint.child.part) {
+                    return Err(format!(
+                        "joint {} closes a loop, each part can only be moved by one joint",
+                        i + 1
+                    ));
+                }
+                placements.insert(joint.child.part.clone(), joint.child_placement(&parent));
+                placed[i] = true;
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+    match placed.iter().position(|placed| !placed) {
+        Some(i) => Err(format!("joint {} is not connected to a fixed part", i + 1)),
+        None => Ok(placements),
+    }
+}
+
+// Collects the constraints of solve_constraints, or the joints of articulate for mechanisms.
+// The Lua functions below pump the anchors and constraints from their tables into it, anchors
+// first, since hlua can not read nested tables. The fields are always set, they are only Options
+// since hlua requires all-zero to be a valid value.
+pub struct ConstraintSystem {
+    mechanism: bool,
+    constraints: Option<Vec<Constraint>>,
+    joints: Option<Vec<Joint>>,
+    // The anchors of the next constraint.
+    anchors: Option<Vec<Anchor>>,
+    fixed: Option<Vec<String>>,
+}
+
+implement_lua_push!(ConstraintSystem, |mut metatable| {
+    let mut index = metatable.empty_array("__index");
+    index.set(
+        "anchor",
+        ::hlua::function5(
+            |s: &mut ConstraintSystem, part: String, x: Float, y: Float, z: Float| {
+                s.anchor(part, x, y, z)
+            },
+        ),
+    );
+    index.set(
+        "axis",
+        ::hlua::function4(|s: &mut ConstraintSystem, x: Float, y: Float, z: Float| s.axis(x, y, z)),
+    );
+    index.set(
+        "add",
+        ::hlua::function3(|s: &mut ConstraintSystem, kind: String, value: Float| {
+            s.add(&kind, value)
+        }),
+    );
+    index.set(
+        "joint",
+        ::hlua::function5(
+            |s: &mut ConstraintSystem, kind: String, value: Float, min: Float, max: Float| {
+                s.joint(&kind, value, min, max)
+            },
+        ),
+    );
+    index.set(
+        "fix",
+        ::hlua::function2(|s: &mut ConstraintSystem, part: String| s.fix(part)),
+    );
+    index.set(
+        "solve",
+        ::hlua::function1(|s: &mut ConstraintSystem| s.solve()),
+    );
+    index.set(
+        "pose",
+        ::hlua::function1(|s: &mut ConstraintSystem| s.pose()),
+    );
+});
+
+implement_lua_read!(ConstraintSystem);
+
+impl ConstraintSystem {
+    pub fn new() -> ConstraintSystem {
+        ConstraintSystem {
+            mechanism: false,
+            constraints: Some(Vec::new()),
+            joints: Some(Vec::new()),
+            anchors: Some(Vec::new()),
+            fixed: Some(Vec::new()),
+        }
+    }
+    fn constraints(&mut self) -> &mut Vec<Constraint> {
+        self.constraints.as_mut().unwrap()
+    }
+    pub fn mechanism() -> ConstraintSystem {
+        ConstraintSystem {
+            mechanism: true,
+            ..ConstraintSystem::new()
+        }
+    }
+    fn anchors(&mut self) -> &mut Vec<Anchor> {
+        self.anchors.as_mut().unwrap()
+    }
+    fn joints(&mut self) -> &mut Vec<Joint> {
+        self.joints.as_mut().unwrap()
+    }
+    pub fn anchor(
+        &mut self,
+        part: String,
+        x: Float,
+        y: Float,
+        z: Float,
+    ) -> Result<(), PrimitiveError> {
+        let at = na::Point3::new(x, y, z);
+        if !at.coords.iter().all(|x| x.is_finite()) {
+            return Err(self.invalid("has an anchor which is not finite"));
+        }
+        self.anchors().push(Anchor {
+            part,
+            at,
+            axis: None,
+        });
+        Ok(())
+    }
+    // The axis of the last anchor.
+    pub fn axis(&mut self, x: Float, y: Float, z: Float) -> Result<(), PrimitiveError> {
+        let axis = na::Vector3::new(x, y, z);
+        match axis.try_normalize(0.) {
+            Some(axis) if axis.iter().all(|x| x.is_finite()) => {
+                if let Some(anchor) = self.anchors().last_mut() {
+                    anchor.axis = Some(axis);
+                }
+                Ok(())
+            }
+            _ => Err(self.invalid("has an axis which is zero or not finite")),
+        }
+    }
+    // A constraint of kind between the last two anchors, with the distance or the angle in
+    // degrees.
+    pub fn add(&mut self, kind: &str, value: Float) -> Result<(), PrimitiveError> {
+        if self.anchors().len() != 2 {
+            return Err(self.invalid("needs two anchors"));
+        }
+        let b = self.anchors().pop().unwrap();
+        let a = self.anchors().pop().unwrap();
+        let constraint = match kind {
+            "coincident" => Constraint::Coincident(a, b),
+            "distance" if value >= 0. && value.is_finite() => Constraint::Distance(a, b, value),
+            "distance" => return Err(self.invalid("needs a distance of at least 0")),
+            "angle" if a.axis.is_none() || b.axis.is_none() => {
+                return Err(self.invalid("needs anchors with axes"))
+            }
+            "angle" if (0. ..=180.).contains(&value) => Constraint::Angle(a, b, value.to_radians()),
+            "angle" => return Err(self.invalid("needs an angle from 0 to 180 degrees")),
+            _ => {
+                return Err(self.invalid(&format!(
+                    "is {}, expected coincident, distance or angle",
+                    kind
+                )))
+            }
+        };
+        self.constraints().push(constraint);
+        Ok(())
+    }
+    // A joint of kind from the part of the first of the last two anchors to the part of the
+    // second, at value clamped to min and max, with angles in degrees.
+    pub fn joint(
+        &mut self,
+        kind: &str,
+        value: Float,
+        min: Float,
+        max: Float,
+    ) -> Result<(), PrimitiveError> {
+        if self.anchors().len() != 2 {
+            return Err(self.invalid("needs two anchors"));
+        }
+        let child = self.anchors().pop().unwrap();
+        let parent = self.anchors().pop().unwrap();
+        let kind = match kind {
+            "revolute" => JointKind::Revolute,
+            "prismatic" => JointKind::Prismatic,
+            _ => return Err(self.invalid(&format!("is {}, expected revolute or prismatic", kind))),
+        };
+        if parent.axis.is_none() || child.axis.is_none() {
+            return Err(self.invalid("needs anchors with axes"));
+        }
+        if !value.is_finite() {
+            return Err(self.invalid("needs a finite angle or offset"));
+        }
+        if min.is_nan() || max.is_nan() || min > max {
+            return Err(self.invalid("needs a min which is at most its max"));
+        }
+        let value = value.max(min).min(max);
+        let value = match kind {
+            JointKind::Revolute => value.to_radians(),
+            JointKind::Prismatic => value,
+        };
+        self.joints().push(Joint {
+            kind,
+            parent,
+            child,
+            value,
+        });
+        Ok(())
+    }
+    pub fn fix(&mut self, part: String) {
+        self.fixed.as_mut().unwrap().push(part);
+    }
+    // The placements of the parts as a Lua table, see placement_table. Without fixed parts, the
+    // part of the first anchor stays where it is.
+    pub fn solve(&mut self) -> Result<AnyLuaValue, PrimitiveError> {
+        let mut fixed = self.fixed.clone().unwrap();
+        let constraints = self.constraints.clone().unwrap();
+        if fixed.is_empty() {
+            fixed.extend(constraints.first().map(|c| c.anchors().0.part.clone()));
+        }
+        let solution = solve(&constraints, &fixed);
+        if solution.residual > TOLERANCE {
+            return Err(PrimitiveError::Unsolvable(solution.residual));
+        }
+        Ok(AnyLuaValue::LuaArray(
+            solution
+                .placements
+                .iter()
+                .map(|(part, p)| (AnyLuaValue::LuaString(part.clone()), placement_table(p)))
+                .collect(),
+        ))
+    }
+    // The placements of the parts of the mechanism as a Lua table, like solve. Without fixed
+    // parts, the parent part of the first joint stays where it is.
+    pub fn pose(&mut self) -> Result<AnyLuaValue, PrimitiveError> {
+        let mut fixed = self.fixed.clone().unwrap();
+        let joints = self.joints.clone().unwrap();
+        if fixed.is_empty() {
+            fixed.extend(joints.first().map(|j| j.parent.part.clone()));
+        }
+        let placements =
+            pose(&joints, &fixed).map_err(|message| PrimitiveError::InvalidConstraint {
+                function: "articulate",
+                message,
+            })?;
+        Ok(AnyLuaValue::LuaArray(
+            placements
+                .iter()
+                .map(|(part, p)| (AnyLuaValue::LuaString(part.clone()), placement_table(p)))
+                .collect(),
+        ))
+    }
+    // An error about the constraint or joint being added.
+    fn invalid(&mut self, message: &str) -> PrimitiveError {
+        let (function, item, n) = if self.mechanism {
+            ("articulate", "joint", self.joints().len())
+        } else {
+            ("solve_constraints", "constraint", self.constraints().len())
+        };
+        PrimitiveError::InvalidConstraint {
+            function,
+            message: format!("{} {} {}", item, n + 1, message),
+        }
+    }
+
+    pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+        lua.set(
+            "__new_constraint_system",
+            hlua::function0(ConstraintSystem::new),
+        );
+        lua.set(
+            "__new_mechanism",
+            hlua::function0(ConstraintSystem::mechanism),
+        );
+        lua.execute::<()>(&format!(
+            "
+            function __push_anchor(system, anchor)
+              local at = anchor.at or {{0, 0, 0}}
+              system:anchor(anchor.part, at[1], at[2], at[3])
+              if anchor.axis then
+                system:axis(anchor.axis[1], anchor.axis[2], anchor.axis[3])
+              end
+            end
+
+            function solve_constraints(t)
+              local system = __new_constraint_system()
+              for _, c in ipairs(t) do
+                __push_anchor(system, c[2])
+                __push_anchor(system, c[3])
+                system:add(c[1], c[4] or -1)
+              end
+              if type(t.fixed) == \"table\" then
+                for _, part in ipairs(t.fixed) do
+                  system:fix(part)
+                end
+              elseif t.fixed then
+                system:fix(t.fixed)
+              end
+              return system:solve()
+            end
+
+            -- Joints without an angle or offset sweep from their min to their max with time,
+            -- so that animations show the mechanism moving.
+            function articulate(t)
+              local system = __new_mechanism()
+              for _, j in ipairs(t) do
+                __push_anchor(system, j[2])
+                __push_anchor(system, j[3])
+                local value = j.angle or j.offset
+                if not value then
+                  if j.min and j.max then
+                    value = j.min + (j.max - j.min) * {env}.time
+                  else
+                    value = 0
+                  end
+                end
+                system:joint(j[1], value, j.min or -math.huge, j.max or math.huge)
+              end
+              if type(t.fixed) == \"table\" then
+                for _, part in ipairs(t.fixed) do
+                  system:fix(part)
+                end
+              elseif t.fixed then
+                system:fix(t.fixed)
+              end
+              return system:pose()
+            end
+
+            function place(o, p)
+              return o:rotate(p.rotate[1], p.rotate[2], p.rotate[3])
+                      :translate(p.translate[1], p.translate[2], p.translate[3])
+            end
+
+            {env}.solve_constraints = solve_constraints;
+            {env}.articulate = articulate;
+            {env}.place = place;",
+            env = env_name
+        ))
+        .unwrap();
+    }
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "solve_constraints",
+        args: Some("{{kind, anchor, anchor, value}, ..., fixed = parts}"),
+        doc: "Placements of the parts which meet the constraints. kind is coincident, distance or angle, anchors are {part = name, at = {x, y, z}, axis = {x, y, z}}. Returns a table of {rotate, translate} by part.",
+    },
+    Function {
+        name: "articulate",
+        args: Some("{{kind, parent, child, angle, offset, min, max}, ..., fixed = parts}"),
+        doc: "Placements of the parts of a mechanism of revolute or prismatic joints. Joints without an angle or offset move from min to max with time.",
+    },
+    Function {
+        name: "place",
+        args: Some("o, placement"),
+        doc: "o rotated and translated like a part by solve_constraints or articulate.",
+    },
+];
+
+impl Default for ConstraintSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// placement as a Lua table {rotate = {x, y, z}, translate = {x, y, z}}, with the angles to pass
+// to rotate.
+fn placement_table(placement: &Placement) -> AnyLuaValue {
+    let vector = |v: na::Vector3<Float>| {
+        AnyLuaValue::LuaArray(
+            (0..3)
+                .map(|i| {
+                    (
+                        AnyLuaValue::LuaNumber((i + 1) as f64),
+                        lobject::lua_number(v[i]),
+                    )
+                })
+                .collect(),
+        )
+    };
+    AnyLuaValue::LuaArray(vec![
+        (
+            AnyLuaValue::LuaString("rotate".to_string()),
+            vector(placement.euler_angles()),
+        ),
+        (
+            AnyLuaValue::LuaString("translate".to_string()),
+            vector(placement.translation),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anchor(part: &str, at: [Float; 3], axis: Option<[Float; 3]>) -> Anchor {
+        Anchor {
+            part: part.to_string(),
+            at: na::Point3::new(at[0], at[1], at[2]),
+            axis: axis.map(|v| na::Vector3::new(v[0], v[1], v[2]).normalize()),
+        }
+    }
+
+    fn fixed() -> Vec<String> {
+        vec!["base".to_string()]
+    }
+
+    #[test]
+    fn coincident_anchors_meet() {
+        let on_base = anchor("base", [1., 2., 3.], Some([0., 0., 1.]));
+        let on_part = anchor("part", [0., 0., 0.], Some([1., 0., 0.]));
+        let solution = solve(
+            &[Constraint::Coincident(on_base.clone(), on_part.clone())],
+            &fixed(),
+        );
+        assert!(solution.residual < TOLERANCE, "{}", solution.residual);
+        assert_eq!(solution.placements["base"], Placement::identity());
+        let part = &solution.placements["part"];
+        assert!((part.point(&on_part.at) - on_base.at).norm() < TOLERANCE);
+        let axis = part.vector(&on_part.axis.unwrap());
+        assert!(
+            (axis - on_base.axis.unwrap()).norm() < TOLERANCE,
+            "{}",
+            axis
+        );
+    }
+
+    #[test]
+    fn parallel_axes_and_distances() {
+        let base_axis = anchor("base", [0., 0., 0.], Some([0., 0., 1.]));
+        let part_axis = anchor("part", [0., 0., 0.], Some([1., 1., 0.]));
+        let solution = solve(
+            &[
+                Constraint::Angle(base_axis.clone(), part_axis.clone(), 0.),
+                Constraint::Distance(base_axis.clone(), part_axis.clone(), 5.),
+            ],
+            &fixed(),
+        );
+        assert!(solution.residual < TOLERANCE, "{}", solution.residual);
+        let part = &solution.placements["part"];
+        let axis = part.vector(&part_axis.axis.unwrap());
+        assert!(
+            axis.dot(&base_axis.axis.unwrap()) > 1. - TOLERANCE,
+            "{}",
+            axis
+        );
+        let distance = (part.point(&part_axis.at) - base_axis.at).norm();
+        assert!((distance - 5.).abs() < 5. * TOLERANCE, "{}", distance);
+    }
+
+    #[test]
+    fn over_constrained_parts() {
+        let a = anchor("base", [0., 0., 0.], None);
+        let b = anchor("part", [1., 0., 0.], None);
+        // The same constraint twice is redundant, but can be met.
+        let coincident = Constraint::Coincident(a.clone(), b.clone());
+        let solution = solve(&[coincident.clone(), coincident], &fixed());
+        assert!(solution.residual < TOLERANCE, "{}", solution.residual);
+        // Two distances between the same points can not.
+        let solution = solve(
+            &[
+                Constraint::Distance(a.clone(), b.clone(), 1.),
+                Constraint::Distance(a, b, 2.),
+            ],
+            &fixed(),
+        );
+        assert!(solution.residual > 0.1, "{}", solution.residual);
+        // Nor can a fixed part be moved.
+        let other = anchor("other", [0., 0., 0.], None);
+        let solution = solve(
+            &[Constraint::Distance(
+                anchor("base", [0., 0., 0.], None),
+                other,
+                1.,
+            )],
+            &["base".to_string(), "other".to_string()],
+        );
+        assert!(
+            (solution.residual - 1.).abs() < TOLERANCE,
+            "{}",
+            solution.residual
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Extensions of the CAD files Mesh reads through the converter, lower case.
+pub const EXTENSIONS: &[&str] = &["step", "stp", "iges", "igs"];
+// Placeholders in the command of a Converter.
+const INPUT: &str = "{input}";
+const OUTPUT: &str = "{output}";
+
+// An external program tessellating STEP and IGES files into STL files, which Mesh reads
+// instead of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Converter {
+    // The program and its arguments, separated by whitespace, with {input} replaced by the
+    // path of the CAD file and {output} by the path of the STL file to write, e.g.
+    // "gmsh {input} -2 -format stl -o {output}".
+    pub command: String,
+    // Converted files are kept in this directory, named by the hash of the CAD file and the
+    // command, so each file is converted once until it or the command changes.
+    pub cache: PathBuf,
+}
+
+static CONVERTER: Mutex<Option<Converter>> = Mutex::new(None);
+// Numbers the conversions of this process, see mesh_path.
+static CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Convert CAD files read by scripts evaluated from now on with converter, or refuse them if it
+// is None.
+pub fn set_converter(converter: Option<Converter>) {
+    *CONVERTER.lock().unwrap() = converter;
+}
+
+pub fn is_cad_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+// The mesh file Mesh reads for the file at path: the file itself, unless it is a CAD file, which
+// is converted into the cache unless it is there already.
+pub fn mesh_path(path: &Path) -> Result<PathBuf, String> {
+    if !is_cad_file(path) {
+        return Ok(path.to_path_buf());
+    }
+    let converter = match *CONVERTER.lock().unwrap() {
+        Some(ref converter) => converter.clone(),
+        None => {
+            return Err(format!(
+                "{}: no converter for STEP and IGES files is set up, see mesh_converter in the \
+                 settings",
+                path.display()
+            ))
+        }
+    };
+    let contents = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut hash = fnv1a(FNV_OFFSET, &contents);
+    hash = fnv1a(hash, converter.command.as_bytes());
+    let cached = converter.cache.join(format!("{:016x}.stl", hash));
+    if cached.exists() {
+        return Ok(cached);
+    }
+    fs::create_dir_all(&converter.cache)
+        .map_err(|e| format!("could not create {}: {}", converter.cache.display(), e))?;
+    // The converter writes next to the cache entry, which only appears once it is complete. The
+    // name is unique to the conversion, so concurrent ones of the same file, from this process
+    // or another, do not write into each other.
+    let partial = converter.cache.join(format!(
+        "{:016x}.{}.{}.partial.stl",
+        hash,
+        process::id(),
+        CONVERSIONS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let converted = convert(&converter.command, path, &partial)
+        .map_err(|e| format!("could not convert {}: {}", path.display(), e))
+        .and_then(|()| {
+            fs::rename(&partial, &cached).map_err(|e| format!("{}: {}", cached.display(), e))
+        });
+    if converted.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    converted?;
+    Ok(cached)
+}
+
+// Run command on input, which has to write output.
+fn convert(command: &str, input: &Path, output: &Path) -> Result<(), String> {
+    let mut arguments = command.split_whitespace().map(|a| {
+        a.replace(INPUT, &input.to_string_lossy())
+            .replace(OUTPUT, &output.to_string_lossy())
+    });
+    let program = arguments.next().ok_or("the converter command is empty")?;
+    let result = Command::new(&program)
+        .args(arguments)
+        .output()
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    if !result.status.success() {
+        return Err(format!(
+            "{} failed ({}): {}",
+            program,
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    if !output.exists() {
+        return Err(format!("{} wrote no mesh", program));
+    }
+    Ok(())
+}
+
+// The 64 bit FNV-1a hash, which unlike the hashers of std stays the same between builds, so the
+// cache does.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
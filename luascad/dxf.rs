@@ -0,0 +1,422 @@
+use super::Float;
+use error::PrimitiveError;
+use float::consts::PI;
+use hlua;
+use include::Includes;
+use lobject::LObject;
+use nalgebra as na;
+use outline::{self, Outline};
+use tree::Node;
+
+// Arcs are flattened to polygons which differ from them by at most this, in the units of the
+// script.
+pub const TOLERANCE: Float = 0.01;
+// Ends of entities closer than this fraction of the size of the drawing are joined.
+const JOIN_TOLERANCE: Float = 1e-6;
+
+// An entity of the ENTITIES section: its type and its group codes with their values.
+struct Entity {
+    kind: String,
+    groups: Vec<(i32, String)>,
+}
+
+impl Entity {
+    // The first value of code as a number.
+    fn number(&self, code: i32) -> Option<Float> {
+        self.groups
+            .iter()
+            .find(|&&(c, _)| c == code)
+            .and_then(|(_, v)| v.trim().parse().ok())
+    }
+    fn point(&self, x: i32, y: i32) -> Result<na::Point2<Float>, String> {
+        match (self.number(x), self.number(y)) {
+            (Some(x), Some(y)) => Ok(na::Point2::new(x, y)),
+            _ => Err(format!("{} without coordinates", self.kind)),
+        }
+    }
+    // Arcs, circles and polylines are in the coordinates of their plane, which mirrors x if it
+    // faces down.
+    fn mirrored(&self) -> bool {
+        self.number(230).map_or(false, |z| z < 0.)
+    }
+}
+
+// The closed profiles of the LWPOLYLINE, LINE, ARC and CIRCLE entities of a DXF drawing, in its
+// units. Polylines, lines and arcs which meet end to end are joined. Profiles inside others are
+// holes, see Outline. Other entities, e.g. of blocks, are ignored.
+pub fn parse(dxf: &str, tolerance: Float) -> Result<Vec<Vec<na::Point2<Float>>>, String> {
+    let mut closed = Vec::new();
+    let mut open = Vec::new();
+    for entity in entities(dxf)? {
+        let mirror = |mut contour: Vec<na::Point2<Float>>| {
+            if entity.mirrored() {
+                for p in &mut contour {
+                    p.x = -p.x;
+                }
+            }
+            contour
+        };
+        match entity.kind.as_str() {
+            "LINE" => open.push(vec![entity.point(10, 20)?, entity.point(11, 21)?]),
+            "CIRCLE" => {
+                let center = entity.point(10, 20)?;
+                let radius = radius(&entity)?;
+                let mut contour = Vec::new();
+                outline::arc(&mut contour, &center, radius, 0., 2. * PI, tolerance);
+                closed.push(mirror(contour));
+            }
+            "ARC" => {
+                let center = entity.point(10, 20)?;
+                let radius = radius(&entity)?;
+                let start = entity.number(50).unwrap_or(0.).to_radians();
+                let end = entity.number(51).unwrap_or(360.).to_radians();
+                let mut sweep = (end - start).rem_euclid(2. * PI);
+                if sweep == 0. {
+                    sweep = 2. * PI;
+                }
+                let mut contour =
+                    vec![center + na::Vector2::new(start.cos(), start.sin()) * radius];
+                outline::arc(&mut contour, &center, radius, start, sweep, tolerance);
+                open.push(mirror(contour));
+            }
+            "LWPOLYLINE" => {
+                let (contour, is_closed) = polyline(&entity, tolerance)?;
+                if is_closed {
+                    closed.push(mirror(contour));
+                } else {
+                    open.push(mirror(contour));
+                }
+            }
+            _ => {}
+        }
+    }
+    let size = closed
+        .iter()
+        .chain(&open)
+        .flat_map(|c| c.iter())
+        .map(|p| p.x.abs().max(p.y.abs()))
+        .fold(0., Float::max);
+    closed.extend(join(open, size * JOIN_TOLERANCE)?);
+    Ok(closed.into_iter().filter(|c| c.len() > 2).collect())
+}
+
+fn radius(entity: &Entity) -> Result<Float, String> {
+    match entity.number(40) {
+        Some(r) if r > 0. => Ok(r),
+        _ => Err(format!("{} without a positive radius", entity.kind)),
+    }
+}
+
+// The entities of the ENTITIES section.
+fn entities(dxf: &str) -> Result<Vec<Entity>, String> {
+    let mut lines = dxf.lines();
+    let mut pairs = Vec::new();
+    while let Some(code) = lines.next() {
+        let value = lines.next().ok_or("the last group code has no value")?;
+        let code = code
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid group code {}", code.trim()))?;
+        pairs.push((code, value.trim().to_string()));
+    }
+    let mut entities = Vec::new();
+    let mut section = None;
+    let mut i = 0;
+    while i < pairs.len() {
+        let (code, ref value) = pairs[i];
+        i += 1;
+        if code != 0 {
+            continue;
+        }
+        match value.as_str() {
+            "SECTION" => section = pairs.get(i).map(|p| p.1.clone()),
+            "ENDSEC" => section = None,
+            kind if section.as_ref().map_or(false, |s| s == "ENTITIES") => {
+                let start = i;
+                while i < pairs.len() && pairs[i].0 != 0 {
+                    i += 1;
+                }
+                entities.push(Entity {
+                    kind: kind.to_string(),
+                    groups: pairs[start..i].to_vec(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(entities)
+}
+
+// The points of an LWPOLYLINE, with its arcs flattened, and whether it is closed.
+fn polyline(entity: &Entity, tolerance: Float) -> Result<(Vec<na::Point2<Float>>, bool), String> {
+    // Each vertex starts with its x (10), followed by its y (20) and the bulge (42) of the
+    // segment to the next vertex: the tangent of a fourth of the angle of its arc.
+    let mut vertices: Vec<(na::Point2<Float>, Float)> = Vec::new();
+    let mut x = None;
+    for &(code, ref value) in &entity.groups {
+        let number = || {
+            value
+                .parse::<Float>()
+                .map_err(|_| format!("invalid number {} in LWPOLYLINE", value))
+        };
+        match code {
+            10 => x = Some(number()?),
+            20 => {
+                let x = x.take().ok_or("LWPOLYLINE vertex without x")?;
+                vertices.push((na::Point2::new(x, number()?), 0.));
+            }
+            42 => {
+                if let Some(last) = vertices.last_mut() {
+                    last.1 = number()?;
+                }
+            }
+            _ => {}
+        }
+    }
+    let closed = entity
+        .number(70)
+        .map_or(false, |flags| flags as i32 & 1 == 1);
+    let segments = if closed {
+        vertices.len()
+    } else {
+        vertices.len().saturating_sub(1)
+    };
+    let mut contour: Vec<na::Point2<Float>> = vertices.iter().take(1).map(|v| v.0).collect();
+    for (i, &(a, bulge)) in vertices.iter().enumerate().take(segments) {
+        let b = vertices[(i + 1) % vertices.len()].0;
+        if bulge != 0. && a != b {
+            // The arc sweeps 4 atan(bulge), counter-clockwise if positive, around the center on
+            // the bisector of the chord.
+            let sweep = 4. * bulge.atan();
+            let chord = b - a;
+            let radius = chord.norm() / (2. * (sweep / 2.).sin()).abs();
+            let middle = a + chord / 2.;
+            let normal = na::Vector2::new(-chord.y, chord.x).normalize();
+            let center = middle + normal * (radius * (sweep / 2.).cos() * sweep.signum());
+            let start = (a - center).y.atan2((a - center).x);
+            outline::arc(&mut contour, &center, radius, start, sweep, tolerance);
+            // The last point of the arc is b, up to rounding.
+            contour.pop();
+        }
+        contour.push(b);
+    }
+    if closed && contour.len() > 1 {
+        // The closing segment ends at the first vertex.
+        contour.pop();
+    }
+    Ok((contour, closed))
+}
+
+// Join the open chains of points which meet end to end within tolerance into closed contours.
+fn join(
+    mut open: Vec<Vec<na::Point2<Float>>>,
+    tolerance: Float,
+) -> Result<Vec<Vec<na::Point2<Float>>>, String> {
+    let near = |a: &na::Point2<Float>, b: &na::Point2<Float>| (a - b).norm() <= tolerance;
+    let mut closed = Vec::new();
+    while let Some(mut chain) = open.pop() {
+        loop {
+            let (first, last) = (chain[0], chain[chain.len() - 1]);
+            if chain.len() > 2 && near(&first, &last) {
+                chain.pop();
+                closed.push(chain);
+                break;
+            }
+            let next = open
+                .iter()
+                .position(|c| near(&c[0], &last) || near(&c[c.len() - 1], &last));
+            let mut next = match next {
+                Some(i) => open.swap_remove(i),
+                None => {
+                    return Err(format!(
+                        "the profile is not closed at ({}, {})",
+                        last.x, last.y
+                    ))
+                }
+            };
+            if !near(&next[0], &last) {
+                next.reverse();
+            }
+            chain.extend(next.into_iter().skip(1));
+        }
+    }
+    Ok(closed)
+}
+
+// ImportDXF(filename, scale): the closed profiles of the DXF file as an Outline, with a unit of
+// the drawing scaled to scale units of the script.
+pub fn import_dxf(
+    includes: &Includes,
+    filename: &str,
+    scale: Float,
+) -> Result<LObject, PrimitiveError> {
+    let error = |message: String| PrimitiveError::Import {
+        function: "ImportDXF",
+        filename: filename.to_string(),
+        message,
+    };
+    if !(scale > 0. && scale.is_finite()) {
+        return Err(PrimitiveError::InvalidParameter {
+            function: "ImportDXF",
+            parameter: "scale",
+            value: scale,
+            requirement: "positive and finite",
+        });
+    }
+    let path = includes.path(filename).map_err(error)?;
+    let dxf = ::std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    let contours: Vec<Vec<na::Point2<Float>>> = parse(&dxf, TOLERANCE / scale)
+        .map_err(error)?
+        .into_iter()
+        .map(|c| c.into_iter().map(|p| p * scale).collect())
+        .collect();
+    if contours.is_empty() {
+        return Err(error("no closed profiles".to_string()));
+    }
+    Ok(LObject::new(
+        Outline::new(contours.clone()),
+        Node::Outline { contours },
+    ))
+}
+
+// Define ImportDXF in the env, reading files with includes, see Includes::path.
+pub fn export_factories<'lua>(lua: &mut hlua::Lua<'lua>, env_name: &str, includes: &'lua Includes) {
+    lua.set(
+        "__ImportDXF",
+        hlua::function2(move |filename: String, scale: Float| {
+            import_dxf(includes, &filename, scale)
+        }),
+    );
+    // The scale is optional.
+    lua.execute::<()>(&format!(
+        "
+        function ImportDXF(filename, scale)
+          return __ImportDXF(filename, scale or 1)
+        end
+        {env}.ImportDXF = ImportDXF
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A drawing with the entities, each a type and its group codes, in its ENTITIES section.
+    fn drawing(entities: &[(&str, &[(i32, Float)])]) -> String {
+        let mut dxf = "0\nSECTION\n2\nENTITIES\n".to_string();
+        for &(kind, groups) in entities {
+            dxf += &format!("0\n{}\n", kind);
+            for &(code, value) in groups {
+                dxf += &format!("{}\n{}\n", code, value);
+            }
+        }
+        dxf + "0\nENDSEC\n0\nEOF\n"
+    }
+
+    fn line(a: (Float, Float), b: (Float, Float)) -> Vec<(i32, Float)> {
+        vec![(10, a.0), (20, a.1), (11, b.0), (21, b.1)]
+    }
+
+    // The points of contour, sorted to compare them whatever point it starts with.
+    fn sorted(contour: &[na::Point2<Float>]) -> Vec<(Float, Float)> {
+        let mut points: Vec<_> = contour.iter().map(|p| (p.x, p.y)).collect();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        points
+    }
+
+    #[test]
+    fn closed_polylines_are_profiles() {
+        let square: &[(i32, Float)] = &[
+            (70, 1.),
+            (10, 0.),
+            (20, 0.),
+            (10, 2.),
+            (20, 0.),
+            (10, 2.),
+            (20, 2.),
+            (10, 0.),
+            (20, 2.),
+        ];
+        let contours = parse(&drawing(&[("LWPOLYLINE", square)]), 0.01).unwrap();
+        assert_eq!(contours.len(), 1);
+        assert_eq!(
+            sorted(&contours[0]),
+            vec![(0., 0.), (0., 2.), (2., 0.), (2., 2.)]
+        );
+        // Two half circles, each with a bulge of 1.
+        let circle: &[(i32, Float)] = &[
+            (70, 1.),
+            (10, -1.),
+            (20, 0.),
+            (42, 1.),
+            (10, 1.),
+            (20, 0.),
+            (42, 1.),
+        ];
+        let contours = parse(&drawing(&[("LWPOLYLINE", circle)]), 0.01).unwrap();
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].len() > 8);
+        for p in &contours[0] {
+            assert!((p.coords.norm() - 1.).abs() < 1e-6, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn lines_are_joined_into_loops() {
+        // Out of order, and one of them the other way around.
+        let lines = [
+            line((2., 0.), (2., 2.)),
+            line((0., 2.), (0., 0.)),
+            line((0., 0.), (2., 0.)),
+            line((0., 2.), (2., 2.)),
+        ];
+        let entities: Vec<_> = lines.iter().map(|l| ("LINE", &l[..])).collect();
+        let contours = parse(&drawing(&entities), 0.01).unwrap();
+        assert_eq!(contours.len(), 1);
+        assert_eq!(
+            sorted(&contours[0]),
+            vec![(0., 0.), (0., 2.), (2., 0.), (2., 2.)]
+        );
+    }
+
+    #[test]
+    fn open_profiles_are_rejected() {
+        let lines = [
+            line((0., 0.), (2., 0.)),
+            line((2., 0.), (2., 2.)),
+            line((2., 2.), (0., 2.)),
+        ];
+        let entities: Vec<_> = lines.iter().map(|l| ("LINE", &l[..])).collect();
+        let error = parse(&drawing(&entities), 0.01).unwrap_err();
+        assert!(
+            error.starts_with("the profile is not closed at"),
+            "{}",
+            error
+        );
+        let open: &[(i32, Float)] = &[
+            (70, 0.),
+            (10, 0.),
+            (20, 0.),
+            (10, 2.),
+            (20, 0.),
+            (10, 2.),
+            (20, 2.),
+        ];
+        assert!(parse(&drawing(&[("LWPOLYLINE", open)]), 0.01).is_err());
+    }
+
+    #[test]
+    fn other_sections_are_ignored() {
+        let circle: &[(i32, Float)] = &[(10, 0.), (20, 0.), (40, 1.)];
+        let dxf = format!(
+            "0\nSECTION\n2\nBLOCKS\n0\nLINE\n10\n0\n20\n0\n11\n1\n21\n0\n0\nENDSEC\n{}",
+            drawing(&[("CIRCLE", circle)])
+        );
+        let contours = parse(&dxf, 0.01).unwrap();
+        assert_eq!(contours.len(), 1);
+        assert!(parse("0\nSECTION\n2\nENTITIES\n0\nCIRCLE\n10\n0\n20\n0\n", 0.01).is_err());
+    }
+}
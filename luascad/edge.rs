@@ -0,0 +1,251 @@
+use super::{Float, RELATIVE_STEP};
+use api::Function;
+use error::{self, PrimitiveError};
+use float::consts::{FRAC_1_SQRT_2, SQRT_2};
+use hlua;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use lobject::LObject;
+use lobject_vector::LObjectVector;
+use nalgebra as na;
+use tree::{Boolean, Node};
+
+// How the edges where the surfaces of two objects meet are finished by fillet and chamfer,
+// independent of the smoothing of booleans.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Finish {
+    // Rounded with the radius.
+    Fillet(Float),
+    // Cut flat, reaching this far along the surface of the first and the second object.
+    Chamfer(Float, Float),
+}
+
+impl Finish {
+    // The furthest the finish reaches from the edge.
+    pub fn size(&self) -> Float {
+        match *self {
+            Finish::Fillet(radius) => radius,
+            Finish::Chamfer(a, b) => a.max(b),
+        }
+    }
+    // Where the surfaces of both objects are close and nearly parallel, the finished values
+    // change up to this much faster than the distance.
+    pub fn steepness(&self) -> Float {
+        match *self {
+            Finish::Fillet(_) => SQRT_2,
+            Finish::Chamfer(a, b) => (a + b) / a.hypot(b),
+        }
+    }
+    // The finish only fills in where both values are below this.
+    pub fn reach(&self) -> Float {
+        match *self {
+            Finish::Fillet(radius) => radius * (1. - FRAC_1_SQRT_2),
+            Finish::Chamfer(a, b) => a * b / (a + b),
+        }
+    }
+}
+
+// The value of op of objects with values a and b, with the edges finished. Intersections are
+// unions of the outsides, max(a, b) = -min(-a, -b), and differences intersections with the
+// outside of b.
+pub fn value(op: Boolean, finish: Finish, a: Float, b: Float) -> Float {
+    match op {
+        Boolean::Union => union(finish, a, b),
+        Boolean::Intersection => -union(finish, -a, -b),
+        Boolean::Difference => -union(finish, -a, b),
+    }
+}
+
+// The finished value divided by its steepness remains a lower bound of the distance. Further
+// out than the reach of the finish, the distance to the sharp union less the reach is larger,
+// and inside, the distance to the sharp union.
+fn union(finish: Finish, a: Float, b: Float) -> Float {
+    let sharp = a.min(b);
+    let finished = finished_union(finish, a, b);
+    let bound = finished / finish.steepness();
+    if finished >= 0. {
+        bound.max(sharp - finish.reach())
+    } else {
+        bound.min(sharp)
+    }
+}
+
+fn finished_union(finish: Finish, a: Float, b: Float) -> Float {
+    match finish {
+        // The distance to the circle of the radius touching both surfaces, where both are
+        // closer than the radius.
+        Finish::Fillet(r) => r.max(a.min(b)) - (r - a).max(0.).hypot((r - b).max(0.)),
+        // The line from the surface of a at b = sa to the surface of b at a = sb.
+        Finish::Chamfer(sa, sb) => a.min(b).min((a * sa + b * sb - sa * sb) / sa.hypot(sb)),
+    }
+}
+
+// Two objects combined by op with the edges between them finished.
+#[derive(Clone, Debug)]
+pub struct Edge {
+    a: Box<dyn Object<Float>>,
+    b: Box<dyn Object<Float>>,
+    op: Boolean,
+    finish: Finish,
+    bbox: BoundingBox<Float>,
+}
+
+impl Edge {
+    pub fn new(
+        a: Box<dyn Object<Float>>,
+        b: Box<dyn Object<Float>>,
+        op: Boolean,
+        finish: Finish,
+    ) -> Box<Edge> {
+        // Only unions gain material, up to the size of the finish from both objects.
+        let bbox = match op {
+            Boolean::Union => {
+                let mut bbox = a.bbox().union(b.bbox());
+                bbox.dilate(finish.size());
+                bbox
+            }
+            Boolean::Intersection => a.bbox().intersection(b.bbox()),
+            Boolean::Difference => a.bbox().clone(),
+        };
+        Box::new(Edge {
+            a,
+            b,
+            op,
+            finish,
+            bbox,
+        })
+    }
+}
+
+impl Object<Float> for Edge {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx > slack {
+            return approx;
+        }
+        // Values beyond the size of the finish are combined like by sharp booleans.
+        let slack = slack + self.finish.size();
+        value(
+            self.op,
+            self.finish,
+            self.a.approx_value(p, slack),
+            self.b.approx_value(p, slack),
+        )
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.a.set_parameters(p);
+        self.b.set_parameters(p);
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.finish.size() * RELATIVE_STEP;
+        let difference = |d: na::Vector3<Float>| {
+            self.approx_value(&(p + d), 0.) - self.approx_value(&(p - d), 0.)
+        };
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            difference(na::Vector3::z() * e),
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
+
+// a and b combined by the boolean called op (union, intersection or difference), with the
+// edges between them finished. Empty and Full have no edges, they are combined like by sharp
+// booleans.
+pub fn edge(
+    function: &'static str,
+    a: &LObject,
+    b: &LObject,
+    op: &str,
+    finish: Finish,
+) -> Result<LObject, PrimitiveError> {
+    let op = match op {
+        "union" => Boolean::Union,
+        "intersection" => Boolean::Intersection,
+        "difference" => Boolean::Difference,
+        _ => {
+            return Err(PrimitiveError::UnknownName {
+                function,
+                name: op.to_string(),
+                expected: "union, intersection or difference",
+            })
+        }
+    };
+    let finish = match finish {
+        Finish::Fillet(radius) => Finish::Fillet(error::positive(function, "radius", radius)?),
+        Finish::Chamfer(sa, sb) => Finish::Chamfer(
+            error::positive(function, "size", sa)?,
+            error::positive(function, "size", sb)?,
+        ),
+    };
+    if a.node().is_constant() || b.node().is_constant() {
+        let mut v = LObjectVector::new(a);
+        v.push(b);
+        return match op {
+            Boolean::Union => v.union(0.),
+            Boolean::Intersection => v.intersection(0.),
+            Boolean::Difference => v.difference(0.),
+        };
+    }
+    Ok(LObject::new(
+        Edge::new(a.as_object(), b.as_object(), op, finish),
+        Node::Edge {
+            children: vec![a.as_node(), b.as_node()],
+            op,
+            finish,
+        },
+    ))
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "fillet",
+        args: Some("a, b, radius, op"),
+        doc: "op (union, intersection or difference, union by default) of a and b with a round of radius where they meet.",
+    },
+    Function {
+        name: "chamfer",
+        args: Some("a, b, size, op"),
+        doc: "Like fillet, with a flat chamfer of size, or of {along a, along b}.",
+    },
+];
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.set(
+        "__fillet",
+        hlua::function4(|a: &LObject, b: &LObject, radius: Float, op: String| {
+            edge("fillet", a, b, &op, Finish::Fillet(radius))
+        }),
+    );
+    lua.set(
+        "__chamfer",
+        hlua::function5(
+            |a: &LObject, b: &LObject, sa: Float, sb: Float, op: String| {
+                edge("chamfer", a, b, &op, Finish::Chamfer(sa, sb))
+            },
+        ),
+    );
+    // The size of chamfers is a number, or a table of the sizes along a and b.
+    lua.execute::<()>(&format!(
+        "
+        function fillet(a, b, radius, op)
+          return __fillet(a, b, radius, op or \"union\")
+        end
+        function chamfer(a, b, size, op)
+          if type(size) == \"table\" then
+            return __chamfer(a, b, size[1], size[2], op or \"union\")
+          end
+          return __chamfer(a, b, size, size, op or \"union\")
+        end
+        {env}.fillet = fillet
+        {env}.chamfer = chamfer
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
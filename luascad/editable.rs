@@ -0,0 +1,111 @@
+use super::Float;
+use api::Function;
+use bounded;
+use hlua;
+use lobject::LObject;
+use nalgebra as na;
+
+// Suffixes of the parameters editable declares, in the order of Editable::value.
+pub const FIELDS: [&str; 6] = ["x", "y", "z", "rx", "ry", "rz"];
+
+// A part of the object which can be moved and rotated in the view, declared with
+// editable(o, "name"). Its offset and angles are the parameters name.x, name.y, name.z and
+// name.rx, name.ry, name.rz (in degrees), so the view sets them like the customizer does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Editable {
+    pub name: String,
+    // The point it rotates around: the center of its box before it was moved, or the origin for
+    // infinite objects.
+    pub pivot: na::Point3<Float>,
+    pub translation: na::Vector3<Float>,
+    // Degrees around x, y and z, applied like LObject::rotate.
+    pub rotation: na::Vector3<Float>,
+}
+
+impl Editable {
+    // The name of the parameter of FIELDS[field].
+    pub fn parameter(&self, field: usize) -> String {
+        format!("{}.{}", self.name, FIELDS[field])
+    }
+    // The value of the parameter of FIELDS[field].
+    pub fn value(&self, field: usize) -> Float {
+        if field < 3 {
+            self.translation[field]
+        } else {
+            self.rotation[field - 3]
+        }
+    }
+    // Where the pivot is after moving it.
+    pub fn position(&self) -> na::Point3<Float> {
+        self.pivot + self.translation
+    }
+}
+
+// o rotated around the center of its box and then moved, and the Editable for it.
+pub fn edit(
+    o: &LObject,
+    name: String,
+    translation: na::Vector3<Float>,
+    rotation: na::Vector3<Float>,
+) -> (LObject, Editable) {
+    let bbox = o.object().bbox();
+    let pivot = if bounded::is_finite(bbox) {
+        na::center(&bbox.min, &bbox.max)
+    } else {
+        na::Point3::origin()
+    };
+    let radians = rotation.map(Float::to_radians);
+    let moved = o
+        .translate(-pivot.x, -pivot.y, -pivot.z)
+        .rotate(radians.x, radians.y, radians.z)
+        .translate(
+            pivot.x + translation.x,
+            pivot.y + translation.y,
+            pivot.z + translation.z,
+        );
+    (
+        moved,
+        Editable {
+            name,
+            pivot,
+            translation,
+            rotation,
+        },
+    )
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "editable",
+        args: Some("o, name"),
+        doc: "o moved and rotated by the parameters name.x, name.y, name.z, name.rx, name.ry and name.rz, which can be dragged in the view.",
+    },
+];
+
+// Define editable in the env, which declares the parameters of an Editable and passes them on to
+// the function __editable (see luascad::evaluate_with).
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.execute::<()>(&format!(
+        "
+        function editable(o, name)
+          if type(name) ~= \"string\" then
+            error(\"editable: name must be a string\")
+          end
+          local v = {{}}
+          for i, field in ipairs{{{fields}}} do
+            v[i] = param{{name = name .. \".\" .. field, default = 0}}
+          end
+          return __editable(o, name, v[1], v[2], v[3], v[4], v[5], v[6])
+        end
+        {env}.editable = editable
+        ",
+        fields = FIELDS
+            .iter()
+            .map(|f| format!("\"{}\"", f))
+            .collect::<Vec<_>>()
+            .join(", "),
+        env = env_name
+    ))
+    .unwrap();
+}
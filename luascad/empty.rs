@@ -0,0 +1,55 @@
+use super::Float;
+use implicit3d::{BoundingBox, Object};
+use nalgebra as na;
+
+// The empty set, outside everywhere.
+#[derive(Clone, Debug)]
+pub struct Empty {
+    bbox: BoundingBox<Float>,
+}
+
+impl Empty {
+    pub fn new() -> Box<Empty> {
+        Box::new(Empty {
+            bbox: BoundingBox::neg_infinity(),
+        })
+    }
+}
+
+impl Object<Float> for Empty {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn approx_value(&self, _: &na::Point3<Float>, _: Float) -> Float {
+        Float::INFINITY
+    }
+    fn normal(&self, _: &na::Point3<Float>) -> na::Vector3<Float> {
+        na::Vector3::zeros()
+    }
+}
+
+// Everything, inside everywhere.
+#[derive(Clone, Debug)]
+pub struct Full {
+    bbox: BoundingBox<Float>,
+}
+
+impl Full {
+    pub fn new() -> Box<Full> {
+        Box::new(Full {
+            bbox: BoundingBox::infinity(),
+        })
+    }
+}
+
+impl Object<Float> for Full {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn approx_value(&self, _: &na::Point3<Float>, _: Float) -> Float {
+        Float::NEG_INFINITY
+    }
+    fn normal(&self, _: &na::Point3<Float>) -> na::Vector3<Float> {
+        na::Vector3::zeros()
+    }
+}
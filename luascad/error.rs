@@ -0,0 +1,288 @@
+use super::Float;
+use std::error::Error;
+use std::fmt;
+use units::Units;
+
+// Invalid arguments for an object, which would make implicit3d panic or produce NaNs.
+// Returned from Lua functions, where hlua raises it as a script error with the Debug message.
+#[derive(Clone, PartialEq)]
+pub enum PrimitiveError {
+    // A scale which can not be inverted.
+    Singular {
+        x: Float,
+        y: Float,
+        z: Float,
+    },
+    // A parameter of function outside of its valid range, which requirement describes.
+    InvalidParameter {
+        function: &'static str,
+        parameter: &'static str,
+        value: Float,
+        requirement: &'static str,
+    },
+    // A boolean of no objects.
+    NoObjects,
+    // A measurement of function which needs a finite object.
+    Infinite(&'static str),
+    // An analysis of function which needs a finite object.
+    Unbounded(&'static str),
+    // A query of function which needs an object with a surface, i.e. not Empty or Full.
+    NoSurface(&'static str),
+    // An axis name other than x, y or z.
+    UnknownAxis {
+        function: &'static str,
+        name: String,
+    },
+    // assert_symmetric with an object which is further from symmetric than tolerance.
+    Asymmetric {
+        axis: String,
+        asymmetry: Float,
+        tolerance: Float,
+    },
+    // A table given to solve_constraints or articulate which can not be read or solved, with
+    // what is wrong with it.
+    InvalidConstraint {
+        function: &'static str,
+        message: String,
+    },
+    // Constraints which solve_constraints could not meet, with the residual it left, see
+    // constraints::Solution.
+    Unsolvable(Float),
+    // A name of a standard part or size which function does not know, with those it knows.
+    UnknownName {
+        function: &'static str,
+        name: String,
+        expected: &'static str,
+    },
+    // An argument of function which it can not use, e.g. a table of options, with what is wrong
+    // with it.
+    InvalidArgument {
+        function: &'static str,
+        message: &'static str,
+    },
+    // arrange with a part (counted from 1) which does not fit on the plate next to those
+    // before it, with the size of the plate.
+    NoRoom {
+        part: usize,
+        width: Float,
+        depth: Float,
+    },
+    // A param{...} table which does not declare a valid parameter, with what is wrong with it.
+    InvalidDeclaration {
+        name: String,
+        message: &'static str,
+    },
+    // set_units with a name Units::parse does not know.
+    UnknownUnits(String),
+    // set_units with other units than a previous call, e.g. from a library in other units.
+    ConflictingUnits {
+        units: Units,
+        previous: Units,
+    },
+    // include with a name of no script it can read, with why.
+    Include {
+        name: String,
+        message: String,
+    },
+    // A file which function could not import, with why.
+    Import {
+        function: &'static str,
+        filename: String,
+        message: String,
+    },
+    // A function of a plugin which could not build its object, with why, see
+    // plugin::ObjectProvider.
+    Plugin {
+        function: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for PrimitiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrimitiveError::Singular { x, y, z } => write!(
+                f,
+                "scale({}, {}, {}): all factors must be non-zero and finite",
+                x, y, z
+            ),
+            PrimitiveError::InvalidParameter {
+                function,
+                parameter,
+                value,
+                requirement,
+            } => write!(
+                f,
+                "{}: {} must be {}, got {}",
+                function, parameter, requirement, value
+            ),
+            PrimitiveError::NoObjects => write!(f, "booleans need at least one object"),
+            PrimitiveError::Infinite(function) => {
+                write!(f, "{}: at least one object must be finite", function)
+            }
+            PrimitiveError::Unbounded(function) => {
+                write!(f, "{}: the object must be finite", function)
+            }
+            PrimitiveError::NoSurface(function) => {
+                write!(f, "{}: the object has no surface", function)
+            }
+            PrimitiveError::UnknownAxis { function, ref name } => write!(
+                f,
+                "{}(\"{}\"): unknown axis, expected x, y or z",
+                function, name
+            ),
+            PrimitiveError::Asymmetric {
+                ref axis,
+                asymmetry,
+                tolerance,
+            } => write!(
+                f,
+                "assert_symmetric(\"{}\"): the object is asymmetric by {}, more than the \
+                 tolerance {}",
+                axis, asymmetry, tolerance
+            ),
+            PrimitiveError::InvalidConstraint {
+                function,
+                ref message,
+            } => write!(f, "{}: {}", function, message),
+            PrimitiveError::Unsolvable(residual) => write!(
+                f,
+                "solve_constraints: the constraints can not all be met, they are off by {}",
+                residual
+            ),
+            PrimitiveError::UnknownName {
+                function,
+                ref name,
+                expected,
+            } => write!(
+                f,
+                "{}(\"{}\"): unknown name, expected {}",
+                function, name, expected
+            ),
+            PrimitiveError::InvalidArgument { function, message } => {
+                write!(f, "{}: {}", function, message)
+            }
+            PrimitiveError::NoRoom { part, width, depth } => write!(
+                f,
+                "arrange: part {} does not fit on the {} x {} plate",
+                part, width, depth
+            ),
+            PrimitiveError::InvalidDeclaration { ref name, message } => {
+                if name.is_empty() {
+                    write!(f, "param: {}", message)
+                } else {
+                    write!(f, "param{{name = \"{}\"}}: {}", name, message)
+                }
+            }
+            PrimitiveError::UnknownUnits(ref name) => write!(
+                f,
+                "set_units(\"{}\"): unknown units, expected mm, cm or in",
+                name
+            ),
+            PrimitiveError::ConflictingUnits { units, previous } => write!(
+                f,
+                "set_units(\"{}\"): the units are already set to {}",
+                units, previous
+            ),
+            PrimitiveError::Include {
+                ref name,
+                ref message,
+            } => write!(f, "include(\"{}\"): {}", name, message),
+            PrimitiveError::Import {
+                function,
+                ref filename,
+                ref message,
+            } => write!(f, "{}(\"{}\"): {}", function, filename, message),
+            PrimitiveError::Plugin {
+                ref function,
+                ref message,
+            } => write!(f, "{}: {}", function, message),
+        }
+    }
+}
+
+// The message as is, since this is what Lua shows.
+impl fmt::Debug for PrimitiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for PrimitiveError {}
+
+// The index of the axis called name.
+pub fn axis(function: &'static str, name: &str) -> Result<usize, PrimitiveError> {
+    match name {
+        "x" => Ok(0),
+        "y" => Ok(1),
+        "z" => Ok(2),
+        _ => Err(PrimitiveError::UnknownAxis {
+            function,
+            name: name.to_string(),
+        }),
+    }
+}
+
+// value, if it is positive and finite.
+pub fn positive(
+    function: &'static str,
+    parameter: &'static str,
+    value: Float,
+) -> Result<Float, PrimitiveError> {
+    check(
+        function,
+        parameter,
+        value,
+        value > 0.,
+        "positive and finite",
+    )
+}
+
+// value, if it is non-zero and finite.
+pub fn non_zero(
+    function: &'static str,
+    parameter: &'static str,
+    value: Float,
+) -> Result<Float, PrimitiveError> {
+    check(
+        function,
+        parameter,
+        value,
+        value != 0.,
+        "non-zero and finite",
+    )
+}
+
+// value, if it is at least 0 and finite.
+pub fn non_negative(
+    function: &'static str,
+    parameter: &'static str,
+    value: Float,
+) -> Result<Float, PrimitiveError> {
+    check(
+        function,
+        parameter,
+        value,
+        value >= 0.,
+        "at least 0 and finite",
+    )
+}
+
+fn check(
+    function: &'static str,
+    parameter: &'static str,
+    value: Float,
+    valid: bool,
+    requirement: &'static str,
+) -> Result<Float, PrimitiveError> {
+    if valid && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(PrimitiveError::InvalidParameter {
+            function,
+            parameter,
+            value,
+            requirement,
+        })
+    }
+}
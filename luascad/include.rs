@@ -0,0 +1,92 @@
+use api::Function;
+use error::PrimitiveError;
+use hlua;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+// Where include("name") finds the scripts it runs: in files (e.g. of a project), or else in the
+// directory dir (of the script file).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Includes {
+    pub files: HashMap<String, String>,
+    pub dir: Option<PathBuf>,
+    // Whether the script comes from someone else, e.g. through truescad serve. Untrusted scripts
+    // only include files and can not read any others, e.g. with Mesh or ImportSVG.
+    pub untrusted: bool,
+}
+
+impl Includes {
+    // The files next to script, a path.
+    pub fn beside(script: &str) -> Includes {
+        Includes {
+            files: HashMap::new(),
+            dir: Path::new(script).parent().map(Path::to_path_buf),
+            untrusted: false,
+        }
+    }
+    // The text of the script name. Names are relative to dir and can not leave it, so scripts
+    // can not read other files.
+    pub fn source(&self, name: &str) -> Result<String, PrimitiveError> {
+        if let Some(source) = self.files.get(name) {
+            return Ok(source.clone());
+        }
+        let error = |message: String| PrimitiveError::Include {
+            name: name.to_string(),
+            message,
+        };
+        let path = Path::new(name);
+        let inside = path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !inside {
+            return Err(error(
+                "must be a path within the directory of the script".to_string(),
+            ));
+        }
+        match self.dir {
+            Some(ref dir) if !self.untrusted => {
+                ::std::fs::read_to_string(dir.join(path)).map_err(|e| error(e.to_string()))
+            }
+            _ => Err(error("no such script".to_string())),
+        }
+    }
+    // The path of a file a script reads, e.g. with Mesh or ImportSVG, relative to dir if it is known.
+    pub fn path(&self, name: &str) -> Result<PathBuf, String> {
+        if self.untrusted {
+            return Err("reading files is not allowed".to_string());
+        }
+        Ok(match self.dir {
+            Some(ref dir) => dir.join(name),
+            None => PathBuf::from(name),
+        })
+    }
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "include",
+        args: Some("name"),
+        doc: "Run the script called name, next to this script or in the same project, and return what it returns.",
+    },
+];
+
+// Define include in the env, which runs the script of a name from the function __include (see
+// luascad::evaluate_in) in the env and returns what it returns.
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.execute::<()>(&format!(
+        "
+        function include(name)
+          if type(name) ~= \"string\" then
+            error(\"include: name must be a string\")
+          end
+          local f, e = load(__include(name), \"=\" .. name, \"t\", {env})
+          if not f then
+            error(e, 0)
+          end
+          return f()
+        end
+        {env}.include = include
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
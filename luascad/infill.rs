@@ -0,0 +1,127 @@
+use super::{Float, RELATIVE_STEP};
+use error::{self, PrimitiveError};
+use float::consts::PI;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+
+// The gyroid sin x cos y + sin y cos z + sin z cos x changes by 1.5 per radian on average at
+// its zero surface, and by at most the square root of 3 anywhere, rounded up here so that it
+// stays a bound in f32 too.
+const GYROID_GRADIENT: Float = 1.5;
+const GYROID_MAX_GRADIENT: Float = 1.732_051;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    // Hexagonal cells of the cell size across flats, along z.
+    Hex,
+    // Square cells of the cell size, along z.
+    Grid,
+    // A gyroid with a period of the cell size along each axis.
+    Gyroid,
+}
+
+impl Pattern {
+    pub fn parse(name: &str) -> Result<Pattern, PrimitiveError> {
+        match name {
+            "hex" => Ok(Pattern::Hex),
+            "grid" => Ok(Pattern::Grid),
+            "gyroid" => Ok(Pattern::Gyroid),
+            _ => Err(PrimitiveError::UnknownName {
+                function: "infill",
+                name: name.to_string(),
+                expected: "hex, grid or gyroid",
+            }),
+        }
+    }
+}
+
+// Walls of wall thickness around cells of the pattern, repeating infinitely in all directions.
+#[derive(Clone, Debug)]
+pub struct Lattice {
+    pattern: Pattern,
+    cell: Float,
+    wall: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl Lattice {
+    pub fn new(pattern: Pattern, cell: Float, wall: Float) -> Result<Box<Lattice>, PrimitiveError> {
+        let cell = error::positive("infill", "cell", cell)?;
+        let wall = error::positive("infill", "wall", wall)?;
+        if wall >= cell {
+            return Err(PrimitiveError::InvalidParameter {
+                function: "infill",
+                parameter: "wall",
+                value: wall,
+                requirement: "thinner than the cell size",
+            });
+        }
+        Ok(Box::new(Lattice {
+            pattern,
+            cell,
+            wall,
+            bbox: BoundingBox::infinity(),
+        }))
+    }
+    fn value(&self, p: &na::Point3<Float>) -> Float {
+        match self.pattern {
+            Pattern::Hex => {
+                // The centers of the cells are on a triangular lattice. The nearest one is the
+                // one with the smallest distance measured along the normals of the flats.
+                let (a, b) = (
+                    na::Vector2::new(self.cell, 0.),
+                    na::Vector2::new(self.cell / 2., self.cell * Float::sqrt(3.) / 2.),
+                );
+                let j = (p.y / b.y).floor();
+                let i = ((p.x - j * b.x) / a.x).floor();
+                let mut center = Float::INFINITY;
+                for &(di, dj) in &[(0., 0.), (1., 0.), (0., 1.), (1., 1.)] {
+                    let c = a * (i + di) + b * (j + dj);
+                    center = center.min(hex_norm(p.x - c.x, p.y - c.y));
+                }
+                self.cell / 2. - center - self.wall / 2.
+            }
+            Pattern::Grid => {
+                let offset = |x: Float| (x - self.cell * (x / self.cell).round()).abs();
+                offset(p.x).min(offset(p.y)) - self.wall / 2.
+            }
+            Pattern::Gyroid => {
+                let scale = 2. * PI / self.cell;
+                let (x, y, z) = (p.x * scale, p.y * scale, p.z * scale);
+                let f = x.sin() * y.cos() + y.sin() * z.cos() + z.sin() * x.cos();
+                let thickness = self.wall / 2. * GYROID_GRADIENT * scale;
+                (f.abs() - thickness) / (GYROID_MAX_GRADIENT * scale)
+            }
+        }
+    }
+}
+
+// The distance from the center of a hexagon with flats normal to x to the line through the flat
+// towards (x, y).
+fn hex_norm(x: Float, y: Float) -> Float {
+    let s = Float::sqrt(3.) / 2.;
+    x.abs()
+        .max((0.5 * x + s * y).abs())
+        .max((0.5 * x - s * y).abs())
+}
+
+impl Object<Float> for Lattice {
+    fn approx_value(&self, p: &na::Point3<Float>, _: Float) -> Float {
+        self.value(p)
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, _: &PrimitiveParameters<Float>) {}
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.cell * RELATIVE_STEP;
+        let difference = |d: na::Vector3<Float>| self.value(&(p + d)) - self.value(&(p - d));
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            difference(na::Vector3::z() * e),
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
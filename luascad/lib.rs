@@ -1,15 +1,68 @@
 #[macro_use]
 extern crate hlua;
 pub extern crate implicit3d;
+#[cfg(feature = "dynamic-plugins")]
+extern crate libloading;
 extern crate nalgebra;
 
+pub mod analysis;
+pub mod api;
+pub mod arrange;
+pub mod blend;
+pub mod bounded;
+pub mod bvh;
+pub mod cache;
+pub mod component;
+pub mod constraints;
+pub mod convert;
+pub mod dxf;
+pub mod edge;
+pub mod editable;
+pub mod empty;
+pub mod error;
+pub mod include;
+pub mod infill;
 pub mod lobject;
 pub mod lobject_vector;
 pub mod luascad;
+pub mod measure;
+pub mod morphology;
+pub mod normals;
+pub mod optimize;
+pub mod outline;
+pub mod params;
+pub mod parts;
+pub mod plugin;
+pub mod pointcloud;
 pub mod printbuffer;
+pub mod profile;
 pub mod sandbox;
+pub mod svg;
+pub mod testing;
+pub mod threads;
+pub mod transformer;
+pub mod tree;
+pub mod units;
 
-pub use self::luascad::eval;
+pub use self::luascad::{
+    eval, eval_at, evaluate, evaluate_in, evaluate_with, Evaluation, ScriptError,
+};
 
-type Float = f64;
-const EPSILON: f64 = std::f64::EPSILON;
+// The precision of all objects. The f32 feature halves the memory of meshes,
+// point clouds and flattened trees at the cost of accuracy.
+#[cfg(not(feature = "f32"))]
+use std::f64 as float;
+#[cfg(feature = "f32")]
+use std::f32 as float;
+
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+const EPSILON: Float = Float::EPSILON;
+// Finite differences step this fraction of the size of what they differentiate, well above the
+// precision of Float.
+#[cfg(not(feature = "f32"))]
+pub const RELATIVE_STEP: Float = 1e-6;
+#[cfg(feature = "f32")]
+pub const RELATIVE_STEP: Float = 1e-3;
@@ -1,19 +1,63 @@
 use super::{Float, EPSILON};
+use analysis::{self, MassProperties, OverhangRegion, ThinRegion};
+use api::Function;
+use bounded::{self, Bounded};
+use cache::Cache;
+use component::Component;
+use convert;
+use empty::{Empty, Full};
+use error::{self, PrimitiveError};
 use hlua;
 use implicit3d::{
     Bender, BoundingBox, Cone, Cylinder, Intersection, Mesh, NormalPlane, Object, PlaneNegX,
     PlaneNegY, PlaneNegZ, PlaneX, PlaneY, PlaneZ, Sphere, Twister,
 };
+use include::Includes;
+use infill::{Lattice, Pattern};
+use lobject_vector::LObjectVector;
+use measure::{self, SurfaceQuery};
+use morphology::{Morphology, Offset, Operation};
 use nalgebra as na;
-use std::sync::mpsc;
+use printbuffer::{Console, Level};
+use profile::{self, Profile};
+use std::fmt;
+use std::hash::Hasher;
+use std::path::Path;
+use std::sync::Arc;
+use transformer;
+use tree::Node;
 
-#[derive(Clone, Debug)]
+// The default tolerance of assert_symmetric, relative to the size of the object.
+const SYMMETRY_TOLERANCE: Float = 1e-3;
+// split places this many pins, which reach this many diameters into the other part. Their holes
+// are larger by PIN_CLEARANCE of the diameter all around.
+const PINS: usize = 2;
+const PIN_DEPTH: Float = 1.5;
+const PIN_CLEARANCE: Float = 0.05;
+
+// node describes how o was built. Both are always set, they are only Options (and node is boxed)
+// since hlua requires all-zero to be a valid value, which is also why color is boxed. Invalid
+// objects are Empty.
+#[derive(Clone)]
 pub struct LObject {
-    pub o: Option<Box<dyn Object<Float>>>,
+    o: Option<Box<dyn Object<Float>>>,
+    node: Option<Box<Node>>,
+    // The operands, if o is a union without rounding, transformed like it. See
+    // LObjectVector::union and LObject::rotate.
+    pub parts: Option<Vec<LObject>>,
+    // The color set with o:color, as linear RGB from 0 to 1. It stays with transformed objects,
+    // and the parts of unions keep theirs, see LObject::colored_parts.
+    pub color: Option<Box<na::Vector3<Float>>>,
+}
+
+impl fmt::Debug for LObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LObject").field("o", &self.o).finish()
+    }
 }
 
-pub const INFINITY: Float = 1e10;
-pub const NEG_INFINITY: Float = -1e10;
+// A part of an object with the color set with o:color, None for the default color.
+pub type ColoredPart = (Box<dyn Object<Float>>, Option<na::Vector3<Float>>);
 
 // this macro implements the required trait so that we can *push* the object to lua
 // (ie. move it inside lua)
@@ -36,6 +80,117 @@ implement_lua_push!(LObject, |mut metatable| {
             ::hlua::function4(|o: &mut LObject, x: Float, y: Float, z: Float| o.scale(x, y, z)),
         );
         index.set("clone", ::hlua::function1(|o: &mut LObject| o.clone()));
+        index.set(
+            "color",
+            ::hlua::function4(|o: &mut LObject, r: Float, g: Float, b: Float| o.colored(r, g, b)),
+        );
+        // The tolerance is optional.
+        index.set(
+            "collides",
+            ::hlua::function3(
+                |o: &LObject, other: &LObject, tolerance: ::hlua::AnyLuaValue| {
+                    let tolerance = match tolerance {
+                        ::hlua::AnyLuaValue::LuaNumber(t) => t as Float,
+                        _ => 0.,
+                    };
+                    o.collides(other, tolerance)
+                },
+            ),
+        );
+        index.set(
+            "closest_surface_point",
+            ::hlua::function4(|o: &LObject, x: Float, y: Float, z: Float| {
+                o.closest_surface_point(&na::Point3::new(x, y, z))
+                    .map(|p| lua_point(&p))
+            }),
+        );
+        index.set(
+            "mass_properties",
+            ::hlua::function2(|o: &LObject, density: Float| {
+                o.mass_properties(density).map(|m| mass_table(&m))
+            }),
+        );
+        index.set(
+            "thin_walls",
+            ::hlua::function2(|o: &LObject, threshold: Float| {
+                o.thin_walls(threshold)
+                    .map(|regions| regions.iter().map(thin_region_table).collect::<Vec<_>>())
+            }),
+        );
+        index.set(
+            "overhangs",
+            ::hlua::function2(|o: &LObject, max_angle: Float| {
+                o.overhangs(max_angle).map(|regions| {
+                    regions
+                        .iter()
+                        .map(overhang_region_table)
+                        .collect::<Vec<_>>()
+                })
+            }),
+        );
+        // The tolerance is optional.
+        index.set(
+            "assert_symmetric",
+            ::hlua::function3(
+                |o: &LObject, axis: String, tolerance: ::hlua::AnyLuaValue| {
+                    let tolerance = match tolerance {
+                        ::hlua::AnyLuaValue::LuaNumber(t) => Some(t as Float),
+                        _ => None,
+                    };
+                    o.assert_symmetric(&axis, tolerance)
+                },
+            ),
+        );
+        index.set(
+            "infill",
+            ::hlua::function4(|o: &LObject, pattern: String, cell: Float, wall: Float| {
+                o.infill(&pattern, cell, wall)
+            }),
+        );
+        // The pin diameter is optional.
+        index.set(
+            "split",
+            ::hlua::function3(
+                |o: &LObject, plane: &LObject, pin_diameter: ::hlua::AnyLuaValue| {
+                    let pin_diameter = match pin_diameter {
+                        ::hlua::AnyLuaValue::LuaNumber(d) => Some(d as Float),
+                        _ => None,
+                    };
+                    o.split(plane, pin_diameter)
+                },
+            ),
+        );
+        // The options are optional.
+        index.set(
+            "hollow",
+            ::hlua::function3(|o: &LObject, wall: Float, options: HollowOptions| {
+                let (holes, infill) = options.0?;
+                o.hollow(wall, &holes, infill)
+            }),
+        );
+        // The operation is optional.
+        index.set(
+            "smooth",
+            ::hlua::function3(|o: &LObject, radius: Float, op: ::hlua::AnyLuaValue| {
+                let op = match op {
+                    ::hlua::AnyLuaValue::LuaString(name) => Operation::parse(&name)?,
+                    _ => Operation::Opening,
+                };
+                o.smooth(op, radius)
+            }),
+        );
+        index.set(
+            "components",
+            ::hlua::function1(|o: &LObject| o.components()),
+        );
+        index.set(
+            "supports",
+            ::hlua::function4(
+                |o: &LObject, max_angle: Float, spacing: Float, radius: Float| {
+                    o.supports(max_angle, spacing, radius)
+                },
+            ),
+        );
     }
     // Add __tostring metamethod for printing LObjects.
     metatable.set(
@@ -47,9 +202,583 @@ implement_lua_push!(LObject, |mut metatable| {
 // this macro implements the require traits so that we can *read* the object back
 implement_lua_read!(LObject);
 
+// The functions export_factories defines, see api.
+pub const API: &[Function] = &[
+    Function {
+        name: "PlaneX",
+        args: Some("d"),
+        doc: "Everything below x = d.",
+    },
+    Function {
+        name: "PlaneY",
+        args: Some("d"),
+        doc: "Everything below y = d.",
+    },
+    Function {
+        name: "PlaneZ",
+        args: Some("d"),
+        doc: "Everything below z = d.",
+    },
+    Function {
+        name: "PlaneNegX",
+        args: Some("d"),
+        doc: "Everything above x = -d.",
+    },
+    Function {
+        name: "PlaneNegY",
+        args: Some("d"),
+        doc: "Everything above y = -d.",
+    },
+    Function {
+        name: "PlaneNegZ",
+        args: Some("d"),
+        doc: "Everything above z = -d.",
+    },
+    Function {
+        name: "PlaneHessian",
+        args: Some("n, p"),
+        doc: "Everything behind the plane with the normal n = {x, y, z} at distance p from the origin.",
+    },
+    Function {
+        name: "Plane3Points",
+        args: Some("a, b, c"),
+        doc: "Everything behind the plane through the points a, b and c = {x, y, z}.",
+    },
+    Function {
+        name: "Sphere",
+        args: Some("radius"),
+        doc: "Sphere around the origin.",
+    },
+    Function {
+        name: "Box",
+        args: Some("x, y, z, smooth"),
+        doc: "Box of the size x, y, z around the origin, with the edges rounded by smooth (optional).",
+    },
+    Function {
+        name: "Cylinder",
+        args: Some("{l, r, s}"),
+        doc: "Cylinder of length l along the z axis around the origin, or a cone with r1 and r2 instead of r. The edges are rounded by s (optional).",
+    },
+    Function {
+        name: "iCylinder",
+        args: Some("radius"),
+        doc: "Infinite cylinder along the z axis.",
+    },
+    Function {
+        name: "iCone",
+        args: Some("slope"),
+        doc: "Infinite double cone along the z axis with the tip at the origin.",
+    },
+    Function {
+        name: "Bend",
+        args: Some("o, width"),
+        doc: "o bent around the z axis, so that the width along x makes a full turn.",
+    },
+    Function {
+        name: "Twist",
+        args: Some("o, height"),
+        doc: "o twisted around the z axis, by a full turn per height along z.",
+    },
+    Function {
+        name: "Empty",
+        args: Some(""),
+        doc: "Nothing.",
+    },
+    Function {
+        name: "Full",
+        args: Some(""),
+        doc: "Everything.",
+    },
+    Function {
+        name: "Mesh",
+        args: Some("filename"),
+        doc: "The solid of an STL file, or a STEP or IGES file with mesh_converter. Slow, see Cache.",
+    },
+    Function {
+        name: "Cache",
+        args: Some("o, quantum"),
+        doc: "o with its values remembered on a grid of spacing quantum, e.g. for a Mesh.",
+    },
+    Function {
+        name: "Profile",
+        args: Some("o, name"),
+        doc: "o counting how often it is evaluated, printed as name.",
+    },
+    Function {
+        name: "measure_min_distance",
+        args: Some("a, b"),
+        doc: "The distance between the surfaces of a and b, 0 if they overlap.",
+    },
+];
+
+// The methods of objects, see the __index table above.
+pub const METHODS: &[Function] = &[
+    Function {
+        name: "o:translate",
+        args: Some("x, y, z"),
+        doc: "o moved by x, y, z.",
+    },
+    Function {
+        name: "o:rotate",
+        args: Some("x, y, z"),
+        doc: "o rotated around the x, y and z axes, in radians.",
+    },
+    Function {
+        name: "o:scale",
+        args: Some("x, y, z"),
+        doc: "o scaled along the axes. The factors must not be 0.",
+    },
+    Function {
+        name: "o:clone",
+        args: Some(""),
+        doc: "A copy of o.",
+    },
+    Function {
+        name: "o:color",
+        args: Some("r, g, b"),
+        doc: "o shown in the color r, g, b (from 0 to 1) in the preview. Unions keep the colors \
+              of their parts.",
+    },
+    Function {
+        name: "o:collides",
+        args: Some("other, tolerance"),
+        doc: "Whether o and other overlap by more than tolerance (optional).",
+    },
+    Function {
+        name: "o:closest_surface_point",
+        args: Some("x, y, z"),
+        doc: "The point {x, y, z} on the surface of o closest to x, y, z, e.g. to snap features to it.",
+    },
+    Function {
+        name: "o:mass_properties",
+        args: Some("density"),
+        doc: "volume, mass, centroid = {x, y, z} and inertia (a 3x3 table) of o.",
+    },
+    Function {
+        name: "o:thin_walls",
+        args: Some("threshold"),
+        doc: "The regions where the walls of o are thinner than threshold: thickness, at, min and max.",
+    },
+    Function {
+        name: "o:overhangs",
+        args: Some("max_angle"),
+        doc: "The regions of o overhanging by more than max_angle degrees from the vertical: min, max and points.",
+    },
+    Function {
+        name: "o:supports",
+        args: Some("max_angle, spacing, radius"),
+        doc: "Pillars of radius under the overhangs of o, spaced by spacing.",
+    },
+    Function {
+        name: "o:assert_symmetric",
+        args: Some("axis, tolerance"),
+        doc: "Fail unless o is symmetric to the plane normal to the axis x, y or z through the origin, within tolerance (optional).",
+    },
+    Function {
+        name: "o:infill",
+        args: Some("pattern, cell, wall"),
+        doc: "o filled with walls of wall thickness around cells of the pattern hex, grid or gyroid.",
+    },
+    Function {
+        name: "o:split",
+        args: Some("plane, pin_diameter"),
+        doc: "The parts of o behind and in front of the plane object (e.g. PlaneZ(0)), with pins of pin_diameter (optional) on the cut face of the first and holes for them in the second.",
+    },
+    Function {
+        name: "o:hollow",
+        args: Some("wall, {drain_holes, infill}"),
+        doc: "o with a cavity inside walls of wall thickness, e.g. for resin printing. drain_holes (optional) = {{x, y, z, r}, ...} drills holes of radius r through the wall at the surface points, infill (optional) = {pattern, cell, wall} fills the cavity like o:infill.",
+    },
+    Function {
+        name: "o:smooth",
+        args: Some("radius, op"),
+        doc: "o with the convex (opening, the default) or concave (closing) edges rounded by radius.",
+    },
+    Function {
+        name: "o:components",
+        args: Some(""),
+        doc: "The connected solid parts of o, largest first.",
+    },
+];
+
 impl LObject {
-    pub fn as_object(&self) -> Option<Box<dyn Object<Float>>> {
-        self.o.clone()
+    pub fn new(o: Box<dyn Object<Float>>, node: Node) -> LObject {
+        LObject {
+            o: Some(profile::count(o, node.kind())),
+            node: Some(Box::new(node)),
+            parts: None,
+            color: None,
+        }
+    }
+    pub fn empty() -> LObject {
+        LObject::new(Empty::new(), Node::Empty)
+    }
+    pub fn full() -> LObject {
+        LObject::new(Full::new(), Node::Full)
+    }
+    // Empty or Full for the Node resolved by Node::resolve.
+    pub fn constant(node: &Node) -> LObject {
+        match *node {
+            Node::Full => LObject::full(),
+            _ => LObject::empty(),
+        }
+    }
+    pub fn object(&self) -> &dyn Object<Float> {
+        &**self.o.as_ref().expect("LObject without object")
+    }
+    pub fn node(&self) -> &Node {
+        self.node.as_ref().expect("LObject without node")
+    }
+    pub fn as_object(&self) -> Box<dyn Object<Float>> {
+        self.o.clone().expect("LObject without object")
+    }
+    pub fn as_node(&self) -> Node {
+        self.node().clone()
+    }
+    // See Node::tree_eq. implicit3d objects can not be compared, so their nodes are.
+    pub fn tree_eq(&self, other: &LObject) -> bool {
+        self.node().tree_eq(other.node())
+    }
+    pub fn tree_hash<H: Hasher>(&self, state: &mut H) {
+        self.node().tree_hash(state)
+    }
+    // The minimum distance between the surfaces of self and other, 0 if they overlap, see
+    // measure::min_distance. Empty is infinitely far away from everything, Full overlaps
+    // everything else.
+    pub fn min_distance(&self, other: &LObject) -> Result<Float, PrimitiveError> {
+        match (self.node(), other.node()) {
+            (&Node::Empty, _) | (_, &Node::Empty) => Ok(Float::INFINITY),
+            (&Node::Full, _) | (_, &Node::Full) => Ok(0.),
+            _ => measure::min_distance(self.object(), other.object())
+                .map(|clearance| clearance.distance)
+                .ok_or(PrimitiveError::Infinite("measure_min_distance")),
+        }
+    }
+    // Whether self and other overlap by more than tolerance, see measure::intersects.
+    pub fn collides(&self, other: &LObject, tolerance: Float) -> Result<bool, PrimitiveError> {
+        match (self.node(), other.node()) {
+            (&Node::Empty, _) | (_, &Node::Empty) => Ok(false),
+            (&Node::Full, _) | (_, &Node::Full) => Ok(true),
+            _ => measure::intersects(self.object(), other.object(), tolerance)
+                .ok_or(PrimitiveError::Infinite("collides")),
+        }
+    }
+    // See SurfaceQuery::closest_surface_point.
+    pub fn closest_surface_point(
+        &self,
+        p: &na::Point3<Float>,
+    ) -> Result<na::Point3<Float>, PrimitiveError> {
+        if self.node().is_constant() {
+            return Err(PrimitiveError::NoSurface("closest_surface_point"));
+        }
+        self.object()
+            .closest_surface_point(p)
+            .ok_or(PrimitiveError::NoSurface("closest_surface_point"))
+    }
+    // See analysis::mass_properties.
+    pub fn mass_properties(&self, density: Float) -> Result<MassProperties, PrimitiveError> {
+        let density = error::positive("mass_properties", "density", density)?;
+        analysis::mass_properties(self.object(), density)
+            .ok_or(PrimitiveError::Unbounded("mass_properties"))
+    }
+    // The asymmetry of self to the plane through the origin normal to the axis called name, see
+    // analysis::asymmetry. An error if it is more than tolerance, by default a thousandth of the
+    // size of self, so that scripts can check their models.
+    pub fn assert_symmetric(
+        &self,
+        name: &str,
+        tolerance: Option<Float>,
+    ) -> Result<Float, PrimitiveError> {
+        let axis = error::axis("assert_symmetric", name)?;
+        let tolerance = match tolerance {
+            Some(tolerance) => error::positive("assert_symmetric", "tolerance", tolerance)?,
+            None => self.object().bbox().dim().norm() * SYMMETRY_TOLERANCE,
+        };
+        let asymmetry = match *self.node() {
+            Node::Empty | Node::Full => return Ok(0.),
+            _ => analysis::asymmetry(self.object(), axis)
+                .ok_or(PrimitiveError::Unbounded("assert_symmetric"))?,
+        };
+        if asymmetry > tolerance {
+            return Err(PrimitiveError::Asymmetric {
+                axis: name.to_string(),
+                asymmetry,
+                tolerance,
+            });
+        }
+        Ok(asymmetry)
+    }
+    // See analysis::thin_walls. Full has no walls.
+    pub fn thin_walls(&self, threshold: Float) -> Result<Vec<ThinRegion>, PrimitiveError> {
+        let threshold = error::positive("thin_walls", "threshold", threshold)?;
+        match *self.node() {
+            Node::Full => Ok(Vec::new()),
+            _ => analysis::thin_walls(self.object(), threshold)
+                .ok_or(PrimitiveError::Unbounded("thin_walls")),
+        }
+    }
+    // See analysis::overhangs, with max_angle in degrees like slicers. Full has no surface.
+    pub fn overhangs(&self, max_angle: Float) -> Result<Vec<OverhangRegion>, PrimitiveError> {
+        let max_angle = overhang_angle("overhangs", max_angle)?;
+        match *self.node() {
+            Node::Full => Ok(Vec::new()),
+            _ => analysis::overhangs(self.object(), max_angle)
+                .ok_or(PrimitiveError::Unbounded("overhangs")),
+        }
+    }
+    // Support pillars with radius under the overhangs of self, see analysis::support_pillars.
+    // Empty if there are none. The pillars are not united with self, so they can be exported or
+    // colored on their own.
+    pub fn supports(
+        &self,
+        max_angle: Float,
+        spacing: Float,
+        radius: Float,
+    ) -> Result<LObject, PrimitiveError> {
+        let max_angle = overhang_angle("supports", max_angle)?;
+        let spacing = error::positive("supports", "spacing", spacing)?;
+        let radius = error::positive("supports", "radius", radius)?;
+        let pillars = match *self.node() {
+            Node::Full => Vec::new(),
+            _ => analysis::support_pillars(self.object(), max_angle, spacing)
+                .ok_or(PrimitiveError::Unbounded("supports"))?,
+        };
+        let mut union: Option<LObjectVector> = None;
+        for pillar in pillars {
+            let center = na::center(&pillar.bottom, &pillar.top);
+            let pillar = LObject::cylinder(pillar.top.z - pillar.bottom.z, radius, radius, 0.)?
+                .translate(center.x, center.y, center.z);
+            match union {
+                Some(ref mut union) => union.push(&pillar),
+                None => union = Some(LObjectVector::new(&pillar)),
+            }
+        }
+        match union {
+            Some(union) => union.union(0.),
+            None => Ok(LObject::empty()),
+        }
+    }
+    // The connected solid parts of self, largest first, see analysis::components. A part whose
+    // bounding box does not reach into the others is self cropped to that box, the others are
+    // masked by their cells (see component::Component), which the jit can not compile. Full is
+    // a single part, Empty has none.
+    pub fn components(&self) -> Result<Vec<LObject>, PrimitiveError> {
+        if *self.node() == Node::Full {
+            return Ok(vec![self.clone()]);
+        }
+        let components =
+            analysis::components(self.object()).ok_or(PrimitiveError::Unbounded("components"))?;
+        if components.parts.len() <= 1 {
+            return Ok(components.parts.iter().map(|_| self.clone()).collect());
+        }
+        let components = Arc::new(components);
+        Ok((0..components.parts.len())
+            .map(|part| {
+                if components.overlaps(part) {
+                    LObject::new(
+                        Component::new(self.as_object(), components.clone(), part),
+                        Node::Component {
+                            child: Box::new(self.as_node()),
+                            components: components.clone(),
+                            part,
+                        },
+                    )
+                } else {
+                    let (o, node) = bounded::crop(
+                        self.as_object(),
+                        Some(self.as_node()),
+                        &components.bbox(part),
+                    );
+                    LObject::new(o, node.expect("no cropped tree"))
+                }
+            })
+            .collect())
+    }
+    // self filled with walls of wall thickness around cells of the pattern called name (hex,
+    // grid or gyroid) of the cell size, see infill::Lattice. The surface of self is not kept,
+    // united with a shell it lightens parts.
+    pub fn infill(&self, name: &str, cell: Float, wall: Float) -> Result<LObject, PrimitiveError> {
+        let pattern = Pattern::parse(name)?;
+        let lattice = LObject::new(
+            Lattice::new(pattern, cell, wall)?,
+            Node::Lattice {
+                pattern,
+                cell,
+                wall,
+            },
+        );
+        let mut v = LObjectVector::new(self);
+        v.push(&lattice);
+        v.intersection(0.)
+    }
+    // self hollowed out, e.g. for resin printing: walls of wall thickness around a cavity, which
+    // is self shrunk by wall (see morphology::Offset) and filled with infill (pattern name, cell
+    // and wall, see infill) if it is given. Each of holes, {x, y, z, r} for a point on the surface
+    // and a radius, drills a drain hole along the normal there, from wall outside the surface to
+    // twice wall inside it, which reaches into the cavity.
+    pub fn hollow(
+        &self,
+        wall: Float,
+        holes: &[[Float; 4]],
+        infill: Option<(String, Float, Float)>,
+    ) -> Result<LObject, PrimitiveError> {
+        let wall = error::positive("hollow", "wall", wall)?;
+        if self.node().is_constant() {
+            return Ok(self.clone());
+        }
+        let cavity = LObject::new(
+            Offset::new(self.as_object(), -wall),
+            Node::Offset {
+                child: Box::new(self.as_node()),
+                distance: -wall,
+            },
+        );
+        let mut shell = LObjectVector::new(self);
+        shell.push(&cavity);
+        let mut hollowed = shell.difference(0.)?;
+        if let Some((pattern, cell, lattice_wall)) = infill {
+            let mut filled = LObjectVector::new(&hollowed);
+            filled.push(&cavity.infill(&pattern, cell, lattice_wall)?);
+            hollowed = filled.union(0.)?;
+        }
+        if holes.is_empty() {
+            return Ok(hollowed);
+        }
+        let mut drilled = LObjectVector::new(&hollowed);
+        for hole in holes {
+            let at = na::Point3::new(hole[0], hole[1], hole[2]);
+            let radius = error::positive("hollow", "r", hole[3])?;
+            let normal = self.object().normal(&at);
+            let center = at - normal * (wall / 2.);
+            drilled.push(&rod(&center, &normal, wall * 3., radius)?);
+        }
+        drilled.difference(0.)
+    }
+    // self cut by plane (a plane object, e.g. PlaneZ(0)) into the part behind it and the part in
+    // front of it, e.g. to print objects larger than the build plate. With a pin diameter, pins
+    // (see analysis::pin_positions) stand out of the cut face of the part behind into holes in the
+    // other, which are larger by PIN_CLEARANCE of the diameter, so the parts line up when glued.
+    pub fn split(
+        &self,
+        plane: &LObject,
+        pin_diameter: Option<Float>,
+    ) -> Result<Vec<LObject>, PrimitiveError> {
+        let (normal, d) = match *plane.node() {
+            Node::Plane { normal, p } => (normal.normalize(), p),
+            _ => {
+                return Err(PrimitiveError::InvalidArgument {
+                    function: "split",
+                    message: "the plane must be a plane, e.g. PlaneZ(0)",
+                })
+            }
+        };
+        let mut behind = LObjectVector::new(self);
+        behind.push(plane);
+        let mut in_front = LObjectVector::new(self);
+        in_front.push(plane);
+        let diameter = match pin_diameter {
+            Some(diameter) => error::positive("split", "pin_diameter", diameter)?,
+            None => return Ok(vec![behind.intersection(0.)?, in_front.difference(0.)?]),
+        };
+        let (radius, depth) = (diameter / 2., diameter * PIN_DEPTH);
+        let positions = analysis::pin_positions(self.object(), &normal, d, radius, depth, PINS)
+            .ok_or(PrimitiveError::Unbounded("split"))?;
+        if positions.is_empty() {
+            return Err(PrimitiveError::InvalidParameter {
+                function: "split",
+                parameter: "pin_diameter",
+                value: diameter,
+                requirement: "small enough for pins to fit into the cut face",
+            });
+        }
+        let mut pins = LObjectVector::new(&behind.intersection(0.)?);
+        in_front = LObjectVector::new(&in_front.difference(0.)?);
+        let clearance = diameter * PIN_CLEARANCE;
+        for p in positions {
+            // Both reach a radius behind the plane, so they do not end on the cut face.
+            let (pin_end, hole_end) = (depth, depth + clearance);
+            let pin_center = p + normal * ((pin_end - radius) / 2.);
+            let hole_center = p + normal * ((hole_end - radius) / 2.);
+            pins.push(&rod(&pin_center, &normal, pin_end + radius, radius)?);
+            in_front.push(&rod(
+                &hole_center,
+                &normal,
+                hole_end + radius,
+                radius + clearance,
+            )?);
+        }
+        Ok(vec![pins.union(0.)?, in_front.difference(0.)?])
+    }
+    // self with convex (Opening) or concave (Closing) edges rounded by radius, by eroding and
+    // dilating it (or the other way around). Unlike smooth booleans, this also rounds meshes.
+    pub fn smooth(&self, op: Operation, radius: Float) -> Result<LObject, PrimitiveError> {
+        if self.node().is_constant() {
+            return Ok(self.clone());
+        }
+        Ok(LObject::new(
+            Morphology::new(self.as_object(), op, radius)?,
+            Node::Morphology {
+                child: Box::new(self.as_node()),
+                op,
+                radius,
+            },
+        ))
+    }
+    // Cylinder{l, r1, r2, s}: along z, centered at the origin, with radius1 at the bottom and
+    // radius2 at the top (a cone if they differ).
+    pub fn cylinder(
+        length: Float,
+        radius1: Float,
+        radius2: Float,
+        smooth: Float,
+    ) -> Result<LObject, PrimitiveError> {
+        let mut conie;
+        let conie_node;
+        if (radius1 - radius2).abs() < EPSILON {
+            conie = Box::new(Cylinder::new(radius1)) as Box<dyn Object<Float>>;
+            conie_node = Node::Cylinder { radius: radius1 };
+        } else {
+            let length = error::positive("Cylinder", "l", length)?;
+            let slope = (radius2 - radius1).abs() / length;
+            let offset = if radius1 < radius2 {
+                -radius1 / slope - length * 0.5
+            } else {
+                radius2 / slope + length * 0.5
+            };
+            conie = Box::new(Cone::new(slope, offset));
+            conie_node = Node::Cone { slope, offset };
+            let rmax = radius1.max(radius2);
+            let conie_box = BoundingBox::new(
+                &na::Point3::new(-rmax, -rmax, Float::NEG_INFINITY),
+                &na::Point3::new(rmax, rmax, Float::INFINITY),
+            );
+            conie.set_bbox(&conie_box);
+        }
+        Ok(LObject::new(
+            Intersection::from_vec(
+                vec![
+                    conie,
+                    Box::new(PlaneZ::new(length / 2.0)),
+                    Box::new(PlaneNegZ::new(length / 2.0)),
+                ],
+                smooth,
+            )
+            .ok_or(PrimitiveError::NoObjects)?,
+            Node::Intersection {
+                children: vec![
+                    conie_node,
+                    Node::Plane {
+                        normal: na::Vector3::new(0., 0., 1.),
+                        p: length / 2.0,
+                    },
+                    Node::Plane {
+                        normal: na::Vector3::new(0., 0., -1.),
+                        p: length / 2.0,
+                    },
+                ],
+                smooth,
+            },
+        ))
     }
     fn add_aliases(lua: &mut hlua::Lua, env_name: &str) {
         lua.execute::<()>(&format!(
@@ -116,120 +845,193 @@ impl LObject {
         ))
         .unwrap();
     }
-    pub fn export_factories(lua: &mut hlua::Lua, env_name: &str, console: mpsc::Sender<String>) {
+    pub fn export_factories<'lua>(
+        lua: &mut hlua::Lua<'lua>,
+        env_name: &str,
+        console: Console,
+        includes: &'lua Includes,
+    ) {
         {
             let mut env = lua.get::<hlua::LuaTable<_>, _>(env_name).unwrap();
 
             macro_rules! one_param_object {
-                ( $x:ident ) => {
+                ( $x:ident, $nx:expr, $ny:expr, $nz:expr ) => {
                     env.set(
                         stringify!($x),
                         hlua::function1(move |d_lua: hlua::AnyLuaValue| {
                             let mut d = 0.;
                             if let hlua::AnyLuaValue::LuaNumber(v) = d_lua {
-                                d = v;
-                            }
-                            LObject {
-                                o: Some(Box::new($x::new(d))),
+                                d = v as Float;
                             }
+                            LObject::new(
+                                Box::new($x::new(d)),
+                                Node::Plane {
+                                    normal: na::Vector3::new($nx, $ny, $nz),
+                                    p: d,
+                                },
+                            )
                         }),
                     );
                 };
             }
 
-            one_param_object!(PlaneX);
-            one_param_object!(PlaneY);
-            one_param_object!(PlaneZ);
-            one_param_object!(PlaneNegX);
-            one_param_object!(PlaneNegY);
-            one_param_object!(PlaneNegZ);
+            one_param_object!(PlaneX, 1., 0., 0.);
+            one_param_object!(PlaneY, 0., 1., 0.);
+            one_param_object!(PlaneZ, 0., 0., 1.);
+            one_param_object!(PlaneNegX, -1., 0., 0.);
+            one_param_object!(PlaneNegY, 0., -1., 0.);
+            one_param_object!(PlaneNegZ, 0., 0., -1.);
             env.set(
                 "Sphere",
-                hlua::function1(|radius: Float| LObject {
-                    o: Some(Box::new(Sphere::new(radius))),
+                hlua::function1(|radius: Float| {
+                    LObject::new(Box::new(Sphere::new(radius)), Node::Sphere { radius })
                 }),
             );
             env.set(
                 "iCylinder",
-                hlua::function1(|radius: Float| LObject {
-                    o: Some(Box::new(Cylinder::new(radius))),
+                hlua::function1(|radius: Float| {
+                    LObject::new(Box::new(Cylinder::new(radius)), Node::Cylinder { radius })
                 }),
             );
             env.set(
                 "iCone",
-                hlua::function1(|slope: Float| LObject {
-                    o: Some(Box::new(Cone::new(slope, 0.))),
+                hlua::function1(|slope: Float| {
+                    LObject::new(
+                        Box::new(Cone::new(slope, 0.)),
+                        Node::Cone { slope, offset: 0. },
+                    )
                 }),
             );
             env.set(
                 "Bend",
-                hlua::function2(|o: &LObject, width: Float| LObject {
-                    o: if let Some(obj) = o.as_object() {
-                        Some(Box::new(Bender::new(obj, width)))
-                    } else {
-                        None
+                hlua::function2(
+                    |o: &LObject, width: Float| -> Result<LObject, PrimitiveError> {
+                        let width = error::non_zero("Bend", "width", width)?;
+                        if o.node().is_constant() {
+                            return Ok(o.clone());
+                        }
+                        let bent_node = o.node().bend(o.object(), width);
+                        Ok(LObject::new(
+                            Bounded::new(
+                                Box::new(Bender::new(o.as_object(), width)),
+                                &bounded::bent_bbox(o.object().bbox(), width),
+                            ),
+                            bent_node,
+                        ))
                     },
-                }),
+                ),
             );
             env.set(
                 "Twist",
-                hlua::function2(|o: &LObject, height: Float| LObject {
-                    o: if let Some(obj) = o.as_object() {
-                        Some(Box::new(Twister::new(obj, height)))
-                    } else {
-                        None
+                hlua::function2(
+                    |o: &LObject, height: Float| -> Result<LObject, PrimitiveError> {
+                        let height = error::non_zero("Twist", "height", height)?;
+                        if o.node().is_constant() {
+                            return Ok(o.clone());
+                        }
+                        let twisted_node = o.node().twist(o.object(), height);
+                        Ok(LObject::new(
+                            Bounded::new(
+                                Box::new(Twister::new(o.as_object(), height)),
+                                &bounded::twisted_bbox(o.object().bbox(), height),
+                            ),
+                            twisted_node,
+                        ))
                     },
+                ),
+            );
+            env.set(
+                "measure_min_distance",
+                hlua::function2(|a: &LObject, b: &LObject| a.min_distance(b)),
+            );
+            env.set("Empty", hlua::function0(LObject::empty));
+            env.set("Full", hlua::function0(LObject::full));
+            // The cache does not change the shape, so the tree is the one of o.
+            env.set(
+                "Cache",
+                hlua::function2(
+                    |o: &LObject, quantum: Float| -> Result<LObject, PrimitiveError> {
+                        let quantum = error::positive("Cache", "quantum", quantum)?;
+                        Ok(LObject::new(
+                            Cache::new(o.as_object(), quantum),
+                            o.as_node(),
+                        ))
+                    },
+                ),
+            );
+            env.set(
+                "Profile",
+                hlua::function2(|o: &LObject, name: String| {
+                    LObject::new(Profile::new(o.as_object(), &name), o.as_node())
                 }),
             );
             env.set(
                 "Mesh",
-                hlua::function1(move |filename: String| LObject {
-                    o: match Mesh::try_new(&filename) {
+                hlua::function1(move |filename: String| {
+                    let mesh = includes
+                        .path(&filename)
+                        .and_then(|path| convert::mesh_path(&path))
+                        .and_then(|path| read_mesh(&path));
+                    match mesh {
                         Ok(mesh) => {
-                            console
-                                .send(
-                                    "Warning: Mesh support is currently horribly inefficient!"
-                                        .to_string(),
-                                )
-                                .unwrap();
-                            Some(Box::new(mesh))
+                            console.send(
+                                Level::Warning,
+                                "Mesh support is currently horribly inefficient!",
+                            );
+                            LObject::new(mesh, Node::Mesh { filename })
                         }
                         Err(e) => {
-                            console
-                                .send(format!("Could not read mesh: {:}", e))
-                                .unwrap();
-                            None
+                            console.send(Level::Error, format!("could not read mesh: {:}", e));
+                            LObject::empty()
                         }
-                    },
+                    }
                 }),
             );
         }
         lua.set(
             "__Box",
-            hlua::function4(|x: Float, y: Float, z: Float, smooth: Float| LObject {
-                o: Some(
-                    Intersection::from_vec(
-                        vec![
-                            Box::new(PlaneX::new(x / 2.0)),
-                            Box::new(PlaneY::new(y / 2.0)),
-                            Box::new(PlaneZ::new(z / 2.0)),
-                            Box::new(PlaneNegX::new(x / 2.0)),
-                            Box::new(PlaneNegY::new(y / 2.0)),
-                            Box::new(PlaneNegZ::new(z / 2.0)),
-                        ],
-                        smooth,
-                    )
-                    .unwrap(),
-                ),
-            }),
+            hlua::function4(
+                |x: Float, y: Float, z: Float, smooth: Float| -> Result<LObject, PrimitiveError> {
+                    let plane = |nx: Float, ny: Float, nz: Float, p: Float| Node::Plane {
+                        normal: na::Vector3::new(nx, ny, nz),
+                        p: p / 2.0,
+                    };
+                    Ok(LObject::new(
+                        Intersection::from_vec(
+                            vec![
+                                Box::new(PlaneX::new(x / 2.0)),
+                                Box::new(PlaneY::new(y / 2.0)),
+                                Box::new(PlaneZ::new(z / 2.0)),
+                                Box::new(PlaneNegX::new(x / 2.0)),
+                                Box::new(PlaneNegY::new(y / 2.0)),
+                                Box::new(PlaneNegZ::new(z / 2.0)),
+                            ],
+                            smooth,
+                        )
+                        .ok_or(PrimitiveError::NoObjects)?,
+                        Node::Intersection {
+                            children: vec![
+                                plane(1., 0., 0., x),
+                                plane(0., 1., 0., y),
+                                plane(0., 0., 1., z),
+                                plane(-1., 0., 0., x),
+                                plane(0., -1., 0., y),
+                                plane(0., 0., -1., z),
+                            ],
+                            smooth,
+                        },
+                    ))
+                },
+            ),
         );
         lua.set(
             "__PlaneHessian",
-            hlua::function4(|nx: Float, ny: Float, nz: Float, p: Float| LObject {
-                o: Some(Box::new(NormalPlane::from_normal_and_p(
-                    na::Vector3::new(nx, ny, nz),
-                    p,
-                ))),
+            hlua::function4(|nx: Float, ny: Float, nz: Float, p: Float| {
+                let normal = na::Vector3::new(nx, ny, nz);
+                LObject::new(
+                    Box::new(NormalPlane::from_normal_and_p(normal, p)),
+                    Node::Plane { normal, p },
+                )
             }),
         );
         lua.set(
@@ -244,81 +1046,487 @@ impl LObject {
                  cx: Float,
                  cy: Float,
                  cz: Float| {
-                    LObject {
-                        o: Some(Box::new(NormalPlane::from_3_points(
-                            &na::Point3::new(ax, ay, az),
-                            &na::Point3::new(bx, by, bz),
-                            &na::Point3::new(cx, cy, cz),
-                        ))),
-                    }
-                },
-            ),
-        );
-        lua.set(
-            "__Cylinder",
-            hlua::function4(
-                |length: Float, radius1: Float, radius2: Float, smooth: Float| {
-                    let mut conie;
-                    if (radius1 - radius2).abs() < EPSILON {
-                        conie = Box::new(Cylinder::new(radius1)) as Box<dyn Object<Float>>;
-                    } else {
-                        let slope = (radius2 - radius1).abs() / length;
-                        let offset = if radius1 < radius2 {
-                            -radius1 / slope - length * 0.5
-                        } else {
-                            radius2 / slope + length * 0.5
-                        };
-                        conie = Box::new(Cone::new(slope, offset));
-                        let rmax = radius1.max(radius2);
-                        let conie_box = BoundingBox::new(
-                            &na::Point3::new(-rmax, -rmax, NEG_INFINITY),
-                            &na::Point3::new(rmax, rmax, INFINITY),
-                        );
-                        conie.set_bbox(&conie_box);
-                    }
-                    LObject {
-                        o: Some(
-                            Intersection::from_vec(
-                                vec![
-                                    conie,
-                                    Box::new(PlaneZ::new(length / 2.0)),
-                                    Box::new(PlaneNegZ::new(length / 2.0)),
-                                ],
-                                smooth,
-                            )
-                            .unwrap(),
-                        ),
-                    }
+                    let a = na::Point3::new(ax, ay, az);
+                    let b = na::Point3::new(bx, by, bz);
+                    let c = na::Point3::new(cx, cy, cz);
+                    // Same as NormalPlane::from_3_points.
+                    let normal = (a - c).cross(&(b - c)).normalize();
+                    LObject::new(
+                        Box::new(NormalPlane::from_3_points(&a, &b, &c)),
+                        Node::Plane {
+                            normal,
+                            p: normal.dot(&a.coords),
+                        },
+                    )
                 },
             ),
         );
+        lua.set("__Cylinder", hlua::function4(LObject::cylinder));
         LObject::add_aliases(lua, env_name);
     }
-    fn translate(&mut self, x: Float, y: Float, z: Float) -> LObject {
-        LObject {
-            o: if let Some(ref obj) = self.o {
-                Some(obj.clone().translate(&na::Vector3::new(x, y, z)))
-            } else {
-                None
-            },
+    // A copy of self with color r, g, b, clamped to 0 to 1.
+    fn colored(&self, r: Float, g: Float, b: Float) -> LObject {
+        let mut colored = self.clone();
+        let clamp = |c: Float| if c.is_nan() { 0. } else { c.clamp(0., 1.) };
+        colored.color = Some(Box::new(na::Vector3::new(clamp(r), clamp(g), clamp(b))));
+        colored
+    }
+    // The objects covering the surface of self with their colors, None for the default color:
+    // self if it has a color, else the parts of unions. Empty if nothing has a color.
+    pub fn colored_parts(&self) -> Vec<ColoredPart> {
+        let mut colored = Vec::new();
+        self.collect_colors(&mut colored);
+        if colored.iter().all(|&(_, color)| color.is_none()) {
+            colored.clear();
         }
+        colored
     }
-    fn rotate(&mut self, x: Float, y: Float, z: Float) -> LObject {
-        LObject {
-            o: if let Some(ref obj) = self.o {
-                Some(obj.clone().rotate(&na::Vector3::new(x, y, z)))
-            } else {
-                None
-            },
+    fn collect_colors(&self, colored: &mut Vec<ColoredPart>) {
+        match (self.color.as_ref(), self.parts.as_ref()) {
+            (None, Some(parts)) => {
+                for part in parts {
+                    part.collect_colors(colored);
+                }
+            }
+            (color, _) => colored.push((self.as_object(), color.map(|c| **c))),
         }
     }
-    fn scale(&mut self, x: Float, y: Float, z: Float) -> LObject {
-        LObject {
-            o: if let Some(ref obj) = self.o {
-                Some(obj.clone().scale(&na::Vector3::new(x, y, z)))
-            } else {
-                None
-            },
+    // Empty and Full stay the same when transformed. Transforming their infinite bounding boxes
+    // would give NaNs.
+    // The parts of unions are transformed along, see rotate. See transformer for infinite objects.
+    pub fn translate(&self, x: Float, y: Float, z: Float) -> LObject {
+        if self.node().is_constant() {
+            return self.clone();
+        }
+        let v = na::Vector3::new(x, y, z);
+        let mut translated = LObject::new(
+            transformer::translate(self.object(), &v),
+            self.node().translate(&v),
+        );
+        translated.parts = self
+            .parts
+            .as_ref()
+            .map(|parts| parts.iter().map(|p| p.translate(x, y, z)).collect());
+        translated.color = self.color.clone();
+        translated
+    }
+    // The bounding box of a rotated object is the box around its rotated box. For unions, the box
+    // around their rotated parts is much tighter, and does not grow with further rotations.
+    pub fn rotate(&self, x: Float, y: Float, z: Float) -> LObject {
+        if self.node().is_constant() {
+            return self.clone();
+        }
+        let v = na::Vector3::new(x, y, z);
+        let mut rotated = LObject::new(
+            transformer::rotate(self.object(), &v),
+            self.node().rotate(&v),
+        );
+        if let Some(ref parts) = self.parts {
+            let parts: Vec<LObject> = parts.iter().map(|p| p.rotate(x, y, z)).collect();
+            let mut bbox = BoundingBox::neg_infinity();
+            for part in &parts {
+                bbox = bbox.union(part.object().bbox());
+            }
+            rotated.o = Some(Bounded::new(rotated.as_object(), &bbox));
+            rotated.parts = Some(parts);
+        }
+        rotated.color = self.color.clone();
+        rotated
+    }
+    // implicit3d panics for scales which can not be inverted.
+    fn scale(&self, x: Float, y: Float, z: Float) -> Result<LObject, PrimitiveError> {
+        if [x, y, z].iter().any(|s| *s == 0. || !s.is_finite()) {
+            return Err(PrimitiveError::Singular { x, y, z });
+        }
+        if self.node().is_constant() {
+            return Ok(self.clone());
+        }
+        let v = na::Vector3::new(x, y, z);
+        let mut scaled = LObject::new(transformer::scale(self.object(), &v), self.node().scale(&v));
+        if let Some(ref parts) = self.parts {
+            scaled.parts = Some(
+                parts
+                    .iter()
+                    .map(|p| p.scale(x, y, z))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        scaled.color = self.color.clone();
+        Ok(scaled)
+    }
+}
+
+// m as the Lua table mass_properties returns: volume, mass, centroid = {x, y, z} and
+// inertia = {{ixx, ixy, ixz}, {ixy, iyy, iyz}, {ixz, iyz, izz}}.
+fn mass_table(m: &MassProperties) -> hlua::AnyLuaValue {
+    let rows = (0..3)
+        .map(|i| lua_vector(&[m.inertia[(i, 0)], m.inertia[(i, 1)], m.inertia[(i, 2)]]))
+        .collect();
+    lua_table(vec![
+        ("volume", lua_number(m.volume)),
+        ("mass", lua_number(m.mass)),
+        ("centroid", lua_point(&m.centroid)),
+        ("inertia", lua_array(rows)),
+    ])
+}
+
+// r as a Lua table: thickness, at = {x, y, z} and the corners of its box, min and max.
+fn thin_region_table(r: &ThinRegion) -> hlua::AnyLuaValue {
+    lua_table(vec![
+        ("thickness", lua_number(r.thickness)),
+        ("at", lua_point(&r.at)),
+        ("min", lua_point(&r.bbox.min)),
+        ("max", lua_point(&r.bbox.max)),
+    ])
+}
+
+// r as a Lua table: the corners of its box, min and max, and its surface points.
+fn overhang_region_table(r: &OverhangRegion) -> hlua::AnyLuaValue {
+    lua_table(vec![
+        ("min", lua_point(&r.bbox.min)),
+        ("max", lua_point(&r.bbox.max)),
+        (
+            "points",
+            lua_array(r.points.iter().map(lua_point).collect()),
+        ),
+    ])
+}
+
+// The overhang angle in degrees as radians for analysis::overhangs, if it is from 0 to 90.
+fn overhang_angle(function: &'static str, degrees: Float) -> Result<Float, PrimitiveError> {
+    if (0. ..=90.).contains(&degrees) {
+        Ok(degrees.to_radians())
+    } else {
+        Err(PrimitiveError::InvalidParameter {
+            function,
+            parameter: "max_angle",
+            value: degrees,
+            requirement: "from 0 to 90 degrees",
+        })
+    }
+}
+
+// A cylinder of length and radius around center, along direction, which has unit length.
+fn rod(
+    center: &na::Point3<Float>,
+    direction: &na::Vector3<Float>,
+    length: Float,
+    radius: Float,
+) -> Result<LObject, PrimitiveError> {
+    // Cylinders are along z. rotate evaluates the object at the rotated point, which turns it by
+    // the inverse rotation, so it is given the angles of the rotation from direction to z. A rod
+    // along -z needs none, since the cylinder is symmetric.
+    let (x, y, z) = na::Rotation3::rotation_between(direction, &na::Vector3::z())
+        .unwrap_or_else(na::Rotation3::identity)
+        .euler_angles();
+    Ok(LObject::cylinder(length, radius, radius, 0.)?
+        .rotate(x, y, z)
+        .translate(center.x, center.y, center.z))
+}
+
+// The drain holes and the infill of the options table of hollow: drain_holes = {{x, y, z, r},
+// ...} and infill = {pattern, cell, wall}, both optional. They are read through LuaTable in the
+// callback, since AnyLuaValue reads tables as LuaOther; the error is kept until hollow runs.
+pub struct HollowOptions(Result<(Vec<[Float; 4]>, Option<Infill>), PrimitiveError>);
+
+// The pattern name, cell and wall of infill.
+type Infill = (String, Float, Float);
+
+impl<'lua, L> hlua::LuaRead<L> for HollowOptions
+where
+    L: hlua::AsMutLua<'lua>,
+{
+    fn lua_read_at_position(lua: L, index: i32) -> Result<HollowOptions, L> {
+        if let Ok(hlua::AnyLuaValue::LuaNil) = hlua::LuaRead::lua_read_at_position(&lua, index) {
+            return Ok(HollowOptions(Ok((Vec::new(), None))));
+        }
+        let options = match hlua::LuaRead::lua_read_at_position(lua, index) {
+            Ok(mut table) => hollow_options(&mut table),
+            Err(_) => Err("the options must be a table"),
+        };
+        Ok(HollowOptions(options.map_err(|message| {
+            PrimitiveError::InvalidArgument {
+                function: "hollow",
+                message,
+            }
+        })))
+    }
+}
+
+fn hollow_options<'lua, L>(
+    options: &mut hlua::LuaTable<L>,
+) -> Result<(Vec<[Float; 4]>, Option<Infill>), &'static str>
+where
+    L: hlua::AsMutLua<'lua>,
+{
+    let mut holes = Vec::new();
+    if !lua_is_nil(options, "drain_holes") {
+        let mut table: hlua::LuaTable<_> = options
+            .get("drain_holes")
+            .ok_or("drain_holes must be a table")?;
+        let mut i = 1;
+        while !lua_is_nil(&mut table, i) {
+            let mut hole: hlua::LuaTable<_> = table
+                .get(i)
+                .ok_or("each of drain_holes must be {x, y, z, r}")?;
+            match lua_numbers(&mut hole).as_deref() {
+                Some(&[x, y, z, r]) => holes.push([x, y, z, r]),
+                _ => return Err("each of drain_holes must be {x, y, z, r}"),
+            }
+            i += 1;
+        }
+    }
+    let infill = if lua_is_nil(options, "infill") {
+        None
+    } else {
+        let invalid = "infill must be {pattern, cell, wall}";
+        let mut table: hlua::LuaTable<_> = options.get("infill").ok_or(invalid)?;
+        let pattern: String = table.get(1).ok_or(invalid)?;
+        let cell: Float = table.get(2).ok_or(invalid)?;
+        let wall: Float = table.get(3).ok_or(invalid)?;
+        if !lua_is_nil(&mut table, 4) {
+            return Err(invalid);
+        }
+        Some((pattern, cell, wall))
+    };
+    Ok((holes, infill))
+}
+
+// Whether the field key of table is nil (or missing).
+fn lua_is_nil<'lua, L, K>(table: &mut hlua::LuaTable<L>, key: K) -> bool
+where
+    L: hlua::AsMutLua<'lua>,
+    K: for<'a, 'b> hlua::PushOne<&'b mut &'a mut hlua::LuaTable<L>, Err = hlua::Void>,
+{
+    matches!(table.get(key), Some(hlua::AnyLuaValue::LuaNil) | None)
+}
+
+// The numbers of a Lua array in order, None if it is not an array of numbers only.
+fn lua_numbers<'lua, L>(array: &mut hlua::LuaTable<L>) -> Option<Vec<Float>>
+where
+    L: hlua::AsMutLua<'lua>,
+{
+    let mut numbers = Vec::new();
+    while !lua_is_nil(array, numbers.len() as u32 + 1) {
+        numbers.push(array.get(numbers.len() as u32 + 1)?);
+    }
+    Some(numbers)
+}
+
+fn read_mesh(path: &Path) -> Result<Box<dyn Object<Float>>, String> {
+    let mesh = Mesh::try_new(&path.to_string_lossy()).map_err(|e| e.to_string())?;
+    #[cfg(feature = "f32")]
+    let mesh = SinglePrecisionMesh::new(mesh);
+    Ok(Box::new(mesh))
+}
+
+// The meshes of implicit3d only come in f64, so with the f32 feature they are evaluated in f64
+// and their values converted.
+#[cfg(feature = "f32")]
+#[derive(Clone, Debug)]
+struct SinglePrecisionMesh {
+    mesh: Mesh<f64>,
+    bbox: BoundingBox<Float>,
+}
+
+#[cfg(feature = "f32")]
+impl SinglePrecisionMesh {
+    fn new(mesh: Mesh<f64>) -> SinglePrecisionMesh {
+        let point = |p: &na::Point3<f64>| na::Point3::from(p.coords.map(|x| x as Float));
+        SinglePrecisionMesh {
+            bbox: BoundingBox::new(&point(&mesh.bbox().min), &point(&mesh.bbox().max)),
+            mesh,
+        }
+    }
+}
+
+#[cfg(feature = "f32")]
+impl Object<Float> for SinglePrecisionMesh {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        self.mesh.approx_value(&na::convert(*p), f64::from(slack)) as Float
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.mesh.normal(&na::convert(*p)).map(|x| x as Float)
+    }
+}
+
+fn lua_table(fields: Vec<(&str, hlua::AnyLuaValue)>) -> hlua::AnyLuaValue {
+    hlua::AnyLuaValue::LuaArray(
+        fields
+            .into_iter()
+            .map(|(k, v)| (hlua::AnyLuaValue::LuaString(k.to_string()), v))
+            .collect(),
+    )
+}
+
+fn lua_array(values: Vec<hlua::AnyLuaValue>) -> hlua::AnyLuaValue {
+    hlua::AnyLuaValue::LuaArray(
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (hlua::AnyLuaValue::LuaNumber((i + 1) as f64), v))
+            .collect(),
+    )
+}
+
+// x as a Lua number, which is an f64 whatever Float is, so the conversion is only useless
+// without the f32 feature.
+#[allow(clippy::useless_conversion)]
+pub fn lua_number(x: Float) -> hlua::AnyLuaValue {
+    hlua::AnyLuaValue::LuaNumber(x.into())
+}
+
+fn lua_vector(v: &[Float]) -> hlua::AnyLuaValue {
+    lua_array(v.iter().map(|&x| lua_number(x)).collect())
+}
+
+fn lua_point(p: &na::Point3<Float>) -> hlua::AnyLuaValue {
+    lua_vector(&[p.x, p.y, p.z])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use luascad::ScriptError;
+
+    fn object(script: &str) -> Box<dyn Object<Float>> {
+        ::luascad::eval(script).unwrap().1.unwrap()
+    }
+
+    // The error of script, which must fail.
+    fn error(script: &str) -> String {
+        match ::luascad::eval(script) {
+            Ok(_) => panic!("{} did not fail", script),
+            Err(e) => ScriptError::from(&e).message,
+        }
+    }
+
+    fn value(o: &dyn Object<Float>, x: Float, y: Float, z: Float) -> Float {
+        o.approx_value(&na::Point3::new(x, y, z), 0.)
+    }
+
+    // Meshes are read in either precision, see SinglePrecisionMesh.
+    #[test]
+    fn meshes_are_read() {
+        let path = ::std::env::temp_dir().join(format!("truescad-{}.stl", ::std::process::id()));
+        let corners = [[0., 0., 0.], [10., 0., 0.], [0., 10., 0.], [0., 0., 10.]];
+        let mut stl = "solid tetrahedron\n".to_string();
+        for face in &[[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]] {
+            stl.push_str("facet normal 0 0 0\nouter loop\n");
+            for &i in face {
+                let c: [Float; 3] = corners[i];
+                stl.push_str(&format!("vertex {} {} {}\n", c[0], c[1], c[2]));
+            }
+            stl.push_str("endloop\nendfacet\n");
+        }
+        stl.push_str("endsolid tetrahedron\n");
+        ::std::fs::write(&path, stl).unwrap();
+        // The name is relative to the script, like those of ImportSVG.
+        let script = path.with_extension("lua");
+        let name = path.file_name().unwrap().to_string_lossy();
+        let mesh = ::evaluate_in(
+            &format!("build(Mesh({:?}))", name),
+            0.,
+            &::std::collections::HashMap::new(),
+            &Includes::beside(&script.to_string_lossy()),
+        )
+        .unwrap()
+        .object
+        .unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        let bbox = mesh.bbox();
+        assert_eq!(
+            (bbox.min, bbox.max),
+            (na::Point3::origin(), na::Point3::new(10., 10., 10.))
+        );
+        assert!(value(&*mesh, 1., 1., 1.) < 0.);
+        assert!((value(&*mesh, 1., 1., -2.) - 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn hollow_drills_drain_holes() {
+        let shell = object("build(Sphere(10):hollow(1))");
+        assert!(value(&*shell, 0., 0., 9.5) < 0.);
+        let drilled = object("build(Sphere(10):hollow(1, {drain_holes = {{0, 0, 10, 2}}}))");
+        assert!(value(&*drilled, 0., 0., 9.5) > 0.);
+        assert!(value(&*drilled, 0., 9.5, 0.) < 0.);
+    }
+
+    #[test]
+    fn hollow_fills_the_cavity() {
+        let inside = |o: &dyn Object<Float>| {
+            (-4..5)
+                .filter(|&x| value(o, x as Float, 0., 0.) < 0.)
+                .count()
+        };
+        assert_eq!(inside(&*object("build(Sphere(10):hollow(1))")), 0);
+        let filled = object("build(Sphere(10):hollow(1, {infill = {\"grid\", 4, 1}}))");
+        assert!(inside(&*filled) > 0);
+    }
+
+    #[test]
+    fn hollow_rejects_bad_options() {
+        assert_eq!(
+            error("build(Sphere(10):hollow(1, {drain_holes = {{0, 0, 10}}}))"),
+            "hollow: each of drain_holes must be {x, y, z, r}"
+        );
+        assert_eq!(
+            error("build(Sphere(10):hollow(1, {drain_holes = 2}))"),
+            "hollow: drain_holes must be a table"
+        );
+        assert_eq!(
+            error("build(Sphere(10):hollow(1, {infill = {\"grid\", 4}}))"),
+            "hollow: infill must be {pattern, cell, wall}"
+        );
+        assert_eq!(
+            error("build(Sphere(10):hollow(1, 2))"),
+            "hollow: the options must be a table"
+        );
+    }
+
+    #[test]
+    fn rods_point_along_their_direction() {
+        let center = na::Point3::new(1., 2., 3.);
+        for axis in 0..3 {
+            let mut direction = na::Vector3::zeros();
+            direction[axis] = 1.;
+            for &sign in &[1., -1.] {
+                let rod = rod(&center, &(direction * sign), 10., 1.).unwrap();
+                let bbox = rod.object().bbox();
+                for i in 0..3 {
+                    let half = if i == axis { 5. } else { 1. };
+                    assert!(
+                        (bbox.min[i] - (center[i] - half)).abs() < 1e-6,
+                        "{:?}",
+                        bbox
+                    );
+                    assert!(
+                        (bbox.max[i] - (center[i] + half)).abs() < 1e-6,
+                        "{:?}",
+                        bbox
+                    );
+                }
+            }
+        }
+        let direction = na::Vector3::new(1., 1., 1.).normalize();
+        let rod = rod(&center, &direction, 10., 1.).unwrap();
+        assert!(rod.object().approx_value(&(center + direction * 4.), 0.) < 0.);
+        let across = na::Vector3::new(1., -1., 0.).normalize();
+        assert!(rod.object().approx_value(&(center + across * 2.), 0.) > 0.);
+    }
+
+    #[test]
+    fn split_pins_stay_inside_the_cut_face() {
+        let behind = object("build(Box(20, 20, 20):split(PlaneY(0), 2)[1])");
+        let bbox = behind.bbox();
+        for &i in &[0, 2] {
+            assert!(bbox.min[i] >= -10. - 1e-6, "{:?}", bbox);
+            assert!(bbox.max[i] <= 10. + 1e-6, "{:?}", bbox);
         }
+        // The pins stand out of the cut face.
+        assert!(bbox.max.y > 0. && bbox.min.y == -10., "{:?}", bbox);
     }
 }
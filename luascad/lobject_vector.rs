@@ -1,13 +1,18 @@
 use super::Float;
+use api::Function;
+use bvh;
+use error::PrimitiveError;
 use hlua;
-use implicit3d::{Intersection, Object, Union};
+use implicit3d::{Intersection, Object};
 use lobject::LObject;
+use tree::{Boolean, Node};
 
 // Struct to be used to construct boolean Objects.
 // The lua helpers below pump LObjects from Lua Arrays into this LObjectVector, which is then used
 // to construct the boolean Objects.
+// objects is always set, it is only an Option since hlua requires all-zero to be a valid value.
 pub struct LObjectVector {
-    pub v: Option<Vec<Box<dyn Object<Float>>>>,
+    objects: Option<Vec<LObject>>,
 }
 
 // this macro implements the required trait so that we can *push* the object to lua
@@ -18,7 +23,7 @@ implement_lua_push!(LObjectVector, |mut metatable| {
     index.set(
         "push",
         ::hlua::function2(|v: &mut LObjectVector, o: &mut LObject| {
-            v.push(o.as_object());
+            v.push(o);
         }),
     );
 });
@@ -26,46 +31,129 @@ implement_lua_push!(LObjectVector, |mut metatable| {
 // this macro implements the require traits so that we can *read* the object back
 implement_lua_read!(LObjectVector);
 
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "Union",
+        args: Some("objects, smooth"),
+        doc: "All of the table of objects, with the seams rounded by smooth (optional). With sharp = true in the table, e.g. Union({a, b, sharp = true}), the objects are tessellated one by one and their meshes combined, for sharp seams where the tessellation would round them.",
+    },
+    Function {
+        name: "Intersection",
+        args: Some("objects, smooth"),
+        doc: "What the objects have in common, with the seams rounded by smooth (optional).",
+    },
+    Function {
+        name: "Difference",
+        args: Some("objects, smooth"),
+        doc: "The first object without the others, with the seams rounded by smooth (optional). Takes sharp = true in the table like Union.",
+    },
+];
+
 impl LObjectVector {
-    pub fn new(o: Option<Box<dyn Object<Float>>>) -> LObjectVector {
-        LObjectVector {
-            v: if let Some(o) = o { Some(vec![o]) } else { None },
+    pub fn new(o: &LObject) -> LObjectVector {
+        let mut v = LObjectVector {
+            objects: Some(Vec::new()),
+        };
+        v.push(o);
+        v
+    }
+    pub fn objects(&self) -> &[LObject] {
+        self.objects.as_ref().unwrap()
+    }
+    // Unions without rounding are associative, so nested ones (like those built in Lua loops)
+    // are merged into one, which bvh::union_from_vec then balances by spatial proximity.
+    pub fn union(&self, smooth: Float) -> Result<LObject, PrimitiveError> {
+        let objects = self.objects();
+        let node = |children, smooth| Node::Union { children, smooth };
+        if smooth > 0. {
+            return build(Boolean::Union, objects, smooth, bvh::union_from_vec, node);
         }
+        let mut parts = Vec::new();
+        for o in objects {
+            // Transformed unions stay one child, which is evaluated in their own coordinates, and
+            // so do colored ones, whose parts would lose the color.
+            match (&o.parts, o.node(), &o.color) {
+                (Some(p), Node::Union { .. }, None) => parts.extend(p.iter().cloned()),
+                _ => parts.push(o.clone()),
+            }
+        }
+        let mut union = build(Boolean::Union, &parts, smooth, bvh::union_from_vec, node)?;
+        if parts.len() > 1 && !union.node().is_constant() {
+            union.parts = Some(parts);
+        }
+        Ok(union)
+    }
+    pub fn intersection(&self, smooth: Float) -> Result<LObject, PrimitiveError> {
+        build(
+            Boolean::Intersection,
+            self.objects(),
+            smooth,
+            Intersection::from_vec,
+            |children, smooth| Node::Intersection { children, smooth },
+        )
+    }
+    // The first object minus the others.
+    pub fn difference(&self, smooth: Float) -> Result<LObject, PrimitiveError> {
+        build(
+            Boolean::Difference,
+            self.objects(),
+            smooth,
+            Intersection::difference_from_vec,
+            |children, smooth| Node::Difference { children, smooth },
+        )
+    }
+    // The union or difference (op) of the objects, whose tessellations are combined, see
+    // Node::Sharp. It can not be smoothed.
+    pub fn sharp(&self, op: Boolean, smooth: Float) -> Result<LObject, PrimitiveError> {
+        let (function, boolean) = match op {
+            Boolean::Union => ("Union", self.union(0.)?),
+            _ => ("Difference", self.difference(0.)?),
+        };
+        if smooth != 0. {
+            return Err(PrimitiveError::InvalidArgument {
+                function,
+                message: "sharp booleans can not be smoothed",
+            });
+        }
+        // Unless it resolved to one of the objects, or to Empty or Full.
+        let children = match *boolean.node() {
+            Node::Union { ref children, .. } | Node::Difference { ref children, .. } => {
+                children.clone()
+            }
+            _ => return Ok(boolean),
+        };
+        Ok(LObject::new(
+            boolean.as_object(),
+            Node::Sharp { children, op },
+        ))
     }
     pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
         lua.set(
             "__new_object_vector",
-            hlua::function1(|o: &LObject| LObjectVector::new(o.as_object())),
+            hlua::function1(|o: &LObject| LObjectVector::new(o)),
         );
         lua.set(
             "__new_union",
-            hlua::function2(|o: &LObjectVector, smooth: Float| LObject {
-                o: if let Some(ref v) = o.v {
-                    Some(Union::from_vec(v.clone(), smooth).unwrap())
-                } else {
-                    None
-                },
+            hlua::function2(|o: &LObjectVector, smooth: Float| o.union(smooth)),
+        );
+        lua.set(
+            "__new_sharp_union",
+            hlua::function2(|o: &LObjectVector, smooth: Float| o.sharp(Boolean::Union, smooth)),
+        );
+        lua.set(
+            "__new_sharp_difference",
+            hlua::function2(|o: &LObjectVector, smooth: Float| {
+                o.sharp(Boolean::Difference, smooth)
             }),
         );
         lua.set(
             "__new_intersection",
-            hlua::function2(|o: &LObjectVector, smooth: Float| LObject {
-                o: if let Some(ref v) = o.v {
-                    Some(Intersection::from_vec(v.clone(), smooth).unwrap())
-                } else {
-                    None
-                },
-            }),
+            hlua::function2(|o: &LObjectVector, smooth: Float| o.intersection(smooth)),
         );
         lua.set(
             "__new_difference",
-            hlua::function2(|o: &LObjectVector, smooth: Float| LObject {
-                o: if let Some(ref v) = o.v {
-                    Some(Intersection::difference_from_vec(v.clone(), smooth).unwrap())
-                } else {
-                    None
-                },
-            }),
+            hlua::function2(|o: &LObjectVector, smooth: Float| o.difference(smooth)),
         );
         lua.execute::<()>(&format!(
             "
@@ -79,6 +167,9 @@ impl LObjectVector {
 
             function Union(lobjects, smooth)
               smooth = smooth or 0
+              if lobjects.sharp then
+                return __new_sharp_union(__array_to_ov(lobjects), smooth)
+              end
               return __new_union(__array_to_ov(lobjects), smooth)
             end
 
@@ -89,6 +180,9 @@ impl LObjectVector {
 
             function Difference(lobjects, smooth)
               smooth = smooth or 0
+              if lobjects.sharp then
+                return __new_sharp_difference(__array_to_ov(lobjects), smooth)
+              end
               return __new_difference(__array_to_ov(lobjects), smooth)
             end
 
@@ -99,13 +193,36 @@ impl LObjectVector {
         ))
         .unwrap();
     }
-    pub fn push(&mut self, o: Option<Box<dyn Object<Float>>>) {
-        if let Some(o) = o {
-            if let Some(ref mut v) = self.v {
-                v.push(o);
-            }
-        } else {
-            self.v = None
-        }
+    pub fn push(&mut self, o: &LObject) {
+        self.objects.as_mut().unwrap().push(o.clone());
     }
 }
+
+// The boolean object of objects. Empty and Full are resolved first, see Node::resolve.
+fn build<F, N>(
+    op: Boolean,
+    objects: &[LObject],
+    smooth: Float,
+    from_vec: F,
+    node: N,
+) -> Result<LObject, PrimitiveError>
+where
+    F: Fn(Vec<Box<dyn Object<Float>>>, Float) -> Option<Box<dyn Object<Float>>>,
+    N: Fn(Vec<Node>, Float) -> Node,
+{
+    let objects = match Node::resolve(op, objects.iter().collect(), |o| o.node()) {
+        Ok(objects) => objects,
+        Err(constant) => return Ok(LObject::constant(&constant)),
+    };
+    let v = objects.iter().map(|o| o.as_object()).collect();
+    let mut nodes: Vec<Node> = objects.iter().map(|o| o.as_node()).collect();
+    Ok(LObject::new(
+        from_vec(v, smooth).ok_or(PrimitiveError::NoObjects)?,
+        // Like from_vec, a single object is used as is.
+        if nodes.len() == 1 {
+            nodes.pop().unwrap()
+        } else {
+            node(nodes, smooth)
+        },
+    ))
+}
@@ -1,44 +1,380 @@
 use super::Float;
+use api::Function;
+use arrange;
+use blend;
+use constraints::ConstraintSystem;
+use dxf;
+use edge;
+use editable::{self, Editable};
+use error::PrimitiveError;
 use hlua;
-use hlua::{Lua, LuaError};
-use lobject::LObject;
+use hlua::{AnyLuaValue, Lua, LuaError};
+use include::{self, Includes};
+use lobject::{ColoredPart, LObject};
 use lobject_vector::LObjectVector;
-use printbuffer;
+use nalgebra as na;
+use params::{self, Parameter, Value};
+use parts;
+use plugin;
+use pointcloud;
+use printbuffer::{self, Level, Message};
 use sandbox;
+use std::collections::HashMap;
+use std::fmt;
+use svg;
+use threads;
+use tree::Node;
+use units::Units;
 
 pub const USER_FUNCTION_NAME: &str = "__luscad_user_function__";
 pub const SANDBOX_ENV_NAME: &str = "__luascad_sandbox_env__";
+// Name of the script in Lua error messages, which start with "script:<line>:".
+pub const SCRIPT_NAME: &str = "script";
+
+// The functions evaluate_in defines in the env, see api.
+pub const API: &[Function] = &[
+    Function {
+        name: "build",
+        args: Some("o"),
+        doc: "Show o as the result of the script. The last object built wins.",
+    },
+    Function {
+        name: "set_units",
+        args: Some("name"),
+        doc: "Set the units of the coordinates to mm (the default), cm or in.",
+    },
+    Function {
+        name: "set_tessellation_region",
+        args: Some("min_x, min_y, min_z, max_x, max_y, max_z"),
+        doc: "Tessellate and export only the part of the object in this box, closed where it is \
+              cut, e.g. to inspect a detail at a high resolution.",
+    },
+    Function {
+        name: "drawing_anchor",
+        args: Some("name, x, y, z"),
+        doc: "Mark a point for technical drawings (truescad --drawing), which dimension it \
+              from the lower left corner of the object in each view.",
+    },
+    Function {
+        name: "time",
+        args: None,
+        doc: "The time of the animation, from 0 to 1. 0 unless the script is animated.",
+    },
+];
 
 pub type EvalResult = Result<(String, Option<Box<dyn implicit3d::Object<Float>>>), LuaError>;
 
+// Everything produced by running a script.
+#[derive(Clone)]
+pub struct Evaluation {
+    // The text of messages.
+    pub output: String,
+    // What the script printed and warnings about it.
+    pub messages: Vec<Message>,
+    pub object: Option<Box<dyn implicit3d::Object<Float>>>,
+    // How object was built, see tree::Node.
+    pub tree: Option<Node>,
+    // The parts of object with the colors set with o:color, see LObject::colored_parts.
+    pub colored_parts: Vec<ColoredPart>,
+    // The units of the coordinates, millimeters unless the script set them.
+    pub units: Units,
+    // The box set with set_tessellation_region, see bounded::region_of_interest.
+    pub tessellation_region: Option<implicit3d::BoundingBox<Float>>,
+    // The points marked with drawing_anchor, in the order they were first marked.
+    pub drawing_anchors: Vec<(String, na::Point3<Float>)>,
+    // The parameters declared with param{...}, in the order of the declarations.
+    pub parameters: Vec<Parameter>,
+    // The parts declared with editable(o, "name"), in the order of the declarations.
+    pub editables: Vec<Editable>,
+    // The names of the scripts run with include, e.g. to bundle them into a project.
+    pub included: Vec<String>,
+}
+
+// An error of a script, with the line (starting at 1) where it occurred if it is known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl<'a> From<&'a LuaError> for ScriptError {
+    fn from(error: &LuaError) -> ScriptError {
+        let text = match *error {
+            LuaError::SyntaxError(ref text) | LuaError::ExecutionError(ref text) => text.clone(),
+            ref other => {
+                return ScriptError {
+                    line: None,
+                    message: format!("{:?}", other),
+                }
+            }
+        };
+        let prefix = format!("{}:", SCRIPT_NAME);
+        if text.starts_with(&prefix) {
+            let mut rest = text[prefix.len()..].splitn(2, ':');
+            if let (Some(Ok(line)), Some(message)) = (rest.next().map(str::parse), rest.next()) {
+                return ScriptError {
+                    line: Some(line),
+                    message: message.trim().to_string(),
+                };
+            }
+        }
+        ScriptError {
+            line: None,
+            message: text,
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 pub fn eval(script: &str) -> EvalResult {
+    eval_at(script, 0.)
+}
+
+// Evaluate script with the Lua variable time set to time (0..1), for animations.
+pub fn eval_at(script: &str, time: Float) -> EvalResult {
+    let evaluation = evaluate(script, time)?;
+    Ok((evaluation.output, evaluation.object))
+}
+
+pub fn evaluate(script: &str, time: Float) -> Result<Evaluation, LuaError> {
+    evaluate_with(script, time, &HashMap::new())
+}
+
+// Evaluate script with the parameters it declares set to values, where they are valid for them
+// (see params::Parameter::value). The others get their defaults.
+pub fn evaluate_with(
+    script: &str,
+    time: Float,
+    values: &HashMap<String, Value>,
+) -> Result<Evaluation, LuaError> {
+    evaluate_in(script, time, values, &Includes::default())
+}
+
+// Like evaluate_with, with include finding scripts in includes.
+pub fn evaluate_in(
+    script: &str,
+    time: Float,
+    values: &HashMap<String, Value>,
+    includes: &Includes,
+) -> Result<Evaluation, LuaError> {
     let mut result = None;
-    let print_output;
+    let mut units = None;
+    let mut tessellation_region = None;
+    let mut drawing_anchors: Vec<(String, na::Point3<Float>)> = Vec::new();
+    let mut parameters = Vec::new();
+    let mut editables: Vec<Editable> = Vec::new();
+    let mut included: Vec<String> = Vec::new();
+    let mut messages;
+    let console;
     {
         let mut lua = Lua::new();
         lua.openlibs();
         sandbox::set_sandbox_env(&mut lua, SANDBOX_ENV_NAME);
         let printbuffer =
             printbuffer::PrintBuffer::new_and_expose_to_lua(&mut lua, SANDBOX_ENV_NAME);
+        console = printbuffer.get_console();
         {
             let mut sandbox_env = lua.get::<hlua::LuaTable<_>, _>(SANDBOX_ENV_NAME).unwrap();
             sandbox_env.set(
                 "build",
-                hlua::function1(|o: &LObject| result = o.as_object()),
+                hlua::function1(|o: &LObject| result = Some(o.clone())),
             );
+            sandbox_env.set(
+                "set_units",
+                hlua::function1(|name: String| -> Result<(), PrimitiveError> {
+                    let new = Units::parse(&name).ok_or(PrimitiveError::UnknownUnits(name))?;
+                    match units {
+                        Some(previous) if previous != new => {
+                            Err(PrimitiveError::ConflictingUnits {
+                                units: new,
+                                previous,
+                            })
+                        }
+                        _ => {
+                            units = Some(new);
+                            Ok(())
+                        }
+                    }
+                }),
+            );
+            sandbox_env.set(
+                "set_tessellation_region",
+                hlua::function6(
+                    |min_x: Float,
+                     min_y: Float,
+                     min_z: Float,
+                     max_x: Float,
+                     max_y: Float,
+                     max_z: Float|
+                     -> Result<(), PrimitiveError> {
+                        let min = na::Point3::new(min_x, min_y, min_z);
+                        let max = na::Point3::new(max_x, max_y, max_z);
+                        for i in 0..3 {
+                            if max[i] <= min[i] {
+                                return Err(PrimitiveError::InvalidParameter {
+                                    function: "set_tessellation_region",
+                                    parameter: ["max_x", "max_y", "max_z"][i],
+                                    value: max[i],
+                                    requirement: "greater than the minimum",
+                                });
+                            }
+                        }
+                        tessellation_region = Some(implicit3d::BoundingBox::new(&min, &max));
+                        Ok(())
+                    },
+                ),
+            );
+            // Marking a point again under the same name moves it.
+            sandbox_env.set(
+                "drawing_anchor",
+                hlua::function4(|name: String, x: Float, y: Float, z: Float| {
+                    let at = na::Point3::new(x, y, z);
+                    match drawing_anchors.iter_mut().find(|a| a.0 == name) {
+                        Some(anchor) => anchor.1 = at,
+                        None => drawing_anchors.push((name, at)),
+                    }
+                }),
+            );
+            sandbox_env.set("time", time);
         }
-        LObject::export_factories(&mut lua, SANDBOX_ENV_NAME, printbuffer.get_tx());
+        lua.set(
+            "__param",
+            hlua::function6(
+                |name: AnyLuaValue,
+                 default: AnyLuaValue,
+                 min: AnyLuaValue,
+                 max: AnyLuaValue,
+                 step: AnyLuaValue,
+                 choices: AnyLuaValue| {
+                    let parameter = Parameter::from_lua(name, default, min, max, step, choices)?;
+                    params::declare(&mut parameters, values, parameter)
+                },
+            ),
+        );
+        // Parts edited under the same name again (e.g. in a loop) share the parameters, the
+        // view edits them with the first one.
+        lua.set(
+            "__editable",
+            hlua::function8(
+                |o: &LObject,
+                 name: String,
+                 x: Float,
+                 y: Float,
+                 z: Float,
+                 rx: Float,
+                 ry: Float,
+                 rz: Float| {
+                    let (moved, e) = editable::edit(
+                        o,
+                        name,
+                        na::Vector3::new(x, y, z),
+                        na::Vector3::new(rx, ry, rz),
+                    );
+                    if !editables.iter().any(|other| other.name == e.name) {
+                        editables.push(e);
+                    }
+                    moved
+                },
+            ),
+        );
+        lua.set(
+            "__include",
+            hlua::function1(|name: String| -> Result<String, PrimitiveError> {
+                let source = includes.source(&name)?;
+                if !included.contains(&name) {
+                    included.push(name);
+                }
+                Ok(source)
+            }),
+        );
+        params::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        include::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        editable::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        LObject::export_factories(&mut lua, SANDBOX_ENV_NAME, console.clone(), includes);
         // LObjectVector needs access to full lua object and the SANDBOX_ENV_NAME.
         LObjectVector::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        arrange::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        ConstraintSystem::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        blend::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        edge::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        parts::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        threads::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        svg::export_factories(&mut lua, SANDBOX_ENV_NAME, includes);
+        dxf::export_factories(&mut lua, SANDBOX_ENV_NAME, includes);
+        pointcloud::export_factories(&mut lua, SANDBOX_ENV_NAME, includes);
+        plugin::export_factories(&mut lua, SANDBOX_ENV_NAME);
+        // See Includes::untrusted.
+        if includes.untrusted {
+            lua.execute::<()>(&format!(
+                "{env}.Mesh = function() error(\"Mesh: reading files is not allowed\", 0) end",
+                env = SANDBOX_ENV_NAME
+            ))
+            .unwrap();
+        }
 
-        // Store the script in the Lua var USER_FUNCTION_NAME.
-        try!(lua.checked_set(USER_FUNCTION_NAME, hlua::LuaCode(script)));
-        // Use this script wrapper to execute USER_FUNCTION_NAME with sandbox env.
-        try!(lua.execute::<()>(&format!(
-            "debug.setupvalue({}, 1, {}); return {}();",
-            USER_FUNCTION_NAME, SANDBOX_ENV_NAME, USER_FUNCTION_NAME
-        )));
-        print_output = printbuffer.get_buffer();
+        // Compile the script with the sandbox env into the Lua var USER_FUNCTION_NAME. hlua
+        // panics on syntax errors, so Lua's load is used instead.
+        lua.set(USER_FUNCTION_NAME, script);
+        lua.execute::<()>(&format!(
+            "local f, e = load({user}, \"={name}\", \"t\", {env})
+            if not f then error(e, 0) end
+            {user} = f",
+            user = USER_FUNCTION_NAME,
+            name = SCRIPT_NAME,
+            env = SANDBOX_ENV_NAME
+        ))?;
+        // Errors raised by Rust functions have no position, they get the line of the script
+        // which called them.
+        lua.execute::<()>(&format!(
+            "local ok, e = xpcall({user}, function(e)
+              if type(e) == \"string\" and not e:find(\"^{name}:%d+:\") then
+                local level = 2
+                local info = debug.getinfo(level, \"Sl\")
+                while info do
+                  if info.source == \"={name}\" then
+                    return \"{name}:\" .. info.currentline .. \": \" .. e
+                  end
+                  level = level + 1
+                  info = debug.getinfo(level, \"Sl\")
+                end
+              end
+              return e
+            end)
+            if not ok then error(e, 0) end",
+            user = USER_FUNCTION_NAME,
+            name = SCRIPT_NAME
+        ))?;
+        messages = printbuffer.get_messages();
+    }
+    // Empty and Full have no surface to show.
+    let warning = match result.as_ref().map(|o| o.node()) {
+        Some(&Node::Empty) => Some("the object is empty"),
+        Some(&Node::Full) => Some("the object fills all space"),
+        _ => None,
+    };
+    if let Some(warning) = warning {
+        messages.push(console.message(Level::Warning, None, warning));
+        result = None;
     }
-    Ok((print_output, result))
+    Ok(Evaluation {
+        output: messages.iter().map(|m| m.to_string() + "\n").collect(),
+        messages,
+        object: result.as_ref().map(|o| o.as_object()),
+        colored_parts: result.as_ref().map_or_else(Vec::new, |o| o.colored_parts()),
+        tree: result.map(|o| o.as_node()),
+        units: units.unwrap_or_default(),
+        tessellation_region,
+        drawing_anchors,
+        parameters,
+        editables,
+        included,
+    })
 }
@@ -0,0 +1,295 @@
+use super::Float;
+use bounded;
+use implicit3d::{BoundingBox, Object};
+use nalgebra as na;
+
+// Projections and alternations stop when they change less than this fraction of the size of
+// the objects.
+#[cfg(not(feature = "f32"))]
+pub const PRECISION: Float = 1e-9;
+#[cfg(feature = "f32")]
+pub const PRECISION: Float = 1e-5;
+const MAX_PROJECTION_STEPS: usize = 50;
+const MAX_ALTERNATIONS: usize = 200;
+// intersects does not subdivide cells below this fraction of the size of the region it searches.
+const MIN_CELL: Float = 1e-3;
+// Rays hit surfaces where the value is below this fraction of the size of the object (or of 1,
+// for larger or infinite ones), after at most MAX_RAY_STEPS.
+#[cfg(not(feature = "f32"))]
+const HIT_PRECISION: Float = 1e-6;
+#[cfg(feature = "f32")]
+const HIT_PRECISION: Float = 1e-4;
+const MAX_RAY_STEPS: usize = 1000;
+// closest_surface_point takes at most this many steps along the surface, each halved at most
+// MAX_HALVINGS times until it gets closer.
+const MAX_SURFACE_STEPS: usize = 100;
+const MAX_HALVINGS: usize = 20;
+
+// A ray from origin along dir, which need not be a unit vector.
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: na::Point3<Float>,
+    pub dir: na::Vector3<Float>,
+}
+
+impl Ray {
+    pub fn new(o: na::Point3<Float>, d: na::Vector3<Float>) -> Ray {
+        Ray { origin: o, dir: d }
+    }
+}
+
+// Where a ray hits a surface: the point, the normal of the surface there and the distance from
+// the origin of the ray.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub point: na::Point3<Float>,
+    pub normal: na::Vector3<Float>,
+    pub t: Float,
+}
+
+// Queries of the surfaces of objects, for every implicit3d object, so that picking in the GUI,
+// measuring, the analyses and the C API share them.
+pub trait SurfaceQuery {
+    // Where ray first hits the surface within max_t of its origin (at the origin, if it starts
+    // inside), by sphere tracing: the value is the radius of a sphere free of the surface, so
+    // the ray steps that far without passing it. None if it misses.
+    fn raycast(&self, ray: &Ray, max_t: Float) -> Option<Hit>;
+    // The point on the surface closest to p, by projected gradient descent on the distance from
+    // p: a point on the surface (see project) steps towards p along the surface, and is
+    // projected back onto it, for as long as that brings it closer. For exact distance fields,
+    // the first projection already is the closest point, the steps correct for the others. None
+    // where the object has no surface, value or normal.
+    fn closest_surface_point(&self, p: &na::Point3<Float>) -> Option<na::Point3<Float>>;
+}
+
+impl<T: Object<Float> + ?Sized> SurfaceQuery for T {
+    fn raycast(&self, ray: &Ray, max_t: Float) -> Option<Hit> {
+        let dir = ray.dir.normalize();
+        let mut bbox = self.bbox().clone();
+        let size = if bounded::is_finite(&bbox) {
+            bbox.dim().norm()
+        } else {
+            1.
+        };
+        let tolerance = size.max(1.) * HIT_PRECISION;
+        bbox.dilate(tolerance);
+        let (near, far) = bounded::clip_ray(&bbox, &ray.origin, &dir)?;
+        let far = far.min(max_t);
+        let mut t = near.max(0.);
+        for _ in 0..MAX_RAY_STEPS {
+            if t > far {
+                return None;
+            }
+            let point = ray.origin + dir * t;
+            let v = value(self, &point);
+            if !v.is_finite() {
+                return None;
+            }
+            if v < tolerance {
+                return Some(Hit {
+                    point,
+                    normal: self.normal(&point),
+                    t,
+                });
+            }
+            t += v;
+        }
+        None
+    }
+    fn closest_surface_point(&self, p: &na::Point3<Float>) -> Option<na::Point3<Float>> {
+        let bbox = self.bbox();
+        if bounded::is_empty(bbox) {
+            return None;
+        }
+        let size = if bounded::is_finite(bbox) {
+            bbox.dim().norm()
+        } else {
+            1.
+        };
+        let tolerance = size.max(1.) * PRECISION;
+        let mut q = project(self, p, tolerance)?;
+        if value(self, &q).abs() > tolerance.max(na::distance(p, &q) * HIT_PRECISION) {
+            // No surface in reach, e.g. of Full.
+            return None;
+        }
+        let mut scale = 1.;
+        for _ in 0..MAX_SURFACE_STEPS {
+            let normal = self.normal(&q);
+            let towards = p - q;
+            // The gradient of the distance from p, projected onto the tangent plane.
+            let tangent = towards - normal * towards.dot(&normal);
+            if tangent.norm() <= tolerance {
+                break;
+            }
+            let distance = na::distance(p, &q);
+            let mut closer = None;
+            for _ in 0..MAX_HALVINGS {
+                match project(self, &(q + tangent * scale), tolerance) {
+                    Some(next) if na::distance(p, &next) < distance - tolerance => {
+                        closer = Some(next);
+                        break;
+                    }
+                    _ => scale *= 0.5,
+                }
+            }
+            match closer {
+                Some(next) => {
+                    q = next;
+                    scale = (scale * 2.).min(1.);
+                }
+                None => break,
+            }
+        }
+        Some(q)
+    }
+}
+
+// A pair of closest points on the surfaces of two objects, see min_distance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clearance {
+    pub distance: Float,
+    pub a: na::Point3<Float>,
+    pub b: na::Point3<Float>,
+}
+
+// The minimum distance between the surfaces of a and b, 0 if they overlap. Uses alternating
+// projection: a point is moved to the closest point on the surface of a (see
+// SurfaceQuery::closest_surface_point), the result to the closest on the surface of b, and
+// so on, which converges to a pair of closest points. For objects which are not convex, the pair
+// may only be closest locally, so this starts from the corners and centers of both bounding
+// boxes and keeps the closest pair. None if neither bounding box is finite.
+pub fn min_distance(a: &dyn Object<Float>, b: &dyn Object<Float>) -> Option<Clearance> {
+    let boxes: Vec<&BoundingBox<Float>> = [a.bbox(), b.bbox()]
+        .iter()
+        .cloned()
+        .filter(|bbox| bounded::is_finite(bbox))
+        .collect();
+    if boxes.is_empty() {
+        return None;
+    }
+    let size = boxes
+        .iter()
+        .map(|bbox| bbox.dim().norm())
+        .fold(0., Float::max);
+    let tolerance = size.max(1.) * PRECISION;
+    let mut best: Option<Clearance> = None;
+    for seed in boxes.iter().flat_map(|bbox| seeds(bbox)) {
+        match (alternate(a, b, seed, tolerance), best.as_ref()) {
+            (Some(ref clearance), Some(c)) if clearance.distance >= c.distance => {}
+            (Some(clearance), _) => best = Some(clearance),
+            (None, _) => {}
+        }
+    }
+    best
+}
+
+// Whether a and b overlap by more than tolerance, i.e. some point is deeper than tolerance
+// inside of both. Objects which only touch (like stacked parts) do not.
+// Searches an octree over the intersection of their bounding boxes. The values of implicit3d
+// objects are at most the distances to their surfaces, so they bound the values in a cell
+// around its center, and cells which can not be deep enough inside of both are skipped.
+// None if the intersection of the bounding boxes is not finite.
+pub fn intersects(a: &dyn Object<Float>, b: &dyn Object<Float>, tolerance: Float) -> Option<bool> {
+    let region = a.bbox().intersection(b.bbox());
+    if bounded::is_empty(&region) {
+        return Some(false);
+    }
+    if !bounded::is_finite(&region) {
+        return None;
+    }
+    let min_radius = (region.dim().norm() * MIN_CELL).max(tolerance) * 0.5;
+    let mut cells = vec![(na::center(&region.min, &region.max), region.dim() * 0.5)];
+    while let Some((center, half)) = cells.pop() {
+        let (va, vb) = (value(a, &center), value(b, &center));
+        if va < -tolerance && vb < -tolerance {
+            return Some(true);
+        }
+        let radius = half.norm();
+        if va - radius >= -tolerance || vb - radius >= -tolerance || radius <= min_radius {
+            continue;
+        }
+        let half = half * 0.5;
+        // Regions of touching boxes are flat, without splitting along the flat axis.
+        for i in (0..8).filter(|i| (0..3).all(|axis| i & 1 << axis == 0 || half[axis] > 0.)) {
+            let offset = na::Vector3::new(
+                if i & 1 == 0 { -half.x } else { half.x },
+                if i & 2 == 0 { -half.y } else { half.y },
+                if i & 4 == 0 { -half.z } else { half.z },
+            );
+            cells.push((center + offset, half));
+        }
+    }
+    Some(false)
+}
+
+// The corners and the center of bbox.
+fn seeds(bbox: &BoundingBox<Float>) -> Vec<na::Point3<Float>> {
+    let mut seeds = bounded::corners(bbox).to_vec();
+    seeds.push(na::center(&bbox.min, &bbox.max));
+    seeds
+}
+
+fn alternate(
+    a: &dyn Object<Float>,
+    b: &dyn Object<Float>,
+    seed: na::Point3<Float>,
+    tolerance: Float,
+) -> Option<Clearance> {
+    let mut p = seed;
+    let mut previous = Float::INFINITY;
+    let mut clearance = None;
+    for _ in 0..MAX_ALTERNATIONS {
+        let pa = a.closest_surface_point(&p)?;
+        // A surface point of a inside of b.
+        if value(b, &pa) < 0. {
+            return Some(Clearance {
+                distance: 0.,
+                a: pa,
+                b: pa,
+            });
+        }
+        let pb = b.closest_surface_point(&pa)?;
+        let distance = na::distance(&pa, &pb);
+        clearance = Some(Clearance {
+            distance,
+            a: pa,
+            b: pb,
+        });
+        if previous - distance <= tolerance {
+            break;
+        }
+        previous = distance;
+        p = pb;
+    }
+    clearance
+}
+
+// The point on the surface of object reached from p along the normals. None where the object
+// has no value or normal.
+pub fn project<T: Object<Float> + ?Sized>(
+    object: &T,
+    p: &na::Point3<Float>,
+    tolerance: Float,
+) -> Option<na::Point3<Float>> {
+    let mut p = *p;
+    for _ in 0..MAX_PROJECTION_STEPS {
+        let v = value(object, &p);
+        if !v.is_finite() {
+            return None;
+        }
+        if v.abs() <= tolerance {
+            break;
+        }
+        let normal = object.normal(&p);
+        if !normal.iter().all(|x| x.is_finite()) {
+            return None;
+        }
+        p -= normal * v;
+    }
+    Some(p)
+}
+
+// The value without approximation by bounding boxes.
+fn value<T: Object<Float> + ?Sized>(object: &T, p: &na::Point3<Float>) -> Float {
+    object.approx_value(p, Float::INFINITY)
+}
@@ -0,0 +1,228 @@
+use super::{Float, RELATIVE_STEP};
+use error::{self, PrimitiveError};
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Samples are radius / SAMPLES_PER_RADIUS apart. Flat faces get bumps of about 3% of the radius.
+const SAMPLES_PER_RADIUS: Float = 3.;
+// The cache of samples is cleared when it grows beyond this many values.
+const MAX_ENTRIES: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    // Erode, then dilate: convex edges are rounded.
+    Opening,
+    // Dilate, then erode: concave edges are rounded.
+    Closing,
+}
+
+impl Operation {
+    pub fn parse(name: &str) -> Result<Operation, PrimitiveError> {
+        match name {
+            "opening" => Ok(Operation::Opening),
+            "closing" => Ok(Operation::Closing),
+            _ => Err(PrimitiveError::UnknownName {
+                function: "smooth",
+                name: name.to_string(),
+                expected: "opening or closing",
+            }),
+        }
+    }
+}
+
+// The opening of an object is the union of all balls of the radius inside it. It is
+// approximated by the balls around samples on a grid which are at least the radius inside, each
+// with the value of its sample as radius. Closing is the opening of the outside.
+#[derive(Debug)]
+pub struct Morphology {
+    object: Box<dyn Object<Float>>,
+    op: Operation,
+    radius: Float,
+    spacing: Float,
+    samples: Mutex<HashMap<[i64; 3], Float>>,
+}
+
+impl Morphology {
+    pub fn new(
+        object: Box<dyn Object<Float>>,
+        op: Operation,
+        radius: Float,
+    ) -> Result<Box<Morphology>, PrimitiveError> {
+        let radius = error::positive("smooth", "radius", radius)?;
+        Ok(Box::new(Morphology {
+            object,
+            op,
+            radius,
+            spacing: radius / SAMPLES_PER_RADIUS,
+            samples: Mutex::new(HashMap::new()),
+        }))
+    }
+    // The value of the object for Opening, of its outside for Closing.
+    fn inside_value(&self, p: &na::Point3<Float>) -> Float {
+        let value = self.object.approx_value(p, self.radius);
+        match self.op {
+            Operation::Opening => value,
+            Operation::Closing => -value,
+        }
+    }
+    // The value of the opening of inside_value.
+    fn opening(&self, p: &na::Point3<Float>) -> Float {
+        let v = self.inside_value(p);
+        // Balls out of reach of the samples are at least half a cell diagonal away.
+        let far = self.spacing * Float::sqrt(3.) / 2.;
+        // The opening is further away than the object. Deeper than the radius inside, it is at
+        // least the radius inside, and at most that far beyond the eroded object.
+        if v >= far {
+            return v;
+        }
+        if v <= -self.radius {
+            return (v + self.radius).min(-self.radius);
+        }
+        let reach = self.radius + self.spacing * Float::sqrt(3.);
+        let (min, max) = (
+            self.key(&(p - na::Vector3::repeat(reach))),
+            self.key(&(p + na::Vector3::repeat(reach))),
+        );
+        let mut keys = Vec::new();
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    if (self.point(&[x, y, z]) - p).norm() <= reach {
+                        keys.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        let mut best = Float::INFINITY;
+        for (key, sample) in keys.iter().zip(self.samples(&keys)) {
+            if sample <= -self.radius {
+                best = best.min((self.point(key) - p).norm() + sample);
+            }
+        }
+        v.max(best.min(far))
+    }
+    fn key(&self, p: &na::Point3<Float>) -> [i64; 3] {
+        [
+            (p.x / self.spacing).round() as i64,
+            (p.y / self.spacing).round() as i64,
+            (p.z / self.spacing).round() as i64,
+        ]
+    }
+    fn point(&self, key: &[i64; 3]) -> na::Point3<Float> {
+        na::Point3::new(
+            key[0] as Float * self.spacing,
+            key[1] as Float * self.spacing,
+            key[2] as Float * self.spacing,
+        )
+    }
+    // inside_value at the samples of keys. The lock is not held while evaluating the object, so
+    // that threads tessellating in parallel do not wait for each other.
+    fn samples(&self, keys: &[[i64; 3]]) -> Vec<Float> {
+        let mut values: Vec<Option<Float>> = {
+            let samples = self.samples.lock().unwrap();
+            keys.iter().map(|k| samples.get(k).cloned()).collect()
+        };
+        let mut new = Vec::new();
+        for (key, value) in keys.iter().zip(values.iter_mut()) {
+            if value.is_none() {
+                let v = self.inside_value(&self.point(key));
+                *value = Some(v);
+                new.push((*key, v));
+            }
+        }
+        if !new.is_empty() {
+            let mut samples = self.samples.lock().unwrap();
+            if samples.len() + new.len() > MAX_ENTRIES {
+                samples.clear();
+            }
+            samples.extend(new);
+        }
+        values.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+// Clones start without samples.
+impl Clone for Morphology {
+    fn clone(&self) -> Morphology {
+        Morphology {
+            object: self.object.clone(),
+            op: self.op,
+            radius: self.radius,
+            spacing: self.spacing,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Object<Float> for Morphology {
+    // Both stay inside the convex hull of the object.
+    fn bbox(&self) -> &BoundingBox<Float> {
+        self.object.bbox()
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+        self.samples.lock().unwrap().clear();
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox().distance(p);
+        if approx > slack {
+            return approx;
+        }
+        match self.op {
+            Operation::Opening => self.opening(p),
+            Operation::Closing => -self.opening(p),
+        }
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.radius * RELATIVE_STEP;
+        let difference = |d: na::Vector3<Float>| {
+            self.approx_value(&(p + d), 0.) - self.approx_value(&(p - d), 0.)
+        };
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            difference(na::Vector3::z() * e),
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
+
+// The object grown by distance, or shrunk if it is negative, by shifting its values. Since the
+// values are at most the distances to the surface, a shrunk object keeps at least the distance
+// to the surface of the object, e.g. for the cavity of hollow.
+#[derive(Clone, Debug)]
+pub struct Offset {
+    object: Box<dyn Object<Float>>,
+    distance: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl Offset {
+    pub fn new(object: Box<dyn Object<Float>>, distance: Float) -> Box<Offset> {
+        let mut bbox = object.bbox().clone();
+        bbox.dilate(distance.max(0.));
+        Box::new(Offset {
+            object,
+            distance,
+            bbox,
+        })
+    }
+}
+
+impl Object<Float> for Offset {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        self.object.approx_value(p, slack + self.distance.abs()) - self.distance
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.object.normal(p)
+    }
+}
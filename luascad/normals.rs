@@ -0,0 +1,116 @@
+use super::Float;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+
+// How normals are found: from the objects, which know them in closed form or through their
+// children where they can, or estimated from values around the point.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NormalEstimate {
+    #[default]
+    Object,
+    // Differences to the value at the point along each axis: 4 evaluations, but biased to one
+    // side, which shows near edges.
+    Forward,
+    // Differences between both sides along each axis: 6 evaluations.
+    Central,
+    // Differences at the corners of a tetrahedron around the point: 4 evaluations, symmetric
+    // like central differences.
+    Tetrahedron,
+}
+
+impl NormalEstimate {
+    // The estimate of a setting: 0 object, 1 forward, 2 central, 3 tetrahedron.
+    pub fn from_index(index: usize) -> NormalEstimate {
+        match index {
+            1 => NormalEstimate::Forward,
+            2 => NormalEstimate::Central,
+            3 => NormalEstimate::Tetrahedron,
+            _ => NormalEstimate::Object,
+        }
+    }
+}
+
+// The normal at p of the function value, estimated with differences over step. Object is taken
+// as Central, value is all there is to go by. Where the differences vanish, it is x.
+pub fn estimate<F: Fn(&na::Point3<Float>) -> Float>(
+    method: NormalEstimate,
+    value: F,
+    p: &na::Point3<Float>,
+    step: Float,
+) -> na::Vector3<Float> {
+    let along = |x: Float, y: Float, z: Float| value(&(p + na::Vector3::new(x, y, z) * step));
+    let gradient = match method {
+        NormalEstimate::Forward => {
+            let center = value(p);
+            na::Vector3::new(
+                along(1., 0., 0.) - center,
+                along(0., 1., 0.) - center,
+                along(0., 0., 1.) - center,
+            )
+        }
+        NormalEstimate::Object | NormalEstimate::Central => na::Vector3::new(
+            along(1., 0., 0.) - along(-1., 0., 0.),
+            along(0., 1., 0.) - along(0., -1., 0.),
+            along(0., 0., 1.) - along(0., 0., -1.),
+        ),
+        // The corners are weighted by their direction, the other parts of the sum cancel.
+        NormalEstimate::Tetrahedron => {
+            na::Vector3::new(1., -1., -1.) * along(1., -1., -1.)
+                + na::Vector3::new(-1., -1., 1.) * along(-1., -1., 1.)
+                + na::Vector3::new(-1., 1., -1.) * along(-1., 1., -1.)
+                + na::Vector3::new(1., 1., 1.) * along(1., 1., 1.)
+        }
+    };
+    gradient.try_normalize(0.).unwrap_or_else(na::Vector3::x)
+}
+
+// An object with its normals estimated from its values, e.g. to tessellate it with another
+// estimate than its own.
+#[derive(Clone, Debug)]
+pub struct Estimated {
+    object: Box<dyn Object<Float>>,
+    method: NormalEstimate,
+    step: Float,
+}
+
+impl Estimated {
+    // object with its normals estimated with method over step, or object itself if method is
+    // Object.
+    pub fn wrap(
+        object: Box<dyn Object<Float>>,
+        method: NormalEstimate,
+        step: Float,
+    ) -> Box<dyn Object<Float>> {
+        if method == NormalEstimate::Object {
+            return object;
+        }
+        Box::new(Estimated {
+            object,
+            method,
+            step,
+        })
+    }
+}
+
+impl Object<Float> for Estimated {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        self.object.bbox()
+    }
+    fn set_bbox(&mut self, bbox: &BoundingBox<Float>) {
+        self.object.set_bbox(bbox)
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        self.object.approx_value(p, slack)
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        estimate(
+            self.method,
+            |q| self.object.approx_value(q, 0.),
+            p,
+            self.step,
+        )
+    }
+}
@@ -0,0 +1,424 @@
+use super::Float;
+use bounded;
+use implicit3d::BoundingBox;
+use nalgebra as na;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use tree::{Boolean, Node};
+
+// Transformations closer than this to the identity are dropped.
+#[cfg(not(feature = "f32"))]
+const IDENTITY_EPSILON: Float = 1e-12;
+#[cfg(feature = "f32")]
+const IDENTITY_EPSILON: Float = 1e-6;
+
+// Simplify the tree without changing its shape:
+// - fold chains of transformations into one and drop identity transformations,
+// - merge nested unions, intersections and differences with equal smoothing into one,
+// - drop Empty and Full where they do not change the result (see Node::resolve),
+// - drop repeated children of booleans without smoothing (a | a = a, a - b - b = a - b),
+// - replace booleans of a single object by that object.
+// Merging smooth booleans blends all children at once. Where more than two of them are within
+// the smoothing range, this rounds slightly differently than blending them pairwise.
+pub fn optimize(node: Node) -> Node {
+    match node {
+        Node::Union { children, smooth } => {
+            let children = merge(children, smooth, |node| match *node {
+                Node::Union { smooth, .. } => Some(smooth),
+                _ => None,
+            });
+            boolean(Boolean::Union, smooth, children, |children| Node::Union {
+                children,
+                smooth,
+            })
+        }
+        Node::Intersection { children, smooth } => {
+            let children = merge(children, smooth, |node| match *node {
+                Node::Intersection { smooth, .. } => Some(smooth),
+                _ => None,
+            });
+            boolean(Boolean::Intersection, smooth, children, |children| {
+                Node::Intersection { children, smooth }
+            })
+        }
+        Node::Difference { children, smooth } => {
+            // (a - b) - c = a - b - c, so only the first child is merged.
+            let mut children = children.into_iter().map(optimize);
+            let mut merged = match children.next() {
+                Some(Node::Difference {
+                    children: inner,
+                    smooth: s,
+                }) if s == smooth => inner,
+                Some(first) => vec![first],
+                None => Vec::new(),
+            };
+            merged.extend(children);
+            boolean(Boolean::Difference, smooth, merged, |children| {
+                Node::Difference { children, smooth }
+            })
+        }
+        Node::Edge {
+            children,
+            op,
+            finish,
+        } => Node::Edge {
+            children: children.into_iter().map(optimize).collect(),
+            op,
+            finish,
+        },
+        Node::Sharp { children, op } => Node::Sharp {
+            children: children.into_iter().map(optimize).collect(),
+            op,
+        },
+        Node::LocalBlend { children, width } => Node::LocalBlend {
+            children: children.into_iter().map(optimize).collect(),
+            width,
+        },
+        Node::Transform {
+            child,
+            transform,
+            scale,
+        } => {
+            // Like Node::transformed, the inner transformation is applied last.
+            let (child, transform, scale) = match optimize(*child) {
+                Node::Transform {
+                    child: inner,
+                    transform: t,
+                    scale: s,
+                } => (*inner, t * transform, s * scale),
+                other => (other, transform, scale),
+            };
+            if child.is_constant()
+                || is_identity(&transform) && (scale - 1.).abs() < IDENTITY_EPSILON
+            {
+                child
+            } else {
+                Node::Transform {
+                    child: Box::new(child),
+                    transform,
+                    scale,
+                }
+            }
+        }
+        Node::Twist {
+            child,
+            height_scaler,
+            value_scaler,
+        } => match optimize(*child) {
+            child if child.is_constant() => child,
+            child => Node::Twist {
+                child: Box::new(child),
+                height_scaler,
+                value_scaler,
+            },
+        },
+        Node::Bend {
+            child,
+            width_scaler,
+            inner_radius,
+        } => match optimize(*child) {
+            child if child.is_constant() => child,
+            child => Node::Bend {
+                child: Box::new(child),
+                width_scaler,
+                inner_radius,
+            },
+        },
+        Node::Component {
+            child,
+            components,
+            part,
+        } => match optimize(*child) {
+            child if child.is_constant() => child,
+            child => Node::Component {
+                child: Box::new(child),
+                components,
+                part,
+            },
+        },
+        Node::Morphology { child, op, radius } => match optimize(*child) {
+            child if child.is_constant() => child,
+            child => Node::Morphology {
+                child: Box::new(child),
+                op,
+                radius,
+            },
+        },
+        Node::Offset { child, distance } => match optimize(*child) {
+            child if child.is_constant() => child,
+            child => Node::Offset {
+                child: Box::new(child),
+                distance,
+            },
+        },
+        other => other,
+    }
+}
+
+// Optimize children and replace those of the same kind with the same smoothing (smoothing
+// returns it for nodes of the same kind) by their children.
+fn merge<F>(children: Vec<Node>, smooth: Float, smoothing: F) -> Vec<Node>
+where
+    F: Fn(&Node) -> Option<Float>,
+{
+    let mut merged = Vec::with_capacity(children.len());
+    for child in children {
+        let child = optimize(child);
+        if smoothing(&child) == Some(smooth) {
+            merged.extend(into_children(child));
+        } else {
+            merged.push(child);
+        }
+    }
+    merged
+}
+
+fn into_children(node: Node) -> Vec<Node> {
+    match node {
+        Node::Union { children, .. }
+        | Node::Intersection { children, .. }
+        | Node::Difference { children, .. } => children,
+        other => vec![other],
+    }
+}
+
+// Like the boolean objects, a single child is used as is.
+fn boolean<F>(op: Boolean, smooth: Float, children: Vec<Node>, node: F) -> Node
+where
+    F: Fn(Vec<Node>) -> Node,
+{
+    let mut children = match Node::resolve(op, children, |n| n) {
+        Ok(children) => children,
+        Err(constant) => return *constant,
+    };
+    if smooth == 0. {
+        // The first child of a difference is not an operand like the others.
+        let skip = if op == Boolean::Difference { 1 } else { 0 };
+        let rest = dedup(children.split_off(skip.min(children.len())));
+        children.extend(rest);
+    }
+    if children.len() == 1 {
+        children.pop().unwrap()
+    } else {
+        node(children)
+    }
+}
+
+// Drop children which are equal (see Node::tree_eq) to an earlier one.
+fn dedup(children: Vec<Node>) -> Vec<Node> {
+    let mut kept: Vec<Node> = Vec::with_capacity(children.len());
+    let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    for child in children {
+        let mut hasher = DefaultHasher::new();
+        child.tree_hash(&mut hasher);
+        let same = by_hash.entry(hasher.finish()).or_default();
+        if !same.iter().any(|&i| kept[i].tree_eq(&child)) {
+            same.push(kept.len());
+            kept.push(child);
+        }
+    }
+    kept
+}
+
+// A box around the object described by node, computed bottom-up like Node::bbox, but with the
+// transformations pushed down to the children of booleans: their boxes are transformed before
+// they are combined, which is tighter than transforming the box around all of them, e.g. for
+// rotated unions of objects far apart.
+pub fn bbox(node: &Node) -> BoundingBox<Float> {
+    transformed_bbox(node, &na::Matrix4::identity()).intersection(&node.bbox())
+}
+
+// The box around node transformed by m, which maps the coordinates of node to those of the root.
+fn transformed_bbox(node: &Node, m: &na::Matrix4<Float>) -> BoundingBox<Float> {
+    let children = node.children();
+    match *node {
+        // The child is evaluated at transform * p, so its coordinates map back by the inverse.
+        Node::Transform {
+            ref child,
+            ref transform,
+            ..
+        } => match transform.try_inverse() {
+            Some(inverse) => transformed_bbox(child, &(m * inverse)),
+            None => BoundingBox::infinity(),
+        },
+        Node::Union { .. }
+        | Node::Sharp {
+            op: Boolean::Union, ..
+        } => children.iter().fold(BoundingBox::neg_infinity(), |b, c| {
+            b.union(&transformed_bbox(c, m))
+        }),
+        Node::Intersection { .. }
+        | Node::Sharp {
+            op: Boolean::Intersection,
+            ..
+        } => children.iter().fold(BoundingBox::infinity(), |b, c| {
+            b.intersection(&transformed_bbox(c, m))
+        }),
+        Node::Difference { .. }
+        | Node::Sharp {
+            op: Boolean::Difference,
+            ..
+        } => transformed_bbox(&children[0], m),
+        _ => bounded::transform(&node.bbox(), m),
+    }
+}
+
+fn is_identity(m: &na::Matrix4<Float>) -> bool {
+    (m - na::Matrix4::identity()).amax() < IDENTITY_EPSILON
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float::consts::PI;
+
+    fn sphere(radius: Float) -> Node {
+        Node::Sphere { radius }
+    }
+
+    fn transform(child: Node, transform: na::Matrix4<Float>, scale: Float) -> Node {
+        Node::Transform {
+            child: Box::new(child),
+            transform,
+            scale,
+        }
+    }
+
+    #[test]
+    fn transforms_fold_like_node_transformed() {
+        // Rotations and translations do not commute, so this fails if the order is wrong.
+        let r = na::Vector3::new(0.3, 0., 1.2);
+        let v = na::Vector3::new(1., 2., 3.);
+        let rotation = na::Rotation3::from_euler_angles(r.x, r.y, r.z).to_homogeneous();
+        let nested = transform(sphere(1.).translate(&v), rotation, 0.5);
+        let expected = match sphere(1.).translate(&v).rotate(&r) {
+            Node::Transform {
+                child, transform, ..
+            } => Node::Transform {
+                child,
+                transform,
+                scale: 0.5,
+            },
+            other => panic!("not a transformation: {:?}", other),
+        };
+        assert_eq!(optimize(nested), expected);
+    }
+
+    #[test]
+    fn identity_transforms_are_dropped() {
+        let v = na::Vector3::new(1., 2., 3.);
+        assert_eq!(
+            optimize(transform(sphere(1.), na::Matrix4::identity(), 1.)),
+            sphere(1.)
+        );
+        let back = transform(
+            sphere(1.).translate(&v),
+            na::Matrix4::new_translation(&v),
+            1.,
+        );
+        assert_eq!(optimize(back), sphere(1.));
+        // Scaling the value is not the identity.
+        let scaled = transform(sphere(1.), na::Matrix4::identity(), 2.);
+        assert_eq!(optimize(scaled.clone()), scaled);
+        assert_eq!(
+            optimize(transform(Node::Empty, na::Matrix4::new_translation(&v), 1.)),
+            Node::Empty
+        );
+    }
+
+    #[test]
+    fn booleans_merge_with_equal_smoothing() {
+        for op in &[Boolean::Union, Boolean::Intersection] {
+            let inner = |smooth| op.node(vec![sphere(1.), sphere(2.)], smooth);
+            assert_eq!(
+                optimize(op.node(vec![inner(0.5), sphere(3.)], 0.5)),
+                op.node(vec![sphere(1.), sphere(2.), sphere(3.)], 0.5)
+            );
+            let different = op.node(vec![inner(0.2), sphere(3.)], 0.5);
+            assert_eq!(optimize(different.clone()), different);
+        }
+        // A union in an intersection is not merged, even with the same smoothing.
+        let mixed = Node::Intersection {
+            children: vec![
+                Boolean::Union.node(vec![sphere(1.), sphere(2.)], 0.),
+                sphere(3.),
+            ],
+            smooth: 0.,
+        };
+        assert_eq!(optimize(mixed.clone()), mixed);
+    }
+
+    #[test]
+    fn differences_merge_only_the_first_child() {
+        let difference = |children| Boolean::Difference.node(children, 0.);
+        assert_eq!(
+            optimize(difference(vec![
+                difference(vec![sphere(1.), sphere(2.)]),
+                sphere(3.)
+            ])),
+            difference(vec![sphere(1.), sphere(2.), sphere(3.)])
+        );
+        // a - (b - c) is not a - b - c.
+        let nested = difference(vec![sphere(1.), difference(vec![sphere(2.), sphere(3.)])]);
+        assert_eq!(optimize(nested.clone()), nested);
+        let smooth = Boolean::Difference.node(vec![sphere(1.), sphere(2.)], 0.5);
+        let different = difference(vec![smooth, sphere(3.)]);
+        assert_eq!(optimize(different.clone()), different);
+    }
+
+    #[test]
+    fn single_children_replace_booleans() {
+        for op in &[Boolean::Union, Boolean::Intersection, Boolean::Difference] {
+            assert_eq!(optimize(op.node(vec![sphere(1.)], 0.5)), sphere(1.));
+        }
+        assert_eq!(
+            optimize(Boolean::Union.node(vec![sphere(1.), Node::Empty], 0.5)),
+            sphere(1.)
+        );
+        assert_eq!(
+            optimize(Boolean::Intersection.node(vec![Node::Full, sphere(1.)], 0.5)),
+            sphere(1.)
+        );
+        // Repeated children are only dropped without smoothing.
+        assert_eq!(
+            optimize(Boolean::Union.node(vec![sphere(1.), sphere(1.)], 0.)),
+            sphere(1.)
+        );
+        let smooth = Boolean::Union.node(vec![sphere(1.), sphere(1.)], 0.5);
+        assert_eq!(optimize(smooth.clone()), smooth);
+    }
+
+    #[test]
+    fn bbox_of_transformed_union() {
+        let (a, b) = (na::Vector3::new(10., 0., 0.), na::Vector3::new(0., 10., 0.));
+        let r = na::Vector3::new(0., 0., PI / 4.);
+        let union =
+            Boolean::Union.node(vec![sphere(1.).translate(&a), sphere(1.).translate(&b)], 0.);
+        let node = union.rotate(&r);
+        let (tight, loose) = (bbox(&node), node.bbox());
+        let area = |b: &BoundingBox<Float>| (b.max.x - b.min.x) * (b.max.y - b.min.y);
+        assert!(area(&tight) < area(&loose) / 2., "{:?} {:?}", tight, loose);
+        // The spheres are still inside.
+        let inverse = na::Rotation3::from_euler_angles(r.x, r.y, r.z).inverse();
+        for center in &[a, b] {
+            let center = inverse * na::Point3::from(*center);
+            for i in 0..3 {
+                assert!(
+                    tight.min[i] <= center[i] - 1. + 1e-9,
+                    "{:?} {}",
+                    tight,
+                    center
+                );
+                assert!(
+                    tight.max[i] >= center[i] + 1. - 1e-9,
+                    "{:?} {}",
+                    tight,
+                    center
+                );
+            }
+        }
+        // Without transformations, it is the box of the tree.
+        assert_eq!(bbox(&union), union.bbox());
+    }
+}
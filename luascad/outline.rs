@@ -0,0 +1,130 @@
+use super::{Float, RELATIVE_STEP};
+use api::Function;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+
+// The profiles of imported drawings, see svg and dxf.
+
+// No arc is flattened to more segments than this, e.g. for tiny tolerances.
+const MAX_SEGMENTS: usize = 1000;
+
+// The functions importing them, see api.
+pub const API: &[Function] = &[
+    Function {
+        name: "ImportSVG",
+        args: Some("filename, scale"),
+        doc: "The filled shapes of an SVG file next to the script, in the xy plane and infinite along z like Cylinder: intersect it with a Box to extrude it. scale (1 by default) is the size of a unit of the SVG, e.g. 25.4 / 96 for pixels in mm. Curves are flattened to 0.01, holes follow the even-odd rule.",
+    },
+    Function {
+        name: "ImportDXF",
+        args: Some("filename, scale"),
+        doc: "The closed profiles of the lines, arcs, circles and polylines of a DXF file next to the script, like ImportSVG. Profiles inside others are holes. scale (1 by default) is the size of a unit of the drawing, e.g. 25.4 for inches in mm.",
+    },
+];
+
+// A shape in the xy plane made of closed polygons, infinite along z. Points are inside if a ray
+// from them crosses the polygons an odd number of times (the even-odd rule), and the value is
+// the distance to the nearest edge.
+#[derive(Clone, Debug)]
+pub struct Outline {
+    contours: Vec<Vec<na::Point2<Float>>>,
+    // Step of the finite differences of normals.
+    epsilon: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl Outline {
+    pub fn new(contours: Vec<Vec<na::Point2<Float>>>) -> Box<Outline> {
+        let bbox = outline_bbox(&contours);
+        Box::new(Outline {
+            contours,
+            epsilon: (bbox.max.x - bbox.min.x).max(bbox.max.y - bbox.min.y) * RELATIVE_STEP,
+            bbox,
+        })
+    }
+    fn value(&self, p: &na::Point3<Float>) -> Float {
+        let q = na::Vector2::new(p.x, p.y);
+        let mut distance = Float::INFINITY;
+        let mut inside = false;
+        for contour in &self.contours {
+            let mut a = contour[contour.len() - 1].coords;
+            for b in contour {
+                let b = b.coords;
+                let ab = b - a;
+                let t = ((q - a).dot(&ab) / ab.norm_squared()).clamp(0., 1.);
+                if t.is_finite() {
+                    distance = distance.min((q - (a + ab * t)).norm());
+                }
+                if (a.y > q.y) != (b.y > q.y) && q.x < a.x + (q.y - a.y) * ab.x / ab.y {
+                    inside = !inside;
+                }
+                a = b;
+            }
+        }
+        if inside {
+            -distance
+        } else {
+            distance
+        }
+    }
+}
+
+impl Object<Float> for Outline {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        // The distance to the box is at most the distance to the outline.
+        let approx = self.bbox.distance(p);
+        if approx > slack {
+            approx
+        } else {
+            self.value(p)
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, _: &PrimitiveParameters<Float>) {}
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.epsilon;
+        let difference = |d: na::Vector3<Float>| self.value(&(p + d)) - self.value(&(p - d));
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            0.,
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
+
+// The box of contours, infinite along z.
+pub fn outline_bbox(contours: &[Vec<na::Point2<Float>>]) -> BoundingBox<Float> {
+    let mut min = na::Point3::new(Float::INFINITY, Float::INFINITY, Float::NEG_INFINITY);
+    let mut max = na::Point3::new(Float::NEG_INFINITY, Float::NEG_INFINITY, Float::INFINITY);
+    for p in contours.iter().flat_map(|c| c.iter()) {
+        min.x = min.x.min(p.x);
+        min.y = min.y.min(p.y);
+        max.x = max.x.max(p.x);
+        max.y = max.y.max(p.y);
+    }
+    BoundingBox::new(&min, &max)
+}
+
+// Append the points of the arc around center from the angle start by sweep (counter-clockwise
+// if positive, in radians) to contour, after its start, with chords which deviate from it by at
+// most tolerance.
+pub fn arc(
+    contour: &mut Vec<na::Point2<Float>>,
+    center: &na::Point2<Float>,
+    radius: Float,
+    start: Float,
+    sweep: Float,
+    tolerance: Float,
+) {
+    // The chords of an angle step a deviate from the circle by radius (1 - cos(a / 2)).
+    let step = 2. * (1. - (tolerance / radius).min(1.)).acos();
+    let n = ((sweep.abs() / step).ceil() as usize).clamp(1, MAX_SEGMENTS);
+    for i in 1..=n {
+        let a = start + sweep * i as Float / n as Float;
+        contour.push(center + na::Vector2::new(a.cos(), a.sin()) * radius);
+    }
+}
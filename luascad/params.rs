@@ -0,0 +1,190 @@
+use super::Float;
+use api::Function;
+use error::PrimitiveError;
+use hlua;
+use hlua::AnyLuaValue;
+use lobject;
+use std::collections::HashMap;
+
+// Number fields without a step change by this fraction of their range (or by 1 without one).
+const STEPS_PER_RANGE: Float = 100.;
+
+// The value of a parameter, see Parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(Float),
+    Bool(bool),
+    Choice(String),
+}
+
+// What values a parameter takes, e.g. to show a slider, a checkbox or a dropdown for it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    // A slider if both min and max are given, otherwise a field.
+    Number {
+        min: Option<Float>,
+        max: Option<Float>,
+        step: Float,
+    },
+    Bool,
+    // One of the names.
+    Choice(Vec<String>),
+}
+
+// A value of a script which can be set by the caller, declared with
+// param{name = "width", default = 10, min = 1, max = 50, step = 1}.
+// The kind follows from the default, a string needs choices = {"a", "b"}.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: Kind,
+    pub default: Value,
+}
+
+impl Parameter {
+    // The parameter declared by the fields of a param{...} table.
+    pub fn from_lua(
+        name: AnyLuaValue,
+        default: AnyLuaValue,
+        min: AnyLuaValue,
+        max: AnyLuaValue,
+        step: AnyLuaValue,
+        choices: AnyLuaValue,
+    ) -> Result<Parameter, PrimitiveError> {
+        let name = match name {
+            AnyLuaValue::LuaString(name) => name,
+            _ => return Err(invalid("", "name must be a string")),
+        };
+        let number = |value: AnyLuaValue, field: &'static str| match value {
+            AnyLuaValue::LuaNumber(x) if x.is_finite() => Ok(Some(x as Float)),
+            AnyLuaValue::LuaNil => Ok(None),
+            _ => Err(invalid(&name, field)),
+        };
+        let (kind, default) = match (default, choices) {
+            (AnyLuaValue::LuaNumber(default), AnyLuaValue::LuaNil) if default.is_finite() => {
+                let default = default as Float;
+                let min = number(min, "min must be a number")?;
+                let max = number(max, "max must be a number")?;
+                let step = number(step, "step must be a number")?;
+                if min.map_or(false, |min| default < min) || max.map_or(false, |max| default > max)
+                {
+                    return Err(invalid(&name, "default must be between min and max"));
+                }
+                let step = match (step, min, max) {
+                    (Some(step), _, _) if step > 0. => step,
+                    (Some(_), _, _) => return Err(invalid(&name, "step must be positive")),
+                    (None, Some(min), Some(max)) if max > min => (max - min) / STEPS_PER_RANGE,
+                    _ => 1.,
+                };
+                (Kind::Number { min, max, step }, Value::Number(default))
+            }
+            (AnyLuaValue::LuaBoolean(default), AnyLuaValue::LuaNil) => {
+                (Kind::Bool, Value::Bool(default))
+            }
+            (default, AnyLuaValue::LuaString(choices)) => {
+                if choices.is_empty() {
+                    return Err(invalid(&name, "choices must not be empty"));
+                }
+                let choices: Vec<String> = choices.split('\n').map(str::to_string).collect();
+                let default = match default {
+                    AnyLuaValue::LuaString(s) => s,
+                    AnyLuaValue::LuaNumber(x) => x.to_string(),
+                    AnyLuaValue::LuaNil => choices[0].clone(),
+                    _ => return Err(invalid(&name, "default must be one of the choices")),
+                };
+                if !choices.contains(&default) {
+                    return Err(invalid(&name, "default must be one of the choices"));
+                }
+                (Kind::Choice(choices), Value::Choice(default))
+            }
+            _ => {
+                return Err(invalid(
+                    &name,
+                    "default must be a number or a boolean, or choices must be given",
+                ))
+            }
+        };
+        Ok(Parameter {
+            name,
+            kind,
+            default,
+        })
+    }
+    // The value in values if it is one this parameter takes, otherwise the default.
+    pub fn value(&self, values: &HashMap<String, Value>) -> Value {
+        let valid = match (values.get(&self.name), &self.kind) {
+            (Some(&Value::Number(x)), &Kind::Number { min, max, .. }) => {
+                x.is_finite()
+                    && min.map_or(true, |min| x >= min)
+                    && max.map_or(true, |max| x <= max)
+            }
+            (Some(&Value::Bool(_)), &Kind::Bool) => true,
+            (Some(Value::Choice(c)), Kind::Choice(choices)) => choices.contains(c),
+            _ => false,
+        };
+        match values.get(&self.name) {
+            Some(value) if valid => value.clone(),
+            _ => self.default.clone(),
+        }
+    }
+}
+
+fn invalid(name: &str, message: &'static str) -> PrimitiveError {
+    PrimitiveError::InvalidDeclaration {
+        name: name.to_string(),
+        message,
+    }
+}
+
+// Declare the parameter of a param{...} table in declared and return its value from values.
+// Declaring the same parameter again (e.g. in a library used twice) returns the same value.
+pub fn declare(
+    declared: &mut Vec<Parameter>,
+    values: &HashMap<String, Value>,
+    parameter: Parameter,
+) -> Result<AnyLuaValue, PrimitiveError> {
+    match declared.iter().find(|p| p.name == parameter.name) {
+        Some(previous) if *previous != parameter => {
+            return Err(invalid(&parameter.name, "declared again with other fields"))
+        }
+        Some(_) => {}
+        None => declared.push(parameter.clone()),
+    }
+    Ok(match parameter.value(values) {
+        Value::Number(x) => lobject::lua_number(x),
+        Value::Bool(b) => AnyLuaValue::LuaBoolean(b),
+        Value::Choice(c) => AnyLuaValue::LuaString(c),
+    })
+}
+
+// Define param in the env, which passes the fields of its table on to the function __param
+// (see luascad::evaluate_with). Choices are passed as one string, separated by newlines.
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "param",
+        args: Some("{name, default, min, max, step, choices}"),
+        doc: "Declare a parameter of the customizer and return its value. default is a number, a boolean or one of the choices. min, max, step and choices are optional.",
+    },
+];
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.execute::<()>(&format!(
+        "
+        function param(t)
+          local choices = nil
+          if t.choices ~= nil then
+            local names = {{}}
+            for i, c in ipairs(t.choices) do
+              names[i] = tostring(c)
+            end
+            choices = table.concat(names, \"\\n\")
+          end
+          return __param(t.name, t.default, t.min, t.max, t.step, choices)
+        end
+        {env}.param = param
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
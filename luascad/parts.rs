@@ -0,0 +1,567 @@
+use super::{Float, RELATIVE_STEP};
+use api::Function;
+use error::{self, PrimitiveError};
+use float::consts::PI;
+use hlua;
+use implicit3d::{
+    BoundingBox, Cylinder, NormalPlane, Object, PlaneNegZ, PlaneZ, PrimitiveParameters,
+};
+use lobject::LObject;
+use lobject_vector::LObjectVector;
+use nalgebra as na;
+use tree::Node;
+
+// Segments of the tip and root circles of a half tooth of a Gear.
+const ARC_SEGMENTS: usize = 4;
+// Segments of the involute flank of a half tooth of a Gear.
+const FLANK_SEGMENTS: usize = 16;
+// Gears need a root circle, which the dedendum of 1.25 modules leaves from 3 teeth on.
+const MIN_TEETH: Float = 3.;
+// Helix angles of gears are limited to keep the values of steep teeth accurate enough.
+const MAX_HELIX_ANGLE: Float = 60.;
+
+// Metric hex bolt, nut and washer sizes, after ISO 4017, ISO 4032 and ISO 7089.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetricSize {
+    // The nominal diameter, e.g. 8 for M8.
+    pub diameter: Float,
+    pub across_flats: Float,
+    pub head_height: Float,
+    pub nut_height: Float,
+    pub washer_hole: Float,
+    pub washer_diameter: Float,
+    pub washer_thickness: Float,
+}
+
+const fn metric(
+    diameter: Float,
+    across_flats: Float,
+    head_height: Float,
+    nut_height: Float,
+    washer: (Float, Float, Float),
+) -> MetricSize {
+    MetricSize {
+        diameter,
+        across_flats,
+        head_height,
+        nut_height,
+        washer_hole: washer.0,
+        washer_diameter: washer.1,
+        washer_thickness: washer.2,
+    }
+}
+
+pub const METRIC_SIZES: [MetricSize; 9] = [
+    metric(3., 5.5, 2., 2.4, (3.2, 7., 0.5)),
+    metric(4., 7., 2.8, 3.2, (4.3, 9., 0.8)),
+    metric(5., 8., 3.5, 4.7, (5.3, 10., 1.)),
+    metric(6., 10., 4., 5.2, (6.4, 12., 1.6)),
+    metric(8., 13., 5.3, 6.8, (8.4, 16., 1.6)),
+    metric(10., 16., 6.4, 8.4, (10.5, 20., 2.)),
+    metric(12., 18., 7.5, 10.8, (13., 24., 2.5)),
+    metric(16., 24., 10., 14.8, (17., 30., 3.)),
+    metric(20., 30., 12.5, 18., (21., 37., 3.)),
+];
+
+pub fn metric_size(
+    function: &'static str,
+    diameter: Float,
+) -> Result<&'static MetricSize, PrimitiveError> {
+    METRIC_SIZES
+        .iter()
+        .find(|size| size.diameter == diameter)
+        .ok_or(PrimitiveError::InvalidParameter {
+            function,
+            parameter: "d",
+            value: diameter,
+            requirement: "a metric size of 3, 4, 5, 6, 8, 10, 12, 16 or 20",
+        })
+}
+
+// Deep groove ball bearings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bearing {
+    pub name: &'static str,
+    pub bore: Float,
+    pub diameter: Float,
+    pub width: Float,
+}
+
+pub const BEARINGS: [Bearing; 10] = [
+    Bearing {
+        name: "623",
+        bore: 3.,
+        diameter: 10.,
+        width: 4.,
+    },
+    Bearing {
+        name: "625",
+        bore: 5.,
+        diameter: 16.,
+        width: 5.,
+    },
+    Bearing {
+        name: "626",
+        bore: 6.,
+        diameter: 19.,
+        width: 6.,
+    },
+    Bearing {
+        name: "608",
+        bore: 8.,
+        diameter: 22.,
+        width: 7.,
+    },
+    Bearing {
+        name: "688",
+        bore: 8.,
+        diameter: 16.,
+        width: 5.,
+    },
+    Bearing {
+        name: "6000",
+        bore: 10.,
+        diameter: 26.,
+        width: 8.,
+    },
+    Bearing {
+        name: "6001",
+        bore: 12.,
+        diameter: 28.,
+        width: 8.,
+    },
+    Bearing {
+        name: "6200",
+        bore: 10.,
+        diameter: 30.,
+        width: 9.,
+    },
+    Bearing {
+        name: "6201",
+        bore: 12.,
+        diameter: 32.,
+        width: 10.,
+    },
+    Bearing {
+        name: "6202",
+        bore: 15.,
+        diameter: 35.,
+        width: 11.,
+    },
+];
+
+// Regular hexagonal prism with the given width across flats, from z = 0 to height.
+pub fn hex_prism(across_flats: Float, height: Float) -> Result<LObject, PrimitiveError> {
+    // The planes of the sides have infinite bounding boxes, the cylinder through the corners
+    // bounds the prism without changing it.
+    let corner = across_flats / Float::sqrt(3.);
+    let mut sides = vec![LObject::new(
+        Box::new(Cylinder::new(corner)),
+        Node::Cylinder { radius: corner },
+    )];
+    for i in 0..6 {
+        let angle = i as Float * PI / 3.;
+        let normal = na::Vector3::new(angle.cos(), angle.sin(), 0.);
+        let p = across_flats / 2.;
+        sides.push(LObject::new(
+            Box::new(NormalPlane::from_normal_and_p(normal, p)),
+            Node::Plane { normal, p },
+        ));
+    }
+    sides.extend(slab(0., height));
+    boolean(&sides).intersection(0.)
+}
+
+// Cylinder of radius from z = bottom to top.
+fn rod(radius: Float, bottom: Float, top: Float) -> Result<LObject, PrimitiveError> {
+    Ok(LObject::cylinder(top - bottom, radius, radius, 0.)?.translate(0., 0., (top + bottom) / 2.))
+}
+
+// The planes bounding z to bottom..top.
+pub fn slab(bottom: Float, top: Float) -> Vec<LObject> {
+    vec![
+        LObject::new(
+            Box::new(PlaneZ::new(top)),
+            Node::Plane {
+                normal: na::Vector3::z(),
+                p: top,
+            },
+        ),
+        LObject::new(
+            Box::new(PlaneNegZ::new(-bottom)),
+            Node::Plane {
+                normal: -na::Vector3::z(),
+                p: -bottom,
+            },
+        ),
+    ]
+}
+
+// The objects in an LObjectVector, for booleans.
+pub fn boolean(objects: &[LObject]) -> LObjectVector {
+    let mut v = LObjectVector::new(&objects[0]);
+    for o in &objects[1..] {
+        v.push(o);
+    }
+    v
+}
+
+// HexBolt(d, length): the head from z = 0 up, the shaft of the nominal diameter down to -length.
+pub fn hex_bolt(diameter: Float, length: Float) -> Result<LObject, PrimitiveError> {
+    let size = metric_size("HexBolt", diameter)?;
+    let length = error::positive("HexBolt", "length", length)?;
+    boolean(&[
+        hex_prism(size.across_flats, size.head_height)?,
+        rod(size.diameter / 2., -length, 0.)?,
+    ])
+    .union(0.)
+}
+
+// Nut(d): from z = 0 up, with a hole of the nominal diameter (the threads are not modeled).
+pub fn nut(diameter: Float) -> Result<LObject, PrimitiveError> {
+    let size = metric_size("Nut", diameter)?;
+    boolean(&[
+        hex_prism(size.across_flats, size.nut_height)?,
+        rod(size.diameter / 2., -1., size.nut_height + 1.)?,
+    ])
+    .difference(0.)
+}
+
+// Washer(d): from z = 0 up.
+pub fn washer(diameter: Float) -> Result<LObject, PrimitiveError> {
+    let size = metric_size("Washer", diameter)?;
+    boolean(&[
+        rod(size.washer_diameter / 2., 0., size.washer_thickness)?,
+        rod(size.washer_hole / 2., -1., size.washer_thickness + 1.)?,
+    ])
+    .difference(0.)
+}
+
+// BearingPocket(name, clearance): the space to subtract for a bearing pressed in from z = 0
+// down, wider by clearance all around, with a relief below it half way between the races, so
+// that the inner race can turn freely.
+pub fn bearing_pocket(name: &str, clearance: Float) -> Result<LObject, PrimitiveError> {
+    let bearing = BEARINGS
+        .iter()
+        .find(|bearing| bearing.name == name)
+        .ok_or_else(|| PrimitiveError::UnknownName {
+            function: "BearingPocket",
+            name: name.to_string(),
+            expected: "one of 623, 625, 626, 608, 688, 6000, 6001, 6200, 6201 or 6202",
+        })?;
+    if !(clearance >= 0. && clearance.is_finite()) {
+        return Err(PrimitiveError::InvalidParameter {
+            function: "BearingPocket",
+            parameter: "clearance",
+            value: clearance,
+            requirement: "at least 0 and finite",
+        });
+    }
+    let relief = (bearing.bore + bearing.diameter) / 4.;
+    boolean(&[
+        rod(bearing.diameter / 2. + clearance, -bearing.width, 0.)?,
+        rod(relief, -bearing.width * 2., -bearing.width)?,
+    ])
+    .union(0.)
+}
+
+// Gear(module, teeth, thickness, pressure_angle): involute spur gear around the z axis, from
+// -thickness / 2 to thickness / 2, with the pressure angle in degrees (20 by default).
+pub fn gear(
+    module: Float,
+    teeth: Float,
+    thickness: Float,
+    pressure_angle: Float,
+) -> Result<LObject, PrimitiveError> {
+    new_gear("Gear", module, teeth, thickness, pressure_angle, 0.)
+}
+
+// InvoluteGear(module, teeth, thickness, pressure_angle, helix_angle): like Gear, with the teeth
+// turning along z by the helix angle in degrees at the pitch circle. module is the transverse
+// module, i.e. in the xy plane.
+pub fn involute_gear(
+    module: Float,
+    teeth: Float,
+    thickness: Float,
+    pressure_angle: Float,
+    helix_angle: Float,
+) -> Result<LObject, PrimitiveError> {
+    new_gear(
+        "InvoluteGear",
+        module,
+        teeth,
+        thickness,
+        pressure_angle,
+        helix_angle,
+    )
+}
+
+fn new_gear(
+    function: &'static str,
+    module: Float,
+    teeth: Float,
+    thickness: Float,
+    pressure_angle: Float,
+    helix_angle: Float,
+) -> Result<LObject, PrimitiveError> {
+    let module = error::positive(function, "module", module)?;
+    let thickness = error::positive(function, "thickness", thickness)?;
+    if !(teeth >= MIN_TEETH && teeth.fract() == 0. && teeth.is_finite()) {
+        return Err(PrimitiveError::InvalidParameter {
+            function,
+            parameter: "teeth",
+            value: teeth,
+            requirement: "a whole number of at least 3",
+        });
+    }
+    if !(pressure_angle > 0. && pressure_angle < 45.) {
+        return Err(PrimitiveError::InvalidParameter {
+            function,
+            parameter: "pressure_angle",
+            value: pressure_angle,
+            requirement: "between 0 and 45 degrees",
+        });
+    }
+    if helix_angle.is_nan() || helix_angle.abs() >= MAX_HELIX_ANGLE {
+        return Err(PrimitiveError::InvalidParameter {
+            function,
+            parameter: "helix_angle",
+            value: helix_angle,
+            requirement: "between -60 and 60 degrees",
+        });
+    }
+    let pressure_angle = pressure_angle.to_radians();
+    // The teeth move along the pitch circle by tan(helix_angle) per unit of z.
+    let twist = helix_angle.to_radians().tan() * 2. / (module * teeth);
+    let profile = LObject::new(
+        GearProfile::new(module, teeth as usize, pressure_angle, twist),
+        Node::Gear {
+            module,
+            teeth: teeth as usize,
+            pressure_angle,
+            twist,
+        },
+    );
+    let mut objects = vec![profile];
+    objects.extend(slab(-thickness / 2., thickness / 2.));
+    boolean(&objects).intersection(0.)
+}
+
+// The cross section of an involute gear, infinite along z and turned by twist radians per unit
+// of z for helical gears. The value is the distance to a polygon along the outline of the
+// teeth, computed for one half tooth: points are folded into the sector from the middle of a
+// tooth (on the x axis for the first one at z = 0) to the middle of the next gap, since the
+// gear is symmetric to the lines bounding it.
+#[derive(Clone, Debug)]
+pub struct GearProfile {
+    // From the tip on the x axis to the root in the middle of the gap.
+    outline: Vec<na::Point2<Float>>,
+    // The angle from one tooth to the next.
+    pitch_angle: Float,
+    tip_radius: Float,
+    twist: Float,
+    // Turning the cross section along z stretches distances by up to this factor, at the tip
+    // circle. Values are divided by it to remain lower bounds, like those of Twister.
+    stretch: Float,
+    // Step of the finite differences of normals.
+    epsilon: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl GearProfile {
+    pub fn new(
+        module: Float,
+        teeth: usize,
+        pressure_angle: Float,
+        twist: Float,
+    ) -> Box<GearProfile> {
+        let z = teeth as Float;
+        let pitch_radius = module * z / 2.;
+        let base_radius = pitch_radius * pressure_angle.cos();
+        let tip_radius = pitch_radius + module;
+        let root_radius = pitch_radius - 1.25 * module;
+        let half = PI / z;
+        // The teeth are half the pitch wide on the pitch circle. The involute of the base
+        // circle turns by inv(a) = tan(a) - a up to the radius where its pressure angle is a.
+        let involute = |a: Float| a.tan() - a;
+        let base_angle = half / 2. + involute(pressure_angle);
+        let flank =
+            |r: Float| (base_angle - involute((base_radius / r).min(1.).acos())).clamp(0., half);
+        let polar = |r: Float, angle: Float| na::Point2::new(r * angle.cos(), r * angle.sin());
+        let mut outline = Vec::new();
+        let tip = flank(tip_radius);
+        for i in 0..=ARC_SEGMENTS {
+            outline.push(polar(tip_radius, tip * i as Float / ARC_SEGMENTS as Float));
+        }
+        let low = base_radius.max(root_radius);
+        for i in 1..=FLANK_SEGMENTS {
+            let r = tip_radius + (low - tip_radius) * i as Float / FLANK_SEGMENTS as Float;
+            outline.push(polar(r, flank(r)));
+        }
+        // Below the base circle, the flank goes on radially.
+        let root = flank(low);
+        if root_radius < base_radius {
+            outline.push(polar(root_radius, root));
+        }
+        for i in 1..=ARC_SEGMENTS {
+            outline.push(polar(
+                root_radius,
+                root + (half - root) * i as Float / ARC_SEGMENTS as Float,
+            ));
+        }
+        Box::new(GearProfile {
+            outline,
+            pitch_angle: 2. * half,
+            tip_radius,
+            twist,
+            stretch: (1. + (tip_radius * twist).powi(2)).sqrt(),
+            epsilon: module * RELATIVE_STEP,
+            bbox: BoundingBox::new(
+                &na::Point3::new(-tip_radius, -tip_radius, Float::NEG_INFINITY),
+                &na::Point3::new(tip_radius, tip_radius, Float::INFINITY),
+            ),
+        })
+    }
+    // The signed distance of the point to the outline, in the cross section at its z.
+    fn value(&self, p: &na::Point3<Float>) -> Float {
+        let r = p.x.hypot(p.y);
+        let mut angle = (p.y.atan2(p.x) - p.z * self.twist).rem_euclid(self.pitch_angle);
+        if angle > self.pitch_angle / 2. {
+            angle = self.pitch_angle - angle;
+        }
+        let direction = na::Vector2::new(angle.cos(), angle.sin());
+        let p = na::Point2::from(direction * r);
+        let cross = |a: &na::Vector2<Float>, b: &na::Vector2<Float>| a.x * b.y - a.y * b.x;
+        let mut distance = Float::INFINITY;
+        // The outline is star-shaped around the origin, so the point is inside if it is closer
+        // to the origin than the segment which the ray through it crosses. That is the segment
+        // the direction is most between the ends of, which tolerates rounding at its ends.
+        let mut crossed = (Float::NEG_INFINITY, r);
+        for w in self.outline.windows(2) {
+            let (a, b) = (w[0].coords, w[1].coords);
+            let ab = b - a;
+            let t = ((p.coords - a).dot(&ab) / ab.norm_squared()).clamp(0., 1.);
+            distance = distance.min((p.coords - (a + ab * t)).norm());
+            let between = (cross(&a, &direction) / a.norm()).min(cross(&direction, &b) / b.norm());
+            let denominator = cross(&direction, &ab);
+            if between > crossed.0 && denominator != 0. {
+                crossed = (between, cross(&a, &b) / denominator);
+            }
+        }
+        if r < crossed.1 {
+            -distance / self.stretch
+        } else {
+            distance / self.stretch
+        }
+    }
+}
+
+impl Object<Float> for GearProfile {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        // The distance to the tip circle is at most the distance to the teeth.
+        let approx = p.x.hypot(p.y) - self.tip_radius;
+        if approx > slack {
+            approx
+        } else {
+            self.value(p)
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, _: &PrimitiveParameters<Float>) {}
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.epsilon;
+        let difference = |d: na::Vector3<Float>| self.value(&(p + d)) - self.value(&(p - d));
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            difference(na::Vector3::z() * e),
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "HexBolt",
+        args: Some("d, length"),
+        doc: "ISO metric hex bolt of nominal diameter d, the head from z = 0 up, the shaft down to -length.",
+    },
+    Function {
+        name: "Nut",
+        args: Some("d"),
+        doc: "Hex nut for a bolt of nominal diameter d, from z = 0 up.",
+    },
+    Function {
+        name: "Washer",
+        args: Some("d"),
+        doc: "Washer for a bolt of nominal diameter d, from z = 0 up.",
+    },
+    Function {
+        name: "BearingPocket",
+        args: Some("name, clearance"),
+        doc: "The space to subtract for a bearing like \"608\" pressed in from z = 0 down, wider by the optional clearance.",
+    },
+    Function {
+        name: "Gear",
+        args: Some("module, teeth, thickness, pressure_angle"),
+        doc: "Involute spur gear around the z axis. The pressure angle is in degrees, 20 by default.",
+    },
+    Function {
+        name: "InvoluteGear",
+        args: Some("module, teeth, thickness, pressure_angle, helix_angle"),
+        doc: "Like Gear, with helical teeth turning by the helix angle in degrees, 0 by default.",
+    },
+];
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    {
+        let mut env = lua.get::<hlua::LuaTable<_>, _>(env_name).unwrap();
+        env.set("HexBolt", hlua::function2(hex_bolt));
+        env.set("Nut", hlua::function1(nut));
+        env.set("Washer", hlua::function1(washer));
+        // The clearance is optional.
+        env.set(
+            "BearingPocket",
+            hlua::function2(|name: String, clearance: hlua::AnyLuaValue| {
+                let clearance = match clearance {
+                    hlua::AnyLuaValue::LuaNumber(c) => c as Float,
+                    _ => 0.,
+                };
+                bearing_pocket(&name, clearance)
+            }),
+        );
+        // The pressure angle is optional.
+        env.set(
+            "Gear",
+            hlua::function4(
+                |module: Float,
+                 teeth: Float,
+                 thickness: Float,
+                 pressure_angle: hlua::AnyLuaValue| {
+                    let pressure_angle = match pressure_angle {
+                        hlua::AnyLuaValue::LuaNumber(a) => a as Float,
+                        _ => 20.,
+                    };
+                    gear(module, teeth, thickness, pressure_angle)
+                },
+            ),
+        );
+    }
+    // hlua reads missing arguments from the wrong places if more than the last one is
+    // optional, so the defaults of the angles are filled in by Lua.
+    lua.set("__InvoluteGear", hlua::function5(involute_gear));
+    lua.execute::<()>(&format!(
+        "
+        function InvoluteGear(module, teeth, thickness, pressure_angle, helix_angle)
+          return __InvoluteGear(module, teeth, thickness, pressure_angle or 20, helix_angle or 0)
+        end
+        {env}.InvoluteGear = InvoluteGear
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
@@ -0,0 +1,285 @@
+use super::Float;
+use api::{self, Function};
+use error::PrimitiveError;
+use hlua;
+use implicit3d::Object;
+use lobject::LObject;
+use std::sync::{Arc, Mutex};
+use tree::Node;
+
+// The version of this interface. Plugins built against another version are not loaded.
+pub const VERSION: u32 = 1;
+
+// A plugin adding primitives, which scripts build with the functions it lists. Each function
+// takes numbers and returns an object, which is tracked in the tree as Node::Plugin, so
+// primitives of plugins are evaluated as they are, neither flattened nor compiled.
+//
+// Providers are compiled in (e.g. behind a feature of the application) and registered with
+// register before scripts are evaluated, or built as dynamic libraries declaring them with
+// declare_plugin! and loaded with load (with the dynamic-plugins feature). Dynamic libraries
+// use the Rust ABI, so they must be built with the same compiler as the application.
+pub trait ObjectProvider: Send + Sync {
+    // The name of the plugin, e.g. its crate. Registering another provider with the same name
+    // replaces this one.
+    fn name(&self) -> &'static str;
+    // The functions scripts can call, like Name(a, b), which are listed in the reference like
+    // the others, see api::groups. Their names must not be taken by other functions. Methods,
+    // variables and functions of tables are not supported.
+    fn api(&self) -> &'static [Function];
+    // Build the object of the function called function (from api) with the arguments the script
+    // passed, or say what is wrong with them.
+    fn build(&self, function: &str, arguments: &[Float]) -> Result<Box<dyn Object<Float>>, String>;
+}
+
+static PROVIDERS: Mutex<Vec<Arc<dyn ObjectProvider>>> = Mutex::new(Vec::new());
+
+// Make the functions of provider available to scripts evaluated from now on.
+pub fn register(provider: Box<dyn ObjectProvider>) -> Result<(), String> {
+    let mut providers = PROVIDERS.lock().unwrap();
+    for function in provider.api() {
+        let name = function.name;
+        if function.args.map_or(true, |a| a.starts_with('{')) || function.is_method() {
+            return Err(format!(
+                "{}: {} is not a function of numbers",
+                provider.name(),
+                name
+            ));
+        }
+        if name.is_empty()
+            || name.starts_with(|c: char| c.is_ascii_digit())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!("{}: {} is not a valid name", provider.name(), name));
+        }
+        let others = providers
+            .iter()
+            .filter(|p| p.name() != provider.name())
+            .map(|p| (p.name(), p.api()));
+        let taken = api::builtin_groups()
+            .into_iter()
+            .chain(others)
+            .any(|(_, functions)| functions.iter().any(|f| f.name == name));
+        if taken {
+            return Err(format!("{}: {} is already defined", provider.name(), name));
+        }
+    }
+    providers.retain(|p| p.name() != provider.name());
+    providers.push(Arc::from(provider));
+    Ok(())
+}
+
+// The registered providers, in the order they were registered.
+pub fn providers() -> Vec<Arc<dyn ObjectProvider>> {
+    PROVIDERS.lock().unwrap().clone()
+}
+
+// The arguments of a call of a function of a plugin. Its Lua function pumps them in one by one,
+// since hlua can not read tables. The field is always set, it is only an Option since hlua
+// requires all-zero to be a valid value.
+pub struct Arguments {
+    // None for arguments which are not numbers.
+    values: Option<Vec<Option<Float>>>,
+}
+
+implement_lua_push!(Arguments, |mut metatable| {
+    let mut index = metatable.empty_array("__index");
+    index.set(
+        "push",
+        ::hlua::function2(|a: &mut Arguments, value: hlua::AnyLuaValue| a.push(value)),
+    );
+});
+
+implement_lua_read!(Arguments);
+
+impl Arguments {
+    fn new() -> Arguments {
+        Arguments {
+            values: Some(Vec::new()),
+        }
+    }
+    fn push(&mut self, value: hlua::AnyLuaValue) {
+        let value = match value {
+            hlua::AnyLuaValue::LuaNumber(x) => Some(x as Float),
+            _ => None,
+        };
+        self.values.as_mut().unwrap().push(value);
+    }
+    // The arguments, None if not all of them are numbers.
+    fn numbers(&self) -> Option<Vec<Float>> {
+        self.values.as_ref().unwrap().iter().cloned().collect()
+    }
+}
+
+fn build(provider: &str, function: &str, arguments: &Arguments) -> Result<LObject, PrimitiveError> {
+    let error = |message: String| PrimitiveError::Plugin {
+        function: function.to_string(),
+        message,
+    };
+    let provider = providers()
+        .into_iter()
+        .find(|p| p.name() == provider)
+        .ok_or_else(|| error(format!("the plugin {} is not loaded", provider)))?;
+    let arguments = arguments
+        .numbers()
+        .ok_or_else(|| error("all arguments must be numbers".to_string()))?;
+    let object = provider.build(function, &arguments).map_err(error)?;
+    Ok(LObject::new(
+        object,
+        Node::Plugin {
+            provider: provider.name().to_string(),
+            function: function.to_string(),
+            arguments,
+        },
+    ))
+}
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.set("__plugin_arguments", hlua::function0(Arguments::new));
+    lua.set(
+        "__plugin",
+        hlua::function3(
+            |provider: String, function: String, arguments: &Arguments| {
+                build(&provider, &function, arguments)
+            },
+        ),
+    );
+    for provider in providers() {
+        for function in provider.api() {
+            lua.execute::<()>(&format!(
+                "
+                function {name}(...)
+                  local arguments = __plugin_arguments()
+                  for i = 1, select(\"#\", ...) do
+                    arguments:push((select(i, ...)))
+                  end
+                  return __plugin({provider:?}, {name:?}, arguments)
+                end
+                {env}.{name} = {name}
+                ",
+                name = function.name,
+                provider = provider.name(),
+                env = env_name
+            ))
+            .unwrap();
+        }
+    }
+}
+
+// Declare the providers of a dynamic library, for load:
+//
+//     declare_plugin!(MyProvider, OtherProvider::new());
+#[macro_export]
+macro_rules! declare_plugin {
+    ($($provider:expr),*) => {
+        #[no_mangle]
+        pub static TRUESCAD_PLUGIN_VERSION: u32 = $crate::plugin::VERSION;
+        #[no_mangle]
+        pub fn truescad_providers() -> Vec<Box<dyn $crate::plugin::ObjectProvider>> {
+            vec![$(Box::new($provider)),*]
+        }
+    };
+}
+
+// Register the providers of the dynamic library at path, see declare_plugin!.
+#[cfg(feature = "dynamic-plugins")]
+pub fn load(path: &::std::path::Path) -> Result<(), String> {
+    let name = path.to_string_lossy();
+    let error = |e: ::libloading::Error| format!("{}: {}", name, e);
+    // The library runs its code on loading it, which is what plugins are for.
+    unsafe {
+        let library = ::libloading::Library::new(path).map_err(error)?;
+        let version = **library
+            .get::<*const u32>(b"TRUESCAD_PLUGIN_VERSION\0")
+            .map_err(error)?;
+        if version != VERSION {
+            return Err(format!(
+                "{}: built for version {} of the plugin interface instead of {}",
+                name, version, VERSION
+            ));
+        }
+        let providers = library
+            .get::<fn() -> Vec<Box<dyn ObjectProvider>>>(b"truescad_providers\0")
+            .map_err(error)?;
+        for provider in providers() {
+            register(provider)?;
+        }
+        // The functions and objects of the providers live in the library, which therefore stays
+        // loaded.
+        ::std::mem::forget(library);
+    }
+    Ok(())
+}
+
+// Load the dynamic libraries in dir, see load. Returns what went wrong with those which could not
+// be loaded.
+#[cfg(feature = "dynamic-plugins")]
+pub fn load_dir(dir: &::std::path::Path) -> Vec<String> {
+    let entries = match ::std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Having no plugins is fine.
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |e| e == ::std::env::consts::DLL_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+    paths.iter().filter_map(|path| load(path).err()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use implicit3d::Sphere;
+    use luascad::ScriptError;
+    use nalgebra as na;
+
+    struct Balls;
+
+    impl ObjectProvider for Balls {
+        fn name(&self) -> &'static str {
+            "balls"
+        }
+        fn api(&self) -> &'static [Function] {
+            &[Function {
+                name: "PluginBall",
+                args: Some("radius"),
+                doc: "A sphere of radius.",
+            }]
+        }
+        fn build(&self, _: &str, arguments: &[Float]) -> Result<Box<dyn Object<Float>>, String> {
+            match *arguments {
+                [radius] if radius > 0. => Ok(Box::new(Sphere::new(radius))),
+                _ => Err("takes a positive radius".to_string()),
+            }
+        }
+    }
+
+    // The error of script, which must fail.
+    fn error(script: &str) -> String {
+        match ::luascad::eval(script) {
+            Ok(_) => panic!("{} did not fail", script),
+            Err(e) => ScriptError::from(&e).message,
+        }
+    }
+
+    #[test]
+    fn scripts_call_registered_functions() {
+        register(Box::new(Balls)).unwrap();
+        let (_, object) = ::luascad::eval("build(PluginBall(2):translate(1, 0, 0))").unwrap();
+        let object = object.unwrap();
+        let value = object.approx_value(&na::Point3::new(1., 0., 0.), 0.);
+        assert!((value + 2.).abs() < 1e-6, "{}", value);
+        assert_eq!(
+            error("build(PluginBall(\"large\"))"),
+            "PluginBall: all arguments must be numbers"
+        );
+        assert_eq!(
+            error("build(PluginBall(1, 2))"),
+            "PluginBall: takes a positive radius"
+        );
+    }
+}
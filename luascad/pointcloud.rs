@@ -0,0 +1,601 @@
+use super::Float;
+use api::Function;
+use error::{self, PrimitiveError};
+use hlua;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use include::Includes;
+use lobject::LObject;
+use nalgebra as na;
+use normals::{self, NormalEstimate};
+use std::collections::VecDeque;
+use tree::Node;
+
+// The functions export_factories defines, see api.
+pub const API: &[Function] = &[Function {
+    name: "PointCloud",
+    args: Some("filename, radius"),
+    doc: "The surface through the points of a PLY or XYZ file next to the script, e.g. of a 3D scan, as a solid for booleans with other objects. radius (automatic by default) is how far each point reaches: larger values smooth out noise and gaps, smaller ones keep details. Points without normals get them from their neighbors, which only works if the points enclose a solid.",
+}];
+
+// Points within this many radii count for the value, see PointCloud.
+const SUPPORT: Float = 3.;
+// Number of neighbors normals are estimated from.
+const NEIGHBORS: usize = 12;
+// The automatic radius is this many times the mean distance of points to their nearest
+// neighbor, measured on at most SAMPLES points.
+const SPACINGS: Float = 2.;
+const SAMPLES: usize = 1000;
+
+// A surface reconstructed from points with normals pointing out of it, by implicit moving least
+// squares: the value is the mean of the distances to the tangent planes of the points near it,
+// weighted by a gaussian of the distance to them with the radius. Further than SUPPORT radii
+// from all points, it is the distance to the nearest point, with the sign of its plane.
+//
+// The points are stored in the order of an implicit k-d tree: the middle point of each range
+// splits it along the axis of its depth (x, y, z, x, ...).
+#[derive(Clone, Debug)]
+pub struct PointCloud {
+    points: Vec<na::Point3<Float>>,
+    normals: Vec<na::Vector3<Float>>,
+    radius: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl PointCloud {
+    // The surface through points, with normals estimated from the neighbors unless they are
+    // given, and the radius from the spacing of the points if it is 0.
+    pub fn new(
+        mut points: Vec<na::Point3<Float>>,
+        normals: Option<Vec<na::Vector3<Float>>>,
+        radius: Float,
+    ) -> Box<PointCloud> {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        build(&points, &mut order, 0);
+        let normals: Option<Vec<na::Vector3<Float>>> =
+            normals.map(|n| order.iter().map(|&i| n[i]).collect());
+        points = order.iter().map(|&i| points[i]).collect();
+        let mut min = points[0];
+        let mut max = points[0];
+        for p in &points {
+            min = na::Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = na::Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        let mut cloud = PointCloud {
+            points,
+            normals: Vec::new(),
+            radius,
+            bbox: BoundingBox::new(&min, &max),
+        };
+        if cloud.radius == 0. {
+            cloud.radius = SPACINGS * cloud.spacing();
+        }
+        cloud.normals = match normals {
+            Some(normals) => normals
+                .iter()
+                .map(|n| n.try_normalize(0.).unwrap_or_else(na::Vector3::z))
+                .collect(),
+            None => cloud.estimate_normals(),
+        };
+        cloud.bbox.dilate(cloud.radius);
+        Box::new(cloud)
+    }
+    pub fn radius(&self) -> Float {
+        self.radius
+    }
+    fn value(&self, p: &na::Point3<Float>) -> Float {
+        let mut sum = 0.;
+        let mut weights = 0.;
+        let factor = -1. / (self.radius * self.radius);
+        self.within(p, SUPPORT * self.radius, &mut |i| {
+            let d = p - self.points[i];
+            let w = (d.norm_squared() * factor).exp();
+            sum += w * d.dot(&self.normals[i]);
+            weights += w;
+        });
+        if weights > 0. {
+            return sum / weights;
+        }
+        let i = self.nearest(p, 1)[0];
+        let d = p - self.points[i];
+        if d.dot(&self.normals[i]) < 0. {
+            -d.norm()
+        } else {
+            d.norm()
+        }
+    }
+    // The mean distance of points to their nearest neighbor.
+    fn spacing(&self) -> Float {
+        let step = (self.points.len() / SAMPLES).max(1);
+        let distances: Vec<Float> = self
+            .points
+            .iter()
+            .step_by(step)
+            .filter_map(|p| {
+                let nearest = self.nearest(p, 2);
+                nearest.get(1).map(|&i| (self.points[i] - p).norm())
+            })
+            .collect();
+        let mean = distances.iter().sum::<Float>() / distances.len().max(1) as Float;
+        if mean > 0. {
+            mean
+        } else {
+            1.
+        }
+    }
+    // The normal of each point, perpendicular to the plane fitted through its neighbors,
+    // turned like those of its neighbors, starting outwards at the point farthest from the
+    // center of each group of points.
+    fn estimate_normals(&self) -> Vec<na::Vector3<Float>> {
+        let neighbors: Vec<Vec<usize>> = self
+            .points
+            .iter()
+            .map(|p| self.nearest(p, NEIGHBORS))
+            .collect();
+        let mut normals: Vec<na::Vector3<Float>> = neighbors
+            .iter()
+            .map(|near| {
+                let n = near.len() as Float;
+                let center = near
+                    .iter()
+                    .fold(na::Vector3::zeros(), |c, &i| c + self.points[i].coords)
+                    / n;
+                let covariance = near.iter().fold(na::Matrix3::zeros(), |m, &i| {
+                    let d = self.points[i].coords - center;
+                    m + d * d.transpose()
+                });
+                // The direction in which the neighbors vary least.
+                let eigen = covariance.symmetric_eigen();
+                let mut least = 0;
+                for i in 1..3 {
+                    if eigen.eigenvalues[i] < eigen.eigenvalues[least] {
+                        least = i;
+                    }
+                }
+                eigen.eigenvectors.column(least).into_owned()
+            })
+            .collect();
+        let center = self
+            .points
+            .iter()
+            .fold(na::Vector3::zeros(), |c, p| c + p.coords)
+            / self.points.len() as Float;
+        let mut visited = vec![false; self.points.len()];
+        let mut queue = VecDeque::new();
+        loop {
+            let start = (0..self.points.len())
+                .filter(|&i| !visited[i])
+                .max_by(|&a, &b| {
+                    let distance = |i: usize| (self.points[i].coords - center).norm_squared();
+                    distance(a).partial_cmp(&distance(b)).unwrap()
+                });
+            let start = match start {
+                Some(start) => start,
+                None => break,
+            };
+            if normals[start].dot(&(self.points[start].coords - center)) < 0. {
+                normals[start] = -normals[start];
+            }
+            visited[start] = true;
+            queue.push_back(start);
+            while let Some(i) = queue.pop_front() {
+                for &j in &neighbors[i] {
+                    if !visited[j] {
+                        if normals[j].dot(&normals[i]) < 0. {
+                            normals[j] = -normals[j];
+                        }
+                        visited[j] = true;
+                        queue.push_back(j);
+                    }
+                }
+            }
+        }
+        normals
+    }
+    // Call f with each point within radius of p.
+    fn within<F: FnMut(usize)>(&self, p: &na::Point3<Float>, radius: Float, f: &mut F) {
+        self.within_in(0, self.points.len(), 0, p, radius, f)
+    }
+    fn within_in<F: FnMut(usize)>(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        p: &na::Point3<Float>,
+        radius: Float,
+        f: &mut F,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let q = &self.points[middle];
+        if (q - p).norm_squared() <= radius * radius {
+            f(middle);
+        }
+        let axis = depth % 3;
+        if p[axis] - radius <= q[axis] {
+            self.within_in(start, middle, depth + 1, p, radius, f);
+        }
+        if p[axis] + radius >= q[axis] {
+            self.within_in(middle + 1, end, depth + 1, p, radius, f);
+        }
+    }
+    // The k points nearest to p, the nearest first.
+    fn nearest(&self, p: &na::Point3<Float>, k: usize) -> Vec<usize> {
+        let mut found = Vec::with_capacity(k + 1);
+        self.nearest_in(0, self.points.len(), 0, p, k, &mut found);
+        found.into_iter().map(|(_, i)| i).collect()
+    }
+    // Add the points in start..end to found, the k nearest pairs of squared distance and point.
+    fn nearest_in(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        p: &na::Point3<Float>,
+        k: usize,
+        found: &mut Vec<(Float, usize)>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let q = &self.points[middle];
+        let distance = (q - p).norm_squared();
+        if found.len() < k || distance < found[found.len() - 1].0 {
+            let at = found
+                .iter()
+                .position(|&(d, _)| d > distance)
+                .unwrap_or(found.len());
+            found.insert(at, (distance, middle));
+            found.truncate(k);
+        }
+        let axis = depth % 3;
+        let offset = p[axis] - q[axis];
+        let (near, far) = if offset < 0. {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.nearest_in(near.0, near.1, depth + 1, p, k, found);
+        if found.len() < k || offset * offset < found[found.len() - 1].0 {
+            self.nearest_in(far.0, far.1, depth + 1, p, k, found);
+        }
+    }
+}
+
+// Order the points of order as an implicit k-d tree, see PointCloud.
+fn build(points: &[na::Point3<Float>], order: &mut [usize], depth: usize) {
+    if order.len() < 2 {
+        return;
+    }
+    let axis = depth % 3;
+    let middle = order.len() / 2;
+    order.select_nth_unstable_by(middle, |&a, &b| {
+        points[a][axis].partial_cmp(&points[b][axis]).unwrap()
+    });
+    let (before, after) = order.split_at_mut(middle);
+    build(points, before, depth + 1);
+    build(points, &mut after[1..], depth + 1);
+}
+
+impl Object<Float> for PointCloud {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx > slack {
+            approx
+        } else {
+            self.value(p)
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, _: &PrimitiveParameters<Float>) {}
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        normals::estimate(
+            NormalEstimate::Central,
+            |q| self.value(q),
+            p,
+            self.radius * 1e-3,
+        )
+    }
+}
+
+// The points of a file and their normals, if it has them for all points.
+type Points = (Vec<na::Point3<Float>>, Option<Vec<na::Vector3<Float>>>);
+
+// The points of an XYZ file: a line of numbers separated by spaces or commas for each point, x,
+// y and z, optionally followed by the normal, or by anything else like colors, which are
+// ignored. Lines starting with # are comments.
+pub fn parse_xyz(text: &str) -> Result<Points, String> {
+    let mut points = Vec::new();
+    let mut normals = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<Float>().ok().filter(|v| v.is_finite()))
+            .collect::<Option<Vec<Float>>>()
+            .ok_or_else(|| format!("invalid number in line {}", number + 1))?;
+        if values.len() < 3 {
+            return Err(format!("line {} has no x, y and z", number + 1));
+        }
+        points.push(na::Point3::new(values[0], values[1], values[2]));
+        if values.len() >= 6 {
+            normals.push(na::Vector3::new(values[3], values[4], values[5]));
+        }
+    }
+    // Other columns, e.g. colors, are not normals of unit length.
+    let unit = |n: &na::Vector3<Float>| (n.norm() - 1.).abs() < 0.01;
+    if normals.len() == points.len() && normals.iter().all(unit) {
+        Ok((points, Some(normals)))
+    } else {
+        Ok((points, None))
+    }
+}
+
+// A property of an element of a PLY file: its name, its type and the type of the count if it is
+// a list.
+struct Property {
+    name: String,
+    kind: String,
+    count: Option<String>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// The values of the body of a PLY file.
+enum Values<'a> {
+    Ascii(::std::str::SplitWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+impl<'a> Values<'a> {
+    fn next(&mut self, kind: &str) -> Result<Float, String> {
+        match *self {
+            Values::Ascii(ref mut words) => words
+                .next()
+                .ok_or("the PLY file ends early")?
+                .parse()
+                .map_err(|_| "invalid number in the PLY file".to_string()),
+            Values::Binary {
+                data,
+                ref mut position,
+                big_endian,
+            } => {
+                let size = size(kind)?;
+                let bytes = data
+                    .get(*position..*position + size)
+                    .ok_or("the PLY file ends early")?;
+                *position += size;
+                // In little endian order.
+                let mut b = [0u8; 8];
+                b[..size].copy_from_slice(bytes);
+                if big_endian {
+                    b[..size].reverse();
+                }
+                let four = [b[0], b[1], b[2], b[3]];
+                Ok(match kind {
+                    "char" | "int8" => Float::from(b[0] as i8),
+                    "uchar" | "uint8" => Float::from(b[0]),
+                    "short" | "int16" => Float::from(i16::from_le_bytes([b[0], b[1]])),
+                    "ushort" | "uint16" => Float::from(u16::from_le_bytes([b[0], b[1]])),
+                    "int" | "int32" => i32::from_le_bytes(four) as Float,
+                    "uint" | "uint32" => u32::from_le_bytes(four) as Float,
+                    "float" | "float32" => f32::from_le_bytes(four) as Float,
+                    _ => f64::from_le_bytes(b) as Float,
+                })
+            }
+        }
+    }
+}
+
+// The size in bytes of the values of a PLY type.
+fn size(kind: &str) -> Result<usize, String> {
+    match kind {
+        "char" | "int8" | "uchar" | "uint8" => Ok(1),
+        "short" | "int16" | "ushort" | "uint16" => Ok(2),
+        "int" | "int32" | "uint" | "uint32" | "float" | "float32" => Ok(4),
+        "double" | "float64" => Ok(8),
+        _ => Err(format!("unknown PLY type {}", kind)),
+    }
+}
+
+// The vertices of a PLY file, ascii or binary, with their normals (nx, ny, nz) if they have
+// them. Other elements, like faces, are ignored.
+pub fn parse_ply(data: &[u8]) -> Result<Points, String> {
+    let end = b"end_header";
+    let header_end = data
+        .windows(end.len())
+        .position(|w| w == end)
+        .ok_or("the PLY file has no end_header")?;
+    let header = String::from_utf8_lossy(&data[..header_end]);
+    // The body starts after the line of end_header.
+    let body_start = data[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| header_end + i + 1);
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", f, ..] => format = Some(f.to_string()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid count of {}", name))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, kind, name] => {
+                size(count)?;
+                size(kind)?;
+                elements
+                    .last_mut()
+                    .ok_or("property before element")?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        kind: kind.to_string(),
+                        count: Some(count.to_string()),
+                    })
+            }
+            ["property", kind, name] => {
+                size(kind)?;
+                elements
+                    .last_mut()
+                    .ok_or("property before element")?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        kind: kind.to_string(),
+                        count: None,
+                    })
+            }
+            _ => {}
+        }
+    }
+    let body = &data[body_start..];
+    let mut values = match format.as_deref() {
+        Some("ascii") => Values::Ascii(
+            ::std::str::from_utf8(body)
+                .map_err(|_| "the PLY file is not text")?
+                .split_whitespace(),
+        ),
+        Some("binary_little_endian") | Some("binary_big_endian") => Values::Binary {
+            data: body,
+            position: 0,
+            big_endian: format.as_ref().map_or(false, |f| f == "binary_big_endian"),
+        },
+        _ => return Err("unknown PLY format".to_string()),
+    };
+    for element in &elements {
+        let index = |name: &str| element.properties.iter().position(|p| p.name == name);
+        let coordinates = if element.name == "vertex" {
+            match (index("x"), index("y"), index("z")) {
+                (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+                _ => return Err("the PLY vertices have no x, y and z".to_string()),
+            }
+        } else {
+            None
+        };
+        let normal = match (index("nx"), index("ny"), index("nz")) {
+            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+            _ => None,
+        };
+        let mut points = Vec::new();
+        let mut normals = Vec::new();
+        let mut scalars = vec![0.; element.properties.len()];
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.count {
+                    Some(ref count) => {
+                        for _ in 0..values.next(count)? as usize {
+                            values.next(&property.kind)?;
+                        }
+                    }
+                    None => scalars[i] = values.next(&property.kind)?,
+                }
+            }
+            if let Some([x, y, z]) = coordinates {
+                points.push(na::Point3::new(scalars[x], scalars[y], scalars[z]));
+                if let Some([x, y, z]) = normal {
+                    normals.push(na::Vector3::new(scalars[x], scalars[y], scalars[z]));
+                }
+            }
+        }
+        if coordinates.is_some() {
+            let normals = if normal.is_some() {
+                Some(normals)
+            } else {
+                None
+            };
+            return Ok((points, normals));
+        }
+    }
+    Err("the PLY file has no vertices".to_string())
+}
+
+// PointCloud(filename, radius), see API.
+pub fn point_cloud(
+    includes: &Includes,
+    filename: &str,
+    radius: Float,
+) -> Result<LObject, PrimitiveError> {
+    if radius != 0. {
+        error::positive("PointCloud", "radius", radius)?;
+    }
+    let error = |message: String| PrimitiveError::Import {
+        function: "PointCloud",
+        filename: filename.to_string(),
+        message,
+    };
+    let path = includes.path(filename).map_err(error)?;
+    let data = ::std::fs::read(&path).map_err(|e| error(e.to_string()))?;
+    let ply = path
+        .extension()
+        .map_or(false, |e| e.to_string_lossy().eq_ignore_ascii_case("ply"));
+    let (points, normals) = if ply {
+        parse_ply(&data)
+    } else {
+        parse_xyz(&String::from_utf8_lossy(&data))
+    }
+    .map_err(error)?;
+    if points.len() < NEIGHBORS {
+        return Err(error(format!("fewer than {} points", NEIGHBORS)));
+    }
+    if !points
+        .iter()
+        .all(|p| p.coords.iter().all(|c| c.is_finite()))
+    {
+        return Err(error("a point is not finite".to_string()));
+    }
+    let cloud = PointCloud::new(points, normals, radius);
+    let radius = cloud.radius();
+    Ok(LObject::new(
+        cloud,
+        Node::PointCloud {
+            filename: filename.to_string(),
+            radius,
+        },
+    ))
+}
+
+// Define PointCloud in the env, reading files with includes, see Includes::path.
+pub fn export_factories<'lua>(lua: &mut hlua::Lua<'lua>, env_name: &str, includes: &'lua Includes) {
+    lua.set(
+        "__PointCloud",
+        hlua::function2(move |filename: String, radius: Float| {
+            point_cloud(includes, &filename, radius)
+        }),
+    );
+    // The radius is optional, 0 picks it from the spacing of the points.
+    lua.execute::<()>(&format!(
+        "
+        function PointCloud(filename, radius)
+          return __PointCloud(filename, radius or 0)
+        end
+        {env}.PointCloud = PointCloud
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
@@ -1,43 +1,149 @@
+use api::Function;
 use hlua;
+use luascad::SCRIPT_NAME;
+use std::fmt;
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
+// How important a message is, in increasing order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        })
+    }
+}
+
+// A message for the console, e.g. printed by the script.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub level: Level,
+    // Since the start of the evaluation.
+    pub time: Duration,
+    // The line of the script (starting at 1) it came from, if it is known.
+    pub line: Option<usize>,
+    pub text: String,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.level != Level::Info {
+            write!(f, "{}: ", self.level)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        f.write_str(&self.text)
+    }
+}
+
+// Sends messages to a PrintBuffer, e.g. from the functions of the script.
+#[derive(Clone)]
+pub struct Console {
+    tx: mpsc::Sender<Message>,
+    start: Instant,
+}
+
+impl Console {
+    // A message at the current time.
+    pub fn message<S: Into<String>>(&self, level: Level, line: Option<usize>, text: S) -> Message {
+        Message {
+            level,
+            time: self.start.elapsed(),
+            line,
+            text: text.into(),
+        }
+    }
+    pub fn send<S: Into<String>>(&self, level: Level, text: S) {
+        // The PrintBuffer is only gone after the script has run.
+        let _ = self.tx.send(self.message(level, None, text));
+    }
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "print",
+        args: Some("..."),
+        doc: "Print the values to the console, separated by tabs.",
+    },
+    Function {
+        name: "warn",
+        args: Some("..."),
+        doc: "Like print, as a warning.",
+    },
+];
+
+// Collects the messages of print and warn, which the script calls, and of Rust functions the
+// script calls (see get_console).
 pub struct PrintBuffer {
-    rx: mpsc::Receiver<String>,
-    tx: mpsc::Sender<String>,
+    rx: mpsc::Receiver<Message>,
+    console: Console,
 }
 
 impl PrintBuffer {
     pub fn new_and_expose_to_lua(lua: &mut hlua::Lua, env_name: &str) -> PrintBuffer {
-        let (tx, rx): (mpsc::Sender<String>, mpsc::Receiver<String>) = mpsc::channel();
-        let lua_tx = tx.clone();
+        let (tx, rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
+        let console = Console {
+            tx,
+            start: Instant::now(),
+        };
+        let lua_console = console.clone();
         lua.set(
             "__print",
-            hlua::function1(move |s: String| {
-                lua_tx.send(s).unwrap();
+            hlua::function3(move |warning: bool, line: i32, text: String| {
+                let level = if warning { Level::Warning } else { Level::Info };
+                let line = if line > 0 { Some(line as usize) } else { None };
+                lua_console
+                    .tx
+                    .send(lua_console.message(level, line, text))
+                    .unwrap();
             }),
         );
+        // The line is the one of the script which called print or warn, -1 if they are called
+        // from elsewhere.
         lua.execute::<()>(&format!(
             "
-            function print (...)
+            local function __print_all(warning, ...)
+              local info = debug.getinfo(3, \"Sl\")
+              local line = -1
+              if info and info.source == \"={name}\" then
+                line = info.currentline
+              end
+              local texts = {{}}
               for i,v in ipairs{{...}} do
-                __print(tostring(v) .. \"\\t\")
+                texts[i] = tostring(v)
               end
-              __print(\"\\n\")
+              __print(warning, line, table.concat(texts, \"\\t\"))
+            end
+            function print (...)
+              __print_all(false, ...)
+            end
+            function warn (...)
+              __print_all(true, ...)
             end
-            {env}.print = print;",
+            {env}.print = print;
+            {env}.warn = warn;",
+            name = SCRIPT_NAME,
             env = env_name
         ))
         .unwrap();
-        PrintBuffer { rx, tx }
+        PrintBuffer { rx, console }
     }
-    pub fn get_tx(&self) -> mpsc::Sender<String> {
-        self.tx.clone()
+    pub fn get_console(&self) -> Console {
+        self.console.clone()
     }
-    pub fn get_buffer(&self) -> String {
-        let mut result = String::new();
-        for s in self.rx.try_iter() {
-            result += &s;
-        }
-        result
+    // The messages sent since the last call.
+    pub fn get_messages(&self) -> Vec<Message> {
+        self.rx.try_iter().collect()
     }
 }
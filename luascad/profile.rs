@@ -0,0 +1,182 @@
+use super::Float;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Counts how often an object is evaluated, and how often its bounding box check prunes the
+// evaluation (the point is further away than slack). Clones share the counts, which are printed
+// when the last clone is dropped, e.g. when the next object is shown.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    object: Box<dyn Object<Float>>,
+    stats: Arc<Stats>,
+}
+
+#[derive(Debug)]
+struct Stats {
+    name: String,
+    evaluations: AtomicUsize,
+    pruned: AtomicUsize,
+}
+
+impl Drop for Stats {
+    fn drop(&mut self) {
+        let evaluations = self.evaluations.load(Ordering::Relaxed);
+        if evaluations > 0 {
+            let pruned = self.pruned.load(Ordering::Relaxed);
+            println!(
+                "profile {}: {} evaluations, {} pruned by the bounding box ({:.1}%)",
+                self.name,
+                evaluations,
+                pruned,
+                pruned as Float * 100. / evaluations as Float
+            );
+        }
+    }
+}
+
+impl Profile {
+    pub fn new(object: Box<dyn Object<Float>>, name: &str) -> Box<Profile> {
+        Box::new(Profile {
+            object,
+            stats: Arc::new(Stats {
+                name: name.to_string(),
+                evaluations: AtomicUsize::new(0),
+                pruned: AtomicUsize::new(0),
+            }),
+        })
+    }
+}
+
+impl Object<Float> for Profile {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        self.object.bbox()
+    }
+    fn set_bbox(&mut self, bbox: &BoundingBox<Float>) {
+        self.object.set_bbox(bbox)
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        self.stats.evaluations.fetch_add(1, Ordering::Relaxed);
+        if self.bbox().distance(p) > slack {
+            self.stats.pruned.fetch_add(1, Ordering::Relaxed);
+        }
+        self.object.approx_value(p, slack)
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.object.normal(p)
+    }
+}
+
+// How often the objects of a kind of node were evaluated, and how long that took without the
+// time spent in their children.
+#[derive(Debug, Default)]
+pub struct Counter {
+    evaluations: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Counter {
+    pub fn evaluations(&self) -> u64 {
+        self.evaluations.load(Ordering::Relaxed)
+    }
+    pub fn time(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+// The counters of all objects built by a script evaluated by counting, by kind of node (see
+// tree::Node::kind).
+#[derive(Debug, Default)]
+pub struct Counters {
+    kinds: Mutex<BTreeMap<&'static str, Arc<Counter>>>,
+}
+
+impl Counters {
+    pub fn counter(&self, kind: &'static str) -> Arc<Counter> {
+        self.kinds.lock().unwrap().entry(kind).or_default().clone()
+    }
+    // The counters by kind, the ones which took the longest first.
+    pub fn by_time(&self) -> Vec<(&'static str, Arc<Counter>)> {
+        let mut kinds: Vec<_> = self
+            .kinds
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, counter)| (*kind, counter.clone()))
+            .collect();
+        kinds.sort_by(|a, b| b.1.time().cmp(&a.1.time()));
+        kinds
+    }
+}
+
+thread_local! {
+    // The counters of the script being evaluated by counting on this thread.
+    static COUNTING: RefCell<Option<Arc<Counters>>> = const { RefCell::new(None) };
+    // Time spent in the children of the object being evaluated on this thread.
+    static CHILDREN: Cell<Duration> = const { Cell::new(Duration::from_secs(0)) };
+}
+
+// Call build (e.g. evaluating a script) with every object built by LObject::new in it counting
+// its evaluations, see Counted.
+pub fn counting<R, F: FnOnce() -> R>(build: F) -> (R, Arc<Counters>) {
+    let counters = Arc::new(Counters::default());
+    COUNTING.with(|c| *c.borrow_mut() = Some(counters.clone()));
+    let result = build();
+    COUNTING.with(|c| *c.borrow_mut() = None);
+    (result, counters)
+}
+
+// object counting its evaluations as kind, if it is built by counting.
+pub fn count(object: Box<dyn Object<Float>>, kind: &'static str) -> Box<dyn Object<Float>> {
+    match COUNTING.with(|c| c.borrow().clone()) {
+        Some(counters) => Box::new(Counted {
+            object,
+            counter: counters.counter(kind),
+        }),
+        None => object,
+    }
+}
+
+// Counts the evaluations of an object and their time, without the time of the objects below it
+// which count themselves. Measuring the time slows evaluation down, so times are only good to
+// compare kinds.
+#[derive(Clone, Debug)]
+pub struct Counted {
+    object: Box<dyn Object<Float>>,
+    counter: Arc<Counter>,
+}
+
+impl Object<Float> for Counted {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        self.object.bbox()
+    }
+    fn set_bbox(&mut self, bbox: &BoundingBox<Float>) {
+        self.object.set_bbox(bbox)
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let outer = CHILDREN.with(|c| c.replace(Duration::from_secs(0)));
+        let start = Instant::now();
+        let value = self.object.approx_value(p, slack);
+        let elapsed = start.elapsed();
+        let children = CHILDREN.with(|c| c.replace(outer + elapsed));
+        let own = elapsed.checked_sub(children).unwrap_or_default();
+        self.counter.evaluations.fetch_add(1, Ordering::Relaxed);
+        self.counter
+            .nanos
+            .fetch_add(own.as_nanos() as u64, Ordering::Relaxed);
+        value
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.object.normal(p)
+    }
+}
@@ -0,0 +1,716 @@
+use super::Float;
+use error::PrimitiveError;
+use float::consts::PI;
+use hlua;
+use include::Includes;
+use lobject::LObject;
+use nalgebra as na;
+use outline::Outline;
+use tree::Node;
+
+// Curves are flattened to polygons which differ from them by at most this, in the units of the
+// script.
+pub const TOLERANCE: Float = 0.01;
+// No curve is flattened to more segments than this, e.g. for tiny tolerances.
+const MAX_SEGMENTS: usize = 1000;
+// The contents of these elements are not drawn by themselves.
+const HIDDEN: &[&str] = &[
+    "defs", "clipPath", "mask", "symbol", "pattern", "marker", "metadata",
+];
+
+// The filled shapes of an SVG document as closed polygons, in its user units with y down:
+// paths, rectangles, circles, ellipses, polygons and polylines, moved by the transforms of
+// them and the groups around them. Curves are flattened to tolerance. Strokes, styles, text and
+// references (use) are ignored. Outline fills by the even-odd rule, which agrees with fill-rule
+// nonzero for holes wound the other way, as drawing programs write them.
+pub fn parse(svg: &str, tolerance: Float) -> Result<Vec<Vec<na::Point2<Float>>>, String> {
+    let mut contours = Vec::new();
+    // The transform and whether the contents are hidden, of each open element.
+    let mut stack: Vec<(na::Matrix3<Float>, bool)> = vec![(na::Matrix3::identity(), false)];
+    let mut rest = svg;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if rest.starts_with("!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let end = tag_end(rest).ok_or("unterminated tag")?;
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if tag.starts_with('/') {
+            if stack.len() > 1 {
+                stack.pop();
+            }
+            continue;
+        }
+        let closed = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        let name = &tag[..name_end];
+        let attributes = attributes(&tag[name_end..]);
+        let attribute = |key: &str| attributes.iter().find(|&&(k, _)| k == key).map(|&(_, v)| v);
+        let (parent, hidden) = stack[stack.len() - 1];
+        let transform = match attribute("transform") {
+            Some(t) => parent * parse_transform(t)?,
+            None => parent,
+        };
+        let hidden = hidden || HIDDEN.contains(&name);
+        if !hidden {
+            let number = |key: &str| attribute(key).map_or(Ok(0.), |v| length(key, v));
+            let path = match name {
+                "path" => attribute("d").map(str::to_string),
+                "rect" => Some(rect_path(
+                    number("x")?,
+                    number("y")?,
+                    number("width")?,
+                    number("height")?,
+                    number("rx")?,
+                    number("ry")?,
+                )),
+                "circle" => {
+                    let r = number("r")?;
+                    Some(ellipse_path(number("cx")?, number("cy")?, r, r))
+                }
+                "ellipse" => Some(ellipse_path(
+                    number("cx")?,
+                    number("cy")?,
+                    number("rx")?,
+                    number("ry")?,
+                )),
+                "polygon" | "polyline" => attribute("points").map(|p| format!("M{}z", p)),
+                _ => None,
+            };
+            if let Some(path) = path {
+                // Transforms may scale up the deviations of the flattened curves.
+                let scale = (0..2)
+                    .map(|i| transform[(0, i)].hypot(transform[(1, i)]))
+                    .fold(0., Float::max);
+                let local = if scale > 0. {
+                    tolerance / scale
+                } else {
+                    tolerance
+                };
+                for contour in parse_path(&path, local)? {
+                    contours.push(
+                        contour
+                            .iter()
+                            .map(|p| na::Point2::from_homogeneous(transform * p.to_homogeneous()))
+                            .collect::<Option<Vec<_>>>()
+                            .ok_or("invalid transform")?,
+                    );
+                }
+            }
+        }
+        if !closed {
+            stack.push((transform, hidden));
+        }
+    }
+    Ok(contours)
+}
+
+// The index of the > ending the tag at the start of s, outside of quotes.
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '>') => return Some(i),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    None
+}
+
+// The name="value" pairs of a tag.
+fn attributes(s: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = s;
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let quote = match value.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        let end = match value[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+        attributes.push((key, &value[1..end]));
+        rest = &value[end + 1..];
+    }
+    attributes
+}
+
+// A length in user units, with an optional px suffix.
+fn length(key: &str, value: &str) -> Result<Float, String> {
+    value
+        .trim()
+        .trim_end_matches("px")
+        .parse()
+        .map_err(|_| format!("invalid {} {}", key, value))
+}
+
+fn rect_path(x: Float, y: Float, width: Float, height: Float, rx: Float, ry: Float) -> String {
+    // A missing radius is the other one, and neither is more than half the side.
+    let (rx, ry) = match (rx > 0., ry > 0.) {
+        (true, false) => (rx, rx),
+        (false, true) => (ry, ry),
+        _ => (rx, ry),
+    };
+    let (rx, ry) = (rx.min(width / 2.), ry.min(height / 2.));
+    if !(rx > 0. && ry > 0.) {
+        return format!("M{},{}h{}v{}h{}z", x, y, width, height, -width);
+    }
+    let corner = |dx: Float, dy: Float| format!("a{},{} 0 0 1 {},{}", rx, ry, dx, dy);
+    format!(
+        "M{},{}h{}{}v{}{}h{}{}v{}{}z",
+        x + rx,
+        y,
+        width - 2. * rx,
+        corner(rx, ry),
+        height - 2. * ry,
+        corner(-rx, ry),
+        2. * rx - width,
+        corner(-rx, -ry),
+        2. * ry - height,
+        corner(rx, -ry)
+    )
+}
+
+fn ellipse_path(cx: Float, cy: Float, rx: Float, ry: Float) -> String {
+    format!(
+        "M{},{}a{},{} 0 1 1 {},0a{},{} 0 1 1 {},0z",
+        cx - rx,
+        cy,
+        rx,
+        ry,
+        2. * rx,
+        rx,
+        ry,
+        -2. * rx
+    )
+}
+
+// The numbers and flags of path data and transforms, separated by whitespace or commas or not
+// at all, as in "M10-5.5.5".
+struct Scanner<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(s: &'a str) -> Scanner<'a> {
+        Scanner {
+            s: s.as_bytes(),
+            i: 0,
+        }
+    }
+    fn skip_separators(&mut self) {
+        while self.i < self.s.len()
+            && (self.s[self.i].is_ascii_whitespace() || self.s[self.i] == b',')
+        {
+            self.i += 1;
+        }
+    }
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_separators();
+        self.s.get(self.i).cloned()
+    }
+    // Whether a number follows, rather than a command or the end.
+    fn at_number(&mut self) -> bool {
+        match self.peek() {
+            Some(c) => c.is_ascii_digit() || c == b'-' || c == b'+' || c == b'.',
+            None => false,
+        }
+    }
+    fn number(&mut self) -> Result<Float, String> {
+        self.skip_separators();
+        let start = self.i;
+        self.skip_one(b"+-");
+        self.skip_digits();
+        if self.skip_one(b".") {
+            self.skip_digits();
+        }
+        if self.skip_one(b"eE") {
+            self.skip_one(b"+-");
+            self.skip_digits();
+        }
+        String::from_utf8_lossy(&self.s[start..self.i])
+            .parse()
+            .map_err(|_| format!("invalid number at {} of {}", start, self.text()))
+    }
+    // Skip a byte in set, returns whether there was one.
+    fn skip_one(&mut self, set: &[u8]) -> bool {
+        let found = self.s.get(self.i).map_or(false, |c| set.contains(c));
+        if found {
+            self.i += 1;
+        }
+        found
+    }
+    fn skip_digits(&mut self) {
+        while self.s.get(self.i).map_or(false, u8::is_ascii_digit) {
+            self.i += 1;
+        }
+    }
+    // The flags of arcs, a single 0 or 1.
+    fn flag(&mut self) -> Result<bool, String> {
+        match self.peek() {
+            Some(b'0') => {
+                self.i += 1;
+                Ok(false)
+            }
+            Some(b'1') => {
+                self.i += 1;
+                Ok(true)
+            }
+            _ => Err(format!("invalid flag at {} of {}", self.i, self.text())),
+        }
+    }
+    fn point(&mut self) -> Result<na::Point2<Float>, String> {
+        Ok(na::Point2::new(self.number()?, self.number()?))
+    }
+    fn text(&self) -> String {
+        String::from_utf8_lossy(self.s).into_owned()
+    }
+}
+
+// The transform attribute of an element, as a matrix of homogeneous coordinates.
+fn parse_transform(s: &str) -> Result<na::Matrix3<Float>, String> {
+    let mut transform = na::Matrix3::identity();
+    let mut rest = s;
+    while let Some(open) = rest.find('(') {
+        let name = rest[..open].trim_matches(|c: char| c.is_whitespace() || c == ',');
+        let close = rest[open..]
+            .find(')')
+            .ok_or_else(|| format!("invalid transform {}", s))?
+            + open;
+        let mut scanner = Scanner::new(&rest[open + 1..close]);
+        let mut v = Vec::new();
+        while scanner.at_number() {
+            v.push(scanner.number()?);
+        }
+        let arg = |i: usize, default: Float| v.get(i).cloned().unwrap_or(default);
+        let m = match (name, v.len()) {
+            ("matrix", 6) => na::Matrix3::new(v[0], v[2], v[4], v[1], v[3], v[5], 0., 0., 1.),
+            ("translate", 1) | ("translate", 2) => {
+                na::Matrix3::new_translation(&na::Vector2::new(v[0], arg(1, 0.)))
+            }
+            ("scale", 1) | ("scale", 2) => {
+                na::Matrix3::new_nonuniform_scaling(&na::Vector2::new(v[0], arg(1, v[0])))
+            }
+            ("rotate", 1) | ("rotate", 3) => {
+                let center = na::Vector2::new(arg(1, 0.), arg(2, 0.));
+                na::Matrix3::new_translation(&center)
+                    * na::Matrix3::new_rotation(v[0].to_radians())
+                    * na::Matrix3::new_translation(&-center)
+            }
+            ("skewX", 1) => {
+                na::Matrix3::new(1., v[0].to_radians().tan(), 0., 0., 1., 0., 0., 0., 1.)
+            }
+            ("skewY", 1) => {
+                na::Matrix3::new(1., 0., 0., v[0].to_radians().tan(), 1., 0., 0., 0., 1.)
+            }
+            _ => return Err(format!("invalid transform {}", s)),
+        };
+        transform *= m;
+        rest = &rest[close + 1..];
+    }
+    Ok(transform)
+}
+
+// The subpaths of path data, flattened to tolerance. Each is closed, like the area a path fills.
+pub fn parse_path(d: &str, tolerance: Float) -> Result<Vec<Vec<na::Point2<Float>>>, String> {
+    let mut scanner = Scanner::new(d);
+    let mut contours: Vec<Vec<na::Point2<Float>>> = Vec::new();
+    let mut contour: Vec<na::Point2<Float>> = Vec::new();
+    let mut current = na::Point2::origin();
+    let mut start = current;
+    // The second control point of the last curve, for the reflection of S and T.
+    let mut control: Option<na::Point2<Float>> = None;
+    let mut command = None;
+    while let Some(c) = scanner.peek() {
+        if c.is_ascii_alphabetic() {
+            scanner.i += 1;
+            command = Some(c);
+            if c == b'z' || c == b'Z' {
+                if contour.len() > 2 {
+                    contours.push(contour);
+                }
+                contour = Vec::new();
+                current = start;
+                control = None;
+                continue;
+            }
+        }
+        let c = command.ok_or_else(|| format!("path data must start with a command: {}", d))?;
+        let relative = c.is_ascii_lowercase();
+        let offset = if relative {
+            current.coords
+        } else {
+            na::Vector2::zeros()
+        };
+        if contour.is_empty() && c != b'm' && c != b'M' {
+            contour.push(current);
+        }
+        let mut next_control = None;
+        match c.to_ascii_uppercase() {
+            b'M' => {
+                if contour.len() > 2 {
+                    contours.push(contour);
+                }
+                current = scanner.point()? + offset;
+                start = current;
+                contour = vec![current];
+                // Further pairs are lines.
+                command = Some(if relative { b'l' } else { b'L' });
+            }
+            b'L' => {
+                current = scanner.point()? + offset;
+                contour.push(current);
+            }
+            b'H' => {
+                let x = scanner.number()?;
+                current.x = if relative { current.x + x } else { x };
+                contour.push(current);
+            }
+            b'V' => {
+                let y = scanner.number()?;
+                current.y = if relative { current.y + y } else { y };
+                contour.push(current);
+            }
+            b'C' | b'S' => {
+                let first = if c.to_ascii_uppercase() == b'C' {
+                    scanner.point()? + offset
+                } else {
+                    reflect(control, current)
+                };
+                let second = scanner.point()? + offset;
+                let end = scanner.point()? + offset;
+                cubic(&mut contour, current, first, second, end, tolerance);
+                next_control = Some(second);
+                current = end;
+            }
+            b'Q' | b'T' => {
+                let middle = if c.to_ascii_uppercase() == b'Q' {
+                    scanner.point()? + offset
+                } else {
+                    reflect(control, current)
+                };
+                let end = scanner.point()? + offset;
+                quadratic(&mut contour, current, middle, end, tolerance);
+                next_control = Some(middle);
+                current = end;
+            }
+            b'A' => {
+                let (rx, ry, angle) = (scanner.number()?, scanner.number()?, scanner.number()?);
+                let (large, sweep) = (scanner.flag()?, scanner.flag()?);
+                let end = scanner.point()? + offset;
+                arc(
+                    &mut contour,
+                    current,
+                    (rx, ry, angle.to_radians()),
+                    (large, sweep),
+                    end,
+                    tolerance,
+                );
+                current = end;
+            }
+            _ => return Err(format!("unknown path command {} in {}", c as char, d)),
+        }
+        // Reflections only follow curves of the same kind.
+        control = next_control;
+    }
+    if contour.len() > 2 {
+        contours.push(contour);
+    }
+    Ok(contours)
+}
+
+// The control point of a smooth curve: the last one reflected at current, or current.
+fn reflect(control: Option<na::Point2<Float>>, current: na::Point2<Float>) -> na::Point2<Float> {
+    control.map_or(current, |c| current + (current - c))
+}
+
+// Segments of a curve whose second derivative is at most bound in length, so that the chords
+// deviate from it by at most tolerance: bound / 8 / n^2.
+fn segments(bound: Float, tolerance: Float) -> usize {
+    let n = (bound / (8. * tolerance)).sqrt().ceil();
+    if n.is_finite() {
+        (n as usize).clamp(1, MAX_SEGMENTS)
+    } else {
+        MAX_SEGMENTS
+    }
+}
+
+fn cubic(
+    contour: &mut Vec<na::Point2<Float>>,
+    p0: na::Point2<Float>,
+    p1: na::Point2<Float>,
+    p2: na::Point2<Float>,
+    p3: na::Point2<Float>,
+    tolerance: Float,
+) {
+    let bound = 6.
+        * (p0.coords - p1.coords * 2. + p2.coords)
+            .norm()
+            .max((p1.coords - p2.coords * 2. + p3.coords).norm());
+    let n = segments(bound, tolerance);
+    for i in 1..=n {
+        let t = i as Float / n as Float;
+        let s = 1. - t;
+        contour.push(na::Point2::from(
+            p0.coords * (s * s * s)
+                + p1.coords * (3. * s * s * t)
+                + p2.coords * (3. * s * t * t)
+                + p3.coords * (t * t * t),
+        ));
+    }
+}
+
+fn quadratic(
+    contour: &mut Vec<na::Point2<Float>>,
+    p0: na::Point2<Float>,
+    p1: na::Point2<Float>,
+    p2: na::Point2<Float>,
+    tolerance: Float,
+) {
+    let n = segments(
+        2. * (p0.coords - p1.coords * 2. + p2.coords).norm(),
+        tolerance,
+    );
+    for i in 1..=n {
+        let t = i as Float / n as Float;
+        let s = 1. - t;
+        contour.push(na::Point2::from(
+            p0.coords * (s * s) + p1.coords * (2. * s * t) + p2.coords * (t * t),
+        ));
+    }
+}
+
+// An elliptical arc from p0 to p1, with the radii and the rotation of the ellipse in radians,
+// after the implementation notes of the SVG specification.
+fn arc(
+    contour: &mut Vec<na::Point2<Float>>,
+    p0: na::Point2<Float>,
+    (rx, ry, phi): (Float, Float, Float),
+    (large, sweep): (bool, bool),
+    p1: na::Point2<Float>,
+    tolerance: Float,
+) {
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if p0 == p1 {
+        return;
+    }
+    if rx == 0. || ry == 0. {
+        contour.push(p1);
+        return;
+    }
+    let rotation = na::Rotation2::new(phi);
+    let p = rotation.inverse() * ((p0 - p1) / 2.);
+    // Radii too small to reach are scaled up.
+    let lambda = (p.x / rx).powi(2) + (p.y / ry).powi(2);
+    if lambda > 1. {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = (rx * ry).powi(2) - (rx * p.y).powi(2) - (ry * p.x).powi(2);
+    let denominator = (rx * p.y).powi(2) + (ry * p.x).powi(2);
+    let mut factor = (numerator / denominator).max(0.).sqrt();
+    if large == sweep {
+        factor = -factor;
+    }
+    let c = na::Vector2::new(rx * p.y / ry, -ry * p.x / rx) * factor;
+    let center = rotation * c + (p0.coords + p1.coords) / 2.;
+    let angle = |v: na::Vector2<Float>| v.y.atan2(v.x);
+    let start = angle(na::Vector2::new((p.x - c.x) / rx, (p.y - c.y) / ry));
+    let end = angle(na::Vector2::new((-p.x - c.x) / rx, (-p.y - c.y) / ry));
+    let mut delta = end - start;
+    if sweep && delta < 0. {
+        delta += 2. * PI;
+    } else if !sweep && delta > 0. {
+        delta -= 2. * PI;
+    }
+    // The chords of an angle step a deviate from the circle of radius r by r (1 - cos(a / 2)).
+    let r = rx.max(ry);
+    let step = 2. * (1. - (tolerance / r).min(1.)).acos();
+    let n = ((delta.abs() / step).ceil() as usize).clamp(1, MAX_SEGMENTS);
+    for i in 1..n {
+        let a = start + delta * i as Float / n as Float;
+        contour.push(na::Point2::from(
+            rotation * na::Vector2::new(rx * a.cos(), ry * a.sin()) + center,
+        ));
+    }
+    contour.push(p1);
+}
+
+// ImportSVG(filename, scale): the filled shapes of the SVG file as an Outline, with a unit of
+// the SVG scaled to scale units of the script and y up.
+pub fn import_svg(
+    includes: &Includes,
+    filename: &str,
+    scale: Float,
+) -> Result<LObject, PrimitiveError> {
+    let error = |message: String| PrimitiveError::Import {
+        function: "ImportSVG",
+        filename: filename.to_string(),
+        message,
+    };
+    if !(scale > 0. && scale.is_finite()) {
+        return Err(PrimitiveError::InvalidParameter {
+            function: "ImportSVG",
+            parameter: "scale",
+            value: scale,
+            requirement: "positive and finite",
+        });
+    }
+    let path = includes.path(filename).map_err(error)?;
+    let svg = ::std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    let contours: Vec<Vec<na::Point2<Float>>> = parse(&svg, TOLERANCE / scale)
+        .map_err(error)?
+        .into_iter()
+        .map(|c| {
+            c.iter()
+                .map(|p| na::Point2::new(p.x, -p.y) * scale)
+                .collect()
+        })
+        .collect();
+    if contours.is_empty() {
+        return Err(error("no filled shapes".to_string()));
+    }
+    Ok(LObject::new(
+        Outline::new(contours.clone()),
+        Node::Outline { contours },
+    ))
+}
+
+// Define ImportSVG in the env, reading files with includes, see Includes::path.
+pub fn export_factories<'lua>(lua: &mut hlua::Lua<'lua>, env_name: &str, includes: &'lua Includes) {
+    lua.set(
+        "__ImportSVG",
+        hlua::function2(move |filename: String, scale: Float| {
+            import_svg(includes, &filename, scale)
+        }),
+    );
+    // The scale is optional.
+    lua.execute::<()>(&format!(
+        "
+        function ImportSVG(filename, scale)
+          return __ImportSVG(filename, scale or 1)
+        end
+        {env}.ImportSVG = ImportSVG
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use implicit3d::Object;
+
+    fn points(contour: &[na::Point2<Float>]) -> Vec<(Float, Float)> {
+        contour.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    fn path(d: &str) -> Vec<Vec<(Float, Float)>> {
+        parse_path(d, 0.01)
+            .unwrap()
+            .iter()
+            .map(|c| points(c))
+            .collect()
+    }
+
+    #[test]
+    fn lines_absolute_and_relative() {
+        let square = vec![vec![(0., 0.), (10., 0.), (10., 10.), (0., 10.)]];
+        assert_eq!(path("M0,0 L10,0 L10,10 L0,10 Z"), square);
+        assert_eq!(path("m0,0 h10 v10 h-10 z"), square);
+        // Pairs after a move are lines, and numbers need no separators.
+        assert_eq!(path("M0 0 10 0 10 10 0 10z"), square);
+        assert_eq!(
+            path("M10-5.5.5-5 1,1z"),
+            vec![vec![(10., -5.5), (0.5, -5.), (1., 1.)]]
+        );
+        // Each subpath is a contour, also without a closing z.
+        assert_eq!(path("M0,0h1v1zM5,5h1v1").len(), 2);
+        assert!(parse_path("1,1 L2,2", 0.01).is_err());
+        assert!(parse_path("M0,0 X1,1", 0.01).is_err());
+        assert!(parse_path("M0,0 L1", 0.01).is_err());
+    }
+
+    #[test]
+    fn curves_are_flattened_to_tolerance() {
+        // The cubic from (0, 0) to (10, 0) peaks at 7.5 in its middle.
+        let cubic = parse_path("M0,0 C0,10 10,10 10,0 z", 0.01).unwrap();
+        let top = cubic[0].iter().map(|p| p.y).fold(0., Float::max);
+        assert!((top - 7.5).abs() < 0.01, "{}", top);
+        assert_eq!(cubic[0][cubic[0].len() - 1], na::Point2::new(10., 0.));
+        // The half circle sweeping in the direction of positive angles, up in y down.
+        let arc = parse_path("M0,0 A5,5 0 0 1 10,0 z", 0.01).unwrap();
+        for p in &arc[0] {
+            assert!((p - na::Point2::new(5., 0.)).norm() - 5. < 1e-6, "{:?}", p);
+            assert!(p.y <= 1e-6, "{:?}", p);
+        }
+        assert!(arc[0].iter().any(|p| p.y < -4.99));
+        // T reflects the control point of Q, so the second half curves the other way.
+        let smooth = parse_path("M0,0 Q5,10 10,0 T20,0 z", 0.01).unwrap();
+        for p in &smooth[0] {
+            if p.x > 10. && p.x < 20. {
+                assert!(p.y < 0., "{:?}", p);
+            } else if p.x > 0. && p.x < 10. {
+                assert!(p.y > 0., "{:?}", p);
+            }
+        }
+    }
+
+    #[test]
+    fn transforms_of_elements_and_groups() {
+        let transform = parse_transform("translate(1,2) scale(2)").unwrap();
+        let p = transform * na::Point3::new(1., 1., 1.);
+        assert_eq!((p.x, p.y), (3., 4.));
+        let rotate = parse_transform("rotate(90, 1, 1)").unwrap();
+        let p = rotate * na::Point3::new(2., 1., 1.);
+        assert!(
+            (p.x - 1.).abs() < 1e-6 && (p.y - 2.).abs() < 1e-6,
+            "{:?}",
+            p
+        );
+        let matrix = parse_transform("matrix(1 0 0 1 5 6)").unwrap();
+        assert_eq!(
+            matrix * na::Point3::new(0., 0., 1.),
+            na::Point3::new(5., 6., 1.)
+        );
+        assert!(parse_transform("spin(3)").is_err());
+        let svg = r#"<svg><defs><rect width="5" height="5"/></defs>
+            <g transform="translate(10,0)"><!-- <rect/> -->
+              <rect width="1" height="1" transform="scale(2)"/>
+            </g>
+            <rect x="20" width="1" height="1"/></svg>"#;
+        let contours = parse(svg, 0.01).unwrap();
+        assert_eq!(
+            contours.iter().map(|c| points(c)).collect::<Vec<_>>(),
+            vec![
+                vec![(10., 0.), (12., 0.), (12., 2.), (10., 2.)],
+                vec![(20., 0.), (21., 0.), (21., 1.), (20., 1.)],
+            ]
+        );
+    }
+
+    #[test]
+    fn inner_subpaths_are_holes() {
+        for svg in &[
+            r#"<path fill-rule="evenodd" d="M0,0h10v10h-10z M3,3h4v4h-4z"/>"#,
+            r#"<path fill-rule="nonzero" d="M0,0h10v10h-10z M3,3v4h4v-4z"/>"#,
+        ] {
+            let outline = Outline::new(parse(svg, 0.01).unwrap());
+            let value = |x, y| outline.approx_value(&na::Point3::new(x, y, 0.), 0.);
+            assert!(value(1., 1.) < 0. && value(5., 5.) > 0. && value(11., 5.) > 0.);
+        }
+    }
+}
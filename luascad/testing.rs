@@ -0,0 +1,414 @@
+// The checks negate comparisons on purpose, so that NaNs fail them (or are skipped by them).
+#![allow(clippy::neg_cmp_op_on_partial_ord)]
+
+use super::Float;
+use implicit3d::{BoundingBox, Object};
+use nalgebra as na;
+use std::fmt;
+use transformer;
+
+// Conformance checks for implementations of implicit3d::Object, e.g. new primitives here or in
+// other crates. Each property samples points around the object, the same points for the same
+// object, and fails with the first point which breaks it:
+//
+//     let object = Sphere::new(1.);
+//     assert_eq!(testing::check(&*object), Vec::new());
+//
+// Values are compared up to tolerance times the size of the sampled box.
+
+// Points sampled by check for each property.
+pub const SAMPLES: usize = 1000;
+// The relative tolerance check compares with, well above the precision of Float.
+#[cfg(not(feature = "f32"))]
+pub const TOLERANCE: Float = 1e-6;
+#[cfg(feature = "f32")]
+pub const TOLERANCE: Float = 1e-4;
+// Objects with infinite boxes are sampled within this distance of the origin.
+const REGION: Float = 10.;
+// Points in the ball of each point checked by conservative_outside.
+const BALL_SAMPLES: usize = 8;
+// Newton steps moving points onto the surface for normals_match_differences.
+const SURFACE_STEPS: usize = 8;
+// Normals may differ from the differences by this much of the cosine of their angle, and points
+// where the differences on either side of the point differ by more than CREASE, or the normals
+// within a step of it by more than NORMAL_TOLERANCE, are skipped.
+const NORMAL_TOLERANCE: Float = 1e-3;
+const CREASE: Float = 0.1;
+
+// A point where an object breaks a property.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub property: &'static str,
+    pub at: na::Point3<Float>,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at ({}, {}, {}): {}",
+            self.property, self.at.x, self.at.y, self.at.z, self.message
+        )
+    }
+}
+
+fn failure(property: &'static str, at: &na::Point3<Float>, message: String) -> Failure {
+    Failure {
+        property,
+        at: *at,
+        message,
+    }
+}
+
+// Check all properties with SAMPLES points each. Returns the failures, nothing if object conforms.
+pub fn check(object: &dyn Object<Float>) -> Vec<Failure> {
+    let region = region(object.bbox());
+    let tolerance = TOLERANCE * (region.max - region.min).norm();
+    let points = points(&region, SAMPLES);
+    vec![
+        conservative_outside(object, &points, tolerance),
+        sign_within_bbox(object, &points, tolerance),
+        normals_match_differences(object, &points, tolerance),
+        transform_invariance(object, &points, tolerance),
+    ]
+    .into_iter()
+    .filter_map(Result::err)
+    .collect()
+}
+
+// The value of object at p, without the shortcuts of bounding boxes.
+pub fn value(object: &dyn Object<Float>, p: &na::Point3<Float>) -> Float {
+    object.approx_value(p, Float::INFINITY)
+}
+
+// The box of object, grown by a fourth so that points outside of it are sampled too, and limited
+// to REGION around the origin.
+pub fn region(bbox: &BoundingBox<Float>) -> BoundingBox<Float> {
+    let limit = na::Point3::new(REGION, REGION, REGION);
+    let mut region = bbox.intersection(&BoundingBox::new(&-limit, &limit));
+    // Boxes of infinite objects may intersect to nothing, or to NaNs.
+    if (0..3).any(|i| !(region.min[i] <= region.max[i])) {
+        region = BoundingBox::new(&-limit, &limit);
+    }
+    let size = (region.max - region.min).amax();
+    region.dilate(size.max(1.) / 4.);
+    region
+}
+
+// count points spread over bbox, the same ones for the same box.
+pub fn points(bbox: &BoundingBox<Float>, count: usize) -> Vec<na::Point3<Float>> {
+    let mut random = Random::new(count as u64);
+    (0..count)
+        .map(|_| {
+            let t = na::Vector3::new(random.uniform(), random.uniform(), random.uniform());
+            bbox.min + (bbox.max - bbox.min).component_mul(&t)
+        })
+        .collect()
+}
+
+// Values may underestimate the distance to the surface, but never overestimate it: no point
+// closer to a point outside than its value may be inside. Else tessellation and rendering, which
+// step by the values, skip parts of the object.
+pub fn conservative_outside(
+    object: &dyn Object<Float>,
+    points: &[na::Point3<Float>],
+    tolerance: Float,
+) -> Result<(), Failure> {
+    let mut random = Random::new(points.len() as u64);
+    for p in points {
+        let distance = value(object, p);
+        if !(distance > tolerance) || !distance.is_finite() {
+            continue;
+        }
+        for _ in 0..BALL_SAMPLES {
+            let q = p + random.direction() * distance * (1. - TOLERANCE) * random.uniform().cbrt();
+            let v = value(object, &q);
+            if v < -tolerance {
+                return Err(failure(
+                    "conservative outside",
+                    p,
+                    format!(
+                        "value {} but ({}, {}, {}) is inside at {}",
+                        distance,
+                        q.x,
+                        q.y,
+                        q.z,
+                        (q - p).norm()
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The object is within its box: points outside of it are outside, also with the values the
+// shortcuts of the box give.
+pub fn sign_within_bbox(
+    object: &dyn Object<Float>,
+    points: &[na::Point3<Float>],
+    tolerance: Float,
+) -> Result<(), Failure> {
+    for p in points {
+        if !(object.bbox().distance(p) > tolerance) {
+            continue;
+        }
+        for &(v, what) in &[
+            (value(object, p), "value"),
+            (object.approx_value(p, 0.), "approximate value"),
+        ] {
+            if !(v > 0.) {
+                return Err(failure(
+                    "sign within bbox",
+                    p,
+                    format!("{} {} outside of the box", what, v),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The normal on the surface is the direction the value grows fastest in. Points are moved onto
+// the surface first, those on creases, where the value grows differently on either side, are
+// skipped, as are those next to one, where the differences reach across it.
+pub fn normals_match_differences(
+    object: &dyn Object<Float>,
+    points: &[na::Point3<Float>],
+    tolerance: Float,
+) -> Result<(), Failure> {
+    let step = tolerance.sqrt();
+    for p in points {
+        let mut p = *p;
+        for _ in 0..SURFACE_STEPS {
+            let gradient = match gradient(object, &p, step) {
+                Some(g) => g,
+                None => break,
+            };
+            p -= gradient * value(object, &p);
+        }
+        if !(value(object, &p).abs() < step) {
+            continue;
+        }
+        let (forward, backward) = match (gradient(object, &p, step), gradient(object, &p, -step)) {
+            (Some(f), Some(b)) => (f, b),
+            _ => continue,
+        };
+        if (forward - backward).norm() > CREASE {
+            continue;
+        }
+        let normal = object.normal(&p);
+        let axes = [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()];
+        let near_crease = axes.iter().any(|axis| {
+            [step, -step]
+                .iter()
+                .any(|&s| !(object.normal(&(p + axis * s)).dot(&normal) > 1. - NORMAL_TOLERANCE))
+        });
+        if near_crease {
+            continue;
+        }
+        let expected = (forward + backward).normalize();
+        if !(normal.dot(&expected) > 1. - NORMAL_TOLERANCE) {
+            return Err(failure(
+                "normals match differences",
+                &p,
+                format!(
+                    "normal ({}, {}, {}) instead of ({}, {}, {})",
+                    normal.x, normal.y, normal.z, expected.x, expected.y, expected.z
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The normalized differences of the values at p and a step along each axis.
+fn gradient(
+    object: &dyn Object<Float>,
+    p: &na::Point3<Float>,
+    step: Float,
+) -> Option<na::Vector3<Float>> {
+    let v = value(object, p);
+    let difference = |d: na::Vector3<Float>| (value(object, &(p + d * step)) - v) / step;
+    na::Vector3::new(
+        difference(na::Vector3::x()),
+        difference(na::Vector3::y()),
+        difference(na::Vector3::z()),
+    )
+    .try_normalize(0.)
+}
+
+// Translating and rotating the object moves its values along: the transformed object has the
+// value of the object at the point the transformation moves there.
+pub fn transform_invariance(
+    object: &dyn Object<Float>,
+    points: &[na::Point3<Float>],
+    tolerance: Float,
+) -> Result<(), Failure> {
+    let offset = na::Vector3::new(0.3, -0.2, 0.1) * REGION;
+    let angles = na::Vector3::new(0.3, 0.5, -0.7);
+    let translated = transformer::translate(object, &offset);
+    let rotated = transformer::rotate(object, &angles);
+    // Like transformer::rotate, which maps points of object to those of rotated.
+    let rotation = na::Rotation3::from_euler_angles(angles.x, angles.y, angles.z).inverse();
+    for p in points {
+        let expected = value(object, p);
+        if !expected.is_finite() {
+            continue;
+        }
+        let transformed: [(&dyn Object<Float>, na::Point3<Float>, &str); 2] = [
+            (&*translated, p + offset, "translated"),
+            (&*rotated, rotation * p, "rotated"),
+        ];
+        for &(transformed, ref q, what) in &transformed {
+            let v = value(transformed, q);
+            if !((v - expected).abs() <= tolerance * expected.abs().max(1.)) {
+                return Err(failure(
+                    "transform invariance",
+                    p,
+                    format!("{} value {} instead of {}", what, v, expected),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// xorshift64*, so that the same points are checked on every run without another dependency.
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+    // Uniform in 0..1.
+    pub fn uniform(&mut self) -> Float {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as Float / (1u64 << 53) as Float
+    }
+    // Uniform on the unit sphere.
+    pub fn direction(&mut self) -> na::Vector3<Float> {
+        let z = 2. * self.uniform() - 1.;
+        let angle = 2. * ::float::consts::PI * self.uniform();
+        let r = (1. - z * z).sqrt();
+        na::Vector3::new(r * angle.cos(), r * angle.sin(), z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bvh;
+    use cache::Cache;
+    use empty::{Empty, Full};
+    use implicit3d::{PrimitiveParameters, Sphere};
+
+    // Like the defaults of the settings.
+    const PARAMETERS: PrimitiveParameters<Float> = PrimitiveParameters {
+        fade_range: 0.1,
+        r_multiplier: 1.0,
+    };
+
+    // The object script builds.
+    fn build(script: &str) -> Box<dyn Object<Float>> {
+        let (_, object) = ::eval(script).unwrap();
+        let mut object = object.unwrap();
+        object.set_parameters(&PARAMETERS);
+        object
+    }
+
+    fn sphere(x: Float, y: Float, z: Float) -> Box<dyn Object<Float>> {
+        transformer::translate(&Sphere::new(1.), &na::Vector3::new(x, y, z))
+    }
+
+    #[test]
+    fn primitives() {
+        for script in &[
+            "build(Sphere(1))",
+            "build(Box(1, 2, 3))",
+            "build(Cylinder{l = 2, r = 1})",
+            "build(Cylinder{l = 2, r1 = 1, r2 = 0.5})",
+        ] {
+            assert_eq!(check(&*build(script)), Vec::new(), "{}", script);
+        }
+    }
+
+    #[test]
+    fn transformer() {
+        let object = build("build(Box(1, 2, 3))");
+        let rotated = transformer::rotate(&*object, &na::Vector3::new(0.1, 0.2, 0.3));
+        let moved = transformer::translate(&*rotated, &na::Vector3::new(1., -2., 0.5));
+        assert_eq!(check(&*moved), Vec::new());
+    }
+
+    #[test]
+    fn bvh_union() {
+        let spheres = (0..10)
+            .map(|i| sphere(i as Float * 1.5, (i % 3) as Float, 0.))
+            .collect();
+        let union = bvh::union_from_vec(spheres, 0.).unwrap();
+        assert_eq!(check(&*union), Vec::new());
+    }
+
+    // Cached values are off by up to the quantum, which is far below the tolerance here.
+    #[test]
+    fn cache() {
+        let cache = Cache::new(sphere(0.5, 0., 0.), 1e-9);
+        assert_eq!(check(&*cache), Vec::new());
+    }
+
+    #[test]
+    fn empty_and_full() {
+        assert_eq!(check(&*Empty::new()), Vec::new());
+        assert_eq!(check(&*Full::new()), Vec::new());
+    }
+
+    #[test]
+    fn gears_threads_and_edges() {
+        for script in &[
+            "build(Gear(1, 12, 2))",
+            "build(InvoluteGear(1, 12, 2, 20, 15))",
+            "build(MetricThread(\"M8\", 10))",
+            "build(fillet(Box(2, 2, 2), Sphere(1.2):translate(0, 0, 1), 0.3))",
+            "build(chamfer(Box(2, 2, 2), Sphere(1.2):translate(0, 0, 1), 0.3))",
+        ] {
+            assert_eq!(check(&*build(script)), Vec::new(), "{}", script);
+        }
+    }
+
+    // A sphere whose values outside are twice the distance to its surface.
+    #[derive(Clone, Debug)]
+    struct Overestimating(Box<dyn Object<Float>>);
+
+    impl Object<Float> for Overestimating {
+        fn bbox(&self) -> &BoundingBox<Float> {
+            self.0.bbox()
+        }
+        fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+            let v = self.0.approx_value(p, slack);
+            if v > 0. {
+                v * 2.
+            } else {
+                v
+            }
+        }
+        fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+            self.0.normal(p)
+        }
+    }
+
+    #[test]
+    fn overestimating() {
+        let failures = check(&Overestimating(sphere(0., 0., 0.)));
+        assert!(
+            failures
+                .iter()
+                .any(|f| f.property == "conservative outside"),
+            "{:?}",
+            failures
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use super::{Float, RELATIVE_STEP};
+use api::Function;
+use error::{self, PrimitiveError};
+use float::consts::PI;
+use hlua;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use lobject::LObject;
+use nalgebra as na;
+use parts;
+use tree::Node;
+
+// Coarse pitches of ISO metric threads, by nominal diameter.
+pub const METRIC_PITCHES: [(Float, Float); 12] = [
+    (2., 0.4),
+    (2.5, 0.45),
+    (3., 0.5),
+    (4., 0.7),
+    (5., 0.8),
+    (6., 1.),
+    (8., 1.25),
+    (10., 1.5),
+    (12., 1.75),
+    (16., 2.),
+    (20., 2.5),
+    (24., 3.),
+];
+
+// Pitches of ISO trapezoidal threads, by nominal diameter.
+pub const TRAPEZOIDAL_PITCHES: [(Float, Float); 7] = [
+    (8., 1.5),
+    (10., 2.),
+    (12., 3.),
+    (14., 3.),
+    (16., 4.),
+    (20., 4.),
+    (24., 5.),
+];
+
+// NPT pipe threads: the name, the outside diameter of the pipe and the threads per inch.
+pub const PIPE_SIZES: [(&str, Float, Float); 6] = [
+    ("1/8", 10.287, 27.),
+    ("1/4", 13.716, 18.),
+    ("3/8", 17.145, 18.),
+    ("1/2", 21.336, 14.),
+    ("3/4", 26.67, 14.),
+    ("1", 33.401, 11.5),
+];
+
+// NPT threads widen by 1 in 16 on the diameter.
+const PIPE_TAPER: Float = 1. / 32.;
+
+// Widths of the tolerance zones of the grades 3 to 9, relative to grade 6.
+const GRADE_FACTORS: [Float; 7] = [0.5, 0.63, 0.8, 1., 1.25, 1.6, 2.];
+
+// The profile of a thread over one pitch, as the radial offsets from the crest (0 and
+// negative) at axial positions from 0 to the pitch, with a crest centered on 0.
+pub fn metric_profile(pitch: Float) -> Vec<na::Point2<Float>> {
+    // The ISO 68-1 basic profile: 60 degree flanks, flat crests of P / 8 and flat roots of
+    // P / 4, 5 / 8 of the fundamental triangle deep.
+    let depth = 5. / 8. * Float::sqrt(3.) / 2. * pitch;
+    profile(pitch, pitch / 16., 3. / 8. * pitch, depth)
+}
+
+pub fn trapezoidal_profile(pitch: Float) -> Vec<na::Point2<Float>> {
+    // The ISO 2904 basic profile: 30 degree flanks, P / 2 deep.
+    let depth = pitch / 2.;
+    let flank = depth * Float::to_radians(15.).tan();
+    let flat = (pitch / 2. - flank) / 2.;
+    profile(pitch, flat, flat + flank, depth)
+}
+
+pub fn pipe_profile(pitch: Float) -> Vec<na::Point2<Float>> {
+    // The ASME B1.20.1 profile: 60 degree flanks, truncated to 0.8 P deep.
+    let depth = 0.8 * pitch;
+    let flank = depth * Float::to_radians(30.).tan();
+    let flat = (pitch / 2. - flank) / 2.;
+    profile(pitch, flat, flat + flank, depth)
+}
+
+// A symmetric profile with the crest up to crest, the flank down to root and the root in the
+// middle of the pitch.
+fn profile(pitch: Float, crest: Float, root: Float, depth: Float) -> Vec<na::Point2<Float>> {
+    vec![
+        na::Point2::new(0., 0.),
+        na::Point2::new(crest, 0.),
+        na::Point2::new(root, -depth),
+        na::Point2::new(pitch - root, -depth),
+        na::Point2::new(pitch - crest, 0.),
+        na::Point2::new(pitch, 0.),
+    ]
+}
+
+// The nominal diameter and pitch of a name like "M8" or "M8x1" (with prefix "M"), with the
+// pitch from pitches if it is not given.
+fn parse(name: &str, prefix: &str, pitches: &[(Float, Float)]) -> Option<(Float, Float)> {
+    if !name.starts_with(prefix) {
+        return None;
+    }
+    let mut parts = name[prefix.len()..].splitn(2, 'x');
+    let diameter = parts.next()?.parse::<Float>().ok()?;
+    let pitch = match parts.next() {
+        Some(pitch) => pitch.parse::<Float>().ok()?,
+        None => pitches
+            .iter()
+            .find(|&&(d, _)| d == diameter)
+            .map(|&(_, pitch)| pitch)?,
+    };
+    if diameter > 0. && pitch > 0. && pitch < diameter && diameter.is_finite() {
+        Some((diameter, pitch))
+    } else {
+        None
+    }
+}
+
+// The radial offset of a thread in the tolerance class, like "6g" for an external or "6H" for an
+// internal thread, in the middle of its tolerance zone for the pitch diameter. Internal threads
+// are the space to subtract and grow with it, external ones shrink. Fundamental deviations and
+// tolerances are those of ISO 965-1.
+pub fn class_offset(
+    function: &'static str,
+    class: &str,
+    diameter: Float,
+    pitch: Float,
+    internal: bool,
+) -> Result<Float, PrimitiveError> {
+    let invalid = || PrimitiveError::UnknownName {
+        function,
+        name: class.to_string(),
+        expected: "a tolerance class like 6g (e, f, g or h) or 6H (G or H) with a grade of 3 to 9",
+    };
+    let mut chars = class.chars();
+    let grade = chars
+        .next()
+        .and_then(|c| c.to_digit(10))
+        .filter(|&g| (3..=9).contains(&g))
+        .ok_or_else(invalid)?;
+    // In micrometers.
+    let deviation = match (chars.next(), chars.next(), internal) {
+        (Some('e'), None, false) => -(50. + 11. * pitch),
+        (Some('f'), None, false) => -(30. + 11. * pitch),
+        (Some('g'), None, false) => -(15. + 11. * pitch),
+        (Some('h'), None, false) | (Some('H'), None, true) => 0.,
+        (Some('G'), None, true) => 15. + 11. * pitch,
+        _ => return Err(invalid()),
+    };
+    let mut tolerance =
+        90. * pitch.powf(0.4) * diameter.powf(0.1) * GRADE_FACTORS[grade as usize - 3];
+    if internal {
+        tolerance *= 1.32;
+        Ok((deviation + tolerance / 2.) / 2000.)
+    } else {
+        Ok((deviation - tolerance / 2.) / 2000.)
+    }
+}
+
+// MetricThread(name, length, internal, class): an ISO metric thread like "M8" (coarse pitch)
+// or "M8x1" from z = 0 to length. External threads are the bolt, internal ones the space to
+// subtract for a nut. class defaults to 6g and 6H.
+pub fn metric_thread(
+    name: &str,
+    length: Float,
+    internal: bool,
+    class: Option<&str>,
+) -> Result<LObject, PrimitiveError> {
+    let (diameter, pitch) =
+        parse(name, "M", &METRIC_PITCHES).ok_or_else(|| PrimitiveError::UnknownName {
+            function: "MetricThread",
+            name: name.to_string(),
+            expected: "a metric thread like M8 or M8x1",
+        })?;
+    let default = if internal { "6H" } else { "6g" };
+    let offset = class_offset(
+        "MetricThread",
+        class.unwrap_or(default),
+        diameter,
+        pitch,
+        internal,
+    )?;
+    new_thread(
+        "MetricThread",
+        metric_profile(pitch),
+        diameter / 2. + offset,
+        0.,
+        length,
+    )
+}
+
+// TrapezoidalThread(name, length, internal, class): like MetricThread, for ISO trapezoidal
+// threads like "Tr8" or "Tr8x2", with classes defaulting to 7e and 7H.
+pub fn trapezoidal_thread(
+    name: &str,
+    length: Float,
+    internal: bool,
+    class: Option<&str>,
+) -> Result<LObject, PrimitiveError> {
+    let (diameter, pitch) =
+        parse(name, "Tr", &TRAPEZOIDAL_PITCHES).ok_or_else(|| PrimitiveError::UnknownName {
+            function: "TrapezoidalThread",
+            name: name.to_string(),
+            expected: "a trapezoidal thread like Tr8 or Tr8x2",
+        })?;
+    let default = if internal { "7H" } else { "7e" };
+    let offset = class_offset(
+        "TrapezoidalThread",
+        class.unwrap_or(default),
+        diameter,
+        pitch,
+        internal,
+    )?;
+    new_thread(
+        "TrapezoidalThread",
+        trapezoidal_profile(pitch),
+        diameter / 2. + offset,
+        0.,
+        length,
+    )
+}
+
+// PipeThread(name, length): a tapered NPT pipe thread like "1/4" from z = 0 to
+// length, which has the outside diameter of the pipe at length and narrows towards z = 0.
+pub fn pipe_thread(name: &str, length: Float) -> Result<LObject, PrimitiveError> {
+    let &(_, diameter, threads_per_inch) = PIPE_SIZES
+        .iter()
+        .find(|&&(n, _, _)| n == name)
+        .ok_or_else(|| PrimitiveError::UnknownName {
+            function: "PipeThread",
+            name: name.to_string(),
+            expected: "one of 1/8, 1/4, 3/8, 1/2, 3/4 or 1",
+        })?;
+    let length = error::positive("PipeThread", "length", length)?;
+    let pitch = 25.4 / threads_per_inch;
+    new_thread(
+        "PipeThread",
+        pipe_profile(pitch),
+        diameter / 2. - length * PIPE_TAPER,
+        PIPE_TAPER,
+        length,
+    )
+}
+
+fn new_thread(
+    function: &'static str,
+    profile: Vec<na::Point2<Float>>,
+    radius: Float,
+    taper: Float,
+    length: Float,
+) -> Result<LObject, PrimitiveError> {
+    let length = error::positive(function, "length", length)?;
+    if profile[profile.len() - 1].x >= length {
+        return Err(PrimitiveError::InvalidParameter {
+            function,
+            parameter: "length",
+            value: length,
+            requirement: "longer than the pitch",
+        });
+    }
+    let thread = LObject::new(
+        Thread::new(profile.clone(), radius, taper, length),
+        Node::Thread {
+            profile,
+            radius,
+            taper,
+            length,
+        },
+    );
+    let mut objects = vec![thread];
+    objects.extend(parts::slab(0., length));
+    parts::boolean(&objects).intersection(0.)
+}
+
+// A single start right-hand thread around the z axis, from z = 0 to length. The value is the
+// distance to the profile in the plane through the axis and the point, where the thread moves
+// up by the pitch for each turn.
+#[derive(Clone, Debug)]
+pub struct Thread {
+    // See metric_profile. The last point is at the pitch.
+    profile: Vec<na::Point2<Float>>,
+    pitch: Float,
+    // The radius of the crest at z = 0, which grows by taper per unit of z.
+    radius: Float,
+    taper: Float,
+    length: Float,
+    // Moving around the axis moves along the profile by up to this factor more, at the root.
+    // Values are divided by it to remain lower bounds.
+    stretch: Float,
+    // Step of the finite differences of normals.
+    epsilon: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl Thread {
+    pub fn new(
+        profile: Vec<na::Point2<Float>>,
+        radius: Float,
+        taper: Float,
+        length: Float,
+    ) -> Box<Thread> {
+        let pitch = profile[profile.len() - 1].x;
+        let depth = profile
+            .iter()
+            .map(|p| -p.y)
+            .fold(Float::NEG_INFINITY, Float::max);
+        let root = (radius - depth).max(pitch);
+        let outer = radius + taper * length;
+        Box::new(Thread {
+            profile,
+            pitch,
+            radius,
+            taper,
+            length,
+            stretch: (1. + (pitch / (2. * PI * root)).powi(2) + taper * taper).sqrt(),
+            epsilon: pitch * RELATIVE_STEP,
+            bbox: BoundingBox::new(
+                &na::Point3::new(-outer, -outer, 0.),
+                &na::Point3::new(outer, outer, length),
+            ),
+        })
+    }
+    fn value(&self, p: &na::Point3<Float>) -> Float {
+        let r = p.x.hypot(p.y);
+        let crest = self.radius + self.taper * p.z.clamp(0., self.length);
+        let u = (p.z - self.pitch * p.y.atan2(p.x) / (2. * PI)).rem_euclid(self.pitch);
+        let q = na::Point2::new(u, r - crest);
+        let mut distance = Float::INFINITY;
+        let mut inside = false;
+        // The neighbouring pitches may be closer.
+        for &shift in &[-self.pitch, 0., self.pitch] {
+            for w in self.profile.windows(2) {
+                let a = w[0] + na::Vector2::new(shift, 0.);
+                let ab = w[1] - w[0];
+                let t = ((q - a).dot(&ab) / ab.norm_squared()).clamp(0., 1.);
+                distance = distance.min((q - (a + ab * t)).norm());
+                if shift == 0. && a.x <= u && u <= w[1].x && ab.x > 0. {
+                    inside = q.y < a.y + ab.y * (u - a.x) / ab.x;
+                }
+            }
+        }
+        if inside {
+            -distance / self.stretch
+        } else {
+            distance / self.stretch
+        }
+    }
+}
+
+impl Object<Float> for Thread {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        // The distance to the cylinder around the crests is at most the distance to the thread.
+        let approx = p.x.hypot(p.y) - self.bbox.max.x;
+        if approx > slack {
+            approx
+        } else {
+            self.value(p)
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, _: &PrimitiveParameters<Float>) {}
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let e = self.epsilon;
+        let difference = |d: na::Vector3<Float>| self.value(&(p + d)) - self.value(&(p - d));
+        na::Vector3::new(
+            difference(na::Vector3::x() * e),
+            difference(na::Vector3::y() * e),
+            difference(na::Vector3::z() * e),
+        )
+        .try_normalize(0.)
+        .unwrap_or_else(na::Vector3::x)
+    }
+}
+
+// The class is optional, see export_factories.
+fn class(class: hlua::AnyLuaValue) -> Option<String> {
+    match class {
+        hlua::AnyLuaValue::LuaString(class) => Some(class),
+        _ => None,
+    }
+}
+
+// See api.
+pub const API: &[Function] = &[
+    Function {
+        name: "MetricThread",
+        args: Some("name, length, internal, class"),
+        doc: "ISO metric thread like \"M8\" or \"M8x1\" from z = 0 to length. Internal threads are the space to subtract for a nut. class defaults to 6g or 6H.",
+    },
+    Function {
+        name: "TrapezoidalThread",
+        args: Some("name, length, internal, class"),
+        doc: "Trapezoidal thread like \"Tr8\", like MetricThread. class defaults to 7e or 7H.",
+    },
+    Function {
+        name: "PipeThread",
+        args: Some("name, length"),
+        doc: "Tapered NPT pipe thread like \"1/4\" from z = 0 to length.",
+    },
+];
+
+pub fn export_factories(lua: &mut hlua::Lua, env_name: &str) {
+    lua.set(
+        "__MetricThread",
+        hlua::function4(
+            |name: String, length: Float, internal: bool, c: hlua::AnyLuaValue| {
+                metric_thread(&name, length, internal, class(c).as_ref().map(|c| &c[..]))
+            },
+        ),
+    );
+    lua.set(
+        "__TrapezoidalThread",
+        hlua::function4(
+            |name: String, length: Float, internal: bool, c: hlua::AnyLuaValue| {
+                trapezoidal_thread(&name, length, internal, class(c).as_ref().map(|c| &c[..]))
+            },
+        ),
+    );
+    // Pipe threads seal on their taper, so internal ones are the same as external ones.
+    lua.set(
+        "PipeThread",
+        hlua::function2(|name: String, length: Float| pipe_thread(&name, length)),
+    );
+    // hlua reads missing arguments from the wrong places if more than the last one is
+    // optional, so all of them are passed on by Lua.
+    lua.execute::<()>(&format!(
+        "
+        function MetricThread(name, length, internal, class)
+          return __MetricThread(name, length, internal or false, class)
+        end
+        function TrapezoidalThread(name, length, internal, class)
+          return __TrapezoidalThread(name, length, internal or false, class)
+        end
+        {env}.MetricThread = MetricThread
+        {env}.TrapezoidalThread = TrapezoidalThread
+        {env}.PipeThread = PipeThread
+        ",
+        env = env_name
+    ))
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The angle of the flank from the crest to the root of profile to the radial direction, in
+    // degrees, and the widths of the crest and the root.
+    fn shape(profile: &[na::Point2<Float>]) -> (Float, Float, Float) {
+        let flank = profile[2] - profile[1];
+        let pitch = profile[5].x;
+        (
+            (flank.x / -flank.y).atan().to_degrees(),
+            profile[1].x + pitch - profile[4].x,
+            profile[3].x - profile[2].x,
+        )
+    }
+
+    #[test]
+    fn profiles_have_their_flank_angles() {
+        let (angle, crest, root) = shape(&metric_profile(2.));
+        assert!((angle - 30.).abs() < 1e-4, "{}", angle);
+        assert!((crest - 2. / 8.).abs() < 1e-4 && (root - 2. / 4.).abs() < 1e-4);
+        let depth = -metric_profile(2.)[2].y;
+        assert!(
+            (depth - 5. / 8. * Float::sqrt(3.)).abs() < 1e-6,
+            "{}",
+            depth
+        );
+        let (angle, crest, root) = shape(&trapezoidal_profile(4.));
+        assert!((angle - 15.).abs() < 1e-4, "{}", angle);
+        assert!((crest - root).abs() < 1e-4 && -trapezoidal_profile(4.)[2].y == 2.);
+        let (angle, _, _) = shape(&pipe_profile(25.4 / 18.));
+        assert!((angle - 30.).abs() < 1e-4, "{}", angle);
+    }
+
+    #[test]
+    fn names_give_the_pitch() {
+        assert_eq!(parse("M8", "M", &METRIC_PITCHES), Some((8., 1.25)));
+        assert_eq!(parse("M8x1", "M", &METRIC_PITCHES), Some((8., 1.)));
+        assert_eq!(parse("Tr12", "Tr", &TRAPEZOIDAL_PITCHES), Some((12., 3.)));
+        assert_eq!(parse("M7", "M", &METRIC_PITCHES), None);
+        assert_eq!(parse("M8x8", "M", &METRIC_PITCHES), None);
+        assert_eq!(parse("Tr8", "M", &METRIC_PITCHES), None);
+    }
+
+    #[test]
+    fn classes_follow_iso_965() {
+        // M8 6g: es = -28 and Td2 = 118 micrometers, 6H: EI = 0 and TD2 = 160, in the middle of
+        // the zone on the radius.
+        let external = class_offset("test", "6g", 8., 1.25, false).unwrap();
+        assert!(
+            (external - (-28. - 118. / 2.) / 2000.).abs() < 0.002,
+            "{}",
+            external
+        );
+        let internal = class_offset("test", "6H", 8., 1.25, true).unwrap();
+        assert!((internal - 160. / 2. / 2000.).abs() < 0.002, "{}", internal);
+        // Finer grades have narrower zones, and h sits on the basic profile.
+        let offset = |class| class_offset("test", class, 8., 1.25, false).unwrap();
+        assert!(offset("4h") > offset("6h") && offset("6h") > offset("8h"));
+        assert!(offset("6h") > offset("6g") && offset("6g") > offset("6f"));
+        assert!(offset("6f") > offset("6e"));
+        for &(class, internal) in &[
+            ("6G", false),
+            ("6g", true),
+            ("2g", false),
+            ("6gg", false),
+            ("g", false),
+            ("", true),
+        ] {
+            assert!(
+                class_offset("test", class, 8., 1.25, internal).is_err(),
+                "{}",
+                class
+            );
+        }
+    }
+
+    #[test]
+    fn threads_have_their_diameter() {
+        let bolt = metric_thread("M8", 10., false, None).unwrap();
+        let value = |x: Float| bolt.object().approx_value(&na::Point3::new(x, 0., 5.), 0.);
+        assert!(value(0.) < 0. && value(3.) < 0. && value(4.1) > 0.);
+        let nut = metric_thread("M8", 10., true, None).unwrap();
+        assert!(nut.object().bbox().max.x > bolt.object().bbox().max.x);
+        assert!(metric_thread("M8", 1., false, None).is_err());
+        let pipe = pipe_thread("1/4", 10.).unwrap();
+        // Five turns further up, the thread is at the same place of its profile.
+        let pitch = 25.4 / 18.;
+        let radius = |z: Float| {
+            (0..1000)
+                .map(|i| i as Float * 0.01)
+                .take_while(|&x| pipe.object().approx_value(&na::Point3::new(x, 0., z), 0.) < 0.)
+                .count() as Float
+                * 0.01
+        };
+        let taper = (radius(1. + 5. * pitch) - radius(1.)) / (5. * pitch);
+        assert!((taper - PIPE_TAPER).abs() < 0.01 / pitch, "{}", taper);
+    }
+}
@@ -0,0 +1,130 @@
+use super::Float;
+use bounded;
+use implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use nalgebra as na;
+
+// implicit3d's AffineTransformer, for objects with infinite bounding boxes (like iCylinder or
+// planes). AffineTransformer transforms the corners of the box, which gives NaNs (and then an
+// empty box, so the object vanishes) as soon as an infinite extent is multiplied by zero, e.g.
+// for any translation. This one transforms boxes with bounded::transform instead.
+#[derive(Clone, Debug)]
+pub struct Transformer {
+    object: Box<dyn Object<Float>>,
+    // Maps points to those the object is evaluated at.
+    transform: na::Matrix4<Float>,
+    transposed3x3: na::Matrix3<Float>,
+    scale_min: Float,
+    bbox: BoundingBox<Float>,
+}
+
+impl Transformer {
+    fn new(
+        object: Box<dyn Object<Float>>,
+        transform: na::Matrix4<Float>,
+        scale_min: Float,
+    ) -> Box<Transformer> {
+        // Scales are checked by LObject::scale, so this can be inverted.
+        let inverse = transform.try_inverse().expect("singular transformation");
+        let bbox = bounded::transform(object.bbox(), &inverse);
+        let transposed3x3 = transform.fixed_slice::<na::U3, na::U3>(0, 0).transpose();
+        Box::new(Transformer {
+            object,
+            transform,
+            transposed3x3,
+            scale_min,
+            bbox,
+        })
+    }
+}
+
+impl Object<Float> for Transformer {
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx <= slack {
+            self.object
+                .approx_value(&self.transform.transform_point(p), slack / self.scale_min)
+                * self.scale_min
+        } else {
+            approx
+        }
+    }
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        let normal = self.object.normal(&self.transform.transform_point(p));
+        (self.transposed3x3 * normal).normalize()
+    }
+    // Like AffineTransformer, so that the object matches tree::Node.
+    fn translate(&self, v: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+        Transformer::new(
+            self.object.clone(),
+            self.transform.prepend_translation(&-v),
+            self.scale_min,
+        )
+    }
+    fn rotate(&self, r: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+        let euler = na::Rotation3::from_euler_angles(r.x, r.y, r.z).to_homogeneous();
+        Transformer::new(self.object.clone(), self.transform * euler, self.scale_min)
+    }
+    fn scale(&self, s: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+        Transformer::new(
+            self.object.clone(),
+            self.transform.prepend_nonuniform_scaling(&na::Vector3::new(
+                1. / s.x,
+                1. / s.y,
+                1. / s.z,
+            )),
+            self.scale_min * s.x.min(s.y.min(s.z)),
+        )
+    }
+}
+
+// object translated, rotated or scaled. These use the transformations of object, unless they
+// lose some of it, as AffineTransformer does for infinite objects.
+pub fn translate(object: &dyn Object<Float>, v: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+    transformed(object, &na::Matrix4::new_translation(v), |o| o.translate(v))
+}
+
+pub fn rotate(object: &dyn Object<Float>, r: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+    let rotation = na::Rotation3::from_euler_angles(r.x, r.y, r.z).inverse();
+    transformed(object, &rotation.to_homogeneous(), |o| o.rotate(r))
+}
+
+pub fn scale(object: &dyn Object<Float>, s: &na::Vector3<Float>) -> Box<dyn Object<Float>> {
+    transformed(object, &na::Matrix4::new_nonuniform_scaling(s), |o| {
+        o.scale(s)
+    })
+}
+
+// m maps the points of object to those of the transformed object.
+fn transformed<F>(
+    object: &dyn Object<Float>,
+    m: &na::Matrix4<Float>,
+    transform: F,
+) -> Box<dyn Object<Float>>
+where
+    F: Fn(&dyn Object<Float>) -> Box<dyn Object<Float>>,
+{
+    let result = transform(object);
+    if bounded::is_finite(object.bbox()) || bounded::is_empty(object.bbox()) {
+        return result;
+    }
+    // The corners of a box which is infinite in some direction give NaNs for directions which
+    // are finite, or finite values for directions which are infinite.
+    let (expected, bbox) = (bounded::transform(object.bbox(), m), result.bbox());
+    let lost = (0..3).any(|i| {
+        bbox.min[i] > bbox.max[i]
+            || (expected.min[i].is_infinite() && bbox.min[i].is_finite())
+            || (expected.max[i].is_infinite() && bbox.max[i].is_finite())
+    });
+    if lost {
+        let identity = Transformer::new(object.clone_box(), na::Matrix4::identity(), 1.);
+        transform(&*identity)
+    } else {
+        result
+    }
+}
@@ -0,0 +1,757 @@
+use super::Float;
+use analysis::Components;
+use bounded;
+use edge::Finish;
+use float::consts::PI;
+use implicit3d::{BoundingBox, Object};
+use infill::Pattern;
+use morphology::Operation;
+use nalgebra as na;
+use outline;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::slice;
+use std::sync::Arc;
+
+// Description of how an object was built by the Lua script.
+// implicit3d objects can not be inspected, so this is recorded alongside them. It allows to
+// reproduce the distance function outside of implicit3d, e.g. for code generation.
+// Values derived from bounding boxes are stored precomputed, exactly as implicit3d uses them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Sphere {
+        radius: Float,
+    },
+    // Infinite along z.
+    Cylinder {
+        radius: Float,
+    },
+    // Double cone around the z axis, with its tip at z = -offset.
+    Cone {
+        slope: Float,
+        offset: Float,
+    },
+    // normal . p - p
+    Plane {
+        normal: na::Vector3<Float>,
+        p: Float,
+    },
+    // Smooth boolean operations, smooth is the rounding radius.
+    Union {
+        children: Vec<Node>,
+        smooth: Float,
+    },
+    Intersection {
+        children: Vec<Node>,
+        smooth: Float,
+    },
+    // The first child minus all others.
+    Difference {
+        children: Vec<Node>,
+        smooth: Float,
+    },
+    // Two children combined by op with the edges between them finished, see edge::Edge.
+    Edge {
+        children: Vec<Node>,
+        op: Boolean,
+        finish: Finish,
+    },
+    // A union or difference of the children whose tessellations are combined instead of
+    // tessellating the boolean, for edges between them as sharp as the meshes (see the
+    // mesh_boolean module of truescad). Otherwise it is the boolean without smoothing.
+    Sharp {
+        children: Vec<Node>,
+        op: Boolean,
+    },
+    // The sharp and the blended version of a boolean, and the region where the blended one is
+    // used, see blend::LocalBlend.
+    LocalBlend {
+        children: Vec<Node>,
+        width: Float,
+    },
+    // The child is evaluated at transform * p and its value multiplied by scale.
+    Transform {
+        child: Box<Node>,
+        transform: na::Matrix4<Float>,
+        scale: Float,
+    },
+    Twist {
+        child: Box<Node>,
+        // 2 * pi / height for a full rotation
+        height_scaler: Float,
+        value_scaler: Float,
+    },
+    Bend {
+        child: Box<Node>,
+        // width for a full rotation / (2 * pi)
+        width_scaler: Float,
+        // Minimum y of the child. Closer to the z axis, the distance to this radius is used.
+        inner_radius: Float,
+    },
+    // One of the parts of the child found by analysis::components, see component::Component.
+    Component {
+        child: Box<Node>,
+        components: Arc<Components>,
+        part: usize,
+    },
+    // Cross section of an involute gear around the z axis, infinite along z and turned by
+    // twist radians per unit of z, see parts::GearProfile. pressure_angle is in radians.
+    Gear {
+        module: Float,
+        teeth: usize,
+        pressure_angle: Float,
+        twist: Float,
+    },
+    // Thread around the z axis from z = 0 to length, see threads::Thread.
+    Thread {
+        profile: Vec<na::Point2<Float>>,
+        radius: Float,
+        taper: Float,
+        length: Float,
+    },
+    // Walls around cells of the pattern, infinite in all directions, see infill::Lattice.
+    Lattice {
+        pattern: Pattern,
+        cell: Float,
+        wall: Float,
+    },
+    // The child with edges rounded by the radius, see morphology::Morphology.
+    Morphology {
+        child: Box<Node>,
+        op: Operation,
+        radius: Float,
+    },
+    // The child grown by the distance, or shrunk if it is negative, see morphology::Offset.
+    Offset {
+        child: Box<Node>,
+        distance: Float,
+    },
+    // Closed polygons in the xy plane, infinite along z, see outline::Outline.
+    Outline {
+        contours: Vec<Vec<na::Point2<Float>>>,
+    },
+    // Triangle mesh loaded from a file.
+    Mesh {
+        filename: String,
+    },
+    // Surface through the points of a file, see pointcloud::PointCloud.
+    PointCloud {
+        filename: String,
+        radius: Float,
+    },
+    // A primitive of a plugin, built by the function of the provider called provider with the
+    // arguments, see plugin::ObjectProvider.
+    Plugin {
+        provider: String,
+        function: String,
+        arguments: Vec<Float>,
+    },
+    // The empty set and everything. Booleans and transformations of them are resolved when
+    // built (see Node::resolve), so they only remain as the root or the first child of a
+    // Difference.
+    Empty,
+    Full,
+}
+
+// The boolean operations, for Node::resolve.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boolean {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Boolean {
+    // The node of this boolean of children, smoothed by smooth.
+    pub fn node(self, children: Vec<Node>, smooth: Float) -> Node {
+        match self {
+            Boolean::Union => Node::Union { children, smooth },
+            Boolean::Intersection => Node::Intersection { children, smooth },
+            Boolean::Difference => Node::Difference { children, smooth },
+        }
+    }
+}
+
+impl Node {
+    // Structural equality: same kinds of nodes with the same parameters and children.
+    // Unlike ==, floats are compared bitwise, so that NaN equals itself and the result is
+    // consistent with tree_hash.
+    pub fn tree_eq(&self, other: &Node) -> bool {
+        if mem::discriminant(self) != mem::discriminant(other) {
+            return false;
+        }
+        if let (Node::Mesh { filename: a }, Node::Mesh { filename: b }) = (self, other) {
+            return a == b;
+        }
+        if let (
+            Node::PointCloud {
+                filename: a,
+                radius: r,
+            },
+            Node::PointCloud {
+                filename: b,
+                radius: s,
+            },
+        ) = (self, other)
+        {
+            return a == b && r.to_bits() == s.to_bits();
+        }
+        if let (
+            Node::Plugin {
+                provider: a,
+                function: f,
+                ..
+            },
+            Node::Plugin {
+                provider: b,
+                function: g,
+                ..
+            },
+        ) = (self, other)
+        {
+            if a != b || f != g {
+                return false;
+            }
+        }
+        let (a, b) = (self.parameters(), other.parameters());
+        let (children, other_children) = (self.children(), other.children());
+        a.len() == b.len()
+            && a.iter().zip(&b).all(|(x, y)| x.to_bits() == y.to_bits())
+            && children.len() == other_children.len()
+            && children
+                .iter()
+                .zip(other_children)
+                .all(|(c, o)| c.tree_eq(o))
+    }
+    // Hash of the structure compared by tree_eq.
+    pub fn tree_hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        if let Node::Mesh { ref filename } | Node::PointCloud { ref filename, .. } = *self {
+            filename.hash(state);
+        }
+        if let Node::Plugin {
+            ref provider,
+            ref function,
+            ..
+        } = *self
+        {
+            provider.hash(state);
+            function.hash(state);
+        }
+        for x in self.parameters() {
+            x.to_bits().hash(state);
+        }
+        let children = self.children();
+        children.len().hash(state);
+        for child in children {
+            child.tree_hash(state);
+        }
+    }
+    // The children of booleans and transformations.
+    pub fn children(&self) -> &[Node] {
+        match *self {
+            Node::Union { ref children, .. }
+            | Node::Intersection { ref children, .. }
+            | Node::Difference { ref children, .. }
+            | Node::Edge { ref children, .. }
+            | Node::Sharp { ref children, .. }
+            | Node::LocalBlend { ref children, .. } => children,
+            Node::Transform { ref child, .. }
+            | Node::Twist { ref child, .. }
+            | Node::Bend { ref child, .. }
+            | Node::Component { ref child, .. }
+            | Node::Morphology { ref child, .. }
+            | Node::Offset { ref child, .. } => slice::from_ref(&**child),
+            _ => &[],
+        }
+    }
+    // The name of the kind of this node, e.g. for profiles.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Node::Sphere { .. } => "sphere",
+            Node::Cylinder { .. } => "cylinder",
+            Node::Cone { .. } => "cone",
+            Node::Plane { .. } => "plane",
+            Node::Union { .. } => "union",
+            Node::Intersection { .. } => "intersection",
+            Node::Difference { .. } => "difference",
+            Node::Edge { .. } => "edge",
+            Node::Sharp { .. } => "sharp boolean",
+            Node::LocalBlend { .. } => "local blend",
+            Node::Transform { .. } => "transform",
+            Node::Twist { .. } => "twist",
+            Node::Bend { .. } => "bend",
+            Node::Component { .. } => "part",
+            Node::Gear { .. } => "gear",
+            Node::Thread { .. } => "thread",
+            Node::Lattice { .. } => "infill",
+            Node::Morphology { .. } => "smooth",
+            Node::Offset { .. } => "offset",
+            Node::Outline { .. } => "outline",
+            Node::Mesh { .. } => "mesh",
+            Node::PointCloud { .. } => "point cloud",
+            Node::Plugin { .. } => "plugin",
+            Node::Empty => "empty",
+            Node::Full => "full",
+        }
+    }
+    // Short description of this node without its children, e.g. for a tree view.
+    pub fn label(&self) -> String {
+        match *self {
+            Node::Sphere { radius } => format!("sphere r={}", radius),
+            Node::Cylinder { radius } => format!("cylinder r={}", radius),
+            Node::Cone { slope, .. } => format!("cone slope={}", slope),
+            Node::Plane { ref normal, p } => {
+                format!("plane ({}, {}, {}) {}", normal.x, normal.y, normal.z, p)
+            }
+            Node::Union { smooth, .. } => format!("union smooth={}", smooth),
+            Node::Intersection { smooth, .. } => format!("intersection smooth={}", smooth),
+            Node::Difference { smooth, .. } => format!("difference smooth={}", smooth),
+            Node::Edge { op, finish, .. } => format!("{:?} {:?}", op, finish).to_lowercase(),
+            Node::Sharp { op, .. } => format!("sharp {:?}", op).to_lowercase(),
+            Node::LocalBlend { width, .. } => format!("local blend width={}", width),
+            Node::Transform { .. } => "transform".to_string(),
+            Node::Twist { height_scaler, .. } => {
+                format!("twist height={}", 2. * PI / height_scaler)
+            }
+            Node::Bend { width_scaler, .. } => format!("bend width={}", width_scaler * 2. * PI),
+            Node::Component { part, .. } => format!("part {}", part + 1),
+            Node::Gear { module, teeth, .. } => format!("gear module={} teeth={}", module, teeth),
+            Node::Thread { radius, length, .. } => format!("thread r={} length={}", radius, length),
+            Node::Lattice { pattern, cell, .. } => {
+                format!("{:?} infill cell={}", pattern, cell).to_lowercase()
+            }
+            Node::Morphology { op, radius, .. } => format!("{:?} r={}", op, radius).to_lowercase(),
+            Node::Offset { distance, .. } => format!("offset d={}", distance),
+            Node::Outline { ref contours } => format!("outline of {} contours", contours.len()),
+            Node::Mesh { ref filename } => format!("mesh {}", filename),
+            Node::PointCloud { ref filename, .. } => format!("point cloud {}", filename),
+            Node::Plugin {
+                ref function,
+                ref arguments,
+                ..
+            } => format!("{} {:?}", function, arguments),
+            Node::Empty => "empty".to_string(),
+            Node::Full => "full".to_string(),
+        }
+    }
+    // A box around the object described by this node. Unlike the boxes of the implicit3d
+    // objects, it is not always tight, e.g. for transformed booleans, see optimize::bbox for a
+    // tighter one. Meshes and point clouds are not loaded, so their box is infinite, as is that
+    // of primitives of plugins.
+    pub fn bbox(&self) -> BoundingBox<Float> {
+        let children = self.children();
+        let child = || children[0].bbox();
+        let around_z = |r: Float, min_z: Float, max_z: Float| {
+            BoundingBox::new(
+                &na::Point3::new(-r, -r, min_z),
+                &na::Point3::new(r, r, max_z),
+            )
+        };
+        match *self {
+            Node::Sphere { radius } => around_z(radius, -radius, radius),
+            Node::Cylinder { radius } => around_z(radius, Float::NEG_INFINITY, Float::INFINITY),
+            Node::Union { .. }
+            | Node::Sharp {
+                op: Boolean::Union, ..
+            } => children
+                .iter()
+                .fold(BoundingBox::neg_infinity(), |b, c| b.union(&c.bbox())),
+            Node::Intersection { .. }
+            | Node::Sharp {
+                op: Boolean::Intersection,
+                ..
+            } => children
+                .iter()
+                .fold(BoundingBox::infinity(), |b, c| b.intersection(&c.bbox())),
+            Node::Difference { .. }
+            | Node::Sharp {
+                op: Boolean::Difference,
+                ..
+            }
+            | Node::Morphology { .. } => child(),
+            Node::Offset { distance, .. } => {
+                let mut bbox = child();
+                bbox.dilate(distance.max(0.));
+                bbox
+            }
+            // Like edge::Edge.
+            Node::Edge { op, finish, .. } => match op {
+                Boolean::Union => {
+                    let mut bbox = child().union(&children[1].bbox());
+                    bbox.dilate(finish.size());
+                    bbox
+                }
+                Boolean::Intersection => child().intersection(&children[1].bbox()),
+                Boolean::Difference => child(),
+            },
+            Node::LocalBlend { .. } => child().union(&children[1].bbox()),
+            Node::Transform { .. } | Node::Twist { .. } | Node::Bend { .. } => {
+                self.enclose(&child())
+            }
+            Node::Component {
+                ref components,
+                part,
+                ..
+            } => components.bbox(part).intersection(&child()),
+            Node::Gear { module, teeth, .. } => around_z(
+                module * (teeth as Float / 2. + 1.),
+                Float::NEG_INFINITY,
+                Float::INFINITY,
+            ),
+            Node::Thread {
+                radius,
+                taper,
+                length,
+                ..
+            } => {
+                let outer = radius + taper * length;
+                around_z(outer, 0., length)
+            }
+            Node::Outline { ref contours } => outline::outline_bbox(contours),
+            Node::Empty => BoundingBox::neg_infinity(),
+            Node::Cone { .. }
+            | Node::Plane { .. }
+            | Node::Lattice { .. }
+            | Node::Mesh { .. }
+            | Node::PointCloud { .. }
+            | Node::Plugin { .. }
+            | Node::Full => BoundingBox::infinity(),
+        }
+    }
+    // The box around bbox, a box in the coordinates of the child of a transformation, twist or
+    // bend, in the coordinates of this node.
+    fn enclose(&self, bbox: &BoundingBox<Float>) -> BoundingBox<Float> {
+        let around_z = |r: Float| {
+            BoundingBox::new(
+                &na::Point3::new(-r, -r, bbox.min.z),
+                &na::Point3::new(r, r, bbox.max.z),
+            )
+        };
+        match *self {
+            Node::Transform { ref transform, .. } => match transform.try_inverse() {
+                Some(inverse) => bounded::transform(bbox, &inverse),
+                None => BoundingBox::infinity(),
+            },
+            Node::Twist { .. } => {
+                let mx = bbox.min.x.abs().max(bbox.max.x.abs());
+                let my = bbox.min.y.abs().max(bbox.max.y.abs());
+                around_z(mx.hypot(my))
+            }
+            // The child is wrapped around the z axis, its y becoming the radius.
+            Node::Bend { .. } => around_z(bbox.max.y),
+            _ => bbox.clone(),
+        }
+    }
+    // The node at path (see Node::without) and its box in the coordinates of this node, e.g. to
+    // show where it is in the whole object.
+    pub fn subtree(&self, path: &[usize]) -> Option<(&Node, BoundingBox<Float>)> {
+        match path.split_first() {
+            None => Some((self, self.bbox())),
+            Some((&i, rest)) => {
+                let (node, bbox) = self.children().get(i)?.subtree(rest)?;
+                Some((node, self.enclose(&bbox)))
+            }
+        }
+    }
+    // This tree with the subtrees at paths replaced by Empty, e.g. to hide parts of an object.
+    // A path lists the index of the child (see Node::children) on each level below this node.
+    // Booleans are resolved as when built by a script (see Node::resolve), so the result can be
+    // flattened or compiled like the original tree.
+    pub fn without(&self, paths: &[Vec<usize>]) -> Node {
+        if paths.is_empty() {
+            return self.clone();
+        }
+        if paths.iter().any(Vec::is_empty) {
+            return Node::Empty;
+        }
+        let children: Vec<Node> = self
+            .children()
+            .iter()
+            .enumerate()
+            .map(|(i, child)| {
+                let below: Vec<Vec<usize>> = paths
+                    .iter()
+                    .filter(|path| path[0] == i)
+                    .map(|path| path[1..].to_vec())
+                    .collect();
+                child.without(&below)
+            })
+            .collect();
+        let boolean = |op: Boolean, children: Vec<Node>, node: &dyn Fn(Vec<Node>) -> Node| {
+            match Node::resolve(op, children, |c| c) {
+                Ok(mut children) => {
+                    if children.len() == 1 {
+                        children.pop().unwrap()
+                    } else {
+                        node(children)
+                    }
+                }
+                Err(constant) => *constant,
+            }
+        };
+        match *self {
+            Node::Union { smooth, .. } => boolean(Boolean::Union, children, &|children| {
+                Node::Union { children, smooth }
+            }),
+            Node::Intersection { smooth, .. } => {
+                boolean(Boolean::Intersection, children, &|children| {
+                    Node::Intersection { children, smooth }
+                })
+            }
+            Node::Difference { smooth, .. } => {
+                boolean(Boolean::Difference, children, &|children| {
+                    Node::Difference { children, smooth }
+                })
+            }
+            // Without one of the two objects, there is no edge to finish.
+            Node::Edge { op, finish, .. } => boolean(op, children, &|children| Node::Edge {
+                children,
+                op,
+                finish,
+            }),
+            Node::Sharp { op, .. } => {
+                boolean(op, children, &|children| Node::Sharp { children, op })
+            }
+            // The sharp and the blended child are the same boolean, the region only selects
+            // between them.
+            Node::LocalBlend { width, .. } => {
+                if children[0].is_constant() {
+                    children[0].clone()
+                } else if children[1].is_constant() {
+                    children[1].clone()
+                } else if children[2] == Node::Empty {
+                    children[0].clone()
+                } else if children[2] == Node::Full {
+                    children[1].clone()
+                } else {
+                    Node::LocalBlend { children, width }
+                }
+            }
+            // Like the script functions, transformations and the like keep Empty and Full.
+            _ => match children.into_iter().next() {
+                Some(child) => {
+                    if child.is_constant() {
+                        child
+                    } else {
+                        self.with_child(child)
+                    }
+                }
+                None => self.clone(),
+            },
+        }
+    }
+    // This node with its only child replaced.
+    fn with_child(&self, new_child: Node) -> Node {
+        let mut node = self.clone();
+        match node {
+            Node::Transform { ref mut child, .. }
+            | Node::Twist { ref mut child, .. }
+            | Node::Bend { ref mut child, .. }
+            | Node::Component { ref mut child, .. }
+            | Node::Morphology { ref mut child, .. }
+            | Node::Offset { ref mut child, .. } => **child = new_child,
+            _ => {}
+        }
+        node
+    }
+    // The numeric parameters of this node, without those of its children.
+    fn parameters(&self) -> Vec<Float> {
+        match *self {
+            Node::Sphere { radius } | Node::Cylinder { radius } => vec![radius],
+            Node::Cone { slope, offset } => vec![slope, offset],
+            Node::Plane { ref normal, p } => vec![normal.x, normal.y, normal.z, p],
+            Node::Union { smooth, .. }
+            | Node::Intersection { smooth, .. }
+            | Node::Difference { smooth, .. } => vec![smooth],
+            Node::Edge { op, finish, .. } => match finish {
+                Finish::Fillet(radius) => vec![op as usize as Float, 0., radius],
+                Finish::Chamfer(a, b) => vec![op as usize as Float, 1., a, b],
+            },
+            Node::Sharp { op, .. } => vec![op as usize as Float],
+            Node::LocalBlend { width, .. } => vec![width],
+            Node::Transform {
+                ref transform,
+                scale,
+                ..
+            } => {
+                let mut v: Vec<Float> = transform.iter().cloned().collect();
+                v.push(scale);
+                v
+            }
+            Node::Twist {
+                height_scaler,
+                value_scaler,
+                ..
+            } => vec![height_scaler, value_scaler],
+            Node::Bend {
+                width_scaler,
+                inner_radius,
+                ..
+            } => vec![width_scaler, inner_radius],
+            // The cells which make up the part.
+            Node::Component {
+                ref components,
+                part,
+                ..
+            } => {
+                let mut v = vec![
+                    components.origin.x,
+                    components.origin.y,
+                    components.origin.z,
+                    components.cell.x,
+                    components.cell.y,
+                    components.cell.z,
+                ];
+                for index in &components.parts[part] {
+                    v.extend(index.iter().map(|&i| i as Float));
+                }
+                v
+            }
+            Node::Gear {
+                module,
+                teeth,
+                pressure_angle,
+                twist,
+            } => vec![module, teeth as Float, pressure_angle, twist],
+            Node::Thread {
+                ref profile,
+                radius,
+                taper,
+                length,
+            } => {
+                let mut v = vec![radius, taper, length];
+                for p in profile {
+                    v.extend(&[p.x, p.y]);
+                }
+                v
+            }
+            Node::Lattice {
+                pattern,
+                cell,
+                wall,
+            } => vec![pattern as usize as Float, cell, wall],
+            Node::Morphology { op, radius, .. } => vec![op as usize as Float, radius],
+            Node::Offset { distance, .. } => vec![distance],
+            // The number of points of each contour separates them.
+            Node::Outline { ref contours } => {
+                let mut v = Vec::new();
+                for contour in contours {
+                    v.push(contour.len() as Float);
+                    for p in contour {
+                        v.extend(&[p.x, p.y]);
+                    }
+                }
+                v
+            }
+            Node::Plugin { ref arguments, .. } => arguments.clone(),
+            Node::PointCloud { radius, .. } => vec![radius],
+            Node::Mesh { .. } | Node::Empty | Node::Full => vec![],
+        }
+    }
+    pub fn is_constant(&self) -> bool {
+        matches!(*self, Node::Empty | Node::Full)
+    }
+    // Drop the children of a boolean which do not change its result. Returns Err with Empty or
+    // Full if the result is one of them regardless of the other children. node returns the
+    // Node of a child.
+    pub fn resolve<T, F>(op: Boolean, children: Vec<T>, node: F) -> Result<Vec<T>, Box<Node>>
+    where
+        F: Fn(&T) -> &Node,
+    {
+        let (neutral, absorbing) = match op {
+            Boolean::Union => (Node::Empty, Node::Full),
+            Boolean::Intersection => (Node::Full, Node::Empty),
+            Boolean::Difference => {
+                let mut children = children.into_iter();
+                let first = match children.next() {
+                    Some(first) => first,
+                    None => return Err(Box::new(Node::Empty)),
+                };
+                if *node(&first) == Node::Empty {
+                    return Err(Box::new(Node::Empty));
+                }
+                let mut kept = vec![first];
+                for child in children {
+                    match *node(&child) {
+                        Node::Empty => {}
+                        Node::Full => return Err(Box::new(Node::Empty)),
+                        _ => kept.push(child),
+                    }
+                }
+                return Ok(kept);
+            }
+        };
+        if children.iter().any(|c| *node(c) == absorbing) {
+            return Err(Box::new(absorbing));
+        }
+        let kept: Vec<T> = children
+            .into_iter()
+            .filter(|c| *node(c) != neutral)
+            .collect();
+        if kept.is_empty() {
+            Err(Box::new(neutral))
+        } else {
+            Ok(kept)
+        }
+    }
+    pub fn translate(&self, v: &na::Vector3<Float>) -> Node {
+        self.transformed(na::Matrix4::new_translation(&-v), 1.)
+    }
+    pub fn rotate(&self, r: &na::Vector3<Float>) -> Node {
+        self.transformed(
+            na::Rotation3::from_euler_angles(r.x, r.y, r.z).to_homogeneous(),
+            1.,
+        )
+    }
+    pub fn scale(&self, s: &na::Vector3<Float>) -> Node {
+        self.transformed(
+            na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(1. / s.x, 1. / s.y, 1. / s.z)),
+            s.x.min(s.y.min(s.z)),
+        )
+    }
+    // Like implicit3d, merge consecutive transformations into one.
+    fn transformed(&self, m: na::Matrix4<Float>, s: Float) -> Node {
+        match *self {
+            Node::Empty | Node::Full => self.clone(),
+            Node::Transform {
+                ref child,
+                ref transform,
+                scale,
+            } => Node::Transform {
+                child: child.clone(),
+                transform: transform * m,
+                scale: scale * s,
+            },
+            _ => Node::Transform {
+                child: Box::new(self.clone()),
+                transform: m,
+                scale: s,
+            },
+        }
+    }
+    pub fn twist(&self, object: &dyn Object<Float>, height: Float) -> Node {
+        if self.is_constant() {
+            return self.clone();
+        }
+        let bbox = object.bbox();
+        let mx = bbox.min.x.abs().max(bbox.max.x.abs());
+        let my = bbox.min.y.abs().max(bbox.max.y.abs());
+        let tan_a = height.abs() / (2. * PI * mx.hypot(my));
+        Node::Twist {
+            child: Box::new(self.clone()),
+            height_scaler: 2. * PI / height,
+            value_scaler: tan_a / (tan_a * tan_a + 1.).sqrt(),
+        }
+    }
+    pub fn bend(&self, object: &dyn Object<Float>, width: Float) -> Node {
+        if self.is_constant() {
+            return self.clone();
+        }
+        Node::Bend {
+            child: Box::new(self.clone()),
+            width_scaler: width / (2. * PI),
+            inner_radius: object.bbox().min.y,
+        }
+    }
+}
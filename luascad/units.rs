@@ -0,0 +1,45 @@
+use super::Float;
+use std::fmt;
+
+// The length unit of the coordinates of a script, set by it with set_units("mm"). Objects are
+// not converted, this only tells exporters and measurements what the coordinates mean.
+// The default is what slicers assume for files without units.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Units {
+    #[default]
+    Millimeter,
+    Centimeter,
+    Inch,
+}
+
+impl Units {
+    pub fn parse(name: &str) -> Option<Units> {
+        match name {
+            "mm" => Some(Units::Millimeter),
+            "cm" => Some(Units::Centimeter),
+            "in" | "inch" => Some(Units::Inch),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Units::Millimeter => "mm",
+            Units::Centimeter => "cm",
+            Units::Inch => "in",
+        }
+    }
+    // The length of one unit in millimeters.
+    pub fn millimeters(self) -> Float {
+        match self {
+            Units::Millimeter => 1.,
+            Units::Centimeter => 10.,
+            Units::Inch => 25.4,
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
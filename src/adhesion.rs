@@ -0,0 +1,169 @@
+use super::Float;
+use indexed_mesh::IndexedMesh;
+use na;
+use std::collections::HashSet;
+
+// Geometry added to exported meshes to keep them on the build plate: a brim of disks along the
+// footprint of the mesh at z = 0, and breakaway tabs which connect its separate parts there, so
+// that small parts do not come loose. Both are a layer of LAYER_HEIGHT, and overlap the mesh,
+// which slicers join with it.
+
+// In millimeters, converted to the units of the mesh.
+const LAYER_HEIGHT: Float = 0.3;
+// Sides of the polygons the disks of a brim are made of.
+const DISK_SEGMENTS: usize = 16;
+// The centers of the disks of a brim are at most this fraction of its width apart along the
+// footprint, so the outer edge of the brim is nearly smooth.
+const DISK_SPACING: Float = 0.5;
+
+type Segment = (na::Point2<Float>, na::Point2<Float>);
+
+// Add a brim and tabs of the widths to mesh, in its units, where they are positive. Returns false
+// if mesh has no footprint at z = 0, and gets nothing.
+pub fn add(mesh: &mut IndexedMesh, brim: Float, tabs: Float) -> bool {
+    if brim <= 0. && tabs <= 0. {
+        return true;
+    }
+    let height = LAYER_HEIGHT / mesh.units.millimeters();
+    let footprints: Vec<Vec<Segment>> = mesh
+        .split()
+        .iter()
+        .map(|part| footprint(part, height / 2.))
+        .filter(|f| !f.is_empty())
+        .collect();
+    if footprints.is_empty() {
+        return false;
+    }
+    let mut added = IndexedMesh::default();
+    if brim > 0. {
+        for center in disk_centers(&footprints, brim) {
+            let disk: Vec<na::Point2<Float>> = (0..DISK_SEGMENTS)
+                .map(|i| {
+                    let angle = i as Float / DISK_SEGMENTS as Float * 2. * ::float::consts::PI;
+                    center + na::Vector2::new(angle.cos(), angle.sin()) * brim
+                })
+                .collect();
+            add_prism(&mut added, &disk, height);
+        }
+    }
+    if tabs > 0. {
+        for (a, b) in connections(&footprints) {
+            let along = (b - a).try_normalize(0.).unwrap_or_else(na::Vector2::x);
+            let side = na::Vector2::new(-along.y, along.x) * (tabs / 2.);
+            // Into both parts by half the width, so they hold on to them.
+            let (a, b) = (a - along * (tabs / 2.), b + along * (tabs / 2.));
+            add_prism(
+                &mut added,
+                &[a - side, b - side, b + side, a + side],
+                height,
+            );
+        }
+    }
+    added.compute_vertex_normals();
+    mesh.append(&added);
+    true
+}
+
+// Where the faces of mesh cross the plane at z.
+fn footprint(mesh: &IndexedMesh, z: Float) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    for face in &mesh.faces {
+        let corners: Vec<&na::Point3<Float>> = face.iter().map(|&v| &mesh.vertices[v]).collect();
+        let mut crossings = Vec::new();
+        for i in 0..3 {
+            let (p, q) = (corners[i], corners[(i + 1) % 3]);
+            if (p.z < z) != (q.z < z) {
+                let t = (z - p.z) / (q.z - p.z);
+                let c = p + (q - p) * t;
+                crossings.push(na::Point2::new(c.x, c.y));
+            }
+        }
+        if crossings.len() == 2 {
+            segments.push((crossings[0], crossings[1]));
+        }
+    }
+    segments
+}
+
+// The centers of the disks of a brim of width along the footprints. Centers closer than a
+// fraction of the spacing to one already taken are left out, since segments share their ends.
+fn disk_centers(footprints: &[Vec<Segment>], width: Float) -> Vec<na::Point2<Float>> {
+    let spacing = width * DISK_SPACING;
+    let mut taken = HashSet::new();
+    let mut centers = Vec::new();
+    for &(a, b) in footprints.iter().flatten() {
+        let steps = ((b - a).norm() / spacing).ceil().max(1.) as usize;
+        for i in 0..=steps {
+            let p = a + (b - a) * (i as Float / steps as Float);
+            let key = (
+                (p.x / spacing * 4.).round() as i64,
+                (p.y / spacing * 4.).round() as i64,
+            );
+            if taken.insert(key) {
+                centers.push(p);
+            }
+        }
+    }
+    centers
+}
+
+// The points where tabs connect the footprints, so that all are connected with the shortest
+// tabs: the closest points of pairs of footprints along a minimum spanning tree of them.
+fn connections(footprints: &[Vec<Segment>]) -> Vec<Segment> {
+    let n = footprints.len();
+    // The distance and the closest ends of the segments of each pair.
+    let mut closest = vec![(Float::INFINITY, None); n * n];
+    for i in 0..n {
+        for j in i + 1..n {
+            for &(a, b) in &footprints[i] {
+                for &(c, d) in &footprints[j] {
+                    for &(p, q) in &[(a, c), (a, d), (b, c), (b, d)] {
+                        let distance = (q - p).norm();
+                        if distance < closest[i * n + j].0 {
+                            closest[i * n + j] = (distance, Some((p, q)));
+                        }
+                    }
+                }
+            }
+            closest[j * n + i] = closest[i * n + j];
+        }
+    }
+    // Prim's algorithm, from the first footprint.
+    let mut connected = vec![false; n];
+    connected[0] = true;
+    let mut result = Vec::new();
+    for _ in 1..n {
+        let (mut best, mut next) = ((Float::INFINITY, None), 0);
+        for i in (0..n).filter(|&i| connected[i]) {
+            for j in (0..n).filter(|&j| !connected[j]) {
+                if closest[i * n + j].0 < best.0 || best.1.is_none() {
+                    best = closest[i * n + j];
+                    next = j;
+                }
+            }
+        }
+        connected[next] = true;
+        result.extend(best.1);
+    }
+    result
+}
+
+// Add the prism of height on the counterclockwise polygon from z = 0 to mesh.
+fn add_prism(mesh: &mut IndexedMesh, polygon: &[na::Point2<Float>], height: Float) {
+    let n = polygon.len();
+    let start = mesh.vertices.len();
+    for &z in &[0., height] {
+        for p in polygon {
+            mesh.vertices.push(na::Point3::new(p.x, p.y, z));
+        }
+    }
+    let (bottom, top) = (|i: usize| start + i % n, |i: usize| start + n + i % n);
+    for i in 1..n - 1 {
+        mesh.faces.push([bottom(0), bottom(i + 1), bottom(i)]);
+        mesh.faces.push([top(0), top(i), top(i + 1)]);
+    }
+    for i in 0..n {
+        mesh.faces.push([bottom(i), bottom(i + 1), top(i + 1)]);
+        mesh.faces.push([bottom(i), top(i + 1), top(i)]);
+    }
+}
@@ -0,0 +1,105 @@
+use super::Float;
+use gif;
+use image::RgbaImage;
+use nalgebra as na;
+use render::{RenderSettings, Renderer};
+use std::fs::File;
+use std::path::Path;
+use truescad_luascad::implicit3d::Object;
+
+// Delay between GIF frames in hundredths of a second.
+const GIF_FRAME_DELAY: u16 = 4;
+
+// Render frames images, starting from the current view of renderer and orbiting the camera
+// once around the vertical (Z) axis.
+pub fn turntable(
+    renderer: &Renderer,
+    frames: usize,
+    width: u32,
+    height: u32,
+    settings: &RenderSettings,
+) -> Vec<RgbaImage> {
+    let z_up = na::Vector3::new(0., 0., 1.);
+    (0..frames)
+        .map(|i| {
+            let mut frame_renderer = renderer.clone();
+            frame_renderer.orbit(
+                &z_up,
+                ::float::consts::PI * 2. * i as Float / frames as Float,
+            );
+            frame_renderer.render_to_image(width, height, settings)
+        })
+        .collect()
+}
+
+// Render frames images with the object built at time 0, 1 / frames, 2 / frames, ... by load,
+// keeping the view of renderer.
+pub fn animate<F>(
+    renderer: &Renderer,
+    frames: usize,
+    width: u32,
+    height: u32,
+    settings: &RenderSettings,
+    load: F,
+) -> Result<Vec<RgbaImage>, String>
+where
+    F: Fn(Float) -> Result<Box<dyn Object<Float>>, String>,
+{
+    let mut frame_renderer = renderer.clone();
+    (0..frames)
+        .map(|i| {
+            frame_renderer.set_object(Some(load(i as Float / frames as Float)?));
+            Ok(frame_renderer.render_to_image(width, height, settings))
+        })
+        .collect()
+}
+
+// Write frames as animated GIF, if path ends in .gif, or else as numbered images
+// (out.png becomes out_0000.png, out_0001.png, ...).
+pub fn save_frames(frames: &[RgbaImage], path: &str) -> Result<(), String> {
+    let is_gif = Path::new(path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+    if is_gif {
+        return save_gif(frames, path);
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        let frame_path = numbered_path(path, i);
+        frame
+            .save(&frame_path)
+            .map_err(|e| format!("could not write {}: {}", frame_path, e))?;
+    }
+    Ok(())
+}
+
+fn numbered_path(path: &str, i: usize) -> String {
+    let p = Path::new(path);
+    let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+    let name = match p.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}_{:04}.{}", stem, i, ext),
+        None => format!("{}_{:04}.png", stem, i),
+    };
+    p.with_file_name(name).to_string_lossy().into_owned()
+}
+
+fn save_gif(frames: &[RgbaImage], path: &str) -> Result<(), String> {
+    let (width, height) = match frames.first() {
+        Some(frame) => frame.dimensions(),
+        None => return Err("no frames to write".to_string()),
+    };
+    if width > u32::from(u16::MAX) || height > u32::from(u16::MAX) {
+        return Err(format!("{}x{} is too large for a GIF", width, height));
+    }
+    let write_error = |e: ::std::io::Error| format!("could not write {}: {}", path, e);
+    let file = File::create(path).map_err(write_error)?;
+    let mut encoder =
+        gif::Encoder::new(file, width as u16, height as u16, &[]).map_err(write_error)?;
+    gif::SetParameter::set(&mut encoder, gif::Repeat::Infinite).map_err(write_error)?;
+    for frame in frames {
+        let mut pixels = frame.clone().into_raw();
+        let mut gif_frame = gif::Frame::from_rgba(width as u16, height as u16, &mut pixels);
+        gif_frame.delay = GIF_FRAME_DELAY;
+        encoder.write_frame(&gif_frame).map_err(write_error)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+extern crate truescad;
+
+use truescad::cli;
+use truescad::lsp::Server;
+
+// The language server for editors other than truescad, on the standard input and output.
+fn main() {
+    for e in cli::load_plugins() {
+        eprintln!("{}", e);
+    }
+    let stdin = ::std::io::stdin();
+    let stdout = ::std::io::stdout();
+    let mut server = Server::new();
+    if let Err(e) = server.run(&mut stdin.lock(), &mut stdout.lock()) {
+        eprintln!("{}", e);
+        ::std::process::exit(1);
+    }
+    // The protocol asks for an error if the client exits without shutting the server down.
+    if !server.is_shut_down() {
+        ::std::process::exit(1);
+    }
+}
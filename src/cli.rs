@@ -0,0 +1,953 @@
+use super::Float;
+use adhesion;
+use animation;
+use drawing;
+use editor;
+use export_dialog::{self, Conversion, ExportOptions, Format};
+use features;
+use field_check;
+use flat_tree;
+use flat_tree::FlatTree;
+use golden::{self, Outcome};
+use hermite;
+use indexed_mesh::IndexedMesh;
+use jit;
+use na;
+use preview;
+use project::{self, Project};
+use render;
+use server;
+use settings;
+use shader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use truescad_luascad;
+use truescad_luascad::analysis;
+use truescad_luascad::api;
+use truescad_luascad::bounded;
+use truescad_luascad::implicit3d;
+use truescad_luascad::include::Includes;
+#[cfg(feature = "plugins")]
+use truescad_luascad::plugin;
+use truescad_luascad::profile;
+use truescad_luascad::tree::Node;
+use truescad_luascad::units::Units;
+use truescad_luascad::ScriptError;
+
+pub const USAGE: &str = "usage: truescad [script.lua|project.tscad] [--render out.png|out.gif] \
+                         [--size WIDTHxHEIGHT] [--camera VIEW|camera.toml] [--time T] \
+                         [--turntable FRAMES] [--animate FRAMES] \
+                         [--shader out.glsl|out.wgsl] [--drawing out.svg] [--draft X,Y,Z] \
+                         [--profile-eval] [--check-field]\n       \
+                         truescad export script.lua|project.tscad [--profile NAME] \
+                         [--output out.stl|out.3mf|out.amf|out.ply|out.hermite|out.dxf|out.svg|out.obj] \
+                         [--feature-angle DEGREES] [--brim WIDTH] [--tabs WIDTH] \
+                         [--convert in-to-mm|mm-to-in] [--fit SIZE] [--center] [--time T]\n       \
+                         truescad render-bench script.lua|project.tscad [--frames N] \
+                         [--size WIDTHxHEIGHT] [--time T]\n       \
+                         truescad golden [DIR] [--update]\n       \
+                         truescad serve [ADDRESS] [--jobs N]\n       \
+                         truescad preview [ADDRESS|SOCKET]\n       \
+                         truescad --list-api";
+
+const DEFAULT_SIZE: (u32, u32) = (800, 600);
+// Where truescad serve listens without an address, and how many scripts it runs at once.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+const DEFAULT_JOBS: usize = 2;
+// Where truescad preview listens without an address.
+const DEFAULT_PREVIEW_ADDRESS: &str = "127.0.0.1:8081";
+// --profile-eval evaluates the object at this many points along each axis of its box.
+const PROFILE_SAMPLES: usize = 64;
+// render-bench renders this many frames of each backend without --frames.
+const BENCH_FRAMES: usize = 16;
+
+// Command line options. Without render, the GUI is started.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub script: Option<String>,
+    pub render: Option<String>,
+    pub size: Option<(u32, u32)>,
+    // Render from this view of the project, or the camera in this file, see camera.
+    pub camera: Option<String>,
+    // Value of the Lua variable time (0..1).
+    pub time: Option<Float>,
+    // Render this many frames orbiting the object, instead of a single image.
+    pub turntable: Option<usize>,
+    // Render this many frames stepping time from 0 towards 1, instead of a single image.
+    pub animate: Option<usize>,
+    // Write the distance function of the object to this GLSL or WGSL file.
+    pub shader: Option<String>,
+    // Write the front, top and right views of the object to this SVG file, see export_drawing.
+    pub drawing: Option<String>,
+    // Report the draft angles and undercuts of the object for a mold pulled off along this
+    // direction.
+    pub draft: Option<na::Vector3<Float>>,
+    // Report how often each kind of node of the object is evaluated, see profile_evaluation.
+    pub profile_eval: bool,
+    // Report where the values of the object overestimate the distance to its surface, see
+    // check_field.
+    pub check_field: bool,
+    // Tessellate the script and write it to an STL file, see export.
+    pub export: bool,
+    // Time rendering the script with each backend, see render_bench.
+    pub render_bench: bool,
+    // How many frames render_bench renders.
+    pub frames: Option<usize>,
+    // The export profile of the project to export with.
+    pub profile: Option<String>,
+    // The STL, 3MF, AMF or PLY (or hermite data, see hermite::HermiteData, or feature lines, see
+    // features) file to export to, the script with the extension .stl without it.
+    pub output: Option<String>,
+    // The angle between faces, in degrees, from which the edge between them is a feature line.
+    pub feature_angle: Option<Float>,
+    // The widths of the brim and of the tabs between parts export adds, instead of those of the
+    // export options, see adhesion::add.
+    pub brim: Option<Float>,
+    pub tabs: Option<Float>,
+    // The transforms of the mesh export applies, instead of those of the export options, see
+    // export_dialog::transform.
+    pub convert: Option<Conversion>,
+    pub fit: Option<Float>,
+    pub center: bool,
+    // Print the reference of the functions scripts can use, see list_api.
+    pub list_api: bool,
+    // Check the meshes of the scripts in the directory of script against their goldens, see
+    // check_goldens.
+    pub golden: bool,
+    // Write the goldens of changed meshes and of scripts without one instead of failing.
+    pub update: bool,
+    // Serve requests on the address in script, see server::serve.
+    pub serve: bool,
+    // How many scripts serve runs at once.
+    pub jobs: Option<usize>,
+    // Run a single job of serve (export or render) on the request read from stdin, answering in
+    // the file in script, see server::run_job. serve starts itself like this for each job.
+    pub job: Option<String>,
+    // Preview the scripts of editors connecting to the address in script, see preview::listen.
+    pub preview: bool,
+}
+
+impl Options {
+    // Parse the arguments (without the program name).
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut iter = args.iter().peekable();
+        match iter.peek().map(|arg| arg.as_str()) {
+            Some("export") => options.export = true,
+            Some("render-bench") => options.render_bench = true,
+            Some("golden") => options.golden = true,
+            Some("serve") => options.serve = true,
+            Some("preview") => options.preview = true,
+            _ => {}
+        }
+        if options.export
+            || options.render_bench
+            || options.golden
+            || options.serve
+            || options.preview
+        {
+            iter.next();
+        }
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--render" => options.render = Some(value(&mut iter, arg)?.clone()),
+                "--size" => options.size = Some(parse_size(value(&mut iter, arg)?)?),
+                "--camera" => options.camera = Some(value(&mut iter, arg)?.clone()),
+                "--time" => {
+                    let time = value(&mut iter, arg)?;
+                    options.time = match time.parse() {
+                        Ok(t) if (0. ..=1.).contains(&t) => Some(t),
+                        _ => return Err(format!("invalid time {}, expected 0..1", time)),
+                    }
+                }
+                "--turntable" => options.turntable = Some(parse_frames(value(&mut iter, arg)?)?),
+                "--animate" => options.animate = Some(parse_frames(value(&mut iter, arg)?)?),
+                "--frames" => options.frames = Some(parse_frames(value(&mut iter, arg)?)?),
+                "--shader" => options.shader = Some(value(&mut iter, arg)?.clone()),
+                "--drawing" => options.drawing = Some(value(&mut iter, arg)?.clone()),
+                "--draft" => options.draft = Some(parse_direction(value(&mut iter, arg)?)?),
+                "--profile" => options.profile = Some(value(&mut iter, arg)?.clone()),
+                "--output" => options.output = Some(value(&mut iter, arg)?.clone()),
+                "--feature-angle" => {
+                    let angle = value(&mut iter, arg)?;
+                    options.feature_angle = match angle.parse::<Float>() {
+                        Ok(a) if a > 0. && a < 180. => Some(a),
+                        _ => return Err(format!("invalid angle {}, expected 0..180", angle)),
+                    }
+                }
+                "--brim" => options.brim = Some(parse_width(value(&mut iter, arg)?)?),
+                "--tabs" => options.tabs = Some(parse_width(value(&mut iter, arg)?)?),
+                "--convert" => {
+                    let name = value(&mut iter, arg)?;
+                    options.convert = match Conversion::parse(name) {
+                        Some(c) => Some(c),
+                        None => {
+                            return Err(format!(
+                                "invalid conversion {}, expected none, in-to-mm or mm-to-in",
+                                name
+                            ))
+                        }
+                    }
+                }
+                "--fit" => options.fit = Some(parse_width(value(&mut iter, arg)?)?),
+                "--center" => options.center = true,
+                "--list-api" => options.list_api = true,
+                "--profile-eval" => options.profile_eval = true,
+                "--check-field" => options.check_field = true,
+                "--update" => options.update = true,
+                "--jobs" => options.jobs = Some(parse_jobs(value(&mut iter, arg)?)?),
+                "--job" => options.job = Some(value(&mut iter, arg)?.clone()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ if options.script.is_none() => options.script = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        if options.render.is_none() && (options.turntable.is_some() || options.animate.is_some()) {
+            return Err("--turntable and --animate need --render".to_string());
+        }
+        if options.render.is_none() && options.camera.is_some() {
+            return Err("--camera needs --render".to_string());
+        }
+        if options.time.is_some() && !options.headless() {
+            return Err(
+                "--time needs --render, --shader, --drawing, --draft, --profile-eval, \
+                 --check-field, export or render-bench"
+                    .to_string(),
+            );
+        }
+        if options.frames.is_some() && !options.render_bench {
+            return Err("--frames needs render-bench".to_string());
+        }
+        if !options.export
+            && (options.profile.is_some()
+                || options.output.is_some()
+                || options.feature_angle.is_some()
+                || options.brim.is_some()
+                || options.tabs.is_some()
+                || options.convert.is_some()
+                || options.fit.is_some()
+                || options.center)
+        {
+            return Err(
+                "--profile, --output, --feature-angle, --brim, --tabs, --convert, --fit and \
+                 --center need export"
+                    .to_string(),
+            );
+        }
+        if options.update && !options.golden {
+            return Err("--update needs golden".to_string());
+        }
+        if options.golden && (options.headless() || options.list_api) {
+            return Err("golden can not be combined with other options".to_string());
+        }
+        if (options.jobs.is_some() || options.job.is_some()) && !options.serve {
+            return Err("--jobs and --job need serve".to_string());
+        }
+        if options.serve && (options.headless() || options.list_api || options.update) {
+            return Err("serve can not be combined with other options".to_string());
+        }
+        if options.preview && (options.headless() || options.list_api || options.update) {
+            return Err("preview can not be combined with other options".to_string());
+        }
+        if options.turntable.is_some() && options.animate.is_some() {
+            return Err("--turntable and --animate can not be combined".to_string());
+        }
+        if options.headless() && options.script.is_none() {
+            return Err(
+                "--render, --shader, --drawing, --draft, --profile-eval, --check-field, export \
+                 and render-bench need a script"
+                    .to_string(),
+            );
+        }
+        Ok(options)
+    }
+    // Whether to run without the GUI.
+    pub fn headless(&self) -> bool {
+        self.render.is_some()
+            || self.shader.is_some()
+            || self.drawing.is_some()
+            || self.draft.is_some()
+            || self.profile_eval
+            || self.check_field
+            || self.export
+            || self.render_bench
+    }
+    // Whether to answer clients until the process is killed, see serve and preview.
+    pub fn listens(&self) -> bool {
+        self.serve || self.preview
+    }
+}
+
+fn value<'a, I: Iterator<Item = &'a String>>(
+    iter: &mut I,
+    option: &str,
+) -> Result<&'a String, String> {
+    iter.next()
+        .ok_or_else(|| format!("{} needs a value", option))
+}
+
+fn parse_frames(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid number of frames {}", s)),
+    }
+}
+
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid number of jobs {}", s)),
+    }
+}
+
+// A width of a brim or tabs, 0 for none.
+fn parse_width(s: &str) -> Result<Float, String> {
+    match s.parse::<Float>() {
+        Ok(w) if w >= 0. && w.is_finite() => Ok(w),
+        _ => Err(format!("invalid width {}", s)),
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid size {}, expected WIDTHxHEIGHT", s);
+    let mut parts = s.split('x');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(w), Some(h), None) => match (w.parse(), h.parse()) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+// A direction X,Y,Z, normalized.
+fn parse_direction(s: &str) -> Result<na::Vector3<Float>, String> {
+    let invalid = || format!("invalid direction {}, expected X,Y,Z", s);
+    let v: Vec<Float> = s
+        .split(',')
+        .map(|x| x.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    match v[..] {
+        [x, y, z] => na::Vector3::new(x, y, z)
+            .try_normalize(0.)
+            .filter(|v| v.iter().all(|x| x.is_finite()))
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+// Evaluate the script, or the script of the project with its parameter values, at time.
+fn evaluate(script: &str, time: Float) -> Result<truescad_luascad::Evaluation, String> {
+    let (code, values, includes) = if project::is_project(script) {
+        let project = Project::open(script)?;
+        let includes = project.includes();
+        (project.script, project.parameters, includes)
+    } else {
+        let code = ::std::fs::read_to_string(script)
+            .map_err(|e| format!("could not read {}: {}", script, e))?;
+        (code, HashMap::new(), Includes::beside(script))
+    };
+    let mut evaluation = truescad_luascad::evaluate_in(&code, time, &values, &includes)
+        .map_err(|e| format!("error : {}", ScriptError::from(&e)))?;
+    print!("{}", evaluation.output);
+    evaluation.tree = settings::SettingsData::default().optimize(evaluation.tree);
+    Ok(evaluation)
+}
+
+// Evaluate the script at time with the parameters from the settings file.
+pub fn load_object(
+    script: &str,
+    time: Float,
+) -> Result<Box<dyn implicit3d::Object<Float>>, String> {
+    load(script, time).map(|(object, _)| object)
+}
+
+// An object and the tree for Renderer::set_flat_tree.
+type Loaded = (Box<dyn implicit3d::Object<Float>>, Option<FlatTree>);
+
+// Like load_object, also returning the tree.
+fn load(script: &str, time: Float) -> Result<Loaded, String> {
+    let evaluation = evaluate(script, time)?;
+    let mut object = evaluation
+        .object
+        .ok_or("no object - did you call build()?")?;
+    let parameters = parameters();
+    object.set_parameters(&parameters);
+    let tree = flat_tree::for_rendering(evaluation.tree.as_ref(), &parameters);
+    let object = jit::try_accelerate(object.clone(), evaluation.tree.as_ref(), &parameters)
+        .unwrap_or_else(|e| {
+            println!("jit: {}, falling back to the interpreted object", e);
+            object
+        });
+    Ok((object, tree))
+}
+
+// editor::tessellate, with its warnings printed.
+fn tessellate(
+    object: Box<dyn implicit3d::Object<Float>>,
+    tree: Option<&Node>,
+    units: Units,
+    resolution: Float,
+    error: Float,
+) -> Result<IndexedMesh, String> {
+    let mut warnings = Vec::new();
+    let mesh = editor::tessellate(object, tree, units, resolution, error, &mut warnings);
+    for warning in warnings {
+        println!("{}", warning);
+    }
+    mesh.ok_or_else(|| "tessellation failed".to_string())
+}
+
+// The parameters of objects from the settings file.
+fn parameters() -> implicit3d::PrimitiveParameters<Float> {
+    let s = settings::SettingsData::default();
+    implicit3d::PrimitiveParameters {
+        fade_range: s.fade_range,
+        r_multiplier: s.r_multiplier,
+    }
+}
+
+// Do what options ask for without the GUI.
+pub fn run(options: &Options) -> Result<(), String> {
+    if options.list_api {
+        list_api();
+    }
+    if options.shader.is_some() {
+        export_shader(options)?;
+    }
+    if options.drawing.is_some() {
+        export_drawing(options)?;
+    }
+    if options.render.is_some() {
+        render(options)?;
+    }
+    if options.draft.is_some() {
+        report_draft(options)?;
+    }
+    if options.profile_eval {
+        profile_evaluation(options)?;
+    }
+    if options.check_field {
+        check_field(options)?;
+    }
+    if options.export {
+        export(options)?;
+    }
+    if options.render_bench {
+        render_bench(options)?;
+    }
+    if options.golden {
+        check_goldens(options)?;
+    }
+    if let Some(ref job) = options.job {
+        let answer = options
+            .script
+            .as_ref()
+            .ok_or("--job needs a file to answer in")?;
+        server::run_job(job, answer)?;
+    } else if options.serve {
+        let address = options
+            .script
+            .as_ref()
+            .map_or(DEFAULT_ADDRESS, |address| address.as_str());
+        server::serve(address, options.jobs.unwrap_or(DEFAULT_JOBS))?;
+    }
+    if options.preview {
+        let address = options
+            .script
+            .as_ref()
+            .map_or(DEFAULT_PREVIEW_ADDRESS, |address| address.as_str());
+        preview::listen(address)?;
+    }
+    Ok(())
+}
+
+// Load the plugins in the plugin directory next to the settings, see truescad_luascad::plugin.
+// Returns what went wrong with those which could not be loaded.
+#[cfg(feature = "plugins")]
+pub fn load_plugins() -> Vec<String> {
+    match settings::SettingsData::plugin_dir() {
+        Ok(dir) => plugin::load_dir(&dir),
+        Err(e) => vec![format!("could not find the plugins: {:?}", e)],
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+pub fn load_plugins() -> Vec<String> {
+    Vec::new()
+}
+
+// Print the functions scripts can use by topic, see truescad_luascad::api.
+pub fn list_api() {
+    for (topic, functions) in api::groups() {
+        println!("{}", topic);
+        for function in functions {
+            println!("  {}\n      {}", function.signature(), function.doc);
+        }
+        println!();
+    }
+}
+
+// Tessellate the scripts in the directory (golden::DIRECTORY unless given) and compare their
+// meshes to the goldens next to them, see golden::check. Fails if a mesh changed, a golden is
+// missing or a script failed, unless the goldens are updated.
+pub fn check_goldens(options: &Options) -> Result<(), String> {
+    let dir = options
+        .script
+        .as_ref()
+        .map_or(golden::DIRECTORY, |dir| dir.as_str());
+    let mut failures = 0;
+    for script in golden::scripts(dir)? {
+        let name = script.to_string_lossy();
+        match golden::check(&script, options.update) {
+            Ok(Outcome::Matched) => println!("ok       {}", name),
+            Ok(Outcome::Missing) => {
+                failures += 1;
+                println!("missing  {}", name);
+            }
+            Ok(Outcome::New) => println!("new      {}", name),
+            Ok(Outcome::Updated) => println!("updated  {}", name),
+            Ok(Outcome::Changed(differences)) => {
+                failures += 1;
+                println!("changed  {}", name);
+                for difference in differences {
+                    println!("           {}", difference);
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("failed   {}", e);
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!(
+            "{} meshes differ from or lack their goldens, run golden --update if that is intended",
+            failures
+        ));
+    }
+    Ok(())
+}
+
+// Evaluate the object of the script on a grid over its box, and report how often the objects of
+// each kind of node were evaluated and how long that took, see truescad_luascad::profile::Counted.
+// The object is interpreted, not compiled or flattened, so the bounding boxes prune like in the
+// view.
+pub fn profile_evaluation(options: &Options) -> Result<(), String> {
+    let script = match options.script {
+        Some(ref script) => script,
+        None => return Err(USAGE.to_string()),
+    };
+    let (evaluation, counters) = profile::counting(|| evaluate(script, options.time.unwrap_or(0.)));
+    let mut object = evaluation?
+        .object
+        .ok_or("no object - did you call build()?")?;
+    object.set_parameters(&parameters());
+    let bbox = object.bbox().clone();
+    if !bounded::is_finite(&bbox) {
+        return Err(
+            "can not profile an infinite object, intersect it with a finite one".to_string(),
+        );
+    }
+    let step = bbox.dim() / PROFILE_SAMPLES as Float;
+    // Points are pruned like for neighbors at the distance of the grid.
+    let slack = step.norm() / 2.;
+    let start = Instant::now();
+    for i in 0..PROFILE_SAMPLES {
+        for j in 0..PROFILE_SAMPLES {
+            for k in 0..PROFILE_SAMPLES {
+                let cell = na::Vector3::new(i as Float, j as Float, k as Float).add_scalar(0.5);
+                object.approx_value(&(bbox.min + step.component_mul(&cell)), slack);
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{} points in {:.1} ms",
+        PROFILE_SAMPLES.pow(3),
+        milliseconds(elapsed)
+    );
+    println!(
+        "{:<14} {:>12} {:>10} {:>8}",
+        "kind", "evaluations", "ms", "ns each"
+    );
+    for (kind, counter) in counters.by_time() {
+        let evaluations = counter.evaluations();
+        if evaluations == 0 {
+            continue;
+        }
+        println!(
+            "{:<14} {:>12} {:>10.1} {:>8.0}",
+            kind,
+            evaluations,
+            milliseconds(counter.time()),
+            milliseconds(counter.time()) * 1e6 / evaluations as Float
+        );
+    }
+    Ok(())
+}
+
+// Check the values of each node of the script against the distances to its surface, and print
+// the nodes whose values overestimate them, see field_check::check. Marks the nodes which do
+// while their children do not, as that is where the field goes wrong.
+pub fn check_field(options: &Options) -> Result<(), String> {
+    let script = match options.script {
+        Some(ref script) => script,
+        None => return Err(USAGE.to_string()),
+    };
+    let tree = evaluate(script, options.time.unwrap_or(0.))?
+        .tree
+        .ok_or("no object - did you call build()?")?;
+    let reports = field_check::check(&tree, parameters().r_multiplier, field_check::SAMPLES);
+    let mut failed = 0;
+    for report in &reports {
+        let indent = "  ".repeat(report.depth);
+        if !report.checked {
+            println!("{}{}: not checked", indent, report.label);
+            continue;
+        }
+        match report.at {
+            Some(ref at) => {
+                failed += 1;
+                println!(
+                    "{}{}: {} of {} points overestimate by up to {:.2}x, e.g. at \
+                     ({:.3}, {:.3}, {:.3}){}",
+                    indent,
+                    report.label,
+                    report.violations,
+                    field_check::SAMPLES,
+                    report.worst,
+                    at.x,
+                    at.y,
+                    at.z,
+                    if report.culprit { " <- here" } else { "" }
+                );
+            }
+            None => println!("{}{}: ok", indent, report.label),
+        }
+    }
+    if failed > 0 {
+        return Err(format!(
+            "the values of {} nodes overestimate the distance to their surface",
+            failed
+        ));
+    }
+    Ok(())
+}
+
+// Render frames of the script orbiting it like --turntable with each backend: the interpreted
+// object, the flat tree (see Renderer::set_flat_tree) and, with the jit feature, the compiled
+// object, and report how long that took. The default render settings are used rather than those
+// of the settings file, so the numbers compare between machines.
+pub fn render_bench(options: &Options) -> Result<(), String> {
+    let script = match options.script {
+        Some(ref script) => script,
+        None => return Err(USAGE.to_string()),
+    };
+    let evaluation = evaluate(script, options.time.unwrap_or(0.))?;
+    let mut object = evaluation
+        .object
+        .ok_or("no object - did you call build()?")?;
+    let parameters = parameters();
+    object.set_parameters(&parameters);
+    let tree = evaluation.tree.as_ref();
+    let mut backends: Vec<(&str, Loaded)> = vec![("interpreted", (object.clone(), None))];
+    match tree.map(|tree| FlatTree::new(tree, parameters.r_multiplier)) {
+        Some(Ok(flat)) => backends.push(("flat tree", (object.clone(), Some(flat)))),
+        Some(Err(e)) => println!("skipping the flat tree: {}", e),
+        None => {}
+    }
+    if cfg!(feature = "jit") {
+        backends.push(("jit", (jit::accelerate(object, tree, &parameters), None)));
+    }
+    let frames = options.frames.unwrap_or(BENCH_FRAMES);
+    let (width, height) = options.size.unwrap_or(DEFAULT_SIZE);
+    println!("{} frames of {}x{}", frames, width, height);
+    println!(
+        "{:<12} {:>10} {:>12} {:>12}",
+        "backend", "ms/frame", "Mrays/s", "iterations"
+    );
+    let z_up = na::Vector3::new(0., 0., 1.);
+    let mut buf = vec![0; (width * height * 4) as usize];
+    for (name, (object, flat)) in backends {
+        let mut renderer = render::Renderer::new();
+        renderer.set_object(Some(object));
+        renderer.set_flat_tree(flat);
+        renderer.set_view_preset(render::ViewPreset::Isometric);
+        let mut stats = render::RenderStats::default();
+        for i in 0..frames {
+            let mut frame_renderer = renderer.clone();
+            frame_renderer.orbit(
+                &z_up,
+                ::float::consts::PI * 2. * i as Float / frames as Float,
+            );
+            let frame = frame_renderer.draw_on_buf(&mut buf, width as i32, height as i32);
+            stats.rays += frame.rays;
+            stats.iterations += frame.iterations;
+            stats.duration += frame.duration;
+        }
+        println!(
+            "{:<12} {:>10.1} {:>12.2} {:>12.1}",
+            name,
+            stats.millis() / frames as Float,
+            stats.rays as Float / stats.duration.as_secs_f64() as Float / 1e6,
+            stats.avg_iterations()
+        );
+    }
+    Ok(())
+}
+
+fn milliseconds(d: Duration) -> Float {
+    d.as_secs_f64() as Float * 1e3
+}
+
+// Tessellate the script and write it to a mesh file (see export_dialog::save_mesh), with the
+// export profile of its project, the options pinned by the project settings (see
+// settings::ProjectSettings) or the export settings from the settings file. Outputs with the
+// extension hermite::EXTENSION get the hermite data of the object at the resolution of the export
+// instead, and those of features::EXTENSIONS the feature lines of the mesh. Only the tessellation
+// region of the script is exported, if it sets one. --brim, --tabs, --convert and --fit override
+// those of the options, --center adds to them.
+pub fn export(options: &Options) -> Result<(), String> {
+    let script = match options.script {
+        Some(ref script) => script,
+        None => return Err(USAGE.to_string()),
+    };
+    let export = match options.profile {
+        Some(ref name) if project::is_project(script) => Project::open(script)?.profile(name)?,
+        Some(_) => return Err("--profile needs a project".to_string()),
+        None => match settings::ProjectSettings::for_script(script).export {
+            Some(pinned) => pinned,
+            None => {
+                let s = settings::SettingsData::default();
+                ExportOptions {
+                    resolution: s.export_resolution,
+                    error: s.export_error,
+                    format: Format::BinaryStl,
+                    brim: 0.,
+                    tabs: 0.,
+                    plate: 0.,
+                    spacing: 0.,
+                    convert: Conversion::None,
+                    fit: 0.,
+                    center: false,
+                }
+            }
+        },
+    };
+    let export = ExportOptions {
+        convert: options.convert.unwrap_or(export.convert),
+        fit: options.fit.unwrap_or(export.fit),
+        center: options.center || export.center,
+        ..export
+    };
+    let evaluation = evaluate(script, options.time.unwrap_or(0.))?;
+    let object = evaluation
+        .object
+        .ok_or("no object - did you call build()?")?;
+    let (mut object, tree) = match evaluation.tessellation_region {
+        Some(ref region) => bounded::region_of_interest(object, evaluation.tree, region)?,
+        None => (object, evaluation.tree),
+    };
+    object.set_parameters(&parameters());
+    if !bounded::is_finite(object.bbox()) {
+        return Err(
+            "can not export an infinite object, intersect it with a finite one".to_string(),
+        );
+    }
+    let path = match options.output {
+        Some(ref path) => path.clone(),
+        None => Path::new(script)
+            .with_extension("stl")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    if hermite::is_hermite_path(&path) {
+        let object = editor::accelerate(object, tree.as_ref(), &parameters());
+        let data = hermite::sample(&*object, evaluation.units, export.resolution)?;
+        let file = File::create(&path).map_err(|e| format!("could not create {}: {}", path, e))?;
+        data.write(&mut BufWriter::new(file))
+            .map_err(|e| format!("could not write {}: {}", path, e))?;
+        println!("wrote {} cells to {}", data.cells.len(), path);
+        return Ok(());
+    }
+    let mut mesh = tessellate(
+        object,
+        tree.as_ref(),
+        evaluation.units,
+        export.resolution,
+        export.error,
+    )?;
+    export_dialog::transform(&mut mesh, &export)?;
+    if features::is_features_path(&path) {
+        let angle = options.feature_angle.unwrap_or(features::DEFAULT_ANGLE);
+        let lines = features::feature_lines(&mesh, angle);
+        features::save(&path, &lines, mesh.units.millimeters())?;
+        println!("wrote {} feature lines to {}", lines.len(), path);
+        return Ok(());
+    }
+    let (brim, tabs) = (
+        options.brim.unwrap_or(export.brim),
+        options.tabs.unwrap_or(export.tabs),
+    );
+    if !adhesion::add(&mut mesh, brim, tabs) {
+        return Err("no footprint at z = 0 for a brim or tabs".to_string());
+    }
+    export_dialog::save_mesh(&path, &mesh, export.format)?;
+    println!("wrote {}", path);
+    Ok(())
+}
+
+// Write the distance function of the script to a shader file, see shader::distance_function.
+pub fn export_shader(options: &Options) -> Result<(), String> {
+    let (script, path) = match (&options.script, &options.shader) {
+        (Some(script), Some(path)) => (script, path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let tree = evaluate(script, options.time.unwrap_or(0.))?
+        .tree
+        .ok_or("no object - did you call build()?")?;
+    let code = shader::distance_function(
+        &tree,
+        shader::ShaderLanguage::from_path(path),
+        settings::SettingsData::default().r_multiplier,
+    )?;
+    ::std::fs::write(path, code).map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+// Write the front, top and right views of the script, tessellated with the export settings of
+// the settings file, to an SVG file, dimensioned with its drawing anchors, see drawing::save. Like
+// export, only the tessellation region of the script is drawn, if it sets one.
+pub fn export_drawing(options: &Options) -> Result<(), String> {
+    let (script, path) = match (&options.script, &options.drawing) {
+        (Some(script), Some(path)) => (script, path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let evaluation = evaluate(script, options.time.unwrap_or(0.))?;
+    let object = evaluation
+        .object
+        .ok_or("no object - did you call build()?")?;
+    let (mut object, tree) = match evaluation.tessellation_region {
+        Some(ref region) => bounded::region_of_interest(object, evaluation.tree, region)?,
+        None => (object, evaluation.tree),
+    };
+    object.set_parameters(&parameters());
+    if !bounded::is_finite(object.bbox()) {
+        return Err("can not draw an infinite object, intersect it with a finite one".to_string());
+    }
+    let s = settings::SettingsData::default();
+    let accelerated = editor::accelerate(object.clone(), tree.as_ref(), &parameters());
+    let mesh = tessellate(
+        object,
+        tree.as_ref(),
+        evaluation.units,
+        s.export_resolution,
+        s.export_error,
+    )?;
+    drawing::save(
+        path,
+        &mesh,
+        &*accelerated,
+        s.export_resolution,
+        &evaluation.drawing_anchors,
+    )?;
+    println!("wrote {}", path);
+    Ok(())
+}
+
+// Print the regions of the surface of the script with their draft angles and undercuts, see
+// analysis::draft_regions.
+pub fn report_draft(options: &Options) -> Result<(), String> {
+    let (script, pull) = match (&options.script, &options.draft) {
+        (Some(script), Some(pull)) => (script, pull),
+        _ => return Err(USAGE.to_string()),
+    };
+    let object = load_object(script, options.time.unwrap_or(0.))?;
+    let regions = analysis::draft_regions(&*object, pull)
+        .ok_or("can not analyze the draft of an infinite object")?;
+    println!(
+        "{} regions, pulled along ({:.3}, {:.3}, {:.3}):",
+        regions.len(),
+        pull.x,
+        pull.y,
+        pull.z
+    );
+    for r in regions {
+        let (min, max) = (&r.bbox.min, &r.bbox.max);
+        println!(
+            "  draft {:.2} degrees, {:.0}% undercut, normal ({:.2}, {:.2}, {:.2}), \
+             ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2})",
+            r.draft.to_degrees(),
+            r.undercut * 100.,
+            r.normal.x,
+            r.normal.y,
+            r.normal.z,
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z
+        );
+    }
+    Ok(())
+}
+
+// The camera called name: the view of the project script with that name, or the camera in the
+// file name if it ends in .toml (like a view in the project.toml of a project, see
+// render::CameraState).
+fn camera(script: &str, name: &str) -> Result<render::CameraState, String> {
+    if name.ends_with(".toml") {
+        let text = ::std::fs::read_to_string(name)
+            .map_err(|e| format!("could not read {}: {}", name, e))?;
+        return ::toml::from_str(&text).map_err(|e| format!("could not read {}: {}", name, e));
+    }
+    if !project::is_project(script) {
+        return Err(format!(
+            "only projects have views, --camera needs a camera file for {}",
+            script
+        ));
+    }
+    Project::open(script)?.view(name)
+}
+
+// Render the script to an image file, using the render settings from the settings file.
+pub fn render(options: &Options) -> Result<(), String> {
+    let (script, path) = match (&options.script, &options.render) {
+        (Some(script), Some(path)) => (script, path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let (width, height) = options.size.unwrap_or(DEFAULT_SIZE);
+    let mut renderer = render::Renderer::new();
+    let time = options.time.unwrap_or(0.);
+    let (object, tree) = load(script, time)?;
+    renderer.set_object(Some(object));
+    renderer.set_flat_tree(tree);
+    if let Some(ref name) = options.camera {
+        renderer.set_camera_state(&camera(script, name)?);
+    }
+    let mut render_settings = renderer.settings().clone();
+    settings::SettingsData::default().apply_to(&mut render_settings);
+    if let Some(frames) = options.turntable {
+        // Looking down the turntable axis would only spin the image.
+        if options.camera.is_none() {
+            renderer.set_view_preset(render::ViewPreset::Isometric);
+        }
+        let images = animation::turntable(&renderer, frames, width, height, &render_settings);
+        return animation::save_frames(&images, path);
+    }
+    if let Some(frames) = options.animate {
+        let images = animation::animate(&renderer, frames, width, height, &render_settings, |t| {
+            load_object(script, t)
+        })?;
+        return animation::save_frames(&images, path);
+    }
+    renderer
+        .render_to_image(width, height, &render_settings)
+        .save(path)
+        .map_err(|e| format!("could not write {}: {}", path, e))
+}
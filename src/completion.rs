@@ -0,0 +1,228 @@
+use gtk::traits::*;
+use gtk::Inhibit;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use truescad_luascad::api::{self, Function};
+
+// Names are completed from this many typed characters on, methods right after the colon.
+const MIN_PREFIX: usize = 2;
+// At most this many functions are proposed.
+const MAX_PROPOSALS: usize = 12;
+
+// Whether prefix, the name before the cursor, is long enough to complete.
+fn completes(prefix: &str, method: bool) -> bool {
+    !prefix.starts_with(|c: char| c.is_ascii_digit()) && (method || prefix.len() >= MIN_PREFIX)
+}
+
+// The function of the innermost call still open at the end of line.
+fn open_call(line: &str) -> Option<&'static Function> {
+    let mut depth = 0;
+    for (i, c) in line.char_indices().rev() {
+        match c {
+            ')' | '}' => depth += 1,
+            '(' | '{' if depth > 0 => depth -= 1,
+            '(' | '{' => {
+                let (start, name) = last_name(&line[..i]);
+                return api::find(name, line[..start].ends_with(':'));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// The name at the end of text, and where it starts.
+pub fn last_name(text: &str) -> (usize, &str) {
+    let start = text
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+        .last()
+        .map_or(text.len(), |(i, _)| i);
+    (start, &text[start..])
+}
+
+// Completion of the functions scripts can use while typing in the editor, from the registry of
+// truescad_luascad::api. A popover below the cursor proposes the functions starting with the
+// name being typed (the methods of objects after a colon), which are chosen with the arrow keys
+// and inserted with Return or Tab. Inside the arguments of a call, it shows the signature of the
+// function instead.
+#[derive(Clone)]
+pub struct Completion {
+    view: ::sourceview::View,
+    popover: ::gtk::Popover,
+    list: ::gtk::ListBox,
+    hint: ::gtk::Label,
+    // The proposed functions and the length of the name they complete.
+    proposals: Rc<RefCell<(Vec<&'static Function>, usize)>>,
+    // Whether the popover handled the last key pressed, which does not change the text.
+    handled: Rc<Cell<bool>>,
+}
+
+impl Completion {
+    pub fn new(view: &::sourceview::View) -> Completion {
+        let popover = ::gtk::Popover::new(Some(view));
+        popover.set_modal(false);
+        popover.set_position(::gtk::PositionType::Bottom);
+        let list = ::gtk::ListBox::new();
+        let hint = ::gtk::Label::new(None);
+        hint.set_xalign(0.);
+        hint.set_line_wrap(true);
+        hint.set_max_width_chars(60);
+        let v_box = ::gtk::Box::new(::gtk::Orientation::Vertical, 0);
+        v_box.pack_start(&list, false, false, 0);
+        v_box.pack_start(&hint, false, false, 5);
+        popover.add(&v_box);
+        let completion = Completion {
+            view: view.clone(),
+            popover,
+            list,
+            hint,
+            proposals: Rc::new(RefCell::new((Vec::new(), 0))),
+            handled: Rc::new(Cell::new(false)),
+        };
+        {
+            let c = completion.clone();
+            view.connect_key_press_event(move |_, key| {
+                let handled = c.key_pressed(key.get_keyval());
+                c.handled.set(handled);
+                Inhibit(handled)
+            });
+        }
+        {
+            let c = completion.clone();
+            view.connect_key_release_event(move |_, _| {
+                if !c.handled.replace(false) {
+                    c.update();
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let c = completion.clone();
+            view.connect_focus_out_event(move |_, _| {
+                c.popover.hide();
+                Inhibit(false)
+            });
+        }
+        {
+            let c = completion.clone();
+            completion
+                .list
+                .connect_row_activated(move |_, row| c.accept(row.get_index() as usize));
+        }
+        completion
+    }
+    // Handle the keys choosing a proposal while the popover is shown. True if key was handled.
+    fn key_pressed(&self, key: u32) -> bool {
+        let count = self.proposals.borrow().0.len();
+        if !self.popover.is_visible() {
+            return false;
+        }
+        let selected = self
+            .list
+            .get_selected_row()
+            .map_or(0, |row| row.get_index() as usize);
+        match key {
+            ::gdk::enums::key::Escape => self.popover.hide(),
+            _ if count == 0 => return false,
+            ::gdk::enums::key::Down => self.select((selected + 1) % count),
+            ::gdk::enums::key::Up => self.select((selected + count - 1) % count),
+            ::gdk::enums::key::Return | ::gdk::enums::key::Tab => self.accept(selected),
+            _ => return false,
+        }
+        true
+    }
+    fn select(&self, index: usize) {
+        if let Some(row) = self.list.get_row_at_index(index as i32) {
+            self.list.select_row(Some(&row));
+        }
+    }
+    // Replace the name being typed by the proposal at index, and open its arguments.
+    fn accept(&self, index: usize) {
+        let (function, typed) = {
+            let proposals = self.proposals.borrow();
+            match proposals.0.get(index) {
+                Some(function) => (*function, proposals.1),
+                None => return,
+            }
+        };
+        let buffer = self.view.get_buffer().unwrap();
+        let mut end = buffer.get_iter_at_mark(&buffer.get_insert().unwrap());
+        let mut start = end.clone();
+        start.backward_chars(typed as i32);
+        buffer.delete(&mut start, &mut end);
+        let open = match function.args {
+            Some(args) if args.starts_with('{') => "{",
+            Some(_) => "(",
+            None => "",
+        };
+        buffer.insert_at_cursor(&format!("{}{}", function.short_name(), open));
+        self.update();
+    }
+    // Propose what fits the text before the cursor, or hide the popover if nothing does.
+    fn update(&self) {
+        let buffer = self.view.get_buffer().unwrap();
+        let cursor = buffer.get_iter_at_mark(&buffer.get_insert().unwrap());
+        let mut line_start = cursor.clone();
+        line_start.set_line_offset(0);
+        let line = buffer
+            .get_text(&line_start, &cursor, false)
+            .unwrap_or_default();
+        let (start, prefix) = last_name(&line);
+        let method = line[..start].ends_with(':');
+        let mut proposals = if completes(prefix, method) {
+            api::complete(prefix, method)
+        } else {
+            Vec::new()
+        };
+        proposals.truncate(MAX_PROPOSALS);
+        // Nothing is left to complete once the name is typed.
+        if proposals.len() == 1 && proposals[0].short_name() == prefix {
+            proposals.clear();
+        }
+        // Inside the arguments of a call, e.g. typing a variable, its signature is shown.
+        let hint = if proposals.is_empty() {
+            open_call(&line)
+        } else {
+            None
+        };
+        for row in self.list.get_children() {
+            self.list.remove(&row);
+        }
+        if proposals.is_empty() && hint.is_none() {
+            *self.proposals.borrow_mut() = (Vec::new(), 0);
+            self.popover.hide();
+            return;
+        }
+        for function in &proposals {
+            let label = ::gtk::Label::new(Some(function.signature().as_str()));
+            label.set_xalign(0.);
+            label.set_tooltip_text(function.doc);
+            self.list.insert(&label, -1);
+        }
+        *self.proposals.borrow_mut() = (proposals, prefix.len());
+        let rect = self.view.get_iter_location(&cursor);
+        let (x, y) =
+            self.view
+                .buffer_to_window_coords(::gtk::TextWindowType::Widget, rect.x, rect.y);
+        self.popover.set_pointing_to(&::gdk::Rectangle {
+            x,
+            y,
+            width: 1,
+            height: rect.height,
+        });
+        self.popover.show_all();
+        match hint {
+            Some(function) => {
+                self.hint
+                    .set_text(&format!("{}\n{}", function.signature(), function.doc));
+                self.list.hide();
+            }
+            None => {
+                self.hint.hide();
+                self.select(0);
+            }
+        }
+    }
+}
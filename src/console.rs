@@ -0,0 +1,190 @@
+use gtk::traits::*;
+use gtk::{GtkListStoreExtManual, TreeModelFilterExtManual};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Instant;
+use truescad_luascad::printbuffer::{Level, Message};
+
+// Columns of the store.
+const TIME: u32 = 0;
+const LEVEL: u32 = 1;
+const LOCATION: u32 = 2;
+const TEXT: u32 = 3;
+// The line of the message, 0 if it has none.
+const LINE: u32 = 4;
+// The Level as number, for filtering.
+const SEVERITY: u32 = 5;
+
+// Choices of the level filter, see Level.
+const LEVEL_FILTERS: [(&str, Level); 3] = [
+    ("all", Level::Info),
+    ("warnings and errors", Level::Warning),
+    ("errors", Level::Error),
+];
+
+// The location callback, if any.
+type LocationActivated = Rc<RefCell<Option<Rc<dyn Fn(usize)>>>>;
+
+// Panel below the editor listing the messages of the last evaluation, with their time since
+// its start and the line of the script they came from. Activating a message with a line calls
+// the location callback, e.g. to show the line in the editor. Messages can be filtered by level
+// and text.
+#[derive(Clone)]
+pub struct ConsolePanel {
+    pub widget: ::gtk::Box,
+    store: ::gtk::ListStore,
+    view: ::gtk::TreeView,
+    filter: ::gtk::TreeModelFilter,
+    level: ::gtk::ComboBoxText,
+    search: ::gtk::SearchEntry,
+    // When the current evaluation started, for the times of messages from outside the script.
+    start: Rc<Cell<Instant>>,
+    location_activated: LocationActivated,
+}
+
+impl ConsolePanel {
+    pub fn new() -> ConsolePanel {
+        let widget = ::gtk::Box::new(::gtk::Orientation::Vertical, 0);
+        let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
+        let level = ::gtk::ComboBoxText::new();
+        for &(name, _) in &LEVEL_FILTERS {
+            level.append_text(name);
+        }
+        level.set_active(0);
+        level.set_tooltip_text("the least important messages to list");
+        let search = ::gtk::SearchEntry::new();
+        search.set_tooltip_text("only list messages containing this");
+        h_box.pack_start(&level, false, false, 5);
+        h_box.pack_start(&search, true, true, 5);
+        let store = ::gtk::ListStore::new(&[
+            ::gtk::Type::String,
+            ::gtk::Type::String,
+            ::gtk::Type::String,
+            ::gtk::Type::String,
+            ::gtk::Type::U32,
+            ::gtk::Type::U32,
+        ]);
+        let filter = ::gtk::TreeModelFilter::new(&store, None);
+        let view = ::gtk::TreeView::new_with_model(&filter);
+        view.set_tooltip_text("activate a message with a line to show it in the editor");
+        for &(title, column) in &[
+            ("time", TIME),
+            ("level", LEVEL),
+            ("line", LOCATION),
+            ("message", TEXT),
+        ] {
+            let cell = ::gtk::CellRendererText::new();
+            let view_column = ::gtk::TreeViewColumn::new();
+            view_column.set_title(title);
+            view_column.pack_start(&cell, true);
+            view_column.add_attribute(&cell, "text", column as i32);
+            view.append_column(&view_column);
+        }
+        let scrolled_window = ::gtk::ScrolledWindow::new(None, None);
+        scrolled_window.add(&view);
+        widget.pack_start(&h_box, false, false, 0);
+        widget.pack_start(&scrolled_window, true, true, 0);
+        let panel = ConsolePanel {
+            widget,
+            store,
+            view,
+            filter,
+            level,
+            search,
+            start: Rc::new(Cell::new(Instant::now())),
+            location_activated: Rc::new(RefCell::new(None)),
+        };
+        {
+            let (level, search) = (panel.level.clone(), panel.search.clone());
+            panel.filter.set_visible_func(move |model, iter| {
+                let min = LEVEL_FILTERS
+                    .get(level.get_active() as usize)
+                    .map_or(Level::Info, |&(_, l)| l) as u32;
+                let severity = model
+                    .get_value(iter, SEVERITY as i32)
+                    .get::<u32>()
+                    .unwrap_or(0);
+                let pattern = search.get_text().unwrap_or_default().to_lowercase();
+                let text = model
+                    .get_value(iter, TEXT as i32)
+                    .get::<String>()
+                    .unwrap_or_default();
+                severity >= min && text.to_lowercase().contains(&pattern)
+            });
+        }
+        {
+            let filter = panel.filter.clone();
+            panel.level.connect_changed(move |_| filter.refilter());
+        }
+        {
+            let filter = panel.filter.clone();
+            panel
+                .search
+                .connect_search_changed(move |_| filter.refilter());
+        }
+        {
+            let location_activated = panel.location_activated.clone();
+            panel.view.connect_row_activated(move |view, path, _| {
+                let line = view
+                    .get_model()
+                    .and_then(|model| {
+                        let iter = model.get_iter(path)?;
+                        model.get_value(&iter, LINE as i32).get::<u32>()
+                    })
+                    .unwrap_or(0);
+                let f = location_activated.borrow().clone();
+                if let (Some(f), true) = (f, line > 0) {
+                    f(line as usize);
+                }
+            });
+        }
+        panel
+    }
+    // Call f with the line of a message which was activated.
+    pub fn connect_location_activated<F: Fn(usize) + 'static>(&self, f: F) {
+        *self.location_activated.borrow_mut() = Some(Rc::new(f));
+    }
+    // Remove all messages and start the times of new ones.
+    pub fn clear(&self) {
+        self.store.clear();
+        self.start.set(Instant::now());
+    }
+    // A message at the current time.
+    pub fn message<S: Into<String>>(&self, level: Level, line: Option<usize>, text: S) -> Message {
+        Message {
+            level,
+            time: self.start.get().elapsed(),
+            line,
+            text: text.into(),
+        }
+    }
+    // Add a message at the current time.
+    pub fn log<S: Into<String>>(&self, level: Level, line: Option<usize>, text: S) {
+        let message = self.message(level, line, text);
+        self.add(&message);
+    }
+    pub fn add(&self, message: &Message) {
+        let time = message.time;
+        let time = format!("{}.{:03}", time.as_secs(), time.subsec_millis());
+        let location = message.line.map_or(String::new(), |line| line.to_string());
+        let line = message.line.unwrap_or(0) as u32;
+        self.store.insert_with_values(
+            None,
+            &[TIME, LEVEL, LOCATION, TEXT, LINE, SEVERITY],
+            &[
+                &time,
+                &message.level.to_string(),
+                &location,
+                &message.text.trim_end(),
+                &line,
+                &(message.level as u32),
+            ],
+        );
+    }
+}
+
+impl Default for ConsolePanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,181 @@
+use super::Float;
+use gtk::traits::*;
+use gtk::{Cast, SpinButtonSignals};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use truescad_luascad::params::{Kind, Parameter, Value};
+
+// Range of number fields without min or max.
+const MAX_FIELD_VALUE: Float = 1e6;
+
+// The function connect_changed set, if any.
+type Changed = Rc<RefCell<Option<Rc<dyn Fn()>>>>;
+
+// Panel below the view with a slider, field, checkbox or dropdown for each parameter the script
+// declares with param{...}, so designs can be changed without editing the script. It is hidden
+// for scripts without parameters.
+#[derive(Clone)]
+pub struct CustomizerPanel {
+    pub widget: ::gtk::Expander,
+    grid: ::gtk::Grid,
+    // The declarations the widgets were made for.
+    parameters: Rc<RefCell<Vec<Parameter>>>,
+    // The values set in the widgets, others are at their defaults.
+    values: Rc<RefCell<HashMap<String, Value>>>,
+    changed: Changed,
+}
+
+impl CustomizerPanel {
+    pub fn new() -> CustomizerPanel {
+        let widget = ::gtk::Expander::new(Some("Parameters"));
+        widget.set_expanded(true);
+        widget.set_no_show_all(true);
+        let grid = ::gtk::Grid::new();
+        grid.set_column_spacing(5);
+        widget.add(&grid);
+        CustomizerPanel {
+            widget,
+            grid,
+            parameters: Rc::new(RefCell::new(Vec::new())),
+            values: Rc::new(RefCell::new(HashMap::new())),
+            changed: Rc::new(RefCell::new(None)),
+        }
+    }
+    // Call f after a value was changed in the panel.
+    pub fn connect_changed<F: Fn() + 'static>(&self, f: F) {
+        *self.changed.borrow_mut() = Some(Rc::new(f));
+    }
+    // The values to evaluate the script with, see truescad_luascad::evaluate_with.
+    pub fn values(&self) -> HashMap<String, Value> {
+        self.values.borrow().clone()
+    }
+    // Show widgets for parameters. Values of parameters which were declared the same way before
+    // are kept, the others start at their defaults. Values set for parameters which were not
+    // declared before (see set_values) are kept as well.
+    pub fn show(&self, parameters: &[Parameter]) {
+        if *self.parameters.borrow() == parameters {
+            return;
+        }
+        let previous = self.parameters.replace(parameters.to_vec());
+        self.values.borrow_mut().retain(|name, _| {
+            match previous.iter().find(|p| p.name == *name) {
+                Some(p) => parameters.contains(p),
+                None => true,
+            }
+        });
+        self.rebuild();
+    }
+    // Replace all values, e.g. by those of a project, for the parameters the script will declare.
+    pub fn set_values(&self, values: HashMap<String, Value>) {
+        *self.parameters.borrow_mut() = Vec::new();
+        *self.values.borrow_mut() = values;
+        self.rebuild();
+    }
+    // Set the parameter name to value, e.g. from a gizmo in the view, and call the changed
+    // callback.
+    pub fn set(&self, name: &str, value: Value) {
+        self.values.borrow_mut().insert(name.to_string(), value);
+        self.rebuild();
+        let f = self.changed.borrow().clone();
+        if let Some(f) = f {
+            f();
+        }
+    }
+    // Replace the widgets by ones for the parameters, showing their values.
+    fn rebuild(&self) {
+        for child in self.grid.get_children() {
+            self.grid.remove(&child);
+        }
+        let parameters = self.parameters.borrow().clone();
+        for (row, parameter) in parameters.iter().enumerate() {
+            let label = ::gtk::Label::new(Some(parameter.name.as_str()));
+            label.set_halign(::gtk::Align::Start);
+            self.grid.attach(&label, 0, row as i32, 1, 1);
+            let value = parameter.value(&self.values.borrow());
+            let input = self.input(parameter, &value);
+            input.set_hexpand(true);
+            self.grid.attach(&input, 1, row as i32, 1, 1);
+        }
+        if parameters.is_empty() {
+            self.widget.hide();
+        } else {
+            self.widget.show();
+            self.grid.show_all();
+        }
+    }
+    // The widget to set parameter, starting at value.
+    fn input(&self, parameter: &Parameter, value: &Value) -> ::gtk::Widget {
+        let set = {
+            let name = parameter.name.clone();
+            let values = self.values.clone();
+            let changed = self.changed.clone();
+            move |value: Value| {
+                values.borrow_mut().insert(name.clone(), value);
+                let f = changed.borrow().clone();
+                if let Some(f) = f {
+                    f();
+                }
+            }
+        };
+        match (&parameter.kind, value) {
+            (&Kind::Number { min, max, step }, &Value::Number(x)) => {
+                let digits = (-step.log10()).ceil().max(0.) as u32;
+                if let (Some(min), Some(max)) = (min, max) {
+                    let scale = ::gtk::Scale::new_with_range(
+                        ::gtk::Orientation::Horizontal,
+                        min.into(),
+                        max.into(),
+                        step.into(),
+                    );
+                    scale.set_digits(digits as i32);
+                    scale.set_value(x.into());
+                    scale.connect_value_changed(move |scale| {
+                        set(Value::Number(scale.get_value() as Float))
+                    });
+                    scale.upcast()
+                } else {
+                    let field = ::gtk::SpinButton::new_with_range(
+                        min.unwrap_or(-MAX_FIELD_VALUE).into(),
+                        max.unwrap_or(MAX_FIELD_VALUE).into(),
+                        step.into(),
+                    );
+                    field.set_digits(digits);
+                    field.set_value(x.into());
+                    field.connect_value_changed(move |field| {
+                        set(Value::Number(field.get_value() as Float))
+                    });
+                    field.upcast()
+                }
+            }
+            (Kind::Choice(choices), Value::Choice(c)) => {
+                let dropdown = ::gtk::ComboBoxText::new();
+                for choice in choices {
+                    dropdown.append_text(choice);
+                }
+                let active = choices.iter().position(|choice| choice == c).unwrap_or(0);
+                dropdown.set_active(active as i32);
+                dropdown.connect_changed(move |dropdown| {
+                    if let Some(text) = dropdown.get_active_text() {
+                        set(Value::Choice(text));
+                    }
+                });
+                dropdown.upcast()
+            }
+            (_, &Value::Bool(b)) => {
+                let check = ::gtk::CheckButton::new();
+                check.set_active(b);
+                check.connect_toggled(move |check| set(Value::Bool(check.get_active())));
+                check.upcast()
+            }
+            // Parameter::value only returns values of the kind of the parameter.
+            _ => ::gtk::Label::new(None).upcast(),
+        }
+    }
+}
+
+impl Default for CustomizerPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,323 @@
+use super::Float;
+use features;
+use indexed_mesh::{Edge, IndexedMesh};
+use na;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use truescad_luascad::implicit3d::Object;
+
+// Technical drawings: the front, top and right views of a mesh in third angle projection (the
+// top view above the front view, the right view right of it), as SVG at 1:1 in millimeters. Each
+// view shows the feature lines of the mesh (see features) and its silhouette, the edges between
+// faces turned towards and away from the viewer. Those the object hides, found by tracing its
+// values towards the viewer, are dashed. Below and left of each view, the extent of the object
+// is dimensioned, and the distances of the anchors of the script (see drawing_anchor) from its
+// lower left corner, in the units of the script.
+
+// The height of text and the length of arrows, in millimeters.
+const TEXT_SIZE: Float = 3.5;
+const ARROW: Float = 2.5;
+// The distance of the first dimension line from its view, and between dimension lines.
+const DIMENSION_SPACING: Float = 8.;
+// The space between the dimensions of a view and the next view, and around the drawing.
+const GAP: Float = 10.;
+// Half the size of the crosses marking anchors.
+const MARK: Float = 1.5;
+// Lines are hidden where the object is deeper than this many resolutions between them and the
+// viewer.
+const HIDDEN_DEPTH: Float = 0.5;
+
+// A view of the object, looking at it against right x up.
+struct View {
+    right: na::Vector3<Float>,
+    up: na::Vector3<Float>,
+}
+
+impl View {
+    fn toward_viewer(&self) -> na::Vector3<Float> {
+        self.right.cross(&self.up)
+    }
+    fn project(&self, p: &na::Point3<Float>) -> na::Point2<Float> {
+        na::Point2::new(p.coords.dot(&self.right), p.coords.dot(&self.up))
+    }
+}
+
+// A drawing in millimeters, with y pointing up.
+#[derive(Default)]
+struct Sheet {
+    visible: Vec<Vec<na::Point2<Float>>>,
+    hidden: Vec<Vec<na::Point2<Float>>>,
+    // Dimension lines, extension lines, arrows and the marks of anchors.
+    thin: Vec<Vec<na::Point2<Float>>>,
+    // Texts at the middle of their baselines, and whether they run upwards.
+    texts: Vec<(na::Point2<Float>, String, bool)>,
+}
+
+// Write the drawing of mesh, the tessellation of object with resolution, to path, dimensioning
+// anchors in the units of the mesh.
+pub fn save(
+    path: &str,
+    mesh: &IndexedMesh,
+    object: &dyn Object<Float>,
+    resolution: Float,
+    anchors: &[(String, na::Point3<Float>)],
+) -> Result<(), String> {
+    let sheet = draw(mesh, object, resolution, anchors);
+    let file = File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+    write_svg(&mut BufWriter::new(file), &sheet)
+        .map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+fn draw(
+    mesh: &IndexedMesh,
+    object: &dyn Object<Float>,
+    resolution: Float,
+    anchors: &[(String, na::Point3<Float>)],
+) -> Sheet {
+    let x = na::Vector3::x();
+    let y = na::Vector3::y();
+    let z = na::Vector3::z();
+    let (front, top, right) = (
+        View { right: x, up: z },
+        View { right: x, up: y },
+        View { right: y, up: z },
+    );
+    let millimeters = mesh.units.millimeters();
+    let (min, max) = mesh.bounds();
+    let size = |view: &View| (view.project(&max) - view.project(&min)) * millimeters;
+    // Room for the dimensions between the views.
+    let room = DIMENSION_SPACING * (1 + anchors.len()) as Float + GAP;
+    let placed = [
+        (&front, na::Point2::origin()),
+        (&top, na::Point2::new(0., size(&front).y + room)),
+        (&right, na::Point2::new(size(&front).x + room, 0.)),
+    ];
+    let edge_faces = mesh.edge_faces();
+    let normals: Vec<na::Vector3<Float>> =
+        (0..mesh.faces.len()).map(|f| mesh.face_normal(f)).collect();
+    let cos = features::DEFAULT_ANGLE.to_radians().cos();
+    let mut sheet = Sheet::default();
+    for &(view, origin) in &placed {
+        let toward = view.toward_viewer();
+        let corner = view.project(&min);
+        // Where p of the object is on the sheet.
+        let place = |p: &na::Point3<Float>| origin + (view.project(p) - corner) * millimeters;
+        let (mut visible, mut hidden): (Vec<Edge>, Vec<Edge>) = (Vec::new(), Vec::new());
+        for (&(a, b), faces) in &edge_faces {
+            let drawn = match faces[..] {
+                [f, g] => {
+                    normals[f].dot(&normals[g]) < cos
+                        || (normals[f].dot(&toward) > 0.) != (normals[g].dot(&toward) > 0.)
+                }
+                _ => true,
+            };
+            if !drawn {
+                continue;
+            }
+            let middle = na::center(&mesh.vertices[a], &mesh.vertices[b]);
+            if is_visible(object, &middle, &toward, resolution) {
+                visible.push((a, b));
+            } else {
+                hidden.push((a, b));
+            }
+        }
+        let lines = |edges: &[Edge]| -> Vec<Vec<na::Point2<Float>>> {
+            features::chain(edges)
+                .iter()
+                .map(|line| line.iter().map(|&v| place(&mesh.vertices[v])).collect())
+                .collect()
+        };
+        sheet.visible.extend(lines(&visible));
+        sheet.hidden.extend(lines(&hidden));
+        let extent = origin + size(view);
+        sheet.horizontal(origin, na::Point2::new(extent.x, origin.y), 1, millimeters);
+        sheet.vertical(origin, na::Point2::new(origin.x, extent.y), 1, millimeters);
+        for (i, (name, anchor)) in anchors.iter().enumerate() {
+            let at = place(anchor);
+            sheet.horizontal(origin, at, i + 2, millimeters);
+            sheet.vertical(origin, at, i + 2, millimeters);
+            sheet.thin.push(vec![
+                at - na::Vector2::x() * MARK,
+                at + na::Vector2::x() * MARK,
+            ]);
+            sheet.thin.push(vec![
+                at - na::Vector2::y() * MARK,
+                at + na::Vector2::y() * MARK,
+            ]);
+            sheet
+                .texts
+                .push((at + na::Vector2::y() * (MARK * 2.), name.clone(), false));
+        }
+    }
+    sheet
+}
+
+// Whether the point p on the surface of object can be seen from toward, by sphere tracing the
+// ray from p towards the viewer until it leaves the box of the object.
+fn is_visible(
+    object: &dyn Object<Float>,
+    p: &na::Point3<Float>,
+    toward: &na::Vector3<Float>,
+    resolution: Float,
+) -> bool {
+    let bbox = object.bbox();
+    let length = (bbox.max - bbox.min).norm();
+    let mut t = resolution;
+    while t < length {
+        let value = object.approx_value(&(p + toward * t), resolution);
+        if value < -resolution * HIDDEN_DEPTH {
+            return false;
+        }
+        t += value.abs().max(resolution / 2.);
+    }
+    true
+}
+
+impl Sheet {
+    // The distance along x from a on the bottom edge of a view to b, on the level-th dimension
+    // line below the view.
+    fn horizontal(
+        &mut self,
+        a: na::Point2<Float>,
+        b: na::Point2<Float>,
+        level: usize,
+        millimeters: Float,
+    ) {
+        let length = (b.x - a.x).abs();
+        if length <= 0. {
+            return;
+        }
+        let y = a.y - DIMENSION_SPACING * level as Float;
+        let (from, to) = (na::Point2::new(a.x, y), na::Point2::new(b.x, y));
+        self.thin
+            .push(vec![a, from - na::Vector2::y() * (ARROW / 2.)]);
+        self.thin
+            .push(vec![b, to - na::Vector2::y() * (ARROW / 2.)]);
+        self.dimension(from, to, length / millimeters, false);
+    }
+    // The distance along y from a on the left edge of a view to b, on the level-th dimension
+    // line left of the view.
+    fn vertical(
+        &mut self,
+        a: na::Point2<Float>,
+        b: na::Point2<Float>,
+        level: usize,
+        millimeters: Float,
+    ) {
+        let length = (b.y - a.y).abs();
+        if length <= 0. {
+            return;
+        }
+        let x = a.x - DIMENSION_SPACING * level as Float;
+        let (from, to) = (na::Point2::new(x, a.y), na::Point2::new(x, b.y));
+        self.thin
+            .push(vec![a, from - na::Vector2::x() * (ARROW / 2.)]);
+        self.thin
+            .push(vec![b, to - na::Vector2::x() * (ARROW / 2.)]);
+        self.dimension(from, to, length / millimeters, true);
+    }
+    // A dimension line from from to to with arrows at both ends, labeled with length above it,
+    // or left of it if it runs upwards.
+    fn dimension(
+        &mut self,
+        from: na::Point2<Float>,
+        to: na::Point2<Float>,
+        length: Float,
+        upwards: bool,
+    ) {
+        let along = (to - from).normalize();
+        let across = na::Vector2::new(-along.y, along.x) * (ARROW / 3.);
+        for &(tip, back) in &[(to, along), (from, -along)] {
+            let base = tip - back * ARROW;
+            self.thin.push(vec![base + across, tip, base - across]);
+        }
+        self.thin.push(vec![from, to]);
+        let middle = na::center(&from, &to);
+        let label = if upwards {
+            middle - na::Vector2::x()
+        } else {
+            middle + na::Vector2::y()
+        };
+        self.texts.push((label, number(length), upwards));
+    }
+}
+
+// x with at most two decimals, without trailing zeros.
+fn number(x: Float) -> String {
+    let s = format!("{:.2}", x);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_svg<W: Write>(w: &mut W, sheet: &Sheet) -> io::Result<()> {
+    let (mut min, mut max) = (na::Point2::origin(), na::Point2::origin());
+    let lines = sheet.visible.iter().chain(&sheet.hidden).chain(&sheet.thin);
+    for p in lines.flatten() {
+        min = na::inf(&min, p);
+        max = na::sup(&max, p);
+    }
+    // Text reaches about its height around where it is.
+    for &(p, _, _) in &sheet.texts {
+        let reach = na::Vector2::new(TEXT_SIZE, TEXT_SIZE);
+        min = na::inf(&min, &(p - reach));
+        max = na::sup(&max, &(p + reach));
+    }
+    let (width, height) = (max.x - min.x + 2. * GAP, max.y - min.y + 2. * GAP);
+    // Where p of the sheet is in the SVG, whose y points down.
+    let at = |p: &na::Point2<Float>| (p.x - min.x + GAP, max.y + GAP - p.y);
+    writeln!(
+        w,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}mm\" height=\"{height}mm\" \
+         viewBox=\"0 0 {width} {height}\">",
+        width = width,
+        height = height
+    )?;
+    let groups = [
+        (
+            &sheet.hidden,
+            "stroke-width=\"0.35\" stroke-dasharray=\"2 1\"",
+        ),
+        (&sheet.thin, "stroke-width=\"0.18\""),
+        (&sheet.visible, "stroke-width=\"0.5\""),
+    ];
+    for &(lines, style) in &groups {
+        writeln!(w, "<g fill=\"none\" stroke=\"black\" {}>", style)?;
+        for line in lines {
+            let points: Vec<String> = line
+                .iter()
+                .map(|p| {
+                    let (x, y) = at(p);
+                    format!("{},{}", x, y)
+                })
+                .collect();
+            writeln!(w, "<polyline points=\"{}\"/>", points.join(" "))?;
+        }
+        writeln!(w, "</g>")?;
+    }
+    writeln!(
+        w,
+        "<g font-family=\"sans-serif\" font-size=\"{}\" text-anchor=\"middle\">",
+        TEXT_SIZE
+    )?;
+    for &(ref p, ref text, upwards) in &sheet.texts {
+        let (x, y) = at(p);
+        if upwards {
+            writeln!(
+                w,
+                "<text x=\"{x}\" y=\"{y}\" transform=\"rotate(-90 {x} {y})\">{}</text>",
+                escape(text),
+                x = x,
+                y = y
+            )?;
+        } else {
+            writeln!(w, "<text x=\"{}\" y=\"{}\">{}</text>", x, y, escape(text))?;
+        }
+    }
+    writeln!(w, "</g>\n</svg>")
+}
@@ -1,46 +1,175 @@
 use super::Float;
+use completion;
+use console;
+use customizer;
+use flat_tree;
+use gpu;
 use gtk::traits::*;
-use gtk::Inhibit;
+use gtk::{Continue, Inhibit};
+use indexed_mesh::IndexedMesh;
+use jit;
+use mesh_boolean;
 use mesh_view;
 use na;
 use object_widget;
+use project::Project;
+use properties;
+use reference;
+use render;
 use settings;
-use sourceview::{BufferExt, LanguageManagerExt, StyleSchemeManagerExt};
+use sourceview::{
+    BufferExt, LanguageManagerExt, MarkAttributesExt, StyleSchemeManagerExt, ViewExt,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
-use tessellation::{ImplicitFunction, ManifoldDualContouring, Mesh};
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use tessellation::{ImplicitFunction, ManifoldDualContouring};
+use tree_panel;
 use truescad_luascad;
+use truescad_luascad::analysis;
+use truescad_luascad::bounded;
+use truescad_luascad::editable::Editable;
 use truescad_luascad::implicit3d;
+use truescad_luascad::include::Includes;
+use truescad_luascad::lobject::ColoredPart;
+use truescad_luascad::normals::{Estimated, NormalEstimate};
+use truescad_luascad::params::Value;
+use truescad_luascad::printbuffer::{Level, Message};
+use truescad_luascad::tree::Node;
+use truescad_luascad::units::Units;
+use truescad_luascad::{Evaluation, ScriptError};
+use worker::{self, Worker};
+
+// The console lists at most this many regions found by an analysis.
+const MAX_REGIONS: usize = 10;
+// Category of the source marks on lines with script errors.
+const ERROR_MARK: &str = "error";
+// Number of evaluations kept, see Editor::cached.
+const MAX_CACHED_EVALUATIONS: usize = 16;
+// Normals estimated from values (see NormalEstimate) are differences over this fraction of the
+// resolution of the tessellation.
+const NORMAL_STEP: Float = 1e-3;
+
+// An evaluation job with its result, from the evaluation worker.
+type Evaluated = (EvaluationJob, Result<Evaluation, ScriptError>);
+// An object, how it was built and its units, see Editor::get_object.
+type Built = (Box<dyn implicit3d::Object<Float>>, Option<Node>, Units);
 
 #[derive(Clone)]
 pub struct Editor {
     pub widget: ::gtk::ScrolledWindow,
     source_view: ::sourceview::View,
     buffer: Option<::sourceview::Buffer>,
+    // The parts of the last object with the colors set with o:color, None for the material
+    // color. Empty if nothing has a color.
+    colored_parts: Rc<RefCell<Vec<ColoredPart>>>,
+    // The style of the source view with the font from the settings.
+    font: ::gtk::CssProvider,
+    renderer: Rc<RefCell<render::Renderer>>,
+    drawing_area: ::gtk::DrawingArea,
+    // Value of the Lua variable time.
+    time: Rc<Cell<Float>>,
+    // Units of the evaluated object, for measurements in the object widget.
+    units: Rc<Cell<Units>>,
+    pub properties: properties::PropertiesPanel,
+    pub tree_panel: tree_panel::TreePanel,
+    pub customizer: customizer::CustomizerPanel,
+    pub console: console::ConsolePanel,
+    pub reference: reference::ReferencePanel,
+    // The parts of the object the script declared editable, with a gizmo in the view.
+    editables: Rc<RefCell<Vec<Editable>>>,
+    // Where the script finds what it includes, and the names of what the last evaluation
+    // included.
+    includes: Rc<RefCell<Includes>>,
+    included: Rc<RefCell<Vec<String>>>,
+    // The box the last evaluation set with set_tessellation_region, if it set one.
+    tessellation_region: Rc<RefCell<Option<implicit3d::BoundingBox<Float>>>>,
+    // Recent evaluations, the most recently used one last.
+    evaluations: Rc<RefCell<Vec<(EvaluationJob, Evaluation)>>>,
+    // The script is evaluated and tessellated on threads of their own, while the view shows the
+    // previous object with a badge.
+    view: object_widget::ObjectWidget,
+    evaluation_worker: Rc<Worker<EvaluationJob, Evaluated>>,
+    tessellation_worker: Rc<Worker<TessellationJob, Tessellated>>,
+    // Message of the last script error, shown as tooltip of its mark.
+    error: Rc<RefCell<String>>,
+    // The resolution and error of the tessellation to start once the running evaluation is
+    // done, see tessellate.
+    pending_tessellation: Rc<Cell<Option<(Float, Float)>>>,
+}
+
+// An evaluation of script at time with the parameter values.
+#[derive(Clone, PartialEq)]
+struct EvaluationJob {
+    script: String,
+    time: Float,
+    values: HashMap<String, Value>,
+    includes: Includes,
+}
+
+fn evaluate_job(
+    job: EvaluationJob,
+    _: &AtomicBool,
+) -> (EvaluationJob, Result<Evaluation, ScriptError>) {
+    // Lua can not be interrupted, the result of a cancelled evaluation is dropped.
+    let result = truescad_luascad::evaluate_in(&job.script, job.time, &job.values, &job.includes)
+        .map_err(|e| ScriptError::from(&e));
+    (job, result)
+}
+
+struct TessellationJob {
+    object: Box<dyn implicit3d::Object<Float>>,
+    tree: Option<Node>,
+    units: Units,
+    resolution: Float,
+    error: Float,
 }
 
-struct ObjectAdaptor<S> {
-    implicit: Box<dyn implicit3d::Object<S>>,
-    resolution: S,
+// A mesh, if the tessellation succeeded, with the warnings of the tessellation for the console.
+type Tessellated = (Option<IndexedMesh>, Vec<String>);
+
+fn tessellate_job(job: TessellationJob, _: &AtomicBool) -> Tessellated {
+    let mut warnings = Vec::new();
+    let mesh = tessellate(
+        job.object,
+        job.tree.as_ref(),
+        job.units,
+        job.resolution,
+        job.error,
+        &mut warnings,
+    );
+    (mesh, warnings)
+}
+
+// The object to tessellate, with the corners of the grid evaluated with its flat tree if there is
+// one, see flat_tree::Corners.
+struct ObjectAdaptor {
+    implicit: Box<dyn implicit3d::Object<Float>>,
+    corners: Option<flat_tree::Corners>,
+    resolution: Float,
 }
 
-impl<S: ::std::fmt::Debug + na::Real + ::alga::general::Real + ::num_traits::Float + From<f32>>
-    ImplicitFunction<S> for ObjectAdaptor<S>
-{
-    fn bbox(&self) -> &implicit3d::BoundingBox<S> {
+impl ImplicitFunction<Float> for ObjectAdaptor {
+    fn bbox(&self) -> &implicit3d::BoundingBox<Float> {
         self.implicit.bbox()
     }
-    fn value(&self, p: &na::Point3<S>) -> S {
-        self.implicit.approx_value(&p, self.resolution)
+    fn value(&self, p: &na::Point3<Float>) -> Float {
+        match self.corners {
+            Some(ref corners) => corners.value(p),
+            None => self.implicit.approx_value(p, self.resolution),
+        }
     }
-    fn normal(&self, p: &na::Point3<S>) -> na::Vector3<S> {
-        self.implicit.normal(&p)
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.implicit.normal(p)
     }
 }
 
 impl Editor {
-    pub fn new(xw: &object_widget::ObjectWidget, debug_buffer: &::gtk::TextBuffer) -> Editor {
+    pub fn new(xw: &object_widget::ObjectWidget) -> Editor {
         let widget = ::gtk::ScrolledWindow::new(None, None);
         let mut buffer = None;
         let mut src_view = ::sourceview::View::new();
@@ -72,35 +201,304 @@ impl Editor {
             println!("failed to get default LanguageManager.");
         }
         src_view.set_monospace(true);
+        src_view.set_show_line_numbers(true);
+        src_view.set_show_line_marks(true);
+        let font = ::gtk::CssProvider::new();
+        if let Some(context) = src_view.get_style_context() {
+            context.add_provider(&font, ::gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+        }
+        let error = Rc::new(RefCell::new(String::new()));
+        let error_attributes = ::sourceview::MarkAttributes::new();
+        error_attributes.set_icon_name("dialog-error");
+        error_attributes.set_background(&::gdk::RGBA {
+            red: 1.,
+            green: 0.8,
+            blue: 0.8,
+            alpha: 1.,
+        });
+        let error_clone = error.clone();
+        error_attributes.connect_query_tooltip_text(move |_, _| error_clone.borrow().clone());
+        src_view.set_mark_attributes(ERROR_MARK, &error_attributes, 0);
+        // The completion lives as long as the handlers it connects to the view.
+        completion::Completion::new(&src_view);
         widget.add(&src_view);
         // TODO: Find out why this causes a non-draw on startup.
         // tv.set_wrap_mode(::gtk::WrapMode::WordChar);
-        let renderer = xw.renderer.clone();
-        let drawing_area = xw.drawing_area.clone();
-        let debug_buffer_clone = debug_buffer.clone();
         let editor = Editor {
             widget,
             source_view: src_view,
             buffer,
+            colored_parts: Rc::new(RefCell::new(Vec::new())),
+            font,
+            renderer: xw.renderer.clone(),
+            drawing_area: xw.drawing_area.clone(),
+            time: Rc::new(Cell::new(0.)),
+            units: xw.units.clone(),
+            properties: properties::PropertiesPanel::new(),
+            tree_panel: tree_panel::TreePanel::new(&xw.renderer, &xw.drawing_area),
+            customizer: customizer::CustomizerPanel::new(),
+            console: console::ConsolePanel::new(),
+            reference: reference::ReferencePanel::new(),
+            editables: Rc::new(RefCell::new(Vec::new())),
+            includes: Rc::new(RefCell::new(Includes::default())),
+            included: Rc::new(RefCell::new(Vec::new())),
+            tessellation_region: Rc::new(RefCell::new(None)),
+            evaluations: Rc::new(RefCell::new(Vec::new())),
+            view: xw.clone(),
+            evaluation_worker: Rc::new(Worker::new("evaluation", evaluate_job)),
+            tessellation_worker: Rc::new(Worker::new("tessellation", tessellate_job)),
+            error,
+            pending_tessellation: Rc::new(Cell::new(None)),
         };
+        let console = editor.console.clone();
+        xw.connect_gpu_failed(move |e| {
+            console.log(
+                Level::Warning,
+                None,
+                format!("gpu: {}, rendering on the CPU", e),
+            );
+        });
+        let editor_clone = editor.clone();
+        ::gtk::timeout_add(worker::POLL_INTERVAL, move || {
+            editor_clone.poll_workers();
+            Continue(true)
+        });
+        let editor_clone = editor.clone();
+        editor.properties.compute.connect_clicked(move |_| {
+            editor_clone.mass_properties();
+        });
+        let editor_clone = editor.clone();
+        editor.tree_panel.connect_hidden_changed(move || {
+            editor_clone.evaluate();
+        });
+        let editor_clone = editor.clone();
+        editor.customizer.connect_changed(move || {
+            editor_clone.evaluate();
+        });
+        let editor_clone = editor.clone();
+        xw.connect_gizmo_dragged(move |gizmo, handle, delta| {
+            editor_clone.move_editable(gizmo, handle, delta);
+        });
+        let editor_clone = editor.clone();
+        editor.console.connect_location_activated(move |line| {
+            editor_clone.show_line(line);
+        });
+        let editor_clone = editor.clone();
+        editor.reference.connect_insert(move |signature| {
+            editor_clone.insert(signature);
+        });
         let editor_clone = editor.clone();
 
         editor.source_view.connect_key_release_event(
             move |_: &::sourceview::View, key: &::gdk::EventKey| -> Inhibit {
                 if let ::gdk::enums::key::F5 = key.get_keyval() {
-                    // compile
-                    let mut output = Vec::new();
-                    let obj = editor_clone.get_object(&mut output);
-                    debug_buffer_clone.set_text(&String::from_utf8(output).unwrap());
-                    renderer.borrow_mut().set_object(obj);
-                    drawing_area.queue_draw();
+                    // Run the script again, e.g. for changed meshes it loads.
+                    editor_clone.evaluations.borrow_mut().clear();
+                    editor_clone.evaluate();
                 }
                 Inhibit(false)
             },
         );
+        editor.apply_settings(&settings::SettingsData::default());
         editor
     }
-    fn get_object(&self, msg: &mut dyn Write) -> Option<Box<dyn implicit3d::Object<Float>>> {
+    pub fn apply_settings(&self, s: &settings::SettingsData) {
+        if let Err(e) = self
+            .font
+            .load_from_data(font_css(&s.editor_font).as_bytes())
+        {
+            println!("can not use font {:?}: {}", s.editor_font, e);
+        }
+    }
+    // Compile the script and show the result in the renderer, once it is done. An earlier
+    // evaluation of the same is shown at once.
+    pub fn evaluate(&self) {
+        let job = self.job();
+        match self.cached(&job) {
+            Some(evaluation) => {
+                self.evaluation_worker.cancel();
+                self.show_evaluation(Ok(evaluation.clone()));
+                self.tessellate_evaluation(Ok(evaluation));
+            }
+            None => self.evaluation_worker.submit(job),
+        }
+        self.update_badge();
+    }
+    // Show the results of the workers which are done.
+    fn poll_workers(&self) {
+        if let Some((job, result)) = self.evaluation_worker.poll() {
+            if let Ok(ref evaluation) = result {
+                self.cache(job, evaluation.clone());
+            }
+            self.show_evaluation(result.clone());
+            self.tessellate_evaluation(result);
+        }
+        if let Some((mesh, warnings)) = self.tessellation_worker.poll() {
+            self.show_tessellated(mesh.as_ref(), warnings);
+        }
+        self.update_badge();
+    }
+    fn update_badge(&self) {
+        self.view.set_badge(if self.evaluation_worker.busy() {
+            Some("rebuilding…")
+        } else if self.tessellation_worker.busy() {
+            Some("tessellating…")
+        } else {
+            None
+        });
+    }
+    fn show_evaluation(&self, result: Result<Evaluation, ScriptError>) {
+        self.console.clear();
+        let evaluated = self.object_of(result, &|m| self.console.add(&m));
+        // The mass properties are of the previous object.
+        self.properties.show(None, Units::default());
+        // Before borrowing the renderer, which the tree panel sets the highlight of.
+        let evaluated = evaluated.and_then(|(o, tree, units)| {
+            self.tree_panel.show(tree.as_ref());
+            self.hide(o, tree).map(|(o, tree)| (o, tree, units))
+        });
+        {
+            let mut renderer = self.renderer.borrow_mut();
+            match evaluated {
+                Some((o, tree, units)) => {
+                    self.units.set(units);
+                    let s = settings::SettingsData::default();
+                    if s.wall_thickness > 0. {
+                        self.report_thin_walls(&*o, s.wall_thickness, units);
+                    }
+                    if s.overhang_angle > 0. {
+                        self.report_overhangs(&*o, s.overhang_angle, units);
+                    }
+                    renderer.set_object(Some(o));
+                    renderer.set_flat_tree(flat_tree::for_rendering(tree.as_ref(), &parameters()));
+                    let shader =
+                        gpu::for_rendering(tree.as_ref(), &parameters()).unwrap_or_else(|e| {
+                            self.console.log(
+                                Level::Info,
+                                None,
+                                format!("gpu: {}, rendering on the CPU", e),
+                            );
+                            None
+                        });
+                    renderer.set_shader(shader);
+                    let positions: Vec<_> = self
+                        .editables
+                        .borrow()
+                        .iter()
+                        .map(|e| e.position())
+                        .collect();
+                    renderer.set_gizmos(&positions);
+                }
+                None => renderer.set_object(None),
+            }
+            let material = renderer.settings().material.color;
+            let parts = self
+                .colored_parts
+                .borrow()
+                .iter()
+                .map(|&(ref object, color)| render::ColoredPart {
+                    object: object.clone(),
+                    color: color.unwrap_or(material),
+                })
+                .collect();
+            renderer.set_parts(parts);
+        }
+        self.drawing_area.queue_draw();
+    }
+    // The object to render, with the nodes hidden in the tree panel replaced by Empty, or None
+    // if all of it is hidden.
+    fn hide(
+        &self,
+        o: Box<dyn implicit3d::Object<Float>>,
+        tree: Option<Node>,
+    ) -> Option<(Box<dyn implicit3d::Object<Float>>, Option<Node>)> {
+        let hidden = self.tree_panel.hidden();
+        let tree = match tree {
+            Some(ref tree) if !hidden.is_empty() => tree,
+            _ => return Some((self.compile(o, tree.as_ref()), tree)),
+        };
+        let visible = tree.without(&hidden);
+        if visible == Node::Empty {
+            return None;
+        }
+        match flat_tree::hide(o.clone(), &visible, &parameters()) {
+            Ok(o) => Some((o, Some(visible))),
+            Err(e) => {
+                self.console
+                    .log(Level::Error, None, format!("can not hide parts, {}", e));
+                Some((self.compile(o, Some(tree)), Some(tree.clone())))
+            }
+        }
+    }
+    // o compiled with jit::try_accelerate, or o itself with a warning in the console if that
+    // failed.
+    fn compile(
+        &self,
+        o: Box<dyn implicit3d::Object<Float>>,
+        tree: Option<&Node>,
+    ) -> Box<dyn implicit3d::Object<Float>> {
+        jit::try_accelerate(o.clone(), tree, &parameters()).unwrap_or_else(|e| {
+            self.console.log(
+                Level::Warning,
+                None,
+                format!("jit: {}, falling back to the interpreted object", e),
+            );
+            o
+        })
+    }
+    // List where the walls of o are thinner than threshold in the console.
+    fn report_thin_walls(&self, o: &dyn implicit3d::Object<Float>, threshold: Float, units: Units) {
+        let regions = match analysis::thin_walls(o, threshold) {
+            Some(regions) => regions,
+            None => return,
+        };
+        let mut text = format!(
+            "{} regions with walls thinner than {} {}\n",
+            regions.len(),
+            threshold,
+            units
+        );
+        for r in regions.iter().take(MAX_REGIONS) {
+            text.push_str(&format!(
+                "  {:.3} {} at ({:.2}, {:.2}, {:.2})\n",
+                r.thickness, units, r.at.x, r.at.y, r.at.z
+            ));
+        }
+        if regions.len() > MAX_REGIONS {
+            text.push_str("  ...\n");
+        }
+        self.console.log(Level::Warning, None, text);
+    }
+    // List where o overhangs by more than max_angle degrees in the console.
+    fn report_overhangs(&self, o: &dyn implicit3d::Object<Float>, max_angle: Float, units: Units) {
+        let regions = match analysis::overhangs(o, max_angle.to_radians()) {
+            Some(regions) => regions,
+            None => return,
+        };
+        let mut text = format!(
+            "{} regions overhanging by more than {} degrees\n",
+            regions.len(),
+            max_angle
+        );
+        for r in regions.iter().take(MAX_REGIONS) {
+            let (min, max) = (&r.bbox.min, &r.bbox.max);
+            text.push_str(&format!(
+                "  ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2}) {}\n",
+                min.x, min.y, min.z, max.x, max.y, max.z, units
+            ));
+        }
+        if regions.len() > MAX_REGIONS {
+            text.push_str("  ...\n");
+        }
+        self.console.log(Level::Warning, None, text);
+    }
+    // Set the Lua variable time (0..1) and re-evaluate the script.
+    pub fn set_time(&self, time: Float) {
+        self.time.set(time);
+        self.evaluate();
+    }
+    // The script with the current time and parameter values.
+    fn job(&self) -> EvaluationJob {
         let code_buffer = self.source_view.get_buffer().unwrap();
         let code_text = code_buffer
             .get_text(
@@ -109,73 +507,464 @@ impl Editor {
                 true,
             )
             .unwrap();
-        match truescad_luascad::eval(&code_text) {
-            Ok((print_result, maybe_object)) => {
-                writeln!(msg, "{}", print_result).unwrap();
-                match maybe_object {
+        EvaluationJob {
+            script: code_text,
+            time: self.time.get(),
+            values: self.customizer.values(),
+            includes: self.includes.borrow().clone(),
+        }
+    }
+    // The evaluation of the script, run here instead of in the background unless it is cached.
+    fn evaluation(&self) -> Result<Evaluation, ScriptError> {
+        let job = self.job();
+        match self.cached(&job) {
+            Some(evaluation) => Ok(evaluation),
+            None => {
+                let (job, result) = evaluate_job(job, &AtomicBool::new(false));
+                if let Ok(ref evaluation) = result {
+                    self.cache(job, evaluation.clone());
+                }
+                result
+            }
+        }
+    }
+    // The object with parameters from the settings, how it was built and its units, evaluated
+    // here instead of in the background. The messages of the evaluation go to log.
+    fn get_object(&self, log: &dyn Fn(Message)) -> Option<Built> {
+        self.object_of(self.evaluation(), log)
+    }
+    // The object of the result of an evaluation, see get_object.
+    fn object_of(
+        &self,
+        result: Result<Evaluation, ScriptError>,
+        log: &dyn Fn(Message),
+    ) -> Option<Built> {
+        self.mark_error(None);
+        match result {
+            Ok(evaluation) => {
+                self.customizer.show(&evaluation.parameters);
+                *self.editables.borrow_mut() = evaluation.editables;
+                *self.included.borrow_mut() = evaluation.included;
+                *self.tessellation_region.borrow_mut() = evaluation.tessellation_region;
+                for m in evaluation.messages {
+                    log(m);
+                }
+                let mut colored_parts = evaluation.colored_parts;
+                for &mut (ref mut part, _) in &mut colored_parts {
+                    part.set_parameters(&parameters());
+                }
+                *self.colored_parts.borrow_mut() = colored_parts;
+                match evaluation.object {
                     Some(mut o) => {
-                        let s = settings::SettingsData::default();
-                        o.set_parameters(&implicit3d::PrimitiveParameters {
-                            fade_range: s.fade_range,
-                            r_multiplier: s.r_multiplier,
-                        });
-                        Some(o)
+                        o.set_parameters(&parameters());
+                        if bounded::is_finite(o.bbox()) {
+                            let dim = o.bbox().dim();
+                            log(self.console.message(
+                                Level::Info,
+                                None,
+                                format!(
+                                    "bounding box: {:.2} x {:.2} x {:.2} {}",
+                                    dim.x, dim.y, dim.z, evaluation.units
+                                ),
+                            ));
+                        }
+                        Some((
+                            o,
+                            settings::SettingsData::default().optimize(evaluation.tree),
+                            evaluation.units,
+                        ))
                     }
                     None => {
-                        writeln!(msg, "\nwarning : no object - did you call build()?").unwrap();
+                        log(self.console.message(
+                            Level::Warning,
+                            None,
+                            "no object - did you call build()?",
+                        ));
                         None
                     }
                 }
             }
-            Err(x) => {
-                writeln!(msg, "\nerror : {:?}", x).unwrap();
+            Err(error) => {
+                self.colored_parts.borrow_mut().clear();
+                self.mark_error(Some(&error));
+                self.editables.borrow_mut().clear();
+                log(self
+                    .console
+                    .message(Level::Error, error.line, error.message));
                 None
             }
         }
     }
+    // Change the value of field (see editable::FIELDS) of the editable part by delta and
+    // evaluate the script with it.
+    fn move_editable(&self, index: usize, field: usize, delta: Float) {
+        let (name, value) = match self.editables.borrow().get(index) {
+            Some(e) => (e.parameter(field), e.value(field) + delta),
+            None => return,
+        };
+        self.customizer.set(&name, Value::Number(value));
+    }
+    // The result of an earlier evaluation of job, e.g. when a slider of the customizer is moved
+    // back.
+    fn cached(&self, job: &EvaluationJob) -> Option<Evaluation> {
+        let mut evaluations = self.evaluations.borrow_mut();
+        let i = evaluations.iter().position(|(j, _)| j == job)?;
+        let cached = evaluations.remove(i);
+        let evaluation = cached.1.clone();
+        evaluations.push(cached);
+        Some(evaluation)
+    }
+    fn cache(&self, job: EvaluationJob, evaluation: Evaluation) {
+        let mut evaluations = self.evaluations.borrow_mut();
+        if evaluations.len() >= MAX_CACHED_EVALUATIONS {
+            evaluations.remove(0);
+        }
+        evaluations.push((job, evaluation));
+    }
+    // Mark the line of error in the editor, or remove the mark of the previous one.
+    fn mark_error(&self, error: Option<&ScriptError>) {
+        let buffer = match self.buffer {
+            Some(ref buffer) => buffer,
+            None => return,
+        };
+        buffer.remove_source_marks(&buffer.get_start_iter(), &buffer.get_end_iter(), ERROR_MARK);
+        if let Some(error) = error {
+            *self.error.borrow_mut() = error.message.clone();
+            if let Some(line) = error.line {
+                let iter = buffer.get_iter_at_line(line as i32 - 1);
+                buffer.create_source_mark(None, ERROR_MARK, &iter);
+            }
+        }
+    }
+    // Compute the mass properties of the object with the density in the properties panel and
+    // show them there.
+    pub fn mass_properties(&self) {
+        self.properties.show(None, Units::default());
+        if let Some((obj, tree, units)) = self.get_object(&print_message) {
+            let obj = jit::accelerate(obj, tree.as_ref(), &parameters());
+            match analysis::mass_properties(&*obj, self.properties.density()) {
+                Some(m) => self.properties.show(Some(&m), units),
+                None => self.console.log(
+                    Level::Error,
+                    None,
+                    "can not compute the mass properties of an infinite object",
+                ),
+            }
+        }
+    }
     pub fn open(&self, filename: &str) {
-        let open_result = File::open(&filename);
+        let open_result = File::open(filename);
         if let Ok(f) = open_result {
             let reader = BufReader::new(f);
             let mut buffer = String::new();
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    buffer.push_str(&line);
-                    buffer.push_str("\n");
-                }
+            for line in reader.lines().map_while(Result::ok) {
+                buffer.push_str(&line);
+                buffer.push('\n');
             }
+            *self.includes.borrow_mut() = Includes::beside(filename);
             self.source_view.get_buffer().unwrap().set_text(&buffer);
         } else {
             println!("could not open {:?}: {:?}", &filename, open_result);
         }
     }
+    // Show the script of project with its parameter values, see project::Project.
+    pub fn open_project(&self, project: &Project) {
+        *self.includes.borrow_mut() = project.includes();
+        self.customizer.set_values(project.parameters.clone());
+        self.source_view
+            .get_buffer()
+            .unwrap()
+            .set_text(&project.script);
+    }
+    // project with the script, the scripts the last evaluation included and the parameter
+    // values, e.g. to save it.
+    pub fn project(&self, project: &Project) -> Project {
+        let buffer = self.source_view.get_buffer().unwrap();
+        let includes = self.includes.borrow();
+        Project {
+            script: buffer
+                .get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), true)
+                .unwrap(),
+            includes: self
+                .included
+                .borrow()
+                .iter()
+                .filter_map(|name| includes.source(name).ok().map(|s| (name.clone(), s)))
+                .collect(),
+            parameters: self.customizer.values(),
+            ..project.clone()
+        }
+    }
+    // Replace the script by filename if they differ, e.g. after it was changed in another
+    // editor, and evaluate it.
+    pub fn reload(&self, filename: &str) {
+        let text = match ::std::fs::read_to_string(filename) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("could not reload {:?}: {:?}", filename, e);
+                return;
+            }
+        };
+        let buffer = self.source_view.get_buffer().unwrap();
+        let current = buffer
+            .get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), true)
+            .unwrap();
+        if text != current {
+            buffer.set_text(&text);
+            self.evaluate();
+        }
+    }
+    // Put the cursor on line (starting at 1) and scroll to it.
+    pub fn show_line(&self, line: usize) {
+        let buffer = self.source_view.get_buffer().unwrap();
+        let mut iter = buffer.get_iter_at_line(line as i32 - 1);
+        buffer.place_cursor(&iter);
+        self.source_view
+            .scroll_to_iter(&mut iter, 0.1, false, 0., 0.);
+        self.source_view.grab_focus();
+    }
+    // Insert text at the cursor, e.g. a function from the reference.
+    pub fn insert(&self, text: &str) {
+        let buffer = self.source_view.get_buffer().unwrap();
+        buffer.insert_at_cursor(text);
+        self.source_view.grab_focus();
+    }
     pub fn save(&self, filename: &str) {
         save_from_sourceview(&self.source_view, filename);
+        // Scripts of a project are included from it, others from files next to the script.
+        let mut includes = self.includes.borrow_mut();
+        if includes.files.is_empty() {
+            *includes = Includes::beside(filename);
+        }
     }
-    pub fn tessellate(&self) -> Option<Mesh<Float>> {
-        let maybe_obj = self.get_object(&mut ::std::io::stdout());
-        if let Some(obj) = maybe_obj {
-            let s = settings::SettingsData::default();
-            let adaptor = ObjectAdaptor {
-                implicit: obj,
-                resolution: s.tessellation_resolution,
-            };
-
-            let mesh = ManifoldDualContouring::new(
-                &adaptor,
-                s.tessellation_resolution,
-                s.tessellation_error,
-            )
-            .tessellate();
-            if let Some(ref mesh) = mesh {
-                mesh_view::show_mesh(&mesh);
+    // The box the tessellation of the object covers, e.g. to estimate the size of the mesh.
+    pub fn tessellation_bbox(&self) -> Option<implicit3d::BoundingBox<Float>> {
+        let (obj, tree, _) = self.get_object(&print_message)?;
+        self.finite(obj, tree).map(|(obj, _)| obj.bbox().clone())
+    }
+    // obj cropped to the tessellation region of the script, or if it has none and obj is
+    // infinite, obj clipped to infinite_clip_size from the settings. The tessellation covers the
+    // bounding box, which has to be finite.
+    fn finite(
+        &self,
+        obj: Box<dyn implicit3d::Object<Float>>,
+        tree: Option<Node>,
+    ) -> Option<(Box<dyn implicit3d::Object<Float>>, Option<Node>)> {
+        let s = settings::SettingsData::default();
+        if let Some(ref region) = *self.tessellation_region.borrow() {
+            match bounded::region_of_interest(obj, tree, region) {
+                Ok(cropped) => Some(cropped),
+                Err(e) => {
+                    self.console.log(Level::Error, None, e);
+                    None
+                }
             }
+        } else if bounded::is_finite(obj.bbox()) {
+            Some((obj, tree))
+        } else if s.infinite_clip_size > 0. {
+            let r = s.infinite_clip_size;
+            let region = implicit3d::BoundingBox::new(
+                &na::Point3::new(-r, -r, -r),
+                &na::Point3::new(r, r, r),
+            );
+            Some(bounded::clip(obj, tree, &region))
+        } else {
+            self.console.log(
+                Level::Error,
+                None,
+                "can not tessellate an infinite object, intersect it with a finite one or set \
+                 infinite_clip_size in the settings",
+            );
+            None
+        }
+    }
+    // The object of result to tessellate with the resolution and error, None if it has none or
+    // is infinite.
+    fn tessellation_job(
+        &self,
+        result: Result<Evaluation, ScriptError>,
+        resolution: Float,
+        error: Float,
+    ) -> Option<TessellationJob> {
+        let (obj, tree, units) = self.object_of(result, &print_message)?;
+        let (object, tree) = self.finite(obj, tree)?;
+        Some(TessellationJob {
+            object,
+            tree,
+            units,
+            resolution,
+            error,
+        })
+    }
+    // Tessellate with the resolution and error from the settings in the background and show the
+    // mesh, once it is done. Unless the script is cached, it is evaluated in the background
+    // first, and tessellated once the evaluation is done.
+    pub fn tessellate(&self) {
+        let s = settings::SettingsData::default();
+        self.pending_tessellation
+            .set(Some((s.tessellation_resolution, s.tessellation_error)));
+        let job = self.job();
+        match self.cached(&job) {
+            Some(evaluation) => self.tessellate_evaluation(Ok(evaluation)),
+            None => self.evaluation_worker.submit(job),
+        }
+        self.update_badge();
+    }
+    // Start the pending tessellation of the object of result, if there is one.
+    fn tessellate_evaluation(&self, result: Result<Evaluation, ScriptError>) {
+        let (resolution, error) = match self.pending_tessellation.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        if let Some(job) = self.tessellation_job(result, resolution, error) {
+            self.tessellation_worker.submit(job);
+        }
+    }
+    // Tessellate here, e.g. for an export, and show the mesh.
+    pub fn tessellate_with(&self, resolution: Float, error: Float) -> Option<IndexedMesh> {
+        let job = self.tessellation_job(self.evaluation(), resolution, error)?;
+        let (mesh, warnings) = tessellate_job(job, &AtomicBool::new(false));
+        self.show_tessellated(mesh.as_ref(), warnings);
+        mesh
+    }
+    fn show_tessellated(&self, mesh: Option<&IndexedMesh>, warnings: Vec<String>) {
+        for warning in warnings {
+            self.console.log(Level::Warning, None, warning);
+        }
+        self.show_mesh(mesh);
+    }
+    fn show_mesh(&self, mesh: Option<&IndexedMesh>) {
+        if let Some(mesh) = mesh {
+            mesh_view::show_mesh(mesh);
+        }
+        self.renderer.borrow_mut().set_wireframe(mesh);
+        self.drawing_area.queue_draw();
+    }
+}
+
+// Tessellate the finite object, built as tree says, with resolution and error. What did not go
+// as planned, but still gave a mesh, is added to warnings.
+pub fn tessellate(
+    object: Box<dyn implicit3d::Object<Float>>,
+    tree: Option<&Node>,
+    units: Units,
+    resolution: Float,
+    error: Float,
+    warnings: &mut Vec<String>,
+) -> Option<IndexedMesh> {
+    let normals = NormalEstimate::from_index(settings::SettingsData::default().normal_estimate);
+    tessellate_with_parameters(
+        object,
+        tree,
+        units,
+        resolution,
+        error,
+        &parameters(),
+        normals,
+        warnings,
+    )
+}
+
+// Like tessellate, with the parameters the object was set to and the estimate of its normals
+// instead of those from the settings. Sharp booleans at the root of tree are combined from the
+// meshes of their children, see mesh_boolean.
+#[allow(clippy::too_many_arguments)]
+pub fn tessellate_with_parameters(
+    object: Box<dyn implicit3d::Object<Float>>,
+    tree: Option<&Node>,
+    units: Units,
+    resolution: Float,
+    error: Float,
+    parameters: &implicit3d::PrimitiveParameters<Float>,
+    normals: NormalEstimate,
+    warnings: &mut Vec<String>,
+) -> Option<IndexedMesh> {
+    if let Some(&Node::Sharp { ref children, op }) = tree {
+        let mesh = mesh_boolean::tessellate(
+            children,
+            op,
+            object.bbox(),
+            units,
+            resolution,
+            error,
+            parameters,
+            normals,
+            warnings,
+        );
+        if mesh.is_some() {
             return mesh;
         }
-        None
+        warnings
+            .push("could not tessellate the children of a sharp boolean one by one".to_string());
+    }
+    let obj = Estimated::wrap(
+        accelerate(object, tree, parameters),
+        normals,
+        resolution * NORMAL_STEP,
+    );
+    let corners = flat_tree::for_rendering(tree, parameters)
+        .map(|flat| flat_tree::Corners::new(flat, obj.bbox(), resolution));
+    let adaptor = ObjectAdaptor {
+        implicit: obj,
+        corners,
+        resolution,
+    };
+    ManifoldDualContouring::new(&adaptor, resolution, error)
+        .tessellate()
+        .map(|mesh| {
+            let mut mesh = IndexedMesh::from_tessellation(&mesh);
+            mesh.set_normals_from_object(&*adaptor.implicit);
+            mesh.units = units;
+            mesh
+        })
+}
+
+// object, compiled or flattened for evaluating it densely around its surface, e.g. to tessellate
+// it, where there is little to gain from the bounding boxes of the interpreted object.
+pub fn accelerate(
+    object: Box<dyn implicit3d::Object<Float>>,
+    tree: Option<&Node>,
+    parameters: &implicit3d::PrimitiveParameters<Float>,
+) -> Box<dyn implicit3d::Object<Float>> {
+    if cfg!(feature = "jit") {
+        jit::accelerate(object, tree, parameters)
+    } else {
+        flat_tree::flatten(object, tree, parameters)
     }
 }
 
+fn parameters() -> implicit3d::PrimitiveParameters<Float> {
+    let s = settings::SettingsData::default();
+    implicit3d::PrimitiveParameters {
+        fade_range: s.fade_range,
+        r_multiplier: s.r_multiplier,
+    }
+}
+
+// CSS for a source view with font, a font name like "Monospace 10" (family, then size in
+// points).
+fn font_css(font: &str) -> String {
+    let mut words: Vec<&str> = font.split_whitespace().collect();
+    let size = match words.last().and_then(|w| w.parse::<f64>().ok()) {
+        Some(size) => {
+            words.pop();
+            format!("font-size: {}pt;", size)
+        }
+        None => String::new(),
+    };
+    let family = if words.is_empty() {
+        String::new()
+    } else {
+        format!("font-family: \"{}\";", words.join(" "))
+    };
+    format!("textview {{ {} {} }}", family, size)
+}
+
+// Messages of evaluations which are not shown in the console, e.g. for tessellation.
+fn print_message(m: Message) {
+    println!("{}", m);
+}
+
 fn save_from_sourceview(source_view: &::sourceview::View, filename: &str) {
     let open_result = File::create(filename);
     if let Ok(f) = open_result {
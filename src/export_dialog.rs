@@ -0,0 +1,414 @@
+use super::Float;
+use gtk::traits::*;
+use gtk::{Cast, SpinButtonSignals};
+use indexed_mesh::IndexedMesh;
+use na;
+use settings;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::rc::Rc;
+use stl_io::write_stl;
+use truescad_luascad::bounded;
+use truescad_luascad::implicit3d::BoundingBox;
+
+// Size of a triangle in a binary STL file, and about that in an ASCII one.
+const BINARY_STL_TRIANGLE_BYTES: Float = 50.;
+const ASCII_STL_TRIANGLE_BYTES: Float = 250.;
+
+// Resolution presets: name, cells along the largest side of the object and tessellation error.
+const PRESETS: [(&str, Float, Float); 3] = [
+    ("draft", 100., 4.),
+    ("normal", 250., 2.),
+    ("fine", 600., 1.),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    BinaryStl,
+    AsciiStl,
+}
+
+impl Format {
+    // Its index in the format dropdown.
+    fn index(self) -> i32 {
+        match self {
+            Format::BinaryStl => 0,
+            Format::AsciiStl => 1,
+        }
+    }
+    fn triangle_bytes(self) -> Float {
+        match self {
+            Format::BinaryStl => BINARY_STL_TRIANGLE_BYTES,
+            Format::AsciiStl => ASCII_STL_TRIANGLE_BYTES,
+        }
+    }
+}
+
+// A fix for a mesh drawn in other units than those of its script, e.g. in inches without
+// set_units("in"): its coordinates are scaled to the units of the script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Conversion {
+    #[default]
+    None,
+    InchesToMillimeters,
+    MillimetersToInches,
+}
+
+impl Conversion {
+    // The conversion of a name on the command line.
+    pub fn parse(name: &str) -> Option<Conversion> {
+        match name {
+            "none" => Some(Conversion::None),
+            "in-to-mm" => Some(Conversion::InchesToMillimeters),
+            "mm-to-in" => Some(Conversion::MillimetersToInches),
+            _ => None,
+        }
+    }
+    // Its index in the conversion dropdown.
+    fn index(self) -> i32 {
+        match self {
+            Conversion::None => 0,
+            Conversion::InchesToMillimeters => 1,
+            Conversion::MillimetersToInches => 2,
+        }
+    }
+    fn factor(self) -> Float {
+        match self {
+            Conversion::None => 1.,
+            Conversion::InchesToMillimeters => 25.4,
+            Conversion::MillimetersToInches => 1. / 25.4,
+        }
+    }
+}
+
+// What the export dialog asks for, see show_export_dialog.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportOptions {
+    pub resolution: Float,
+    pub error: Float,
+    #[serde(default)]
+    pub format: Format,
+    // The widths of the brim and of the tabs between parts added to the mesh, none if 0, see
+    // adhesion::add.
+    #[serde(default)]
+    pub brim: Float,
+    #[serde(default)]
+    pub tabs: Float,
+    // The size of the square plate the parts of the mesh are arranged on, spacing apart, left
+    // as they are if 0, see IndexedMesh::arrange.
+    #[serde(default)]
+    pub plate: Float,
+    #[serde(default)]
+    pub spacing: Float,
+    // Transforms of the mesh before it is arranged, see transform: the conversion of its
+    // coordinates, and the size of its largest side it is scaled to, as it is if 0.
+    #[serde(default)]
+    pub convert: Conversion,
+    #[serde(default)]
+    pub fit: Float,
+    // Whether the mesh is moved to the middle of the plate (or the origin without one), resting
+    // on z = 0.
+    #[serde(default)]
+    pub center: bool,
+}
+
+// About how many triangles tessellating bbox with resolution gives: two per cell at the surface,
+// taking the surface of the box for the one of the object. Objects with much detail (or holes)
+// give more, round ones less.
+pub fn estimate_triangles(bbox: &BoundingBox<Float>, resolution: Float) -> Option<Float> {
+    if !bounded::is_finite(bbox) || resolution <= 0. {
+        return None;
+    }
+    let d = bbox.dim();
+    let area = 2. * (d.x * d.y + d.y * d.z + d.z * d.x);
+    Some(2. * area / (resolution * resolution))
+}
+
+// Apply the transforms of options to the tessellated mesh: convert its coordinates, scale it to
+// fit, arrange its parts on the plate and center it, in that order.
+pub fn transform(mesh: &mut IndexedMesh, options: &ExportOptions) -> Result<(), String> {
+    mesh.scale(options.convert.factor());
+    if options.fit > 0. {
+        let (min, max) = mesh.bounds();
+        let largest = (max - min).amax();
+        if largest > 0. {
+            mesh.scale(options.fit / largest);
+        }
+    }
+    if options.plate > 0. {
+        arrange(mesh, options)?;
+    }
+    if options.center && !mesh.vertices.is_empty() {
+        let (min, max) = mesh.bounds();
+        let middle = na::Point2::new(options.plate, options.plate) / 2.;
+        mesh.translate(na::Vector3::new(
+            middle.x - (min.x + max.x) / 2.,
+            middle.y - (min.y + max.y) / 2.,
+            -min.z,
+        ));
+    }
+    Ok(())
+}
+
+// Arrange the parts of mesh on the plate of options and print where they went.
+fn arrange(mesh: &mut IndexedMesh, options: &ExportOptions) -> Result<(), String> {
+    let footprints = mesh.arrange(options.plate, options.spacing).map_err(|i| {
+        format!(
+            "part {} does not fit on the {} x {} plate",
+            i + 1,
+            options.plate,
+            options.plate
+        )
+    })?;
+    for (i, (min, max)) in footprints.iter().enumerate() {
+        println!(
+            "part {}: x {:.2} to {:.2}, y {:.2} to {:.2}",
+            i + 1,
+            min.x,
+            max.x,
+            min.y,
+            max.y
+        );
+    }
+    Ok(())
+}
+
+// Write mesh to path in format, or as 3MF, AMF, PLY or OBJ if path has their extension.
+pub fn save_mesh(path: &str, mesh: &IndexedMesh, format: Format) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+    let mut writer = BufWriter::new(file);
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    match (extension.as_deref(), format) {
+        (Some("3mf"), _) => mesh.write_3mf(&mut writer),
+        (Some("amf"), _) => mesh.write_amf(&mut writer),
+        (Some("ply"), _) => mesh.write_ply(&mut writer),
+        (Some("obj"), _) => mesh.write_obj(&mut writer),
+        (_, Format::BinaryStl) => write_stl(&mut writer, mesh.stl_triangles().iter()),
+        (_, Format::AsciiStl) => mesh.write_ascii_stl(&mut writer),
+    }
+    .map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+// Ask for the tessellation settings and format of an export, starting from the settings.
+// bbox is the box which will be tessellated, for the presets and the estimate of the size of
+// the result. The profiles of a project are offered next to the presets. Options pinned by the
+// project settings are shown, but can not be changed. None if the export is cancelled.
+pub fn show_export_dialog<T: ::gtk::IsA<::gtk::Window>>(
+    parent: Option<&T>,
+    bbox: Option<&BoundingBox<Float>>,
+    profiles: &BTreeMap<String, ExportOptions>,
+    pinned: Option<&ExportOptions>,
+) -> Option<ExportOptions> {
+    let s = settings::SettingsData::default();
+    let dialog = ::gtk::Dialog::new_with_buttons(
+        Some("Export"),
+        parent,
+        ::gtk::DialogFlags::MODAL,
+        &[
+            ("Export", ::gtk::ResponseType::Ok.into()),
+            ("Cancel", ::gtk::ResponseType::Cancel.into()),
+        ],
+    );
+    let grid = ::gtk::Grid::new();
+    grid.set_column_spacing(5);
+    grid.set_row_spacing(5);
+    grid.set_border_width(5);
+    // The presets are relative to the size of the object, so there are none for infinite ones.
+    let largest = bbox
+        .filter(|b| bounded::is_finite(b))
+        .map(|b| b.dim().amax());
+    let presets: &[(&str, Float, Float)] = if largest.is_some() { &PRESETS } else { &[] };
+    let preset = ::gtk::ComboBoxText::new();
+    for &(name, _, _) in presets {
+        preset.append_text(name);
+    }
+    for name in profiles.keys() {
+        preset.append_text(&format!("{} (project)", name));
+    }
+    preset.set_tooltip_text(
+        "resolution relative to the size of the object, or a profile of the project",
+    );
+    preset.set_sensitive(!presets.is_empty() || !profiles.is_empty());
+    let resolution = ::gtk::SpinButton::new_with_range(0.0001, 1000., 0.01);
+    resolution.set_digits(4);
+    resolution.set_value(s.export_resolution.into());
+    resolution.set_tooltip_text("size of the cells the object is sampled in");
+    let error = ::gtk::SpinButton::new_with_range(0.0001, 1000., 0.01);
+    error.set_digits(4);
+    error.set_value(s.export_error.into());
+    error.set_tooltip_text("how far the mesh may be off, relative to the resolution");
+    let format = ::gtk::ComboBoxText::new();
+    format.append_text("binary STL");
+    format.append_text("ASCII STL");
+    format.set_active(0);
+    let brim = ::gtk::SpinButton::new_with_range(0., 100., 0.5);
+    brim.set_digits(2);
+    brim.set_tooltip_text("width of a brim around the footprint at z = 0, none if 0");
+    let tabs = ::gtk::SpinButton::new_with_range(0., 100., 0.5);
+    tabs.set_digits(2);
+    tabs.set_tooltip_text("width of breakaway tabs connecting the parts at z = 0, none if 0");
+    let plate = ::gtk::SpinButton::new_with_range(0., 10000., 10.);
+    plate.set_digits(1);
+    plate.set_tooltip_text("size of a square plate to arrange the parts on, none if 0");
+    let spacing = ::gtk::SpinButton::new_with_range(0., 1000., 1.);
+    spacing.set_digits(2);
+    spacing.set_value(5.);
+    spacing.set_tooltip_text("space between the parts arranged on the plate");
+    let convert = ::gtk::ComboBoxText::new();
+    convert.append_text("none");
+    convert.append_text("inches to mm");
+    convert.append_text("mm to inches");
+    convert.set_active(0);
+    convert.set_tooltip_text("scale a mesh drawn in other units than those of the script");
+    let fit = ::gtk::SpinButton::new_with_range(0., 10000., 1.);
+    fit.set_digits(2);
+    fit.set_tooltip_text("size to scale the largest side of the mesh to, as it is if 0");
+    let center = ::gtk::CheckButton::new();
+    center.set_tooltip_text("move the mesh to the middle of the plate, or the origin without one");
+    let estimate = ::gtk::Label::new(None);
+    estimate.set_halign(::gtk::Align::Start);
+    let rows: [(&str, &::gtk::Widget); 12] = [
+        ("preset", preset.upcast_ref()),
+        ("resolution", resolution.upcast_ref()),
+        ("error", error.upcast_ref()),
+        ("format", format.upcast_ref()),
+        ("brim", brim.upcast_ref()),
+        ("tabs", tabs.upcast_ref()),
+        ("plate", plate.upcast_ref()),
+        ("spacing", spacing.upcast_ref()),
+        ("convert", convert.upcast_ref()),
+        ("fit", fit.upcast_ref()),
+        ("center", center.upcast_ref()),
+        ("estimate", estimate.upcast_ref()),
+    ];
+    for (i, &(name, widget)) in rows.iter().enumerate() {
+        let label = ::gtk::Label::new(Some(name));
+        label.set_halign(::gtk::Align::Start);
+        grid.attach(&label, 0, i as i32, 1, 1);
+        grid.attach(widget, 1, i as i32, 1, 1);
+    }
+    dialog.get_content_area().add(&grid);
+    if let Some(pinned) = pinned {
+        resolution.set_value(pinned.resolution.into());
+        error.set_value(pinned.error.into());
+        format.set_active(pinned.format.index());
+        brim.set_value(pinned.brim.into());
+        tabs.set_value(pinned.tabs.into());
+        plate.set_value(pinned.plate.into());
+        spacing.set_value(pinned.spacing.into());
+        convert.set_active(pinned.convert.index());
+        fit.set_value(pinned.fit.into());
+        center.set_active(pinned.center);
+        for widget in &rows[..11] {
+            widget.1.set_sensitive(false);
+        }
+        let note = ::gtk::Label::new(Some(&*format!(
+            "pinned by the project in {}",
+            settings::PROJECT_FILENAME
+        )));
+        note.set_halign(::gtk::Align::Start);
+        grid.attach(&note, 0, rows.len() as i32, 2, 1);
+    }
+
+    let selected_format = {
+        let format = format.clone();
+        move || match format.get_active() {
+            1 => Format::AsciiStl,
+            _ => Format::BinaryStl,
+        }
+    };
+    let update_estimate = Rc::new({
+        let (resolution, estimate, selected_format) = (
+            resolution.clone(),
+            estimate.clone(),
+            selected_format.clone(),
+        );
+        let bbox = bbox.cloned();
+        move || {
+            let text = match bbox
+                .as_ref()
+                .and_then(|b| estimate_triangles(b, resolution.get_value() as Float))
+            {
+                Some(triangles) => format!(
+                    "about {:.0} triangles, {:.1} MB",
+                    triangles,
+                    triangles * selected_format().triangle_bytes() / 1e6
+                ),
+                None => "unknown for infinite objects".to_string(),
+            };
+            estimate.set_text(&text);
+        }
+    });
+    update_estimate();
+    {
+        let update_estimate = update_estimate.clone();
+        resolution.connect_value_changed(move |_| update_estimate());
+    }
+    {
+        let update_estimate = update_estimate.clone();
+        format.connect_changed(move |_| update_estimate());
+    }
+    {
+        let profiles: Vec<ExportOptions> = profiles.values().cloned().collect();
+        let (resolution, error, format, brim, tabs, plate, spacing) = (
+            resolution.clone(),
+            error.clone(),
+            format.clone(),
+            brim.clone(),
+            tabs.clone(),
+            plate.clone(),
+            spacing.clone(),
+        );
+        let (convert, fit, center) = (convert.clone(), fit.clone(), center.clone());
+        preset.connect_changed(move |preset| {
+            let i = preset.get_active() as usize;
+            if let (Some(&(_, cells, e)), Some(largest)) = (presets.get(i), largest) {
+                resolution.set_value((largest / cells).into());
+                error.set_value(e.into());
+            } else if let Some(profile) = profiles.get(i.wrapping_sub(presets.len())) {
+                resolution.set_value(profile.resolution.into());
+                error.set_value(profile.error.into());
+                format.set_active(profile.format.index());
+                brim.set_value(profile.brim.into());
+                tabs.set_value(profile.tabs.into());
+                plate.set_value(profile.plate.into());
+                spacing.set_value(profile.spacing.into());
+                convert.set_active(profile.convert.index());
+                fit.set_value(profile.fit.into());
+                center.set_active(profile.center);
+            }
+        });
+    }
+
+    dialog.show_all();
+    let ret = dialog.run();
+    let options = ExportOptions {
+        resolution: resolution.get_value() as Float,
+        error: error.get_value() as Float,
+        format: selected_format(),
+        brim: brim.get_value() as Float,
+        tabs: tabs.get_value() as Float,
+        plate: plate.get_value() as Float,
+        spacing: spacing.get_value() as Float,
+        convert: match convert.get_active() {
+            1 => Conversion::InchesToMillimeters,
+            2 => Conversion::MillimetersToInches,
+            _ => Conversion::None,
+        },
+        fit: fit.get_value() as Float,
+        center: center.get_active(),
+    };
+    dialog.destroy();
+    if ::gtk::ResponseType::from(ret) == ::gtk::ResponseType::Ok {
+        Some(options)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,191 @@
+use super::Float;
+use indexed_mesh::{Edge, IndexedMesh};
+use na;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Feature lines of a mesh: the edges where the normals of the faces on both sides differ by more
+// than an angle, and the borders of open meshes, chained into polylines, e.g. for engraving
+// toolpaths or technical drawings. They are written (like STL) in millimeters as DXF (3D
+// polylines), SVG (seen from above, along -z) or OBJ (lines), chosen by the extension of the file.
+
+// Files with these extensions get feature lines instead of a mesh.
+pub const EXTENSIONS: [&str; 3] = ["dxf", "svg", "obj"];
+// Edges between faces whose normals differ by more than this many degrees are features.
+pub const DEFAULT_ANGLE: Float = 30.;
+// Space around the lines in SVG files, in millimeters.
+const SVG_MARGIN: Float = 1.;
+
+// Whether path is a file for feature lines, by its extension.
+pub fn is_features_path(path: &str) -> bool {
+    extension(path).map_or(false, |e| EXTENSIONS.contains(&e.as_str()))
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+}
+
+// The feature lines of mesh with angle in degrees, see above. Closed lines end with their first
+// point.
+pub fn feature_lines(mesh: &IndexedMesh, angle: Float) -> Vec<Vec<na::Point3<Float>>> {
+    let cos = angle.to_radians().cos();
+    let edges: Vec<Edge> = mesh
+        .edge_faces()
+        .into_iter()
+        .filter(|(_, faces)| match faces[..] {
+            [a, b] => mesh.face_normal(a).dot(&mesh.face_normal(b)) < cos,
+            _ => true,
+        })
+        .map(|(edge, _)| edge)
+        .collect();
+    chain(&edges)
+        .into_iter()
+        .map(|line| line.iter().map(|&v| mesh.vertices[v]).collect())
+        .collect()
+}
+
+// The vertices of polylines along edges, each edge in one of them. Closed lines end with their
+// first vertex.
+pub fn chain(edges: &[Edge]) -> Vec<Vec<usize>> {
+    let mut neighbors: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &(a, b) in edges {
+        neighbors.entry(a).or_default().push(b);
+        neighbors.entry(b).or_default().push(a);
+    }
+    // Lines start at their ends and where they branch, the remaining edges form loops.
+    let mut starts: Vec<usize> = neighbors
+        .iter()
+        .filter(|&(_, n)| n.len() != 2)
+        .map(|(&v, _)| v)
+        .collect();
+    starts.sort();
+    let mut loops: Vec<usize> = neighbors.keys().cloned().collect();
+    loops.sort();
+    let mut lines = Vec::new();
+    for start in starts.into_iter().chain(loops) {
+        while let Some(line) = walk(&mut neighbors, start) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+// The vertices of a line along unused edges from start, removing its edges from neighbors, or
+// None if there are none left at start.
+fn walk(neighbors: &mut HashMap<usize, Vec<usize>>, start: usize) -> Option<Vec<usize>> {
+    let mut line = vec![start];
+    let mut current = start;
+    while let Some(next) = neighbors.get_mut(&current).and_then(|n| n.pop()) {
+        if let Some(n) = neighbors.get_mut(&next) {
+            if let Some(i) = n.iter().position(|&v| v == current) {
+                n.swap_remove(i);
+            }
+        }
+        line.push(next);
+        // Stop where the line branches, so that lines through it start there.
+        if next == start || neighbors.get(&next).map_or(0, Vec::len) != 1 {
+            break;
+        }
+        current = next;
+    }
+    if line.len() > 1 {
+        Some(line)
+    } else {
+        None
+    }
+}
+
+// Write lines, whose units are millimeters long, to path in the format of its extension.
+pub fn save(
+    path: &str,
+    lines: &[Vec<na::Point3<Float>>],
+    millimeters: Float,
+) -> Result<(), String> {
+    let lines: Vec<Vec<na::Point3<Float>>> = lines
+        .iter()
+        .map(|line| line.iter().map(|p| p * millimeters).collect())
+        .collect();
+    let file = File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+    let mut w = BufWriter::new(file);
+    match extension(path).as_deref() {
+        Some("dxf") => write_dxf(&mut w, &lines),
+        Some("svg") => write_svg(&mut w, &lines),
+        _ => write_obj(&mut w, &lines),
+    }
+    .map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+// A 3D POLYLINE entity for each line, in the ENTITIES section only, which DXF readers accept.
+fn write_dxf<W: Write>(w: &mut W, lines: &[Vec<na::Point3<Float>>]) -> io::Result<()> {
+    writeln!(w, "0\nSECTION\n2\nENTITIES")?;
+    for line in lines {
+        writeln!(w, "0\nPOLYLINE\n8\n0\n66\n1\n70\n8")?;
+        for p in line {
+            writeln!(
+                w,
+                "0\nVERTEX\n8\n0\n10\n{}\n20\n{}\n30\n{}\n70\n32",
+                p.x, p.y, p.z
+            )?;
+        }
+        writeln!(w, "0\nSEQEND")?;
+    }
+    writeln!(w, "0\nENDSEC\n0\nEOF")
+}
+
+// The lines seen from above, y pointing up as in the view, with lengths in millimeters.
+fn write_svg<W: Write>(w: &mut W, lines: &[Vec<na::Point3<Float>>]) -> io::Result<()> {
+    let (mut min, mut max) = (
+        na::Point2::new(Float::MAX, Float::MAX),
+        na::Point2::new(Float::MIN, Float::MIN),
+    );
+    for p in lines.iter().flatten() {
+        min = na::inf(&min, &na::Point2::new(p.x, p.y));
+        max = na::sup(&max, &na::Point2::new(p.x, p.y));
+    }
+    if lines.is_empty() {
+        min = na::Point2::origin();
+        max = na::Point2::origin();
+    }
+    let (width, height) = (
+        max.x - min.x + 2. * SVG_MARGIN,
+        max.y - min.y + 2. * SVG_MARGIN,
+    );
+    writeln!(
+        w,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}mm\" height=\"{height}mm\" \
+         viewBox=\"0 0 {width} {height}\">",
+        width = width,
+        height = height
+    )?;
+    writeln!(
+        w,
+        "<g fill=\"none\" stroke=\"black\" stroke-width=\"0.1\" \
+         transform=\"translate({} {}) scale(1 -1)\">",
+        SVG_MARGIN - min.x,
+        SVG_MARGIN + max.y
+    )?;
+    for line in lines {
+        let points: Vec<String> = line.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+        writeln!(w, "<polyline points=\"{}\"/>", points.join(" "))?;
+    }
+    writeln!(w, "</g>\n</svg>")
+}
+
+// The vertices of each line, followed by a line element through them.
+fn write_obj<W: Write>(w: &mut W, lines: &[Vec<na::Point3<Float>>]) -> io::Result<()> {
+    writeln!(w, "# feature lines written by truescad")?;
+    let mut index = 1;
+    for line in lines {
+        for p in line {
+            writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        let indices: Vec<String> = (index..index + line.len()).map(|i| i.to_string()).collect();
+        writeln!(w, "l {}", indices.join(" "))?;
+        index += line.len();
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use super::Float;
+use flat_tree::FlatTree;
+use na;
+use truescad_luascad::testing::{self, Random};
+use truescad_luascad::tree::Node;
+
+// Checks that the values of each node of a tree never overestimate the distance to its surface,
+// which renderer and tessellation step by: from points outside (inside), rays are probed for
+// points inside (outside) closer than the value. Nodes which break it while their children do
+// not are where the field goes wrong, e.g. a twist too tight for its value_scaler.
+
+// Points sampled in the box of each node.
+pub const SAMPLES: usize = 500;
+// Rays probed from each point, and points probed along each ray up to the value.
+const RAYS: usize = 8;
+const STEPS: usize = 8;
+
+// What check found for a node.
+pub struct NodeReport {
+    // Depth in the tree, 0 for the root.
+    pub depth: usize,
+    pub label: String,
+    // Whether the node could be evaluated on its own (see FlatTree).
+    pub checked: bool,
+    // Points whose value overestimates the distance to the surface, and by how much at most:
+    // the value divided by the distance at which the probe crossed the surface.
+    pub violations: usize,
+    pub worst: Float,
+    pub at: Option<na::Point3<Float>>,
+    // Whether the node breaks it and none of its children does.
+    pub culprit: bool,
+}
+
+// The reports of tree and all nodes below it, in pre-order, with samples points for each.
+pub fn check(tree: &Node, r_multiplier: Float, samples: usize) -> Vec<NodeReport> {
+    let mut reports = Vec::new();
+    add(tree, 0, r_multiplier, samples, &mut reports);
+    reports
+}
+
+// Add the reports of node and its children, returns whether the node breaks it.
+fn add(
+    node: &Node,
+    depth: usize,
+    r_multiplier: Float,
+    samples: usize,
+    reports: &mut Vec<NodeReport>,
+) -> bool {
+    let index = reports.len();
+    reports.push(NodeReport {
+        depth,
+        label: node.label(),
+        checked: false,
+        violations: 0,
+        worst: 0.,
+        at: None,
+        culprit: false,
+    });
+    let mut children_break = false;
+    for child in node.children() {
+        children_break |= add(child, depth + 1, r_multiplier, samples, reports);
+    }
+    let tree = match FlatTree::new(node, r_multiplier) {
+        Ok(tree) => tree,
+        Err(_) => return children_break,
+    };
+    let region = testing::region(&node.bbox());
+    let tolerance = testing::TOLERANCE * (region.max - region.min).norm();
+    let mut random = Random::new(samples as u64);
+    let report = &mut reports[index];
+    report.checked = true;
+    for p in testing::points(&region, samples) {
+        let value = tree.value(&p);
+        if !value.is_finite() || value.abs() <= tolerance {
+            continue;
+        }
+        if let Some(distance) = crossing(&tree, &p, value, tolerance, &mut random) {
+            report.violations += 1;
+            let factor = value.abs() / distance;
+            if factor > report.worst {
+                report.worst = factor;
+                report.at = Some(p);
+            }
+        }
+    }
+    let breaks = report.violations > 0;
+    report.culprit = breaks && !children_break;
+    breaks || children_break
+}
+
+// The distance to the nearest point found on the other side of the surface than p, closer than
+// its value, if there is one.
+fn crossing(
+    tree: &FlatTree,
+    p: &na::Point3<Float>,
+    value: Float,
+    tolerance: Float,
+    random: &mut Random,
+) -> Option<Float> {
+    let mut nearest: Option<Float> = None;
+    for _ in 0..RAYS {
+        let direction = random.direction();
+        for step in 1..STEPS {
+            let t = value.abs() * step as Float / STEPS as Float;
+            if nearest.map_or(false, |n| t >= n) {
+                break;
+            }
+            let v = tree.value(&(p + direction * t));
+            if v * value.signum() < -tolerance {
+                nearest = Some(t);
+                break;
+            }
+        }
+    }
+    nearest
+}
@@ -0,0 +1,1100 @@
+use super::Float;
+#[cfg(feature = "f32")]
+use lanes::Lanes8;
+use lanes::{Lanes, Lanes4};
+use nalgebra as na;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use truescad_luascad::blend;
+use truescad_luascad::edge::{self, Finish};
+use truescad_luascad::implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use truescad_luascad::optimize;
+use truescad_luascad::tree::{Boolean, Node};
+
+// Step of the central differences for normals where the gradient of the tree vanishes, and for
+// the derivatives of edges and blends by the values of their children, see FlatTree::gradient.
+const NORMAL_EPSILON: Float = 1e-6;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Kind {
+    Sphere {
+        radius: Float,
+    },
+    Cylinder {
+        radius: Float,
+    },
+    Cone {
+        slope: Float,
+        offset: Float,
+        // cos(atan(slope))
+        distance_multiplier: Float,
+    },
+    Plane {
+        normal: [Float; 3],
+        p: Float,
+    },
+    Union {
+        smooth: Float,
+    },
+    Intersection {
+        smooth: Float,
+    },
+    Difference {
+        smooth: Float,
+    },
+    Fillet {
+        op: EdgeOp,
+        radius: Float,
+    },
+    // Sizes along the first and the second child.
+    Chamfer {
+        op: EdgeOp,
+        sizes: [Float; 2],
+    },
+    // Children are the sharp boolean, the blended boolean and the region.
+    LocalBlend {
+        width: Float,
+    },
+    // Rows of the upper 3x4 part of the transformation matrix.
+    Transform {
+        rows: [[Float; 4]; 3],
+        scale: Float,
+    },
+    Twist {
+        height_scaler: Float,
+        value_scaler: Float,
+    },
+    Bend {
+        width_scaler: Float,
+        inner_radius: Float,
+    },
+    Empty,
+    Full,
+}
+
+// tree::Boolean of fillets and chamfers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EdgeOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl From<Boolean> for EdgeOp {
+    fn from(op: Boolean) -> EdgeOp {
+        match op {
+            Boolean::Union => EdgeOp::Union,
+            Boolean::Intersection => EdgeOp::Intersection,
+            Boolean::Difference => EdgeOp::Difference,
+        }
+    }
+}
+
+impl From<EdgeOp> for Boolean {
+    fn from(op: EdgeOp) -> Boolean {
+        match op {
+            EdgeOp::Union => Boolean::Union,
+            EdgeOp::Intersection => Boolean::Intersection,
+            EdgeOp::Difference => Boolean::Difference,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+// Fields are ordered with tables last, as required by TOML.
+pub struct FlatNode {
+    // Index of the point this node is evaluated at, see FlatTree::point_nodes.
+    pub point: usize,
+    // Range in FlatTree::children.
+    pub children: (usize, usize),
+    pub kind: Kind,
+}
+
+// tree::Node stored in one array in pre-order (parents before children), with children referenced
+// by index. It is evaluated iteratively: points top down, then values bottom up.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlatTree {
+    pub r_multiplier: Float,
+    pub children: Vec<usize>,
+    // Nodes which evaluate their children at a different point (transforms, twists and bends).
+    // Point 0 is the point the tree is evaluated at, point i + 1 is the one of point_nodes[i].
+    pub point_nodes: Vec<usize>,
+    pub nodes: Vec<FlatNode>,
+}
+
+// Per thread buffers for evaluation, to avoid allocating for each point.
+thread_local!(static SCRATCH: RefCell<(Vec<na::Point3<Float>>, Vec<Float>)> =
+    const { RefCell::new((Vec::new(), Vec::new())) });
+// Same for gradients, see FlatTree::gradient.
+thread_local!(static GRADIENT_SCRATCH: RefCell<(Vec<na::Point3<Float>>, Vec<Gradient>)> =
+    const { RefCell::new((Vec::new(), Vec::new())) });
+
+// A value with its gradient.
+type Gradient = (Float, na::Vector3<Float>);
+
+impl FlatTree {
+    pub fn new(tree: &Node, r_multiplier: Float) -> Result<FlatTree, String> {
+        let mut flat = FlatTree {
+            r_multiplier,
+            children: Vec::new(),
+            point_nodes: Vec::new(),
+            nodes: Vec::new(),
+        };
+        flat.add(tree, 0)?;
+        Ok(flat)
+    }
+    // Add node and its subtree, return its index.
+    fn add(&mut self, node: &Node, point: usize) -> Result<usize, String> {
+        let (kind, children): (Kind, Vec<&Node>) = match *node {
+            Node::Sphere { radius } => (Kind::Sphere { radius }, vec![]),
+            Node::Cylinder { radius } => (Kind::Cylinder { radius }, vec![]),
+            Node::Cone { slope, offset } => (
+                Kind::Cone {
+                    slope,
+                    offset,
+                    distance_multiplier: 1. / (slope * slope + 1.).sqrt(),
+                },
+                vec![],
+            ),
+            Node::Plane { ref normal, p } => (
+                Kind::Plane {
+                    normal: [
+                        positive_zero(normal.x),
+                        positive_zero(normal.y),
+                        positive_zero(normal.z),
+                    ],
+                    p,
+                },
+                vec![],
+            ),
+            Node::Union {
+                ref children,
+                smooth,
+            } => (Kind::Union { smooth }, children.iter().collect()),
+            Node::Intersection {
+                ref children,
+                smooth,
+            } => (Kind::Intersection { smooth }, children.iter().collect()),
+            Node::Difference {
+                ref children,
+                smooth,
+            } => (Kind::Difference { smooth }, children.iter().collect()),
+            Node::Edge {
+                ref children,
+                op,
+                finish,
+            } => {
+                let op = op.into();
+                let kind = match finish {
+                    Finish::Fillet(radius) => Kind::Fillet { op, radius },
+                    Finish::Chamfer(sa, sb) => Kind::Chamfer {
+                        op,
+                        sizes: [sa, sb],
+                    },
+                };
+                (kind, children.iter().collect())
+            }
+            Node::Sharp { ref children, op } => {
+                return self.add(&op.node(children.clone(), 0.), point)
+            }
+            Node::LocalBlend {
+                ref children,
+                width,
+            } => (Kind::LocalBlend { width }, children.iter().collect()),
+            Node::Transform {
+                ref child,
+                ref transform,
+                scale,
+            } => {
+                let mut rows = [[0.; 4]; 3];
+                for (i, row) in rows.iter_mut().enumerate() {
+                    for (j, e) in row.iter_mut().enumerate() {
+                        *e = positive_zero(transform[(i, j)]);
+                    }
+                }
+                (Kind::Transform { rows, scale }, vec![&**child])
+            }
+            Node::Twist {
+                ref child,
+                height_scaler,
+                value_scaler,
+            } => (
+                Kind::Twist {
+                    height_scaler,
+                    value_scaler,
+                },
+                vec![&**child],
+            ),
+            Node::Bend {
+                ref child,
+                width_scaler,
+                inner_radius,
+            } => (
+                Kind::Bend {
+                    width_scaler,
+                    inner_radius,
+                },
+                vec![&**child],
+            ),
+            Node::Component { .. } => return Err("components can not be flattened".to_string()),
+            Node::Gear { .. } => return Err("gears can not be flattened".to_string()),
+            Node::Thread { .. } => return Err("threads can not be flattened".to_string()),
+            Node::Lattice { .. } => return Err("infill can not be flattened".to_string()),
+            Node::Outline { .. } => return Err("outlines can not be flattened".to_string()),
+            Node::Morphology { .. } => {
+                return Err("smoothed objects can not be flattened".to_string())
+            }
+            Node::Offset { .. } => return Err("offsets can not be flattened".to_string()),
+            Node::PointCloud { ref filename, .. } => {
+                return Err(format!("point cloud {} can not be flattened", filename))
+            }
+            Node::Mesh { ref filename } => {
+                return Err(format!("mesh {} can not be flattened", filename))
+            }
+            Node::Plugin { ref function, .. } => {
+                return Err(format!("{} can not be flattened", function))
+            }
+            Node::Empty => (Kind::Empty, vec![]),
+            Node::Full => (Kind::Full, vec![]),
+        };
+        let index = self.nodes.len();
+        let child_point = match kind {
+            Kind::Transform { .. } | Kind::Twist { .. } | Kind::Bend { .. } => {
+                self.point_nodes.push(index);
+                self.point_nodes.len()
+            }
+            _ => point,
+        };
+        self.nodes.push(FlatNode {
+            point,
+            children: (0, 0),
+            kind,
+        });
+        let mut indices = Vec::with_capacity(children.len());
+        for child in children {
+            indices.push(self.add(child, child_point)?);
+        }
+        let start = self.children.len();
+        self.children.extend(indices);
+        self.nodes[index].children = (start, self.children.len());
+        Ok(index)
+    }
+    pub fn value(&self, p: &na::Point3<Float>) -> Float {
+        SCRATCH.with(|scratch| {
+            let (ref mut points, ref mut values) = *scratch.borrow_mut();
+            self.evaluate(p, points, values)
+        })
+    }
+    fn evaluate(
+        &self,
+        p: &na::Point3<Float>,
+        points: &mut Vec<na::Point3<Float>>,
+        values: &mut Vec<Float>,
+    ) -> Float {
+        let n = self.nodes.len();
+        points.clear();
+        points.push(*p);
+        for &i in &self.point_nodes {
+            let node = &self.nodes[i];
+            let q = node.child_point(&points[node.point]);
+            points.push(q);
+        }
+        values.clear();
+        values.resize(n, 0.);
+        for i in (0..n).rev() {
+            let node = &self.nodes[i];
+            let p = &points[node.point];
+            let children = &self.children[node.children.0..node.children.1];
+            let child = |k: usize| values[children[k]];
+            values[i] = match node.kind {
+                Kind::Sphere { radius } => p.coords.norm() - radius,
+                Kind::Cylinder { radius } => p.x.hypot(p.y) - radius,
+                Kind::Cone {
+                    slope,
+                    offset,
+                    distance_multiplier,
+                } => (p.x.hypot(p.y) - (slope * (p.z + offset)).abs()) * distance_multiplier,
+                Kind::Plane { ref normal, p: d } => {
+                    normal[0] * p.x + normal[1] * p.y + normal[2] * p.z - d
+                }
+                Kind::Union { smooth } => {
+                    smooth_min(children.len(), child, smooth, smooth * self.r_multiplier)
+                }
+                // max(v) = -min(-v)
+                Kind::Intersection { smooth } => -smooth_min(
+                    children.len(),
+                    |k| -child(k),
+                    smooth,
+                    smooth * self.r_multiplier,
+                ),
+                Kind::Difference { smooth } => -smooth_min(
+                    children.len(),
+                    |k| if k == 0 { -child(k) } else { child(k) },
+                    smooth,
+                    smooth * self.r_multiplier,
+                ),
+                Kind::Fillet { op, radius } => {
+                    edge::value(op.into(), Finish::Fillet(radius), child(0), child(1))
+                }
+                Kind::Chamfer { op, sizes } => edge::value(
+                    op.into(),
+                    Finish::Chamfer(sizes[0], sizes[1]),
+                    child(0),
+                    child(1),
+                ),
+                Kind::LocalBlend { width } => blend::value(child(0), child(1), child(2), width),
+                Kind::Empty => Float::INFINITY,
+                Kind::Full => Float::NEG_INFINITY,
+                Kind::Transform { scale, .. } => child(0) * scale,
+                Kind::Twist { value_scaler, .. } => child(0) * value_scaler,
+                Kind::Bend {
+                    width_scaler,
+                    inner_radius,
+                } => {
+                    let r = p.x.hypot(p.y);
+                    if inner_radius - r > 0. {
+                        inner_radius - r
+                    } else {
+                        child(0) * (r / width_scaler).min(1.)
+                    }
+                }
+            };
+        }
+        values[0]
+    }
+    // The value at p with its gradient, propagated up the tree: booleans take the gradient of the
+    // child which makes their value, or where they are smoothed, those of the children they are
+    // smoothed with, weighted by their share. Transforms, twists and bends chain the gradients
+    // of their children with their Jacobians. Edges and blends combine those of their children
+    // with their derivatives by the values of the children, which are central differences.
+    pub fn gradient(&self, p: &na::Point3<Float>) -> Gradient {
+        GRADIENT_SCRATCH.with(|scratch| {
+            let (ref mut points, ref mut values) = *scratch.borrow_mut();
+            self.evaluate_gradient(p, points, values)
+        })
+    }
+    // The normal of the surface through p, the normalized gradient, or where it vanishes (e.g. at
+    // the center of a sphere), the central differences of the values.
+    pub fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        match self.gradient(p).1.try_normalize(0.) {
+            Some(normal) if normal.iter().all(|x| x.is_finite()) => normal,
+            _ => {
+                let difference =
+                    |d: na::Vector3<Float>| self.value(&(p + d)) - self.value(&(p - d));
+                na::Vector3::new(
+                    difference(na::Vector3::x() * NORMAL_EPSILON),
+                    difference(na::Vector3::y() * NORMAL_EPSILON),
+                    difference(na::Vector3::z() * NORMAL_EPSILON),
+                )
+                .try_normalize(0.)
+                .unwrap_or_else(na::Vector3::x)
+            }
+        }
+    }
+    // Like evaluate.
+    fn evaluate_gradient(
+        &self,
+        p: &na::Point3<Float>,
+        points: &mut Vec<na::Point3<Float>>,
+        values: &mut Vec<Gradient>,
+    ) -> Gradient {
+        let n = self.nodes.len();
+        points.clear();
+        points.push(*p);
+        for &i in &self.point_nodes {
+            let node = &self.nodes[i];
+            let q = node.child_point(&points[node.point]);
+            points.push(q);
+        }
+        let zero = na::Vector3::zeros();
+        values.clear();
+        values.resize(n, (0., zero));
+        for i in (0..n).rev() {
+            let node = &self.nodes[i];
+            let p = &points[node.point];
+            let children = &self.children[node.children.0..node.children.1];
+            let child = |k: usize| values[children[k]];
+            // The direction away from the z axis.
+            let radial = na::Vector3::new(p.x, p.y, 0.)
+                .try_normalize(0.)
+                .unwrap_or(zero);
+            values[i] = match node.kind {
+                Kind::Sphere { radius } => (
+                    p.coords.norm() - radius,
+                    p.coords.try_normalize(0.).unwrap_or(zero),
+                ),
+                Kind::Cylinder { radius } => (p.x.hypot(p.y) - radius, radial),
+                Kind::Cone {
+                    slope,
+                    offset,
+                    distance_multiplier,
+                } => {
+                    let height = slope * (p.z + offset);
+                    (
+                        (p.x.hypot(p.y) - height.abs()) * distance_multiplier,
+                        (radial - na::Vector3::z() * (slope * height.signum()))
+                            * distance_multiplier,
+                    )
+                }
+                Kind::Plane { ref normal, p: d } => (
+                    normal[0] * p.x + normal[1] * p.y + normal[2] * p.z - d,
+                    na::Vector3::new(normal[0], normal[1], normal[2]),
+                ),
+                Kind::Union { smooth } => {
+                    smooth_min_gradient(children.len(), child, smooth, smooth * self.r_multiplier)
+                }
+                Kind::Intersection { smooth } => negate(smooth_min_gradient(
+                    children.len(),
+                    |k| negate(child(k)),
+                    smooth,
+                    smooth * self.r_multiplier,
+                )),
+                Kind::Difference { smooth } => negate(smooth_min_gradient(
+                    children.len(),
+                    |k| if k == 0 { negate(child(k)) } else { child(k) },
+                    smooth,
+                    smooth * self.r_multiplier,
+                )),
+                Kind::Fillet { op, radius } => combine(
+                    |v| edge::value(op.into(), Finish::Fillet(radius), v[0], v[1]),
+                    &[child(0), child(1)],
+                ),
+                Kind::Chamfer { op, sizes } => combine(
+                    |v| {
+                        let finish = Finish::Chamfer(sizes[0], sizes[1]);
+                        edge::value(op.into(), finish, v[0], v[1])
+                    },
+                    &[child(0), child(1)],
+                ),
+                Kind::LocalBlend { width } => combine(
+                    |v| blend::value(v[0], v[1], v[2], width),
+                    &[child(0), child(1), child(2)],
+                ),
+                Kind::Empty => (Float::INFINITY, zero),
+                Kind::Full => (Float::NEG_INFINITY, zero),
+                Kind::Transform { ref rows, scale } => {
+                    // The linear part of the transform, transposed.
+                    let (v, g) = child(0);
+                    let column = |c: usize| rows[0][c] * g.x + rows[1][c] * g.y + rows[2][c] * g.z;
+                    (
+                        v * scale,
+                        na::Vector3::new(column(0), column(1), column(2)) * scale,
+                    )
+                }
+                Kind::Twist {
+                    height_scaler,
+                    value_scaler,
+                } => {
+                    // The Jacobian of child_point, transposed: the rotation by the angle at z,
+                    // which turns the child point by height_scaler per unit of z.
+                    let (v, g) = child(0);
+                    let q = node.child_point(p);
+                    let (sin, cos) = (p.z * height_scaler).sin_cos();
+                    let g = na::Vector3::new(
+                        cos * g.x + sin * g.y,
+                        cos * g.y - sin * g.x,
+                        height_scaler * (q.x * g.y - q.y * g.x) + g.z,
+                    );
+                    (v * value_scaler, g * value_scaler)
+                }
+                Kind::Bend {
+                    width_scaler,
+                    inner_radius,
+                } => {
+                    let r = p.x.hypot(p.y);
+                    if inner_radius - r > 0. {
+                        (inner_radius - r, -radial)
+                    } else {
+                        // The Jacobian of child_point, transposed: x of the child grows along
+                        // the circle around the z axis, y away from it.
+                        let (v, g) = child(0);
+                        let tangent = na::Vector3::new(-p.y, p.x, 0.) * (width_scaler / (r * r));
+                        let g = tangent * g.x + radial * g.y + na::Vector3::z() * g.z;
+                        let s = r / width_scaler;
+                        if s < 1. {
+                            (v * s, g * s + radial * (v / width_scaler))
+                        } else {
+                            (v, g)
+                        }
+                    }
+                }
+            };
+        }
+        values[0]
+    }
+    // The values at four points, evaluated at once in SIMD lanes, see lanes::Lanes.
+    pub fn value4(&self, p: &[na::Point3<Float>; 4]) -> [Float; 4] {
+        let mut values = [0.; 4];
+        self.evaluate_lanes::<Lanes4>(p, &mut values);
+        values
+    }
+    // Same for eight points, which are two vectors of four with f64.
+    pub fn value8(&self, p: &[na::Point3<Float>; 8]) -> [Float; 8] {
+        let mut values = [0.; 8];
+        #[cfg(feature = "f32")]
+        self.evaluate_lanes::<Lanes8>(p, &mut values);
+        #[cfg(not(feature = "f32"))]
+        for (p, values) in p.chunks_exact(4).zip(values.chunks_exact_mut(4)) {
+            self.evaluate_lanes::<Lanes4>(p, values);
+        }
+        values
+    }
+    // Like evaluate, for the L::LANES points p, one in each lane, with their values written to
+    // result. The nodes are walked once for all of them, and all but edges and blends are
+    // evaluated with the vector math of L.
+    fn evaluate_lanes<L: Lanes>(&self, p: &[na::Point3<Float>], result: &mut [Float]) {
+        L::with_scratch(|points, values| {
+            let n = self.nodes.len();
+            // There are at most eight lanes.
+            let coordinate = |c: usize| {
+                let mut lanes = [0.; 8];
+                for (l, p) in lanes.iter_mut().zip(p) {
+                    *l = p[c];
+                }
+                L::load(&lanes)
+            };
+            points.clear();
+            points.push([coordinate(0), coordinate(1), coordinate(2)]);
+            for &i in &self.point_nodes {
+                let node = &self.nodes[i];
+                let q = node.child_lanes(&points[node.point]);
+                points.push(q);
+            }
+            values.clear();
+            values.resize(n, L::splat(0.));
+            for i in (0..n).rev() {
+                let node = &self.nodes[i];
+                let [x, y, z] = points[node.point];
+                let children = &self.children[node.children.0..node.children.1];
+                let child = |k: usize| values[children[k]];
+                values[i] = match node.kind {
+                    Kind::Sphere { radius } => (x * x + y * y + z * z).sqrt() - L::splat(radius),
+                    Kind::Cylinder { radius } => (x * x + y * y).sqrt() - L::splat(radius),
+                    Kind::Cone {
+                        slope,
+                        offset,
+                        distance_multiplier,
+                    } => {
+                        ((x * x + y * y).sqrt() - (L::splat(slope) * (z + L::splat(offset))).abs())
+                            * L::splat(distance_multiplier)
+                    }
+                    Kind::Plane { ref normal, p: d } => {
+                        L::splat(normal[0]) * x + L::splat(normal[1]) * y + L::splat(normal[2]) * z
+                            - L::splat(d)
+                    }
+                    Kind::Union { smooth } => {
+                        smooth_min_lanes(children.len(), child, smooth, smooth * self.r_multiplier)
+                    }
+                    Kind::Intersection { smooth } => -smooth_min_lanes(
+                        children.len(),
+                        |k| -child(k),
+                        smooth,
+                        smooth * self.r_multiplier,
+                    ),
+                    Kind::Difference { smooth } => -smooth_min_lanes(
+                        children.len(),
+                        |k| if k == 0 { -child(k) } else { child(k) },
+                        smooth,
+                        smooth * self.r_multiplier,
+                    ),
+                    Kind::Fillet { op, radius } => by_lane(&[child(0), child(1)], |v| {
+                        edge::value(op.into(), Finish::Fillet(radius), v[0], v[1])
+                    }),
+                    Kind::Chamfer { op, sizes } => by_lane(&[child(0), child(1)], |v| {
+                        edge::value(op.into(), Finish::Chamfer(sizes[0], sizes[1]), v[0], v[1])
+                    }),
+                    Kind::LocalBlend { width } => by_lane(&[child(0), child(1), child(2)], |v| {
+                        blend::value(v[0], v[1], v[2], width)
+                    }),
+                    Kind::Empty => L::splat(Float::INFINITY),
+                    Kind::Full => L::splat(Float::NEG_INFINITY),
+                    Kind::Transform { scale, .. } => child(0) * L::splat(scale),
+                    Kind::Twist { value_scaler, .. } => child(0) * L::splat(value_scaler),
+                    Kind::Bend {
+                        width_scaler,
+                        inner_radius,
+                    } => {
+                        let r = (x * x + y * y).sqrt();
+                        let inside = L::splat(inner_radius) - r;
+                        L::splat(0.).lt(inside).blend(
+                            inside,
+                            child(0) * (r / L::splat(width_scaler)).min(L::splat(1.)),
+                        )
+                    }
+                };
+            }
+            values[0].store(result);
+        })
+    }
+}
+
+impl FlatNode {
+    // The point at which the children of this node are evaluated.
+    fn child_point(&self, p: &na::Point3<Float>) -> na::Point3<Float> {
+        match self.kind {
+            Kind::Transform { ref rows, .. } => {
+                let row = |r: &[Float; 4]| r[0] * p.x + r[1] * p.y + r[2] * p.z + r[3];
+                na::Point3::new(row(&rows[0]), row(&rows[1]), row(&rows[2]))
+            }
+            Kind::Twist { height_scaler, .. } => {
+                let (sin, cos) = (p.z * height_scaler).sin_cos();
+                na::Point3::new(cos * p.x - sin * p.y, sin * p.x + cos * p.y, p.z)
+            }
+            Kind::Bend { width_scaler, .. } => {
+                na::Point3::new(p.x.atan2(-p.y) * width_scaler, p.x.hypot(p.y), p.z)
+            }
+            _ => *p,
+        }
+    }
+    // child_point for the points in lanes.
+    fn child_lanes<L: Lanes>(&self, &[x, y, z]: &[L; 3]) -> [L; 3] {
+        match self.kind {
+            Kind::Transform { ref rows, .. } => {
+                let row = |r: &[Float; 4]| {
+                    L::splat(r[0]) * x + L::splat(r[1]) * y + L::splat(r[2]) * z + L::splat(r[3])
+                };
+                [row(&rows[0]), row(&rows[1]), row(&rows[2])]
+            }
+            Kind::Twist { height_scaler, .. } => {
+                let (sin, cos) = (z * L::splat(height_scaler)).sin_cos();
+                [cos * x - sin * y, sin * x + cos * y, z]
+            }
+            Kind::Bend { width_scaler, .. } => [
+                x.atan2(-y) * L::splat(width_scaler),
+                (x * x + y * y).sqrt(),
+                z,
+            ],
+            _ => [x, y, z],
+        }
+    }
+}
+
+// f of the values of up to three children, lane by lane, for nodes without a form in lanes.
+fn by_lane<L: Lanes, F: Fn(&[Float; 3]) -> Float>(children: &[L], f: F) -> L {
+    let mut lanes = [[0.; 8]; 3];
+    for (c, lanes) in children.iter().zip(lanes.iter_mut()) {
+        c.store(lanes);
+    }
+    let mut values = [0.; 8];
+    for (l, v) in values.iter_mut().enumerate().take(L::LANES) {
+        *v = f(&[lanes[0][l], lanes[1][l], lanes[2][l]]);
+    }
+    L::load(&values)
+}
+
+// toml writes -0.0 as --0.0, which it can not read back.
+fn positive_zero(x: Float) -> Float {
+    x + 0.
+}
+
+// Same as rvmin in implicit3d: the minimum of the n values, rounded by r where values are closer
+// than exact_range.
+fn smooth_min<F: Fn(usize) -> Float>(n: usize, value: F, r: Float, exact_range: Float) -> Float {
+    let mut close = false;
+    let mut minimum = Float::INFINITY;
+    for k in 0..n {
+        let x = value(k);
+        if x < minimum {
+            close = minimum - x < exact_range;
+            minimum = x;
+        } else if x - minimum < exact_range {
+            close = true;
+        }
+    }
+    if !close {
+        return minimum;
+    }
+    let r4 = r / 4.;
+    let exp_sum: Float = (0..n)
+        .map(&value)
+        .filter(|&x| x < minimum + r)
+        .map(|x| (-x / r4).exp())
+        .sum();
+    exp_sum.ln() * -r4
+}
+
+// smooth_min for lanes, rounded in the lanes where values are closer than exact_range.
+fn smooth_min_lanes<L: Lanes, F: Fn(usize) -> L>(
+    n: usize,
+    value: F,
+    r: Float,
+    exact_range: Float,
+) -> L {
+    let mut minimum = L::splat(Float::INFINITY);
+    for k in 0..n {
+        minimum = minimum.min(value(k));
+    }
+    // Lanes where another value than the minimum is within exact_range of it.
+    let one = L::splat(1.);
+    let range = minimum + L::splat(exact_range);
+    let mut count = L::splat(0.);
+    for k in 0..n {
+        count = count + (value(k).lt(range) & one);
+    }
+    let close = one.lt(count);
+    if !close.any() {
+        return minimum;
+    }
+    let r4 = L::splat(r / 4.);
+    let limit = minimum + L::splat(r);
+    let mut exp_sum = L::splat(0.);
+    for k in 0..n {
+        let x = value(k);
+        exp_sum = exp_sum + (x.lt(limit) & (-x / r4).exp());
+    }
+    close.blend(exp_sum.ln() * -r4, minimum)
+}
+
+// smooth_min with gradients: that of the minimum, or where it is rounded, those of the values it
+// is rounded with, weighted by their share of it.
+fn smooth_min_gradient<F: Fn(usize) -> Gradient>(
+    n: usize,
+    value: F,
+    r: Float,
+    exact_range: Float,
+) -> Gradient {
+    let mut close = false;
+    let mut minimum = (Float::INFINITY, na::Vector3::zeros());
+    for k in 0..n {
+        let x = value(k);
+        if x.0 < minimum.0 {
+            close = minimum.0 - x.0 < exact_range;
+            minimum = x;
+        } else if x.0 - minimum.0 < exact_range {
+            close = true;
+        }
+    }
+    if !close {
+        return minimum;
+    }
+    let r4 = r / 4.;
+    let mut exp_sum = 0.;
+    let mut gradient = na::Vector3::zeros();
+    for (x, g) in (0..n).map(&value).filter(|x| x.0 < minimum.0 + r) {
+        let weight = (-x / r4).exp();
+        exp_sum += weight;
+        gradient += g * weight;
+    }
+    (exp_sum.ln() * -r4, gradient / exp_sum)
+}
+
+fn negate((value, gradient): Gradient) -> Gradient {
+    (-value, -gradient)
+}
+
+// The value of f at the values of children (at most three), with its gradient by the chain rule.
+// Edges and blends have no closed form for their derivatives, so they are central differences.
+fn combine<F: Fn(&[Float; 3]) -> Float>(f: F, children: &[Gradient]) -> Gradient {
+    let mut values = [0.; 3];
+    for (v, c) in values.iter_mut().zip(children) {
+        *v = c.0;
+    }
+    let value = f(&values);
+    let mut gradient = na::Vector3::zeros();
+    for (k, &(x, g)) in children.iter().enumerate() {
+        values[k] = x + NORMAL_EPSILON;
+        let above = f(&values);
+        values[k] = x - NORMAL_EPSILON;
+        let below = f(&values);
+        values[k] = x;
+        // Infinite values (e.g. of Empty) give NaN, they do not change the value anyway.
+        let derivative = (above - below) / (2. * NORMAL_EPSILON);
+        if derivative.is_finite() {
+            gradient += g * derivative;
+        }
+    }
+    (value, gradient)
+}
+
+// The tree for Renderer::set_flat_tree and Corners, if it can be flattened. Objects compiled with
+// the jit feature are faster one point at a time, so there is none then.
+pub fn for_rendering(
+    tree: Option<&Node>,
+    parameters: &PrimitiveParameters<Float>,
+) -> Option<FlatTree> {
+    if cfg!(feature = "jit") {
+        return None;
+    }
+    tree.and_then(|tree| FlatTree::new(tree, parameters.r_multiplier).ok())
+}
+
+// Replace object by a FlatObject, if tree can be flattened (e.g. has no meshes).
+pub fn flatten(
+    object: Box<dyn Object<Float>>,
+    tree: Option<&Node>,
+    parameters: &PrimitiveParameters<Float>,
+) -> Box<dyn Object<Float>> {
+    match tree.map(|tree| FlatObject::new(object.clone(), tree, parameters)) {
+        Some(Ok(flat_object)) => Box::new(flat_object),
+        _ => object,
+    }
+}
+
+// object with parts hidden, evaluated with tree: its tree with subtrees replaced by Empty (see
+// Node::without). The normals come from tree as well, so they do not show the hidden parts.
+pub fn hide(
+    object: Box<dyn Object<Float>>,
+    tree: &Node,
+    parameters: &PrimitiveParameters<Float>,
+) -> Result<Box<dyn Object<Float>>, String> {
+    Ok(Box::new(FlatObject::new(object, tree, parameters)?))
+}
+
+// Object evaluated with a FlatTree. The bounding box is that of the wrapped object, tightened by
+// the one of the tree (see optimize::bbox), the normals come from the gradient of the tree, see
+// FlatTree::gradient.
+#[derive(Clone)]
+pub struct FlatObject {
+    object: Box<dyn Object<Float>>,
+    tree: FlatTree,
+    bbox: BoundingBox<Float>,
+}
+
+impl FlatObject {
+    pub fn new(
+        object: Box<dyn Object<Float>>,
+        tree: &Node,
+        parameters: &PrimitiveParameters<Float>,
+    ) -> Result<FlatObject, String> {
+        Ok(FlatObject {
+            bbox: object.bbox().intersection(&optimize::bbox(tree)),
+            object,
+            tree: FlatTree::new(tree, parameters.r_multiplier)?,
+        })
+    }
+    pub fn tree(&self) -> &FlatTree {
+        &self.tree
+    }
+}
+
+impl fmt::Debug for FlatObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FlatObject")
+            .field("object", &self.object)
+            .finish()
+    }
+}
+
+impl Object<Float> for FlatObject {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+        self.tree.r_multiplier = p.r_multiplier;
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        // Like implicit3d objects, far away the bounding box is good enough.
+        let approx = self.bbox.distance(p);
+        if approx <= slack {
+            self.tree.value(p)
+        } else {
+            approx
+        }
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.tree.normal(p)
+    }
+}
+
+// Off by more than this many grid steps, points are not on the grid of Corners.
+const GRID_TOLERANCE: Float = 1e-3;
+
+// The values of a FlatObject (its tree and bounding box) for ManifoldDualContouring, with the
+// corners of its cubes evaluated at once with FlatTree::value8. It samples a grid of step res
+// from the first point it asks for, its origin, in cubes of sizes halving down from a power of
+// two: the first corner of a cube of size h it asks for is the one at (h, 0, 0), its base at a
+// multiple of 2h, which it came from, is known. Then all corners are evaluated, and the others
+// kept until they are asked for. Points off the grid (edges and normals, or the origin of a
+// retry after a corner was exactly zero) are evaluated one at a time.
+pub struct Corners {
+    tree: FlatTree,
+    bbox: BoundingBox<Float>,
+    res: Float,
+    origin: Cell<Option<na::Point3<Float>>>,
+    values: RefCell<HashMap<[i64; 3], Float>>,
+}
+
+impl Corners {
+    pub fn new(tree: FlatTree, bbox: &BoundingBox<Float>, res: Float) -> Corners {
+        Corners {
+            tree,
+            bbox: bbox.clone(),
+            res,
+            origin: Cell::new(None),
+            values: RefCell::new(HashMap::new()),
+        }
+    }
+    // Like FlatObject::approx_value with a slack of res.
+    pub fn value(&self, p: &na::Point3<Float>) -> Float {
+        let index = match self.origin.get() {
+            Some(origin) => (p - origin) / self.res,
+            // Off the grid, the first point starts it.
+            None => na::Vector3::repeat(0.5),
+        };
+        let rounded = index.map(Float::round);
+        if (index - rounded).amax() > GRID_TOLERANCE {
+            self.origin.set(Some(*p));
+            self.values.borrow_mut().clear();
+            return self.approx_value(p);
+        }
+        let index = [rounded.x as i64, rounded.y as i64, rounded.z as i64];
+        if let Some(value) = self.values.borrow_mut().remove(&index) {
+            return value;
+        }
+        let h = index[0] & -index[0];
+        if h == 0 || index[1] % (2 * h) != 0 || index[2] % (2 * h) != 0 {
+            return self.approx_value(p);
+        }
+        // Corner k is at base + (k & 1, k >> 1 & 1, k >> 2) * h, p is corner 1.
+        let step = h as Float * self.res;
+        let base = na::Point3::new(p.x - step, p.y, p.z);
+        let offset = |k: usize| [k & 1, k >> 1 & 1, k >> 2];
+        let mut corners = [*p; 8];
+        for (k, c) in corners.iter_mut().enumerate().skip(2) {
+            let o = offset(k);
+            *c = base + na::Vector3::new(o[0] as Float, o[1] as Float, o[2] as Float) * step;
+        }
+        corners[0] = base;
+        let values = self.approx_values(&corners);
+        let mut cache = self.values.borrow_mut();
+        for (k, &value) in values.iter().enumerate().skip(2) {
+            let o = offset(k);
+            cache.insert(
+                [
+                    index[0] + (o[0] as i64 - 1) * h,
+                    index[1] + o[1] as i64 * h,
+                    index[2] + o[2] as i64 * h,
+                ],
+                value,
+            );
+        }
+        values[1]
+    }
+    fn approx_value(&self, p: &na::Point3<Float>) -> Float {
+        let approx = self.bbox.distance(p);
+        if approx <= self.res {
+            self.tree.value(p)
+        } else {
+            approx
+        }
+    }
+    fn approx_values(&self, p: &[na::Point3<Float>; 8]) -> [Float; 8] {
+        let mut values = [0.; 8];
+        for (v, p) in values.iter_mut().zip(p) {
+            *v = self.bbox.distance(p);
+        }
+        if values.iter().any(|&v| v <= self.res) {
+            for (v, exact) in values.iter_mut().zip(&self.tree.value8(p)) {
+                if *v <= self.res {
+                    *v = *exact;
+                }
+            }
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shader::tests::{assert_value, build, points, PARAMETERS, SCRIPTS, TOLERANCE};
+
+    #[test]
+    fn values_match_the_object() {
+        for script in SCRIPTS {
+            let (object, tree) = build(script);
+            let flat = FlatTree::new(&tree, PARAMETERS.r_multiplier).unwrap();
+            for p in points(&*object) {
+                assert_value(script, &*object, &p, flat.value(&p));
+            }
+        }
+    }
+
+    #[test]
+    fn batches_match_the_object() {
+        for script in SCRIPTS {
+            let (object, tree) = build(script);
+            let flat = FlatTree::new(&tree, PARAMETERS.r_multiplier).unwrap();
+            for chunk in points(&*object).chunks_exact(8) {
+                let batch = [chunk[0], chunk[1], chunk[2], chunk[3]];
+                for (p, value) in batch.iter().zip(flat.value4(&batch).iter()) {
+                    assert_value(script, &*object, p, *value);
+                }
+                let mut batch = [chunk[0]; 8];
+                batch.copy_from_slice(chunk);
+                for (p, value) in batch.iter().zip(flat.value8(&batch).iter()) {
+                    assert_value(script, &*object, p, *value);
+                }
+            }
+        }
+    }
+
+    // The corners of the cubes of size / 2 from pos, and recursively those of their subcubes, in
+    // the order of ManifoldDualContouring::sample_value_grid, with their values.
+    fn sample(
+        corners: &Corners,
+        pos: na::Point3<Float>,
+        size: usize,
+        values: &mut Vec<(na::Point3<Float>, Float)>,
+    ) {
+        let size = size / 2;
+        let vpos = [pos, pos + na::Vector3::repeat(corners.res) * size as Float];
+        for k in 0..8 {
+            let p = na::Point3::new(vpos[k & 1].x, vpos[k >> 1 & 1].y, vpos[k >> 2].z);
+            if k > 0 {
+                values.push((p, corners.value(&p)));
+            }
+            if size > 1 {
+                sample(corners, p, size, values);
+            }
+        }
+    }
+
+    #[test]
+    fn corners_are_the_values_of_the_grid() {
+        for script in SCRIPTS {
+            let (object, tree) = build(script);
+            let flat = FlatTree::new(&tree, PARAMETERS.r_multiplier).unwrap();
+            let bbox = object.bbox();
+            let res = (bbox.max - bbox.min).amax() / 8.;
+            let corners = Corners::new(flat.clone(), bbox, res);
+            let mut values = Vec::new();
+            for origin in &[bbox.min, bbox.min + na::Vector3::repeat(res / 10.)] {
+                values.push((*origin, corners.value(origin)));
+                sample(&corners, *origin, 16, &mut values);
+                assert!(corners.values.borrow().is_empty(), "{}", script);
+            }
+            for (p, value) in values {
+                let approx = bbox.distance(&p);
+                let expected = if approx <= res {
+                    flat.value(&p)
+                } else {
+                    approx
+                };
+                assert!(
+                    (value - expected).abs() <= TOLERANCE * (1. + expected.abs()),
+                    "{} at {}: {}, expected {}",
+                    script,
+                    p,
+                    value,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bends_use_their_inner_radius() {
+        let script = "build(Bend(Box(4, 1, 1):translate(0, 3, 0), 10))";
+        let (object, tree) = build(script);
+        let flat = FlatTree::new(&tree, PARAMETERS.r_multiplier).unwrap();
+        let p = na::Point3::new(0.5, -1., 0.2);
+        assert_eq!(flat.value(&p), 2.5 - p.coords.xy().norm());
+        assert_eq!(flat.value(&p), object.approx_value(&p, 0.));
+        let q = na::Point3::new(0.5, -3., 0.2);
+        assert_value(script, &*object, &q, flat.value(&q));
+    }
+}
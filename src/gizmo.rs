@@ -0,0 +1,154 @@
+use super::Float;
+use float::consts::PI;
+use nalgebra as na;
+use overlay::Line;
+use render::Color;
+
+// How close (in pixels) the mouse has to be to a handle to drag it.
+const PICK_DISTANCE: Float = 6.;
+// Segments of the lines drawn for a ring.
+const RING_SEGMENTS: usize = 32;
+// Radius of the rings relative to the length of the arrows.
+const RING_RADIUS: Float = 0.7;
+// Brightness of the rings relative to the arrows, to tell them apart where they cross.
+const RING_BRIGHTNESS: Float = 0.6;
+
+// Handles to move and rotate a part of the object in the view: an arrow along each axis and a
+// ring around it. Handles are numbered like editable::FIELDS, 0..3 are the arrows for x, y and z
+// and 3..6 the rings around them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gizmo {
+    pub position: na::Point3<Float>,
+    // Length of the arrows.
+    pub size: Float,
+}
+
+impl Gizmo {
+    pub fn new(position: na::Point3<Float>, size: Float) -> Gizmo {
+        Gizmo { position, size }
+    }
+    // The lines of all handles, in the colors of their axes.
+    pub fn lines(&self) -> Vec<Line> {
+        (0..6)
+            .flat_map(|handle| self.handle_lines(handle))
+            .collect()
+    }
+    fn handle_lines(&self, handle: usize) -> Vec<Line> {
+        let axis = handle % 3;
+        let mut color = Color::zeros();
+        color[axis] = 1.;
+        if handle < 3 {
+            return vec![Line {
+                a: self.position,
+                b: self.position + unit(axis) * self.size,
+                color,
+            }];
+        }
+        let color = color * RING_BRIGHTNESS;
+        let (u, v) = (unit((axis + 1) % 3), unit((axis + 2) % 3));
+        let r = self.size * RING_RADIUS;
+        let point = |i: usize| {
+            let angle = 2. * PI * i as Float / RING_SEGMENTS as Float;
+            self.position + (u * angle.cos() + v * angle.sin()) * r
+        };
+        (0..RING_SEGMENTS)
+            .map(|i| Line {
+                a: point(i),
+                b: point(i + 1),
+                color,
+            })
+            .collect()
+    }
+    // The handle closest to pixel x, y and its distance in pixels, if one is close enough to
+    // drag it. project maps object space to pixels as for overlay::draw_line.
+    pub fn pick<F>(&self, project: &F, x: Float, y: Float) -> Option<(usize, Float)>
+    where
+        F: Fn(&na::Point3<Float>) -> Option<na::Point3<Float>>,
+    {
+        let mouse = na::Point2::new(x, y);
+        (0..6)
+            .filter_map(|handle| {
+                self.handle_lines(handle)
+                    .iter()
+                    .filter_map(|line| {
+                        let a = project(&line.a)?;
+                        let b = project(&line.b)?;
+                        Some(segment_distance(&mouse, &flat(&a), &flat(&b)))
+                    })
+                    .fold(None, |min: Option<Float>, d| {
+                        Some(min.map_or(d, |m| m.min(d)))
+                    })
+                    .map(|d| (handle, d))
+            })
+            .filter(|&(_, d)| d <= PICK_DISTANCE)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+    }
+    // How much dragging handle from pixel from to pixel to changes its value: the distance along
+    // the axis of an arrow, or the degrees around the axis of a ring.
+    pub fn drag<F>(
+        &self,
+        project: &F,
+        handle: usize,
+        from: (Float, Float),
+        to: (Float, Float),
+    ) -> Float
+    where
+        F: Fn(&na::Point3<Float>) -> Option<na::Point3<Float>>,
+    {
+        let axis = unit(handle % 3);
+        let (center, tip) = match (
+            project(&self.position),
+            project(&(self.position + axis * self.size)),
+        ) {
+            (Some(center), Some(tip)) => (center, tip),
+            _ => return 0.,
+        };
+        let (from, to) = (na::Point2::new(from.0, from.1), na::Point2::new(to.0, to.1));
+        if handle < 3 {
+            // The arrow as it appears on the screen.
+            let arrow = flat(&tip) - flat(&center);
+            let length = arrow.norm_squared();
+            if length == 0. {
+                return 0.;
+            }
+            return (to - from).dot(&arrow) / length * self.size;
+        }
+        let angle = |p: na::Point2<Float>| (p.y - center.y).atan2(p.x - center.x);
+        let mut delta = angle(to) - angle(from);
+        if delta > PI {
+            delta -= 2. * PI;
+        } else if delta < -PI {
+            delta += 2. * PI;
+        }
+        // Screen y points down, so angles grow clockwise. Rotations are counterclockwise seen
+        // from the tip of their axis.
+        if tip.z < center.z {
+            -delta.to_degrees()
+        } else {
+            delta.to_degrees()
+        }
+    }
+}
+
+// The pixel position of a projected point.
+fn flat(p: &na::Point3<Float>) -> na::Point2<Float> {
+    na::Point2::new(p.x, p.y)
+}
+
+fn unit(axis: usize) -> na::Vector3<Float> {
+    let mut v = na::Vector3::zeros();
+    v[axis] = 1.;
+    v
+}
+
+// Distance of p to the segment from a to b.
+fn segment_distance(p: &na::Point2<Float>, a: &na::Point2<Float>, b: &na::Point2<Float>) -> Float {
+    let ab = b - a;
+    let length = ab.norm_squared();
+    let t = if length == 0. {
+        0.
+    } else {
+        ((p - a).dot(&ab) / length).clamp(0., 1.)
+    };
+    na::distance(p, &(a + ab * t))
+}
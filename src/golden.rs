@@ -0,0 +1,217 @@
+use super::Float;
+use editor;
+use indexed_mesh::IndexedMesh;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use truescad_luascad;
+use truescad_luascad::implicit3d::PrimitiveParameters;
+use truescad_luascad::include::Includes;
+use truescad_luascad::normals::NormalEstimate;
+use truescad_luascad::ScriptError;
+
+// The directory of the scripts checked by default.
+pub const DIRECTORY: &str = "golden";
+// Goldens are stored next to their script, with this extension instead of .lua.
+const EXTENSION: &str = "golden.toml";
+// The settings the scripts are tessellated with. Changing them changes all meshes, so the
+// goldens have to be updated along.
+const RESOLUTION: Float = 0.12;
+const ERROR: Float = 2.;
+const PARAMETERS: PrimitiveParameters<Float> = PrimitiveParameters {
+    fade_range: 0.1,
+    r_multiplier: 1.0,
+};
+// Relative differences of the statistics which are tolerated, for floating point noise and
+// triangles which flip at the same surface.
+const TOLERANCE: Float = 1e-3;
+const TRIANGLE_TOLERANCE: Float = 0.01;
+// Vertices are rounded to this fraction of the resolution for the hash.
+const HASH_PRECISION: Float = 1e-4;
+
+// What is known about the mesh of a script: a hash of its triangles, and statistics which are
+// compared within tolerances when the hash changes, e.g. because of rounding.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Golden {
+    pub hash: String,
+    pub triangles: usize,
+    pub vertices: usize,
+    pub area: Float,
+    pub volume: Float,
+    pub min: [Float; 3],
+    pub max: [Float; 3],
+}
+
+// The result of checking a script against its golden.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Matched,
+    // The differences to the golden.
+    Changed(Vec<String>),
+    // The script has no golden.
+    Missing,
+    // The script had no golden, which was written.
+    New,
+    // The golden was overwritten.
+    Updated,
+}
+
+impl Golden {
+    pub fn of(mesh: &IndexedMesh) -> Golden {
+        let mut min = [Float::INFINITY; 3];
+        let mut max = [Float::NEG_INFINITY; 3];
+        for v in &mesh.vertices {
+            for i in 0..3 {
+                min[i] = min[i].min(v[i]);
+                max[i] = max[i].max(v[i]);
+            }
+        }
+        Golden {
+            hash: mesh_hash(mesh),
+            triangles: mesh.faces.len(),
+            vertices: mesh.vertices.len(),
+            area: mesh.area(),
+            volume: mesh.volume(),
+            min,
+            max,
+        }
+    }
+    // How self differs from expected beyond the tolerances, nothing if it matches.
+    pub fn compare(&self, expected: &Golden) -> Vec<String> {
+        if self.hash == expected.hash {
+            return Vec::new();
+        }
+        let mut differences = Vec::new();
+        let relative =
+            |a: Float, b: Float| (a - b).abs() / a.abs().max(b.abs()).max(Float::MIN_POSITIVE);
+        if relative(self.triangles as Float, expected.triangles as Float) > TRIANGLE_TOLERANCE {
+            differences.push(format!(
+                "{} triangles instead of {}",
+                self.triangles, expected.triangles
+            ));
+        }
+        if relative(self.area, expected.area) > TOLERANCE {
+            differences.push(format!("area {} instead of {}", self.area, expected.area));
+        }
+        if relative(self.volume, expected.volume) > TOLERANCE {
+            differences.push(format!(
+                "volume {} instead of {}",
+                self.volume, expected.volume
+            ));
+        }
+        let corners = self.min.iter().chain(&self.max);
+        let expected_corners = expected.min.iter().chain(&expected.max);
+        if corners
+            .zip(expected_corners)
+            .any(|(a, b)| (a - b).abs() > RESOLUTION)
+        {
+            differences.push(format!(
+                "box {:?} {:?} instead of {:?} {:?}",
+                self.min, self.max, expected.min, expected.max
+            ));
+        }
+        differences
+    }
+}
+
+// A hash of the triangles of mesh, which does not depend on the order of the vertices and
+// faces. DefaultHasher is stable between runs, but may change with Rust, which only makes the
+// statistics be compared.
+fn mesh_hash(mesh: &IndexedMesh) -> String {
+    let quantum = RESOLUTION * HASH_PRECISION;
+    let vertex = |i: usize| {
+        let v = mesh.vertices[i];
+        [
+            (v.x / quantum).round() as i64,
+            (v.y / quantum).round() as i64,
+            (v.z / quantum).round() as i64,
+        ]
+    };
+    let mut triangles: Vec<_> = mesh
+        .faces
+        .iter()
+        .map(|f| {
+            let mut t = [vertex(f[0]), vertex(f[1]), vertex(f[2])];
+            // Rotate the smallest vertex first, which keeps the orientation.
+            let first = (0..3).min_by_key(|&i| t[i]).unwrap();
+            t.rotate_left(first);
+            t
+        })
+        .collect();
+    triangles.sort();
+    let mut hasher = DefaultHasher::new();
+    triangles.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// Evaluate and tessellate the script at path with the settings of the goldens. Unlike export,
+// nothing depends on the settings file.
+pub fn tessellate(path: &Path) -> Result<IndexedMesh, String> {
+    let name = path.to_string_lossy();
+    let script = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", name, e))?;
+    let evaluation =
+        truescad_luascad::evaluate_in(&script, 0., &HashMap::new(), &Includes::beside(&name))
+            .map_err(|e| format!("{}: {}", name, ScriptError::from(&e)))?;
+    let mut object = evaluation
+        .object
+        .ok_or_else(|| format!("{}: no object - did you call build()?", name))?;
+    object.set_parameters(&PARAMETERS);
+    let mut warnings = Vec::new();
+    let mesh = editor::tessellate_with_parameters(
+        object,
+        evaluation.tree.as_ref(),
+        evaluation.units,
+        RESOLUTION,
+        ERROR,
+        &PARAMETERS,
+        NormalEstimate::Object,
+        &mut warnings,
+    );
+    for warning in warnings {
+        println!("{}: {}", name, warning);
+    }
+    mesh.ok_or_else(|| format!("{}: tessellation failed", name))
+}
+
+// The golden of the script at path.
+pub fn golden_path(path: &Path) -> PathBuf {
+    path.with_extension(EXTENSION)
+}
+
+// Check the mesh of the script at path against its golden. With update, missing and changed
+// goldens are written.
+pub fn check(path: &Path, update: bool) -> Result<Outcome, String> {
+    let golden = Golden::of(&tessellate(path)?);
+    let golden_path = golden_path(path);
+    let name = golden_path.to_string_lossy().into_owned();
+    let outcome = match fs::read_to_string(&golden_path) {
+        Ok(text) => {
+            let expected: Golden =
+                ::toml::from_str(&text).map_err(|e| format!("could not read {}: {}", name, e))?;
+            match golden.compare(&expected) {
+                ref differences if differences.is_empty() => return Ok(Outcome::Matched),
+                _ if update => Outcome::Updated,
+                differences => return Ok(Outcome::Changed(differences)),
+            }
+        }
+        Err(_) if update => Outcome::New,
+        Err(_) => return Ok(Outcome::Missing),
+    };
+    let text =
+        ::toml::to_string(&golden).map_err(|e| format!("could not write {}: {}", name, e))?;
+    fs::write(&golden_path, text).map_err(|e| format!("could not write {}: {}", name, e))?;
+    Ok(outcome)
+}
+
+// The scripts in dir, by name.
+pub fn scripts(dir: &str) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("could not read {}: {}", dir, e))?;
+    let mut scripts: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "lua"))
+        .collect();
+    scripts.sort();
+    Ok(scripts)
+}
@@ -0,0 +1,614 @@
+use super::Float;
+use render::GpuScene;
+use shader::{self, ShaderLanguage};
+use truescad_luascad::implicit3d::PrimitiveParameters;
+use truescad_luascad::tree::Node;
+
+#[cfg(feature = "gpu")]
+use gdk::{self, GLContextExt, WindowExt};
+#[cfg(feature = "gpu")]
+use gl::types::{GLchar, GLenum, GLfloat, GLint, GLuint};
+#[cfg(feature = "gpu")]
+use render::LightSource;
+#[cfg(feature = "gpu")]
+use std::ffi::CString;
+#[cfg(feature = "gpu")]
+use std::os::raw::c_void;
+#[cfg(feature = "gpu")]
+use std::ptr;
+
+// Lights beyond this many are left to the CPU.
+#[cfg(feature = "gpu")]
+const MAX_LIGHTS: usize = 4;
+
+// A triangle covering the viewport.
+#[cfg(feature = "gpu")]
+const VERTEX_SHADER: &str = "#version 330 core
+void main() {
+    vec2 p = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    gl_Position = vec4(p * 2.0 - 1.0, 0.0, 1.0);
+}
+";
+
+// Renderer::draw for a single pixel, with sdf appended, see Gpu::program. Rows are counted from
+// the bottom of the framebuffer, so that it reads back top row first, like the buffers of the
+// CPU. Depth is the distance of the hit from the camera, infinity for misses.
+#[cfg(feature = "gpu")]
+const FRAGMENT_SHADER: &str = "#version 330 core
+#define MAX_LIGHTS 4
+// Same as in render.rs.
+#define MIN_STEP 0.5
+#define AO_STEPS 5
+#define AO_STEP 0.01
+#define AO_STRENGTH 0.6
+#define SHADOW_STEPS 64
+#define SHADOW_SHARPNESS 8.0
+#define PI 3.14159265358979
+
+uniform vec3 origin;
+uniform vec3 front;
+uniform vec3 right;
+uniform vec3 down;
+uniform vec2 center;
+uniform float viewer_dist;
+uniform bool orthographic;
+uniform vec3 box_min;
+uniform vec3 box_max;
+uniform float epsilon;
+uniform float maxval;
+uniform float relaxation;
+uniform int max_iterations;
+uniform int aa_samples;
+uniform bool soft_shadows;
+uniform bool ambient_occlusion;
+// Directions (w = 0) or positions (w = 1) of the lights.
+uniform int light_count;
+uniform vec4 lights[MAX_LIGHTS];
+uniform vec3 light_colors[MAX_LIGHTS];
+uniform vec3 material_color;
+uniform float ambient;
+uniform float diffuse;
+uniform float specular;
+uniform bool ggx_specular;
+uniform float shininess;
+uniform float roughness;
+uniform vec3 background;
+
+layout(location = 0) out vec4 color;
+layout(location = 1) out float depth;
+
+float sdf(vec3 p);
+
+float infinity() {
+    return uintBitsToFloat(0x7F800000u);
+}
+
+vec3 normal(vec3 p) {
+    vec2 e = vec2(epsilon, 0.0);
+    return normalize(vec3(sdf(p + e.xyy) - sdf(p - e.xyy),
+                          sdf(p + e.yxy) - sdf(p - e.yxy),
+                          sdf(p + e.yyx) - sdf(p - e.yyx)));
+}
+
+// Renderer::span and Tracer: the distance of the hit along the unit vector d, or -1.
+float march(vec3 o, vec3 d) {
+    vec3 a = (box_min - o) / d;
+    vec3 b = (box_max - o) / d;
+    float near = max(max(min(a.x, b.x), min(a.y, b.y)), min(a.z, b.z));
+    float far = min(min(max(a.x, b.x), max(a.y, b.y)), max(a.z, b.z));
+    if (far < max(near, 0.0)) {
+        return -1.0;
+    }
+    bool infinite = far > 1e29;
+    float t = max(near, 0.0);
+    float radius = sdf(o + d * t);
+    float relax = relaxation;
+    for (int i = 0; i < max_iterations; i++) {
+        float ahead = max(radius * relax, epsilon * MIN_STEP);
+        float value = sdf(o + d * (t + ahead));
+        if (relax > 1.0 && (value < 0.0 || radius + value < ahead)) {
+            relax = 1.0;
+        } else {
+            t += ahead;
+            radius = value;
+            if (value > maxval && infinite) {
+                return -1.0;
+            }
+            if (value < epsilon) {
+                return t;
+            }
+        }
+        if (t > far) {
+            return -1.0;
+        }
+    }
+    return -1.0;
+}
+
+float soft_shadow(vec3 p, vec3 n, vec3 l, float light_dist) {
+    vec3 start = p + n * (epsilon * 2.0);
+    vec3 a = (box_min - start) / l;
+    vec3 b = (box_max - start) / l;
+    float far = min(min(max(a.x, b.x), max(a.y, b.y)), max(a.z, b.z));
+    light_dist = min(max(far, 0.0), light_dist);
+    float result = 1.0;
+    float t = epsilon;
+    for (int i = 0; i < SHADOW_STEPS; i++) {
+        float value = sdf(start + l * t);
+        if (value < epsilon) {
+            return 0.0;
+        }
+        result = min(result, SHADOW_SHARPNESS * value / t);
+        t += value;
+        if (t > light_dist) {
+            break;
+        }
+    }
+    return result;
+}
+
+float occlusion(vec3 p, vec3 n) {
+    float origin_value = sdf(p);
+    float slope = (sdf(p + n * epsilon) - origin_value) / epsilon;
+    if (slope <= 0.0) {
+        return 1.0;
+    }
+    float spacing = maxval * AO_STEP;
+    float occluded = 0.0;
+    float weight = 1.0;
+    for (int i = 1; i <= AO_STEPS; i++) {
+        float d = spacing * float(i);
+        occluded += weight * max(origin_value + slope * d - sdf(p + n * d), 0.0) / (slope * d);
+        weight *= 0.5;
+    }
+    return max(1.0 - AO_STRENGTH * occluded, 0.0);
+}
+
+float ggx(vec3 n, vec3 l, vec3 v) {
+    vec3 h = normalize(l + v);
+    float n_dot_h = max(dot(n, h), 0.0);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    float n_dot_l = max(dot(n, l), 1e-4);
+    float a2 = pow(roughness * roughness, 2.0);
+    float d = a2 / (PI * pow(n_dot_h * n_dot_h * (a2 - 1.0) + 1.0, 2.0));
+    float k = pow(roughness + 1.0, 2.0) / 8.0;
+    float g = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+    float f = 0.04 + 0.96 * pow(1.0 - max(dot(h, v), 0.0), 5.0);
+    return d * g * f / (4.0 * n_dot_v * n_dot_l);
+}
+
+vec3 shade(vec3 p, vec3 v) {
+    vec3 n = normal(p);
+    vec3 result = material_color * ambient;
+    for (int i = 0; i < light_count; i++) {
+        vec3 l = lights[i].xyz;
+        float light_dist = maxval;
+        if (lights[i].w > 0.0) {
+            l = lights[i].xyz - p;
+            light_dist = length(l);
+            l /= light_dist;
+        }
+        float d = dot(n, l);
+        if (d <= 0.0) {
+            continue;
+        }
+        float intensity = 1.0;
+        if (soft_shadows) {
+            intensity = soft_shadow(p, n, l, light_dist);
+            if (intensity <= 0.0) {
+                continue;
+            }
+        }
+        float s = ggx_specular ? ggx(n, l, v) * d
+                               : pow(max(dot(n * (2.0 * d) - l, v), 0.0), shininess);
+        vec3 lit = material_color * (diffuse * d) + vec3(specular * s);
+        result += light_colors[i] * lit * intensity;
+    }
+    if (ambient_occlusion) {
+        result *= occlusion(p, n);
+    }
+    return result;
+}
+
+void main() {
+    vec3 sum = vec3(0.0);
+    depth = infinity();
+    for (int i = 0; i < aa_samples; i++) {
+        for (int j = 0; j < aa_samples; j++) {
+            vec2 sub = (vec2(i, j) + 0.5) / float(aa_samples) - 0.5;
+            vec2 pixel = gl_FragCoord.xy - 0.5 + sub;
+            vec3 offset = right * (pixel.x - center.x) + down * (pixel.y - center.y);
+            vec3 o = orthographic ? origin + offset * viewer_dist : origin;
+            vec3 d = normalize(orthographic ? front : front + offset);
+            float t = march(o, d);
+            if (t < 0.0) {
+                sum += background;
+            } else {
+                sum += shade(o + d * t, -d);
+                depth = min(depth, t);
+            }
+        }
+    }
+    color = vec4(clamp(sum / float(aa_samples * aa_samples), 0.0, 1.0), 1.0);
+}
+";
+
+// The object for Renderer::set_shader: tree as GLSL distance function, or Err with why it can not
+// be converted (e.g. it has meshes). Without the gpu feature there is none, and the CPU renders
+// everything.
+pub fn for_rendering(
+    tree: Option<&Node>,
+    parameters: &PrimitiveParameters<Float>,
+) -> Result<Option<String>, String> {
+    match tree {
+        Some(tree) if cfg!(feature = "gpu") => {
+            shader::distance_function(tree, ShaderLanguage::Glsl, parameters.r_multiplier).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+// An image in the format of Renderer::draw_on_buf and its depth, like that of
+// Renderer::draw_on_buf_with_depth.
+type Image = (Vec<u8>, Vec<Float>);
+
+// The GPU of a widget. It is started when it first renders, and once that failed, the CPU renders
+// from then on.
+#[derive(Default)]
+pub struct Device {
+    #[cfg(feature = "gpu")]
+    gpu: Option<Result<Gpu, ()>>,
+    // Why the last frame was not rendered, which is only reported once, not for every frame.
+    #[cfg(feature = "gpu")]
+    failure: Option<String>,
+}
+
+impl Device {
+    pub fn new() -> Device {
+        Device::default()
+    }
+
+    // The image of scene (see Renderer::gpu_scene) in the format of Renderer::draw_on_buf, and
+    // its depth, like that of Renderer::draw_on_buf_with_depth. None if the GPU can not render it,
+    // e.g. before window is realized, and Err with why it failed, the first time it does.
+    #[cfg(feature = "gpu")]
+    pub fn render(
+        &mut self,
+        window: Option<gdk::Window>,
+        scene: &GpuScene,
+        width: i32,
+        height: i32,
+    ) -> Result<Option<Image>, String> {
+        if self.gpu.is_none() {
+            let window = match window {
+                Some(window) => window,
+                None => return Ok(None),
+            };
+            // It counts as failed until it started, so that failing to start is reported once.
+            self.gpu = Some(Err(()));
+            self.gpu = Some(Ok(Gpu::new(&window)?));
+        }
+        let gpu = match self.gpu {
+            Some(Ok(ref mut gpu)) => gpu,
+            _ => return Ok(None),
+        };
+        match gpu.render(scene, width, height) {
+            Ok(image) => {
+                self.failure = None;
+                Ok(Some(image))
+            }
+            Err(ref e) if self.failure.as_ref() == Some(e) => Ok(None),
+            Err(e) => {
+                self.failure = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    pub fn render(
+        &mut self,
+        _: Option<::gdk::Window>,
+        _: &GpuScene,
+        _: i32,
+        _: i32,
+    ) -> Result<Option<Image>, String> {
+        Ok(None)
+    }
+}
+
+// An OpenGL context of a window, rendering into a framebuffer of its own, which is read back.
+// The GL objects go with the context.
+#[cfg(feature = "gpu")]
+struct Gpu {
+    context: gdk::GLContext,
+    vertex_array: GLuint,
+    // The program and the distance function it was built with. A shader which does not compile
+    // is remembered as well, with program 0, so that it is not compiled for every frame.
+    program: Option<(String, GLuint)>,
+    // The framebuffer, its color and depth textures, and their size.
+    framebuffer: Option<(GLuint, [GLuint; 2], i32, i32)>,
+}
+
+#[cfg(feature = "gpu")]
+impl Gpu {
+    fn new(window: &gdk::Window) -> Result<Gpu, String> {
+        let context = window.create_gl_context().map_err(|e| e.to_string())?;
+        context.set_required_version(3, 3);
+        context.realize().map_err(|e| e.to_string())?;
+        context.make_current();
+        load()?;
+        let mut vertex_array = 0;
+        unsafe { gl::GenVertexArrays(1, &mut vertex_array) };
+        gdk::GLContext::clear_current();
+        Ok(Gpu {
+            context,
+            vertex_array,
+            program: None,
+            framebuffer: None,
+        })
+    }
+
+    fn render(
+        &mut self,
+        scene: &GpuScene,
+        width: i32,
+        height: i32,
+    ) -> Result<Image, String> {
+        if scene.lights.len() > MAX_LIGHTS {
+            return Err(format!("more than {} lights", MAX_LIGHTS));
+        }
+        self.context.make_current();
+        let result = self.program(&scene.shader).and_then(|program| unsafe {
+            let framebuffer = self.framebuffer(width, height)?;
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Viewport(0, 0, width, height);
+            gl::UseProgram(program);
+            set_uniforms(program, scene);
+            gl::BindVertexArray(self.vertex_array);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            let mut buf = vec![0u8; (width * height * 4) as usize];
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gl::BGRA,
+                gl::UNSIGNED_BYTE,
+                buf.as_mut_ptr() as *mut c_void,
+            );
+            let mut depth = vec![0 as GLfloat; (width * height) as usize];
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT1);
+            gl::ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gl::RED,
+                gl::FLOAT,
+                depth.as_mut_ptr() as *mut c_void,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            match gl::GetError() {
+                gl::NO_ERROR => Ok((buf, depth.into_iter().map(Float::from).collect())),
+                e => Err(format!("OpenGL error {:#x}", e)),
+            }
+        });
+        gdk::GLContext::clear_current();
+        result
+    }
+
+    // The program for the distance function shader, compiled if it changed.
+    fn program(&mut self, shader: &str) -> Result<GLuint, String> {
+        match self.program {
+            Some((ref compiled, 0)) if compiled == shader => {
+                return Err("the shader did not compile".to_string())
+            }
+            Some((ref compiled, program)) if compiled == shader => return Ok(program),
+            Some((_, program)) => unsafe { gl::DeleteProgram(program) },
+            None => {}
+        }
+        let fragment_shader = format!("{}\n{}", FRAGMENT_SHADER, shader);
+        let program = unsafe { link(VERTEX_SHADER, &fragment_shader) };
+        self.program = Some((shader.to_string(), *program.as_ref().unwrap_or(&0)));
+        program
+    }
+
+    // The framebuffer of the size, made anew if the size changed.
+    unsafe fn framebuffer(&mut self, width: i32, height: i32) -> Result<GLuint, String> {
+        match self.framebuffer {
+            Some((framebuffer, _, w, h)) if (w, h) == (width, height) => return Ok(framebuffer),
+            Some((framebuffer, textures, _, _)) => {
+                gl::DeleteFramebuffers(1, &framebuffer);
+                gl::DeleteTextures(2, textures.as_ptr());
+                self.framebuffer = None;
+            }
+            None => {}
+        }
+        let mut framebuffer = 0;
+        let mut textures = [0; 2];
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::GenTextures(2, textures.as_mut_ptr());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        let formats = [
+            (gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE),
+            (gl::R32F, gl::RED, gl::FLOAT),
+        ];
+        let attachments = [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1];
+        for i in 0..2 {
+            let (internal, format, ty) = formats[i];
+            gl::BindTexture(gl::TEXTURE_2D, textures[i]);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                internal as GLint,
+                width,
+                height,
+                0,
+                format,
+                ty,
+                ptr::null(),
+            );
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                attachments[i],
+                gl::TEXTURE_2D,
+                textures[i],
+                0,
+            );
+        }
+        gl::DrawBuffers(2, attachments.as_ptr());
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        self.framebuffer = Some((framebuffer, textures, width, height));
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("incomplete framebuffer {:#x}", status));
+        }
+        Ok(framebuffer)
+    }
+}
+
+// Look up the OpenGL functions. GTK links libepoxy, which resolves them for the current context,
+// from function pointers named epoxy_ and the name of the function.
+#[cfg(all(feature = "gpu", unix))]
+fn load() -> Result<(), String> {
+    let library = ::libloading::os::unix::Library::this();
+    gl::load_with(|name| unsafe {
+        library
+            .get::<*const *const c_void>(format!("epoxy_{}\0", name).as_bytes())
+            .map_or(ptr::null(), |pointer| **pointer)
+    });
+    if gl::DrawArrays::is_loaded() {
+        Ok(())
+    } else {
+        Err("libepoxy was not found".to_string())
+    }
+}
+
+#[cfg(all(feature = "gpu", not(unix)))]
+fn load() -> Result<(), String> {
+    Err("OpenGL functions are only looked up on unix".to_string())
+}
+
+#[cfg(feature = "gpu")]
+unsafe fn compile(kind: GLenum, source: &str) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    let source = CString::new(source).map_err(|e| e.to_string())?;
+    gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+    let mut status = 0;
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+    if status == gl::TRUE as GLint {
+        return Ok(shader);
+    }
+    let mut len = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+    let mut log = vec![0u8; len.max(1) as usize];
+    gl::GetShaderInfoLog(
+        shader,
+        len,
+        ptr::null_mut(),
+        log.as_mut_ptr() as *mut GLchar,
+    );
+    gl::DeleteShader(shader);
+    Err(format!(
+        "the shader did not compile: {}",
+        String::from_utf8_lossy(&log).trim_end_matches('\0')
+    ))
+}
+
+#[cfg(feature = "gpu")]
+unsafe fn link(vertex_source: &str, fragment_source: &str) -> Result<GLuint, String> {
+    let vertex_shader = compile(gl::VERTEX_SHADER, vertex_source)?;
+    let fragment_shader = match compile(gl::FRAGMENT_SHADER, fragment_source) {
+        Ok(shader) => shader,
+        Err(e) => {
+            gl::DeleteShader(vertex_shader);
+            return Err(e);
+        }
+    };
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vertex_shader);
+    gl::AttachShader(program, fragment_shader);
+    gl::LinkProgram(program);
+    gl::DeleteShader(vertex_shader);
+    gl::DeleteShader(fragment_shader);
+    let mut status = 0;
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+    if status != gl::TRUE as GLint {
+        gl::DeleteProgram(program);
+        return Err("the shader did not link".to_string());
+    }
+    Ok(program)
+}
+
+#[cfg(feature = "gpu")]
+unsafe fn set_uniforms(program: GLuint, scene: &GpuScene) {
+    let location = |name: &str| {
+        let name = CString::new(name).unwrap();
+        gl::GetUniformLocation(program, name.as_ptr())
+    };
+    let float = |name: &str, x: Float| gl::Uniform1f(location(name), x as GLfloat);
+    let int = |name: &str, i: usize| gl::Uniform1i(location(name), i as GLint);
+    let vec3 = |name: &str, x: Float, y: Float, z: Float| {
+        gl::Uniform3f(location(name), x as GLfloat, y as GLfloat, z as GLfloat)
+    };
+    let s = &scene.settings;
+    vec3("origin", scene.origin.x, scene.origin.y, scene.origin.z);
+    vec3("front", scene.front.x, scene.front.y, scene.front.z);
+    vec3("right", scene.right.x, scene.right.y, scene.right.z);
+    vec3("down", scene.down.x, scene.down.y, scene.down.z);
+    gl::Uniform2f(
+        location("center"),
+        scene.center.0 as GLfloat,
+        scene.center.1 as GLfloat,
+    );
+    float("viewer_dist", scene.viewer_dist);
+    int("orthographic", scene.orthographic as usize);
+    // The box may be infinite, which GLSL has no literal for.
+    let finite = |x: Float| x.clamp(-1e30, 1e30);
+    let (min, max) = (&scene.bbox.min, &scene.bbox.max);
+    vec3("box_min", finite(min.x), finite(min.y), finite(min.z));
+    vec3("box_max", finite(max.x), finite(max.y), finite(max.z));
+    float("epsilon", scene.epsilon);
+    float("maxval", scene.maxval);
+    float("relaxation", s.relaxation);
+    int("max_iterations", s.max_iterations);
+    int("aa_samples", s.aa_samples);
+    int("soft_shadows", s.soft_shadows as usize);
+    int("ambient_occlusion", s.ambient_occlusion as usize);
+    int("light_count", scene.lights.len());
+    for (i, light) in scene.lights.iter().enumerate() {
+        let (v, w) = match light.source {
+            LightSource::Directional(dir) => (dir, 0.),
+            LightSource::Point(pos) => (pos.coords, 1.),
+        };
+        gl::Uniform4f(
+            location(&format!("lights[{}]", i)),
+            v.x as GLfloat,
+            v.y as GLfloat,
+            v.z as GLfloat,
+            w,
+        );
+        let color = &light.color;
+        vec3(&format!("light_colors[{}]", i), color.x, color.y, color.z);
+    }
+    let m = &s.material;
+    vec3("material_color", m.color.x, m.color.y, m.color.z);
+    float("ambient", m.ambient);
+    float("diffuse", m.diffuse);
+    float("specular", m.specular);
+    match m.specular_model {
+        ::render::Specular::Phong { shininess } => {
+            int("ggx_specular", 0);
+            float("shininess", shininess);
+        }
+        ::render::Specular::Ggx { roughness } => {
+            int("ggx_specular", 1);
+            float("roughness", roughness);
+        }
+    }
+    let b = &s.background;
+    vec3("background", b.x, b.y, b.z);
+}
@@ -0,0 +1,230 @@
+use super::Float;
+use na;
+use std::io::{self, Write};
+use truescad_luascad::bounded;
+use truescad_luascad::implicit3d::Object;
+use truescad_luascad::units::Units;
+
+// Hermite data of an object: where the edges of a grid of cubic cells cross its surface, with
+// the normals there, for meshing it with other tools (e.g. dual contouring). The cells are the
+// leaves of an octree, which is only refined where the surface may be, and only leaves the
+// surface passes through are kept.
+//
+// Files (with the extension EXTENSION) are little endian:
+//
+//     magic        8 bytes     "TSHERMIT"
+//     version      u32         VERSION
+//     millimeters  f64         the length of a unit of the coordinates in mm
+//     origin       3 x f64     the lowest corner of the root of the octree
+//     cell size    f64         the length of the edges of the leaves
+//     depth        u32         the levels below the root, which has 2^depth leaves along each
+//                              axis
+//     cells        u64         the number of cells which follow
+//
+// followed by each cell:
+//
+//     index        3 x u32     its position in cells from the origin along x, y and z
+//     signs        u8          bit c is set if corner c is inside the object
+//     edges        u16         bit e is set if edge e crosses the surface
+//     crossings                for each set bit of edges, the lowest first:
+//         t        f32         where the edge crosses, from 0 at its first corner to 1
+//         normal   3 x f32     the normal of the surface there, of unit length
+//
+// Corner c is at (c & 1, c >> 1 & 1, c >> 2 & 1) cells from the lowest one. Edges 0 to 3 are
+// along x, 4 to 7 along y and 8 to 11 along z. Edge e starts at the corner whose coordinate
+// along its axis is 0 and whose other two, in the order x, y, z, are the bits e & 1 and
+// e >> 1 & 1. Edges are shared by up to four cells, each of which lists them.
+
+pub const EXTENSION: &str = "hermite";
+const MAGIC: &[u8; 8] = b"TSHERMIT";
+const VERSION: u32 = 1;
+// The octree has at most this many levels, so cell indices fit into u32 with room to spare.
+const MAX_DEPTH: u32 = 16;
+// Crossings are found to 2^-BISECTIONS of the length of an edge.
+const BISECTIONS: usize = 16;
+// Cells are skipped if the value at their center is more than this many times the distance to
+// their corners, which leaves room for objects whose values are not distances.
+const MARGIN: Float = 1.5;
+
+pub struct Crossing {
+    // Which edge of the cell, see the format above.
+    pub edge: u8,
+    pub t: Float,
+    pub normal: na::Vector3<Float>,
+}
+
+pub struct Cell {
+    pub index: [u32; 3],
+    pub signs: u8,
+    pub crossings: Vec<Crossing>,
+}
+
+pub struct HermiteData {
+    pub units: Units,
+    pub origin: na::Point3<Float>,
+    pub cell_size: Float,
+    pub depth: u32,
+    pub cells: Vec<Cell>,
+}
+
+// Whether path is a file of hermite data, by its extension.
+pub fn is_hermite_path(path: &str) -> bool {
+    ::std::path::Path::new(path)
+        .extension()
+        .map_or(false, |e| e == EXTENSION)
+}
+
+// The hermite data of object, whose coordinates are in units, with cells of about resolution.
+// Err if the object is infinite.
+pub fn sample(
+    object: &dyn Object<Float>,
+    units: Units,
+    resolution: Float,
+) -> Result<HermiteData, String> {
+    let mut bbox = object.bbox().clone();
+    if !bounded::is_finite(&bbox) {
+        return Err("an infinite object has no hermite data".to_string());
+    }
+    // Leave a cell around the object, so that all of its surface crosses edges.
+    bbox.dilate(resolution);
+    let dim = bbox.dim();
+    let size = dim.x.max(dim.y).max(dim.z);
+    let depth = (size / resolution).log2().ceil().max(0.) as u32;
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "a resolution of {} needs more than {} cells along the object",
+            resolution,
+            1 << MAX_DEPTH
+        ));
+    }
+    let mut data = HermiteData {
+        units,
+        origin: bbox.min,
+        cell_size: resolution,
+        depth,
+        cells: Vec::new(),
+    };
+    data.refine(object, [0, 0, 0], 1 << depth);
+    Ok(data)
+}
+
+impl HermiteData {
+    fn corner(&self, index: [u32; 3]) -> na::Point3<Float> {
+        self.origin
+            + na::Vector3::new(index[0] as Float, index[1] as Float, index[2] as Float)
+                * self.cell_size
+    }
+    // Add the leaves of the cell at index with size leaves along each axis which the surface
+    // passes through.
+    fn refine(&mut self, object: &dyn Object<Float>, index: [u32; 3], size: u32) {
+        let half = size as Float * self.cell_size / 2.;
+        let center = self.corner(index) + na::Vector3::repeat(half);
+        let reach = half * Float::sqrt(3.) * MARGIN;
+        if object.approx_value(&center, reach).abs() > reach {
+            return;
+        }
+        if size > 1 {
+            let half = size / 2;
+            for c in 0..8 {
+                let child = [
+                    index[0] + (c & 1) * half,
+                    index[1] + (c >> 1 & 1) * half,
+                    index[2] + (c >> 2 & 1) * half,
+                ];
+                self.refine(object, child, half);
+            }
+            return;
+        }
+        let corners: Vec<na::Point3<Float>> = (0..8)
+            .map(|c| {
+                self.corner([
+                    index[0] + (c & 1),
+                    index[1] + (c >> 1 & 1),
+                    index[2] + (c >> 2 & 1),
+                ])
+            })
+            .collect();
+        let values: Vec<Float> = corners.iter().map(|p| object.approx_value(p, 0.)).collect();
+        let signs = (0..8).fold(0u8, |s, c| s | ((values[c] < 0.) as u8) << c);
+        if signs == 0 || signs == 0xff {
+            return;
+        }
+        let mut crossings = Vec::new();
+        for edge in 0..12 {
+            let (a, b) = edge_corners(edge);
+            if (values[a] < 0.) == (values[b] < 0.) {
+                continue;
+            }
+            let t = crossing(object, &corners[a], &corners[b], values[a] < 0.);
+            let p = corners[a] + (corners[b] - corners[a]) * t;
+            crossings.push(Crossing {
+                edge: edge as u8,
+                t,
+                normal: object.normal(&p),
+            });
+        }
+        self.cells.push(Cell {
+            index,
+            signs,
+            crossings,
+        });
+    }
+    // Write the data in the format above. The crossings are f32 whatever Float is, so their casts
+    // are only unnecessary with the f32 feature.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.units.millimeters().to_le_bytes())?;
+        for x in self.origin.coords.iter() {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        w.write_all(&self.cell_size.to_le_bytes())?;
+        w.write_all(&self.depth.to_le_bytes())?;
+        w.write_all(&(self.cells.len() as u64).to_le_bytes())?;
+        for cell in &self.cells {
+            for i in &cell.index {
+                w.write_all(&i.to_le_bytes())?;
+            }
+            w.write_all(&[cell.signs])?;
+            let edges = cell.crossings.iter().fold(0u16, |e, c| e | 1 << c.edge);
+            w.write_all(&edges.to_le_bytes())?;
+            for c in &cell.crossings {
+                w.write_all(&(c.t as f32).to_le_bytes())?;
+                for x in c.normal.iter() {
+                    w.write_all(&(*x as f32).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The corners edge starts and ends at, see the format above.
+fn edge_corners(edge: usize) -> (usize, usize) {
+    let axis = edge / 4;
+    // The bits of the other two axes, in the order x, y, z.
+    let others: Vec<usize> = (0..3).filter(|&a| a != axis).collect();
+    let start = (edge & 1) << others[0] | (edge >> 1 & 1) << others[1];
+    (start, start | 1 << axis)
+}
+
+// Where the surface crosses from a to b, from 0 to 1, by bisection. a is inside if inside.
+fn crossing(
+    object: &dyn Object<Float>,
+    a: &na::Point3<Float>,
+    b: &na::Point3<Float>,
+    inside: bool,
+) -> Float {
+    let (mut low, mut high) = (0., 1.);
+    for _ in 0..BISECTIONS {
+        let middle = (low + high) / 2.;
+        let p = a + (b - a) * middle;
+        if (object.approx_value(&p, 0.) < 0.) == inside {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) / 2.
+}
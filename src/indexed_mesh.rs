@@ -0,0 +1,652 @@
+use super::Float;
+use na;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Seek, Write};
+use tessellation::Mesh;
+use truescad_luascad::arrange;
+use truescad_luascad::implicit3d::Object;
+use truescad_luascad::units::Units;
+
+// The parts of a 3MF package besides the model, see IndexedMesh::write_3mf.
+const THREE_MF_CONTENT_TYPES: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">
+<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>
+<Default Extension=\"model\" ContentType=\"application/vnd.ms-package.3dmanufacturing-3dmodel+xml\"/>
+</Types>
+";
+const THREE_MF_RELATIONSHIPS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">
+<Relationship Target=\"/3D/3dmodel.model\" Id=\"rel0\" \
+Type=\"http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel\"/>
+</Relationships>
+";
+
+// An undirected edge, stored with the smaller vertex index first.
+pub type Edge = (usize, usize);
+// The lower and upper corners of a footprint on the plate, see IndexedMesh::arrange.
+type Footprint = (na::Point2<Float>, na::Point2<Float>);
+
+pub fn edge(a: usize, b: usize) -> Edge {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// In-memory triangle mesh with per vertex normals.
+// This is what tessellation hands to viewers, post-processing and exporters.
+#[derive(Clone, Debug, Default)]
+pub struct IndexedMesh {
+    pub vertices: Vec<na::Point3<Float>>,
+    pub normals: Vec<na::Vector3<Float>>,
+    pub faces: Vec<[usize; 3]>,
+    // The units of the vertices.
+    pub units: Units,
+}
+
+impl IndexedMesh {
+    pub fn new(vertices: Vec<na::Point3<Float>>, faces: Vec<[usize; 3]>) -> IndexedMesh {
+        let mut mesh = IndexedMesh {
+            vertices,
+            normals: Vec::new(),
+            faces,
+            units: Units::default(),
+        };
+        mesh.compute_vertex_normals();
+        mesh
+    }
+
+    pub fn from_tessellation(mesh: &Mesh<Float>) -> IndexedMesh {
+        IndexedMesh::new(
+            mesh.vertices
+                .iter()
+                .map(|v| na::Point3::new(v[0], v[1], v[2]))
+                .collect(),
+            mesh.faces.clone(),
+        )
+    }
+
+    // Set vertex normals to the area weighted average of the adjacent face normals.
+    pub fn compute_vertex_normals(&mut self) {
+        let mut normals = vec![na::Vector3::new(0., 0., 0.); self.vertices.len()];
+        for face in &self.faces {
+            // The cross product is twice the face area, which gives the weighting for free.
+            let n = self.face_cross(face);
+            for i in face.iter() {
+                normals[*i] += n;
+            }
+        }
+        for n in &mut normals {
+            let norm = n.norm();
+            if norm > 0. {
+                *n /= norm;
+            }
+        }
+        self.normals = normals;
+    }
+
+    // The vertex normals for exporters, recomputed if some vertices have none (like those
+    // adhesion adds).
+    pub fn vertex_normals(&self) -> Cow<[na::Vector3<Float>]> {
+        if self.normals.len() == self.vertices.len() {
+            Cow::Borrowed(&self.normals)
+        } else {
+            let mut mesh = self.clone();
+            mesh.compute_vertex_normals();
+            Cow::Owned(mesh.normals)
+        }
+    }
+
+    // Add the vertices, normals and faces of other, which is in the same units.
+    pub fn append(&mut self, other: &IndexedMesh) {
+        let offset = self.vertices.len();
+        self.vertices.extend(&other.vertices);
+        self.normals.extend(&other.normals);
+        self.faces.extend(
+            other
+                .faces
+                .iter()
+                .map(|f| [f[0] + offset, f[1] + offset, f[2] + offset]),
+        );
+    }
+
+    // The lowest and highest corner of the box around the vertices, inverted if there are none.
+    pub fn bounds(&self) -> (na::Point3<Float>, na::Point3<Float>) {
+        self.vertices.iter().fold(
+            (
+                na::Point3::new(Float::MAX, Float::MAX, Float::MAX),
+                na::Point3::new(Float::MIN, Float::MIN, Float::MIN),
+            ),
+            |(min, max), v| (na::inf(&min, v), na::sup(&max, v)),
+        )
+    }
+
+    // Move the vertices by offset.
+    pub fn translate(&mut self, offset: na::Vector3<Float>) {
+        for v in &mut self.vertices {
+            *v += offset;
+        }
+    }
+
+    // Scale the vertices by factor around the origin, which leaves the normals as they are.
+    pub fn scale(&mut self, factor: Float) {
+        for v in &mut self.vertices {
+            *v *= factor;
+        }
+    }
+
+    // Lay the parts of the mesh (see split) out on a square plate of size, spacing apart and
+    // resting on z = 0, see arrange::pack. Returns the footprints of the parts, in the order of
+    // split, or Err with the index of a part which does not fit.
+    pub fn arrange(&mut self, size: Float, spacing: Float) -> Result<Vec<Footprint>, usize> {
+        let parts = self.split();
+        let boxes: Vec<(na::Point3<Float>, na::Point3<Float>)> =
+            parts.iter().map(|part| part.bounds()).collect();
+        let sizes: Vec<na::Vector2<Float>> = boxes
+            .iter()
+            .map(|(min, max)| na::Vector2::new(max.x - min.x, max.y - min.y))
+            .collect();
+        let corners = arrange::pack(&sizes, na::Vector2::new(size, size), spacing)?;
+        let mut arranged = IndexedMesh {
+            units: self.units,
+            ..IndexedMesh::default()
+        };
+        for (mut part, (&(min, _), corner)) in parts.into_iter().zip(boxes.iter().zip(&corners)) {
+            part.translate(na::Vector3::new(corner.x - min.x, corner.y - min.y, -min.z));
+            arranged.append(&part);
+        }
+        *self = arranged;
+        Ok(corners
+            .iter()
+            .zip(&sizes)
+            .map(|(&corner, &size)| (corner, corner + size))
+            .collect())
+    }
+
+    // Replace vertex normals with the exact normals of the implicit function.
+    pub fn set_normals_from_object(&mut self, object: &dyn Object<Float>) {
+        self.normals = self.vertices.iter().map(|v| object.normal(v)).collect();
+    }
+
+    pub fn face_normal(&self, face: usize) -> na::Vector3<Float> {
+        let n = self.face_cross(&self.faces[face]);
+        let norm = n.norm();
+        if norm > 0. {
+            n / norm
+        } else {
+            n
+        }
+    }
+
+    pub fn face_area(&self, face: usize) -> Float {
+        self.face_cross(&self.faces[face]).norm() * 0.5
+    }
+
+    pub fn area(&self) -> Float {
+        (0..self.faces.len()).map(|f| self.face_area(f)).sum()
+    }
+
+    // The enclosed volume, by the divergence theorem. Only meaningful for closed meshes.
+    pub fn volume(&self) -> Float {
+        self.faces
+            .iter()
+            .map(|f| {
+                let (a, b, c) = (
+                    self.vertices[f[0]],
+                    self.vertices[f[1]],
+                    self.vertices[f[2]],
+                );
+                a.coords.dot(&b.coords.cross(&c.coords)) / 6.
+            })
+            .sum()
+    }
+
+    fn face_cross(&self, face: &[usize; 3]) -> na::Vector3<Float> {
+        let a = self.vertices[face[0]];
+        let b = self.vertices[face[1]];
+        let c = self.vertices[face[2]];
+        (b - a).cross(&(c - a))
+    }
+
+    // For each vertex, the list of faces using it.
+    pub fn vertex_faces(&self) -> Vec<Vec<usize>> {
+        let mut result = vec![Vec::new(); self.vertices.len()];
+        for (fi, face) in self.faces.iter().enumerate() {
+            for i in face.iter() {
+                result[*i].push(fi);
+            }
+        }
+        result
+    }
+
+    // For each vertex, the sorted list of vertices sharing an edge with it.
+    pub fn vertex_neighbors(&self) -> Vec<Vec<usize>> {
+        let mut result = vec![Vec::new(); self.vertices.len()];
+        for &(a, b) in self.edge_faces().keys() {
+            result[a].push(b);
+            result[b].push(a);
+        }
+        for n in &mut result {
+            n.sort();
+        }
+        result
+    }
+
+    // Map from each edge to the faces containing it.
+    // In a closed 2-manifold mesh every edge has exactly two faces.
+    pub fn edge_faces(&self) -> HashMap<Edge, Vec<usize>> {
+        let mut result: HashMap<Edge, Vec<usize>> = HashMap::new();
+        for (fi, face) in self.faces.iter().enumerate() {
+            for i in 0..3 {
+                result
+                    .entry(edge(face[i], face[(i + 1) % 3]))
+                    .or_default()
+                    .push(fi);
+            }
+        }
+        result
+    }
+
+    // For each face, the face across each of its edges (edge i goes from vertex i to i+1).
+    // None, if the edge is a boundary or non-manifold edge.
+    pub fn face_neighbors(&self) -> Vec<[Option<usize>; 3]> {
+        let edge_faces = self.edge_faces();
+        self.faces
+            .iter()
+            .enumerate()
+            .map(|(fi, face)| {
+                let mut neighbors = [None; 3];
+                for (i, neighbor) in neighbors.iter_mut().enumerate() {
+                    let faces = &edge_faces[&edge(face[i], face[(i + 1) % 3])];
+                    if faces.len() == 2 {
+                        *neighbor = faces.iter().cloned().find(|f| *f != fi);
+                    }
+                }
+                neighbors
+            })
+            .collect()
+    }
+
+    // The connected parts of the mesh, of faces sharing vertices, with the most faces first.
+    pub fn split(&self) -> Vec<IndexedMesh> {
+        // Union-find over the vertices.
+        let mut parent: Vec<usize> = (0..self.vertices.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for face in &self.faces {
+            let a = root(&mut parent, face[0]);
+            for &v in &face[1..] {
+                let b = root(&mut parent, v);
+                parent[b] = a;
+            }
+        }
+        let mut parts: HashMap<usize, IndexedMesh> = HashMap::new();
+        let mut index: HashMap<usize, usize> = HashMap::new();
+        for face in &self.faces {
+            let part = parts
+                .entry(root(&mut parent, face[0]))
+                .or_insert_with(|| IndexedMesh {
+                    units: self.units,
+                    ..IndexedMesh::default()
+                });
+            let mut new_face = [0; 3];
+            for (new, &v) in new_face.iter_mut().zip(face) {
+                *new = *index.entry(v).or_insert_with(|| {
+                    part.vertices.push(self.vertices[v]);
+                    if let Some(n) = self.normals.get(v) {
+                        part.normals.push(*n);
+                    }
+                    part.vertices.len() - 1
+                });
+            }
+            part.faces.push(new_face);
+        }
+        let mut parts: Vec<IndexedMesh> = parts.into_values().collect();
+        parts.sort_by_key(|part| ::std::cmp::Reverse(part.faces.len()));
+        parts
+    }
+
+    // STL has no units, and is read as millimeters, so the vertices are converted to those. The
+    // casts are only unnecessary with the f32 feature.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn stl_triangles(&self) -> Vec<::stl_io::Triangle> {
+        let mm = self.units.millimeters();
+        let to32 =
+            |p: &na::Point3<Float>| [(p.x * mm) as f32, (p.y * mm) as f32, (p.z * mm) as f32];
+        self.faces
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let n = self.face_normal(i);
+                ::stl_io::Triangle {
+                    normal: [n.x as f32, n.y as f32, n.z as f32],
+                    vertices: [
+                        to32(&self.vertices[f[0]]),
+                        to32(&self.vertices[f[1]]),
+                        to32(&self.vertices[f[2]]),
+                    ],
+                }
+            })
+            .collect()
+    }
+
+    // Write the mesh as ASCII STL, for tools which do not read binary STL.
+    pub fn write_ascii_stl<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "solid truescad")?;
+        for t in self.stl_triangles() {
+            let n = t.normal;
+            writeln!(w, "  facet normal {:e} {:e} {:e}", n[0], n[1], n[2])?;
+            writeln!(w, "    outer loop")?;
+            for v in &t.vertices {
+                writeln!(w, "      vertex {:e} {:e} {:e}", v[0], v[1], v[2])?;
+            }
+            writeln!(w, "    endloop")?;
+            writeln!(w, "  endfacet")?;
+        }
+        writeln!(w, "endsolid truescad")
+    }
+
+    // Write the mesh as a 3MF package, in the units of the script, which 3MF declares.
+    pub fn write_3mf<W: Write + Seek>(&self, w: W) -> io::Result<()> {
+        let unit = match self.units {
+            Units::Millimeter => "millimeter",
+            Units::Centimeter => "centimeter",
+            Units::Inch => "inch",
+        };
+        let mut zip = ::zip::ZipWriter::new(w);
+        let options = ::zip::write::FileOptions::default();
+        zip.start_file("[Content_Types].xml", options)?;
+        zip.write_all(THREE_MF_CONTENT_TYPES.as_bytes())?;
+        zip.start_file("_rels/.rels", options)?;
+        zip.write_all(THREE_MF_RELATIONSHIPS.as_bytes())?;
+        zip.start_file("3D/3dmodel.model", options)?;
+        writeln!(zip, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            zip,
+            "<model unit=\"{}\" \
+             xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">",
+            unit
+        )?;
+        writeln!(
+            zip,
+            "<resources><object id=\"1\" type=\"model\"><mesh><vertices>"
+        )?;
+        for v in &self.vertices {
+            writeln!(zip, "<vertex x=\"{}\" y=\"{}\" z=\"{}\"/>", v.x, v.y, v.z)?;
+        }
+        writeln!(zip, "</vertices><triangles>")?;
+        for f in &self.faces {
+            writeln!(
+                zip,
+                "<triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"/>",
+                f[0], f[1], f[2]
+            )?;
+        }
+        writeln!(zip, "</triangles></mesh></object></resources>")?;
+        writeln!(zip, "<build><item objectid=\"1\"/></build></model>")?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    // Write the mesh as AMF, in the units of the script, which AMF declares. It has no
+    // centimeters, so those are converted to millimeters.
+    pub fn write_amf<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (unit, scale) = match self.units {
+            Units::Millimeter => ("millimeter", 1.),
+            Units::Centimeter => ("millimeter", Units::Centimeter.millimeters()),
+            Units::Inch => ("inch", 1.),
+        };
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(w, "<amf unit=\"{}\" version=\"1.1\">", unit)?;
+        writeln!(w, "<object id=\"0\"><mesh><vertices>")?;
+        for (v, n) in self.vertices.iter().zip(self.vertex_normals().iter()) {
+            writeln!(
+                w,
+                "<vertex><coordinates><x>{}</x><y>{}</y><z>{}</z></coordinates>\
+                 <normal><nx>{}</nx><ny>{}</ny><nz>{}</nz></normal></vertex>",
+                v.x * scale,
+                v.y * scale,
+                v.z * scale,
+                n.x,
+                n.y,
+                n.z
+            )?;
+        }
+        writeln!(w, "</vertices><volume>")?;
+        for f in &self.faces {
+            writeln!(
+                w,
+                "<triangle><v1>{}</v1><v2>{}</v2><v3>{}</v3></triangle>",
+                f[0], f[1], f[2]
+            )?;
+        }
+        writeln!(w, "</volume></mesh></object></amf>")
+    }
+
+    // Write the mesh as ASCII PLY with vertex normals. PLY has no units, so like STL the
+    // vertices are converted to millimeters.
+    pub fn write_ply<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mm = self.units.millimeters();
+        writeln!(w, "ply")?;
+        writeln!(w, "format ascii 1.0")?;
+        writeln!(w, "comment truescad, in millimeters")?;
+        writeln!(w, "element vertex {}", self.vertices.len())?;
+        for p in &["x", "y", "z", "nx", "ny", "nz"] {
+            writeln!(w, "property float {}", p)?;
+        }
+        writeln!(w, "element face {}", self.faces.len())?;
+        writeln!(w, "property list uchar int vertex_indices")?;
+        writeln!(w, "end_header")?;
+        for (v, n) in self.vertices.iter().zip(self.vertex_normals().iter()) {
+            writeln!(
+                w,
+                "{} {} {} {} {} {}",
+                v.x * mm,
+                v.y * mm,
+                v.z * mm,
+                n.x,
+                n.y,
+                n.z
+            )?;
+        }
+        for f in &self.faces {
+            writeln!(w, "3 {} {} {}", f[0], f[1], f[2])?;
+        }
+        Ok(())
+    }
+
+    // Write the mesh as Wavefront OBJ with vertex normals, in millimeters like STL.
+    pub fn write_obj<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mm = self.units.millimeters();
+        writeln!(w, "# truescad, in millimeters")?;
+        for v in &self.vertices {
+            writeln!(w, "v {} {} {}", v.x * mm, v.y * mm, v.z * mm)?;
+        }
+        for n in self.vertex_normals().iter() {
+            writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        // OBJ counts from 1, and a vertex has the normal of the same index.
+        for f in &self.faces {
+            writeln!(
+                w,
+                "f {0}//{0} {1}//{1} {2}//{2}",
+                f[0] + 1,
+                f[1] + 1,
+                f[2] + 1
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    // A tetrahedron in units.
+    fn tetrahedron(units: Units) -> IndexedMesh {
+        let mut mesh = IndexedMesh::new(
+            vec![
+                na::Point3::new(0., 0., 0.),
+                na::Point3::new(1., 0., 0.),
+                na::Point3::new(0., 1., 0.),
+                na::Point3::new(0., 0., 1.),
+            ],
+            vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+        );
+        mesh.units = units;
+        mesh
+    }
+
+    fn assert_same(a: &IndexedMesh, b: &IndexedMesh) {
+        assert_eq!(a.faces, b.faces);
+        assert_eq!(a.vertices.len(), b.vertices.len());
+        assert_eq!(a.normals.len(), b.normals.len());
+        for (p, q) in a.vertices.iter().zip(&b.vertices) {
+            assert!((p - q).norm() < 1e-5, "{} {}", p, q);
+        }
+        for (m, n) in a.normals.iter().zip(&b.normals) {
+            assert!((m - n).norm() < 1e-5, "{} {}", m, n);
+        }
+    }
+
+    // The vertices, normals and faces of an OBJ file written by write_obj.
+    fn read_obj(obj: &str) -> IndexedMesh {
+        let mut mesh = IndexedMesh::default();
+        for line in obj.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |i: usize| words[i].parse::<Float>().unwrap();
+            let index = |i: usize| {
+                let (v, n) = words[i].split_at(words[i].find("//").unwrap());
+                assert_eq!(v, &n[2..]);
+                v.parse::<usize>().unwrap() - 1
+            };
+            match words.first() {
+                Some(&"v") => mesh
+                    .vertices
+                    .push(na::Point3::new(number(1), number(2), number(3))),
+                Some(&"vn") => mesh
+                    .normals
+                    .push(na::Vector3::new(number(1), number(2), number(3))),
+                Some(&"f") => mesh.faces.push([index(1), index(2), index(3)]),
+                _ => {}
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn obj_round_trip() {
+        let mesh = tetrahedron(Units::Millimeter);
+        let mut bytes = Vec::new();
+        mesh.write_obj(&mut bytes).unwrap();
+        assert_same(&read_obj(&String::from_utf8(bytes).unwrap()), &mesh);
+    }
+
+    #[test]
+    fn ply_round_trip() {
+        let mesh = tetrahedron(Units::Centimeter);
+        let mut bytes = Vec::new();
+        mesh.write_ply(&mut bytes).unwrap();
+        let (vertices, normals) = ::truescad_luascad::pointcloud::parse_ply(&bytes).unwrap();
+        let ply = String::from_utf8(bytes).unwrap();
+        // parse_ply reads the vertices only.
+        let faces = ply
+            .lines()
+            .skip_while(|l| *l != "end_header")
+            .skip(1 + mesh.vertices.len())
+            .map(|l| {
+                let words: Vec<usize> = l.split(' ').map(|w| w.parse().unwrap()).collect();
+                assert_eq!(words[0], 3);
+                [words[1], words[2], words[3]]
+            })
+            .collect();
+        let mut millimeters = mesh.clone();
+        millimeters.scale(Units::Centimeter.millimeters());
+        assert_same(
+            &IndexedMesh {
+                vertices,
+                normals: normals.unwrap(),
+                faces,
+                units: Units::Millimeter,
+            },
+            &millimeters,
+        );
+    }
+
+    #[test]
+    fn missing_normals_are_recomputed() {
+        let mut mesh = tetrahedron(Units::Millimeter);
+        let normals = mesh.normals.clone();
+        mesh.normals.truncate(2);
+        assert_eq!(mesh.vertex_normals().as_ref(), normals.as_slice());
+        let mut bytes = Vec::new();
+        mesh.write_obj(&mut bytes).unwrap();
+        assert_eq!(
+            read_obj(&String::from_utf8(bytes).unwrap()).normals.len(),
+            4
+        );
+    }
+
+    fn three_mf_model(mesh: &IndexedMesh) -> String {
+        let mut bytes = Cursor::new(Vec::new());
+        mesh.write_3mf(&mut bytes).unwrap();
+        let mut zip = ::zip::ZipArchive::new(bytes).unwrap();
+        let mut model = String::new();
+        zip.by_name("3D/3dmodel.model")
+            .unwrap()
+            .read_to_string(&mut model)
+            .unwrap();
+        model
+    }
+
+    #[test]
+    fn three_mf_declares_the_units() {
+        for &(units, unit) in &[
+            (Units::Millimeter, "millimeter"),
+            (Units::Centimeter, "centimeter"),
+            (Units::Inch, "inch"),
+        ] {
+            let model = three_mf_model(&tetrahedron(units));
+            assert!(
+                model.contains(&format!("<model unit=\"{}\"", unit)),
+                "{}",
+                model
+            );
+            assert!(
+                model.contains("<vertex x=\"1\" y=\"0\" z=\"0\"/>"),
+                "{}",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn amf_declares_the_units() {
+        let amf = |units| {
+            let mut bytes = Vec::new();
+            tetrahedron(units).write_amf(&mut bytes).unwrap();
+            String::from_utf8(bytes).unwrap()
+        };
+        let inches = amf(Units::Inch);
+        assert!(inches.contains("<amf unit=\"inch\""), "{}", inches);
+        assert!(inches.contains("<x>1</x><y>0</y><z>0</z>"), "{}", inches);
+        assert_eq!(inches.matches("<normal>").count(), 4);
+        assert_eq!(inches.matches("<triangle>").count(), 4);
+        let centimeters = amf(Units::Centimeter);
+        assert!(centimeters.contains("<amf unit=\"millimeter\""));
+        assert!(
+            centimeters.contains("<x>10</x><y>0</y><z>0</z>"),
+            "{}",
+            centimeters
+        );
+    }
+}
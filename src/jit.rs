@@ -0,0 +1,628 @@
+use super::Float;
+use truescad_luascad::implicit3d::{Object, PrimitiveParameters};
+use truescad_luascad::tree::Node;
+
+#[cfg(feature = "jit")]
+use cranelift_codegen::ir::condcodes::FloatCC;
+#[cfg(feature = "jit")]
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, Type, Value};
+#[cfg(feature = "jit")]
+use cranelift_codegen::settings;
+#[cfg(feature = "jit")]
+use cranelift_codegen::settings::Configurable;
+#[cfg(feature = "jit")]
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+#[cfg(feature = "jit")]
+use cranelift_jit::{JITBuilder, JITModule};
+#[cfg(feature = "jit")]
+use cranelift_module::{Linkage, Module};
+#[cfg(feature = "jit")]
+use flat_tree::FlatTree;
+#[cfg(feature = "jit")]
+use nalgebra as na;
+#[cfg(feature = "jit")]
+use std::collections::HashMap;
+#[cfg(feature = "jit")]
+use std::fmt;
+#[cfg(feature = "jit")]
+use std::sync::Arc;
+#[cfg(feature = "jit")]
+use truescad_luascad::edge::Finish;
+#[cfg(feature = "jit")]
+use truescad_luascad::implicit3d::BoundingBox;
+#[cfg(feature = "jit")]
+use truescad_luascad::optimize;
+#[cfg(feature = "jit")]
+use truescad_luascad::tree::Boolean;
+
+// The cranelift type of Float.
+#[cfg(all(feature = "jit", not(feature = "f32")))]
+const FLOAT: Type = types::F64;
+#[cfg(all(feature = "jit", feature = "f32"))]
+const FLOAT: Type = types::F32;
+
+// Replace object by a version compiled to native code, which evaluates the whole tree in a single
+// function instead of a virtual call and bounding box check per node.
+// Without the jit feature, or if tree can not be compiled (e.g. meshes), object is returned as is.
+// See try_accelerate for why it could not be compiled.
+#[cfg(feature = "jit")]
+pub fn accelerate(
+    object: Box<dyn Object<Float>>,
+    tree: Option<&Node>,
+    parameters: &PrimitiveParameters<Float>,
+) -> Box<dyn Object<Float>> {
+    match tree {
+        Some(_) => try_accelerate(object.clone(), tree, parameters).unwrap_or(object),
+        None => object,
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+pub fn accelerate(
+    object: Box<dyn Object<Float>>,
+    _: Option<&Node>,
+    _: &PrimitiveParameters<Float>,
+) -> Box<dyn Object<Float>> {
+    object
+}
+
+// Like accelerate, but Err with why tree can not be compiled instead of object.
+#[cfg(feature = "jit")]
+pub fn try_accelerate(
+    object: Box<dyn Object<Float>>,
+    tree: Option<&Node>,
+    parameters: &PrimitiveParameters<Float>,
+) -> Result<Box<dyn Object<Float>>, String> {
+    match tree {
+        Some(tree) => Ok(Box::new(JitObject::new(object, tree, parameters)?)),
+        None => Ok(object),
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+pub fn try_accelerate(
+    object: Box<dyn Object<Float>>,
+    _: Option<&Node>,
+    _: &PrimitiveParameters<Float>,
+) -> Result<Box<dyn Object<Float>>, String> {
+    Ok(object)
+}
+
+// Object using compiled code for values. The bounding box is that of the wrapped object,
+// tightened by the one of the tree (see optimize::bbox), the normals come from the gradient of the
+// flattened tree, see FlatTree::gradient.
+#[cfg(feature = "jit")]
+#[derive(Clone)]
+pub struct JitObject {
+    object: Box<dyn Object<Float>>,
+    bbox: BoundingBox<Float>,
+    function: Arc<Function>,
+    // The argument of function, see compile.
+    r_multiplier: Float,
+    normals: FlatTree,
+}
+
+#[cfg(feature = "jit")]
+impl JitObject {
+    pub fn new(
+        object: Box<dyn Object<Float>>,
+        tree: &Node,
+        parameters: &PrimitiveParameters<Float>,
+    ) -> Result<JitObject, String> {
+        Ok(JitObject {
+            bbox: object.bbox().intersection(&optimize::bbox(tree)),
+            object,
+            function: Arc::new(compile(tree)?),
+            r_multiplier: parameters.r_multiplier,
+            normals: FlatTree::new(tree, parameters.r_multiplier)?,
+        })
+    }
+}
+
+#[cfg(feature = "jit")]
+impl fmt::Debug for JitObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JitObject")
+            .field("object", &self.object)
+            .finish()
+    }
+}
+
+#[cfg(feature = "jit")]
+impl Object<Float> for JitObject {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.object.set_parameters(p);
+        self.r_multiplier = p.r_multiplier;
+        self.normals.r_multiplier = p.r_multiplier;
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, slack: Float) -> Float {
+        // Like implicit3d objects, far away the bounding box is good enough.
+        let approx = self.bbox.distance(p);
+        if approx <= slack {
+            (self.function.code)(p.x, p.y, p.z, self.r_multiplier)
+        } else {
+            approx
+        }
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.normals.normal(p)
+    }
+}
+
+#[cfg(feature = "jit")]
+struct Function {
+    // Owns the memory of code.
+    module: Option<JITModule>,
+    code: extern "C" fn(Float, Float, Float, Float) -> Float,
+}
+
+// The code is not modified after compilation.
+#[cfg(feature = "jit")]
+unsafe impl Send for Function {}
+#[cfg(feature = "jit")]
+unsafe impl Sync for Function {}
+
+#[cfg(feature = "jit")]
+impl Drop for Function {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+// Math functions not available as Cranelift instructions.
+#[cfg(feature = "jit")]
+extern "C" fn jit_exp(x: Float) -> Float {
+    x.exp()
+}
+#[cfg(feature = "jit")]
+extern "C" fn jit_ln(x: Float) -> Float {
+    x.ln()
+}
+#[cfg(feature = "jit")]
+extern "C" fn jit_sin(x: Float) -> Float {
+    x.sin()
+}
+#[cfg(feature = "jit")]
+extern "C" fn jit_cos(x: Float) -> Float {
+    x.cos()
+}
+#[cfg(feature = "jit")]
+extern "C" fn jit_atan2(y: Float, x: Float) -> Float {
+    y.atan2(x)
+}
+
+// Compile tree into a function of x, y, z and r_multiplier, with the same values as the implicit3d
+// object with that r_multiplier. It is an argument, so that new parameters need no new code.
+#[cfg(feature = "jit")]
+fn compile(tree: &Node) -> Result<Function, String> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+    let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    builder.symbol("jit_exp", jit_exp as *const u8);
+    builder.symbol("jit_ln", jit_ln as *const u8);
+    builder.symbol("jit_sin", jit_sin as *const u8);
+    builder.symbol("jit_cos", jit_cos as *const u8);
+    builder.symbol("jit_atan2", jit_atan2 as *const u8);
+    let mut module = JITModule::new(builder);
+    let mut ctx = module.make_context();
+    for _ in 0..4 {
+        ctx.func.signature.params.push(AbiParam::new(FLOAT));
+    }
+    ctx.func.signature.returns.push(AbiParam::new(FLOAT));
+    let mut function_context = FunctionBuilderContext::new();
+    {
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut function_context);
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        b.seal_block(entry);
+        let (p, r_multiplier) = {
+            let params = b.block_params(entry);
+            ([params[0], params[1], params[2]], params[3])
+        };
+        let mut compiler = Compiler {
+            b,
+            module: &mut module,
+            functions: HashMap::new(),
+            r_multiplier,
+        };
+        let value = compiler.node(tree, p)?;
+        compiler.b.ins().return_(&[value]);
+        compiler.b.finalize();
+    }
+    let id = module
+        .declare_function("sdf", Linkage::Export, &ctx.func.signature)
+        .map_err(|e| e.to_string())?;
+    module
+        .define_function(id, &mut ctx)
+        .map_err(|e| e.to_string())?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().map_err(|e| e.to_string())?;
+    let code = module.get_finalized_function(id);
+    Ok(Function {
+        module: Some(module),
+        code: unsafe {
+            ::std::mem::transmute::<*const u8, extern "C" fn(Float, Float, Float, Float) -> Float>(
+                code,
+            )
+        },
+    })
+}
+
+#[cfg(feature = "jit")]
+struct Compiler<'a> {
+    b: FunctionBuilder<'a>,
+    module: &'a mut JITModule,
+    functions: HashMap<&'static str, FuncRef>,
+    r_multiplier: Value,
+}
+
+#[cfg(feature = "jit")]
+impl<'a> Compiler<'a> {
+    #[cfg(not(feature = "f32"))]
+    fn constant(&mut self, c: Float) -> Value {
+        self.b.ins().f64const(c)
+    }
+    #[cfg(feature = "f32")]
+    fn constant(&mut self, c: Float) -> Value {
+        self.b.ins().f32const(c)
+    }
+    // Call one of the jit_ math functions above.
+    fn call(&mut self, name: &'static str, args: &[Value]) -> Result<Value, String> {
+        let function = match self.functions.get(name) {
+            Some(&f) => f,
+            None => {
+                let mut signature = self.module.make_signature();
+                for _ in args {
+                    signature.params.push(AbiParam::new(FLOAT));
+                }
+                signature.returns.push(AbiParam::new(FLOAT));
+                let id = self
+                    .module
+                    .declare_function(name, Linkage::Import, &signature)
+                    .map_err(|e| e.to_string())?;
+                let f = self.module.declare_func_in_func(id, self.b.func);
+                self.functions.insert(name, f);
+                f
+            }
+        };
+        let call = self.b.ins().call(function, args);
+        Ok(self.b.inst_results(call)[0])
+    }
+    fn length(&mut self, v: &[Value]) -> Value {
+        let mut sum = self.b.ins().fmul(v[0], v[0]);
+        for &c in &v[1..] {
+            let square = self.b.ins().fmul(c, c);
+            sum = self.b.ins().fadd(sum, square);
+        }
+        self.b.ins().sqrt(sum)
+    }
+    // row[0] * p.x + row[1] * p.y + row[2] * p.z + row[3]
+    fn affine(&mut self, row: &[Float], p: [Value; 3]) -> Value {
+        let mut sum = self.constant(row[3]);
+        for i in 0..3 {
+            let term = match row[i] {
+                0. => continue,
+                1. => p[i],
+                c => {
+                    let c = self.constant(c);
+                    self.b.ins().fmul(c, p[i])
+                }
+            };
+            sum = self.b.ins().fadd(sum, term);
+        }
+        sum
+    }
+    fn scaled(&mut self, v: Value, scale: Float) -> Value {
+        let scale = self.constant(scale);
+        self.b.ins().fmul(v, scale)
+    }
+    fn node(&mut self, node: &Node, p: [Value; 3]) -> Result<Value, String> {
+        Ok(match *node {
+            Node::Sphere { radius } => {
+                let length = self.length(&p);
+                let radius = self.constant(radius);
+                self.b.ins().fsub(length, radius)
+            }
+            Node::Cylinder { radius } => {
+                let length = self.length(&p[..2]);
+                let radius = self.constant(radius);
+                self.b.ins().fsub(length, radius)
+            }
+            Node::Cone { slope, offset } => {
+                let length = self.length(&p[..2]);
+                let radius = self.affine(&[0., 0., slope, slope * offset], p);
+                let radius = self.b.ins().fabs(radius);
+                let value = self.b.ins().fsub(length, radius);
+                self.scaled(value, 1. / (slope * slope + 1.).sqrt())
+            }
+            Node::Plane { ref normal, p: d } => self.affine(&[normal.x, normal.y, normal.z, -d], p),
+            Node::Union {
+                ref children,
+                smooth,
+            } => {
+                let values = self.children(children, p)?;
+                self.blend(&values, smooth, false)?
+            }
+            Node::Intersection {
+                ref children,
+                smooth,
+            } => {
+                let values = self.children(children, p)?;
+                self.blend(&values, smooth, true)?
+            }
+            Node::Difference {
+                ref children,
+                smooth,
+            } => {
+                let mut values = self.children(children, p)?;
+                for value in values.iter_mut().skip(1) {
+                    *value = self.b.ins().fneg(*value);
+                }
+                self.blend(&values, smooth, true)?
+            }
+            Node::Edge {
+                ref children,
+                op,
+                finish,
+            } => {
+                let values = self.children(children, p)?;
+                self.edge(values[0], values[1], op, finish)
+            }
+            Node::Sharp { ref children, op } => self.node(&op.node(children.clone(), 0.), p)?,
+            Node::LocalBlend {
+                ref children,
+                width,
+            } => {
+                // Like blend::value.
+                let values = self.children(children, p)?;
+                let weight = self.scaled(values[2], 1. / width);
+                let zero = self.constant(0.);
+                let one = self.constant(1.);
+                let weight = self.b.ins().fmax(weight, zero);
+                let weight = self.b.ins().fmin(weight, one);
+                let difference = self.b.ins().fsub(values[0], values[1]);
+                let difference = self.b.ins().fmul(difference, weight);
+                self.b.ins().fadd(values[1], difference)
+            }
+            Node::Transform {
+                ref child,
+                ref transform,
+                scale,
+            } => {
+                let mut q = p;
+                for (i, coordinate) in q.iter_mut().enumerate() {
+                    let row = transform.row(i);
+                    *coordinate = self.affine(&[row[0], row[1], row[2], row[3]], p);
+                }
+                let value = self.node(child, q)?;
+                self.scaled(value, scale)
+            }
+            Node::Twist {
+                ref child,
+                height_scaler,
+                value_scaler,
+            } => {
+                let angle = self.scaled(p[2], height_scaler);
+                let cos = self.call("jit_cos", &[angle])?;
+                let sin = self.call("jit_sin", &[angle])?;
+                let (cx, sy) = (self.b.ins().fmul(cos, p[0]), self.b.ins().fmul(sin, p[1]));
+                let (sx, cy) = (self.b.ins().fmul(sin, p[0]), self.b.ins().fmul(cos, p[1]));
+                let q = [self.b.ins().fsub(cx, sy), self.b.ins().fadd(sx, cy), p[2]];
+                let value = self.node(child, q)?;
+                self.scaled(value, value_scaler)
+            }
+            Node::Bend {
+                ref child,
+                width_scaler,
+                inner_radius,
+            } => {
+                let r = self.length(&p[..2]);
+                let minus_y = self.b.ins().fneg(p[1]);
+                let phi = self.call("jit_atan2", &[p[0], minus_y])?;
+                let q = [self.scaled(phi, width_scaler), r, p[2]];
+                let value = self.node(child, q)?;
+                let x_scaler = self.scaled(r, 1. / width_scaler);
+                let one = self.constant(1.);
+                let x_scaler = self.b.ins().fmin(x_scaler, one);
+                let value = self.b.ins().fmul(value, x_scaler);
+                if inner_radius.is_finite() {
+                    // Inside of a ring, use the distance to its inner radius.
+                    let inner_radius = self.constant(inner_radius);
+                    let center_to_bbox = self.b.ins().fsub(inner_radius, r);
+                    let zero = self.constant(0.);
+                    let inside = self
+                        .b
+                        .ins()
+                        .fcmp(FloatCC::GreaterThan, center_to_bbox, zero);
+                    self.b.ins().select(inside, center_to_bbox, value)
+                } else {
+                    value
+                }
+            }
+            Node::Component { .. } => return Err("components can not be compiled".to_string()),
+            Node::Gear { .. } => return Err("gears can not be compiled".to_string()),
+            Node::Thread { .. } => return Err("threads can not be compiled".to_string()),
+            Node::Lattice { .. } => return Err("infill can not be compiled".to_string()),
+            Node::Outline { .. } => return Err("outlines can not be compiled".to_string()),
+            Node::Morphology { .. } => {
+                return Err("smoothed objects can not be compiled".to_string())
+            }
+            Node::Offset { .. } => return Err("offsets can not be compiled".to_string()),
+            Node::PointCloud { ref filename, .. } => {
+                return Err(format!("point cloud {} can not be compiled", filename))
+            }
+            Node::Mesh { ref filename } => {
+                return Err(format!("mesh {} can not be compiled", filename))
+            }
+            Node::Plugin { ref function, .. } => {
+                return Err(format!("{} can not be compiled", function))
+            }
+            Node::Empty => self.constant(Float::INFINITY),
+            Node::Full => self.constant(Float::NEG_INFINITY),
+        })
+    }
+    // Like edge::value.
+    fn edge(&mut self, a: Value, b: Value, op: Boolean, finish: Finish) -> Value {
+        let (a, b) = match op {
+            Boolean::Union => (a, b),
+            Boolean::Intersection => (self.b.ins().fneg(a), self.b.ins().fneg(b)),
+            Boolean::Difference => (self.b.ins().fneg(a), b),
+        };
+        let min = self.b.ins().fmin(a, b);
+        let union = match finish {
+            Finish::Fillet(r) => {
+                let r = self.constant(r);
+                let zero = self.constant(0.);
+                let ua = self.b.ins().fsub(r, a);
+                let ua = self.b.ins().fmax(ua, zero);
+                let ub = self.b.ins().fsub(r, b);
+                let ub = self.b.ins().fmax(ub, zero);
+                let length = self.length(&[ua, ub]);
+                let outer = self.b.ins().fmax(r, min);
+                self.b.ins().fsub(outer, length)
+            }
+            Finish::Chamfer(sa, sb) => {
+                let n = sa.hypot(sb);
+                let line = self.scaled(a, sa / n);
+                let along_b = self.scaled(b, sb / n);
+                let line = self.b.ins().fadd(line, along_b);
+                let offset = self.constant(sa * sb / n);
+                let line = self.b.ins().fsub(line, offset);
+                self.b.ins().fmin(min, line)
+            }
+        };
+        let bound = self.scaled(union, 1. / finish.steepness());
+        let reach = self.constant(finish.reach());
+        let beyond = self.b.ins().fsub(min, reach);
+        let outside = self.b.ins().fmax(bound, beyond);
+        let inside = self.b.ins().fmin(bound, min);
+        let zero = self.constant(0.);
+        let is_outside = self.b.ins().fcmp(FloatCC::GreaterThanOrEqual, union, zero);
+        let union = self.b.ins().select(is_outside, outside, inside);
+        if op == Boolean::Union {
+            union
+        } else {
+            self.b.ins().fneg(union)
+        }
+    }
+    fn children(&mut self, children: &[Node], p: [Value; 3]) -> Result<Vec<Value>, String> {
+        children.iter().map(|c| self.node(c, p)).collect()
+    }
+    // Minimum (or maximum) of values, rounded by smooth where they are close, like rvmin and rvmax
+    // in implicit3d. The rounding is only evaluated if needed.
+    fn blend(&mut self, values: &[Value], smooth: Float, maximum: bool) -> Result<Value, String> {
+        let mut result = values[0];
+        if smooth <= 0. {
+            for &value in &values[1..] {
+                result = if maximum {
+                    self.b.ins().fmax(result, value)
+                } else {
+                    self.b.ins().fmin(result, value)
+                };
+            }
+            return Ok(result);
+        }
+        let better = if maximum {
+            FloatCC::GreaterThan
+        } else {
+            FloatCC::LessThan
+        };
+        let exact_range = self.constant(smooth);
+        let exact_range = self.b.ins().fmul(exact_range, self.r_multiplier);
+        let mut close = self.b.ins().iconst(types::I8, 0);
+        for &value in &values[1..] {
+            let is_better = self.b.ins().fcmp(better, value, result);
+            let difference = self.b.ins().fsub(value, result);
+            let difference = self.b.ins().fabs(difference);
+            let within = self
+                .b
+                .ins()
+                .fcmp(FloatCC::LessThan, difference, exact_range);
+            let close_or_within = self.b.ins().bor(close, within);
+            close = self.b.ins().select(is_better, within, close_or_within);
+            result = self.b.ins().select(is_better, value, result);
+        }
+
+        let smooth_block = self.b.create_block();
+        let done = self.b.create_block();
+        self.b.append_block_param(done, FLOAT);
+        self.b.ins().brif(close, smooth_block, &[], done, &[result]);
+        self.b.switch_to_block(smooth_block);
+        self.b.seal_block(smooth_block);
+        // Relative to result, to stay in the range of exp.
+        let r4 = smooth / 4.;
+        let r = self.constant(smooth);
+        let zero = self.constant(0.);
+        let mut sum = zero;
+        for &value in values {
+            let difference = self.b.ins().fsub(value, result);
+            let difference = self.b.ins().fabs(difference);
+            let within = self.b.ins().fcmp(FloatCC::LessThan, difference, r);
+            let exponent = self.scaled(difference, -1. / r4);
+            let term = self.call("jit_exp", &[exponent])?;
+            let term = self.b.ins().select(within, term, zero);
+            sum = self.b.ins().fadd(sum, term);
+        }
+        let ln = self.call("jit_ln", &[sum])?;
+        let rounding = self.scaled(ln, if maximum { r4 } else { -r4 });
+        let smoothed = self.b.ins().fadd(result, rounding);
+        self.b.ins().jump(done, &[smoothed]);
+        self.b.switch_to_block(done);
+        self.b.seal_block(done);
+        Ok(self.b.block_params(done)[0])
+    }
+}
+
+#[cfg(all(test, feature = "jit"))]
+mod tests {
+    use super::*;
+    use shader::tests::{assert_value, build, points, PARAMETERS, SCRIPTS};
+
+    #[test]
+    fn values_match_the_object() {
+        for script in SCRIPTS {
+            let (object, tree) = build(script);
+            let jit_object = JitObject::new(object.clone(), &tree, &PARAMETERS).unwrap();
+            for p in points(&*object) {
+                assert_value(
+                    script,
+                    &*object,
+                    &p,
+                    jit_object.approx_value(&p, Float::INFINITY),
+                );
+            }
+        }
+    }
+
+    // r_multiplier is an argument of the compiled code, which new parameters change.
+    #[test]
+    fn values_match_after_new_parameters() {
+        let parameters = PrimitiveParameters {
+            fade_range: 0.2,
+            r_multiplier: 2.,
+        };
+        for script in SCRIPTS {
+            let (mut object, tree) = build(script);
+            let mut jit_object = JitObject::new(object.clone(), &tree, &PARAMETERS).unwrap();
+            object.set_parameters(&parameters);
+            jit_object.set_parameters(&parameters);
+            for p in points(&*object) {
+                assert_value(
+                    script,
+                    &*object,
+                    &p,
+                    jit_object.approx_value(&p, Float::INFINITY),
+                );
+            }
+        }
+    }
+}
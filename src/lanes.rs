@@ -0,0 +1,121 @@
+use super::Float;
+use std::cell::RefCell;
+use std::ops::{Add, BitAnd, Div, Mul, Neg, Sub};
+#[cfg(not(feature = "f32"))]
+use wide::f64x4;
+use wide::CmpLt;
+#[cfg(feature = "f32")]
+use wide::{f32x4, f32x8};
+
+// SIMD vectors of Float the FlatTree is evaluated with (see FlatTree::value4), one point per lane.
+// The math of the wide crate works on all lanes at once; comparisons give masks, lanes with all
+// bits set where they hold, which select lanes with blend.
+pub trait Lanes:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + BitAnd<Output = Self>
+{
+    const LANES: usize;
+    fn splat(x: Float) -> Self;
+    // The first LANES values of x.
+    fn load(x: &[Float]) -> Self;
+    fn store(self, x: &mut [Float]);
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn sin_cos(self) -> (Self, Self);
+    // atan2 of self and x per lane, like Float::atan2.
+    fn atan2(self, x: Self) -> Self;
+    // The mask of the lanes where self < other.
+    fn lt(self, other: Self) -> Self;
+    // For a mask: t in its lanes, f in the others.
+    fn blend(self, t: Self, f: Self) -> Self;
+    // Whether a mask has any lane.
+    fn any(self) -> bool;
+    // Call f with per thread buffers for the points (x, y and z) and values of FlatTree nodes.
+    fn with_scratch<R, F: FnOnce(&mut Vec<[Self; 3]>, &mut Vec<Self>) -> R>(f: F) -> R;
+}
+
+macro_rules! impl_lanes {
+    ($t:ident, $n:expr, $scratch:ident) => {
+        thread_local!(static $scratch: RefCell<(Vec<[$t; 3]>, Vec<$t>)> =
+            const { RefCell::new((Vec::new(), Vec::new())) });
+
+        impl Lanes for $t {
+            const LANES: usize = $n;
+            fn splat(x: Float) -> $t {
+                $t::splat(x)
+            }
+            fn load(x: &[Float]) -> $t {
+                let mut a = [0.; $n];
+                a.copy_from_slice(&x[..$n]);
+                $t::new(a)
+            }
+            fn store(self, x: &mut [Float]) {
+                x[..$n].copy_from_slice(&self.to_array());
+            }
+            fn sqrt(self) -> $t {
+                $t::sqrt(self)
+            }
+            fn abs(self) -> $t {
+                $t::abs(self)
+            }
+            fn min(self, other: $t) -> $t {
+                $t::min(self, other)
+            }
+            fn max(self, other: $t) -> $t {
+                $t::max(self, other)
+            }
+            fn exp(self) -> $t {
+                $t::exp(self)
+            }
+            fn ln(self) -> $t {
+                $t::ln(self)
+            }
+            fn sin_cos(self) -> ($t, $t) {
+                $t::sin_cos(self)
+            }
+            fn atan2(self, x: $t) -> $t {
+                $t::atan2(self, x)
+            }
+            fn lt(self, other: $t) -> $t {
+                self.cmp_lt(other)
+            }
+            fn blend(self, t: $t, f: $t) -> $t {
+                $t::blend(self, t, f)
+            }
+            fn any(self) -> bool {
+                $t::any(self)
+            }
+            fn with_scratch<R, F: FnOnce(&mut Vec<[$t; 3]>, &mut Vec<$t>) -> R>(f: F) -> R {
+                $scratch.with(|scratch| {
+                    let (ref mut points, ref mut values) = *scratch.borrow_mut();
+                    f(points, values)
+                })
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "f32"))]
+impl_lanes!(f64x4, 4, SCRATCH_F64X4);
+#[cfg(feature = "f32")]
+impl_lanes!(f32x4, 4, SCRATCH_F32X4);
+#[cfg(feature = "f32")]
+impl_lanes!(f32x8, 8, SCRATCH_F32X8);
+
+// The vectors for FlatTree::value4 and FlatTree::value8. There are no eight f64 lanes in wide,
+// value8 evaluates two Lanes4 then.
+#[cfg(not(feature = "f32"))]
+pub type Lanes4 = f64x4;
+#[cfg(feature = "f32")]
+pub type Lanes4 = f32x4;
+#[cfg(feature = "f32")]
+pub type Lanes8 = f32x8;
@@ -7,10 +7,28 @@
 
 extern crate alga;
 extern crate cairo;
+#[cfg(feature = "jit")]
+extern crate cranelift_codegen;
+#[cfg(feature = "jit")]
+extern crate cranelift_frontend;
+#[cfg(feature = "jit")]
+extern crate cranelift_jit;
+#[cfg(feature = "jit")]
+extern crate cranelift_module;
+#[cfg(feature = "jit")]
+extern crate cranelift_native;
 extern crate dirs;
 extern crate gdk;
+extern crate gif;
+#[cfg(feature = "gpu")]
+extern crate gl;
 extern crate gtk;
+extern crate image;
 extern crate kiss3d;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "gpu")]
+extern crate libloading;
 extern crate nalgebra;
 extern crate nalgebra as na;
 extern crate num_traits;
@@ -18,19 +36,59 @@ extern crate rayon;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 extern crate kiss3ddeps;
 extern crate sourceview;
 extern crate stl_io;
 extern crate tessellation;
 extern crate toml;
 extern crate truescad_luascad;
+extern crate wide;
+extern crate zip;
 
+pub mod adhesion;
+pub mod animation;
+pub mod cli;
+pub mod completion;
+pub mod console;
+pub mod customizer;
+pub mod drawing;
 pub mod editor;
+pub mod export_dialog;
+pub mod features;
+pub mod field_check;
+pub mod flat_tree;
+pub mod gizmo;
+pub mod golden;
+pub mod gpu;
+pub mod hermite;
+pub mod indexed_mesh;
+pub mod jit;
+pub mod lanes;
+pub mod lsp;
 pub mod menu;
+pub mod mesh_boolean;
 pub mod mesh_view;
 pub mod object_widget;
+pub mod overlay;
+pub mod preview;
+pub mod project;
+pub mod properties;
+pub mod reference;
 pub mod render;
+pub mod server;
 pub mod settings;
+pub mod shader;
+pub mod tree_panel;
+pub mod watch;
 pub mod window;
+pub mod worker;
+
+// See truescad_luascad::Float.
+#[cfg(not(feature = "f32"))]
+use std::f64 as float;
+#[cfg(feature = "f32")]
+use std::f32 as float;
 
-type Float = f64;
+type Float = truescad_luascad::Float;
@@ -0,0 +1,319 @@
+use completion;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use truescad_luascad;
+use truescad_luascad::api::{self, Function};
+use truescad_luascad::include::Includes;
+use truescad_luascad::printbuffer::Level;
+use truescad_luascad::ScriptError;
+
+// Kinds of completion items and severities of diagnostics, as numbered by the protocol.
+const FUNCTION_ITEM: u32 = 3;
+const METHOD_ITEM: u32 = 2;
+const VARIABLE_ITEM: u32 = 6;
+const ERROR_SEVERITY: u32 = 1;
+const WARNING_SEVERITY: u32 = 2;
+// Error code of requests the server does not know.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// A language server for scripts, speaking the Language Server Protocol on input and output
+// (see truescad-lsp), so that other editors show what the GUI shows: the errors and warnings of
+// evaluating the script at time 0 with the defaults of its parameters, completion of the
+// functions of truescad_luascad::api and their documentation on hover. Documents are synced in
+// full. Positions count characters, not UTF-16 code units, which is the same for ASCII scripts.
+// Profile prints to the standard output, which carries the protocol, so scripts using it confuse
+// the client.
+pub struct Server {
+    // The text of the open documents by URI.
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+    // Serve the messages from input until the client exits.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: &mut R, output: &mut W) -> io::Result<()> {
+        while let Some(message) = read_message(input)? {
+            let method = message["method"].as_str().unwrap_or("").to_string();
+            if method == "exit" {
+                break;
+            }
+            let params = &message["params"];
+            let result = match method.as_str() {
+                "initialize" => Some(capabilities()),
+                "shutdown" => {
+                    self.shutdown = true;
+                    Some(Value::Null)
+                }
+                "textDocument/completion" => Some(self.completion(params)),
+                "textDocument/hover" => Some(self.hover(params)),
+                "textDocument/didOpen" => {
+                    let document = &params["textDocument"];
+                    self.update(output, document, &document["text"])?;
+                    None
+                }
+                // The last change has the full text.
+                "textDocument/didChange" => {
+                    let changes = params["contentChanges"].as_array();
+                    let text = changes.and_then(|c| c.last()).map(|c| &c["text"]);
+                    if let Some(text) = text {
+                        self.update(output, &params["textDocument"], text)?;
+                    }
+                    None
+                }
+                "textDocument/didClose" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    self.documents.remove(uri);
+                    publish(output, uri, Vec::new())?;
+                    None
+                }
+                _ => None,
+            };
+            // Notifications have no id and get no response.
+            let id = match message.get("id") {
+                Some(id) => id.clone(),
+                None => continue,
+            };
+            let response = match result {
+                Some(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unknown method {}", method),
+                    },
+                }),
+            };
+            write_message(output, &response)?;
+        }
+        Ok(())
+    }
+    // Whether the client asked the server to shut down before it exited.
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown
+    }
+    // Keep the new text of the document and publish its diagnostics.
+    fn update<W: Write>(
+        &mut self,
+        output: &mut W,
+        document: &Value,
+        text: &Value,
+    ) -> io::Result<()> {
+        let (uri, text) = match (document["uri"].as_str(), text.as_str()) {
+            (Some(uri), Some(text)) => (uri.to_string(), text.to_string()),
+            _ => return Ok(()),
+        };
+        let diagnostics = diagnostics(&uri, &text);
+        self.documents.insert(uri.clone(), text);
+        publish(output, &uri, diagnostics)
+    }
+    // The line of the document in params up to the position in params, and the rest of it.
+    fn line(&self, params: &Value) -> Option<(String, String)> {
+        let text = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?;
+        let position = &params["position"];
+        let line = text.lines().nth(position["line"].as_u64()? as usize)?;
+        let character = position["character"].as_u64()? as usize;
+        let split = line
+            .char_indices()
+            .nth(character)
+            .map_or(line.len(), |(i, _)| i);
+        Some((line[..split].to_string(), line[split..].to_string()))
+    }
+    fn completion(&self, params: &Value) -> Value {
+        let (before, _) = match self.line(params) {
+            Some(line) => line,
+            None => return json!([]),
+        };
+        let (start, prefix) = completion::last_name(&before);
+        let method = before[..start].ends_with(':');
+        Value::Array(
+            api::complete(prefix, method)
+                .into_iter()
+                .map(|f| {
+                    json!({
+                        "label": f.short_name(),
+                        "kind": item_kind(f),
+                        "detail": f.signature(),
+                        "documentation": f.doc,
+                    })
+                })
+                .collect(),
+        )
+    }
+    fn hover(&self, params: &Value) -> Value {
+        let (before, after) = match self.line(params) {
+            Some(line) => line,
+            None => return Value::Null,
+        };
+        let (start, head) = completion::last_name(&before);
+        let tail: String = after
+            .chars()
+            .take_while(|&c| c.is_ascii_alphanumeric() || c == '_')
+            .collect();
+        let name = format!("{}{}", head, tail);
+        let method = before[..start].ends_with(':');
+        match api::find(&name, method).or_else(|| api::find(&name, !method)) {
+            Some(f) => json!({
+                "contents": {
+                    "kind": "markdown",
+                    "value": format!("```lua\n{}\n```\n{}", f.signature(), f.doc),
+                },
+            }),
+            None => Value::Null,
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 1,
+            "completionProvider": {"triggerCharacters": [":"]},
+            "hoverProvider": true,
+        },
+        "serverInfo": {"name": "truescad-lsp"},
+    })
+}
+
+fn item_kind(f: &Function) -> u32 {
+    if f.args.is_none() {
+        VARIABLE_ITEM
+    } else if f.is_method() {
+        METHOD_ITEM
+    } else {
+        FUNCTION_ITEM
+    }
+}
+
+// The errors and warnings of evaluating text, the script at uri, with the scripts next to it to
+// include.
+fn diagnostics(uri: &str, text: &str) -> Vec<Value> {
+    let includes = match path_of(uri) {
+        Some(path) => Includes::beside(&path),
+        None => Includes::default(),
+    };
+    match truescad_luascad::evaluate_in(text, 0., &HashMap::new(), &includes) {
+        Ok(evaluation) => evaluation
+            .messages
+            .iter()
+            .filter_map(|m| {
+                let severity = match m.level {
+                    Level::Error => ERROR_SEVERITY,
+                    Level::Warning => WARNING_SEVERITY,
+                    _ => return None,
+                };
+                Some(diagnostic(m.line, severity, &m.text))
+            })
+            .collect(),
+        Err(e) => {
+            let e = ScriptError::from(&e);
+            vec![diagnostic(e.line, ERROR_SEVERITY, &e.message)]
+        }
+    }
+}
+
+// A diagnostic of the whole line (starting at 1), or of the first one without a line.
+fn diagnostic(line: Option<usize>, severity: u32, message: &str) -> Value {
+    let line = line.map_or(0, |l| l.saturating_sub(1));
+    json!({
+        "range": {
+            "start": {"line": line, "character": 0},
+            "end": {"line": line + 1, "character": 0},
+        },
+        "severity": severity,
+        "source": "truescad",
+        "message": message.trim_end(),
+    })
+}
+
+fn publish<W: Write>(output: &mut W, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+    write_message(
+        output,
+        &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }),
+    )
+}
+
+// The path of a file URI.
+fn path_of(uri: &str) -> Option<String> {
+    let path = uri.trim_start_matches("file://");
+    if path.len() == uri.len() {
+        return None;
+    }
+    // Percent-encoded bytes, e.g. %20 for spaces.
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|h| ::std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (b, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// The next message, None at the end of input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no Content-Length",
+            ))
+        }
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
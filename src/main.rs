@@ -1,7 +1,32 @@
 extern crate gtk;
 extern crate truescad;
 
+use truescad::cli;
+use truescad::settings::SettingsData;
+
 fn main() {
+    let args: Vec<String> = ::std::env::args().skip(1).collect();
+    let options = match cli::Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n{}", e, cli::USAGE);
+            ::std::process::exit(1);
+        }
+    };
+
+    for e in cli::load_plugins() {
+        println!("{}", e);
+    }
+    SettingsData::default().set_up_converter();
+
+    if options.headless() || options.list_api || options.golden || options.listens() {
+        if let Err(e) = cli::run(&options) {
+            println!("{}", e);
+            ::std::process::exit(1);
+        }
+        return;
+    }
+
     if gtk::init().is_err() {
         println!("Failed to initialize GTK.");
         return;
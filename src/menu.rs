@@ -1,39 +1,84 @@
 extern crate gtk;
 
 use gtk::traits::*;
+use settings;
+use std::rc::Rc;
 
+// One action for each item of the menu, so many of them.
+#[allow(clippy::too_many_arguments)]
 pub fn create_menu<
     FT: Fn() + 'static,
     FO: Fn() + 'static,
+    FR: Fn(&str) + 'static,
     FS: Fn() + 'static,
     FSA: Fn() + 'static,
     FSET: Fn() + 'static,
     FEX: Fn() + 'static,
+    FEP: Fn() + 'static,
     FQ: Fn() + 'static,
+    FW: Fn(bool) + 'static,
+    FREF: Fn() + 'static,
+    FSV: Fn() + 'static,
+    FVL: Fn() -> Vec<String> + 'static,
+    FV: Fn(&str) + 'static,
 >(
     tessellate_action: FT,
     open_action: FO,
+    open_recent_action: FR,
     save_action: FS,
     save_as_action: FSA,
     settings_action: FSET,
     export_action: FEX,
+    export_parts_action: FEP,
     quit_action: FQ,
+    watch_action: FW,
+    reference_action: FREF,
+    save_view_action: FSV,
+    views: FVL,
+    view_action: FV,
 ) -> gtk::MenuBar {
     let menu_bar = gtk::MenuBar::new();
     let file = gtk::MenuItem::new_with_mnemonic("_File");
     let f_menu = gtk::Menu::new();
     let f_new = gtk::MenuItem::new_with_mnemonic("_New");
     let f_open = gtk::MenuItem::new_with_mnemonic("_Open");
+    let f_recent = gtk::MenuItem::new_with_mnemonic("Open _recent");
+    let r_menu = gtk::Menu::new();
+    f_recent.set_submenu(Some(&r_menu));
     let f_save = gtk::MenuItem::new_with_mnemonic("_Save");
     let f_save_as = gtk::MenuItem::new_with_mnemonic("Save _as");
     let f_tessellate = gtk::MenuItem::new_with_mnemonic("_Tessellate");
     let f_export_stl = gtk::MenuItem::new_with_mnemonic("_Export STL");
+    let f_export_parts = gtk::MenuItem::new_with_mnemonic("Export split _parts");
+    f_export_parts.set_tooltip_text("one STL file for each connected part");
+    let f_watch = gtk::CheckMenuItem::new_with_mnemonic("_Watch file");
+    f_watch.set_tooltip_text("reload the script when it is changed by another editor");
     let f_settings = gtk::MenuItem::new_with_mnemonic("_Settings");
     let f_quit = gtk::MenuItem::new_with_mnemonic("_Quit");
 
     f_open.connect_activate(move |_| {
         open_action();
     });
+    // The recent files change with every file opened or saved, so they are listed again each
+    // time the file menu opens.
+    let open_recent_action = Rc::new(open_recent_action);
+    let f_recent_clone = f_recent.clone();
+    file.connect_activate(move |_| {
+        for item in r_menu.get_children() {
+            r_menu.remove(&item);
+        }
+        let recent_files = settings::SettingsData::default().recent_files;
+        f_recent_clone.set_sensitive(!recent_files.is_empty());
+        for path in recent_files {
+            let item = gtk::MenuItem::new_with_label(&path);
+            let open_recent_action = open_recent_action.clone();
+            item.connect_activate(move |_| {
+                open_recent_action(&path);
+            });
+            r_menu.append(&item);
+        }
+        r_menu.show_all();
+    });
     f_save.connect_activate(move |_| {
         save_action();
     });
@@ -46,27 +91,76 @@ pub fn create_menu<
     f_export_stl.connect_activate(move |_| {
         export_action();
     });
+    f_export_parts.connect_activate(move |_| {
+        export_parts_action();
+    });
+    f_watch.connect_toggled(move |item| {
+        watch_action(item.get_active());
+    });
     f_settings.connect_activate(move |_| {
         settings_action();
     });
     f_quit.connect_activate(move |_| {
         quit_action();
     });
+    let view = gtk::MenuItem::new_with_mnemonic("_View");
+    let v_menu = gtk::Menu::new();
+    let v_save = gtk::MenuItem::new_with_mnemonic("_Save view");
+    v_save.set_tooltip_text("remember the camera by name, saved with the project");
+    v_save.connect_activate(move |_| {
+        save_view_action();
+    });
+    let v_views = gtk::MenuItem::new_with_mnemonic("_Views");
+    let vs_menu = gtk::Menu::new();
+    v_views.set_submenu(Some(&vs_menu));
+    // Like the recent files, the views are listed again each time the menu opens.
+    let view_action = Rc::new(view_action);
+    let v_views_clone = v_views.clone();
+    view.connect_activate(move |_| {
+        for item in vs_menu.get_children() {
+            vs_menu.remove(&item);
+        }
+        let names = views();
+        v_views_clone.set_sensitive(!names.is_empty());
+        for name in names {
+            let item = gtk::MenuItem::new_with_label(&name);
+            let view_action = view_action.clone();
+            item.connect_activate(move |_| {
+                view_action(&name);
+            });
+            vs_menu.append(&item);
+        }
+        vs_menu.show_all();
+    });
     let help = gtk::MenuItem::new_with_mnemonic("_Help");
     let h_menu = gtk::Menu::new();
+    let h_reference = gtk::MenuItem::new_with_mnemonic("Lua _reference");
+    h_reference.set_tooltip_text("search the functions scripts can use");
+    h_reference.connect_activate(move |_| {
+        reference_action();
+    });
     let h_about = gtk::MenuItem::new_with_mnemonic("A_bout");
 
     f_menu.append(&f_new);
     f_menu.append(&f_open);
+    f_menu.append(&f_recent);
     f_menu.append(&f_save);
     f_menu.append(&f_save_as);
     f_menu.append(&f_tessellate);
     f_menu.append(&f_export_stl);
+    f_menu.append(&f_export_parts);
+    f_menu.append(&f_watch);
     f_menu.append(&f_settings);
     f_menu.append(&f_quit);
     file.set_submenu(Some(&f_menu));
     menu_bar.append(&file);
 
+    v_menu.append(&v_save);
+    v_menu.append(&v_views);
+    view.set_submenu(Some(&v_menu));
+    menu_bar.append(&view);
+
+    h_menu.append(&h_reference);
     h_menu.append(&h_about);
     help.set_submenu(Some(&h_menu));
     menu_bar.append(&help);
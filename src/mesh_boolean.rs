@@ -0,0 +1,182 @@
+use super::Float;
+use editor;
+use flat_tree::FlatTree;
+use indexed_mesh::{self, Edge, IndexedMesh};
+use na;
+use std::collections::HashMap;
+use truescad_luascad::implicit3d::{BoundingBox, Object, PrimitiveParameters};
+use truescad_luascad::normals::NormalEstimate;
+use truescad_luascad::tree::{Boolean, Node};
+use truescad_luascad::units::Units;
+
+// Booleans of meshes, for Node::Sharp: the children are tessellated one by one, the faces of
+// each are cut where they cross the surfaces of the others, and kept where they are on the
+// surface of the result. Tessellating the boolean rounds the seams between the children to the
+// resolution, the meshes keep them as sharp as they are. The surfaces are cut exactly where the
+// values of the other children vanish, so the cuts of two children meet along their seam, but do
+// not share vertices there.
+
+// Points where faces are cut are found to 2^-BISECTIONS of the length of their edges.
+const BISECTIONS: usize = 12;
+
+// A child of a sharp boolean, evaluated with its FlatTree in the box it is tessellated in.
+#[derive(Clone, Debug)]
+struct Operand {
+    tree: FlatTree,
+    bbox: BoundingBox<Float>,
+}
+
+impl Object<Float> for Operand {
+    fn bbox(&self) -> &BoundingBox<Float> {
+        &self.bbox
+    }
+    fn set_parameters(&mut self, p: &PrimitiveParameters<Float>) {
+        self.tree.r_multiplier = p.r_multiplier;
+    }
+    fn approx_value(&self, p: &na::Point3<Float>, _: Float) -> Float {
+        self.tree.value(p)
+    }
+    fn normal(&self, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        self.tree.normal(p)
+    }
+}
+
+// The mesh of the boolean op of children within bbox, the box of the whole object. None if a
+// child can not be flattened (see FlatTree) or tessellated, e.g. because it is infinite. The
+// warnings of tessellating the children are added to warnings.
+#[allow(clippy::too_many_arguments)]
+pub fn tessellate(
+    children: &[Node],
+    op: Boolean,
+    bbox: &BoundingBox<Float>,
+    units: Units,
+    resolution: Float,
+    error: Float,
+    parameters: &PrimitiveParameters<Float>,
+    normals: NormalEstimate,
+    warnings: &mut Vec<String>,
+) -> Option<IndexedMesh> {
+    if op == Boolean::Intersection {
+        return None;
+    }
+    // Children reaching beyond the object, e.g. half-spaces subtracted from it, are only
+    // tessellated around it, the open borders of their meshes are left out with the faces
+    // outside of it.
+    let mut region = bbox.clone();
+    region.dilate(resolution * 2.);
+    let mut operands = Vec::new();
+    for child in children {
+        operands.push(Operand {
+            tree: FlatTree::new(child, parameters.r_multiplier).ok()?,
+            bbox: child.bbox().intersection(&region),
+        });
+    }
+    let mut result = IndexedMesh {
+        units,
+        ..IndexedMesh::default()
+    };
+    for (i, (child, operand)) in children.iter().zip(&operands).enumerate() {
+        let mesh = editor::tessellate_with_parameters(
+            Box::new(operand.clone()),
+            Some(child),
+            units,
+            resolution,
+            error,
+            parameters,
+            normals,
+            warnings,
+        )?;
+        // The faces of the others subtracted from the first are turned inside out.
+        let inverted = op == Boolean::Difference && i > 0;
+        // Positive where the surface of this child is on the surface of the result.
+        let keep = |p: &na::Point3<Float>| {
+            operands
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(j, other)| {
+                    let value = other.tree.value(p);
+                    if inverted && j == 0 {
+                        -value
+                    } else {
+                        value
+                    }
+                })
+                .fold(Float::INFINITY, Float::min)
+        };
+        let mut kept = cut(&mesh, keep);
+        let sign = if inverted { -1. } else { 1. };
+        kept.normals = kept
+            .vertices
+            .iter()
+            .map(|v| operand.tree.normal(v) * sign)
+            .collect();
+        if inverted {
+            for face in &mut kept.faces {
+                face.swap(1, 2);
+            }
+        }
+        result.append(&kept);
+    }
+    Some(result)
+}
+
+// The parts of the faces of mesh where keep is positive. Faces crossing its zero set are cut
+// there into triangles, whose new vertices are shared with the neighbor across the cut edge.
+fn cut<F: Fn(&na::Point3<Float>) -> Float>(mesh: &IndexedMesh, keep: F) -> IndexedMesh {
+    let values: Vec<Float> = mesh.vertices.iter().map(&keep).collect();
+    let mut result = IndexedMesh {
+        units: mesh.units,
+        ..IndexedMesh::default()
+    };
+    // The vertices of result of those of mesh, and of the cuts of its edges.
+    let mut vertices: HashMap<usize, usize> = HashMap::new();
+    let mut cuts: HashMap<Edge, usize> = HashMap::new();
+    for face in &mesh.faces {
+        let inside = |v: usize| values[v] > 0.;
+        if !face.iter().any(|&v| inside(v)) {
+            continue;
+        }
+        let mut polygon = Vec::with_capacity(4);
+        for k in 0..3 {
+            let (a, b) = (face[k], face[(k + 1) % 3]);
+            if inside(a) {
+                polygon.push(*vertices.entry(a).or_insert_with(|| {
+                    result.vertices.push(mesh.vertices[a]);
+                    result.vertices.len() - 1
+                }));
+            }
+            if inside(a) != inside(b) {
+                polygon.push(*cuts.entry(indexed_mesh::edge(a, b)).or_insert_with(|| {
+                    let (p, q) = if inside(a) { (a, b) } else { (b, a) };
+                    result
+                        .vertices
+                        .push(crossing(&keep, &mesh.vertices[p], &mesh.vertices[q]));
+                    result.vertices.len() - 1
+                }));
+            }
+        }
+        for k in 1..polygon.len() - 1 {
+            result.faces.push([polygon[0], polygon[k], polygon[k + 1]]);
+        }
+    }
+    result
+}
+
+// Where keep changes sign between p, where it is positive, and q, by bisection.
+fn crossing<F: Fn(&na::Point3<Float>) -> Float>(
+    keep: &F,
+    p: &na::Point3<Float>,
+    q: &na::Point3<Float>,
+) -> na::Point3<Float> {
+    let (mut low, mut high) = (0., 1.);
+    for _ in 0..BISECTIONS {
+        let middle = (low + high) / 2.;
+        if keep(&(p + (q - p) * middle)) > 0. {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    p + (q - p) * ((low + high) / 2.)
+}
@@ -1,12 +1,10 @@
-use super::Float;
+use indexed_mesh::IndexedMesh;
 use kiss3d::light::Light;
 use kiss3d::window::Window;
 use kiss3ddeps;
 use std::cell::RefCell;
-use std::mem;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex, Once, ONCE_INIT};
-use tessellation::Mesh;
+use std::sync::{Arc, Mutex, Once};
 
 #[derive(Clone)]
 struct SingletonWindow {
@@ -18,17 +16,19 @@ struct SingletonWindow {
 fn singleton_window() -> SingletonWindow {
     // Initialize it to a null value
     static mut SINGLETON: *const SingletonWindow = 0 as *const SingletonWindow;
-    static ONCE: Once = ONCE_INIT;
+    static ONCE: Once = Once::new();
 
     unsafe {
         ONCE.call_once(|| {
             // Make it
+            // The window is only used from the GTK thread, so the Arc need not be Send.
+            #[allow(clippy::arc_with_non_send_sync)]
             let window = SingletonWindow {
                 inner: Arc::new(Mutex::new(Window::new("MeshView"))),
             };
 
             // Put it in the heap so it can outlive this call
-            SINGLETON = mem::transmute(Box::new(window));
+            SINGLETON = Box::into_raw(Box::new(window));
         });
 
         // Now we give out a copy of the data that is safe to use concurrently.
@@ -36,7 +36,7 @@ fn singleton_window() -> SingletonWindow {
     }
 }
 
-pub fn show_mesh(mesh: &Mesh<Float>) {
+pub fn show_mesh(mesh: &IndexedMesh) {
     let window_mutex = singleton_window();
     let mut window = window_mutex.inner.lock().unwrap();
     window.glfw_window_mut().set_should_close(false);
@@ -55,8 +55,12 @@ pub fn show_mesh(mesh: &Mesh<Float>) {
     window.hide();
 }
 
-fn tessellation_to_kiss3d_mesh(mesh: &Mesh<Float>) -> Rc<RefCell<::kiss3d::resource::Mesh>> {
+// kiss3d takes f32, which Float is too with the f32 feature, so the casts are only necessary
+// without it.
+#[allow(clippy::unnecessary_cast)]
+fn tessellation_to_kiss3d_mesh(mesh: &IndexedMesh) -> Rc<RefCell<::kiss3d::resource::Mesh>> {
     let mut na_verts = Vec::new();
+    let mut na_normals = Vec::new();
     let mut na_faces = Vec::new();
     for face in &mesh.faces {
         let i = na_verts.len();
@@ -67,14 +71,16 @@ fn tessellation_to_kiss3d_mesh(mesh: &Mesh<Float>) -> Rc<RefCell<::kiss3d::resou
         ));
         for index in face.iter() {
             let p = &mesh.vertices[*index];
-            na_verts.push(kiss3ddeps::Point3::new(
-                p[0] as f32,
-                p[1] as f32,
-                p[2] as f32,
-            ));
+            na_verts.push(kiss3ddeps::Point3::new(p.x as f32, p.y as f32, p.z as f32));
+            let n = &mesh.normals[*index];
+            na_normals.push(kiss3ddeps::Vector3::new(n.x as f32, n.y as f32, n.z as f32));
         }
     }
     Rc::new(RefCell::new(::kiss3d::resource::Mesh::new(
-        na_verts, na_faces, None, None, true,
+        na_verts,
+        na_faces,
+        Some(na_normals),
+        None,
+        true,
     )))
 }
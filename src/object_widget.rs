@@ -2,36 +2,226 @@ use super::Float;
 use cairo::{Context, Format, ImageSurface};
 use gtk::traits::*;
 use gtk::DrawingArea;
-use gtk::Inhibit;
+use gtk::{Continue, Inhibit};
+use gpu;
+use na;
 use render;
+use settings;
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use truescad_luascad::units::Units;
+use worker::{self, Worker};
 
+// Zoom factor for one step of the scroll wheel.
+const ZOOM_STEP: Float = 1.1;
+
+// After the camera moved, frames are first rendered with this pixel stride and then refined,
+// unless the camera moves on.
+const PROGRESSIVE_START_STRIDE: usize = 4;
+
+struct Progressive {
+    enabled: Cell<bool>,
+    // The stride to start rendering a changed image with.
+    stride: Cell<usize>,
+}
+
+impl Progressive {
+    fn camera_moved(&self) {
+        if self.enabled.get() {
+            self.stride.set(PROGRESSIVE_START_STRIDE);
+        }
+    }
+}
+
+// An image rendered by the render worker, or on the GPU.
+struct Frame {
+    buf: Vec<u8>,
+    width: i32,
+    height: i32,
+    stride: usize,
+    // Renderer::version it shows.
+    version: u64,
+}
+
+struct RenderJob {
+    renderer: render::Renderer,
+    width: i32,
+    height: i32,
+    stride: usize,
+}
+
+fn render(job: RenderJob, cancel: &AtomicBool) -> Option<Frame> {
+    let mut buf = vec![0; (job.width * job.height * 4) as usize];
+    job.renderer
+        .draw_on_buf_progressive(&mut buf, job.width, job.height, job.stride, cancel)?;
+    Some(Frame {
+        buf,
+        width: job.width,
+        height: job.height,
+        stride: job.stride,
+        version: job.renderer.version(),
+    })
+}
+
+// The function connect_gizmo_dragged set, if any.
+type GizmoDragged = Rc<RefCell<Option<Rc<dyn Fn(usize, usize, Float)>>>>;
+
+// The function connect_gpu_failed set, if any.
+type GpuFailed = Rc<RefCell<Option<Rc<dyn Fn(String)>>>>;
+
+// The version, size and stride of a render job.
+type Request = (u64, i32, i32, usize);
+
+// The view of the object. It is rendered on a thread of its own, showing the previous image until
+// the new one is done.
+#[derive(Clone)]
 pub struct ObjectWidget {
+    // The drawing area, with a badge on top while the object is rebuilt or tessellated.
+    pub widget: ::gtk::Overlay,
     pub drawing_area: DrawingArea,
+    badge: ::gtk::Label,
     pub renderer: Rc<RefCell<::render::Renderer>>,
     mouse_pos: Rc<Cell<(f64, f64)>>,
+    progressive: Rc<Progressive>,
+    // The first point of a measurement, picked with Ctrl+click. The next one gives the distance.
+    measure_start: Rc<Cell<Option<na::Point3<Float>>>>,
+    // Units of the object, for measurements.
+    pub units: Rc<Cell<Units>>,
+    // The gizmo and handle being dragged, see Renderer::pick_gizmo.
+    dragged: Rc<Cell<Option<(usize, usize)>>>,
+    gizmo_dragged: GizmoDragged,
+    // The latest rendered image, and the version, size and stride of the latest render job.
+    frame: Rc<RefCell<Option<Frame>>>,
+    requested: Rc<Cell<Option<Request>>>,
+    render_worker: Rc<Worker<RenderJob, Option<Frame>>>,
+    // Renders the frames instead of the render worker where Renderer::gpu_scene allows it.
+    gpu: Rc<RefCell<gpu::Device>>,
+    gpu_failed: GpuFailed,
 }
 
 impl ObjectWidget {
     pub fn new() -> ObjectWidget {
+        let widget = ::gtk::Overlay::new();
+        let drawing_area = DrawingArea::new();
+        widget.add(&drawing_area);
+        let badge = ::gtk::Label::new(None);
+        badge.set_halign(::gtk::Align::End);
+        badge.set_valign(::gtk::Align::Start);
+        badge.set_margin_top(5);
+        badge.set_margin_end(5);
+        badge.set_no_show_all(true);
+        widget.add_overlay(&badge);
         let xw = ObjectWidget {
-            drawing_area: DrawingArea::new(),
+            widget,
+            drawing_area,
+            badge,
             renderer: Rc::new(RefCell::new(render::Renderer::new())),
             mouse_pos: Rc::new(Cell::new((0., 0.))),
+            progressive: Rc::new(Progressive {
+                enabled: Cell::new(false),
+                stride: Cell::new(1),
+            }),
+            measure_start: Rc::new(Cell::new(None)),
+            units: Rc::new(Cell::new(Units::default())),
+            dragged: Rc::new(Cell::new(None)),
+            gizmo_dragged: Rc::new(RefCell::new(None)),
+            frame: Rc::new(RefCell::new(None)),
+            requested: Rc::new(Cell::new(None)),
+            render_worker: Rc::new(Worker::new("render", render)),
+            gpu: Rc::new(RefCell::new(gpu::Device::new())),
+            gpu_failed: Rc::new(RefCell::new(None)),
         };
         {
             let renderer_clone = xw.renderer.clone();
+            let progressive_clone = xw.progressive.clone();
+            let frame = xw.frame.clone();
+            let requested = xw.requested.clone();
+            let render_worker = xw.render_worker.clone();
+            let gpu = xw.gpu.clone();
+            let gpu_failed = xw.gpu_failed.clone();
             xw.drawing_area
-                .connect_draw(move |_: &DrawingArea, cr: &Context| {
-                    let (clip_x1, clip_y1, clip_x2, clip_y2) = cr.clip_extents();
-                    let (width, height) = (clip_x2 - clip_x1, clip_y2 - clip_y1);
-                    let image = draw_on_image(&renderer_clone, width as i32, height as i32);
-                    cr.set_source_surface(&image, 0., 0.);
-                    cr.paint();
+                .connect_draw(move |da: &DrawingArea, cr: &Context| {
+                    let (width, height) = (da.get_allocated_width(), da.get_allocated_height());
+                    let version = renderer_clone.borrow().version();
+                    let current = frame.borrow().as_ref().is_some_and(|f| {
+                        (f.version, f.width, f.height, f.stride) == (version, width, height, 1)
+                    });
+                    if !current {
+                        let renderer = renderer_clone.borrow();
+                        let image = renderer.gpu_scene(width, height).and_then(|scene| {
+                            gpu.borrow_mut()
+                                .render(da.get_window(), &scene, width, height)
+                                .unwrap_or_else(|e| {
+                                    if let Some(ref f) = *gpu_failed.borrow() {
+                                        f(e);
+                                    }
+                                    None
+                                })
+                        });
+                        if let Some((mut buf, depth)) = image {
+                            renderer.draw_overlays_on_buf(&mut buf, &depth, width, height);
+                            // The render worker has nothing left to do for this version.
+                            requested.set(Some((version, width, height, 1)));
+                            *frame.borrow_mut() = Some(Frame {
+                                buf,
+                                width,
+                                height,
+                                stride: 1,
+                                version,
+                            });
+                        }
+                    }
+                    // Show the latest image until a new one is done, even if it is out of date.
+                    let stride = match *frame.borrow() {
+                        Some(ref f) => {
+                            paint(cr, f);
+                            if f.version != version || f.width != width || f.height != height {
+                                Some(progressive_clone.stride.get())
+                            } else if f.stride > 1 {
+                                Some(f.stride / 2)
+                            } else {
+                                None
+                            }
+                        }
+                        None => Some(progressive_clone.stride.get()),
+                    };
+                    if let Some(stride) = stride {
+                        let key = (version, width, height, stride);
+                        if requested.get() != Some(key) {
+                            requested.set(Some(key));
+                            render_worker.submit(RenderJob {
+                                renderer: renderer_clone.borrow().clone(),
+                                width,
+                                height,
+                                stride,
+                            });
+                        }
+                    }
                     Inhibit(false)
                 });
         }
+        {
+            let progressive = xw.progressive.clone();
+            let frame = xw.frame.clone();
+            let render_worker = xw.render_worker.clone();
+            let da = xw.drawing_area.clone();
+            ::gtk::timeout_add(worker::POLL_INTERVAL, move || {
+                if let Some(Some(f)) = render_worker.poll() {
+                    // A job may finish after the GPU rendered a later version.
+                    if frame.borrow().as_ref().is_some_and(|g| g.version > f.version) {
+                        return Continue(true);
+                    }
+                    // Refined to full resolution, changes of the object start there as well.
+                    if f.stride == 1 {
+                        progressive.stride.set(1);
+                    }
+                    *frame.borrow_mut() = Some(f);
+                    da.queue_draw();
+                }
+                Continue(true)
+            });
+        }
         xw.drawing_area
             .add_events(::gdk::ModifierType::BUTTON1_MASK.bits() as i32);
         xw.drawing_area
@@ -39,10 +229,18 @@ impl ObjectWidget {
         xw.drawing_area
             .add_events(::gdk::ModifierType::BUTTON3_MASK.bits() as i32);
         xw.drawing_area.add_events(1 << 4);
+        xw.drawing_area
+            .add_events(::gdk::EventMask::BUTTON_RELEASE_MASK.bits() as i32);
+        xw.drawing_area.add_events(
+            (::gdk::EventMask::SCROLL_MASK | ::gdk::EventMask::SMOOTH_SCROLL_MASK).bits() as i32,
+        );
 
         {
             let mouse_pos_clone = xw.mouse_pos.clone();
             let renderer_clone = xw.renderer.clone();
+            let progressive_clone = xw.progressive.clone();
+            let dragged = xw.dragged.clone();
+            let gizmo_dragged = xw.gizmo_dragged.clone();
             xw.drawing_area.connect_motion_notify_event(
                 move |da: &DrawingArea, em: &::gdk::EventMotion| -> Inhibit {
                     let da_alloc = da.get_allocation();
@@ -54,12 +252,31 @@ impl ObjectWidget {
                     );
                     mouse_pos_clone.set(em.get_position());
                     match em.get_state() {
+                        x if ::gdk::ModifierType::BUTTON1_MASK.intersects(x)
+                            && dragged.get().is_some() =>
+                        {
+                            let (gizmo, handle) = dragged.get().unwrap();
+                            let delta = renderer_clone.borrow().drag_gizmo(
+                                gizmo,
+                                handle,
+                                (ox as Float, oy as Float),
+                                (nx as Float, ny as Float),
+                                da_alloc.width,
+                                da_alloc.height,
+                            );
+                            let f = gizmo_dragged.borrow().clone();
+                            if let (Some(f), true) = (f, delta != 0.) {
+                                f(gizmo, handle, delta);
+                            }
+                        }
                         x if ::gdk::ModifierType::BUTTON1_MASK.intersects(x) => {
                             renderer_clone.borrow_mut().rotate_from_screen(dx, dy);
+                            progressive_clone.camera_moved();
                             da.queue_draw();
                         }
                         x if ::gdk::ModifierType::BUTTON3_MASK.intersects(x) => {
                             renderer_clone.borrow_mut().translate_from_screen(dx, dy);
+                            progressive_clone.camera_moved();
                             da.queue_draw();
                         }
                         _ => println!("unkown {:?}: {:?} {:?}", em.get_state(), dx, dy),
@@ -68,17 +285,148 @@ impl ObjectWidget {
                 },
             );
         }
+        {
+            let renderer_clone = xw.renderer.clone();
+            let progressive_clone = xw.progressive.clone();
+            xw.drawing_area.connect_scroll_event(
+                move |da: &DrawingArea, es: &::gdk::EventScroll| -> Inhibit {
+                    let steps = match es.get_direction() {
+                        ::gdk::ScrollDirection::Up => -1.,
+                        ::gdk::ScrollDirection::Down => 1.,
+                        ::gdk::ScrollDirection::Smooth => es.get_delta().1,
+                        _ => 0.,
+                    };
+                    if steps != 0. {
+                        renderer_clone.borrow_mut().zoom(ZOOM_STEP.powf(steps as Float));
+                        progressive_clone.camera_moved();
+                        da.queue_draw();
+                    }
+                    Inhibit(true)
+                },
+            );
+        }
         {
             let mouse_pos_clone = xw.mouse_pos.clone();
+            let renderer_clone = xw.renderer.clone();
+            let measure_start = xw.measure_start.clone();
+            let units = xw.units.clone();
+            let dragged = xw.dragged.clone();
             xw.drawing_area.connect_button_press_event(
-                move |_: &DrawingArea, eb: &::gdk::EventButton| -> Inhibit {
+                move |da: &DrawingArea, eb: &::gdk::EventButton| -> Inhibit {
                     mouse_pos_clone.set(eb.get_position());
+                    dragged.set(None);
+                    if eb.get_button() == 1
+                        && !eb.get_state().contains(::gdk::ModifierType::CONTROL_MASK)
+                    {
+                        // Dragging a gizmo instead of rotating the view.
+                        let da_alloc = da.get_allocation();
+                        let (x, y) = eb.get_position();
+                        dragged.set(renderer_clone.borrow().pick_gizmo(
+                            x as Float,
+                            y as Float,
+                            da_alloc.width,
+                            da_alloc.height,
+                        ));
+                    }
+                    if eb.get_button() == 1
+                        && eb.get_state().contains(::gdk::ModifierType::CONTROL_MASK)
+                    {
+                        let da_alloc = da.get_allocation();
+                        let (x, y) = eb.get_position();
+                        let mut renderer = renderer_clone.borrow_mut();
+                        let picked = renderer.pick(
+                            x as Float,
+                            y as Float,
+                            da_alloc.width,
+                            da_alloc.height,
+                        );
+                        // Clicking next to the object cancels the measurement.
+                        let text = match (measure_start.get(), picked) {
+                            (_, None) => {
+                                measure_start.set(None);
+                                renderer.set_measurement(None);
+                                None
+                            }
+                            (None, Some(a)) => {
+                                measure_start.set(Some(a));
+                                renderer.set_measurement(Some((a, a)));
+                                Some("Ctrl+click a second point to measure".to_string())
+                            }
+                            (Some(a), Some(b)) => {
+                                measure_start.set(None);
+                                renderer.set_measurement(Some((a, b)));
+                                Some(format!(
+                                    "distance: {:.3} {}",
+                                    na::distance(&a, &b),
+                                    units.get()
+                                ))
+                            }
+                        };
+                        drop(renderer);
+                        da.set_tooltip_text(text.as_deref());
+                        da.queue_draw();
+                    }
                     Inhibit(false)
                 },
             );
         }
+        {
+            let dragged = xw.dragged.clone();
+            xw.drawing_area.connect_button_release_event(move |_, _| {
+                dragged.set(None);
+                Inhibit(false)
+            });
+        }
+        xw.apply_settings(&settings::SettingsData::default());
         xw
     }
+
+    // Call f with why the GPU failed to render a frame, which the CPU renders instead, see
+    // gpu::Device::render.
+    pub fn connect_gpu_failed<F: Fn(String) + 'static>(&self, f: F) {
+        *self.gpu_failed.borrow_mut() = Some(Rc::new(f));
+    }
+
+    // Call f with the gizmo, its handle and the change of its value while a gizmo is dragged,
+    // see Renderer::drag_gizmo.
+    pub fn connect_gizmo_dragged<F: Fn(usize, usize, Float) + 'static>(&self, f: F) {
+        *self.gizmo_dragged.borrow_mut() = Some(Rc::new(f));
+    }
+
+    // Show the badge with text, e.g. while the object is rebuilt, or hide it.
+    pub fn set_badge(&self, text: Option<&str>) {
+        match text {
+            Some(text) => {
+                self.badge.set_markup(&format!(
+                    "<span background=\"#ffcc00\" foreground=\"black\"> {} </span>",
+                    text
+                ));
+                self.badge.show();
+            }
+            None => self.badge.hide(),
+        }
+    }
+
+    // Move the camera to a view saved with Renderer::camera_state.
+    pub fn set_camera_state(&self, state: &render::CameraState) {
+        self.renderer.borrow_mut().set_camera_state(state);
+        self.progressive.camera_moved();
+        self.drawing_area.queue_draw();
+    }
+
+    pub fn apply_settings(&self, s: &settings::SettingsData) {
+        self.progressive.enabled.set(s.progressive);
+        if !s.progressive {
+            self.progressive.stride.set(1);
+        }
+        {
+            let mut renderer = self.renderer.borrow_mut();
+            let mut render_settings = renderer.settings().clone();
+            s.apply_to(&mut render_settings);
+            renderer.set_settings(render_settings);
+        }
+        self.drawing_area.queue_draw();
+    }
 }
 
 impl Default for ObjectWidget {
@@ -87,14 +435,13 @@ impl Default for ObjectWidget {
     }
 }
 
-fn draw_on_image(
-    renderer: &Rc<RefCell<render::Renderer>>,
-    width: i32,
-    height: i32,
-) -> ImageSurface {
-    let size: usize = (width * height * 4) as usize;
-    let mut buf = vec![0; size].into_boxed_slice();
-    renderer.borrow().draw_on_buf(&mut *buf, width, height);
-    let image2 = ImageSurface::create_for_data(buf, Format::Rgb24, width, height, width * 4);
-    image2.unwrap()
+fn paint(cr: &Context, frame: &Frame) {
+    let buf = frame.buf.clone().into_boxed_slice();
+    let stride = frame.width * 4;
+    if let Ok(image) =
+        ImageSurface::create_for_data(buf, Format::Rgb24, frame.width, frame.height, stride)
+    {
+        cr.set_source_surface(&image, 0., 0.);
+        cr.paint();
+    }
 }
@@ -0,0 +1,142 @@
+use super::Float;
+use indexed_mesh::IndexedMesh;
+use nalgebra as na;
+use render::Color;
+use truescad_luascad::implicit3d::BoundingBox;
+
+// A line segment in object space, drawn on top of the rendered image.
+#[derive(Copy, Clone, Debug)]
+pub struct Line {
+    pub a: na::Point3<Float>,
+    pub b: na::Point3<Float>,
+    pub color: Color,
+}
+
+// All edges of mesh, each one once.
+pub fn mesh_edges(mesh: &IndexedMesh, color: Color) -> Vec<Line> {
+    let mut edges: Vec<_> = mesh.edge_faces().keys().cloned().collect();
+    edges.sort();
+    edges
+        .iter()
+        .map(|&(a, b)| Line {
+            a: mesh.vertices[a],
+            b: mesh.vertices[b],
+            color,
+        })
+        .collect()
+}
+
+// Draw line into buf (4 bytes per pixel, BGRx).
+// project maps object space to pixel x, y and the distance from the camera (z), or None if the
+// point is behind the camera. Where depth is not empty, only pixels no further than tolerance
+// behind the depth buffer are drawn.
+pub fn draw_line<F>(
+    line: &Line,
+    project: &F,
+    buf: &mut [u8],
+    depth: &[Float],
+    width: usize,
+    height: usize,
+    tolerance: Float,
+) where
+    F: Fn(&na::Point3<Float>) -> Option<na::Point3<Float>>,
+{
+    let (a, b) = match (project(&line.a), project(&line.b)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return,
+    };
+    let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.);
+    // Limit the work for lines running far outside of the image.
+    if steps > 4. * (width + height) as Float {
+        return;
+    }
+    let to_u8 = |c: Float| (c.clamp(0., 1.) * 255.) as u8;
+    for i in 0..=steps as usize {
+        let p = a + (b - a) * (i as Float / steps);
+        let (x, y) = (p.x.round(), p.y.round());
+        if x < 0. || y < 0. || x >= width as Float || y >= height as Float {
+            continue;
+        }
+        let index = y as usize * width + x as usize;
+        if !depth.is_empty() && p.z > depth[index] + tolerance {
+            continue;
+        }
+        buf[index * 4] = to_u8(line.color.z);
+        buf[index * 4 + 1] = to_u8(line.color.y);
+        buf[index * 4 + 2] = to_u8(line.color.x);
+    }
+}
+
+// X, Y and Z axes from the origin, in red, green and blue.
+pub fn axes(length: Float) -> Vec<Line> {
+    let origin = na::Point3::origin();
+    (0..3)
+        .map(|i| {
+            let mut dir = na::Vector3::zeros();
+            dir[i] = length;
+            let mut color = Color::zeros();
+            color[i] = 1.;
+            Line {
+                a: origin,
+                b: origin + dir,
+                color,
+            }
+        })
+        .collect()
+}
+
+// The twelve edges of bbox.
+pub fn bbox_edges(bbox: &BoundingBox<Float>, color: Color) -> Vec<Line> {
+    let corner = |i: usize| {
+        na::Point3::new(
+            if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+            if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+            if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+        )
+    };
+    let mut lines = Vec::new();
+    for i in 0..8 {
+        for bit in &[1, 2, 4] {
+            if i & bit == 0 {
+                lines.push(Line {
+                    a: corner(i),
+                    b: corner(i | bit),
+                    color,
+                });
+            }
+        }
+    }
+    lines
+}
+
+// Grid in the plane below bbox (Z is up), with a power of ten spacing giving about ten lines
+// across the object.
+pub fn ground_grid(bbox: &BoundingBox<Float>, color: Color) -> Vec<Line> {
+    let dim = bbox.dim();
+    let extent = dim.x.max(dim.y);
+    if !extent.is_finite() || extent <= 0. {
+        return Vec::new();
+    }
+    let spacing = Float::powf(10., extent.log10().floor() - 1.);
+    let snap = |v: Float| (v / spacing).round() as i64;
+    let (x0, x1) = (snap(bbox.min.x) - 1, snap(bbox.max.x) + 1);
+    let (y0, y1) = (snap(bbox.min.y) - 1, snap(bbox.max.y) + 1);
+    let z = bbox.min.z;
+    let to_coord = |i: i64| i as Float * spacing;
+    let mut lines = Vec::new();
+    for x in x0..=x1 {
+        lines.push(Line {
+            a: na::Point3::new(to_coord(x), to_coord(y0), z),
+            b: na::Point3::new(to_coord(x), to_coord(y1), z),
+            color,
+        });
+    }
+    for y in y0..=y1 {
+        lines.push(Line {
+            a: na::Point3::new(to_coord(x0), to_coord(y), z),
+            b: na::Point3::new(to_coord(x1), to_coord(y), z),
+            color,
+        });
+    }
+    lines
+}
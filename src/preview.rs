@@ -0,0 +1,344 @@
+use super::Float;
+use flat_tree;
+use image::{self, ImageFormat};
+use jit;
+use render::{Renderer, ViewPreset};
+use serde_json::{self, Value};
+use settings;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::thread;
+use truescad_luascad;
+use truescad_luascad::implicit3d;
+use truescad_luascad::include::Includes;
+use truescad_luascad::printbuffer::Level;
+use truescad_luascad::ScriptError;
+
+// A live preview for external editors (see truescad preview): an editor plugin pushes the text
+// of the script whenever it changes and gets back its errors and an image of its object, so
+// that scripts can be written in any editor with the renderer next to it.
+//
+// Each line a client sends is a JSON request, each with only the fields it changes:
+//
+//     {"script": "build(Sphere(1))", "path": "/home/me/part.lua", "time": 0,
+//      "width": 400, "height": 300, "view": "front", "rotate": [0.1, 0], "zoom": 0.5}
+//
+// The script is evaluated again if it is sent, with the scripts next to path to include, and
+// the view is kept between requests. Each request is answered with a JSON line
+//
+//     {"errors": [{"line": 1, "message": "..."}], "warnings": [...], "output": "...",
+//      "image": 1234}
+//
+// followed by that many bytes of a PNG image, none if the script has no object. Images are at
+// most MAX_SIZE pixels wide and high. Clients are trusted like the GUI, so listen on a local
+// address or socket.
+
+const DEFAULT_SIZE: (u32, u32) = (400, 300);
+const MAX_SIZE: u32 = 2048;
+
+// The script of a client and how it is shown.
+pub struct Session {
+    renderer: Renderer,
+    // Whether the last evaluation built an object.
+    object: bool,
+    size: (u32, u32),
+    // What the last evaluation printed, and its errors and warnings.
+    output: String,
+    errors: Vec<Value>,
+    warnings: Vec<Value>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            renderer: Renderer::new(),
+            object: false,
+            size: DEFAULT_SIZE,
+            output: String::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+    // Answer the requests from input until it ends.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: &mut R, output: &mut W) -> io::Result<()> {
+        let mut line = String::new();
+        while input.read_line(&mut line)? > 0 {
+            if !line.trim().is_empty() {
+                let (head, image) = match serde_json::from_str(&line) {
+                    Ok(request) => self.answer(&request),
+                    Err(e) => (json!({ "errors": [problem(None, &e.to_string())] }), None),
+                };
+                write_answer(output, head, image.as_ref().map_or(&[], |i| &i[..]))?;
+            }
+            line.clear();
+        }
+        Ok(())
+    }
+    // The answer to request, and the image if there is an object.
+    fn answer(&mut self, request: &Value) -> (Value, Option<Vec<u8>>) {
+        if let Some(script) = request["script"].as_str() {
+            let time = request["time"].as_f64().unwrap_or(0.) as Float;
+            self.evaluate(script, request["path"].as_str(), time);
+        }
+        if let (Some(width), Some(height)) = (request["width"].as_u64(), request["height"].as_u64())
+        {
+            match (u32::try_from(width), u32::try_from(height)) {
+                (Ok(w), Ok(h)) if w > 0 && h > 0 && w <= MAX_SIZE && h <= MAX_SIZE => {
+                    self.size = (w, h)
+                }
+                _ => {
+                    let message = format!(
+                        "invalid size {}x{}, expected at most {}x{}",
+                        width, height, MAX_SIZE, MAX_SIZE
+                    );
+                    return (json!({ "errors": [problem(None, &message)] }), None);
+                }
+            }
+        }
+        if let Some(view) = request["view"].as_str() {
+            match view_preset(view) {
+                Some(preset) => self.renderer.set_view_preset(preset),
+                None => {
+                    let message = format!("unknown view {}", view);
+                    return (json!({ "errors": [problem(None, &message)] }), None);
+                }
+            }
+        }
+        if let Some(rotate) = request["rotate"].as_array() {
+            let angle = |i: usize| rotate.get(i).and_then(Value::as_f64).unwrap_or(0.) as Float;
+            self.renderer.rotate_from_screen(angle(0), angle(1));
+        }
+        if let Some(zoom) = request["zoom"].as_f64() {
+            self.renderer.zoom(zoom as Float);
+        }
+        let image = if self.errors.is_empty() {
+            self.render()
+        } else {
+            None
+        };
+        let head = json!({
+            "errors": self.errors,
+            "warnings": self.warnings,
+            "output": self.output,
+        });
+        (head, image)
+    }
+    fn evaluate(&mut self, script: &str, path: Option<&str>, time: Float) {
+        let includes = path.map_or_else(Includes::default, Includes::beside);
+        self.errors.clear();
+        self.warnings.clear();
+        let evaluation =
+            match truescad_luascad::evaluate_in(script, time, &HashMap::new(), &includes) {
+                Ok(evaluation) => evaluation,
+                Err(e) => {
+                    let e = ScriptError::from(&e);
+                    self.errors.push(problem(e.line, &e.message));
+                    self.output.clear();
+                    self.object = false;
+                    self.renderer.set_object(None);
+                    return;
+                }
+            };
+        for m in &evaluation.messages {
+            match m.level {
+                Level::Error => self.errors.push(problem(m.line, &m.text)),
+                Level::Warning => self.warnings.push(problem(m.line, &m.text)),
+                _ => {}
+            }
+        }
+        self.output = evaluation.output;
+        let s = settings::SettingsData::default();
+        let tree = s.optimize(evaluation.tree);
+        let parameters = implicit3d::PrimitiveParameters {
+            fade_range: s.fade_range,
+            r_multiplier: s.r_multiplier,
+        };
+        let object = evaluation.object.map(|mut object| {
+            object.set_parameters(&parameters);
+            jit::accelerate(object, tree.as_ref(), &parameters)
+        });
+        self.object = object.is_some();
+        self.renderer.set_object(object);
+        self.renderer
+            .set_flat_tree(flat_tree::for_rendering(tree.as_ref(), &parameters));
+    }
+    // The object as a PNG image, None if there is no object.
+    fn render(&self) -> Option<Vec<u8>> {
+        if !self.object {
+            return None;
+        }
+        let mut render_settings = self.renderer.settings().clone();
+        settings::SettingsData::default().apply_to(&mut render_settings);
+        let (width, height) = self.size;
+        let image = self
+            .renderer
+            .render_to_image(width, height, &render_settings);
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(image)
+            .save(&mut png, ImageFormat::PNG)
+            .ok()?;
+        Some(png)
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// An error or warning at line (starting at 1), if it is known.
+fn problem(line: Option<usize>, message: &str) -> Value {
+    json!({ "line": line, "message": message.trim_end() })
+}
+
+fn view_preset(name: &str) -> Option<ViewPreset> {
+    Some(match name {
+        "front" => ViewPreset::Front,
+        "back" => ViewPreset::Back,
+        "top" => ViewPreset::Top,
+        "bottom" => ViewPreset::Bottom,
+        "left" => ViewPreset::Left,
+        "right" => ViewPreset::Right,
+        "isometric" => ViewPreset::Isometric,
+        _ => return None,
+    })
+}
+
+fn write_answer<W: Write>(output: &mut W, mut head: Value, image: &[u8]) -> io::Result<()> {
+    head["image"] = json!(image.len());
+    writeln!(output, "{}", head)?;
+    output.write_all(image)?;
+    output.flush()
+}
+
+// Answer the clients connecting to address, a host:port, or on Unix the path of a socket, each
+// with its own Session, until the process is killed.
+pub fn listen(address: &str) -> Result<(), String> {
+    let error = |e: io::Error| format!("could not listen on {}: {}", address, e);
+    #[cfg(unix)]
+    {
+        if !address.contains(':') {
+            // A socket left over by an earlier preview is replaced, anything else at the path
+            // is left alone.
+            match ::std::fs::symlink_metadata(address) {
+                Ok(ref m) if m.file_type().is_socket() => {
+                    ::std::fs::remove_file(address).map_err(error)?
+                }
+                Ok(_) => return Err(format!("could not listen on {}: address in use", address)),
+                Err(_) => {}
+            }
+            let listener = UnixListener::bind(address).map_err(error)?;
+            println!("previewing on {}", address);
+            for stream in listener.incoming() {
+                match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok((reader, writer)) => serve(reader, writer),
+                    Err(e) => eprintln!("could not accept a connection: {}", e),
+                }
+            }
+            return Ok(());
+        }
+    }
+    let listener = TcpListener::bind(address).map_err(error)?;
+    println!("previewing on {}", address);
+    for stream in listener.incoming() {
+        match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+            Ok((reader, writer)) => serve(reader, writer),
+            Err(e) => eprintln!("could not accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+// Run a Session for a client in a thread of its own.
+fn serve<R: Read + Send + 'static, W: Write + Send + 'static>(reader: R, mut writer: W) {
+    thread::spawn(move || {
+        let mut session = Session::new();
+        if let Err(e) = session.run(&mut BufReader::new(reader), &mut writer) {
+            eprintln!("connection failed: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImage;
+    use std::io::Cursor;
+
+    // The answers of a Session to the lines of input, with their images.
+    fn answers(input: &str) -> Vec<(Value, Vec<u8>)> {
+        let mut output = Vec::new();
+        Session::new()
+            .run(&mut Cursor::new(input), &mut output)
+            .unwrap();
+        let mut output = Cursor::new(output);
+        let mut answers = Vec::new();
+        let mut line = String::new();
+        while output.read_line(&mut line).unwrap() > 0 {
+            let head: Value = serde_json::from_str(&line).unwrap();
+            let mut image = vec![0; head["image"].as_u64().unwrap() as usize];
+            output.read_exact(&mut image).unwrap();
+            answers.push((head, image));
+            line.clear();
+        }
+        answers
+    }
+
+    // The width and height of a PNG image.
+    fn png_size(png: &[u8]) -> (u32, u32) {
+        image::load_from_memory_with_format(png, ImageFormat::PNG)
+            .unwrap()
+            .dimensions()
+    }
+
+    #[test]
+    fn objects_are_rendered() {
+        let answers = answers(
+            "{\"script\": \"print(1) build(Sphere(1))\", \"width\": 40, \"height\": 30}\n\
+             {\"view\": \"top\", \"zoom\": 0.5}\n",
+        );
+        assert_eq!(answers.len(), 2);
+        for (head, image) in &answers {
+            assert_eq!(head["errors"], json!([]));
+            assert!(head["output"].as_str().unwrap().ends_with("1\n"));
+            assert_eq!(png_size(image), (40, 30));
+        }
+    }
+
+    #[test]
+    fn errors_have_their_lines() {
+        let (ref head, ref image) = answers("{\"script\": \"\\nbuild(Sphere(\"}\n")[0];
+        assert_eq!(head["errors"][0]["line"], 2);
+        assert!(image.is_empty());
+        let invalid = answers("not json\n{\"view\": \"sideways\"}\n");
+        assert_eq!(invalid.len(), 2);
+        for (head, image) in &invalid {
+            assert_eq!(head["errors"].as_array().unwrap().len(), 1);
+            assert!(image.is_empty());
+        }
+    }
+
+    #[test]
+    fn sizes_are_limited() {
+        let answers = answers(
+            "{\"script\": \"build(Sphere(1))\", \"width\": 40, \"height\": 30}\n\
+             {\"width\": 4294967336, \"height\": 30}\n\
+             {\"width\": 4000, \"height\": 3000}\n\
+             {\"width\": 0, \"height\": 30}\n\
+             {}\n",
+        );
+        for (head, image) in &answers[1..4] {
+            let message = head["errors"][0]["message"].as_str().unwrap();
+            assert!(message.starts_with("invalid size"), "{}", message);
+            assert!(image.is_empty());
+        }
+        assert_eq!(png_size(&answers[4].1), (40, 30));
+    }
+}
@@ -0,0 +1,199 @@
+use super::Float;
+use export_dialog::ExportOptions;
+use render::CameraState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Write};
+use truescad_luascad::include::Includes;
+use truescad_luascad::params::Value;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+// Extension of project files.
+pub const EXTENSION: &str = ".tscad";
+// Name of the manifest in a project file.
+const MANIFEST: &str = "project.toml";
+// Name of the main script of new projects.
+const DEFAULT_MAIN: &str = "main.lua";
+
+// What project.toml says about the other files of a project.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    main: String,
+    #[serde(default)]
+    parameters: BTreeMap<String, ::toml::Value>,
+    #[serde(default)]
+    profiles: BTreeMap<String, ExportOptions>,
+    #[serde(default)]
+    views: BTreeMap<String, CameraState>,
+}
+
+// A script bundled with the scripts it includes, the values of its parameters, named export
+// profiles and named views, so it can be passed on as one file. A project file is a zip file of
+//
+//   project.toml  the name of the main script, the parameter values, the profiles and the
+//                 views (see CameraState):
+//                   main = "main.lua"
+//                   [parameters]
+//                   width = 20.0
+//                   [profiles.fine]
+//                   resolution = 0.05
+//                   error = 1.0
+//                   [views.detail]
+//                   transform = [[1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], ...]
+//                   distance = 40.0
+//   main.lua      the main script
+//   gears.lua     the scripts it includes, see truescad_luascad::include
+#[derive(Clone, Debug)]
+pub struct Project {
+    // The name of the main script in the project file.
+    pub main: String,
+    pub script: String,
+    // The included scripts by name.
+    pub includes: HashMap<String, String>,
+    // The values of the parameters of the script, see CustomizerPanel::values.
+    pub parameters: HashMap<String, Value>,
+    pub profiles: BTreeMap<String, ExportOptions>,
+    // Cameras saved in the GUI by name, e.g. to render the same images for documentation.
+    pub views: BTreeMap<String, CameraState>,
+}
+
+impl Default for Project {
+    fn default() -> Project {
+        Project {
+            main: DEFAULT_MAIN.to_string(),
+            script: String::new(),
+            includes: HashMap::new(),
+            parameters: HashMap::new(),
+            profiles: BTreeMap::new(),
+            views: BTreeMap::new(),
+        }
+    }
+}
+
+// Whether path is a project file, rather than a script.
+pub fn is_project(path: &str) -> bool {
+    path.ends_with(EXTENSION)
+}
+
+fn failed<E: Display>(what: &str, path: &str, e: E) -> String {
+    format!("could not {} {}: {}", what, path, e)
+}
+
+impl Project {
+    pub fn open(path: &str) -> Result<Project, String> {
+        let file = File::open(path).map_err(|e| failed("open", path, e))?;
+        let mut archive = ZipArchive::new(file).map_err(|e| failed("read", path, e))?;
+        let mut files = HashMap::new();
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).map_err(|e| failed("read", path, e))?;
+            let mut text = String::new();
+            entry
+                .read_to_string(&mut text)
+                .map_err(|e| failed("read", path, e))?;
+            files.insert(entry.name().to_string(), text);
+        }
+        let manifest: Manifest = match files.remove(MANIFEST) {
+            Some(text) => ::toml::from_str(&text).map_err(|e| failed("read", path, e))?,
+            None => return Err(format!("{} has no {}", path, MANIFEST)),
+        };
+        let script = files
+            .remove(&manifest.main)
+            .ok_or_else(|| format!("{} has no main script {}", path, manifest.main))?;
+        Ok(Project {
+            main: manifest.main,
+            script,
+            includes: files,
+            parameters: manifest
+                .parameters
+                .iter()
+                .filter_map(|(name, value)| from_toml(value).map(|v| (name.clone(), v)))
+                .collect(),
+            profiles: manifest.profiles,
+            views: manifest.views,
+        })
+    }
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let manifest = Manifest {
+            main: self.main.clone(),
+            parameters: self
+                .parameters
+                .iter()
+                .map(|(name, value)| (name.clone(), to_toml(value)))
+                .collect(),
+            profiles: self.profiles.clone(),
+            views: self.views.clone(),
+        };
+        let manifest = ::toml::to_string(&manifest).map_err(|e| failed("write", path, e))?;
+        let mut includes: Vec<_> = self.includes.iter().collect();
+        includes.sort();
+        let file = File::create(path).map_err(|e| failed("create", path, e))?;
+        let mut zip = ZipWriter::new(file);
+        let entries = vec![(MANIFEST, &manifest), (&*self.main, &self.script)];
+        for (name, text) in entries
+            .into_iter()
+            .chain(includes.into_iter().map(|(n, t)| (&**n, t)))
+        {
+            zip.start_file(name, FileOptions::default())
+                .map_err(|e| failed("write", path, e))?;
+            zip.write_all(text.as_bytes())
+                .map_err(|e| failed("write", path, e))?;
+        }
+        zip.finish().map_err(|e| failed("write", path, e))?;
+        Ok(())
+    }
+    // Where the script finds what it includes.
+    pub fn includes(&self) -> Includes {
+        Includes {
+            files: self.includes.clone(),
+            dir: None,
+            untrusted: false,
+        }
+    }
+    // The export profile called name.
+    pub fn profile(&self, name: &str) -> Result<ExportOptions, String> {
+        match self.profiles.get(name) {
+            Some(profile) => Ok(*profile),
+            None => Err(unknown("profile", name, &self.profiles)),
+        }
+    }
+    // The view called name.
+    pub fn view(&self, name: &str) -> Result<CameraState, String> {
+        match self.views.get(name) {
+            Some(view) => Ok(*view),
+            None => Err(unknown("view", name, &self.views)),
+        }
+    }
+}
+
+fn unknown<V>(what: &str, name: &str, known: &BTreeMap<String, V>) -> String {
+    format!(
+        "unknown {} {}, the project has {}",
+        what,
+        name,
+        if known.is_empty() {
+            "none".to_string()
+        } else {
+            known.keys().cloned().collect::<Vec<_>>().join(", ")
+        }
+    )
+}
+
+fn to_toml(value: &Value) -> ::toml::Value {
+    match *value {
+        Value::Number(x) => ::toml::Value::Float(x.into()),
+        Value::Bool(b) => ::toml::Value::Boolean(b),
+        Value::Choice(ref s) => ::toml::Value::String(s.clone()),
+    }
+}
+
+fn from_toml(value: &::toml::Value) -> Option<Value> {
+    match *value {
+        ::toml::Value::Float(x) => Some(Value::Number(x as Float)),
+        ::toml::Value::Integer(x) => Some(Value::Number(x as Float)),
+        ::toml::Value::Boolean(b) => Some(Value::Bool(b)),
+        ::toml::Value::String(ref s) => Some(Value::Choice(s.clone())),
+        _ => None,
+    }
+}
@@ -0,0 +1,82 @@
+use super::Float;
+use gtk::traits::*;
+use truescad_luascad::analysis::MassProperties;
+use truescad_luascad::units::Units;
+
+// Panel below the view showing the mass properties of the object, computed on request since
+// the integration can take a while for complex objects.
+#[derive(Clone)]
+pub struct PropertiesPanel {
+    pub widget: ::gtk::Expander,
+    pub compute: ::gtk::Button,
+    density: ::gtk::SpinButton,
+    label: ::gtk::Label,
+}
+
+impl PropertiesPanel {
+    pub fn new() -> PropertiesPanel {
+        let widget = ::gtk::Expander::new(Some("Mass properties"));
+        let v_box = ::gtk::Box::new(::gtk::Orientation::Vertical, 0);
+        let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
+        let density_label = ::gtk::Label::new(Some("density"));
+        let density = ::gtk::SpinButton::new_with_range(0.0001, 100000., 0.01);
+        density.set_digits(4);
+        density.set_value(1.);
+        density.set_tooltip_text("mass per cubic unit of the script");
+        let compute = ::gtk::Button::new_with_label("Compute");
+        h_box.pack_start(&density_label, false, false, 5);
+        h_box.pack_start(&density, false, false, 5);
+        h_box.pack_start(&compute, false, false, 5);
+        let label = ::gtk::Label::new(None);
+        label.set_selectable(true);
+        label.set_halign(::gtk::Align::Start);
+        v_box.pack_start(&h_box, false, false, 0);
+        v_box.pack_start(&label, false, false, 5);
+        widget.add(&v_box);
+        PropertiesPanel {
+            widget,
+            compute,
+            density,
+            label,
+        }
+    }
+    pub fn density(&self) -> Float {
+        self.density.get_value() as Float
+    }
+    // Show m, or clear the panel for None.
+    pub fn show(&self, m: Option<&MassProperties>, units: Units) {
+        let text = match m {
+            Some(m) => {
+                let i = &m.inertia;
+                format!(
+                    "volume: {:.4} {u}³\nmass: {:.4}\ncentroid: ({:.4}, {:.4}, {:.4}) {u}\n\
+                     inertia about the centroid (mass {u}²):\n\
+                     {:.4}  {:.4}  {:.4}\n{:.4}  {:.4}  {:.4}\n{:.4}  {:.4}  {:.4}",
+                    m.volume,
+                    m.mass,
+                    m.centroid.x,
+                    m.centroid.y,
+                    m.centroid.z,
+                    i[(0, 0)],
+                    i[(0, 1)],
+                    i[(0, 2)],
+                    i[(1, 0)],
+                    i[(1, 1)],
+                    i[(1, 2)],
+                    i[(2, 0)],
+                    i[(2, 1)],
+                    i[(2, 2)],
+                    u = units
+                )
+            }
+            None => String::new(),
+        };
+        self.label.set_text(&text);
+    }
+}
+
+impl Default for PropertiesPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,115 @@
+use gtk::traits::*;
+use gtk::TreeStoreExtManual;
+use std::cell::RefCell;
+use std::rc::Rc;
+use truescad_luascad::api;
+
+// Columns of the store.
+const SIGNATURE: u32 = 0;
+const DOC: u32 = 1;
+
+// The insert callback, if any.
+type Insert = Rc<RefCell<Option<Rc<dyn Fn(&str)>>>>;
+
+// Panel listing the functions scripts can use by topic, see truescad_luascad::api. Only the
+// functions matching the search are listed. Activating a function calls the insert callback with
+// its signature, e.g. to insert it into the editor.
+#[derive(Clone)]
+pub struct ReferencePanel {
+    pub widget: ::gtk::Box,
+    store: ::gtk::TreeStore,
+    view: ::gtk::TreeView,
+    search: ::gtk::SearchEntry,
+    insert: Insert,
+}
+
+impl ReferencePanel {
+    pub fn new() -> ReferencePanel {
+        let widget = ::gtk::Box::new(::gtk::Orientation::Vertical, 0);
+        let search = ::gtk::SearchEntry::new();
+        search.set_tooltip_text("only list functions containing all of these words");
+        let store = ::gtk::TreeStore::new(&[::gtk::Type::String, ::gtk::Type::String]);
+        let view = ::gtk::TreeView::new_with_model(&store);
+        view.set_tooltip_text("activate a function to insert it into the script");
+        for &(title, column) in &[("function", SIGNATURE), ("description", DOC)] {
+            let cell = ::gtk::CellRendererText::new();
+            let view_column = ::gtk::TreeViewColumn::new();
+            view_column.set_title(title);
+            view_column.pack_start(&cell, true);
+            view_column.add_attribute(&cell, "text", column as i32);
+            view.append_column(&view_column);
+        }
+        let scrolled_window = ::gtk::ScrolledWindow::new(None, None);
+        scrolled_window.add(&view);
+        widget.pack_start(&search, false, false, 0);
+        widget.pack_start(&scrolled_window, true, true, 0);
+        let panel = ReferencePanel {
+            widget,
+            store,
+            view,
+            search,
+            insert: Rc::new(RefCell::new(None)),
+        };
+        panel.list("");
+        {
+            let p = panel.clone();
+            panel.search.connect_search_changed(move |search| {
+                p.list(&search.get_text().unwrap_or_default());
+            });
+        }
+        {
+            let insert = panel.insert.clone();
+            panel.view.connect_row_activated(move |view, path, _| {
+                // Topics have no parent and are not inserted.
+                if path.get_depth() < 2 {
+                    return;
+                }
+                let signature = view.get_model().and_then(|model| {
+                    let iter = model.get_iter(path)?;
+                    model.get_value(&iter, SIGNATURE as i32).get::<String>()
+                });
+                let f = insert.borrow().clone();
+                if let (Some(f), Some(signature)) = (f, signature) {
+                    f(&signature);
+                }
+            });
+        }
+        panel
+    }
+    // Call f with the signature of a function which was activated.
+    pub fn connect_insert<F: Fn(&str) + 'static>(&self, f: F) {
+        *self.insert.borrow_mut() = Some(Rc::new(f));
+    }
+    // Start a search, e.g. from the help menu.
+    pub fn focus_search(&self) {
+        self.search.grab_focus();
+    }
+    // List the topics with the functions matching query, see api::Function::matches.
+    fn list(&self, query: &str) {
+        self.store.clear();
+        for (topic, functions) in api::groups() {
+            let matching: Vec<_> = functions.iter().filter(|f| f.matches(query)).collect();
+            if matching.is_empty() {
+                continue;
+            }
+            let parent =
+                self.store
+                    .insert_with_values(None, None, &[SIGNATURE, DOC], &[&topic, &""]);
+            for function in matching {
+                self.store.insert_with_values(
+                    Some(&parent),
+                    None,
+                    &[SIGNATURE, DOC],
+                    &[&function.signature(), &function.doc],
+                );
+            }
+        }
+        self.view.expand_all();
+    }
+}
+
+impl Default for ReferencePanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
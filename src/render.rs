@@ -4,33 +4,571 @@
 // pub type Point = Point<float>;
 
 use super::Float;
+use flat_tree::FlatTree;
+use gizmo::Gizmo;
+use image::{Rgba, RgbaImage};
+use indexed_mesh::IndexedMesh;
 use nalgebra as na;
+use overlay;
 use rayon::prelude::*;
 use std::cmp;
-use truescad_luascad::implicit3d::Object;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use truescad_luascad::analysis;
+use truescad_luascad::bounded;
+use truescad_luascad::implicit3d::{BoundingBox, Object};
+pub use truescad_luascad::measure::Ray;
+use truescad_luascad::measure::SurfaceQuery;
+use truescad_luascad::normals::{self, NormalEstimate};
 
 const EPSILON: Float = 0.003;
 const APPROX_SLACK: Float = 0.1;
 
 const FOCAL_FACTOR: Float = 36. /* 36 mm film */ / 50.;
 
+// Limits for zooming, relative to the default viewer distance.
+const MIN_ZOOM: Float = 0.01;
+const MAX_ZOOM: Float = 100.;
+
+// Ambient occlusion samples the field at AO_STEPS points along the normal, AO_STEP apart
+// (relative to the object width).
+const AO_STEPS: usize = 5;
+const AO_STEP: Float = 0.01;
+const AO_STRENGTH: Float = 0.6;
+
+// Spacing of the hatch pattern on cut faces, relative to the object width.
+const HATCH_SPACING: Float = 0.02;
+
+// How far (relative to the object width) overlay lines may be behind the visible surface and
+// still be drawn.
+const OVERLAY_DEPTH_TOLERANCE: Float = 0.01;
+
+// Iteration count shown as the hottest color in the iteration heatmap.
+const HEATMAP_ITERATIONS: Float = 64.;
+
+// Primary rays are marched together in cones through blocks of about CONE_BLOCK x CONE_BLOCK
+// pixels first, see Renderer::cone_start.
+const CONE_BLOCK: usize = 8;
+
+// Normals reconstructed from neighboring hits are only trusted if the neighbors are less than
+// SCREEN_NORMAL_SLOPE times their spacing off the plane facing the camera, see screen_normals.
+const SCREEN_NORMAL_SLOPE: Float = 4.;
+
+// Rays step at least MIN_STEP times the distance at which they stop at the surface, so they do
+// not stall where values are tiny but not small enough, e.g. along the silhouette.
+const MIN_STEP: Float = 0.5;
+
+// Spacing of the contour bands in the distance slice, relative to the object width.
+const SLICE_BAND: Float = 0.05;
+
+// Soft shadows march at most SHADOW_STEPS towards the light. The larger SHADOW_SHARPNESS, the
+// smaller the penumbra.
+const SHADOW_STEPS: usize = 64;
+const SHADOW_SHARPNESS: Float = 8.;
+
+// Brightness of analysis colors on surfaces seen edge-on. Facing the camera, they are at full
+// brightness.
+const ANALYSIS_AMBIENT: Float = 0.3;
+
+// The overhang analysis leaves out the surface up to this fraction of the size of the object
+// above its bottom, which rests on the build plate. Larger than EPSILON, since rays stop that far
+// off the surface.
+const OVERHANG_PLATE_TOLERANCE: Float = 0.01;
+
+// Edge length of the square tiles the image is split into for rendering.
+const TILE_SIZE: usize = 32;
+
+// Length of the arrows of gizmos, relative to the size of the object.
+const GIZMO_SIZE: Float = 0.15;
+
+// The eyes of stereo images look at the orbit point from directions this far apart (in radians),
+// about as far as eyes at a thirtieth of the distance to it.
+const STEREO_ANGLE: Float = 0.033;
+
+// Linear RGB, each channel in [0, 1].
+pub type Color = na::Vector3<Float>;
+
+// Light positions and directions are given in view space, so lights follow the camera.
 #[derive(Copy, Clone, Debug)]
-pub struct Ray {
-    pub origin: na::Point3<Float>,
-    pub dir: na::Vector3<Float>,
+pub enum LightSource {
+    // Direction pointing towards the light.
+    Directional(na::Vector3<Float>),
+    Point(na::Point3<Float>),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub source: LightSource,
+    pub color: Color,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Specular {
+    Phong { shininess: Float },
+    Ggx { roughness: Float },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
+    pub color: Color,
+    pub ambient: Float,
+    pub diffuse: Float,
+    pub specular: Float,
+    pub specular_model: Specular,
+}
+
+// Removes everything on the side of the plane the normal points to.
+#[derive(Copy, Clone, Debug)]
+pub struct ClipPlane {
+    pub point: na::Point3<Float>,
+    pub normal: na::Vector3<Float>,
+}
+
+impl ClipPlane {
+    pub fn new(point: na::Point3<Float>, normal: na::Vector3<Float>) -> ClipPlane {
+        ClipPlane {
+            point,
+            normal: normal.normalize(),
+        }
+    }
+
+    fn distance(&self, p: &na::Point3<Float>) -> Float {
+        (p - self.point).dot(&self.normal)
+    }
+}
+
+// What the renderer shows. All but Shaded are meant for diagnosing slow or ill-conditioned
+// fields.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum DebugMode {
+    #[default]
+    Shaded,
+    // Surface normals, mapped from [-1, 1] to [0, 1] per channel.
+    Normals,
+    // Number of ray marching steps, from blue (few) to red (many).
+    IterationHeatmap,
+    // Field values on the plane through the orbit point facing the camera. Outside is orange,
+    // inside is blue, with contour bands and the surface in white.
+    DistanceSlice,
+}
+
+impl DebugMode {
+    // In the order of the settings dialog, and of their indices in old settings files.
+    pub const ALL: [DebugMode; 4] = [
+        DebugMode::Shaded,
+        DebugMode::Normals,
+        DebugMode::IterationHeatmap,
+        DebugMode::DistanceSlice,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugMode::Shaded => "shaded",
+            DebugMode::Normals => "normals",
+            DebugMode::IterationHeatmap => "iteration heatmap",
+            DebugMode::DistanceSlice => "distance slice",
+        }
+    }
+}
+
+// How the images of both eyes are shown together. The eyes are turned around the orbit point,
+// so orthographic (axonometric) views get depth too.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stereo {
+    // The brightness of the left eye in red and of the right eye in cyan, for red-cyan glasses.
+    Anaglyph,
+    // The left eye in the left half of the image, the right eye in the right half, for viewing
+    // with parallel eyes.
+    SideBySide,
+}
+
+// Coloring of the surface by an analysis, instead of with the material color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SurfaceAnalysis {
+    // Wall thickness (see analysis::wall_thickness) from red (threshold and thinner) to blue
+    // (three times threshold and thicker).
+    WallThickness {
+        threshold: Float,
+    },
+    // Red where the surface overhangs by more than max_angle (in radians), see
+    // analysis::is_overhang.
+    Overhang {
+        max_angle: Float,
+    },
+    // For a mold pulled off along the unit vector pull: red for undercuts, orange where the draft
+    // angle is less than min_draft (in radians), green elsewhere. See analysis::draft_angle.
+    Draft {
+        pull: na::Vector3<Float>,
+        min_draft: Float,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub lights: Vec<Light>,
+    pub material: Material,
+    pub background: Color,
+    // Supersampling: cast aa_samples x aa_samples rays per pixel.
+    pub aa_samples: usize,
+    pub ambient_occlusion: bool,
+    pub soft_shadows: bool,
+    pub clip_plane: Option<ClipPlane>,
+    // Color of faces created by the clip plane.
+    pub cut_color: Color,
+    pub debug_mode: DebugMode,
+    // Draw the edges set with Renderer::set_wireframe.
+    pub wireframe: bool,
+    pub wireframe_color: Color,
+    pub axes: bool,
+    pub grid: bool,
+    pub grid_color: Color,
+    pub bounding_box: bool,
+    pub bounding_box_color: Color,
+    // Color of the line set with Renderer::set_measurement.
+    pub measurement_color: Color,
+    // Color of the box set with Renderer::set_highlight.
+    pub highlight_color: Color,
+    pub surface_analysis: Option<SurfaceAnalysis>,
+    // How the normals of the surface are found for shading.
+    pub normals: NormalEstimate,
+    // Rays step relaxation times the value (1..2), see Tracer. 1 is plain sphere tracing.
+    pub relaxation: Float,
+    // Rays give up after this many steps. In the debug modes, they show max_iterations_color.
+    pub max_iterations: usize,
+    pub max_iterations_color: Color,
+    // Render an image for each eye, see Stereo.
+    pub stereo: Option<Stereo>,
+    // March cones through blocks of pixels before their rays, see Renderer::cone_start.
+    pub cone_tracing: bool,
+    // Reconstruct the normals from the hits of neighboring rays where the surface is smooth,
+    // instead of evaluating them, for a faster preview. Off in render_to_image.
+    pub screen_space_normals: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            lights: vec![Light {
+                source: LightSource::Directional(na::Vector3::new(-2. / 3., 2. / 3., -1. / 3.)),
+                color: Color::new(1., 1., 1.),
+            }],
+            material: Material {
+                color: Color::new(1., 1., 0.),
+                ambient: 0.05,
+                diffuse: 0.9,
+                specular: 0.3,
+                specular_model: Specular::Phong { shininess: 32. },
+            },
+            background: Color::new(0., 0., 0.),
+            aa_samples: 1,
+            ambient_occlusion: false,
+            soft_shadows: false,
+            clip_plane: None,
+            cut_color: Color::new(0.8, 0.1, 0.1),
+            debug_mode: DebugMode::Shaded,
+            wireframe: false,
+            wireframe_color: Color::new(0.1, 0.1, 0.1),
+            axes: false,
+            grid: false,
+            grid_color: Color::new(0.4, 0.4, 0.4),
+            bounding_box: false,
+            bounding_box_color: Color::new(0., 0.8, 0.8),
+            measurement_color: Color::new(1., 0.3, 1.),
+            highlight_color: Color::new(1., 0.5, 0.),
+            surface_analysis: None,
+            normals: NormalEstimate::Object,
+            relaxation: 1.6,
+            max_iterations: 512,
+            max_iterations_color: Color::new(1., 0., 1.),
+            stereo: None,
+            cone_tracing: true,
+            screen_space_normals: false,
+        }
+    }
+}
+
+// A light transformed into object space.
+struct SceneLight {
+    source: LightSource,
+    color: Color,
+}
+
+impl SceneLight {
+    // Direction towards the light and distance to it.
+    fn direction_from(&self, p: &na::Point3<Float>, maxval: Float) -> (na::Vector3<Float>, Float) {
+        match self.source {
+            LightSource::Directional(dir) => (dir, maxval),
+            LightSource::Point(pos) => {
+                let v = pos - p;
+                let dist = v.norm();
+                (v / dist, dist)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Hit {
+    point: na::Point3<Float>,
+    dir: na::Vector3<Float>,
+    // Distance travelled along the ray.
+    t: Float,
+}
+
+// Over-relaxed sphere tracing (Keinert et al., "Enhanced Sphere Tracing"): a ray steps
+// relaxation times the value, which is the radius of a sphere free of the surface around it.
+// Without relaxation, it would slow down along surfaces it passes closely. If the spheres at both
+// ends of a step do not overlap, or the step ends inside, it may have passed the surface, and it
+// is taken again without relaxation, which stays off for the rest of the ray.
+struct Tracer {
+    origin: na::Point3<Float>,
+    dir: na::Vector3<Float>,
+    // Distance travelled along the ray, and the value there.
+    t: Float,
+    radius: Float,
+    // Where the ray leaves the box of the object, see Span.
+    far: Float,
+    // The step to the point of next.
+    step: Float,
+    relaxation: Float,
+    iter: usize,
+}
+
+// The part of a ray in the box of the object, see Renderer::span: from near, where the value is
+// value, to far, as distances along the ray.
+#[derive(Clone, Copy)]
+struct Span {
+    near: Float,
+    value: Float,
+    far: Float,
+}
+
+impl Tracer {
+    fn new(r: &Ray, span: Span, relaxation: Float) -> Tracer {
+        Tracer {
+            origin: r.origin,
+            dir: r.dir.normalize(),
+            t: span.near,
+            radius: span.value,
+            far: span.far,
+            step: 0.,
+            relaxation,
+            iter: 0,
+        }
+    }
+    // The point to evaluate next, at least min_step ahead.
+    fn next(&mut self, min_step: Float) -> na::Point3<Float> {
+        self.step = (self.radius * self.relaxation).max(min_step);
+        self.origin + self.dir * (self.t + self.step)
+    }
+    // Step to the point of next, whose value is value. Returns the number of iterations and the
+    // hit, if any, once the ray is done: it hit the surface (value below epsilon), left the box
+    // of the object (past far, or where the box is infinite, value above maxval) or took
+    // max_iterations.
+    fn advance(
+        &mut self,
+        value: Float,
+        epsilon: Float,
+        maxval: Float,
+        max_iterations: usize,
+    ) -> Option<(usize, Option<Hit>)> {
+        self.iter += 1;
+        if self.relaxation > 1. && (value < 0. || self.radius + value < self.step) {
+            self.relaxation = 1.;
+        } else {
+            self.t += self.step;
+            self.radius = value;
+            if value > maxval && self.far.is_infinite() {
+                return Some((self.iter, None));
+            }
+            if value < epsilon {
+                let hit = Hit {
+                    point: self.origin + self.dir * self.t,
+                    dir: self.dir,
+                    t: self.t,
+                };
+                return Some((self.iter, Some(hit)));
+            }
+        }
+        if self.t > self.far || self.iter >= max_iterations {
+            return Some((self.iter, None));
+        }
+        None
+    }
+}
+
+// Primary ray setup for one frame.
+struct Camera {
+    origin: na::Point3<Float>,
+    front: na::Vector3<Float>,
+    // Offset of the ray direction per pixel.
+    right: na::Vector3<Float>,
+    down: na::Vector3<Float>,
+    center_x: Float,
+    center_y: Float,
+    viewer_dist: Float,
+    orthographic: bool,
+}
+
+impl Camera {
+    // Ray through pixel position x, y.
+    fn ray(&self, x: Float, y: Float) -> Ray {
+        let offset = self.right * (x - self.center_x) + self.down * (y - self.center_y);
+        if self.orthographic {
+            // Parallel rays, spanning the same area at the orbit point.
+            Ray::new(self.origin + offset * self.viewer_dist, self.front)
+        } else {
+            Ray::new(self.origin, self.front + offset)
+        }
+    }
+
+    // Pixel position and distance from the camera of p, None if p is behind the camera.
+    fn project(&self, p: &na::Point3<Float>) -> Option<na::Point3<Float>> {
+        let d = p - self.origin;
+        let forward = d.dot(&self.front) / self.front.norm_squared();
+        let (scale, dist) = if self.orthographic {
+            (self.viewer_dist, forward * self.front.norm())
+        } else {
+            (forward, d.norm())
+        };
+        if forward <= 0. {
+            return None;
+        }
+        Some(na::Point3::new(
+            d.dot(&self.right) / (self.right.norm_squared() * scale) + self.center_x,
+            d.dot(&self.down) / (self.down.norm_squared() * scale) + self.center_y,
+            dist,
+        ))
+    }
+
+    // The point the camera orbits around.
+    fn target(&self) -> na::Point3<Float> {
+        self.origin + self.front * self.viewer_dist
+    }
 }
 
-impl Ray {
-    pub fn new(o: na::Point3<Float>, d: na::Vector3<Float>) -> Ray {
-        Ray { origin: o, dir: d }
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+// Where the camera of a Renderer is, to save it and restore it later, see Renderer::camera_state.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    // The map from view space to object space, by rows.
+    pub transform: [[Float; 4]; 4],
+    // The distance of the camera to the orbit point, derived from the size of the object if
+    // there is none.
+    #[serde(default)]
+    pub distance: Option<Float>,
+    #[serde(default)]
+    pub orthographic: bool,
+}
+
+// A part of the object with its own color. Surface points get the color of the closest part.
+#[derive(Clone)]
+pub struct ColoredPart {
+    pub object: Box<dyn Object<Float>>,
+    pub color: Color,
+}
+
+// Timing of a single tile, identified by its top left pixel.
+#[derive(Copy, Clone, Debug)]
+pub struct TileStats {
+    pub x: usize,
+    pub y: usize,
+    pub rays: usize,
+    pub iterations: usize,
+    pub duration: Duration,
+}
+
+// Statistics of one rendered frame. Rays and iterations only count primary rays.
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    pub rays: usize,
+    pub iterations: usize,
+    pub duration: Duration,
+    pub tiles: Vec<TileStats>,
+}
+
+impl RenderStats {
+    pub fn avg_iterations(&self) -> Float {
+        if self.rays == 0 {
+            0.
+        } else {
+            self.iterations as Float / self.rays as Float
+        }
+    }
+
+    pub fn millis(&self) -> Float {
+        self.duration.as_secs() as Float * 1000. + self.duration.subsec_nanos() as Float / 1e6
     }
 }
 
+// What gpu::Gpu needs to render the image Renderer::draw would, see Renderer::gpu_scene. Points
+// and directions are in object space, like those of Camera.
+#[derive(Clone, Debug)]
+pub struct GpuScene {
+    // The GLSL distance function set with Renderer::set_shader.
+    pub shader: String,
+    pub origin: na::Point3<Float>,
+    pub front: na::Vector3<Float>,
+    pub right: na::Vector3<Float>,
+    pub down: na::Vector3<Float>,
+    pub center: (Float, Float),
+    pub viewer_dist: Float,
+    pub orthographic: bool,
+    // Rays are marched in this box, see Renderer::span.
+    pub bbox: BoundingBox<Float>,
+    pub epsilon: Float,
+    pub maxval: Float,
+    pub lights: Vec<Light>,
+    pub settings: RenderSettings,
+}
+
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+// Standard CAD views. Z is up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ViewPreset {
+    Front,
+    Back,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    Isometric,
+}
+
 #[derive(Clone)]
 pub struct Renderer {
-    light_dir: na::Vector3<Float>,
+    settings: RenderSettings,
+    projection: Projection,
+    // Maps view space (x right, y up, looking along z) to object space.
+    // The view space origin is the point the camera orbits around.
     trans: na::Matrix4<Float>,
+    // Distance of the camera to the orbit point. None means it is derived from the object size.
+    viewer_dist: Option<Float>,
     object: Option<Box<dyn Object<Float>>>,
+    // The object as FlatTree, to march several rays at once.
+    flat_tree: Option<FlatTree>,
+    // The object as GLSL distance function, to render it with gpu::Gpu.
+    shader: Option<String>,
+    parts: Vec<ColoredPart>,
+    wireframe: Vec<overlay::Line>,
+    // Surface points measured in the GUI.
+    measurement: Option<(na::Point3<Float>, na::Point3<Float>)>,
+    // Box of the node selected in the object tree panel.
+    highlight: Option<BoundingBox<Float>>,
+    // Handles of the parts the script declared editable.
+    gizmos: Vec<Gizmo>,
+    // Changes with everything which changes the image, see Renderer::version.
+    version: u64,
     epsilon: Float,
     maxval: Float,
     approx_slack: Float,
@@ -39,9 +577,19 @@ pub struct Renderer {
 impl Renderer {
     pub fn new() -> Renderer {
         Renderer {
-            light_dir: na::Vector3::new(-2. / 3., 2. / 3., -1. / 3.),
+            settings: RenderSettings::default(),
+            projection: Projection::Perspective,
             trans: na::Matrix4::identity(),
+            viewer_dist: None,
             object: None,
+            flat_tree: None,
+            shader: None,
+            parts: Vec::new(),
+            wireframe: Vec::new(),
+            measurement: None,
+            highlight: None,
+            gizmos: Vec::new(),
+            version: 0,
             epsilon: EPSILON,
             maxval: 0.,
             approx_slack: APPROX_SLACK,
@@ -49,105 +597,1254 @@ impl Renderer {
     }
 
     pub fn set_object(&mut self, object: Option<Box<dyn Object<Float>>>) {
+        self.version += 1;
         self.object = object;
+        self.flat_tree = None;
+        self.shader = None;
+        self.parts.clear();
+        self.wireframe.clear();
+        self.measurement = None;
+        self.gizmos.clear();
         self.epsilon = self.object_width() * EPSILON;
         self.maxval = self.object_width();
         self.approx_slack = self.object_width() * APPROX_SLACK;
     }
 
+    // The object set with set_object as FlatTree. Primary rays are then marched four at a time
+    // with FlatTree::value4.
+    pub fn set_flat_tree(&mut self, tree: Option<FlatTree>) {
+        self.version += 1;
+        self.flat_tree = tree;
+    }
+
+    // The object set with set_object as GLSL distance function (see gpu::for_rendering), which
+    // makes it available to gpu::Gpu through gpu_scene.
+    pub fn set_shader(&mut self, shader: Option<String>) {
+        self.version += 1;
+        self.shader = shader;
+    }
+
+    // The scene to render on the GPU instead of with draw_on_buf, None if there is no shader or
+    // the settings need the CPU: stereo images, debug modes, clip planes, surface analysis and
+    // colored parts.
+    pub fn gpu_scene(&self, width: i32, height: i32) -> Option<GpuScene> {
+        let s = &self.settings;
+        if s.stereo.is_some()
+            || s.debug_mode != DebugMode::Shaded
+            || s.clip_plane.is_some()
+            || s.surface_analysis.is_some()
+            || !self.parts.is_empty()
+        {
+            return None;
+        }
+        let (shader, object) = match (&self.shader, &self.object) {
+            (Some(shader), Some(object)) => (shader, object),
+            _ => return None,
+        };
+        let camera = self.camera(width, height);
+        let mut bbox = object.bbox().clone();
+        bbox.dilate(self.epsilon);
+        Some(GpuScene {
+            shader: shader.clone(),
+            origin: camera.origin,
+            front: camera.front,
+            right: camera.right,
+            down: camera.down,
+            center: (camera.center_x, camera.center_y),
+            viewer_dist: camera.viewer_dist,
+            orthographic: camera.orthographic,
+            bbox,
+            epsilon: self.epsilon,
+            maxval: self.maxval,
+            lights: self
+                .scene_lights()
+                .into_iter()
+                .map(|l| Light {
+                    source: l.source,
+                    color: l.color,
+                })
+                .collect(),
+            settings: s.clone(),
+        })
+    }
+
+    // Edges of the tessellated mesh, shown if wireframe is enabled in the settings.
+    pub fn set_wireframe(&mut self, mesh: Option<&IndexedMesh>) {
+        self.version += 1;
+        self.wireframe = mesh.map_or_else(Vec::new, |m| {
+            overlay::mesh_edges(m, self.settings.wireframe_color)
+        });
+    }
+
+    // Draw a line between the points of a measurement, which stays visible through the object.
+    pub fn set_measurement(&mut self, points: Option<(na::Point3<Float>, na::Point3<Float>)>) {
+        self.version += 1;
+        self.measurement = points;
+    }
+
+    // Draw the edges of bbox through the object, e.g. to show where a part of it is.
+    pub fn set_highlight(&mut self, bbox: Option<BoundingBox<Float>>) {
+        self.version += 1;
+        self.highlight = bbox;
+    }
+
+    // Show gizmos to move and rotate parts of the object at positions, see Renderer::pick_gizmo.
+    pub fn set_gizmos(&mut self, positions: &[na::Point3<Float>]) {
+        self.version += 1;
+        let size = self.object_width() * GIZMO_SIZE;
+        self.gizmos = positions.iter().map(|p| Gizmo::new(*p, size)).collect();
+    }
+
+    // The gizmo and its handle at pixel x, y, if there is one.
+    pub fn pick_gizmo(
+        &self,
+        x: Float,
+        y: Float,
+        width: i32,
+        height: i32,
+    ) -> Option<(usize, usize)> {
+        let camera = self.camera(width, height);
+        let project = |p: &na::Point3<Float>| camera.project(p);
+        self.gizmos
+            .iter()
+            .enumerate()
+            .filter_map(|(i, g)| g.pick(&project, x, y).map(|(handle, d)| (i, handle, d)))
+            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap())
+            .map(|(i, handle, _)| (i, handle))
+    }
+
+    // How much dragging handle of gizmo from pixel from to pixel to changes its value, see
+    // Gizmo::drag.
+    pub fn drag_gizmo(
+        &self,
+        gizmo: usize,
+        handle: usize,
+        from: (Float, Float),
+        to: (Float, Float),
+        width: i32,
+        height: i32,
+    ) -> Float {
+        let camera = self.camera(width, height);
+        let project = |p: &na::Point3<Float>| camera.project(p);
+        self.gizmos
+            .get(gizmo)
+            .map_or(0., |g| g.drag(&project, handle, from, to))
+    }
+
+    // Color the object by parts, instead of with the material color, until the next set_object.
+    // Parts are not rendered on their own, they only need to cover the surface of the object.
+    pub fn set_parts(&mut self, parts: Vec<ColoredPart>) {
+        self.version += 1;
+        self.parts = parts;
+    }
+
+    // A number which changes whenever the image changes, e.g. to tell whether a rendered image
+    // is still up to date.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.version += 1;
+        self.settings = settings;
+        self.settings.aa_samples = cmp::max(self.settings.aa_samples, 1);
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.version += 1;
+        self.projection = projection;
+    }
+
+    pub fn camera_state(&self) -> CameraState {
+        let mut transform = [[0.; 4]; 4];
+        for (i, row) in transform.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.trans[(i, j)];
+            }
+        }
+        CameraState {
+            transform,
+            distance: self.viewer_dist,
+            orthographic: self.projection == Projection::Orthographic,
+        }
+    }
+
+    // Move the camera to where it was at camera_state.
+    pub fn set_camera_state(&mut self, state: &CameraState) {
+        self.version += 1;
+        self.trans = na::Matrix4::from_fn(|i, j| state.transform[i][j]);
+        self.viewer_dist = state.distance;
+        self.projection = if state.orthographic {
+            Projection::Orthographic
+        } else {
+            Projection::Perspective
+        };
+    }
+
+    // Place the camera at eye, looking at target. The camera will orbit around target.
+    pub fn set_view(
+        &mut self,
+        eye: &na::Point3<Float>,
+        target: &na::Point3<Float>,
+        up: &na::Vector3<Float>,
+    ) {
+        self.version += 1;
+        let to_target = target - eye;
+        let dist = to_target.norm();
+        if dist <= 0. {
+            return;
+        }
+        let front = to_target / dist;
+        let right = front.cross(up);
+        let right = if right.norm() > 0. {
+            right.normalize()
+        } else {
+            // up is parallel to the view direction, pick any perpendicular.
+            front.cross(&na::Vector3::new(1., 0., 0.)).normalize()
+        };
+        // View space is left handed (z points away from the viewer).
+        let up = right.cross(&front);
+        let mut trans = na::Matrix4::identity();
+        trans
+            .fixed_slice_mut::<na::U3, na::U3>(0, 0)
+            .copy_from(&na::Matrix3::from_columns(&[right, up, front]));
+        trans
+            .fixed_slice_mut::<na::U3, na::U1>(0, 3)
+            .copy_from(&target.coords);
+        self.trans = trans;
+        self.viewer_dist = Some(dist);
+    }
+
+    pub fn set_view_preset(&mut self, preset: ViewPreset) {
+        self.version += 1;
+        let (target, dist) = match self.object {
+            Some(ref obj) => {
+                let bbox = obj.bbox();
+                (na::center(&bbox.min, &bbox.max), self.default_viewer_dist())
+            }
+            None => (na::Point3::origin(), 1.),
+        };
+        let z_up = na::Vector3::new(0., 0., 1.);
+        let (dir, up) = match preset {
+            ViewPreset::Front => (na::Vector3::new(0., -1., 0.), z_up),
+            ViewPreset::Back => (na::Vector3::new(0., 1., 0.), z_up),
+            ViewPreset::Top => (na::Vector3::new(0., 0., 1.), na::Vector3::new(0., 1., 0.)),
+            ViewPreset::Bottom => (na::Vector3::new(0., 0., -1.), na::Vector3::new(0., -1., 0.)),
+            ViewPreset::Left => (na::Vector3::new(-1., 0., 0.), z_up),
+            ViewPreset::Right => (na::Vector3::new(1., 0., 0.), z_up),
+            ViewPreset::Isometric => (na::Vector3::new(1., -1., 1.).normalize(), z_up),
+        };
+        self.set_view(&(target + dir * dist), &target, &up);
+    }
+
+    // Move the camera towards (factor < 1) or away from (factor > 1) the orbit point.
+    // In orthographic mode this scales the visible area.
+    pub fn zoom(&mut self, factor: Float) {
+        self.version += 1;
+        let default_dist = self.default_viewer_dist();
+        if default_dist <= 0. || factor <= 0. {
+            return;
+        }
+        let dist = self.viewer_dist.unwrap_or(default_dist) * factor;
+        self.viewer_dist = Some(dist.clamp(default_dist * MIN_ZOOM, default_dist * MAX_ZOOM));
+    }
+
     pub fn rotate_from_screen(&mut self, x: Float, y: Float) {
+        self.version += 1;
         let euler = ::na::Rotation::from_euler_angles(y, x, 0.).to_homogeneous();
         self.trans *= euler;
     }
 
+    // Rotate the camera by angle around axis (in object space) through the orbit point.
+    pub fn orbit(&mut self, axis: &na::Vector3<Float>, angle: Float) {
+        self.version += 1;
+        if axis.norm() <= 0. {
+            return;
+        }
+        let target = self.trans.transform_point(&na::Point3::origin());
+        let rotation = na::Rotation3::from_axis_angle(&na::Unit::new_normalize(*axis), angle);
+        self.trans = na::Matrix4::new_translation(&target.coords)
+            * rotation.to_homogeneous()
+            * na::Matrix4::new_translation(&-target.coords)
+            * self.trans;
+    }
+
     pub fn translate_from_screen(&mut self, x: Float, y: Float) {
+        self.version += 1;
         let v = na::Vector3::new(-x as Float, y as Float, 0.);
         self.trans = self.trans.append_translation(&v);
     }
 
-    fn cast_ray(
+    // The normal of obj at p, with the estimate of the settings.
+    fn normal(&self, obj: &dyn Object<Float>, p: &na::Point3<Float>) -> na::Vector3<Float> {
+        match self.settings.normals {
+            NormalEstimate::Object => obj.normal(p),
+            method => normals::estimate(method, |q| obj.approx_value(q, 0.), p, self.epsilon),
+        }
+    }
+
+    // Object value, with the clip plane applied.
+    fn value(&self, obj: &dyn Object<Float>, p: &na::Point3<Float>) -> Float {
+        let value = obj.approx_value(p, self.approx_slack);
+        match self.settings.clip_plane {
+            Some(ref plane) => value.max(plane.distance(p)),
+            None => value,
+        }
+    }
+
+    // Renderer::value for four points, evaluated with tree.
+    fn value4(
+        &self,
+        obj: &dyn Object<Float>,
+        tree: &FlatTree,
+        p: &[na::Point3<Float>; 4],
+    ) -> [Float; 4] {
+        // Like approx_value, far away the bounding box is good enough.
+        let mut values = [0.; 4];
+        for (v, p) in values.iter_mut().zip(p) {
+            *v = obj.bbox().distance(p);
+        }
+        if values.iter().any(|&v| v <= self.approx_slack) {
+            for (v, exact) in values.iter_mut().zip(&tree.value4(p)) {
+                if *v <= self.approx_slack {
+                    *v = *exact;
+                }
+            }
+        }
+        if let Some(ref plane) = self.settings.clip_plane {
+            for (v, p) in values.iter_mut().zip(p) {
+                *v = v.max(plane.distance(p));
+            }
+        }
+        values
+    }
+
+    // The clip plane, if p lies on a face cut by it.
+    fn cut_at(&self, obj: &dyn Object<Float>, p: &na::Point3<Float>) -> Option<&ClipPlane> {
+        match self.settings.clip_plane {
+            Some(ref plane) if plane.distance(p) >= obj.approx_value(p, self.approx_slack) => {
+                Some(plane)
+            }
+            _ => None,
+        }
+    }
+
+    // Diagonal stripes on the plane.
+    fn hatch(&self, p: &na::Point3<Float>, plane: &ClipPlane) -> Color {
+        let axis = if plane.normal.x.abs() < 0.9 {
+            na::Vector3::new(1., 0., 0.)
+        } else {
+            na::Vector3::new(0., 1., 0.)
+        };
+        let u = plane.normal.cross(&axis).normalize();
+        let v = plane.normal.cross(&u);
+        let stripe = ((p - plane.point).dot(&(u + v)) / (self.maxval * HATCH_SPACING)).floor();
+        if stripe as i64 % 2 == 0 {
+            self.settings.cut_color
+        } else {
+            self.settings.cut_color * 0.6
+        }
+    }
+
+    // The part of r in the box of obj (grown by epsilon, so that it holds the points rays stop
+    // at) from start on, None if r misses it. Rays starting in the box start with origin_value,
+    // or the value at their origin if there is none.
+    fn span(
         &self,
         obj: &dyn Object<Float>,
         r: &Ray,
-        light_dir: &na::Vector3<Float>,
-        origin_value: Float,
-    ) -> (usize, Float) {
-        let mut cr = *r;
-        let mut value = origin_value;
-        let mut iter: usize = 0;
+        origin_value: Option<Float>,
+        start: Float,
+    ) -> Option<Span> {
+        let dir = r.dir.normalize();
+        let mut bbox = obj.bbox().clone();
+        bbox.dilate(self.epsilon);
+        let (near, far) = bounded::clip_ray(&bbox, &r.origin, &dir)?;
+        let near = near.max(start);
+        if far < near.max(0.) {
+            return None;
+        }
+        let (near, value) = if near > 0. {
+            (near, self.value(obj, &(r.origin + dir * near)))
+        } else {
+            (
+                0.,
+                origin_value.unwrap_or_else(|| self.value(obj, &r.origin)),
+            )
+        };
+        Some(Span { near, value, far })
+    }
 
+    // Sphere trace along r over span, see Tracer. Returns the number of iterations and the hit,
+    // if any.
+    fn march(&self, obj: &dyn Object<Float>, r: &Ray, span: Span) -> (usize, Option<Hit>) {
+        let mut tracer = Tracer::new(r, span, self.settings.relaxation);
         loop {
-            cr.dir = cr.dir.normalize();
-            cr.origin += cr.dir * value;
-            value = obj.approx_value(&cr.origin, self.approx_slack);
-            iter += 1;
-            if value > self.maxval {
-                return (iter, 0.);
+            let p = tracer.next(self.epsilon * MIN_STEP);
+            let value = self.value(obj, &p);
+            if let Some(result) = tracer.advance(
+                value,
+                self.epsilon,
+                self.maxval,
+                self.settings.max_iterations,
+            ) {
+                return result;
+            }
+        }
+    }
+
+    // march for four rays at once, evaluating the object with tree.
+    fn march4(
+        &self,
+        obj: &dyn Object<Float>,
+        tree: &FlatTree,
+        rays: &[(Ray, Span); 4],
+    ) -> [(usize, Option<Hit>); 4] {
+        let relaxation = self.settings.relaxation;
+        let mut tracers = [
+            Tracer::new(&rays[0].0, rays[0].1, relaxation),
+            Tracer::new(&rays[1].0, rays[1].1, relaxation),
+            Tracer::new(&rays[2].0, rays[2].1, relaxation),
+            Tracer::new(&rays[3].0, rays[3].1, relaxation),
+        ];
+        let mut points = [na::Point3::origin(); 4];
+        let mut result = [(0, None); 4];
+        let mut active = [true; 4];
+        while active.contains(&true) {
+            // Finished rays are evaluated along with the others, at their last point.
+            for l in 0..4 {
+                if active[l] {
+                    points[l] = tracers[l].next(self.epsilon * MIN_STEP);
+                }
+            }
+            let values = self.value4(obj, tree, &points);
+            for l in 0..4 {
+                if !active[l] {
+                    continue;
+                }
+                if let Some(done) = tracers[l].advance(
+                    values[l],
+                    self.epsilon,
+                    self.maxval,
+                    self.settings.max_iterations,
+                ) {
+                    active[l] = false;
+                    result[l] = done;
+                }
+            }
+        }
+        result
+    }
+
+    // How far the rays through the pixels from x, y to x + size, y + size can march together:
+    // the distance along them up to which the cone around them holds no surface, found by
+    // marching its axis with steps which keep the cone within the spheres free of the surface
+    // around the points of the axis. Infinity if the cone passes the object.
+    fn cone_start(
+        &self,
+        obj: &dyn Object<Float>,
+        camera: &Camera,
+        x: Float,
+        y: Float,
+        size: Float,
+    ) -> Float {
+        let bbox = obj.bbox();
+        if bounded::is_empty(bbox) {
+            return Float::INFINITY;
+        }
+        let half = size / 2.;
+        let axis = camera.ray(x + half, y + half);
+        let dir = axis.dir.normalize();
+        // At t along the axis, the cone has the radius r0 + k * t, with a pixel to spare for the
+        // sub pixel offsets. Orthographic rays are parallel, so it is a cylinder then.
+        let spread = camera.right.norm() * (half + 1.) * ::float::consts::SQRT_2;
+        let (r0, k) = if camera.orthographic {
+            (spread * camera.viewer_dist, 0.)
+        } else if spread < axis.dir.norm() {
+            (0., spread / (axis.dir.norm() - spread))
+        } else {
+            return 0.;
+        };
+        let mut t = 0.;
+        // Beyond the farthest corner of the box, the cone has passed the object. Before the
+        // axis enters the box grown by the radius there, the cone is outside of it.
+        let mut end = Float::INFINITY;
+        if bounded::is_finite(bbox) {
+            end = bounded::corners(bbox)
+                .iter()
+                .map(|c| (c - axis.origin).dot(&dir))
+                .fold(Float::NEG_INFINITY, Float::max);
+            let mut grown = bbox.clone();
+            grown.dilate(r0 + k * end.max(0.) + self.epsilon);
+            match bounded::clip_ray(&grown, &axis.origin, &dir) {
+                Some((near, _)) => t = near.max(0.),
+                None => return Float::INFINITY,
+            }
+        }
+        for _ in 0..self.settings.max_iterations {
+            if t > end {
+                return Float::INFINITY;
+            }
+            let radius = r0 + k * t;
+            let value = self.value(obj, &(axis.origin + dir * t));
+            if value < radius + self.epsilon {
+                return t;
+            }
+            t += (value - radius) / (1. + k);
+        }
+        t
+    }
+
+    // The number of iterations, color and depth of each of rays, which start marching at the
+    // distances in starts (see cone_start). Only rays entering the box of the object are marched,
+    // from where they enter it, four at a time if there is a flat tree. If grid has the columns
+    // and stride of the blocks the rays go through, one each, the normals are taken from their
+    // neighbors, see screen_normals.
+    #[allow(clippy::too_many_arguments)]
+    fn cast_rays(
+        &self,
+        obj: &dyn Object<Float>,
+        rays: &[Ray],
+        starts: &[Float],
+        camera: &Camera,
+        lights: &[SceneLight],
+        origin_value: Float,
+        grid: Option<(usize, usize)>,
+    ) -> Vec<(usize, Color, Float)> {
+        if self.settings.debug_mode == DebugMode::DistanceSlice {
+            return rays.iter().map(|r| self.slice(obj, r, camera)).collect();
+        }
+        // Perspective rays all start at the camera, orthographic ones each at their own origin.
+        let shared_value = if camera.orthographic {
+            None
+        } else {
+            Some(origin_value)
+        };
+        let entering: Vec<(usize, Span)> = rays
+            .iter()
+            .zip(starts)
+            .enumerate()
+            .filter_map(|(i, (r, &start))| {
+                self.span(obj, r, shared_value, start).map(|span| (i, span))
+            })
+            .collect();
+        let mut marched = vec![(0, None); rays.len()];
+        let rest = match self.flat_tree {
+            Some(ref tree) => {
+                let mut chunks = entering.chunks_exact(4);
+                for chunk in &mut chunks {
+                    let lanes = [
+                        (rays[chunk[0].0], chunk[0].1),
+                        (rays[chunk[1].0], chunk[1].1),
+                        (rays[chunk[2].0], chunk[2].1),
+                        (rays[chunk[3].0], chunk[3].1),
+                    ];
+                    let results = self.march4(obj, tree, &lanes);
+                    for (&(i, _), result) in chunk.iter().zip(results.iter()) {
+                        marched[i] = *result;
+                    }
+                }
+                chunks.remainder()
+            }
+            None => &entering[..],
+        };
+        for &(i, span) in rest {
+            marched[i] = self.march(obj, &rays[i], span);
+        }
+        let normals = match grid {
+            Some((columns, stride)) => screen_normals(&marched, columns, stride, camera),
+            None => vec![None; marched.len()],
+        };
+        marched
+            .into_iter()
+            .zip(normals)
+            .map(|((iter, hit), normal)| {
+                let (color, depth) = self.color_hit(obj, iter, hit, normal, lights);
+                (iter, color, depth)
+            })
+            .collect()
+    }
+
+    // Color and depth of a ray marched in iter iterations. The normal at the hit is taken from
+    // the object unless it is given.
+    fn color_hit(
+        &self,
+        obj: &dyn Object<Float>,
+        iter: usize,
+        hit: Option<Hit>,
+        normal: Option<na::Vector3<Float>>,
+        lights: &[SceneLight],
+    ) -> (Color, Float) {
+        match (self.settings.debug_mode, hit) {
+            (mode, None) if mode != DebugMode::Shaded && iter >= self.settings.max_iterations => {
+                (self.settings.max_iterations_color, Float::INFINITY)
+            }
+            (DebugMode::IterationHeatmap, hit) => (
+                heat(iter as Float / HEATMAP_ITERATIONS),
+                hit.map_or(Float::INFINITY, |h| h.t),
+            ),
+            (DebugMode::Normals, Some(hit)) => {
+                let norm = match self.cut_at(obj, &hit.point) {
+                    Some(plane) => plane.normal,
+                    None => normal.unwrap_or_else(|| self.normal(obj, &hit.point)),
+                };
+                ((norm + Color::repeat(1.)) * 0.5, hit.t)
+            }
+            (_, Some(hit)) => (
+                self.shade(obj, &hit.point, &-hit.dir, normal, lights),
+                hit.t,
+            ),
+            (_, None) => (self.settings.background, Float::INFINITY),
+        }
+    }
+
+    // Color by the field value where the ray crosses the slice plane.
+    fn slice(&self, obj: &dyn Object<Float>, r: &Ray, camera: &Camera) -> (usize, Color, Float) {
+        let t = (camera.target() - r.origin).dot(&camera.front) / r.dir.dot(&camera.front);
+        if t <= 0. {
+            return (0, self.settings.background, Float::INFINITY);
+        }
+        let value = self.value(obj, &(r.origin + r.dir * t));
+        let color = if value.abs() < self.epsilon * 2. {
+            Color::repeat(1.)
+        } else {
+            let band = value / (self.object_width() * SLICE_BAND);
+            let shade = 0.6 + 0.4 * (band * ::float::consts::PI * 2.).cos();
+            if value > 0. {
+                Color::new(1., 0.6, 0.2) * shade
+            } else {
+                Color::new(0.2, 0.4, 1.) * shade
             }
+        };
+        (0, color, t * r.dir.norm())
+    }
 
+    fn shade(
+        &self,
+        obj: &dyn Object<Float>,
+        p: &na::Point3<Float>,
+        view_dir: &na::Vector3<Float>,
+        normal: Option<na::Vector3<Float>>,
+        lights: &[SceneLight],
+    ) -> Color {
+        let material = &self.settings.material;
+        let (norm, base_color) = match self.cut_at(obj, p) {
+            Some(plane) => (plane.normal, self.hatch(p, plane)),
+            None => {
+                let norm = normal.unwrap_or_else(|| self.normal(obj, p));
+                let analysis = self
+                    .settings
+                    .surface_analysis
+                    .and_then(|analysis| analysis_color(obj, p, &norm, analysis));
+                // Lit from the camera, since the lights may not reach what an analysis shows,
+                // like overhangs.
+                if let Some(color) = analysis {
+                    return color
+                        * (ANALYSIS_AMBIENT
+                            + (1. - ANALYSIS_AMBIENT) * norm.dot(view_dir).max(0.));
+                }
+                (norm, self.part_color(p))
+            }
+        };
+        let mut color = base_color * material.ambient;
+        for light in lights {
+            let (light_dir, light_dist) = light.direction_from(p, self.maxval);
+            let dot = norm.dot(&light_dir);
+            if dot <= 0. {
+                continue;
+            }
+            let mut intensity = 1.;
+            if self.settings.soft_shadows {
+                intensity = self.soft_shadow(obj, p, &norm, &light_dir, light_dist);
+                if intensity <= 0. {
+                    continue;
+                }
+            }
+            let specular = match material.specular_model {
+                Specular::Phong { shininess } => {
+                    let reflected = norm * (2. * dot) - light_dir;
+                    reflected.dot(view_dir).max(0.).powf(shininess)
+                }
+                Specular::Ggx { roughness } => ggx(&norm, &light_dir, view_dir, roughness) * dot,
+            };
+            let lit =
+                base_color * (material.diffuse * dot) + Color::repeat(material.specular * specular);
+            color += light.color.component_mul(&lit) * intensity;
+        }
+        if self.settings.ambient_occlusion {
+            color *= self.occlusion(obj, p, &norm);
+        }
+        color
+    }
+
+    fn part_color(&self, p: &na::Point3<Float>) -> Color {
+        self.parts
+            .iter()
+            .map(|part| {
+                (
+                    part.object.approx_value(p, self.approx_slack).abs(),
+                    part.color,
+                )
+            })
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal))
+            .map_or(self.settings.material.color, |(_, color)| color)
+    }
+
+    // Trace a secondary ray towards the light. The closest miss (relative to the distance
+    // travelled) approximates how much of the light is visible.
+    fn soft_shadow(
+        &self,
+        obj: &dyn Object<Float>,
+        p: &na::Point3<Float>,
+        norm: &na::Vector3<Float>,
+        light_dir: &na::Vector3<Float>,
+        light_dist: Float,
+    ) -> Float {
+        let start = p + norm * (self.epsilon * 2.);
+        // Nothing casts shadows beyond the box of the object.
+        let mut bbox = obj.bbox().clone();
+        bbox.dilate(self.epsilon);
+        let light_dist = bounded::clip_ray(&bbox, &start, light_dir)
+            .map_or(0., |(_, far)| far)
+            .min(light_dist);
+        let mut result: Float = 1.;
+        let mut t = self.epsilon;
+        for _ in 0..SHADOW_STEPS {
+            let value = self.value(obj, &(start + light_dir * t));
             if value < self.epsilon {
+                return 0.;
+            }
+            result = result.min(SHADOW_SHARPNESS * value / t);
+            t += value;
+            if t > light_dist {
                 break;
             }
         }
-        let norm = obj.normal(&cr.origin);
-        let dot = norm.dot(light_dir);
-        if dot < 0. {
-            return (iter, 0.);
+        result
+    }
+
+    // Compare the field along the normal with the value expected for an unoccluded surface.
+    // Any difference is caused by nearby geometry occluding p.
+    fn occlusion(
+        &self,
+        obj: &dyn Object<Float>,
+        p: &na::Point3<Float>,
+        norm: &na::Vector3<Float>,
+    ) -> Float {
+        // Many fields (e.g. twisted ones) are scaled below the euclidean distance, so use the
+        // local slope along the normal as reference.
+        let origin_value = self.value(obj, p);
+        let slope = (self.value(obj, &(p + norm * self.epsilon)) - origin_value) / self.epsilon;
+        if slope <= 0. {
+            return 1.;
+        }
+        let step = self.maxval * AO_STEP;
+        let mut occlusion = 0.;
+        let mut weight = 1.;
+        for i in 1..=AO_STEPS {
+            let d = step * i as Float;
+            let value = self.value(obj, &(p + norm * d));
+            occlusion += weight * (origin_value + slope * d - value).max(0.) / (slope * d);
+            weight *= 0.5;
         }
-        (iter, dot)
+        (1. - AO_STRENGTH * occlusion).max(0.)
     }
 
-    pub fn draw_on_buf(&self, buf: &mut [u8], width: i32, height: i32) {
-        if let Some(my_obj) = &self.object {
-            let object_width = self.object_width();
-            let viewer_dist = FOCAL_FACTOR * object_width * 3.;
+    pub fn draw_on_buf(&self, buf: &mut [u8], width: i32, height: i32) -> RenderStats {
+        self.draw(buf, None, width, height, 1, None).unwrap()
+    }
 
-            let scale = 1. / Float::from(cmp::min(width, height));
-            let w2 = width / 2;
-            let h2 = height / 2;
+    // Render offscreen with settings, independent of the window size and the current settings.
+    pub fn render_to_image(&self, width: u32, height: u32, settings: &RenderSettings) -> RgbaImage {
+        let mut renderer = self.clone();
+        renderer.set_settings(RenderSettings {
+            screen_space_normals: false,
+            ..settings.clone()
+        });
+        let mut buf = vec![0; (width * height * 4) as usize];
+        renderer.draw_on_buf(&mut buf, width as i32, height as i32);
+        RgbaImage::from_fn(width, height, |x, y| {
+            let i = ((y * width + x) * 4) as usize;
+            Rgba([buf[i + 2], buf[i + 1], buf[i], 255])
+        })
+    }
 
-            let dir_front = self.trans.transform_vector(&na::Vector3::new(0., 0., 1.));
-            let dir_rl = self
-                .trans
-                .transform_vector(&na::Vector3::new(FOCAL_FACTOR, 0., 0.));
-            let dir_tb = self
-                .trans
-                .transform_vector(&na::Vector3::new(0., -FOCAL_FACTOR, 0.));
-            let light_dir = self.trans.transform_vector(&self.light_dir);
-            let ray_origin = self
-                .trans
-                .transform_point(&na::Point3::new(0., 0., -viewer_dist));
-            let ray = Ray::new(ray_origin, dir_front);
+    // Render only every stride-th pixel in both directions, and fill the blocks in between.
+    // Rendering stops early and returns None, if cancel gets set.
+    pub fn draw_on_buf_progressive(
+        &self,
+        buf: &mut [u8],
+        width: i32,
+        height: i32,
+        stride: usize,
+        cancel: &AtomicBool,
+    ) -> Option<RenderStats> {
+        self.draw(buf, None, width, height, stride, Some(cancel))
+    }
+
+    // Like draw_on_buf, but also fill depth with the distance of the visible surface from the
+    // camera (infinity, where no surface was hit).
+    pub fn draw_on_buf_with_depth(
+        &self,
+        buf: &mut [u8],
+        depth: &mut [Float],
+        width: i32,
+        height: i32,
+    ) -> RenderStats {
+        self.draw(buf, Some(depth), width, height, 1, None).unwrap()
+    }
+
+    // Return the surface point visible at pixel x, y.
+    pub fn pick(&self, x: Float, y: Float, width: i32, height: i32) -> Option<na::Point3<Float>> {
+        if let Some(my_obj) = &self.object {
+            let ray = self.camera(width, height).ray(x, y);
+            return my_obj.raycast(&ray, Float::INFINITY).map(|hit| hit.point);
+        }
+        None
+    }
 
-            let origin_value = my_obj.approx_value(&ray.origin, self.approx_slack);
+    fn draw(
+        &self,
+        buf: &mut [u8],
+        depth: Option<&mut [Float]>,
+        width: i32,
+        height: i32,
+        stride: usize,
+        cancel: Option<&AtomicBool>,
+    ) -> Option<RenderStats> {
+        if self.settings.stereo.is_some() {
+            return self.draw_stereo(buf, depth, width, height, stride, cancel);
+        }
+        let start = Instant::now();
+        // Overlays need depth to hide lines behind the object.
+        let mut own_depth = Vec::new();
+        let depth = match depth {
+            Some(d) => d,
+            None => {
+                if self.has_overlays() {
+                    own_depth = vec![Float::INFINITY; (width * height) as usize];
+                }
+                &mut own_depth[..]
+            }
+        };
+        let stride = cmp::max(stride, 1);
+        let row_len = width as usize;
+        let cancelled = || cancel.is_some_and(|c| c.load(Ordering::Relaxed));
+        if let Some(my_obj) = &self.object {
+            let camera = self.camera(width, height);
+            let lights = self.scene_lights();
 
-            let mut rows: Vec<_> = buf.chunks_mut((width * 4) as usize).enumerate().collect();
-            rows.par_iter_mut().for_each(|y_and_buf| {
-                let y = y_and_buf.0 as i32;
-                let row_buf = &mut y_and_buf.1;
-                let dir_row = dir_front + dir_tb * (Float::from(y - h2) * scale);
-                let mut row_ray = ray;
-                let mut index: usize = 0;
+            let origin_value = self.value(&**my_obj, &camera.origin);
 
-                for x in 0..width {
-                    row_ray.dir = dir_row + dir_rl * (Float::from(x - w2) * scale);
+            // Ordered grid of sub pixel offsets, centered on the pixel (or block of pixels).
+            let aa = self.settings.aa_samples;
+            let block_center = (stride - 1) as Float * 0.5;
+            let sub_pixels: Vec<Float> = (0..aa)
+                .map(|i| (i as Float + 0.5) / aa as Float - 0.5 + block_center)
+                .collect();
+            let num_samples = (aa * aa) as Float;
 
-                    let (i, v) = self.cast_ray(&**my_obj, &row_ray, &light_dir, origin_value);
+            // Tiles are rendered in parallel, each into its own buffer, starting with the tiles
+            // closest to the image center. Tile sizes are a multiple of stride, so blocks never
+            // cross tile borders.
+            let tile_size = TILE_SIZE.div_ceil(stride) * stride;
+            let mut tiles = Vec::new();
+            for y in (0..height as usize).step_by(tile_size) {
+                for x in (0..row_len).step_by(tile_size) {
+                    tiles.push(Tile {
+                        x,
+                        y,
+                        width: cmp::min(tile_size, row_len - x),
+                        height: cmp::min(tile_size, height as usize - y),
+                    });
+                }
+            }
+            let (center_x, center_y) = (row_len / 2, height as usize / 2);
+            tiles.sort_by_key(|t| {
+                let dx = (t.x + t.width / 2) as isize - center_x as isize;
+                let dy = (t.y + t.height / 2) as isize - center_y as isize;
+                dx * dx + dy * dy
+            });
 
-                    let b = (255.0 * v * v) as u8;
+            let rendered: Vec<_> = tiles
+                .par_iter()
+                .map(|tile| {
+                    let start = Instant::now();
+                    let mut stats = TileStats {
+                        x: tile.x,
+                        y: tile.y,
+                        rays: 0,
+                        iterations: 0,
+                        duration: Duration::default(),
+                    };
+                    let mut colors = vec![self.settings.background; tile.width * tile.height];
+                    let mut depths = vec![Float::INFINITY; tile.width * tile.height];
+                    if cancelled() {
+                        return (stats, colors, depths);
+                    }
+                    // Normals from the neighbors need a single ray per block.
+                    let screen_normals = self.settings.screen_space_normals
+                        && aa == 1
+                        && self.settings.debug_mode != DebugMode::DistanceSlice;
+                    // Cones cover whole blocks, whose rays lie within half a pixel of them.
+                    let cone = CONE_BLOCK.div_ceil(stride) * stride;
+                    let cones_x = tile.width.div_ceil(cone);
+                    let mut cone_starts = vec![0.; cones_x * tile.height.div_ceil(cone)];
+                    if self.settings.cone_tracing
+                        && self.settings.debug_mode != DebugMode::DistanceSlice
+                    {
+                        for (i, start) in cone_starts.iter_mut().enumerate() {
+                            let x = tile.x + i % cones_x * cone;
+                            let y = tile.y + i / cones_x * cone;
+                            *start = self.cone_start(
+                                &**my_obj,
+                                &camera,
+                                x as Float - 0.5,
+                                y as Float - 0.5,
+                                cone as Float,
+                            );
+                        }
+                    }
+                    // The rays of a row of blocks are cast together, so that adjacent pixels can be
+                    // marched at once. For normals from neighbors, the whole tile is.
+                    let columns = tile.width.div_ceil(stride);
+                    let (batch, grid) = if screen_normals {
+                        (
+                            tile.height.div_ceil(stride) * stride,
+                            Some((columns, stride)),
+                        )
+                    } else {
+                        (stride, None)
+                    };
+                    for batch_y in (0..tile.height).step_by(batch) {
+                        let batch_rows = batch_y..cmp::min(batch_y + batch, tile.height);
+                        let mut rays = Vec::new();
+                        let mut starts = Vec::new();
+                        for ty in batch_rows.clone().step_by(stride) {
+                            for tx in (0..tile.width).step_by(stride) {
+                                let (x, y) = (tile.x + tx, tile.y + ty);
+                                let start = cone_starts[ty / cone * cones_x + tx / cone];
+                                for sy in &sub_pixels {
+                                    for sx in &sub_pixels {
+                                        rays.push(camera.ray(x as Float + sx, y as Float + sy));
+                                        starts.push(start);
+                                    }
+                                }
+                            }
+                        }
+                        let samples = self.cast_rays(
+                            &**my_obj,
+                            &rays,
+                            &starts,
+                            &camera,
+                            &lights,
+                            origin_value,
+                            grid,
+                        );
+                        let blocks = samples.chunks(aa * aa);
+                        let positions = batch_rows
+                            .step_by(stride)
+                            .flat_map(|ty| (0..tile.width).step_by(stride).map(move |tx| (tx, ty)));
+                        for ((tx, ty), block) in positions.zip(blocks) {
+                            let mut color = Color::zeros();
+                            let mut min_t = Float::INFINITY;
+                            for &(iter, c, t) in block {
+                                stats.rays += 1;
+                                stats.iterations += iter;
+                                color += c;
+                                min_t = min_t.min(t);
+                            }
+                            let color = color / num_samples;
+                            for by in ty..cmp::min(ty + stride, tile.height) {
+                                for bx in tx..cmp::min(tx + stride, tile.width) {
+                                    colors[by * tile.width + bx] = color;
+                                    depths[by * tile.width + bx] = min_t;
+                                }
+                            }
+                        }
+                    }
+                    stats.duration = start.elapsed();
+                    (stats, colors, depths)
+                })
+                .collect();
 
-                    row_buf[index] = i as u8;
-                    index += 1;
-                    row_buf[index] = b;
-                    index += 1;
-                    row_buf[index] = b;
-                    index += 1;
-                    index += 1;
+            let mut stats = RenderStats::default();
+            for (tile, (tile_stats, colors, depths)) in tiles.iter().zip(rendered) {
+                for ty in 0..tile.height {
+                    let row = (tile.y + ty) * row_len;
+                    let colors = &colors[ty * tile.width..(ty + 1) * tile.width];
+                    for (tx, color) in colors.iter().enumerate() {
+                        set_pixel(&mut buf[row * 4..], tile.x + tx, color);
+                    }
+                    if !depth.is_empty() {
+                        depth[row + tile.x..row + tile.x + tile.width]
+                            .copy_from_slice(&depths[ty * tile.width..(ty + 1) * tile.width]);
+                    }
                 }
+                stats.rays += tile_stats.rays;
+                stats.iterations += tile_stats.iterations;
+                stats.tiles.push(tile_stats);
+            }
+            if !cancelled() {
+                self.draw_overlays(&camera, buf, depth, row_len, height as usize);
+            }
+            stats.duration = start.elapsed();
+            if cancelled() {
+                None
+            } else {
+                Some(stats)
+            }
+        } else {
+            for pixel in buf.chunks_mut(4) {
+                set_pixel(pixel, 0, &self.settings.background);
+            }
+            for d in depth.iter_mut() {
+                *d = Float::INFINITY;
+            }
+            Some(RenderStats {
+                duration: start.elapsed(),
+                ..RenderStats::default()
             })
         }
     }
 
+    // draw the image of each eye, and put them together as the stereo setting says. depth is that
+    // of the left eye for anaglyphs.
+    fn draw_stereo(
+        &self,
+        buf: &mut [u8],
+        depth: Option<&mut [Float]>,
+        width: i32,
+        height: i32,
+        stride: usize,
+        cancel: Option<&AtomicBool>,
+    ) -> Option<RenderStats> {
+        let start = Instant::now();
+        let stereo = self.settings.stereo.unwrap_or(Stereo::Anaglyph);
+        let eye_width = match stereo {
+            Stereo::Anaglyph => width,
+            Stereo::SideBySide => width / 2,
+        };
+        let size = (eye_width * height) as usize;
+        let mut bufs = [vec![0; size * 4], vec![0; size * 4]];
+        let mut depths = [vec![Float::INFINITY; size], vec![Float::INFINITY; size]];
+        let mut stats = RenderStats::default();
+        // Turning the camera left around the orbit point moves it to the left eye.
+        let sides: [Float; 2] = [1., -1.];
+        for (i, side) in sides.iter().enumerate() {
+            let mut eye = self.clone();
+            eye.settings.stereo = None;
+            eye.trans *=
+                na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), side * STEREO_ANGLE / 2.)
+                    .to_homogeneous();
+            let eye_stats = eye.draw(
+                &mut bufs[i][..],
+                Some(&mut depths[i][..]),
+                eye_width,
+                height,
+                stride,
+                cancel,
+            )?;
+            stats.rays += eye_stats.rays;
+            stats.iterations += eye_stats.iterations;
+            let offset = if stereo == Stereo::SideBySide {
+                i * eye_width as usize
+            } else {
+                0
+            };
+            stats
+                .tiles
+                .extend(eye_stats.tiles.into_iter().map(|t| TileStats {
+                    x: t.x + offset,
+                    ..t
+                }));
+        }
+        let row_len = width as usize;
+        let eye_row_len = eye_width as usize;
+        match stereo {
+            Stereo::Anaglyph => {
+                // cairo Rgb24 buffers are BGRx, see set_pixel.
+                let brightness = |p: &[u8]| {
+                    (0.114 * Float::from(p[0])
+                        + 0.587 * Float::from(p[1])
+                        + 0.299 * Float::from(p[2])) as u8
+                };
+                for (i, pixel) in buf.chunks_mut(4).take(size).enumerate() {
+                    let left = brightness(&bufs[0][i * 4..]);
+                    let right = brightness(&bufs[1][i * 4..]);
+                    pixel[0] = right;
+                    pixel[1] = right;
+                    pixel[2] = left;
+                }
+                if let Some(depth) = depth {
+                    depth.copy_from_slice(&depths[0]);
+                }
+            }
+            Stereo::SideBySide => {
+                for pixel in buf.chunks_mut(4) {
+                    set_pixel(pixel, 0, &self.settings.background);
+                }
+                for y in 0..height as usize {
+                    for (i, eye_buf) in bufs.iter().enumerate() {
+                        let from = y * eye_row_len;
+                        let to = y * row_len + i * eye_row_len;
+                        buf[to * 4..(to + eye_row_len) * 4]
+                            .copy_from_slice(&eye_buf[from * 4..(from + eye_row_len) * 4]);
+                    }
+                }
+                if let Some(depth) = depth {
+                    for d in depth.iter_mut() {
+                        *d = Float::INFINITY;
+                    }
+                    for y in 0..height as usize {
+                        for (i, eye_depth) in depths.iter().enumerate() {
+                            let from = y * eye_row_len;
+                            let to = y * row_len + i * eye_row_len;
+                            depth[to..to + eye_row_len]
+                                .copy_from_slice(&eye_depth[from..from + eye_row_len]);
+                        }
+                    }
+                }
+            }
+        }
+        stats.duration = start.elapsed();
+        Some(stats)
+    }
+
+    // The lights of the settings, transformed into object space.
+    fn scene_lights(&self) -> Vec<SceneLight> {
+        self.settings
+            .lights
+            .iter()
+            .map(|l| SceneLight {
+                source: match l.source {
+                    LightSource::Directional(dir) => {
+                        LightSource::Directional(self.trans.transform_vector(&dir).normalize())
+                    }
+                    LightSource::Point(pos) => LightSource::Point(self.trans.transform_point(&pos)),
+                },
+                color: l.color,
+            })
+            .collect()
+    }
+
+    fn has_overlays(&self) -> bool {
+        (self.settings.wireframe && !self.wireframe.is_empty())
+            || self.settings.axes
+            || self.settings.grid
+            || self.settings.bounding_box
+            || self.measurement.is_some()
+            || self.highlight.is_some()
+            || !self.gizmos.is_empty()
+    }
+
+    // Draw the overlays on an image of width x height rendered elsewhere, e.g. by gpu::Gpu, whose
+    // depth is the distance of the visible surface from the camera, like that of
+    // draw_on_buf_with_depth.
+    pub fn draw_overlays_on_buf(&self, buf: &mut [u8], depth: &[Float], width: i32, height: i32) {
+        let camera = self.camera(width, height);
+        self.draw_overlays(&camera, buf, depth, width as usize, height as usize);
+    }
+
+    fn draw_overlays(
+        &self,
+        camera: &Camera,
+        buf: &mut [u8],
+        depth: &[Float],
+        width: usize,
+        height: usize,
+    ) {
+        let project = |p: &na::Point3<Float>| camera.project(p);
+        let tolerance = self.object_width() * OVERLAY_DEPTH_TOLERANCE;
+        if self.settings.wireframe {
+            for line in &self.wireframe {
+                let line = overlay::Line {
+                    color: self.settings.wireframe_color,
+                    ..*line
+                };
+                overlay::draw_line(&line, &project, buf, depth, width, height, tolerance);
+            }
+        }
+        let bbox = match self.object {
+            Some(ref obj) => obj.bbox().clone(),
+            None => return,
+        };
+        if self.settings.grid {
+            for line in overlay::ground_grid(&bbox, self.settings.grid_color) {
+                overlay::draw_line(&line, &project, buf, depth, width, height, tolerance);
+            }
+        }
+        // Axes and bounding box stay visible through the object.
+        if self.settings.bounding_box && bbox.dim().norm().is_finite() {
+            for line in overlay::bbox_edges(&bbox, self.settings.bounding_box_color) {
+                overlay::draw_line(&line, &project, buf, &[], width, height, tolerance);
+            }
+        }
+        if self.settings.axes {
+            for line in overlay::axes(self.object_width() * 0.5) {
+                overlay::draw_line(&line, &project, buf, &[], width, height, tolerance);
+            }
+        }
+        if let Some((a, b)) = self.measurement {
+            let line = overlay::Line {
+                a,
+                b,
+                color: self.settings.measurement_color,
+            };
+            overlay::draw_line(&line, &project, buf, &[], width, height, tolerance);
+        }
+        if let Some(highlight) = self.highlight.as_ref() {
+            if highlight.dim().norm().is_finite() {
+                for line in overlay::bbox_edges(highlight, self.settings.highlight_color) {
+                    overlay::draw_line(&line, &project, buf, &[], width, height, tolerance);
+                }
+            }
+        }
+        for gizmo in &self.gizmos {
+            for line in gizmo.lines() {
+                overlay::draw_line(&line, &project, buf, &[], width, height, tolerance);
+            }
+        }
+    }
+
+    fn camera(&self, width: i32, height: i32) -> Camera {
+        let scale = 1. / cmp::min(width, height) as Float;
+        Camera {
+            origin: self.trans.transform_point(&na::Point3::new(
+                0.,
+                0.,
+                -self
+                    .viewer_dist
+                    .unwrap_or_else(|| self.default_viewer_dist()),
+            )),
+            front: self.trans.transform_vector(&na::Vector3::new(0., 0., 1.)),
+            right: self
+                .trans
+                .transform_vector(&na::Vector3::new(FOCAL_FACTOR * scale, 0., 0.)),
+            down: self
+                .trans
+                .transform_vector(&na::Vector3::new(0., -FOCAL_FACTOR * scale, 0.)),
+            center_x: (width / 2) as Float,
+            center_y: (height / 2) as Float,
+            viewer_dist: self
+                .viewer_dist
+                .unwrap_or_else(|| self.default_viewer_dist()),
+            orthographic: self.projection == Projection::Orthographic,
+        }
+    }
+
+    fn default_viewer_dist(&self) -> Float {
+        FOCAL_FACTOR * self.object_width() * 3.
+    }
+
     fn object_width(&self) -> Float {
         if let Some(ref my_obj) = self.object {
             return my_obj
@@ -169,3 +1866,131 @@ impl Default for Renderer {
         Self::new()
     }
 }
+
+// Write color to a cairo Rgb24 buffer (native endian 0x00RRGGBB, i.e. BGRx on little endian).
+fn set_pixel(buf: &mut [u8], x: usize, color: &Color) {
+    let to_u8 = |c: Float| (c.clamp(0., 1.) * 255.) as u8;
+    buf[x * 4] = to_u8(color.z);
+    buf[x * 4 + 1] = to_u8(color.y);
+    buf[x * 4 + 2] = to_u8(color.x);
+}
+
+// The normals of the hits of a grid of rays with columns columns, stride pixels apart, from
+// the points hit by their neighbors: across the nearer one along each axis, unless it is
+// farther off the plane through the hit facing the camera than SCREEN_NORMAL_SLOPE times the
+// spacing of the rays, where an edge is likely. None where there is no such neighbor along an
+// axis, e.g. at the sides of the grid, where the object gives the normal as usual.
+fn screen_normals(
+    marched: &[(usize, Option<Hit>)],
+    columns: usize,
+    stride: usize,
+    camera: &Camera,
+) -> Vec<Option<na::Vector3<Float>>> {
+    let pixel = camera.right.norm() * stride as Float;
+    let hit = |i: usize| marched.get(i).and_then(|m| m.1);
+    (0..marched.len())
+        .map(|i| {
+            let center = hit(i)?;
+            let size = if camera.orthographic {
+                pixel * camera.viewer_dist
+            } else {
+                pixel * center.t
+            };
+            let (column, row) = (i % columns, i / columns);
+            let nearest = |a: Option<Hit>, b: Option<Hit>| {
+                let offset = |h: &Hit| (h.point - center.point).dot(&center.dir).abs();
+                [a, b]
+                    .iter()
+                    .flatten()
+                    .filter(|h| offset(h) < size * SCREEN_NORMAL_SLOPE)
+                    .min_by(|g, h| offset(g).partial_cmp(&offset(h)).unwrap())
+                    .map(|h| h.point - center.point)
+            };
+            let left = if column > 0 { hit(i - 1) } else { None };
+            let right = if column + 1 < columns {
+                hit(i + 1)
+            } else {
+                None
+            };
+            let up = if row > 0 { hit(i - columns) } else { None };
+            let down = hit(i + columns);
+            let along_x = nearest(left, right)?;
+            let along_y = nearest(up, down)?;
+            let normal = along_x.cross(&along_y).try_normalize(0.)?;
+            // Towards the camera, whichever neighbors were taken.
+            Some(if normal.dot(&center.dir) > 0. {
+                -normal
+            } else {
+                normal
+            })
+        })
+        .collect()
+}
+
+// Heatmap color for x in [0, 1], going from blue over green and yellow to red.
+fn heat(x: Float) -> Color {
+    let x = x.clamp(0., 1.) * 3.;
+    if x < 1. {
+        Color::new(0., x, 1. - x)
+    } else if x < 2. {
+        Color::new(x - 1., 1., 0.)
+    } else {
+        Color::new(1., 3. - x, 0.)
+    }
+}
+
+// Color of the surface point p with normal norm for surface_analysis, None for the usual color.
+fn analysis_color(
+    obj: &dyn Object<Float>,
+    p: &na::Point3<Float>,
+    norm: &na::Vector3<Float>,
+    surface_analysis: SurfaceAnalysis,
+) -> Option<Color> {
+    match surface_analysis {
+        SurfaceAnalysis::WallThickness { threshold } => {
+            // Rays stop within epsilon of the surface, which is too far off to march from.
+            let p = p - norm * obj.approx_value(p, Float::INFINITY);
+            let thickness = analysis::wall_thickness(obj, &p, threshold * 3.);
+            Some(heat(1.5 - thickness / (threshold * 2.)))
+        }
+        SurfaceAnalysis::Overhang { max_angle } => {
+            // The bottom rests on the build plate.
+            let bbox = obj.bbox();
+            let on_plate = p.z - bbox.min.z <= bbox.dim().norm() * OVERHANG_PLATE_TOLERANCE;
+            if analysis::is_overhang(norm, max_angle) && !on_plate {
+                Some(Color::new(1., 0., 0.))
+            } else {
+                None
+            }
+        }
+        SurfaceAnalysis::Draft { pull, min_draft } => {
+            let p = p - norm * obj.approx_value(p, Float::INFINITY);
+            Some(if analysis::is_undercut(obj, &p, norm, &pull) {
+                Color::new(1., 0., 0.)
+            } else if analysis::draft_angle(norm, &pull) < min_draft {
+                Color::new(1., 0.5, 0.)
+            } else {
+                Color::new(0.2, 0.8, 0.2)
+            })
+        }
+    }
+}
+
+// Cook-Torrance specular term with GGX distribution, Schlick fresnel and Smith geometry.
+fn ggx(
+    n: &na::Vector3<Float>,
+    l: &na::Vector3<Float>,
+    v: &na::Vector3<Float>,
+    roughness: Float,
+) -> Float {
+    let h = (l + v).normalize();
+    let n_dot_h = n.dot(&h).max(0.);
+    let n_dot_v = n.dot(v).max(1e-4);
+    let n_dot_l = n.dot(l).max(1e-4);
+    let a2 = (roughness * roughness).powi(2);
+    let d = a2 / (::float::consts::PI * (n_dot_h * n_dot_h * (a2 - 1.) + 1.).powi(2));
+    let k = (roughness + 1.).powi(2) / 8.;
+    let g = n_dot_v / (n_dot_v * (1. - k) + k) * n_dot_l / (n_dot_l * (1. - k) + k);
+    let f = 0.04 + 0.96 * (1. - h.dot(v).max(0.)).powi(5);
+    d * g * f / (4. * n_dot_v * n_dot_l)
+}
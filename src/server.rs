@@ -0,0 +1,623 @@
+use super::Float;
+use editor;
+use flat_tree;
+use image::{self, ImageFormat};
+use indexed_mesh::IndexedMesh;
+use jit;
+use render;
+use serde_json;
+use settings;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use truescad_luascad;
+use truescad_luascad::bounded;
+use truescad_luascad::implicit3d;
+use truescad_luascad::include::Includes;
+use truescad_luascad::params::Value;
+use truescad_luascad::ScriptError;
+
+// An HTTP server (see truescad serve) which evaluates the scripts other programs post and
+// answers with their meshes or images:
+//
+//     POST /export {"script": "...", "parameters": {"r": 2}, "format": "3mf"}
+//     POST /render {"script": "...", "width": 400, "height": 300}
+//     GET /health
+//
+// Scripts are untrusted: they can not include or read files (see Includes::untrusted), their
+// size, the number of cells tessellated and the size of images are limited, and at most jobs of
+// them run at once. Requests beyond that get 503. Each job runs in a child process (see run_job)
+// with at most MAX_MEMORY, which is killed if it takes longer than TIMEOUT, giving 504.
+// Connections are handled by a fixed number of threads, more wait until one of them is free.
+
+// The largest request body, with the script.
+const MAX_BODY: usize = 256 * 1024;
+// How long a job may run before it is killed.
+const TIMEOUT: Duration = Duration::from_secs(30);
+// How often a running job is checked for having finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// The address space a job may use, in bytes.
+const MAX_MEMORY: u64 = 2 << 30;
+// How long a connection may take to send its request, and to take each part of the answer.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// Connections handled at once besides one per job, e.g. to answer 503 or /health while all jobs
+// run.
+const SPARE_HANDLERS: usize = 16;
+// Exports are tessellated with at most this many cells along each axis of the box, coarser than
+// the requested resolution if need be.
+const MAX_CELLS: Float = 400.;
+const MAX_IMAGE_SIZE: u32 = 2048;
+const DEFAULT_IMAGE_SIZE: (u32, u32) = (800, 600);
+
+// What POST /export and POST /render take. Missing fields get the defaults of the settings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Request {
+    script: String,
+    // Values of the parameters the script declares, numbers, booleans or choices.
+    parameters: HashMap<String, serde_json::Value>,
+    // Value of the Lua variable time (0..1).
+    time: Float,
+    resolution: Option<Float>,
+    // stl, ascii-stl, 3mf, amf, ply or obj.
+    format: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+// An answer, with its status and content type.
+struct Response {
+    status: u16,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response {
+        status,
+        content_type: "application/json".to_string(),
+        body: json!({ "error": message }).to_string().into_bytes(),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+// Counts the running jobs, up to the limit.
+struct Slots {
+    running: Mutex<usize>,
+    limit: usize,
+}
+
+// A running job, which frees its slot when it is dropped.
+struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *(self.0).running.lock().unwrap() -= 1;
+    }
+}
+
+impl Slots {
+    fn take(slots: &Arc<Slots>) -> Option<Slot> {
+        let mut running = slots.running.lock().unwrap();
+        if *running >= slots.limit {
+            return None;
+        }
+        *running += 1;
+        Some(Slot(slots.clone()))
+    }
+}
+
+// Serve requests on address (e.g. 127.0.0.1:8080) until the process is killed, running at most
+// jobs scripts at once.
+pub fn serve(address: &str, jobs: usize) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| format!("could not listen on {}: {}", address, e))?;
+    println!("serving on http://{} with {} jobs", address, jobs);
+    let slots = Arc::new(Slots {
+        running: Mutex::new(0),
+        limit: jobs,
+    });
+    // Each handler accepts the next connection once it is done with the previous one.
+    let mut handlers = Vec::new();
+    for _ in 0..jobs + SPARE_HANDLERS {
+        let listener = listener
+            .try_clone()
+            .map_err(|e| format!("could not listen on {}: {}", address, e))?;
+        let slots = slots.clone();
+        handlers.push(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(|e| format!("could not accept a connection: {}", e))
+                    .and_then(|stream| {
+                        handle(stream, &slots).map_err(|e| format!("connection failed: {}", e))
+                    });
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+            }
+        }));
+    }
+    for handler in handlers {
+        let _ = handler.join();
+    }
+    Ok(())
+}
+
+// Answer the request of a connection, which is closed afterwards.
+fn handle(stream: TcpStream, slots: &Arc<Slots>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, path) = match (words.next(), words.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return respond(stream, &error(400, "invalid request line")),
+    };
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = match value.trim().parse() {
+                    Ok(length) => length,
+                    Err(_) => return respond(stream, &error(400, "invalid Content-Length")),
+                };
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return respond(
+            stream,
+            &error(413, &format!("the body is larger than {} bytes", MAX_BODY)),
+        );
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let response = match (method.as_str(), path.as_str()) {
+        ("OPTIONS", _) => Response {
+            status: 204,
+            content_type: "text/plain".to_string(),
+            body: Vec::new(),
+        },
+        ("GET", "/health") => Response {
+            status: 200,
+            content_type: "application/json".to_string(),
+            body: json!({
+                "jobs": *slots.running.lock().unwrap(),
+                "limit": slots.limit,
+            })
+            .to_string()
+            .into_bytes(),
+        },
+        ("POST", "/export") => run(&body, slots, "export"),
+        ("POST", "/render") => run(&body, slots, "render"),
+        (_, "/health") | (_, "/export") | (_, "/render") => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    };
+    respond(stream, &response)
+}
+
+fn respond(mut stream: TcpStream, response: &Response) -> io::Result<()> {
+    let retry = if response.status == 503 {
+        "Retry-After: 1\r\n"
+    } else {
+        ""
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Content-Type\r\n\
+         Connection: close\r\n{}\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        retry
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+// Check the request in body and run job on it in a slot, see run_job.
+fn run(body: &[u8], slots: &Arc<Slots>, job: &str) -> Response {
+    if let Err(e) = serde_json::from_slice::<Request>(body) {
+        return error(400, &format!("invalid request: {}", e));
+    }
+    let _slot = match Slots::take(slots) {
+        Some(slot) => slot,
+        None => return error(503, "all jobs are busy, try again later"),
+    };
+    run_child(body, job).unwrap_or_else(|message| error(500, &message))
+}
+
+// Run job on the request in body in a child process, killing it after TIMEOUT.
+fn run_child(body: &[u8], job: &str) -> Result<Response, String> {
+    let answer = answer_path();
+    let exe = env::current_exe().map_err(|e| format!("could not start the job: {}", e))?;
+    let mut child = Command::new(exe)
+        .args(["serve", "--job", job])
+        .arg(&answer)
+        // glibc reserves address space for a heap per thread, which counts towards MAX_MEMORY.
+        .env("MALLOC_ARENA_MAX", "2")
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start the job: {}", e))?;
+    // Written by another thread, so that a job which does not read it is still killed.
+    if let Some(mut stdin) = child.stdin.take() {
+        let body = body.to_vec();
+        thread::spawn(move || stdin.write_all(&body));
+    }
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            result => {
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = fs::remove_file(&answer);
+                return match result {
+                    Err(e) => Err(format!("could not wait for the job: {}", e)),
+                    _ => Ok(error(
+                        504,
+                        &format!("the script took longer than {} s", TIMEOUT.as_secs()),
+                    )),
+                };
+            }
+        }
+    };
+    let bytes = fs::read(&answer);
+    let _ = fs::remove_file(&answer);
+    match bytes {
+        Ok(ref bytes) if status.success() => read_answer(bytes),
+        _ => Err(format!(
+            "the job failed ({}), it may have used more than {} MB",
+            status,
+            MAX_MEMORY >> 20
+        )),
+    }
+}
+
+// A file in the temporary directory for the answer of a job, unique within this process.
+fn answer_path() -> PathBuf {
+    static JOBS: AtomicUsize = AtomicUsize::new(0);
+    let job = JOBS.fetch_add(1, Ordering::SeqCst);
+    env::temp_dir().join(format!("truescad-job-{}-{}", process::id(), job))
+}
+
+// The response written by write_answer.
+fn read_answer(bytes: &[u8]) -> Result<Response, String> {
+    let invalid = || "the job wrote an invalid answer".to_string();
+    let end = bytes.iter().position(|&b| b == b'\n').ok_or_else(invalid)?;
+    let head = String::from_utf8_lossy(&bytes[..end]);
+    let mut words = head.splitn(2, ' ');
+    let status = words
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(invalid)?;
+    let content_type = words.next().ok_or_else(invalid)?.to_string();
+    Ok(Response {
+        status,
+        content_type,
+        body: bytes[end + 1..].to_vec(),
+    })
+}
+
+// The status and content type of response on a line, followed by its body.
+fn write_answer<W: Write>(w: &mut W, response: &Response) -> io::Result<()> {
+    writeln!(w, "{} {}", response.status, response.content_type)?;
+    w.write_all(&response.body)
+}
+
+// Run job (export or render) on the request read from stdin with at most MAX_MEMORY, and write
+// the response to the file answer, see write_answer. Like the rest of the process, prints go to
+// stdout.
+pub fn run_job(job: &str, answer: &str) -> Result<(), String> {
+    limit_memory(MAX_MEMORY)?;
+    let mut body = Vec::new();
+    io::stdin()
+        .read_to_end(&mut body)
+        .map_err(|e| format!("could not read the request: {}", e))?;
+    let request: Request =
+        serde_json::from_slice(&body).map_err(|e| format!("invalid request: {}", e))?;
+    let response = match job {
+        "export" => export(request),
+        "render" => render(request),
+        _ => return Err(format!("unknown job {}, expected export or render", job)),
+    };
+    let response = response.unwrap_or_else(|message| error(422, &message));
+    fs::File::create(answer)
+        .and_then(|mut file| write_answer(&mut file, &response))
+        .map_err(|e| format!("could not write {}: {}", answer, e))
+}
+
+// Limit the address space of this process to bytes, beyond which allocations fail.
+#[cfg(unix)]
+fn limit_memory(bytes: u64) -> Result<(), String> {
+    let limit = ::libc::rlimit {
+        rlim_cur: bytes as ::libc::rlim_t,
+        rlim_max: bytes as ::libc::rlim_t,
+    };
+    if unsafe { ::libc::setrlimit(::libc::RLIMIT_AS, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "could not limit the memory: {}",
+            io::Error::last_os_error()
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+fn limit_memory(_: u64) -> Result<(), String> {
+    Err("jobs can only be limited on Unix".to_string())
+}
+
+// The values of the parameters of request, see params::Value.
+fn values(request: &Request) -> Result<HashMap<String, Value>, String> {
+    request
+        .parameters
+        .iter()
+        .map(|(name, value)| {
+            let value = match *value {
+                serde_json::Value::Number(ref n) => {
+                    Value::Number(n.as_f64().unwrap_or(0.) as Float)
+                }
+                serde_json::Value::Bool(b) => Value::Bool(b),
+                serde_json::Value::String(ref s) => Value::Choice(s.clone()),
+                _ => return Err(format!("invalid value of the parameter {}", name)),
+            };
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+// Evaluate the script of request, like truescad export.
+fn evaluate(request: &Request) -> Result<truescad_luascad::Evaluation, String> {
+    if !(0. ..=1.).contains(&request.time) {
+        return Err(format!("invalid time {}, expected 0..1", request.time));
+    }
+    let includes = Includes {
+        untrusted: true,
+        ..Includes::default()
+    };
+    let mut evaluation =
+        truescad_luascad::evaluate_in(&request.script, request.time, &values(request)?, &includes)
+            .map_err(|e| ScriptError::from(&e).to_string())?;
+    evaluation.tree = settings::SettingsData::default().optimize(evaluation.tree);
+    Ok(evaluation)
+}
+
+fn export(request: Request) -> Result<Response, String> {
+    let s = settings::SettingsData::default();
+    let format = request.format.as_ref().map_or("stl", |f| f.as_str());
+    let content_type = match format {
+        "stl" | "ascii-stl" => "model/stl",
+        "3mf" => "model/3mf",
+        "amf" => "application/x-amf",
+        "ply" => "application/x-ply",
+        "obj" => "model/obj",
+        _ => {
+            return Err(format!(
+                "unknown format {}, expected stl, ascii-stl, 3mf, amf, ply or obj",
+                format
+            ))
+        }
+    };
+    let evaluation = evaluate(&request)?;
+    let mut object = evaluation
+        .object
+        .ok_or("no object - did you call build()?")?;
+    object.set_parameters(&parameters(&s));
+    if !bounded::is_finite(object.bbox()) {
+        return Err(
+            "can not export an infinite object, intersect it with a finite one".to_string(),
+        );
+    }
+    let resolution = resolution(
+        request.resolution.unwrap_or(s.export_resolution),
+        object.bbox().dim().amax(),
+    )?;
+    // Jobs print to the log of the server.
+    let mut warnings = Vec::new();
+    let mesh = editor::tessellate(
+        object,
+        evaluation.tree.as_ref(),
+        evaluation.units,
+        resolution,
+        s.export_error,
+        &mut warnings,
+    );
+    for warning in warnings {
+        println!("{}", warning);
+    }
+    let mesh = mesh.ok_or("tessellation failed")?;
+    Ok(Response {
+        status: 200,
+        content_type: content_type.to_string(),
+        body: write_mesh(&mesh, format).map_err(|e| format!("could not write the mesh: {}", e))?,
+    })
+}
+
+// The requested resolution for an object of size along its longest axis, coarser if it has more
+// than MAX_CELLS along it.
+fn resolution(requested: Float, size: Float) -> Result<Float, String> {
+    let resolution = requested.max(size / MAX_CELLS);
+    if resolution.is_nan() || resolution <= 0. {
+        return Err(format!("resolution must be positive, got {}", resolution));
+    }
+    Ok(resolution)
+}
+
+fn write_mesh(mesh: &IndexedMesh, format: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    match format {
+        "ascii-stl" => mesh.write_ascii_stl(&mut bytes)?,
+        "3mf" => mesh.write_3mf(&mut bytes)?,
+        "amf" => mesh.write_amf(&mut bytes)?,
+        "ply" => mesh.write_ply(&mut bytes)?,
+        "obj" => mesh.write_obj(&mut bytes)?,
+        _ => ::stl_io::write_stl(&mut bytes, mesh.stl_triangles().iter())?,
+    }
+    Ok(bytes.into_inner())
+}
+
+fn render(request: Request) -> Result<Response, String> {
+    let s = settings::SettingsData::default();
+    let width = request.width.unwrap_or(DEFAULT_IMAGE_SIZE.0);
+    let height = request.height.unwrap_or(DEFAULT_IMAGE_SIZE.1);
+    if width == 0 || height == 0 || width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+        return Err(format!(
+            "invalid size {}x{}, expected at most {}x{}",
+            width, height, MAX_IMAGE_SIZE, MAX_IMAGE_SIZE
+        ));
+    }
+    let evaluation = evaluate(&request)?;
+    let mut object = evaluation
+        .object
+        .ok_or("no object - did you call build()?")?;
+    let parameters = parameters(&s);
+    object.set_parameters(&parameters);
+    let mut renderer = render::Renderer::new();
+    renderer.set_object(Some(jit::accelerate(
+        object,
+        evaluation.tree.as_ref(),
+        &parameters,
+    )));
+    renderer.set_flat_tree(flat_tree::for_rendering(
+        evaluation.tree.as_ref(),
+        &parameters,
+    ));
+    let mut render_settings = renderer.settings().clone();
+    s.apply_to(&mut render_settings);
+    let image = renderer.render_to_image(width, height, &render_settings);
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(image)
+        .save(&mut png, ImageFormat::PNG)
+        .map_err(|e| format!("could not write the image: {}", e))?;
+    Ok(Response {
+        status: 200,
+        content_type: "image/png".to_string(),
+        body: png,
+    })
+}
+
+fn parameters(s: &settings::SettingsData) -> implicit3d::PrimitiveParameters<Float> {
+    implicit3d::PrimitiveParameters {
+        fade_range: s.fade_range,
+        r_multiplier: s.r_multiplier,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slots(limit: usize) -> Arc<Slots> {
+        Arc::new(Slots {
+            running: Mutex::new(0),
+            limit,
+        })
+    }
+
+    // The status of the answer to request, handled on a connection of its own.
+    fn status(request: &[u8]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle(stream, &slots(1)).unwrap();
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request).unwrap();
+        let mut answer = String::new();
+        BufReader::new(client).read_line(&mut answer).unwrap();
+        server.join().unwrap();
+        answer.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn bodies_are_limited() {
+        let request = format!(
+            "POST /export HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(status(request.as_bytes()), 413);
+        assert_eq!(
+            status(b"POST /export HTTP/1.1\r\nContent-Length: x\r\n\r\n"),
+            400
+        );
+        assert_eq!(status(b"GET /health HTTP/1.1\r\n\r\n"), 200);
+        assert_eq!(status(b"GET /export HTTP/1.1\r\n\r\n"), 405);
+    }
+
+    #[test]
+    fn jobs_are_limited_to_the_slots() {
+        let slots = slots(2);
+        let first = Slots::take(&slots).unwrap();
+        let second = Slots::take(&slots).unwrap();
+        assert!(Slots::take(&slots).is_none());
+        let body = br#"{"script": "build(Sphere(1))"}"#;
+        assert_eq!(run(body, &slots, "render").status, 503);
+        assert_eq!(run(b"{", &slots, "render").status, 400);
+        drop(first);
+        assert!(Slots::take(&slots).is_some());
+        drop(second);
+        assert_eq!(*slots.running.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn cells_are_limited() {
+        assert_eq!(resolution(0.1, 4.), Ok(0.1));
+        assert_eq!(resolution(1e-6, 4.), Ok(4. / MAX_CELLS));
+        assert!(resolution(-1., 0.).is_err());
+        assert!(resolution(Float::NAN, Float::NAN).is_err());
+    }
+
+    #[test]
+    fn images_are_limited() {
+        for &(width, height) in &[(0, 300), (400, MAX_IMAGE_SIZE + 1)] {
+            let request = Request {
+                script: "build(Sphere(1))".to_string(),
+                width: Some(width),
+                height: Some(height),
+                ..Default::default()
+            };
+            let message = render(request).err().unwrap();
+            assert!(message.starts_with("invalid size"), "{}", message);
+        }
+    }
+}
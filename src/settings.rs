@@ -1,22 +1,68 @@
+use super::Float;
+use export_dialog::ExportOptions;
 use gtk::{
-    BoxExt, ContainerExt, DialogExt, SpinButton, SpinButtonExt, SpinButtonSignals, WidgetExt,
+    BoxExt, CheckButton, ComboBoxExt, ComboBoxTextExt, ContainerExt, DialogExt, EditableSignals,
+    EntryExt, FontButtonExt, FontChooserExt, SpinButton, SpinButtonExt, SpinButtonSignals,
+    ToggleButtonExt, WidgetExt,
 };
+use na;
+use render;
+use serde::{Deserialize, Deserializer};
 use std::cell::RefCell;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use truescad_luascad::convert;
+use truescad_luascad::normals::NormalEstimate;
+use truescad_luascad::optimize;
+use truescad_luascad::tree::Node;
 
-const SETTINGS_FILENAME: &str = ".truescad";
+// The settings are in SETTINGS_FILENAME in the directory SETTINGS_DIR of the platform config
+// directory. Before, they were in LEGACY_SETTINGS_FILENAME in the home directory, which is read
+// until they are saved.
+const SETTINGS_DIR: &str = "truescad";
+const SETTINGS_FILENAME: &str = "settings.toml";
+const LEGACY_SETTINGS_FILENAME: &str = ".truescad";
+// Plugins are loaded from this directory next to the settings, see cli::load_plugins.
+#[cfg(feature = "plugins")]
+const PLUGIN_DIR: &str = "plugins";
+// Meshes converted from STEP and IGES files are cached in this directory of the platform cache
+// directory, see SettingsData::set_up_converter.
+const MESH_CACHE_DIR: &str = "meshes";
+// The settings of a project, next to its scripts, see ProjectSettings.
+pub const PROJECT_FILENAME: &str = "truescad.toml";
+// Number of files kept in the File > Open recent menu.
+const MAX_RECENT_FILES: usize = 10;
 
 macro_rules! add_setting {
     ($field :ident, $data :expr) => {{
+        add_setting!($field, $data, 0.0001, 1000.)
+    }};
+    ($field :ident, $data :expr, $min :expr, $max :expr) => {{
+        let data_clone = $data.clone();
+        let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
+        let label = ::gtk::Label::new_with_mnemonic(Some(stringify!($field)));
+        let setting = SpinButton::new_with_range($min, $max, 0.01);
+        setting.set_value($data.borrow().$field.into());
+        setting.connect_value_changed(move |f: &SpinButton| {
+            data_clone.borrow_mut().$field = f.get_value() as _;
+        });
+        h_box.pack_start(&label, true, false, 5);
+        h_box.pack_start(&setting, true, false, 5);
+        h_box
+    }};
+}
+
+macro_rules! add_int_setting {
+    ($field :ident, $data :expr, $min :expr, $max :expr) => {{
         let data_clone = $data.clone();
         let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
         let label = ::gtk::Label::new_with_mnemonic(Some(stringify!($field)));
-        let setting = SpinButton::new_with_range(0.0001, 1000., 0.01);
-        setting.set_value($data.borrow().$field);
+        let setting = SpinButton::new_with_range($min as f64, $max as f64, 1.);
+        setting.set_value($data.borrow().$field as f64);
         setting.connect_value_changed(move |f: &SpinButton| {
-            data_clone.borrow_mut().$field = f.get_value();
+            data_clone.borrow_mut().$field = f.get_value_as_int() as _;
         });
         h_box.pack_start(&label, true, false, 5);
         h_box.pack_start(&setting, true, false, 5);
@@ -24,6 +70,18 @@ macro_rules! add_setting {
     }};
 }
 
+macro_rules! add_bool_setting {
+    ($field :ident, $data :expr) => {{
+        let data_clone = $data.clone();
+        let setting = CheckButton::new_with_label(stringify!($field));
+        setting.set_active($data.borrow().$field);
+        setting.connect_toggled(move |f: &CheckButton| {
+            data_clone.borrow_mut().$field = f.get_active();
+        });
+        setting
+    }};
+}
+
 pub fn show_settings_dialog<T: ::gtk::IsA<::gtk::Window>>(parent: Option<&T>) {
     let data = Rc::new(RefCell::new(SettingsData::default()));
 
@@ -49,66 +107,522 @@ pub fn show_settings_dialog<T: ::gtk::IsA<::gtk::Window>>(parent: Option<&T>) {
     dialog
         .get_content_area()
         .add(&add_setting!(r_multiplier, &data));
+    dialog
+        .get_content_area()
+        .add(&add_int_setting!(aa_samples, &data, 1, 4));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(progressive, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(ambient_occlusion, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(soft_shadows, &data));
+    dialog.get_content_area().add(&add_setting!(ambient, &data));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(specular, &data));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(shininess, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(ggx_specular, &data));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(roughness, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(clip_plane, &data));
+    dialog
+        .get_content_area()
+        .add(&add_int_setting!(clip_axis, &data, 0, 2));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(clip_offset, &data, -1000., 1000.));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(infinite_clip_size, &data, 0., 100000.));
+    {
+        let data_clone = data.clone();
+        let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
+        let label = ::gtk::Label::new_with_mnemonic(Some("debug_mode"));
+        let setting = ::gtk::ComboBoxText::new();
+        for mode in &render::DebugMode::ALL {
+            setting.append_text(mode.name());
+        }
+        let active = render::DebugMode::ALL
+            .iter()
+            .position(|&mode| mode == data.borrow().debug_mode)
+            .unwrap_or(0);
+        setting.set_active(active as i32);
+        setting.connect_changed(move |c: &::gtk::ComboBoxText| {
+            if let Some(&mode) = render::DebugMode::ALL.get(c.get_active() as usize) {
+                data_clone.borrow_mut().debug_mode = mode;
+            }
+        });
+        h_box.pack_start(&label, true, false, 5);
+        h_box.pack_start(&setting, true, false, 5);
+        dialog.get_content_area().add(&h_box);
+    }
+    dialog
+        .get_content_area()
+        .add(&add_setting!(relaxation, &data, 1., 1.9));
+    dialog
+        .get_content_area()
+        .add(&add_int_setting!(max_iterations, &data, 16, 4096));
+    dialog
+        .get_content_area()
+        .add(&add_int_setting!(stereo, &data, 0, 2));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(cone_tracing, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(screen_space_normals, &data));
+    dialog
+        .get_content_area()
+        .add(&add_int_setting!(normal_estimate, &data, 0, 3));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(wall_thickness, &data, 0., 1000.));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(overhang_angle, &data, 0., 90.));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(min_draft, &data, 0., 90.));
+    dialog
+        .get_content_area()
+        .add(&add_int_setting!(draft_axis, &data, 0, 2));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(wireframe, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(axes, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(grid, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(bounding_box, &data));
+    dialog
+        .get_content_area()
+        .add(&add_bool_setting!(optimize_tree, &data));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(watch_debounce, &data, 0., 10.));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(export_resolution, &data));
+    dialog
+        .get_content_area()
+        .add(&add_setting!(export_error, &data));
+    {
+        let data_clone = data.clone();
+        let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
+        let label = ::gtk::Label::new_with_mnemonic(Some("editor_font"));
+        let setting = ::gtk::FontButton::new_with_font(&data.borrow().editor_font);
+        setting.connect_font_set(move |f: &::gtk::FontButton| {
+            if let Some(font) = f.get_font() {
+                data_clone.borrow_mut().editor_font = font;
+            }
+        });
+        h_box.pack_start(&label, true, false, 5);
+        h_box.pack_start(&setting, true, false, 5);
+        dialog.get_content_area().add(&h_box);
+    }
+    {
+        let data_clone = data.clone();
+        let h_box = ::gtk::Box::new(::gtk::Orientation::Horizontal, 0);
+        let label = ::gtk::Label::new_with_mnemonic(Some("mesh_converter"));
+        let setting = ::gtk::Entry::new();
+        setting.set_text(&data.borrow().mesh_converter);
+        setting.connect_changed(move |e: &::gtk::Entry| {
+            data_clone.borrow_mut().mesh_converter = e.get_text().unwrap_or_default().to_string();
+        });
+        h_box.pack_start(&label, true, false, 5);
+        h_box.pack_start(&setting, true, false, 5);
+        dialog.get_content_area().add(&h_box);
+    }
 
     dialog.show_all();
     let ret = dialog.run();
 
-    if ret == ::gtk::ResponseType::Ok.into() {
+    if ::gtk::ResponseType::from(ret) == ::gtk::ResponseType::Ok {
         data.borrow().save();
+        data.borrow().set_up_converter();
     }
     dialog.destroy();
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SettingsData {
-    pub tessellation_resolution: f64,
-    pub tessellation_error: f64,
-    pub fade_range: f64,
-    pub r_multiplier: f64,
+    pub tessellation_resolution: Float,
+    pub tessellation_error: Float,
+    pub fade_range: Float,
+    pub r_multiplier: Float,
+    #[serde(default = "default_aa_samples")]
+    pub aa_samples: usize,
+    #[serde(default = "default_progressive")]
+    pub progressive: bool,
+    #[serde(default)]
+    pub ambient_occlusion: bool,
+    #[serde(default)]
+    pub soft_shadows: bool,
+    #[serde(default = "default_ambient")]
+    pub ambient: Float,
+    #[serde(default = "default_specular")]
+    pub specular: Float,
+    #[serde(default = "default_shininess")]
+    pub shininess: Float,
+    #[serde(default)]
+    pub ggx_specular: bool,
+    #[serde(default = "default_roughness")]
+    pub roughness: Float,
+    // Cut away everything above clip_offset along axis clip_axis (0: x, 1: y, 2: z).
+    #[serde(default)]
+    pub clip_plane: bool,
+    #[serde(default = "default_clip_axis")]
+    pub clip_axis: usize,
+    #[serde(default)]
+    pub clip_offset: Float,
+    // Tessellate infinite objects clipped to the cube from -infinite_clip_size to
+    // infinite_clip_size along each axis. Without it (0), they are refused.
+    #[serde(default)]
+    pub infinite_clip_size: Float,
+    #[serde(default, deserialize_with = "debug_mode_or_index")]
+    pub debug_mode: render::DebugMode,
+    // Rays step relaxation times the value, falling back to 1 where that passes the surface, see
+    // render::Tracer. Lower it if thin parts still show holes.
+    #[serde(default = "default_relaxation")]
+    pub relaxation: Float,
+    // Rays give up after max_iterations steps, shown magenta in the debug modes.
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: usize,
+    // 0: one image, 1: red-cyan anaglyph, 2: side by side, see render::Stereo.
+    #[serde(default)]
+    pub stereo: usize,
+    // March cones through blocks of pixels before their rays, which skips most of the empty
+    // space around small objects, see render::Renderer::cone_start.
+    #[serde(default = "default_cone_tracing")]
+    pub cone_tracing: bool,
+    // Take the normals in the preview from the depth of neighboring pixels where the surface is
+    // smooth, instead of from the object. Stills and exports keep the exact normals.
+    #[serde(default)]
+    pub screen_space_normals: bool,
+    // How normals are found for shading and tessellation, see NormalEstimate::from_index: from
+    // the object (0), or by forward (1), central (2) or tetrahedral (3) differences.
+    #[serde(default)]
+    pub normal_estimate: usize,
+    // Color walls by their thickness, red up to wall_thickness, and report where they are
+    // thinner. Off for 0.
+    #[serde(default)]
+    pub wall_thickness: Float,
+    // Color surfaces red which overhang by more than overhang_angle degrees, unless the wall
+    // thickness is shown, and report where. Off for 0.
+    #[serde(default)]
+    pub overhang_angle: Float,
+    // Color surfaces for a mold pulled off along axis draft_axis (0: x, 1: y, 2: z): undercuts
+    // red, draft angles below min_draft degrees orange, unless one of the above is shown.
+    // Off for 0.
+    #[serde(default)]
+    pub min_draft: Float,
+    #[serde(default = "default_draft_axis")]
+    pub draft_axis: usize,
+    // Overlay the edges of the last tessellated mesh.
+    #[serde(default)]
+    pub wireframe: bool,
+    #[serde(default)]
+    pub axes: bool,
+    #[serde(default)]
+    pub grid: bool,
+    #[serde(default)]
+    pub bounding_box: bool,
+    // Simplify the object tree (used by the shader export, flat trees and the jit), see
+    // optimize::optimize.
+    #[serde(default = "default_optimize_tree")]
+    pub optimize_tree: bool,
+    // Seconds a watched script (File > Watch file) has to stay unchanged after a change before
+    // it is reloaded.
+    #[serde(default = "default_watch_debounce")]
+    pub watch_debounce: Float,
+    // What the export dialog starts with, unlike the tessellation settings above, which are
+    // for File > Tessellate.
+    #[serde(default = "default_export_resolution")]
+    pub export_resolution: Float,
+    #[serde(default = "default_export_error")]
+    pub export_error: Float,
+    // Font of the script editor, like "Monospace 10".
+    #[serde(default = "default_editor_font")]
+    pub editor_font: String,
+    // The command Mesh converts STEP and IGES files with, see
+    // truescad_luascad::convert::Converter. They are refused if it is empty.
+    #[serde(default)]
+    pub mesh_converter: String,
+    // Scripts opened or saved, the latest first.
+    #[serde(default)]
+    pub recent_files: Vec<String>,
+}
+
+// Older settings files have the index of the debug mode in DebugMode::ALL.
+fn debug_mode_or_index<'de, D: Deserializer<'de>>(d: D) -> Result<render::DebugMode, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Mode(render::DebugMode),
+        Index(usize),
+    }
+    Ok(match Saved::deserialize(d)? {
+        Saved::Mode(mode) => mode,
+        Saved::Index(i) => render::DebugMode::ALL.get(i).cloned().unwrap_or_default(),
+    })
+}
+
+fn default_aa_samples() -> usize {
+    1
+}
+
+fn default_progressive() -> bool {
+    true
+}
+
+fn default_ambient() -> Float {
+    0.05
+}
+
+fn default_specular() -> Float {
+    0.3
+}
+
+fn default_shininess() -> Float {
+    32.
+}
+
+fn default_roughness() -> Float {
+    0.4
+}
+
+fn default_clip_axis() -> usize {
+    2
+}
+
+fn default_relaxation() -> Float {
+    1.6
+}
+
+fn default_max_iterations() -> usize {
+    512
+}
+
+fn default_cone_tracing() -> bool {
+    true
+}
+
+fn default_draft_axis() -> usize {
+    2
+}
+
+fn default_optimize_tree() -> bool {
+    true
+}
+
+fn default_watch_debounce() -> Float {
+    0.3
+}
+
+fn default_export_resolution() -> Float {
+    0.12
+}
+
+fn default_export_error() -> Float {
+    2.
+}
+
+fn default_editor_font() -> String {
+    "Monospace 10".to_string()
 }
 
 #[derive(Debug)]
-enum SettingsError {
+pub enum SettingsError {
     Io(::std::io::Error),
     Dec(::toml::de::Error),
     Enc(::toml::ser::Error),
 }
 
+fn read_toml<T: ::serde::de::DeserializeOwned>(path: &Path) -> Result<T, SettingsError> {
+    let f = File::open(path).map_err(SettingsError::Io)?;
+    let mut reader = BufReader::new(f);
+    let mut buffer = String::new();
+    reader
+        .read_to_string(&mut buffer)
+        .map_err(SettingsError::Io)?;
+    ::toml::from_str(&buffer).map_err(SettingsError::Dec)
+}
+
 impl SettingsData {
-    fn path() -> Result<::std::path::PathBuf, SettingsError> {
-        let mut path = match ::dirs::home_dir() {
+    fn home() -> Result<PathBuf, SettingsError> {
+        match ::dirs::home_dir() {
+            Some(p) => Ok(p),
+            None => ::std::env::current_dir().map_err(SettingsError::Io),
+        }
+    }
+    fn path() -> Result<PathBuf, SettingsError> {
+        let mut path = match ::dirs::config_dir() {
             Some(p) => p,
-            None => try!(::std::env::current_dir().map_err(SettingsError::Io)),
+            None => SettingsData::home()?,
         };
+        path.push(SETTINGS_DIR);
         path.push(SETTINGS_FILENAME);
         Ok(path)
     }
+    #[cfg(feature = "plugins")]
+    pub fn plugin_dir() -> Result<PathBuf, SettingsError> {
+        let mut path = SettingsData::path()?;
+        path.set_file_name(PLUGIN_DIR);
+        Ok(path)
+    }
+    fn mesh_cache_dir() -> Result<PathBuf, SettingsError> {
+        match ::dirs::cache_dir() {
+            Some(mut path) => {
+                path.push(SETTINGS_DIR);
+                path.push(MESH_CACHE_DIR);
+                Ok(path)
+            }
+            None => {
+                let mut path = SettingsData::path()?;
+                path.set_file_name(MESH_CACHE_DIR);
+                Ok(path)
+            }
+        }
+    }
     fn get_toml() -> Result<Self, SettingsError> {
-        let path = try!(SettingsData::path());
-        let f = try!(File::open(path).map_err(SettingsError::Io));
-        let mut reader = BufReader::new(f);
-        let mut buffer = String::new();
-        let _ = try!(reader
-            .read_to_string(&mut buffer)
-            .map_err(SettingsError::Io));
-        ::toml::from_str(&buffer).map_err(SettingsError::Dec)
+        let path = SettingsData::path()?;
+        if path.exists() {
+            return read_toml(&path);
+        }
+        let mut legacy = SettingsData::home()?;
+        legacy.push(LEGACY_SETTINGS_FILENAME);
+        read_toml(&legacy)
     }
 
     fn put_toml(&self) -> Result<(), SettingsError> {
-        let toml_str = try!(::toml::to_string(self).map_err(SettingsError::Enc));
-        let path = try!(SettingsData::path());
-        let file = try!(File::create(path).map_err(SettingsError::Io));
+        let toml_str = ::toml::to_string(self).map_err(SettingsError::Enc)?;
+        let path = SettingsData::path()?;
+        if let Some(dir) = path.parent() {
+            ::std::fs::create_dir_all(dir).map_err(SettingsError::Io)?;
+        }
+        let file = File::create(path).map_err(SettingsError::Io)?;
         let mut writer = BufWriter::new(file);
-        try!(writer.write(toml_str.as_bytes()).map_err(SettingsError::Io));
+        writer
+            .write_all(toml_str.as_bytes())
+            .map_err(SettingsError::Io)?;
         Ok(())
     }
 
+    // Let Mesh convert STEP and IGES files of scripts evaluated from now on with
+    // mesh_converter, see truescad_luascad::convert.
+    pub fn set_up_converter(&self) {
+        if self.mesh_converter.trim().is_empty() {
+            convert::set_converter(None);
+            return;
+        }
+        match SettingsData::mesh_cache_dir() {
+            Ok(cache) => convert::set_converter(Some(convert::Converter {
+                command: self.mesh_converter.clone(),
+                cache,
+            })),
+            Err(e) => println!("error finding the mesh cache: {:?}", e),
+        }
+    }
+
     pub fn save(&self) {
         match self.put_toml() {
             Ok(_) => {}
             Err(e) => println!("error writing settings: {:?}", e),
         }
     }
+
+    // Put path first in the recent files and save the settings.
+    pub fn add_recent_file(path: &str) {
+        let mut s = SettingsData::default();
+        s.recent_files.retain(|p| p != path);
+        s.recent_files.insert(0, path.to_string());
+        s.recent_files.truncate(MAX_RECENT_FILES);
+        s.save();
+    }
+
+    // The tree of an evaluation, optimized if enabled.
+    pub fn optimize(&self, tree: Option<Node>) -> Option<Node> {
+        if self.optimize_tree {
+            tree.map(optimize::optimize)
+        } else {
+            tree
+        }
+    }
+
+    // Copy the render related settings to r.
+    pub fn apply_to(&self, r: &mut render::RenderSettings) {
+        r.aa_samples = self.aa_samples;
+        r.ambient_occlusion = self.ambient_occlusion;
+        r.soft_shadows = self.soft_shadows;
+        r.material.ambient = self.ambient;
+        r.material.specular = self.specular;
+        r.material.specular_model = if self.ggx_specular {
+            render::Specular::Ggx {
+                roughness: self.roughness,
+            }
+        } else {
+            render::Specular::Phong {
+                shininess: self.shininess,
+            }
+        };
+        r.clip_plane = if self.clip_plane {
+            let mut normal = na::Vector3::zeros();
+            normal[self.clip_axis.min(2)] = 1.;
+            Some(render::ClipPlane::new(
+                na::Point3::origin() + normal * self.clip_offset,
+                normal,
+            ))
+        } else {
+            None
+        };
+        r.debug_mode = self.debug_mode;
+        r.surface_analysis = if self.wall_thickness > 0. {
+            Some(render::SurfaceAnalysis::WallThickness {
+                threshold: self.wall_thickness,
+            })
+        } else if self.overhang_angle > 0. {
+            Some(render::SurfaceAnalysis::Overhang {
+                max_angle: self.overhang_angle.to_radians(),
+            })
+        } else if self.min_draft > 0. {
+            let mut pull = na::Vector3::zeros();
+            pull[self.draft_axis.min(2)] = 1.;
+            Some(render::SurfaceAnalysis::Draft {
+                pull,
+                min_draft: self.min_draft.to_radians(),
+            })
+        } else {
+            None
+        };
+        r.relaxation = self.relaxation.max(1.);
+        r.max_iterations = self.max_iterations.max(1);
+        r.cone_tracing = self.cone_tracing;
+        r.screen_space_normals = self.screen_space_normals;
+        r.stereo = match self.stereo {
+            1 => Some(render::Stereo::Anaglyph),
+            2 => Some(render::Stereo::SideBySide),
+            _ => None,
+        };
+        r.normals = NormalEstimate::from_index(self.normal_estimate);
+        r.wireframe = self.wireframe;
+        r.axes = self.axes;
+        r.grid = self.grid;
+        r.bounding_box = self.bounding_box;
+    }
 }
 
 impl Default for SettingsData {
@@ -122,8 +636,77 @@ impl Default for SettingsData {
                     tessellation_error: 2.,
                     fade_range: 0.1,
                     r_multiplier: 1.0,
+                    aa_samples: default_aa_samples(),
+                    progressive: default_progressive(),
+                    ambient_occlusion: false,
+                    soft_shadows: false,
+                    ambient: default_ambient(),
+                    specular: default_specular(),
+                    shininess: default_shininess(),
+                    ggx_specular: false,
+                    roughness: default_roughness(),
+                    clip_plane: false,
+                    clip_axis: default_clip_axis(),
+                    clip_offset: 0.,
+                    infinite_clip_size: 0.,
+                    debug_mode: render::DebugMode::Shaded,
+                    relaxation: default_relaxation(),
+                    max_iterations: default_max_iterations(),
+                    stereo: 0,
+                    cone_tracing: default_cone_tracing(),
+                    screen_space_normals: false,
+                    normal_estimate: 0,
+                    wall_thickness: 0.,
+                    overhang_angle: 0.,
+                    min_draft: 0.,
+                    draft_axis: default_draft_axis(),
+                    wireframe: false,
+                    axes: false,
+                    grid: false,
+                    bounding_box: false,
+                    optimize_tree: default_optimize_tree(),
+                    watch_debounce: default_watch_debounce(),
+                    export_resolution: default_export_resolution(),
+                    export_error: default_export_error(),
+                    editor_font: default_editor_font(),
+                    mesh_converter: String::new(),
+                    recent_files: Vec::new(),
                 }
             }
         }
     }
 }
+
+// Settings of a project, from PROJECT_FILENAME in the directory of its scripts. They pin what
+// exports depend on, so everyone exporting the project gets the same meshes, e.g.
+//
+//   [export]
+//   resolution = 0.05
+//   error = 2.0
+//   format = "binary_stl"
+#[derive(Default, Serialize, Deserialize)]
+pub struct ProjectSettings {
+    #[serde(default)]
+    pub export: Option<ExportOptions>,
+}
+
+impl ProjectSettings {
+    // The settings of the project of script, the default ones if it has no settings file (or
+    // is not saved yet).
+    pub fn for_script(script: &str) -> ProjectSettings {
+        if script.is_empty() {
+            return ProjectSettings::default();
+        }
+        let path = Path::new(script).with_file_name(PROJECT_FILENAME);
+        if !path.exists() {
+            return ProjectSettings::default();
+        }
+        match read_toml(&path) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("error reading {:?}: {:?}", path, e);
+                ProjectSettings::default()
+            }
+        }
+    }
+}
@@ -0,0 +1,777 @@
+use super::Float;
+use truescad_luascad::edge::Finish;
+use truescad_luascad::tree::{Boolean, Node};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShaderLanguage {
+    Glsl,
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    // WGSL for .wgsl files, GLSL for anything else.
+    pub fn from_path(path: &str) -> ShaderLanguage {
+        if path.to_lowercase().ends_with(".wgsl") {
+            ShaderLanguage::Wgsl
+        } else {
+            ShaderLanguage::Glsl
+        }
+    }
+}
+
+// Emit a standalone distance function sdf(p) for node (negative inside), e.g. for ShaderToy or
+// game engines. It evaluates to the same values as the implicit3d object with the given
+// r_multiplier.
+pub fn distance_function(
+    node: &Node,
+    language: ShaderLanguage,
+    r_multiplier: Float,
+) -> Result<String, String> {
+    let mut generator = Generator {
+        language,
+        r_multiplier,
+        body: String::new(),
+        next_var: 0,
+    };
+    let value = generator.node(node, "p")?;
+    let signature = match language {
+        ShaderLanguage::Glsl => "float sdf(vec3 p)",
+        ShaderLanguage::Wgsl => "fn sdf(p: vec3<f32>) -> f32",
+    };
+    Ok(format!(
+        "// Generated by truescad.\n{} {{\n{}    return {};\n}}\n",
+        signature, generator.body, value
+    ))
+}
+
+struct Generator {
+    language: ShaderLanguage,
+    r_multiplier: Float,
+    body: String,
+    next_var: usize,
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        self.body.push_str("    ");
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+    // Declare a new variable of type ty (float, vec3 or bool) and return its name.
+    fn declare(&mut self, ty: &str, value: &str) -> String {
+        self.next_var += 1;
+        let name = format!("v{}", self.next_var);
+        let line = match self.language {
+            ShaderLanguage::Glsl => format!("{} {} = {};", ty, name, value),
+            ShaderLanguage::Wgsl => format!("var {} = {};", name, value),
+        };
+        self.line(&line);
+        name
+    }
+    fn vec_type(&self, n: usize) -> String {
+        match self.language {
+            ShaderLanguage::Glsl => format!("vec{}", n),
+            ShaderLanguage::Wgsl => format!("vec{}<f32>", n),
+        }
+    }
+    fn vec3(&self, x: &str, y: &str, z: &str) -> String {
+        format!("{}({}, {}, {})", self.vec_type(3), x, y, z)
+    }
+    fn atan2(&self, y: &str, x: &str) -> String {
+        match self.language {
+            ShaderLanguage::Glsl => format!("atan({}, {})", y, x),
+            ShaderLanguage::Wgsl => format!("atan2({}, {})", y, x),
+        }
+    }
+    // Emit the code for node evaluated at point p and return the variable holding its value.
+    fn node(&mut self, node: &Node, p: &str) -> Result<String, String> {
+        Ok(match *node {
+            Node::Sphere { radius } => {
+                self.declare("float", &format!("length({}) - {}", p, lit(radius)))
+            }
+            Node::Cylinder { radius } => {
+                self.declare("float", &format!("length({}.xy) - {}", p, lit(radius)))
+            }
+            Node::Cone { slope, offset } => self.declare(
+                "float",
+                &format!(
+                    "(length({p}.xy) - abs({} * ({p}.z + {}))) * {}",
+                    lit(slope),
+                    lit(offset),
+                    lit(1. / (slope * slope + 1.).sqrt()),
+                    p = p
+                ),
+            ),
+            Node::Plane { ref normal, p: d } => {
+                let normal = self.vec3(&lit(normal.x), &lit(normal.y), &lit(normal.z));
+                self.declare("float", &format!("dot({}, {}) - {}", normal, p, lit(d)))
+            }
+            Node::Union {
+                ref children,
+                smooth,
+            } => {
+                let values = self.children(children, p)?;
+                self.blend(&values, smooth, false)
+            }
+            Node::Intersection {
+                ref children,
+                smooth,
+            } => {
+                let values = self.children(children, p)?;
+                self.blend(&values, smooth, true)
+            }
+            Node::Difference {
+                ref children,
+                smooth,
+            } => {
+                let mut values = self.children(children, p)?;
+                for value in values.iter_mut().skip(1) {
+                    *value = format!("(-{})", value);
+                }
+                self.blend(&values, smooth, true)
+            }
+            Node::Edge {
+                ref children,
+                op,
+                finish,
+            } => {
+                let values = self.children(children, p)?;
+                self.edge(&values[0], &values[1], op, finish)
+            }
+            Node::Sharp { ref children, op } => self.node(&op.node(children.clone(), 0.), p)?,
+            Node::LocalBlend {
+                ref children,
+                width,
+            } => {
+                let values = self.children(children, p)?;
+                self.declare(
+                    "float",
+                    &format!(
+                        "mix({b}, {s}, clamp({r} / {w}, 0.0, 1.0))",
+                        s = values[0],
+                        b = values[1],
+                        r = values[2],
+                        w = lit(width)
+                    ),
+                )
+            }
+            Node::Transform {
+                ref child,
+                ref transform,
+                scale,
+            } => {
+                // Both GLSL and WGSL take the matrix in column major order, like nalgebra.
+                let elements: Vec<_> = transform.as_slice().iter().map(|&e| lit(e)).collect();
+                let matrix = match self.language {
+                    ShaderLanguage::Glsl => format!("mat4({})", elements.join(", ")),
+                    ShaderLanguage::Wgsl => format!("mat4x4<f32>({})", elements.join(", ")),
+                };
+                let point = format!("({} * {}({}, 1.0)).xyz", matrix, self.vec_type(4), p);
+                let q = self.declare("vec3", &point);
+                let value = self.node(child, &q)?;
+                self.declare("float", &format!("{} * {}", value, lit(scale)))
+            }
+            Node::Twist {
+                ref child,
+                height_scaler,
+                value_scaler,
+            } => {
+                let angle = self.declare("float", &format!("{}.z * {}", p, lit(height_scaler)));
+                let point = self.vec3(
+                    &format!("cos({a}) * {p}.x - sin({a}) * {p}.y", a = angle, p = p),
+                    &format!("sin({a}) * {p}.x + cos({a}) * {p}.y", a = angle, p = p),
+                    &format!("{}.z", p),
+                );
+                let q = self.declare("vec3", &point);
+                let value = self.node(child, &q)?;
+                self.declare("float", &format!("{} * {}", value, lit(value_scaler)))
+            }
+            Node::Bend {
+                ref child,
+                width_scaler,
+                inner_radius,
+            } => {
+                let r = self.declare("float", &format!("length({}.xy)", p));
+                let phi = self.atan2(&format!("{}.x", p), &format!("-{}.y", p));
+                let point = self.vec3(
+                    &format!("{} * {}", phi, lit(width_scaler)),
+                    &r,
+                    &format!("{}.z", p),
+                );
+                let q = self.declare("vec3", &point);
+                let value = self.node(child, &q)?;
+                let bent = self.declare(
+                    "float",
+                    &format!("{} * min({} / {}, 1.0)", value, r, lit(width_scaler)),
+                );
+                if inner_radius.is_finite() {
+                    let inner_radius = lit(inner_radius);
+                    self.line(&format!(
+                        "if ({i} - {r} > 0.0) {{ {v} = {i} - {r}; }}",
+                        i = inner_radius,
+                        r = r,
+                        v = bent
+                    ));
+                }
+                bent
+            }
+            Node::Component { .. } => {
+                return Err("components can not be converted to a shader".to_string())
+            }
+            Node::Gear { .. } => return Err("gears can not be converted to a shader".to_string()),
+            Node::Thread { .. } => {
+                return Err("threads can not be converted to a shader".to_string())
+            }
+            Node::Lattice { .. } => {
+                return Err("infill can not be converted to a shader".to_string())
+            }
+            Node::Morphology { .. } => {
+                return Err("smoothed objects can not be converted to a shader".to_string())
+            }
+            Node::Offset { .. } => {
+                return Err("offsets can not be converted to a shader".to_string())
+            }
+            Node::Outline { .. } => {
+                return Err("outlines can not be converted to a shader".to_string())
+            }
+            Node::PointCloud { ref filename, .. } => {
+                return Err(format!(
+                    "point cloud {} can not be converted to a shader",
+                    filename
+                ))
+            }
+            Node::Mesh { ref filename } => {
+                return Err(format!(
+                    "mesh {} can not be converted to a shader",
+                    filename
+                ))
+            }
+            Node::Plugin { ref function, .. } => {
+                return Err(format!("{} can not be converted to a shader", function))
+            }
+            // Shading languages have no literal for infinity, so use values far beyond any scene.
+            Node::Empty => self.declare("float", "1e30"),
+            Node::Full => self.declare("float", "-1e30"),
+        })
+    }
+    fn children(&mut self, children: &[Node], p: &str) -> Result<Vec<String>, String> {
+        children.iter().map(|c| self.node(c, p)).collect()
+    }
+    // Same as edge::value in truescad_luascad.
+    fn edge(&mut self, a: &str, b: &str, op: Boolean, finish: Finish) -> String {
+        let (a, b, sign) = match op {
+            Boolean::Union => (a.to_string(), b.to_string(), ""),
+            Boolean::Intersection => (format!("(-{})", a), format!("(-{})", b), "-"),
+            Boolean::Difference => (format!("(-{})", a), b.to_string(), "-"),
+        };
+        let finished = match finish {
+            Finish::Fillet(r) => format!(
+                "max({r}, min({a}, {b})) - length({}(max({r} - {a}, 0.0), max({r} - {b}, 0.0)))",
+                self.vec_type(2),
+                r = lit(r),
+                a = a,
+                b = b
+            ),
+            Finish::Chamfer(sa, sb) => {
+                let n = sa.hypot(sb);
+                format!(
+                    "min(min({a}, {b}), {a} * {} + {b} * {} - {})",
+                    lit(sa / n),
+                    lit(sb / n),
+                    lit(sa * sb / n),
+                    a = a,
+                    b = b
+                )
+            }
+        };
+        let finished = self.declare("float", &finished);
+        let union = self.declare(
+            "float",
+            &format!("{} * {}", finished, lit(1. / finish.steepness())),
+        );
+        self.line(&format!(
+            "if ({f} >= 0.0) {{ {u} = max({u}, min({a}, {b}) - {}); }} else {{ {u} = min({u}, min({a}, {b})); }}",
+            lit(finish.reach()),
+            f = finished,
+            u = union,
+            a = a,
+            b = b
+        ));
+        self.declare("float", &format!("{}{}", sign, union))
+    }
+    // Minimum (or maximum) of values, rounded by smooth where they are close. Same as rvmin and
+    // rvmax in implicit3d, but relative to the minimum, so that exp does not overflow floats.
+    fn blend(&mut self, values: &[String], smooth: Float, maximum: bool) -> String {
+        let lt = if maximum { ">" } else { "<" };
+        let result = self.declare("float", &values[0]);
+        if smooth <= 0. {
+            let f = if maximum { "max" } else { "min" };
+            for value in &values[1..] {
+                self.line(&format!("{m} = {}({m}, {});", f, value, m = result));
+            }
+            return result;
+        }
+        let exact_range = lit(smooth * self.r_multiplier);
+        let close = self.declare("bool", "false");
+        for value in &values[1..] {
+            self.line(&format!(
+                "if ({v} {lt} {m}) {{ {c} = abs({m} - {v}) < {e}; {m} = {v}; }} \
+                 else if (abs({v} - {m}) < {e}) {{ {c} = true; }}",
+                v = value,
+                m = result,
+                c = close,
+                e = exact_range,
+                lt = lt
+            ));
+        }
+        let r4 = lit(smooth / 4.);
+        // Values within smooth of the result are blended.
+        let (within, sign) = if maximum { ("-", "+") } else { ("+", "-") };
+        let sum = self.declare("float", "0.0");
+        for value in values {
+            self.line(&format!(
+                "if ({v} {lt} {m} {s} {r}) {{ {sum} = {sum} + exp(-abs({v} - {m}) / {r4}); }}",
+                v = value,
+                m = result,
+                s = within,
+                r = lit(smooth),
+                sum = sum,
+                r4 = r4,
+                lt = lt
+            ));
+        }
+        self.line(&format!(
+            "if ({c}) {{ {m} = {m} {s} {r4} * log({sum}); }}",
+            c = close,
+            m = result,
+            s = sign,
+            r4 = r4,
+            sum = sum
+        ));
+        result
+    }
+}
+
+// A float literal valid in GLSL and WGSL.
+fn lit(x: Float) -> String {
+    format!("{:?}", x)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use na;
+    use std::collections::HashMap;
+    use truescad_luascad::implicit3d::{Object, PrimitiveParameters};
+
+    // Like the defaults of the settings.
+    pub const PARAMETERS: PrimitiveParameters<Float> = PrimitiveParameters {
+        fade_range: 0.1,
+        r_multiplier: 1.0,
+    };
+    // Trees with each kind of node which can be flattened, compiled and converted to shaders.
+    pub const SCRIPTS: &[&str] = &[
+        "build(Sphere(1))",
+        "build(Box(1, 2, 3, 0.2):rotate(0.3, 0.2, 0.1):translate(0.2, 0, 0))",
+        "build(Cylinder{l = 2, r1 = 1, r2 = 0.5})",
+        "build(Sphere(1):scale(1, 2, 0.5))",
+        "build(Union({Sphere(1), Sphere(1):translate(1.2, 0, 0), Sphere(0.5):translate(0, 1, 0)}, 0.4))",
+        "build(Union({Sphere(1), Sphere(1):translate(1.2, 0, 0), sharp = true}))",
+        "build(Intersection({Sphere(1), PlaneZ(0.3)}, 0.1))",
+        "build(Difference({Box(1, 1, 1, 0.2), Sphere(0.6)}, 0.2))",
+        "build(fillet(Box(2, 2, 2), Sphere(1.2):translate(0, 0, 1), 0.3))",
+        "build(chamfer(Box(2, 2, 2), Sphere(1.2):translate(0, 0, 1), {0.2, 0.4}, \"difference\"))",
+        "build(LocalBlend(Union, {Box(2, 2, 2), Sphere(1.2):translate(0, 0, 1)}, 0.5, \
+         Sphere(1):translate(1, 0, 1)))",
+        "build(Twist(Box(1, 1, 2), 4))",
+        // Bends use the distance to their inner radius closer to the axis than that, like the
+        // objects only do with enough slack (see flat_tree::tests::bends_use_their_inner_radius),
+        // so this one has none.
+        "build(Bend(Box(4, 1, 1):translate(0, 0.5, 0), 10))",
+    ];
+    // Relative difference of values which still agree, for rounding differences.
+    #[cfg(not(feature = "f32"))]
+    pub const TOLERANCE: Float = 1e-9;
+    #[cfg(feature = "f32")]
+    pub const TOLERANCE: Float = 1e-4;
+
+    // The object script builds, with its tree.
+    pub fn build(script: &str) -> (Box<dyn Object<Float>>, Node) {
+        let evaluation = ::truescad_luascad::evaluate(script, 0.).unwrap();
+        let mut object = evaluation.object.unwrap();
+        object.set_parameters(&PARAMETERS);
+        (object, evaluation.tree.unwrap())
+    }
+
+    // A grid of points through the bounding box of object.
+    pub fn points(object: &dyn Object<Float>) -> Vec<na::Point3<Float>> {
+        const STEPS: usize = 7;
+        let bbox = object.bbox();
+        let mut points = Vec::new();
+        for i in 0..STEPS * STEPS * STEPS {
+            let t = na::Vector3::new(i % STEPS, i / STEPS % STEPS, i / STEPS / STEPS)
+                .map(|j| (j as Float + 0.5) / STEPS as Float);
+            points.push(bbox.min + (bbox.max - bbox.min).component_mul(&t));
+        }
+        points
+    }
+
+    // Assert that value, at p of the tree of script, is the one of object.
+    pub fn assert_value(
+        script: &str,
+        object: &dyn Object<Float>,
+        p: &na::Point3<Float>,
+        value: Float,
+    ) {
+        let expected = object.approx_value(p, Float::INFINITY);
+        assert!(
+            (value - expected).abs() <= TOLERANCE * (1. + expected.abs()),
+            "{} at {}: {}, expected {}",
+            script,
+            p,
+            value,
+            expected
+        );
+    }
+
+    // An interpreter for the part of GLSL and WGSL distance_function emits, to check its values
+    // without a GPU.
+
+    #[derive(Clone, Debug)]
+    enum Value {
+        Float(Float),
+        Bool(bool),
+        // Vectors, and matrices in column major order.
+        Vector(Vec<Float>),
+    }
+
+    impl Value {
+        fn float(&self) -> Float {
+            match *self {
+                Value::Float(x) => x,
+                ref other => panic!("not a float: {:?}", other),
+            }
+        }
+        fn bool(&self) -> bool {
+            match *self {
+                Value::Bool(b) => b,
+                ref other => panic!("not a bool: {:?}", other),
+            }
+        }
+        fn vector(&self) -> &[Float] {
+            match *self {
+                Value::Vector(ref v) => v,
+                ref other => panic!("not a vector: {:?}", other),
+            }
+        }
+        // Apply f to floats, or to each element of vectors.
+        fn map(&self, f: impl Fn(Float) -> Float) -> Value {
+            match *self {
+                Value::Float(x) => Value::Float(f(x)),
+                Value::Vector(ref v) => Value::Vector(v.iter().map(|&x| f(x)).collect()),
+                Value::Bool(_) => panic!("not a number"),
+            }
+        }
+    }
+
+    fn tokens(code: &str) -> Vec<String> {
+        let mut tokens = Vec::new();
+        let mut chars = code.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c.is_alphanumeric() || c == '_' {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek() {
+                    // Exponents of number literals, like 1e-5.
+                    let exponent = (c == '-' || c == '+')
+                        && token.ends_with('e')
+                        && token.starts_with(|c: char| c.is_ascii_digit());
+                    if !(c.is_alphanumeric() || c == '_' || c == '.' || exponent) {
+                        break;
+                    }
+                    // Swizzles.
+                    if c == '.' && !token.starts_with(|c: char| c.is_ascii_digit()) {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            } else {
+                chars.next();
+                let two = format!("{}{}", c, chars.peek().cloned().unwrap_or(' '));
+                if ["<=", ">=", "->"].contains(&two.as_str()) {
+                    chars.next();
+                    tokens.push(two);
+                } else {
+                    tokens.push(c.to_string());
+                }
+            }
+        }
+        tokens
+    }
+
+    struct Interpreter {
+        tokens: Vec<String>,
+        position: usize,
+        variables: HashMap<String, Value>,
+    }
+
+    impl Interpreter {
+        fn peek(&self) -> &str {
+            &self.tokens[self.position]
+        }
+        fn next(&mut self) -> String {
+            self.position += 1;
+            self.tokens[self.position - 1].clone()
+        }
+        fn expect(&mut self, token: &str) {
+            let next = self.next();
+            assert_eq!(next, token, "at token {}", self.position);
+        }
+        // Run the statements up to the closing brace of the block, return the value of return.
+        fn block(&mut self, run: bool) -> Option<Value> {
+            let mut result = None;
+            while self.peek() != "}" {
+                if let Some(value) = self.statement(run) {
+                    result = Some(value);
+                }
+            }
+            self.expect("}");
+            result
+        }
+        fn statement(&mut self, run: bool) -> Option<Value> {
+            let first = self.next();
+            match first.as_str() {
+                "if" => {
+                    self.expect("(");
+                    let condition = self.expression(run).map(|c| c.bool());
+                    self.expect(")");
+                    self.expect("{");
+                    let taken = run && condition == Some(true);
+                    self.block(taken);
+                    if self.peek() == "else" {
+                        self.next();
+                        let run_else = run && !taken;
+                        if self.peek() == "if" {
+                            self.statement(run_else);
+                        } else {
+                            self.expect("{");
+                            self.block(run_else);
+                        }
+                    }
+                    None
+                }
+                "return" => {
+                    let value = self.expression(run);
+                    self.expect(";");
+                    value
+                }
+                _ => {
+                    let name = match first.as_str() {
+                        "float" | "bool" | "vec3" | "var" => self.next(),
+                        _ => first,
+                    };
+                    self.expect("=");
+                    let value = self.expression(run);
+                    self.expect(";");
+                    if let Some(value) = value {
+                        self.variables.insert(name, value);
+                    }
+                    None
+                }
+            }
+        }
+        // Parse an expression, and evaluate it if run.
+        fn expression(&mut self, run: bool) -> Option<Value> {
+            let a = self.sum(run);
+            let op = self.peek().to_string();
+            if !["<", ">", "<=", ">="].contains(&op.as_str()) {
+                return a;
+            }
+            self.next();
+            let b = self.sum(run);
+            a.map(|a| {
+                let (a, b) = (a.float(), b.unwrap().float());
+                Value::Bool(match op.as_str() {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                })
+            })
+        }
+        fn sum(&mut self, run: bool) -> Option<Value> {
+            let mut a = self.product(run);
+            while self.peek() == "+" || self.peek() == "-" {
+                let sign = if self.next() == "+" { 1. } else { -1. };
+                let b = self.product(run);
+                a = a.map(|a| elementwise(&a, &b.unwrap(), |x, y| x + sign * y));
+            }
+            a
+        }
+        fn product(&mut self, run: bool) -> Option<Value> {
+            let mut a = self.unary(run);
+            while self.peek() == "*" || self.peek() == "/" {
+                let op = self.next();
+                let b = self.unary(run);
+                a = a.map(|a| match (op.as_str(), &a, b.unwrap()) {
+                    ("*", Value::Vector(m), Value::Vector(ref v)) if m.len() == 16 => {
+                        Value::Vector(
+                            (0..4)
+                                .map(|i| (0..4).map(|j| m[4 * j + i] * v[j]).sum())
+                                .collect(),
+                        )
+                    }
+                    ("*", a, b) => elementwise(a, &b, |x, y| x * y),
+                    (_, a, b) => elementwise(a, &b, |x, y| x / y),
+                });
+            }
+            a
+        }
+        fn unary(&mut self, run: bool) -> Option<Value> {
+            if self.peek() == "-" {
+                self.next();
+                return self.unary(run).map(|a| a.map(|x| -x));
+            }
+            let mut a = self.primary(run);
+            while self.peek() == "." {
+                self.next();
+                let swizzle = self.next();
+                a = a.map(|a| {
+                    let v = a.vector();
+                    let elements: Vec<Float> =
+                        swizzle.chars().map(|c| v["xyz".find(c).unwrap()]).collect();
+                    match elements.len() {
+                        1 => Value::Float(elements[0]),
+                        _ => Value::Vector(elements),
+                    }
+                });
+            }
+            a
+        }
+        fn primary(&mut self, run: bool) -> Option<Value> {
+            let token = self.next();
+            if token == "(" {
+                let value = self.expression(run);
+                self.expect(")");
+                return value;
+            }
+            if let Ok(x) = token.parse::<Float>() {
+                return Some(Value::Float(x)).filter(|_| run);
+            }
+            if token == "true" || token == "false" {
+                return Some(Value::Bool(token == "true")).filter(|_| run);
+            }
+            let constructor = token.starts_with("vec") || token.starts_with("mat");
+            if self.peek() != "(" && !(constructor && self.peek() == "<") {
+                return if run {
+                    Some(self.variables[&token].clone())
+                } else {
+                    None
+                };
+            }
+            // The element type of WGSL vectors and matrices.
+            if constructor && self.peek() == "<" {
+                self.next();
+                self.expect("f32");
+                self.expect(">");
+            }
+            self.expect("(");
+            let mut arguments = Vec::new();
+            while self.peek() != ")" {
+                arguments.push(self.expression(run));
+                if self.peek() == "," {
+                    self.next();
+                }
+            }
+            self.expect(")");
+            if !run {
+                return None;
+            }
+            let arguments: Vec<Value> = arguments.into_iter().map(Option::unwrap).collect();
+            let x = |i: usize| arguments[i].float();
+            Some(match token.as_str() {
+                "vec2" | "vec3" | "vec4" | "mat4" | "mat4x4" => Value::Vector(
+                    arguments
+                        .iter()
+                        .flat_map(|a| match *a {
+                            Value::Float(x) => vec![x],
+                            ref v => v.vector().to_vec(),
+                        })
+                        .collect(),
+                ),
+                "length" => Value::Float(
+                    arguments[0]
+                        .vector()
+                        .iter()
+                        .map(|x| x * x)
+                        .sum::<Float>()
+                        .sqrt(),
+                ),
+                "dot" => Value::Float(
+                    arguments[0]
+                        .vector()
+                        .iter()
+                        .zip(arguments[1].vector())
+                        .map(|(a, b)| a * b)
+                        .sum(),
+                ),
+                "abs" => arguments[0].map(Float::abs),
+                "cos" => Value::Float(x(0).cos()),
+                "sin" => Value::Float(x(0).sin()),
+                "exp" => Value::Float(x(0).exp()),
+                "log" => Value::Float(x(0).ln()),
+                "atan" | "atan2" => Value::Float(x(0).atan2(x(1))),
+                "min" => Value::Float(x(0).min(x(1))),
+                "max" => Value::Float(x(0).max(x(1))),
+                "clamp" => Value::Float(x(0).max(x(1)).min(x(2))),
+                "mix" => Value::Float(x(0) + (x(1) - x(0)) * x(2)),
+                _ => panic!("unknown function {}", token),
+            })
+        }
+    }
+
+    fn elementwise(a: &Value, b: &Value, f: impl Fn(Float, Float) -> Float) -> Value {
+        match (a, b) {
+            (&Value::Float(x), b) => b.map(|y| f(x, y)),
+            (a, &Value::Float(y)) => a.map(|x| f(x, y)),
+            (a, b) => Value::Vector(
+                a.vector()
+                    .iter()
+                    .zip(b.vector())
+                    .map(|(&x, &y)| f(x, y))
+                    .collect(),
+            ),
+        }
+    }
+
+    // The value of the distance function code at p.
+    fn run(code: &str, p: &na::Point3<Float>) -> Float {
+        // The body of sdf, after the signature.
+        let start = code.find('{').unwrap() + 1;
+        let mut interpreter = Interpreter {
+            tokens: tokens(&code[start..]),
+            position: 0,
+            variables: HashMap::new(),
+        };
+        interpreter
+            .variables
+            .insert("p".to_string(), Value::Vector(vec![p.x, p.y, p.z]));
+        interpreter.block(true).unwrap().float()
+    }
+
+    #[test]
+    fn values_match_the_object() {
+        for script in SCRIPTS {
+            let (object, tree) = build(script);
+            for &language in &[ShaderLanguage::Glsl, ShaderLanguage::Wgsl] {
+                let code = distance_function(&tree, language, PARAMETERS.r_multiplier).unwrap();
+                for p in points(&*object) {
+                    assert_value(script, &*object, &p, run(&code, &p));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use gtk::traits::*;
+use gtk::TreeStoreExtManual;
+use render;
+use std::cell::RefCell;
+use std::rc::Rc;
+use truescad_luascad::tree::Node;
+
+// Columns of the store.
+const LABEL: u32 = 0;
+const VISIBLE: u32 = 1;
+
+// Panel listing the tree of the object, see tree::Node. Selecting a node shows its box in the
+// view, unchecking one hides it (and all below it) until it is checked again or the tree changes.
+// Hiding only affects the view, tessellation and export use the whole object.
+#[derive(Clone)]
+pub struct TreePanel {
+    pub widget: ::gtk::ScrolledWindow,
+    view: ::gtk::TreeView,
+    store: ::gtk::TreeStore,
+    visible_cell: ::gtk::CellRendererToggle,
+    tree: Rc<RefCell<Option<Node>>>,
+    // Paths of the hidden nodes, see Node::without.
+    hidden: Rc<RefCell<Vec<Vec<usize>>>>,
+}
+
+impl TreePanel {
+    pub fn new(
+        renderer: &Rc<RefCell<render::Renderer>>,
+        drawing_area: &::gtk::DrawingArea,
+    ) -> TreePanel {
+        let widget = ::gtk::ScrolledWindow::new(None, None);
+        let store = ::gtk::TreeStore::new(&[::gtk::Type::String, ::gtk::Type::Bool]);
+        let view = ::gtk::TreeView::new_with_model(&store);
+        view.set_headers_visible(false);
+        view.set_tooltip_text("uncheck to hide a part of the object in the view");
+        let visible_cell = ::gtk::CellRendererToggle::new();
+        let visible_column = ::gtk::TreeViewColumn::new();
+        visible_column.pack_start(&visible_cell, false);
+        visible_column.add_attribute(&visible_cell, "active", VISIBLE as i32);
+        view.append_column(&visible_column);
+        let label_cell = ::gtk::CellRendererText::new();
+        let label_column = ::gtk::TreeViewColumn::new();
+        label_column.pack_start(&label_cell, true);
+        label_column.add_attribute(&label_cell, "text", LABEL as i32);
+        view.append_column(&label_column);
+        widget.add(&view);
+        let panel = TreePanel {
+            widget,
+            view,
+            store,
+            visible_cell,
+            tree: Rc::new(RefCell::new(None)),
+            hidden: Rc::new(RefCell::new(Vec::new())),
+        };
+        {
+            let tree = panel.tree.clone();
+            let renderer = renderer.clone();
+            let drawing_area = drawing_area.clone();
+            panel
+                .view
+                .get_selection()
+                .connect_changed(move |selection| {
+                    let bbox = selection.get_selected().and_then(|(model, iter)| {
+                        let path = node_path(&model.get_path(&iter)?);
+                        let tree = tree.borrow();
+                        tree.as_ref()?.subtree(&path).map(|(_, bbox)| bbox)
+                    });
+                    renderer.borrow_mut().set_highlight(bbox);
+                    drawing_area.queue_draw();
+                });
+        }
+        panel
+    }
+    // Call f after a node was hidden or shown again.
+    pub fn connect_hidden_changed<F: Fn() + 'static>(&self, f: F) {
+        let store = self.store.clone();
+        let hidden = self.hidden.clone();
+        self.visible_cell.connect_toggled(move |_, tree_path| {
+            let iter = match store.get_iter(&tree_path) {
+                Some(iter) => iter,
+                None => return,
+            };
+            let visible = !store
+                .get_value(&iter, VISIBLE as i32)
+                .get::<bool>()
+                .unwrap_or(true);
+            store.set(&iter, &[VISIBLE], &[&visible]);
+            let path = node_path(&tree_path);
+            {
+                let mut hidden = hidden.borrow_mut();
+                if visible {
+                    hidden.retain(|p| *p != path);
+                } else {
+                    hidden.push(path);
+                }
+            }
+            f();
+        });
+    }
+    // List tree, keeping the hidden nodes if it has the same structure as the previous one.
+    pub fn show(&self, tree: Option<&Node>) {
+        let unchanged = match (self.tree.borrow().as_ref(), tree) {
+            (Some(a), Some(b)) => a.tree_eq(b),
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            return;
+        }
+        // Clearing the store clears the selection, whose handler reads the tree.
+        *self.tree.borrow_mut() = tree.cloned();
+        self.hidden.borrow_mut().clear();
+        self.store.clear();
+        if let Some(tree) = tree {
+            add_node(&self.store, None, tree);
+            self.view.expand_all();
+        }
+    }
+    // Paths of the hidden nodes, see Node::without.
+    pub fn hidden(&self) -> Vec<Vec<usize>> {
+        self.hidden.borrow().clone()
+    }
+}
+
+// Add node and its subtree below parent.
+fn add_node(store: &::gtk::TreeStore, parent: Option<&::gtk::TreeIter>, node: &Node) {
+    let iter = store.insert_with_values(parent, None, &[LABEL, VISIBLE], &[&node.label(), &true]);
+    for child in node.children() {
+        add_node(store, Some(&iter), child);
+    }
+}
+
+// The path of the node in the row at path, relative to the root of the tree.
+fn node_path(path: &::gtk::TreePath) -> Vec<usize> {
+    path.get_indices()
+        .iter()
+        .skip(1)
+        .map(|&i| i as usize)
+        .collect()
+}
@@ -0,0 +1,56 @@
+use super::Float;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+// How often watched files are checked, in milliseconds.
+pub const POLL_INTERVAL: u32 = 200;
+
+// Polls the modification time of a file, to reload scripts edited in another editor.
+pub struct FileWatch {
+    path: String,
+    // Seconds the file has to stay unchanged after a change, so that editors which save in
+    // several steps trigger one reload.
+    debounce: Float,
+    modified: Option<SystemTime>,
+    // When a change was first seen which has not been reported yet.
+    changed_at: Option<Instant>,
+}
+
+impl FileWatch {
+    pub fn new(path: &str, debounce: Float) -> FileWatch {
+        FileWatch {
+            path: path.to_string(),
+            debounce,
+            modified: modified(path),
+            changed_at: None,
+        }
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    // Whether the file changed and then stayed unchanged for debounce seconds.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        let m = modified(&self.path);
+        if m != self.modified {
+            self.modified = m;
+            self.changed_at = Some(now);
+            return false;
+        }
+        match self.changed_at {
+            Some(t)
+                if now.duration_since(t)
+                    >= Duration::from_millis((self.debounce * 1000.) as u64) =>
+            {
+                self.changed_at = None;
+                // A deleted file is reported when it reappears.
+                m.is_some()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -1,13 +1,22 @@
+use super::Float;
+use adhesion;
+use console::ConsolePanel;
 use editor;
+use export_dialog::{self, Format};
+use features;
 use gtk::traits::*;
-use gtk::{FileChooserAction, FileChooserDialog, FileFilter, Inhibit, ResponseType};
+use gtk::{
+    FileChooserAction, FileChooserDialog, FileFilter, Inhibit, NotebookExtManual, ResponseType,
+};
+use indexed_mesh::IndexedMesh;
 use menu;
 use object_widget;
+use project::{self, Project};
 use settings;
 use std::cell::RefCell;
-use std::fs::OpenOptions;
 use std::rc::Rc;
-use stl_io::write_stl;
+use truescad_luascad::printbuffer::Level;
+use watch::{self, FileWatch};
 
 macro_rules! clone {
     ($($n:ident),+; || $body:stmt) => (
@@ -35,84 +44,199 @@ pub fn create_window() -> ::gtk::Window {
     });
 
     let v_box = ::gtk::Box::new(::gtk::Orientation::Vertical, 0);
-    let debug_scrolled_window = ::gtk::ScrolledWindow::new(None, None);
-    let debug_view = ::gtk::TextView::new();
-    debug_view.set_editable(false);
-    debug_scrolled_window.add(&debug_view);
-    debug_view.set_wrap_mode(::gtk::WrapMode::WordChar);
     let xw = object_widget::ObjectWidget::new();
-    let debug_text = debug_view.get_buffer().unwrap();
-    let editor = editor::Editor::new(&xw, &debug_text);
+    let editor = editor::Editor::new(&xw);
     let h_pane = ::gtk::Paned::new(::gtk::Orientation::Horizontal);
-    h_pane.add2(&xw.drawing_area);
+    let view_box = ::gtk::Box::new(::gtk::Orientation::Vertical, 0);
+    let time_scale = ::gtk::Scale::new_with_range(::gtk::Orientation::Horizontal, 0., 1., 0.01);
+    time_scale.set_tooltip_text("time");
+    time_scale.connect_value_changed(clone!(editor; |scale| {
+        editor.set_time(scale.get_value() as Float);
+    }));
+    view_box.pack_start(&xw.widget, true, true, 0);
+    view_box.pack_start(&time_scale, false, false, 0);
+    view_box.pack_start(&editor.customizer.widget, false, false, 0);
+    view_box.pack_start(&editor.properties.widget, false, false, 0);
+    // The object tree panel is docked right of the view.
+    let view_pane = ::gtk::Paned::new(::gtk::Orientation::Horizontal);
+    view_pane.pack1(&view_box, true, false);
+    view_pane.pack2(&editor.tree_panel.widget, false, true);
+    h_pane.add2(&view_pane);
     h_pane.add1(&editor.widget);
 
     let filename = Rc::new(RefCell::new(String::new()));
+    // The project of the file, with no profiles for scripts.
+    let project = Rc::new(RefCell::new(Project::default()));
+    // The script file while File > Watch file is checked.
+    let watched: Rc<RefCell<Option<FileWatch>>> = Rc::new(RefCell::new(None));
+    // The console and the reference of the Lua functions share the pane below the editor.
+    let notebook = ::gtk::Notebook::new();
+    notebook.append_page(
+        &editor.console.widget,
+        Some(&::gtk::Label::new(Some("Console"))),
+    );
+    let reference_page = notebook.append_page(
+        &editor.reference.widget,
+        Some(&::gtk::Label::new(Some("Reference"))),
+    );
 
     let menu = menu::create_menu(
         clone!(editor; || {
             editor.tessellate();
         }),
-        clone!(window, editor, filename; || {
+        clone!(window, editor, filename, project; || {
             if let Some(path_str) = get_open_name(Some(&window)) {
-                let mut f = filename.borrow_mut();
-                *f = path_str;
-                editor.open(&*f);
+                if open_file(&editor, &project, &path_str) {
+                    settings::SettingsData::add_recent_file(&path_str);
+                    *filename.borrow_mut() = path_str;
+                }
             }
         }),
-        clone!(window, editor, filename; || {
+        {
+            let (editor, filename, project) = (editor.clone(), filename.clone(), project.clone());
+            move |path: &str| {
+                if open_file(&editor, &project, path) {
+                    settings::SettingsData::add_recent_file(path);
+                    *filename.borrow_mut() = path.to_string();
+                }
+            }
+        },
+        clone!(window, editor, filename, project; || {
             let mut f = filename.borrow_mut();
             if f.is_empty() {
                 if let Some(path) = get_save_name(Some(&window),
-                                                  "*.lua") {
+                                                  &["*.lua", "*.tscad"]) {
                     *f = path;
-                    editor.save(&*f);
+                    save_file(&editor, &project, &f);
+                    settings::SettingsData::add_recent_file(&f);
                 }
             } else {
-                editor.save(&*f);
+                save_file(&editor, &project, &f);
             }
         }),
-        clone!(window, editor, filename; || {
+        clone!(window, editor, filename, project; || {
             if let Some(path) = get_save_name(Some(&window),
-                                              "*.lua") {
+                                              &["*.lua", "*.tscad"]) {
                 let mut f = filename.borrow_mut();
                 *f = path;
-                editor.save(&*f);
+                save_file(&editor, &project, &f);
+                settings::SettingsData::add_recent_file(&f);
             }
         }),
-        clone!(window; || settings::show_settings_dialog(Some(&window))),
-        clone!(window, editor; || {
-            let maybe_mesh = editor.tessellate();
+        clone!(window, xw, editor; || {
+            settings::show_settings_dialog(Some(&window));
+            let s = settings::SettingsData::default();
+            xw.apply_settings(&s);
+            editor.apply_settings(&s);
+        }),
+        clone!(window, editor, filename, project; || {
+            let bbox = editor.tessellation_bbox();
+            let pinned = settings::ProjectSettings::for_script(&filename.borrow()).export;
+            if let Some(options) = export_dialog::show_export_dialog(Some(&window),
+                                                                     bbox.as_ref(),
+                                                                     &project.borrow().profiles,
+                                                                     pinned.as_ref()) {
+                if let Some(mut mesh) = editor.tessellate_with(options.resolution, options.error) {
+                    if let Err(message) = export_dialog::transform(&mut mesh, &options) {
+                        editor.console.log(Level::Error, None, message);
+                        return;
+                    }
+                    let patterns = ["*.stl", "*.dxf", "*.svg", "*.obj"];
+                    if let Some(path) = get_save_name(Some(&window), &patterns) {
+                        if features::is_features_path(&path) {
+                            save_features(&editor.console, &path, &mesh);
+                            return;
+                        }
+                        if !adhesion::add(&mut mesh, options.brim, options.tabs) {
+                            editor.console.log(Level::Warning,
+                                               None,
+                                               "no footprint at z = 0 for a brim or tabs");
+                        }
+                        save_mesh(&editor.console, &path, &mesh, options.format);
+                    }
+                }
+            }
+        }),
+        // Floating geometry ends up in files of its own, instead of unnoticed in the main one.
+        clone!(window, editor, filename; || {
+            let s = settings::SettingsData::default();
+            let (resolution, error) =
+                match settings::ProjectSettings::for_script(&filename.borrow()).export {
+                    Some(pinned) => (pinned.resolution, pinned.error),
+                    None => (s.export_resolution, s.export_error),
+                };
+            let maybe_mesh = editor.tessellate_with(resolution, error);
             if let Some(mesh) = maybe_mesh {
                 if let Some(path) = get_save_name(Some(&window),
-                                                  "*.stl") {
-                    let stl_mesh = mesh.faces.iter().enumerate().map(|(i, f)| {
-                        let normal = mesh.normal32(i);
-                        ::stl_io::Triangle{ normal:[normal[0], normal[1], normal[2]],
-                            vertices: [mesh.vertex32(f[0]),
-                             mesh.vertex32(f[1]),
-                             mesh.vertex32(f[2])]}
-                    }).collect::<Vec<_>>();
-                    match OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .truncate(true)
-                        .open(&path) {
-                            Ok(mut file) => println!("writing STL {:}: {:?}",
-                                   path,
-                                     write_stl(&mut file, stl_mesh.iter())),
-                                     Err(e) =>println!("could not open {:} for writing: {:?}", path, e)
-                        }
+                                                  &["*.stl"]) {
+                    let stem = path.trim_end_matches(".stl");
+                    let parts = mesh.split();
+                    editor.console.log(Level::Info, None, format!("{} parts", parts.len()));
+                    for (i, part) in parts.iter().enumerate() {
+                        save_mesh(&editor.console, &format!("{}_{}.stl", stem, i + 1), part,
+                                  Format::BinaryStl);
+                    }
                 }
             }
         }),
         ::gtk::main_quit,
+        clone!(filename, watched; |active| {
+            *watched.borrow_mut() = if active {
+                let debounce = settings::SettingsData::default().watch_debounce;
+                Some(FileWatch::new(&filename.borrow(), debounce))
+            } else {
+                None
+            };
+        }),
+        clone!(editor, notebook; || {
+            notebook.set_current_page(Some(reference_page));
+            editor.reference.focus_search();
+        }),
+        clone!(window, xw, project; || {
+            if let Some(name) = get_view_name(Some(&window)) {
+                let state = xw.renderer.borrow().camera_state();
+                project.borrow_mut().views.insert(name, state);
+            }
+        }),
+        clone!(project; || project.borrow().views.keys().cloned().collect()),
+        {
+            let (xw, project) = (xw.clone(), project.clone());
+            move |name: &str| {
+                if let Some(state) = project.borrow().views.get(name) {
+                    xw.set_camera_state(state);
+                }
+            }
+        },
     );
+    // Follow files opened or saved under a new name while watching.
+    {
+        let (editor, filename, project, watched) = (
+            editor.clone(),
+            filename.clone(),
+            project.clone(),
+            watched.clone(),
+        );
+        ::gtk::timeout_add(watch::POLL_INTERVAL, move || {
+            if let Some(ref mut w) = *watched.borrow_mut() {
+                let f = filename.borrow();
+                if w.path() != *f {
+                    *w = FileWatch::new(&f, settings::SettingsData::default().watch_debounce);
+                } else if w.poll() {
+                    if !project::is_project(&f) {
+                        editor.reload(&f);
+                    } else if open_file(&editor, &project, &f) {
+                        editor.evaluate();
+                    }
+                }
+            }
+            ::gtk::Continue(true)
+        });
+    }
 
     let v_pane = ::gtk::Paned::new(::gtk::Orientation::Vertical);
     v_pane.set_border_width(5);
     v_pane.add1(&h_pane);
-    v_pane.add2(&debug_scrolled_window);
+    v_pane.add2(&notebook);
 
     v_box.pack_start(&menu, false, false, 0);
     v_box.pack_start(&v_pane, true, true, 0);
@@ -122,6 +246,7 @@ pub fn create_window() -> ::gtk::Window {
 
     v_pane.set_position(v_pane.get_allocated_height() * 80 / 100);
     h_pane.set_position(h_pane.get_allocated_width() * 50 / 100);
+    view_pane.set_position(view_pane.get_allocated_width() * 75 / 100);
 
     window
 }
@@ -133,11 +258,12 @@ fn get_open_name<T: ::gtk::IsA<::gtk::Window>>(parent: Option<&T>) -> Option<Str
     dialog.set_select_multiple(false);
     let filter = FileFilter::new();
     filter.add_pattern("*.lua");
+    filter.add_pattern("*.tscad");
     dialog.add_filter(&filter);
     let res = dialog.run();
     let maybe_filename = dialog.get_filename();
     dialog.destroy();
-    if res == ::gtk::ResponseType::Ok.into() {
+    if ::gtk::ResponseType::from(res) == ::gtk::ResponseType::Ok {
         if let Some(path) = maybe_filename {
             if let Some(path_str) = path.to_str() {
                 return Some(path_str.to_string());
@@ -149,7 +275,7 @@ fn get_open_name<T: ::gtk::IsA<::gtk::Window>>(parent: Option<&T>) -> Option<Str
 
 fn get_save_name<T: ::gtk::IsA<::gtk::Window>>(
     parent: Option<&T>,
-    pattern: &str,
+    patterns: &[&str],
 ) -> Option<String> {
     let dialog = FileChooserDialog::new(
         Some("Choose a filename to Save"),
@@ -159,12 +285,14 @@ fn get_save_name<T: ::gtk::IsA<::gtk::Window>>(
     dialog.add_button("Save", ResponseType::Ok.into());
     dialog.add_button("Cancel", ResponseType::Cancel.into());
     let filter = FileFilter::new();
-    filter.add_pattern(pattern);
+    for pattern in patterns {
+        filter.add_pattern(pattern);
+    }
     dialog.add_filter(&filter);
     let res = dialog.run();
     let maybe_filename = dialog.get_filename();
     dialog.destroy();
-    if res == ::gtk::ResponseType::Ok.into() {
+    if ::gtk::ResponseType::from(res) == ::gtk::ResponseType::Ok {
         if let Some(path) = maybe_filename {
             if let Some(path_str) = path.to_str() {
                 return Some(path_str.to_string());
@@ -173,3 +301,82 @@ fn get_save_name<T: ::gtk::IsA<::gtk::Window>>(
     }
     None
 }
+
+// Ask for the name of a view to save, see Project::views.
+fn get_view_name<T: ::gtk::IsA<::gtk::Window>>(parent: Option<&T>) -> Option<String> {
+    let dialog = ::gtk::Dialog::new_with_buttons(
+        Some("Save view"),
+        parent,
+        ::gtk::DialogFlags::MODAL,
+        &[
+            ("Save", ResponseType::Ok.into()),
+            ("Cancel", ResponseType::Cancel.into()),
+        ],
+    );
+    let entry = ::gtk::Entry::new();
+    entry.set_activates_default(true);
+    dialog.set_default_response(ResponseType::Ok.into());
+    dialog.get_content_area().add(&entry);
+    dialog.show_all();
+    let res = dialog.run();
+    let name = entry.get_text().map(|t| t.trim().to_string());
+    dialog.destroy();
+    match name {
+        Some(name) if ResponseType::from(res) == ResponseType::Ok && !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+// Open the script or project file path in the editor, keeping the project in project. False if
+// it can not be opened.
+fn open_file(editor: &editor::Editor, project: &RefCell<Project>, path: &str) -> bool {
+    if project::is_project(path) {
+        match Project::open(path) {
+            Ok(p) => {
+                editor.open_project(&p);
+                *project.borrow_mut() = p;
+            }
+            Err(e) => {
+                editor.console.log(Level::Error, None, e);
+                return false;
+            }
+        }
+    } else {
+        *project.borrow_mut() = Project::default();
+        editor.open(path);
+    }
+    true
+}
+
+// Save the script to path, or the project with it if path is a project file.
+fn save_file(editor: &editor::Editor, project: &RefCell<Project>, path: &str) {
+    if project::is_project(path) {
+        let p = editor.project(&project.borrow());
+        match p.save(path) {
+            Ok(()) => *project.borrow_mut() = p,
+            Err(e) => editor.console.log(Level::Error, None, e),
+        }
+    } else {
+        editor.save(path);
+    }
+}
+
+// Write mesh to path, telling how it went.
+fn save_mesh(console: &ConsolePanel, path: &str, mesh: &IndexedMesh, format: Format) {
+    match export_dialog::save_mesh(path, mesh, format) {
+        Ok(()) => console.log(Level::Info, None, format!("wrote {}", path)),
+        Err(e) => console.log(Level::Error, None, e),
+    }
+}
+
+// Write the feature lines of mesh (see features) instead, for a file with their extension.
+fn save_features(console: &ConsolePanel, path: &str, mesh: &IndexedMesh) {
+    let lines = features::feature_lines(mesh, features::DEFAULT_ANGLE);
+    match features::save(path, &lines, mesh.units.millimeters()) {
+        Ok(()) => {
+            let text = format!("wrote {} feature lines to {}", lines.len(), path);
+            console.log(Level::Info, None, text)
+        }
+        Err(e) => console.log(Level::Error, None, e),
+    }
+}
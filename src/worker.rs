@@ -0,0 +1,123 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+// Interval in which the GUI polls workers for results, in milliseconds.
+pub const POLL_INTERVAL: u32 = 20;
+
+struct State<J> {
+    // The job to run next and its number.
+    pending: Option<(u64, J)>,
+    // Set to cancel the running job, or the pending one if none runs.
+    cancel: Arc<AtomicBool>,
+    closed: bool,
+}
+
+// Runs jobs on a thread of its own, so the GUI stays responsive. Only the latest job matters:
+// submitting one cancels the running job (which may check the flag it gets, or run to its end)
+// and replaces one which did not start yet. Results are polled from the GUI, e.g. every
+// POLL_INTERVAL.
+pub struct Worker<J, R> {
+    state: Arc<(Mutex<State<J>>, Condvar)>,
+    results: mpsc::Receiver<(u64, R)>,
+    // Number of the latest submitted job, and of the latest one with a result.
+    submitted: Cell<u64>,
+    done: Cell<u64>,
+}
+
+impl<J: Send + 'static, R: Send + 'static> Worker<J, R> {
+    // Start a thread running jobs with run, which gets the cancel flag of the job.
+    pub fn new<F>(name: &str, run: F) -> Worker<J, R>
+    where
+        F: Fn(J, &AtomicBool) -> R + Send + 'static,
+    {
+        let state = Arc::new((
+            Mutex::new(State {
+                pending: None,
+                cancel: Arc::new(AtomicBool::new(false)),
+                closed: false,
+            }),
+            Condvar::new(),
+        ));
+        let (tx, results) = mpsc::channel();
+        let thread_state = state.clone();
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || loop {
+                let (number, job, cancel) = {
+                    let (ref lock, ref ready) = *thread_state;
+                    let mut state = lock.lock().unwrap();
+                    loop {
+                        if state.closed {
+                            return;
+                        }
+                        if let Some((number, job)) = state.pending.take() {
+                            break (number, job, state.cancel.clone());
+                        }
+                        state = ready.wait(state).unwrap();
+                    }
+                };
+                let result = run(job, &cancel);
+                if !cancel.load(Ordering::Relaxed) && tx.send((number, result)).is_err() {
+                    return;
+                }
+            })
+            .unwrap();
+        Worker {
+            state,
+            results,
+            submitted: Cell::new(0),
+            done: Cell::new(0),
+        }
+    }
+    // Run job instead of the running and the pending one.
+    pub fn submit(&self, job: J) {
+        let number = self.submitted.get() + 1;
+        self.submitted.set(number);
+        let (ref lock, ref ready) = *self.state;
+        let mut state = lock.lock().unwrap();
+        state.cancel.store(true, Ordering::Relaxed);
+        state.cancel = Arc::new(AtomicBool::new(false));
+        state.pending = Some((number, job));
+        ready.notify_one();
+    }
+    // Cancel the running and the pending job, e.g. when their result is not needed anymore.
+    pub fn cancel(&self) {
+        let number = self.submitted.get() + 1;
+        self.submitted.set(number);
+        self.done.set(number);
+        let (ref lock, _) = *self.state;
+        let mut state = lock.lock().unwrap();
+        state.cancel.store(true, Ordering::Relaxed);
+        state.cancel = Arc::new(AtomicBool::new(false));
+        state.pending = None;
+    }
+    // The result of the latest job, once it is done. Results of earlier jobs are dropped.
+    pub fn poll(&self) -> Option<R> {
+        let mut latest = None;
+        for (number, result) in self.results.try_iter() {
+            if number == self.submitted.get() {
+                self.done.set(number);
+                latest = Some(result);
+            }
+        }
+        latest
+    }
+    // Whether the latest job has no result yet.
+    pub fn busy(&self) -> bool {
+        self.done.get() < self.submitted.get()
+    }
+}
+
+impl<J, R> Drop for Worker<J, R> {
+    fn drop(&mut self) {
+        let (ref lock, ref ready) = *self.state;
+        if let Ok(mut state) = lock.lock() {
+            state.closed = true;
+            state.cancel.store(true, Ordering::Relaxed);
+            ready.notify_one();
+        }
+    }
+}
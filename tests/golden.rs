@@ -0,0 +1,21 @@
+extern crate truescad;
+use truescad::golden::{self, Outcome};
+
+// Like `truescad golden`, the meshes of the scripts in golden::DIRECTORY match their goldens,
+// which have to exist. Run `truescad golden --update` after intended changes. The goldens are
+// tessellated in f64, so single precision builds differ from them.
+#[test]
+#[cfg_attr(feature = "f32", ignore)]
+fn goldens() {
+    let scripts = golden::scripts(golden::DIRECTORY).unwrap();
+    assert!(!scripts.is_empty(), "no scripts in {}", golden::DIRECTORY);
+    let failures: Vec<String> = scripts
+        .iter()
+        .filter_map(|script| match golden::check(script, false) {
+            Ok(Outcome::Matched) => None,
+            Ok(outcome) => Some(format!("{}: {:?}", script.to_string_lossy(), outcome)),
+            Err(e) => Some(e),
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,63 @@
+// The JavaScript API of truescad, on the WebAssembly module built from main.rs:
+//
+//   const truescad = await require("truescad").load();
+//   const mesh = truescad.evalScript("build(Sphere(5))", {resolution: 0.2});
+//   // mesh.positions and mesh.normals are Float32Arrays with x, y, z of each vertex,
+//   // mesh.indices a Uint32Array with the vertices of each triangle.
+//
+// evalScript throws an Error with the message of the script if it fails.
+"use strict";
+
+const createTruescad = require("./truescad.js");
+
+// Like the defaults of truescad export.
+const DEFAULTS = {time: 0, resolution: 0.12, error: 2, files: {}};
+
+function withString(module, s, f) {
+  const pointer = module.stringToNewUTF8(s);
+  try {
+    return f(pointer);
+  } finally {
+    module._free(pointer);
+  }
+}
+
+function api(module) {
+  return {
+    // Evaluate source at options.time (0..1) and tessellate its object with options.resolution
+    // and options.error. options.files are the scripts include() can read, by name.
+    evalScript(source, options) {
+      const o = Object.assign({}, DEFAULTS, options);
+      module._truescad_clear_files();
+      for (const name of Object.keys(o.files)) {
+        withString(module, name, n =>
+          withString(module, o.files[name], s => module._truescad_set_file(n, s)));
+      }
+      const result = withString(module, source, s =>
+        module._truescad_eval(s, o.time, o.resolution, o.error));
+      try {
+        const error = module._truescad_result_error(result);
+        if (error !== 0) {
+          throw new Error(module.UTF8ToString(error));
+        }
+        const vertices = module._truescad_result_vertices(result);
+        const triangles = module._truescad_result_triangles(result);
+        // Copies, since the memory of the module moves when it grows.
+        const floats = (pointer, length) =>
+          new Float32Array(module.HEAPF32.buffer, pointer, length).slice();
+        return {
+          messages: module.UTF8ToString(module._truescad_result_messages(result)),
+          positions: floats(module._truescad_result_positions(result), vertices * 3),
+          normals: floats(module._truescad_result_normals(result), vertices * 3),
+          indices: new Uint32Array(
+            module.HEAPU32.buffer, module._truescad_result_indices(result), triangles * 3).slice(),
+        };
+      } finally {
+        module._truescad_result_free(result);
+      }
+    },
+  };
+}
+
+// Instantiate the module, resolving to the API.
+exports.load = options => createTruescad(options).then(api);
@@ -0,0 +1,148 @@
+// The contract of the unsafe functions is that of their use in index.js.
+#![allow(clippy::missing_safety_doc)]
+
+extern crate truescad_capi;
+extern crate truescad_luascad;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use truescad_capi::CurrentThread;
+use truescad_luascad::include::Includes;
+
+thread_local! {
+    // The scripts include() can read, by name. The browser has no directory of the script.
+    static FILES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    // Browsers only have threads on cross-origin isolated pages, so tessellation runs on the
+    // thread of the page.
+    static THREADS: Result<CurrentThread, String> = CurrentThread::new();
+}
+
+// The result of truescad_eval: the error, or the messages of the script and the mesh of its
+// object, as the buffers of WebGL.
+pub struct Evaluation {
+    error: Option<CString>,
+    messages: CString,
+    // x, y, z of each vertex.
+    positions: Vec<f32>,
+    normals: Vec<f32>,
+    // The vertices of each triangle.
+    indices: Vec<u32>,
+}
+
+// JavaScript passes null for undefined strings.
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+fn c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+fn evaluate(script: &str, time: f64, resolution: f64, error: f64) -> Result<Evaluation, String> {
+    let includes = Includes {
+        files: FILES.with(|f| f.borrow().clone()),
+        dir: None,
+        untrusted: false,
+    };
+    let evaluation = truescad_capi::evaluate(script, time, &includes)?;
+    let mesh = THREADS.with(|threads| {
+        let threads = threads.as_ref().map_err(|e| e.clone())?;
+        truescad_capi::tessellate(threads, &*evaluation.object, resolution, error)
+    })?;
+    let floats = |v: &[f64]| v.iter().map(|&x| x as f32).collect();
+    Ok(Evaluation {
+        error: None,
+        messages: c_string(evaluation.output),
+        positions: floats(&mesh.vertices),
+        normals: floats(&mesh.normals),
+        indices: mesh.indices,
+    })
+}
+
+// Make the script source available to include("name").
+#[no_mangle]
+pub unsafe extern "C" fn truescad_set_file(name: *const c_char, source: *const c_char) {
+    FILES.with(|f| f.borrow_mut().insert(string(name), string(source)));
+}
+
+#[no_mangle]
+pub extern "C" fn truescad_clear_files() {
+    FILES.with(|f| f.borrow_mut().clear());
+}
+
+// Evaluate script at time (0..1) and tessellate its object with resolution and error, like
+// truescad export. Returns an Evaluation to free with truescad_result_free.
+#[no_mangle]
+pub unsafe extern "C" fn truescad_eval(
+    script: *const c_char,
+    time: f64,
+    resolution: f64,
+    error: f64,
+) -> *mut Evaluation {
+    let script = string(script);
+    let result = truescad_capi::catch_panic(|| evaluate(&script, time, resolution, error));
+    let evaluation = match result {
+        Ok(evaluation) => evaluation,
+        Err(e) => Evaluation {
+            error: Some(c_string(e)),
+            messages: c_string(String::new()),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        },
+    };
+    Box::into_raw(Box::new(evaluation))
+}
+
+// The error of the evaluation, null if there was none.
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_error(e: *const Evaluation) -> *const c_char {
+    (*e).error.as_ref().map_or(ptr::null(), |e| e.as_ptr())
+}
+
+// What the script printed.
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_messages(e: *const Evaluation) -> *const c_char {
+    (*e).messages.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_vertices(e: *const Evaluation) -> usize {
+    (*e).positions.len() / 3
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_positions(e: *const Evaluation) -> *const f32 {
+    (*e).positions.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_normals(e: *const Evaluation) -> *const f32 {
+    (*e).normals.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_triangles(e: *const Evaluation) -> usize {
+    (*e).indices.len() / 3
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_indices(e: *const Evaluation) -> *const u32 {
+    (*e).indices.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn truescad_result_free(e: *mut Evaluation) {
+    if !e.is_null() {
+        drop(Box::from_raw(e));
+    }
+}
+
+// The functions above are called from JavaScript, see index.js.
+fn main() {}